@@ -1,8 +1,11 @@
 // ABOUTME: Phone dialer application for MobileOS.
-// ABOUTME: Connects to org.mobileos.Modem via D-Bus for call management.
+// ABOUTME: Uses org.mobileos.Modem over D-Bus to place, hold, swap and conference calls, and ask who is calling.
 
+use std::rc::Rc;
 use std::sync::mpsc;
 
+use futures_util::StreamExt;
+use slint::VecModel;
 use tracing::info;
 
 slint::include_modules!();
@@ -10,8 +13,22 @@ slint::include_modules!();
 enum ModemCommand {
     Dial(String),
     HangUp,
+    HangUpCall(u8),
+    Swap,
+    Merge,
 }
 
+/// What the modem service answers when the network won't drop one caller.
+const NOT_SUPPORTED: &str = "org.freedesktop.DBus.Error.NotSupported";
+
+/// A call as the modem service lists it: index, number, state, whether it came in,
+/// and whether it is in the conference.
+type CallEntry = (u8, String, String, bool, bool);
+
+/// Who a number is, as the modem service's caller ID providers say: name, spam
+/// likelihood from 0 to 1, and the provider that said so.
+type CallerIdEntry = (String, f64, String);
+
 #[zbus::proxy(
     interface = "org.mobileos.Modem",
     default_service = "org.mobileos.Modem",
@@ -20,9 +37,42 @@ enum ModemCommand {
 trait Modem {
     fn dial(&self, number: &str) -> zbus::Result<()>;
     fn hang_up(&self) -> zbus::Result<()>;
+    fn hang_up_call(&self, index: u8) -> zbus::Result<()>;
+    fn swap_calls(&self) -> zbus::Result<()>;
+    fn merge_calls(&self) -> zbus::Result<()>;
+    fn look_up_caller(&self, number: &str) -> zbus::Result<CallerIdEntry>;
 
     #[zbus(property)]
     fn modem_state(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn calls(&self) -> zbus::Result<Vec<CallEntry>>;
+}
+
+/// Show `calls`, the conference's callers first.
+fn show_calls(window: &DialerWindow, calls: Vec<CallEntry>) {
+    let mut rows: Vec<CallRow> = calls
+        .into_iter()
+        .map(|(index, number, state, _, conference)| CallRow {
+            index: index.into(),
+            number: number.into(),
+            state: state.into(),
+            conference,
+        })
+        .collect();
+    rows.sort_by_key(|row| !row.conference);
+    window.set_in_conference(rows.iter().any(|row| row.conference));
+    window.set_calls(Rc::new(VecModel::from(rows)).into());
+}
+
+/// The number of the call ringing, if one is and the caller didn't withhold it.
+fn ringing(calls: &[CallEntry]) -> Option<String> {
+    calls
+        .iter()
+        .find(|(_, number, state, _, _)| {
+            !number.is_empty() && (state == "incoming" || state == "waiting")
+        })
+        .map(|(_, number, _, _, _)| number.clone())
 }
 
 fn main() -> anyhow::Result<()> {
@@ -61,7 +111,7 @@ fn main() -> anyhow::Result<()> {
     });
 
     // Hangup pressed
-    let tx = cmd_tx;
+    let tx = cmd_tx.clone();
     let weak = window.as_weak();
     window.on_hangup_pressed(move || {
         if let Some(w) = weak.upgrade() {
@@ -70,12 +120,30 @@ fn main() -> anyhow::Result<()> {
         }
     });
 
+    // One caller hung up on, such as someone in the conference
+    let tx = cmd_tx.clone();
+    window.on_call_ended(move |index| {
+        if let Ok(index) = u8::try_from(index) {
+            let _ = tx.send(ModemCommand::HangUpCall(index));
+        }
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_swap_pressed(move || {
+        let _ = tx.send(ModemCommand::Swap);
+    });
+
+    let tx = cmd_tx;
+    window.on_merge_pressed(move || {
+        let _ = tx.send(ModemCommand::Merge);
+    });
+
     // Background tokio thread for D-Bus communication
     let weak = window.as_weak();
     std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async move {
-            let conn = match zbus::Connection::session().await {
+            let conn = match zbus::Connection::system().await {
                 Ok(c) => c,
                 Err(e) => {
                     info!("D-Bus not available: {e}");
@@ -91,6 +159,58 @@ fn main() -> anyhow::Result<()> {
                 }
             };
 
+            let calls = proxy.clone();
+            let weak_calls = weak.clone();
+            tokio::spawn(async move {
+                let mut changes = calls.receive_calls_changed().await;
+                // The number last looked up, so it is asked about once per call
+                let mut asked = None;
+                while let Some(change) = changes.next().await {
+                    let Ok(list) = change.get().await else {
+                        continue;
+                    };
+                    let number = ringing(&list);
+                    let changed = number != asked;
+                    let weak = weak_calls.clone();
+                    let shown = number.clone();
+                    let _ = slint::invoke_from_event_loop(move || {
+                        if let Some(w) = weak.upgrade() {
+                            show_calls(&w, list);
+                            w.set_ringing_number(shown.unwrap_or_default().into());
+                            if changed {
+                                w.set_caller_name("".into());
+                                w.set_caller_spam(0.0);
+                            }
+                        }
+                    });
+
+                    if !changed {
+                        continue;
+                    }
+                    asked = number.clone();
+                    let Some(number) = number else {
+                        continue;
+                    };
+                    let (name, spam, provider) = match calls.look_up_caller(&number).await {
+                        Ok(caller) => caller,
+                        Err(e) => {
+                            info!("look_up_caller failed: {e}");
+                            Default::default()
+                        }
+                    };
+                    if !provider.is_empty() {
+                        info!(provider = %provider, spam, "caller identified");
+                    }
+                    let weak = weak_calls.clone();
+                    let _ = slint::invoke_from_event_loop(move || {
+                        if let Some(w) = weak.upgrade() {
+                            w.set_caller_name(name.into());
+                            w.set_caller_spam(spam as f32);
+                        }
+                    });
+                }
+            });
+
             while let Ok(cmd) = cmd_rx.recv() {
                 match cmd {
                     ModemCommand::Dial(number) => {
@@ -107,6 +227,38 @@ fn main() -> anyhow::Result<()> {
                             });
                         }
                     }
+                    ModemCommand::HangUpCall(index) => {
+                        info!(index, "hanging up one call");
+                        if let Err(e) = proxy.hang_up_call(index).await {
+                            info!("hang_up_call failed: {e}");
+                            // Some networks keep a conference's callers together
+                            let status = match e {
+                                zbus::Error::MethodError(name, _, _)
+                                    if name.as_str() == NOT_SUPPORTED =>
+                                {
+                                    "the network can't drop one caller"
+                                }
+                                _ => "couldn't hang up that call",
+                            };
+                            let weak = weak.clone();
+                            let _ = slint::invoke_from_event_loop(move || {
+                                if let Some(w) = weak.upgrade() {
+                                    w.set_call_status(status.into());
+                                }
+                            });
+                        }
+                    }
+                    ModemCommand::Swap => {
+                        if let Err(e) = proxy.swap_calls().await {
+                            info!("swap_calls failed: {e}");
+                        }
+                    }
+                    ModemCommand::Merge => {
+                        info!("merging calls");
+                        if let Err(e) = proxy.merge_calls().await {
+                            info!("merge_calls failed: {e}");
+                        }
+                    }
                     ModemCommand::HangUp => {
                         info!("hanging up");
                         if let Err(e) = proxy.hang_up().await {
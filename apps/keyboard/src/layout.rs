@@ -0,0 +1,131 @@
+// ABOUTME: Key layouts: QWERTY letters and a symbols page, with the accented and related characters
+// ABOUTME: a long press on a key offers.
+
+/// What a key does when tapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Type these characters, capitalized while shifted.
+    Text(&'static str),
+    Shift,
+    Backspace,
+    Enter,
+    /// Switch to the symbols page, or back to the letters.
+    Symbols,
+    Letters,
+    /// A gap, such as the half key at the ends of the middle letter row.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub label: &'static str,
+    pub action: Action,
+    /// Width relative to a letter key.
+    pub width: f32,
+}
+
+impl Key {
+    const fn text(label: &'static str) -> Self {
+        Self {
+            label,
+            action: Action::Text(label),
+            width: 1.0,
+        }
+    }
+
+    const fn special(label: &'static str, action: Action, width: f32) -> Self {
+        Self {
+            label,
+            action,
+            width,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    #[default]
+    Letters,
+    Symbols,
+}
+
+const LETTER_ROWS: [&[&str]; 3] = [
+    &["q", "w", "e", "r", "t", "y", "u", "i", "o", "p"],
+    &["a", "s", "d", "f", "g", "h", "j", "k", "l"],
+    &["z", "x", "c", "v", "b", "n", "m"],
+];
+
+const SYMBOL_ROWS: [&[&str]; 3] = [
+    &["1", "2", "3", "4", "5", "6", "7", "8", "9", "0"],
+    &["@", "#", "$", "%", "&", "-", "+", "(", ")", "/"],
+    &["*", "\"", "'", ":", ";", "!", "?"],
+];
+
+const GAP: Key = Key::special("", Action::None, 0.5);
+
+/// The rows of keys on `page`, top to bottom.
+pub fn rows(page: Page) -> Vec<Vec<Key>> {
+    let (chars, toggle) = match page {
+        Page::Letters => (LETTER_ROWS, Key::special("?123", Action::Symbols, 1.5)),
+        Page::Symbols => (SYMBOL_ROWS, Key::special("ABC", Action::Letters, 1.5)),
+    };
+    let row = |chars: &[&'static str]| chars.iter().map(|&c| Key::text(c)).collect::<Vec<_>>();
+
+    let mut middle = row(chars[1]);
+    if page == Page::Letters {
+        middle.insert(0, GAP);
+        middle.push(GAP);
+    }
+    let mut bottom = row(chars[2]);
+    bottom.insert(
+        0,
+        match page {
+            Page::Letters => Key::special("⇧", Action::Shift, 1.5),
+            Page::Symbols => GAP,
+        },
+    );
+    bottom.push(Key::special("⌫", Action::Backspace, 1.5));
+
+    vec![
+        row(chars[0]),
+        middle,
+        bottom,
+        vec![
+            toggle,
+            Key::text(","),
+            Key::special("space", Action::Text(" "), 5.0),
+            Key::text("."),
+            Key::special("⏎", Action::Enter, 1.5),
+        ],
+    ]
+}
+
+/// What a long press on a key typing `text` offers in its place.
+pub fn alternates(text: &str) -> &'static [&'static str] {
+    match text {
+        "a" => &["à", "á", "â", "ä", "ã", "å", "æ"],
+        "c" => &["ç", "ć", "č"],
+        "e" => &["è", "é", "ê", "ë", "ę"],
+        "i" => &["ì", "í", "î", "ï"],
+        "n" => &["ñ", "ń"],
+        "o" => &["ò", "ó", "ô", "ö", "õ", "ø", "œ"],
+        "s" => &["ß", "ś", "š"],
+        "u" => &["ù", "ú", "û", "ü"],
+        "y" => &["ý", "ÿ"],
+        "z" => &["ź", "ż", "ž"],
+        "$" => &["€", "£", "¥", "¢"],
+        "-" => &["_", "–", "—"],
+        "!" => &["¡"],
+        "?" => &["¿"],
+        "'" => &["‘", "’"],
+        "\"" => &["“", "”", "«", "»"],
+        "." => &["…"],
+        "," => &[";"],
+        _ => &[],
+    }
+}
+
+/// `text` as typed with shift held.
+pub fn shifted(text: &str) -> String {
+    text.to_uppercase()
+}
@@ -0,0 +1,291 @@
+// ABOUTME: On-screen keyboard for MobileOS: shows along the bottom of the screen when a text field is focused.
+// ABOUTME: QWERTY and symbols pages, with alternates on long press; slint draws it into a layer surface.
+
+mod layout;
+mod wayland;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rustix::event::{PollFd, PollFlags, Timespec, poll};
+use rustix::io::Errno;
+use slint::platform::software_renderer::{MinimalSoftwareWindow, RepaintBufferType};
+use slint::platform::{Platform, WindowAdapter};
+use slint::{ComponentHandle, ModelRc, SharedString, TimerMode, VecModel};
+use tracing::info;
+
+use layout::{Action, Key, Page};
+
+slint::include_modules!();
+
+/// How long a key is held before what it offers in its place shows.
+const LONG_PRESS: Duration = Duration::from_millis(450);
+
+/// How often to draw while something is animating.
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// evdev codes of the keys sent as key presses, as there is no text for them.
+const KEY_BACKSPACE: u32 = 14;
+const KEY_ENTER: u32 = 28;
+
+/// What the keys ask the input method for.
+enum Typed {
+    Text(String),
+    Key(u32),
+}
+
+/// Runs slint's windows and timers off the keyboard's own event loop.
+struct KeyboardPlatform {
+    window: Rc<MinimalSoftwareWindow>,
+    start: Instant,
+}
+
+impl Platform for KeyboardPlatform {
+    fn create_window_adapter(&self) -> Result<Rc<dyn WindowAdapter>, slint::PlatformError> {
+        Ok(self.window.clone())
+    }
+
+    fn duration_since_start(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// The keys on the page shown, and the one held down.
+#[derive(Default)]
+struct Keys {
+    shift: bool,
+    rows: Vec<Vec<Key>>,
+    held: Option<(usize, usize)>,
+    /// The key held showed its alternates, so letting go of it types nothing.
+    long_pressed: bool,
+}
+
+impl Keys {
+    fn key(&self, row: i32, column: i32) -> Option<Key> {
+        self.rows.get(row as usize)?.get(column as usize).copied()
+    }
+
+    fn set_page(&mut self, page: Page) {
+        self.shift = false;
+        self.rows = layout::rows(page);
+    }
+
+    /// What a key types, given shift.
+    fn text(&self, text: &str) -> String {
+        if self.shift {
+            layout::shifted(text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+fn show_keys(ui: &KeyboardWindow, keys: &Keys) {
+    let rows: Vec<KeyRow> = keys
+        .rows
+        .iter()
+        .enumerate()
+        .map(|(r, row)| {
+            let caps: Vec<KeyCap> = row
+                .iter()
+                .enumerate()
+                .map(|(c, key)| {
+                    let label = match key.action {
+                        Action::Text(text) if text == key.label => keys.text(text),
+                        _ => key.label.to_string(),
+                    };
+                    KeyCap {
+                        label: label.into(),
+                        row: r as i32,
+                        column: c as i32,
+                        width: key.width,
+                        special: !matches!(key.action, Action::Text(_)),
+                        active: key.action == Action::Shift && keys.shift,
+                        gap: key.action == Action::None,
+                    }
+                })
+                .collect();
+            KeyRow {
+                keys: ModelRc::new(VecModel::from(caps)),
+            }
+        })
+        .collect();
+    ui.set_rows(ModelRc::new(VecModel::from(rows)));
+}
+
+fn show_alternates(ui: &KeyboardWindow, alternates: Vec<SharedString>) {
+    ui.set_alternates(ModelRc::new(VecModel::from(alternates)));
+}
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    info!("starting keyboard");
+
+    let window = MinimalSoftwareWindow::new(RepaintBufferType::NewBuffer);
+    slint::platform::set_platform(Box::new(KeyboardPlatform {
+        window: window.clone(),
+        start: Instant::now(),
+    }))
+    .map_err(|e| anyhow::anyhow!("failed to set up drawing: {e}"))?;
+
+    let (conn, mut queue, mut state) = wayland::connect(window.clone())?;
+    let qh = queue.handle();
+
+    let ui = KeyboardWindow::new()?;
+    ui.show()?;
+
+    let keys = Rc::new(RefCell::new(Keys::default()));
+    keys.borrow_mut().set_page(Page::Letters);
+    show_keys(&ui, &keys.borrow());
+
+    let (typed_tx, typed_rx) = mpsc::channel::<Typed>();
+    let long_press = Rc::new(slint::Timer::default());
+
+    let weak = ui.as_weak();
+    let held = keys.clone();
+    let timer = long_press.clone();
+    ui.on_key_down(move |row, column| {
+        let Some(ui) = weak.upgrade() else {
+            return;
+        };
+        show_alternates(&ui, Vec::new());
+        let pressed = (row as usize, column as usize);
+        let mut keys = held.borrow_mut();
+        keys.held = Some(pressed);
+        keys.long_pressed = false;
+        let Some(Action::Text(text)) = keys.key(row, column).map(|k| k.action) else {
+            return;
+        };
+
+        let weak = weak.clone();
+        let held = held.clone();
+        timer.start(TimerMode::SingleShot, LONG_PRESS, move || {
+            let Some(ui) = weak.upgrade() else {
+                return;
+            };
+            let mut keys = held.borrow_mut();
+            let alternates: Vec<SharedString> = layout::alternates(text)
+                .iter()
+                .map(|a| keys.text(a).into())
+                .collect();
+            if keys.held == Some(pressed) && !alternates.is_empty() {
+                keys.long_pressed = true;
+                show_alternates(&ui, alternates);
+            }
+        });
+    });
+
+    let weak = ui.as_weak();
+    let held = keys.clone();
+    let timer = long_press.clone();
+    let tx = typed_tx.clone();
+    ui.on_key_up(move |row, column| {
+        timer.stop();
+        let Some(ui) = weak.upgrade() else {
+            return;
+        };
+        let mut keys = held.borrow_mut();
+        let tapped = keys.held.take() == Some((row as usize, column as usize));
+        if !tapped || keys.long_pressed {
+            return;
+        }
+        let Some(key) = keys.key(row, column) else {
+            return;
+        };
+        match key.action {
+            Action::Text(text) => {
+                let _ = tx.send(Typed::Text(keys.text(text)));
+                // Shift is for one letter
+                if keys.shift {
+                    keys.shift = false;
+                    show_keys(&ui, &keys);
+                }
+            }
+            Action::Shift => {
+                keys.shift = !keys.shift;
+                show_keys(&ui, &keys);
+            }
+            Action::Backspace => {
+                let _ = tx.send(Typed::Key(KEY_BACKSPACE));
+            }
+            Action::Enter => {
+                let _ = tx.send(Typed::Key(KEY_ENTER));
+            }
+            Action::Symbols => {
+                keys.set_page(Page::Symbols);
+                show_keys(&ui, &keys);
+            }
+            Action::Letters => {
+                keys.set_page(Page::Letters);
+                show_keys(&ui, &keys);
+            }
+            Action::None => {}
+        }
+    });
+
+    let weak = ui.as_weak();
+    let held = keys.clone();
+    ui.on_alternate_chosen(move |text| {
+        let Some(ui) = weak.upgrade() else {
+            return;
+        };
+        let _ = typed_tx.send(Typed::Text(text.to_string()));
+        show_alternates(&ui, Vec::new());
+        let mut keys = held.borrow_mut();
+        keys.long_pressed = false;
+        if keys.shift {
+            keys.shift = false;
+            show_keys(&ui, &keys);
+        }
+    });
+
+    info!("keyboard running");
+    loop {
+        while let Ok(typed) = typed_rx.try_recv() {
+            match typed {
+                Typed::Text(text) => state.type_text(&text),
+                Typed::Key(code) => state.press_key(code),
+            }
+        }
+        slint::platform::update_timers_and_animations();
+        state.present(&qh);
+        conn.flush().context("lost the compositor")?;
+
+        let Some(guard) = queue.prepare_read() else {
+            queue
+                .dispatch_pending(&mut state)
+                .context("lost the compositor")?;
+            continue;
+        };
+        let timeout = if window.has_active_animations() {
+            Some(FRAME_INTERVAL)
+        } else {
+            slint::platform::duration_until_next_timer_update()
+        };
+        let timeout = timeout.and_then(|t| Timespec::try_from(t).ok());
+        let readable = {
+            let fd = guard.connection_fd();
+            let mut fds = [PollFd::new(&fd, PollFlags::IN)];
+            match poll(&mut fds, timeout.as_ref()) {
+                Ok(ready) => ready > 0,
+                Err(Errno::INTR) => false,
+                Err(e) => return Err(e).context("poll failed"),
+            }
+        };
+        if readable {
+            guard.read().context("lost the compositor")?;
+        }
+        queue
+            .dispatch_pending(&mut state)
+            .context("lost the compositor")?;
+    }
+}
@@ -0,0 +1,486 @@
+// ABOUTME: The keyboard's Wayland side: a layer surface along the bottom of the screen while a text field
+// ABOUTME: is focused, typing text through input-method-v2 and editing keys through virtual-keyboard-v1.
+
+use std::os::fd::AsFd;
+use std::rc::Rc;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use rustix::fs::{MemfdFlags, ftruncate, memfd_create};
+use slint::platform::software_renderer::{MinimalSoftwareWindow, PremultipliedRgbaColor};
+use slint::platform::{PointerEventButton, WindowEvent};
+use slint::{LogicalPosition, PhysicalSize};
+use tracing::{info, warn};
+use wayland_client::globals::{GlobalListContents, registry_queue_init};
+use wayland_client::protocol::{
+    wl_buffer, wl_compositor, wl_keyboard, wl_pointer, wl_registry, wl_seat, wl_shm, wl_shm_pool,
+    wl_surface, wl_touch,
+};
+use wayland_client::{Connection, Dispatch, EventQueue, QueueHandle, WEnum, delegate_noop};
+use wayland_protocols_misc::zwp_input_method_v2::client::{
+    zwp_input_method_manager_v2, zwp_input_method_v2,
+};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::{
+    zwp_virtual_keyboard_manager_v1, zwp_virtual_keyboard_v1,
+};
+use wayland_protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
+
+use zwlr_layer_surface_v1::Anchor;
+
+/// Height of the keyboard, which apps give up to it while it shows.
+pub const HEIGHT: u32 = 260;
+
+const NAMESPACE: &str = "osk";
+
+/// The keymap the virtual keyboard's key codes are read with: a plain US layout, as
+/// only editing keys are sent as key codes.
+const KEYMAP: &str = "xkb_keymap {\n\
+    xkb_keycodes { include \"evdev+aliases(qwerty)\" };\n\
+    xkb_types { include \"complete\" };\n\
+    xkb_compat { include \"complete\" };\n\
+    xkb_symbols { include \"pc+us+inet(evdev)\" };\n\
+};\n";
+
+struct Surface {
+    surface: wl_surface::WlSurface,
+    layer: zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+    /// What the compositor made it, once it has said.
+    size: Option<(u32, u32)>,
+}
+
+pub struct State {
+    window: Rc<MinimalSoftwareWindow>,
+    compositor: wl_compositor::WlCompositor,
+    shm: wl_shm::WlShm,
+    layer_shell: zwlr_layer_shell_v1::ZwlrLayerShellV1,
+    input_method: zwp_input_method_v2::ZwpInputMethodV2,
+    virtual_keyboard: zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
+    touch: Option<wl_touch::WlTouch>,
+    pointer: Option<wl_pointer::WlPointer>,
+    /// The finger on a key, and where it is.
+    finger: Option<(i32, LogicalPosition)>,
+    pointer_position: LogicalPosition,
+    /// Done events received so far, which each commit to the input method names.
+    serial: u32,
+    /// Whether a text field is focused: as of the last done event, and as pending.
+    active: bool,
+    pending_active: bool,
+    surface: Option<Surface>,
+    start: Instant,
+}
+
+/// Connect to the compositor as the seat's input method. Fails when the compositor
+/// lacks a protocol the keyboard needs.
+pub fn connect(
+    window: Rc<MinimalSoftwareWindow>,
+) -> Result<(Connection, EventQueue<State>, State)> {
+    let conn = Connection::connect_to_env().context("failed to connect to the compositor")?;
+    let (globals, queue) =
+        registry_queue_init::<State>(&conn).context("failed to read the compositor's globals")?;
+    let qh = queue.handle();
+
+    let compositor: wl_compositor::WlCompositor =
+        globals.bind(&qh, 4..=6, ()).context("no wl_compositor")?;
+    let shm: wl_shm::WlShm = globals.bind(&qh, 1..=1, ()).context("no wl_shm")?;
+    let seat: wl_seat::WlSeat = globals.bind(&qh, 1..=7, ()).context("no wl_seat")?;
+    let layer_shell: zwlr_layer_shell_v1::ZwlrLayerShellV1 = globals
+        .bind(&qh, 1..=4, ())
+        .context("no zwlr_layer_shell_v1")?;
+    let input_methods: zwp_input_method_manager_v2::ZwpInputMethodManagerV2 = globals
+        .bind(&qh, 1..=1, ())
+        .context("no zwp_input_method_manager_v2")?;
+    let virtual_keyboards: zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1 = globals
+        .bind(&qh, 1..=1, ())
+        .context("no zwp_virtual_keyboard_manager_v1")?;
+
+    let input_method = input_methods.get_input_method(&seat, &qh, ());
+    let virtual_keyboard = virtual_keyboards.create_virtual_keyboard(&seat, &qh, ());
+    upload_keymap(&virtual_keyboard)?;
+
+    let state = State {
+        window,
+        compositor,
+        shm,
+        layer_shell,
+        input_method,
+        virtual_keyboard,
+        touch: None,
+        pointer: None,
+        finger: None,
+        pointer_position: LogicalPosition::new(0.0, 0.0),
+        serial: 0,
+        active: false,
+        pending_active: false,
+        surface: None,
+        start: Instant::now(),
+    };
+    Ok((conn, queue, state))
+}
+
+fn upload_keymap(keyboard: &zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1) -> Result<()> {
+    let mut keymap = KEYMAP.as_bytes().to_vec();
+    keymap.push(0);
+    let fd = memfd_create("keymap", MemfdFlags::CLOEXEC).context("memfd_create failed")?;
+    rustix::io::write(&fd, &keymap).context("failed to write the keymap")?;
+    keyboard.keymap(
+        wl_keyboard::KeymapFormat::XkbV1.into(),
+        fd.as_fd(),
+        keymap.len() as u32,
+    );
+    Ok(())
+}
+
+impl State {
+    /// Type `text` into the focused field.
+    pub fn type_text(&self, text: &str) {
+        if !self.active {
+            return;
+        }
+        self.input_method.commit_string(text.to_string());
+        self.input_method.commit(self.serial);
+    }
+
+    /// Press and release the key with evdev code `code`.
+    pub fn press_key(&self, code: u32) {
+        if !self.active {
+            return;
+        }
+        let time = self.start.elapsed().as_millis() as u32;
+        self.virtual_keyboard
+            .key(time, code, wl_keyboard::KeyState::Pressed.into());
+        self.virtual_keyboard
+            .key(time, code, wl_keyboard::KeyState::Released.into());
+    }
+
+    /// Show the keyboard while a text field is focused, and hide it otherwise.
+    fn update_visibility(&mut self, qh: &QueueHandle<Self>) {
+        match (self.active, self.surface.is_some()) {
+            (true, false) => {
+                info!("showing");
+                let surface = self.compositor.create_surface(qh, ());
+                let layer = self.layer_shell.get_layer_surface(
+                    &surface,
+                    None,
+                    zwlr_layer_shell_v1::Layer::Top,
+                    NAMESPACE.to_string(),
+                    qh,
+                    (),
+                );
+                layer.set_anchor(Anchor::Bottom | Anchor::Left | Anchor::Right);
+                layer.set_size(0, HEIGHT);
+                layer.set_exclusive_zone(HEIGHT as i32);
+                surface.commit();
+                self.surface = Some(Surface {
+                    surface,
+                    layer,
+                    size: None,
+                });
+            }
+            (false, true) => {
+                info!("hiding");
+                // Not a tap on the key under the finger, as the field it was for is gone
+                self.finger = None;
+                self.window.dispatch_event(WindowEvent::PointerExited);
+                if let Some(surface) = self.surface.take() {
+                    surface.layer.destroy();
+                    surface.surface.destroy();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Draw the keys, if anything changed since they were last drawn.
+    pub fn present(&self, qh: &QueueHandle<Self>) {
+        let Some(Surface {
+            surface,
+            size: Some((width, height)),
+            ..
+        }) = &self.surface
+        else {
+            return;
+        };
+        let (width, height) = (*width as usize, *height as usize);
+        self.window.draw_if_needed(|renderer| {
+            let mut pixels = vec![PremultipliedRgbaColor::default(); width * height];
+            renderer.render(&mut pixels, width);
+            // wl_shm's ARGB8888 is little-endian
+            let bytes: Vec<u8> = pixels
+                .iter()
+                .flat_map(|p| [p.blue, p.green, p.red, p.alpha])
+                .collect();
+            match self.shm_buffer(qh, width as i32, height as i32, &bytes) {
+                Ok(buffer) => {
+                    surface.attach(Some(&buffer), 0, 0);
+                    surface.damage_buffer(0, 0, width as i32, height as i32);
+                    surface.commit();
+                }
+                Err(e) => warn!(error = %e, "failed to draw the keys"),
+            }
+        });
+    }
+
+    /// A buffer holding `bytes`, in a pool of its own. Destroyed once released.
+    fn shm_buffer(
+        &self,
+        qh: &QueueHandle<Self>,
+        width: i32,
+        height: i32,
+        bytes: &[u8],
+    ) -> Result<wl_buffer::WlBuffer> {
+        let fd = memfd_create("osk-buffer", MemfdFlags::CLOEXEC).context("memfd_create failed")?;
+        ftruncate(&fd, bytes.len() as u64).context("ftruncate failed")?;
+        rustix::io::write(&fd, bytes).context("failed to fill buffer")?;
+
+        let pool = self.shm.create_pool(fd.as_fd(), bytes.len() as i32, qh, ());
+        let buffer = pool.create_buffer(
+            0,
+            width,
+            height,
+            width * 4,
+            wl_shm::Format::Argb8888,
+            qh,
+            (),
+        );
+        pool.destroy();
+        Ok(buffer)
+    }
+
+    fn press_at(&self, position: LogicalPosition) {
+        self.window
+            .dispatch_event(WindowEvent::PointerMoved { position });
+        self.window.dispatch_event(WindowEvent::PointerPressed {
+            position,
+            button: PointerEventButton::Left,
+        });
+    }
+
+    fn release_at(&self, position: LogicalPosition) {
+        self.window.dispatch_event(WindowEvent::PointerReleased {
+            position,
+            button: PointerEventButton::Left,
+        });
+        self.window.dispatch_event(WindowEvent::PointerExited);
+    }
+
+    /// Let go of the key under the finger, if one is down.
+    fn release_finger(&mut self) {
+        if let Some((_, position)) = self.finger.take() {
+            self.release_at(position);
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_seat::WlSeat, ()> for State {
+    fn event(
+        state: &mut Self,
+        seat: &wl_seat::WlSeat,
+        event: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wl_seat::Event::Capabilities {
+            capabilities: WEnum::Value(capabilities),
+        } = event
+        else {
+            return;
+        };
+        if capabilities.contains(wl_seat::Capability::Touch) && state.touch.is_none() {
+            state.touch = Some(seat.get_touch(qh, ()));
+        }
+        // For a mouse, as on a desktop while developing
+        if capabilities.contains(wl_seat::Capability::Pointer) && state.pointer.is_none() {
+            state.pointer = Some(seat.get_pointer(qh, ()));
+        }
+    }
+}
+
+impl Dispatch<wl_touch::WlTouch, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &wl_touch::WlTouch,
+        event: wl_touch::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_touch::Event::Down { id, x, y, .. } => {
+                // Typing with two thumbs: the key under the first is let go of
+                state.release_finger();
+                let position = LogicalPosition::new(x as f32, y as f32);
+                state.finger = Some((id, position));
+                state.press_at(position);
+            }
+            wl_touch::Event::Motion { id, x, y, .. } => {
+                if let Some((finger, position)) = &mut state.finger
+                    && *finger == id
+                {
+                    *position = LogicalPosition::new(x as f32, y as f32);
+                    let position = *position;
+                    state
+                        .window
+                        .dispatch_event(WindowEvent::PointerMoved { position });
+                }
+            }
+            wl_touch::Event::Up { id, .. } => {
+                if state.finger.is_some_and(|(finger, _)| finger == id) {
+                    state.release_finger();
+                }
+            }
+            wl_touch::Event::Cancel => {
+                state.finger = None;
+                state.window.dispatch_event(WindowEvent::PointerExited);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_pointer::WlPointer, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &wl_pointer::WlPointer,
+        event: wl_pointer::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_pointer::Event::Enter {
+                surface_x,
+                surface_y,
+                ..
+            }
+            | wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                let position = LogicalPosition::new(surface_x as f32, surface_y as f32);
+                state.pointer_position = position;
+                state
+                    .window
+                    .dispatch_event(WindowEvent::PointerMoved { position });
+            }
+            wl_pointer::Event::Button {
+                state: WEnum::Value(button),
+                ..
+            } => {
+                if button == wl_pointer::ButtonState::Pressed {
+                    state.press_at(state.pointer_position);
+                } else {
+                    state.window.dispatch_event(WindowEvent::PointerReleased {
+                        position: state.pointer_position,
+                        button: PointerEventButton::Left,
+                    });
+                }
+            }
+            wl_pointer::Event::Leave { .. } => {
+                state.window.dispatch_event(WindowEvent::PointerExited);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        layer: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure {
+                serial,
+                width,
+                height,
+            } => {
+                layer.ack_configure(serial);
+                let Some(surface) = state.surface.as_mut().filter(|s| &s.layer == layer) else {
+                    return;
+                };
+                let size = (width.max(1), if height == 0 { HEIGHT } else { height });
+                surface.size = Some(size);
+                state.window.set_size(PhysicalSize::new(size.0, size.1));
+                state.window.request_redraw();
+            }
+            zwlr_layer_surface_v1::Event::Closed => {
+                if let Some(surface) = state.surface.take_if(|s| &s.layer == layer) {
+                    surface.layer.destroy();
+                    surface.surface.destroy();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<zwp_input_method_v2::ZwpInputMethodV2, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &zwp_input_method_v2::ZwpInputMethodV2,
+        event: zwp_input_method_v2::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwp_input_method_v2::Event::Activate => state.pending_active = true,
+            zwp_input_method_v2::Event::Deactivate => state.pending_active = false,
+            // What came before applies from here, and later commits name it
+            zwp_input_method_v2::Event::Done => {
+                state.serial += 1;
+                state.active = state.pending_active;
+                state.update_visibility(qh);
+            }
+            zwp_input_method_v2::Event::Unavailable => {
+                warn!("another input method is running; staying hidden");
+                state.active = false;
+                state.pending_active = false;
+                state.update_visibility(qh);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_buffer::WlBuffer, ()> for State {
+    fn event(
+        _: &mut Self,
+        buffer: &wl_buffer::WlBuffer,
+        event: wl_buffer::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            buffer.destroy();
+        }
+    }
+}
+
+delegate_noop!(State: wl_compositor::WlCompositor);
+delegate_noop!(State: wl_shm_pool::WlShmPool);
+delegate_noop!(State: zwlr_layer_shell_v1::ZwlrLayerShellV1);
+delegate_noop!(State: zwp_input_method_manager_v2::ZwpInputMethodManagerV2);
+delegate_noop!(State: zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1);
+delegate_noop!(State: zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1);
+delegate_noop!(State: ignore wl_shm::WlShm);
+delegate_noop!(State: ignore wl_surface::WlSurface);
@@ -1,8 +1,11 @@
 // ABOUTME: SMS messaging application for MobileOS.
-// ABOUTME: Connects to org.mobileos.Modem via D-Bus for sending messages.
+// ABOUTME: Connects to org.mobileos.Modem via D-Bus for sending messages, and shows how far each got from its outbox.
 
-use std::sync::mpsc;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, mpsc};
 
+use futures_util::StreamExt;
+use slint::VecModel;
 use tracing::info;
 
 slint::include_modules!();
@@ -12,13 +15,34 @@ struct SmsCommand {
     message: String,
 }
 
+/// A message in the modem service's outbox: id, number, text, when it was written,
+/// and whether it is pending, sent, delivered or failed.
+type OutgoingSmsEntry = (u32, String, String, u64, String);
+
 #[zbus::proxy(
     interface = "org.mobileos.Modem",
     default_service = "org.mobileos.Modem",
     default_path = "/org/mobileos/Modem"
 )]
 trait Modem {
-    fn send_sms(&self, number: &str, message: &str) -> zbus::Result<()>;
+    fn send_sms(&self, number: &str, message: &str) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn outbox(&self) -> zbus::Result<Vec<OutgoingSmsEntry>>;
+}
+
+/// Show what was sent to the contact whose thread is open.
+fn show_sent(window: &MessagesWindow, outbox: &[OutgoingSmsEntry]) {
+    let contact = window.get_selected_contact();
+    let sent: Vec<SentMessage> = outbox
+        .iter()
+        .filter(|(_, number, _, _, _)| *number == contact.as_str())
+        .map(|(_, _, text, _, status)| SentMessage {
+            text: text.into(),
+            status: status.into(),
+        })
+        .collect();
+    window.set_sent(Rc::new(VecModel::from(sent)).into());
 }
 
 fn main() -> anyhow::Result<()> {
@@ -33,6 +57,8 @@ fn main() -> anyhow::Result<()> {
 
     let window = MessagesWindow::new()?;
     let (sms_tx, sms_rx) = mpsc::channel::<SmsCommand>();
+    // The outbox as the modem service last listed it
+    let outbox: Arc<Mutex<Vec<OutgoingSmsEntry>>> = Arc::default();
 
     window.on_send_message(move |contact, text| {
         let contact = contact.to_string();
@@ -46,11 +72,20 @@ fn main() -> anyhow::Result<()> {
         }
     });
 
+    let weak = window.as_weak();
+    let opened = outbox.clone();
+    window.on_thread_opened(move |_| {
+        if let Some(w) = weak.upgrade() {
+            show_sent(&w, &opened.lock().unwrap());
+        }
+    });
+
     // Background tokio thread for D-Bus communication
+    let weak = window.as_weak();
     std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async move {
-            let conn = match zbus::Connection::session().await {
+            let conn = match zbus::Connection::system().await {
                 Ok(c) => c,
                 Err(e) => {
                     info!("D-Bus not available: {e}");
@@ -66,9 +101,29 @@ fn main() -> anyhow::Result<()> {
                 }
             };
 
+            // Messages move from pending to sent to delivered as the network reports
+            let changes = proxy.clone();
+            tokio::spawn(async move {
+                let mut changes = changes.receive_outbox_changed().await;
+                while let Some(change) = changes.next().await {
+                    let Ok(list) = change.get().await else {
+                        continue;
+                    };
+                    *outbox.lock().unwrap() = list;
+                    let weak = weak.clone();
+                    let outbox = outbox.clone();
+                    let _ = slint::invoke_from_event_loop(move || {
+                        if let Some(w) = weak.upgrade() {
+                            show_sent(&w, &outbox.lock().unwrap());
+                        }
+                    });
+                }
+            });
+
             while let Ok(cmd) = sms_rx.recv() {
-                if let Err(e) = proxy.send_sms(&cmd.number, &cmd.message).await {
-                    info!("send_sms failed: {e}");
+                match proxy.send_sms(&cmd.number, &cmd.message).await {
+                    Ok(id) => info!(id, "message queued"),
+                    Err(e) => info!("send_sms failed: {e}"),
                 }
             }
         });
@@ -1,9 +1,17 @@
 // ABOUTME: System settings application for MobileOS.
-// ABOUTME: Connects to Power, Network, and Audio services via D-Bus.
+// ABOUTME: Connects to Power, Network, Audio, Sensors, Modem, and Wellbeing services and the compositor via D-Bus.
 
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
+use std::time::Duration;
 
-use tracing::info;
+use anyhow::{Context, bail};
+use futures_util::StreamExt;
+use tracing::{info, warn};
 
 slint::include_modules!();
 
@@ -11,11 +19,71 @@ enum SettingsCommand {
     WifiScan,
     WifiConnect(String),
     WifiDisconnect,
+    LoadProxy(String),
+    SaveProxy(String, ProxyForm),
+    ClearProxy(String),
+    RefreshData,
+    /// Warning and cap in MB, and the cycle's first day, as typed.
+    SetDataPlan(String, String, String),
+    SetApn(ModemApn),
+    ResetApn,
     SetBrightness(u8),
     SetVolume(u8),
     SetMuted(bool),
+    CompassCalibrate,
+    CompassCancel,
+    SetColorFilter(String),
+    SetColorFilterEnabled(bool),
+    SetSwitchAccess(String),
+    RebootToRecovery,
+    RefreshUsage,
+    RefreshScreenTime,
+    /// App, minutes, hard, and the owner PIN.
+    SetAppTimer(String, u32, bool, String),
+    /// Enabled, start, end, and the owner PIN.
+    SetBedtime(bool, String, String, String),
+    /// The owner PIN, and the new one, empty to remove it.
+    SetScreenTimePin(String, String),
 }
 
+/// The proxy page as filled in.
+struct ProxyForm {
+    mode: String,
+    http: String,
+    https: String,
+    socks: String,
+    /// Comma-separated.
+    ignore_hosts: String,
+    pac_url: String,
+}
+
+/// A proxy as the network service gives it: mode, servers by scheme, hosts reached
+/// directly, and PAC URL.
+type NetworkProxySettings = (String, HashMap<String, String>, Vec<String>, String);
+
+/// An APN as the modem service gives it: carrier, APN, user name, password,
+/// authentication, IP type, MMSC and MMS proxy.
+type ModemApn = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+);
+
+/// An app's day as the wellbeing service gives it: app, seconds on screen and
+/// notifications.
+type AppUsage = (String, u64, u32);
+
+/// initd's control socket, which reports what each service has used.
+const INIT_CONTROL_SOCKET: &str = "/run/mos/initctl";
+
+/// How often the calibration page checks how far the figure-eight sweep got.
+const CALIBRATION_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
 #[zbus::proxy(
     interface = "org.mobileos.Power",
     default_service = "org.mobileos.Power",
@@ -30,6 +98,8 @@ trait Power {
 
     #[zbus(property)]
     fn set_screen_brightness(&self, value: u8) -> zbus::Result<()>;
+
+    fn reboot(&self, mode: &str, reason: &str) -> zbus::Result<()>;
 }
 
 #[zbus::proxy(
@@ -47,6 +117,26 @@ trait Network {
     fn scan(&self) -> zbus::Result<Vec<String>>;
     fn connect(&self, ssid: &str, password: &str) -> zbus::Result<()>;
     fn disconnect(&self) -> zbus::Result<()>;
+
+    fn proxy(&self, ssid: &str) -> zbus::Result<NetworkProxySettings>;
+    fn set_proxy(
+        &self,
+        ssid: &str,
+        mode: &str,
+        servers: HashMap<&str, &str>,
+        ignore_hosts: &[&str],
+        pac_url: &str,
+    ) -> zbus::Result<()>;
+    fn clear_proxy(&self, ssid: &str) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn data_plan(&self) -> zbus::Result<(u64, u64, u32)>;
+
+    #[zbus(property)]
+    fn data_capped(&self) -> zbus::Result<bool>;
+
+    fn set_data_plan(&self, warning: u64, cap: u64, cycle_day: u32) -> zbus::Result<()>;
+    fn data_cycle(&self) -> zbus::Result<Vec<(String, u64)>>;
 }
 
 #[zbus::proxy(
@@ -68,6 +158,95 @@ trait Audio {
     fn set_muted(&self, value: bool) -> zbus::Result<()>;
 }
 
+#[zbus::proxy(
+    interface = "org.mobileos.Sensors",
+    default_service = "org.mobileos.Sensors",
+    default_path = "/org/mobileos/Sensors"
+)]
+trait Sensors {
+    #[zbus(property)]
+    fn needs_calibration(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn calibration_progress(&self) -> zbus::Result<u8>;
+
+    fn start_calibration(&self) -> zbus::Result<()>;
+    fn finish_calibration(&self) -> zbus::Result<()>;
+    fn cancel_calibration(&self) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn calibration_needed(&self) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Modem",
+    default_service = "org.mobileos.Modem",
+    default_path = "/org/mobileos/Modem"
+)]
+trait Modem {
+    #[zbus(property)]
+    fn firmware_version(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn baseband(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn apn(&self) -> zbus::Result<ModemApn>;
+
+    #[zbus(property)]
+    fn apn_manual(&self) -> zbus::Result<bool>;
+
+    fn imei(&self) -> zbus::Result<String>;
+    fn set_apn(&self, apn: &ModemApn) -> zbus::Result<()>;
+    fn reset_apn(&self) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Compositor",
+    default_service = "org.mobileos.Compositor",
+    default_path = "/org/mobileos/Compositor"
+)]
+trait Compositor {
+    #[zbus(property)]
+    fn color_filter(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn color_filter_enabled(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn switch_access(&self) -> zbus::Result<String>;
+
+    fn set_color_filter(&self, name: &str) -> zbus::Result<()>;
+    fn set_color_filter_enabled(&self, enabled: bool) -> zbus::Result<()>;
+    fn set_switch_access(&self, mode: &str) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Wellbeing",
+    default_service = "org.mobileos.Wellbeing",
+    default_path = "/org/mobileos/Wellbeing"
+)]
+trait Wellbeing {
+    fn today(&self) -> zbus::Result<(u32, Vec<AppUsage>)>;
+    fn week(&self) -> zbus::Result<Vec<(String, u64)>>;
+
+    #[zbus(property)]
+    fn app_timers(&self) -> zbus::Result<HashMap<String, (u32, bool)>>;
+
+    #[zbus(property)]
+    fn times_up(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(property)]
+    fn bedtime(&self) -> zbus::Result<(bool, String, String)>;
+
+    #[zbus(property)]
+    fn pin_set(&self) -> zbus::Result<bool>;
+
+    fn set_app_timer(&self, app: &str, minutes: u32, hard: bool, pin: &str) -> zbus::Result<()>;
+    fn set_bedtime(&self, enabled: bool, start: &str, end: &str, pin: &str) -> zbus::Result<()>;
+    fn set_pin(&self, pin: &str, new_pin: &str) -> zbus::Result<()>;
+}
+
 fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
         .with_env_filter(
@@ -96,6 +275,33 @@ fn main() -> anyhow::Result<()> {
         let _ = tx.send(SettingsCommand::WifiDisconnect);
     });
 
+    let tx = cmd_tx.clone();
+    window.on_proxy_load(move |ssid| {
+        let _ = tx.send(SettingsCommand::LoadProxy(ssid.to_string()));
+    });
+
+    let tx = cmd_tx.clone();
+    let weak = window.as_weak();
+    window.on_proxy_save(move |ssid| {
+        let Some(w) = weak.upgrade() else {
+            return;
+        };
+        let form = ProxyForm {
+            mode: w.get_proxy_mode().to_string(),
+            http: w.get_proxy_http().trim().to_string(),
+            https: w.get_proxy_https().trim().to_string(),
+            socks: w.get_proxy_socks().trim().to_string(),
+            ignore_hosts: w.get_proxy_ignore_hosts().to_string(),
+            pac_url: w.get_proxy_pac_url().trim().to_string(),
+        };
+        let _ = tx.send(SettingsCommand::SaveProxy(ssid.to_string(), form));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_proxy_clear(move |ssid| {
+        let _ = tx.send(SettingsCommand::ClearProxy(ssid.to_string()));
+    });
+
     let tx = cmd_tx.clone();
     window.on_brightness_changed(move |val| {
         let _ = tx.send(SettingsCommand::SetBrightness(val as u8));
@@ -106,17 +312,125 @@ fn main() -> anyhow::Result<()> {
         let _ = tx.send(SettingsCommand::SetVolume(val as u8));
     });
 
-    let tx = cmd_tx;
+    let tx = cmd_tx.clone();
     window.on_mute_toggled(move |muted| {
         let _ = tx.send(SettingsCommand::SetMuted(muted));
     });
 
+    let tx = cmd_tx.clone();
+    window.on_compass_calibrate(move || {
+        let _ = tx.send(SettingsCommand::CompassCalibrate);
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_compass_cancel(move || {
+        let _ = tx.send(SettingsCommand::CompassCancel);
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_color_filter_chosen(move |name| {
+        let _ = tx.send(SettingsCommand::SetColorFilter(name.to_string()));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_color_filter_toggled(move |enabled| {
+        let _ = tx.send(SettingsCommand::SetColorFilterEnabled(enabled));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_switch_access_chosen(move |mode| {
+        let _ = tx.send(SettingsCommand::SetSwitchAccess(mode.to_string()));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_reboot_to_recovery(move || {
+        let _ = tx.send(SettingsCommand::RebootToRecovery);
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_usage_refresh(move || {
+        let _ = tx.send(SettingsCommand::RefreshUsage);
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_data_refresh(move || {
+        let _ = tx.send(SettingsCommand::RefreshData);
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_data_plan_save(move |warning, cap, cycle_day| {
+        let _ = tx.send(SettingsCommand::SetDataPlan(
+            warning.to_string(),
+            cap.to_string(),
+            cycle_day.to_string(),
+        ));
+    });
+
+    let tx = cmd_tx.clone();
+    let weak = window.as_weak();
+    window.on_apn_save(move || {
+        let Some(w) = weak.upgrade() else {
+            return;
+        };
+        let field = |text: slint::SharedString| text.trim().to_string();
+        let apn = (
+            w.get_apn_carrier().to_string(),
+            field(w.get_apn_name()),
+            field(w.get_apn_username()),
+            w.get_apn_password().to_string(),
+            w.get_apn_auth().to_string(),
+            w.get_apn_ip_type().to_string(),
+            field(w.get_apn_mmsc()),
+            field(w.get_apn_mms_proxy()),
+        );
+        let _ = tx.send(SettingsCommand::SetApn(apn));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_apn_reset(move || {
+        let _ = tx.send(SettingsCommand::ResetApn);
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_screen_time_refresh(move || {
+        let _ = tx.send(SettingsCommand::RefreshScreenTime);
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_app_timer_set(move |app, minutes, hard, pin| {
+        let minutes = u32::try_from(minutes).unwrap_or(0);
+        let _ = tx.send(SettingsCommand::SetAppTimer(
+            app.to_string(),
+            minutes,
+            hard,
+            pin.to_string(),
+        ));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_bedtime_save(move |enabled, start, end, pin| {
+        let _ = tx.send(SettingsCommand::SetBedtime(
+            enabled,
+            start.to_string(),
+            end.to_string(),
+            pin.to_string(),
+        ));
+    });
+
+    let tx = cmd_tx;
+    window.on_screen_time_pin_changed(move |pin, new_pin| {
+        let _ = tx.send(SettingsCommand::SetScreenTimePin(
+            pin.to_string(),
+            new_pin.to_string(),
+        ));
+    });
+
     // Background tokio thread for D-Bus communication
     let weak = window.as_weak();
     std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async move {
-            let conn = match zbus::Connection::session().await {
+            let conn = match zbus::Connection::system().await {
                 Ok(c) => c,
                 Err(e) => {
                     info!("D-Bus not available: {e}");
@@ -127,6 +441,19 @@ fn main() -> anyhow::Result<()> {
             let power = PowerProxy::new(&conn).await.ok();
             let network = NetworkProxy::new(&conn).await.ok();
             let audio = AudioProxy::new(&conn).await.ok();
+            let sensors = SensorsProxy::new(&conn).await.ok();
+            let modem = ModemProxy::new(&conn).await.ok();
+            let compositor = CompositorProxy::builder(&conn)
+                .cache_properties(zbus::proxy::CacheProperties::No)
+                .build()
+                .await
+                .ok();
+            let wellbeing = WellbeingProxy::builder(&conn)
+                .cache_properties(zbus::proxy::CacheProperties::No)
+                .build()
+                .await
+                .ok();
+            let calibrating = Arc::new(AtomicBool::new(false));
 
             // Load initial state
             if let Some(ref p) = power {
@@ -180,6 +507,67 @@ fn main() -> anyhow::Result<()> {
                 }
             }
 
+            if let Some(ref s) = sensors {
+                if let Ok(needed) = s.needs_calibration().await {
+                    let weak = weak.clone();
+                    let _ = slint::invoke_from_event_loop(move || {
+                        if let Some(w) = weak.upgrade() {
+                            w.set_compass_needs_calibration(needed);
+                        }
+                    });
+                }
+                if let Ok(mut needed) = s.receive_calibration_needed().await {
+                    let weak = weak.clone();
+                    tokio::spawn(async move {
+                        while needed.next().await.is_some() {
+                            let weak = weak.clone();
+                            let _ = slint::invoke_from_event_loop(move || {
+                                if let Some(w) = weak.upgrade() {
+                                    w.set_compass_needs_calibration(true);
+                                }
+                            });
+                        }
+                    });
+                }
+            }
+
+            // Phones without a modem have no modem service, and the About page leaves it out
+            if let Some(ref m) = modem
+                && let Ok(baseband) = m.baseband().await
+            {
+                let firmware = m.firmware_version().await.unwrap_or_default();
+                let imei = match m.imei().await {
+                    Ok(imei) => imei,
+                    Err(e) => {
+                        warn!(error = %e, "failed to read the IMEI");
+                        String::new()
+                    }
+                };
+                let weak = weak.clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(w) = weak.upgrade() {
+                        w.set_modem_baseband(baseband.into());
+                        w.set_modem_firmware(firmware.into());
+                        w.set_modem_imei(imei.into());
+                    }
+                });
+            }
+
+            if let Some(ref c) = compositor
+                && let Ok(filter) = c.color_filter().await
+            {
+                let enabled = c.color_filter_enabled().await.unwrap_or(false);
+                let switch_access = c.switch_access().await.unwrap_or_else(|_| "off".into());
+                let weak = weak.clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(w) = weak.upgrade() {
+                        w.set_color_filter(filter.into());
+                        w.set_color_filter_on(enabled);
+                        w.set_switch_access(switch_access.into());
+                    }
+                });
+            }
+
             while let Ok(cmd) = cmd_rx.recv() {
                 match cmd {
                     SettingsCommand::WifiScan => {
@@ -225,6 +613,68 @@ fn main() -> anyhow::Result<()> {
                             });
                         }
                     }
+                    SettingsCommand::LoadProxy(ssid) => {
+                        let Some(ref n) = network else {
+                            continue;
+                        };
+                        match n.proxy(&ssid).await {
+                            Ok(proxy) => show_proxy(&weak, proxy),
+                            Err(e) => {
+                                warn!(error = %e, "failed to read the proxy settings");
+                                set_proxy_status(&weak, format!("Couldn't read the proxy: {e}"));
+                            }
+                        }
+                    }
+                    SettingsCommand::SaveProxy(ssid, form) => {
+                        let Some(ref n) = network else {
+                            continue;
+                        };
+                        let servers: HashMap<&str, &str> = [
+                            ("http", form.http.as_str()),
+                            ("https", form.https.as_str()),
+                            ("socks", form.socks.as_str()),
+                        ]
+                        .into_iter()
+                        .filter(|(_, server)| !server.is_empty())
+                        .collect();
+                        let ignore_hosts: Vec<&str> = form
+                            .ignore_hosts
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|host| !host.is_empty())
+                            .collect();
+                        let status = match n
+                            .set_proxy(&ssid, &form.mode, servers, &ignore_hosts, &form.pac_url)
+                            .await
+                        {
+                            Ok(()) => "Saved; apps started from now on use it".to_string(),
+                            Err(e) => {
+                                warn!(error = %e, "failed to set the proxy");
+                                format!("Couldn't save the proxy: {e}")
+                            }
+                        };
+                        set_proxy_status(&weak, status);
+                    }
+                    SettingsCommand::ClearProxy(ssid) => {
+                        let Some(ref n) = network else {
+                            continue;
+                        };
+                        if let Err(e) = n.clear_proxy(&ssid).await {
+                            warn!(error = %e, "failed to clear the proxy");
+                            set_proxy_status(&weak, format!("Couldn't clear the proxy: {e}"));
+                            continue;
+                        }
+                        // The network uses the default proxy again, which the page shows
+                        if let Ok(proxy) = n.proxy("").await {
+                            let weak = weak.clone();
+                            let _ = slint::invoke_from_event_loop(move || {
+                                if let Some(w) = weak.upgrade() {
+                                    w.set_proxy_this_network(false);
+                                }
+                            });
+                            show_proxy(&weak, proxy);
+                        }
+                    }
                     SettingsCommand::SetBrightness(val) => {
                         if let Some(ref p) = power {
                             let _ = p.set_screen_brightness(val).await;
@@ -240,6 +690,189 @@ fn main() -> anyhow::Result<()> {
                             let _ = a.set_muted(muted).await;
                         }
                     }
+                    SettingsCommand::CompassCalibrate => {
+                        if let Some(ref s) = sensors {
+                            if let Err(e) = s.start_calibration().await {
+                                warn!(error = %e, "failed to start compass calibration");
+                                continue;
+                            }
+                            calibrating.store(true, Ordering::Relaxed);
+                            tokio::spawn(run_calibration(
+                                s.clone(),
+                                calibrating.clone(),
+                                weak.clone(),
+                            ));
+                        }
+                    }
+                    SettingsCommand::CompassCancel => {
+                        calibrating.store(false, Ordering::Relaxed);
+                        if let Some(ref s) = sensors {
+                            let _ = s.cancel_calibration().await;
+                        }
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                w.set_compass_calibrating(false);
+                            }
+                        });
+                    }
+                    SettingsCommand::SetColorFilter(name) => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.set_color_filter(&name).await
+                        {
+                            warn!(filter = %name, error = %e, "failed to set the color filter");
+                        }
+                    }
+                    SettingsCommand::SetColorFilterEnabled(enabled) => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.set_color_filter_enabled(enabled).await
+                        {
+                            warn!(enabled, error = %e, "failed to toggle the color filter");
+                        }
+                    }
+                    SettingsCommand::SetSwitchAccess(mode) => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.set_switch_access(&mode).await
+                        {
+                            warn!(mode = %mode, error = %e, "failed to set switch access");
+                        }
+                    }
+                    SettingsCommand::RebootToRecovery => {
+                        let Some(ref p) = power else {
+                            continue;
+                        };
+                        if let Err(e) = p.reboot("recovery", "requested from Settings").await {
+                            warn!(error = %e, "failed to reboot to recovery");
+                            let weak = weak.clone();
+                            let _ = slint::invoke_from_event_loop(move || {
+                                if let Some(w) = weak.upgrade() {
+                                    w.set_reboot_status(format!("Reboot failed: {e}").into());
+                                }
+                            });
+                        }
+                    }
+                    SettingsCommand::RefreshUsage => {
+                        let (usage, status) = match service_usage(INIT_CONTROL_SOCKET) {
+                            Ok(usage) => (usage, String::new()),
+                            Err(e) => {
+                                warn!(error = %e, "failed to read service usage");
+                                (Vec::new(), format!("Couldn't read usage: {e:#}"))
+                            }
+                        };
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                let model = std::rc::Rc::new(slint::VecModel::from(usage));
+                                w.set_service_usage(model.into());
+                                w.set_usage_status(status.into());
+                            }
+                        });
+                    }
+                    SettingsCommand::RefreshData => {
+                        if let Some(ref n) = network {
+                            show_data(n, &weak).await;
+                        }
+                        if let Some(ref m) = modem {
+                            show_apn(m, &weak).await;
+                        }
+                    }
+                    SettingsCommand::SetDataPlan(warning, cap, cycle_day) => {
+                        let Some(ref n) = network else {
+                            continue;
+                        };
+                        let plan = parse_data_plan(&warning, &cap, &cycle_day);
+                        let saved = match plan {
+                            Ok((warning, cap, cycle_day)) => n
+                                .set_data_plan(warning, cap, cycle_day)
+                                .await
+                                .map_err(anyhow::Error::from),
+                            Err(e) => Err(e),
+                        };
+                        // A plan that doesn't make sense stays on the page to be fixed
+                        if let Err(e) = saved {
+                            let weak = weak.clone();
+                            let _ = slint::invoke_from_event_loop(move || {
+                                if let Some(w) = weak.upgrade() {
+                                    w.set_data_status(format!("Plan not saved: {e:#}").into());
+                                }
+                            });
+                            continue;
+                        }
+                        show_data(n, &weak).await;
+                    }
+                    SettingsCommand::SetApn(apn) => {
+                        let Some(ref m) = modem else {
+                            continue;
+                        };
+                        // An APN the modem refuses stays on the page to be fixed
+                        if let Err(e) = m.set_apn(&apn).await {
+                            let message = match zbus::fdo::Error::from(e) {
+                                zbus::fdo::Error::InvalidArgs(message) => message,
+                                e => e.to_string(),
+                            };
+                            let weak = weak.clone();
+                            let _ = slint::invoke_from_event_loop(move || {
+                                if let Some(w) = weak.upgrade() {
+                                    w.set_apn_status(format!("APN not saved: {message}").into());
+                                }
+                            });
+                            continue;
+                        }
+                        show_apn(m, &weak).await;
+                    }
+                    SettingsCommand::ResetApn => {
+                        if let Some(ref m) = modem {
+                            if let Err(e) = m.reset_apn().await {
+                                warn!(error = %e, "failed to reset the APN");
+                            }
+                            show_apn(m, &weak).await;
+                        }
+                    }
+                    SettingsCommand::RefreshScreenTime => {
+                        if let Some(ref wb) = wellbeing {
+                            show_screen_time(wb, &weak).await;
+                        }
+                    }
+                    SettingsCommand::SetAppTimer(app, minutes, hard, pin) => {
+                        let Some(ref wb) = wellbeing else {
+                            continue;
+                        };
+                        if let Err(e) = wb.set_app_timer(&app, minutes, hard, &pin).await {
+                            warn!(app = %app, minutes, error = %e, "failed to set the app timer");
+                            screen_time_refused(&weak, "Timer not set", e);
+                            continue;
+                        }
+                        show_screen_time(wb, &weak).await;
+                    }
+                    SettingsCommand::SetBedtime(enabled, start, end, pin) => {
+                        let Some(ref wb) = wellbeing else {
+                            continue;
+                        };
+                        // A time that isn't one stays on the page to be fixed
+                        if let Err(e) = wb.set_bedtime(enabled, &start, &end, &pin).await {
+                            screen_time_refused(&weak, "Bedtime not set", e);
+                            continue;
+                        }
+                        show_screen_time(wb, &weak).await;
+                    }
+                    SettingsCommand::SetScreenTimePin(pin, new_pin) => {
+                        let Some(ref wb) = wellbeing else {
+                            continue;
+                        };
+                        if let Err(e) = wb.set_pin(&pin, &new_pin).await {
+                            screen_time_refused(&weak, "PIN not set", e);
+                            continue;
+                        }
+                        // The new PIN is the one to give from now on
+                        let weak_pin = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak_pin.upgrade() {
+                                w.set_screen_time_pin(new_pin.into());
+                                w.set_screen_time_new_pin("".into());
+                            }
+                        });
+                        show_screen_time(wb, &weak).await;
+                    }
                 }
             }
         });
@@ -250,3 +883,339 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Fill in the proxy page from the network service's settings.
+fn show_proxy(weak: &slint::Weak<SettingsWindow>, proxy: NetworkProxySettings) {
+    let (mode, servers, ignore_hosts, pac_url) = proxy;
+    let server = |scheme: &str| servers.get(scheme).cloned().unwrap_or_default();
+    let (http, https, socks) = (server("http"), server("https"), server("socks"));
+    let ignore_hosts = ignore_hosts.join(", ");
+    let weak = weak.clone();
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(w) = weak.upgrade() {
+            w.set_proxy_mode(mode.into());
+            w.set_proxy_http(http.into());
+            w.set_proxy_https(https.into());
+            w.set_proxy_socks(socks.into());
+            w.set_proxy_ignore_hosts(ignore_hosts.into());
+            w.set_proxy_pac_url(pac_url.into());
+            w.set_proxy_status("".into());
+        }
+    });
+}
+
+fn set_proxy_status(weak: &slint::Weak<SettingsWindow>, status: String) {
+    let weak = weak.clone();
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(w) = weak.upgrade() {
+            w.set_proxy_status(status.into());
+        }
+    });
+}
+
+/// Fill in the mobile data page from the network service: the cycle so far by day,
+/// and the plan.
+async fn show_data(network: &NetworkProxy<'_>, weak: &slint::Weak<SettingsWindow>) {
+    let (cycle, plan) = match (network.data_cycle().await, network.data_plan().await) {
+        (Ok(cycle), Ok(plan)) => (cycle, plan),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!(error = %e, "failed to read mobile data use");
+            let weak = weak.clone();
+            let _ = slint::invoke_from_event_loop(move || {
+                if let Some(w) = weak.upgrade() {
+                    w.set_data_status(format!("Couldn't read mobile data use: {e}").into());
+                }
+            });
+            return;
+        }
+    };
+    let capped = network.data_capped().await.unwrap_or(false);
+    let (warning, cap, cycle_day) = plan;
+
+    let used: u64 = cycle.iter().map(|(_, bytes)| bytes).sum();
+    let used = match cap {
+        0 => format!("{} this cycle", format_bytes(used)),
+        cap => format!("{} of {} this cycle", format_bytes(used), format_bytes(cap)),
+    };
+    let busiest = cycle
+        .iter()
+        .fold(1, |busiest, (_, bytes)| busiest.max(*bytes));
+    let cycle: Vec<DayDataUse> = cycle
+        .into_iter()
+        .map(|(date, bytes)| DayDataUse {
+            day: chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                .map(|date| date.format("%-d").to_string())
+                .unwrap_or(date)
+                .into(),
+            share: bytes as f32 / busiest as f32,
+        })
+        .collect();
+    let megabytes = |bytes: u64| match bytes {
+        0 => String::new(),
+        bytes => (bytes / MB).to_string(),
+    };
+    let (warning, cap) = (megabytes(warning), megabytes(cap));
+
+    let weak = weak.clone();
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(w) = weak.upgrade() {
+            w.set_data_used(used.into());
+            w.set_data_cycle(std::rc::Rc::new(slint::VecModel::from(cycle)).into());
+            w.set_data_capped(capped);
+            w.set_data_warning(warning.into());
+            w.set_data_cap(cap.into());
+            w.set_data_cycle_day(cycle_day.to_string().into());
+            w.set_data_status("".into());
+        }
+    });
+}
+
+/// Fill in the access point from the modem service, which is missing on phones
+/// without a modem.
+async fn show_apn(modem: &ModemProxy<'_>, weak: &slint::Weak<SettingsWindow>) {
+    let apn = match modem.apn().await {
+        Ok(apn) => apn,
+        Err(e) => {
+            warn!(error = %e, "failed to read the APN");
+            return;
+        }
+    };
+    let manual = modem.apn_manual().await.unwrap_or(false);
+    let (carrier, name, username, password, auth, ip_type, mmsc, mms_proxy) = apn;
+    let or = |value: String, default: &str| match value.as_str() {
+        "" => default.to_string(),
+        _ => value,
+    };
+    let (auth, ip_type) = (or(auth, "none"), or(ip_type, "IPV4V6"));
+
+    let weak = weak.clone();
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(w) = weak.upgrade() {
+            w.set_apn_available(true);
+            w.set_apn_carrier(carrier.into());
+            w.set_apn_manual(manual);
+            w.set_apn_name(name.into());
+            w.set_apn_username(username.into());
+            w.set_apn_password(password.into());
+            w.set_apn_auth(auth.into());
+            w.set_apn_ip_type(ip_type.into());
+            w.set_apn_mmsc(mmsc.into());
+            w.set_apn_mms_proxy(mms_proxy.into());
+            w.set_apn_status("".into());
+        }
+    });
+}
+
+/// A megabyte, as plans count them.
+const MB: u64 = 1_000_000;
+
+/// The warning and cap in bytes and the cycle's first day, from the page's MB and day
+/// of the month. An empty warning or cap is none.
+fn parse_data_plan(warning: &str, cap: &str, cycle_day: &str) -> anyhow::Result<(u64, u64, u32)> {
+    let megabytes = |text: &str, what: &str| -> anyhow::Result<u64> {
+        match text.trim() {
+            "" => Ok(0),
+            text => text
+                .parse::<u64>()
+                .map(|mb| mb.saturating_mul(MB))
+                .with_context(|| format!("the {what} is not a number of MB")),
+        }
+    };
+    let cycle_day = cycle_day
+        .trim()
+        .parse()
+        .context("the cycle start is not a day of the month")?;
+    let warning = megabytes(warning, "warning")?;
+    let cap = megabytes(cap, "cap")?;
+    Ok((warning, cap, cycle_day))
+}
+
+/// An amount of data as the page shows it, e.g. "1.5 GB".
+fn format_bytes(bytes: u64) -> String {
+    const GB: u64 = 1_000 * MB;
+    if bytes >= GB {
+        format!("{:.1} GB", bytes as f64 / GB as f64)
+    } else {
+        format!("{} MB", bytes / MB)
+    }
+}
+
+/// Fill in the screen time page from the wellbeing service: today by app, each
+/// with its timer, the week, and bedtime.
+async fn show_screen_time(wellbeing: &WellbeingProxy<'_>, weak: &slint::Weak<SettingsWindow>) {
+    let (today, week) = match (wellbeing.today().await, wellbeing.week().await) {
+        (Ok(today), Ok(week)) => (today, week),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!(error = %e, "failed to read screen time");
+            let weak = weak.clone();
+            let _ = slint::invoke_from_event_loop(move || {
+                if let Some(w) = weak.upgrade() {
+                    w.set_screen_time_status(format!("Couldn't read screen time: {e}").into());
+                }
+            });
+            return;
+        }
+    };
+    let timers = wellbeing.app_timers().await.unwrap_or_default();
+    let times_up = wellbeing.times_up().await.unwrap_or_default();
+    let bedtime = wellbeing.bedtime().await.ok();
+    let pin_set = wellbeing.pin_set().await.unwrap_or(false);
+
+    let (unlocks, mut usage) = today;
+    let total: u64 = usage.iter().map(|(_, seconds, _)| seconds).sum();
+    let notifications: u32 = usage.iter().map(|(_, _, count)| count).sum();
+    // Apps with a timer that haven't been used today still get a row, to change it
+    let mut unused: Vec<&String> = timers
+        .keys()
+        .filter(|app| !usage.iter().any(|(used, _, _)| used == *app))
+        .collect();
+    unused.sort();
+    usage.extend(unused.into_iter().map(|app| (app.clone(), 0, 0)));
+    let apps: Vec<AppScreenTime> = usage
+        .into_iter()
+        .map(|(app, seconds, notifications)| AppScreenTime {
+            time: format_screen_time(seconds).into(),
+            notifications: notifications as i32,
+            timer: timers.get(&app).map_or(0, |(minutes, _)| *minutes) as i32,
+            hard: timers.get(&app).is_some_and(|(_, hard)| *hard),
+            times_up: times_up.contains(&app),
+            app: app.into(),
+        })
+        .collect();
+
+    let longest = week
+        .iter()
+        .fold(1, |longest, (_, seconds)| longest.max(*seconds));
+    let week: Vec<DayScreenTime> = week
+        .into_iter()
+        .map(|(date, seconds)| DayScreenTime {
+            day: chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                .map(|date| date.format("%a").to_string())
+                .unwrap_or(date)
+                .into(),
+            share: seconds as f32 / longest as f32,
+        })
+        .collect();
+
+    let weak = weak.clone();
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(w) = weak.upgrade() {
+            w.set_screen_time_today(format_screen_time(total).into());
+            w.set_screen_time_unlocks(unlocks as i32);
+            w.set_screen_time_notifications(notifications as i32);
+            w.set_screen_time_apps(std::rc::Rc::new(slint::VecModel::from(apps)).into());
+            w.set_screen_time_week(std::rc::Rc::new(slint::VecModel::from(week)).into());
+            if let Some((enabled, start, end)) = bedtime {
+                w.set_bedtime_on(enabled);
+                w.set_bedtime_start(start.into());
+                w.set_bedtime_end(end.into());
+            }
+            w.set_screen_time_pin_set(pin_set);
+            w.set_screen_time_status("".into());
+        }
+    });
+}
+
+/// Say on the screen time page why a change didn't take: the PIN, or what was wrong
+/// with it.
+fn screen_time_refused(weak: &slint::Weak<SettingsWindow>, what: &str, e: zbus::Error) {
+    let status = match zbus::fdo::Error::from(e) {
+        zbus::fdo::Error::AccessDenied(reason) => format!("{what}: {reason}"),
+        e => format!("{what}: {e}"),
+    };
+    let weak = weak.clone();
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(w) = weak.upgrade() {
+            w.set_screen_time_status(status.into());
+        }
+    });
+}
+
+/// Screen time as the page shows it, e.g. "1 h 5 min".
+fn format_screen_time(seconds: u64) -> String {
+    let minutes = seconds / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{minutes} min"),
+        (hours, minutes) => format!("{hours} h {minutes} min"),
+    }
+}
+
+/// Ask init over `socket` for the CPU time and memory of each running service, the
+/// one that used the most CPU first.
+fn service_usage(socket: &str) -> anyhow::Result<Vec<ServiceUsage>> {
+    let mut stream =
+        UnixStream::connect(socket).with_context(|| format!("failed to connect to {socket}"))?;
+    stream.write_all(b"top\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    if let Some(error) = response.strip_prefix("error:") {
+        bail!("init refused: {}", error.trim());
+    }
+    Ok(response.lines().filter_map(parse_usage).collect())
+}
+
+/// One line of init's `top`, e.g. "modem cpu=1.25s rss=5120K".
+fn parse_usage(line: &str) -> Option<ServiceUsage> {
+    let mut words = line.split_whitespace();
+    let name = words.next()?;
+    let (mut cpu, mut rss_kib) = ("", 0u64);
+    for word in words {
+        if let Some(value) = word.strip_prefix("cpu=") {
+            cpu = value;
+        } else if let Some(value) = word.strip_prefix("rss=") {
+            rss_kib = value.strip_suffix('K')?.parse().ok()?;
+        }
+    }
+    Some(ServiceUsage {
+        name: name.into(),
+        cpu: cpu.into(),
+        memory: format!("{:.1} MB", rss_kib as f64 / 1024.0).into(),
+    })
+}
+
+/// Follow the figure-eight sweep until it covers every direction, then store the
+/// calibration. Stops early when `calibrating` is cleared by Cancel.
+async fn run_calibration(
+    sensors: SensorsProxy<'static>,
+    calibrating: Arc<AtomicBool>,
+    weak: slint::Weak<SettingsWindow>,
+) {
+    // `finished` is `None` while the sweep runs, then whether the calibration was stored
+    let update = move |progress: u8, finished: Option<Result<(), String>>| {
+        let weak = weak.clone();
+        let _ = slint::invoke_from_event_loop(move || {
+            if let Some(w) = weak.upgrade() {
+                w.set_compass_progress(progress as i32);
+                w.set_compass_calibrating(finished.is_none());
+                match finished {
+                    None => w.set_compass_status("".into()),
+                    Some(Ok(())) => {
+                        w.set_compass_needs_calibration(false);
+                        w.set_compass_status("Compass calibrated".into());
+                    }
+                    Some(Err(e)) => w.set_compass_status(format!("Calibration failed: {e}").into()),
+                }
+            }
+        });
+    };
+
+    loop {
+        let progress = sensors.calibration_progress().await.unwrap_or(0);
+        if !calibrating.load(Ordering::Relaxed) {
+            break;
+        }
+        if progress < 100 {
+            update(progress, None);
+            tokio::time::sleep(CALIBRATION_POLL_INTERVAL).await;
+            continue;
+        }
+
+        calibrating.store(false, Ordering::Relaxed);
+        let result = sensors.finish_calibration().await;
+        if result.is_err() {
+            let _ = sensors.cancel_calibration().await;
+        }
+        update(progress, Some(result.map_err(|e| e.to_string())));
+        break;
+    }
+}
@@ -0,0 +1,168 @@
+// ABOUTME: SIM toolkit application for MobileOS: the carrier services a SIM offers in its menu.
+// ABOUTME: Follows the menu and prompts org.mobileos.Modem passes on from the SIM, and answers them.
+
+use std::rc::Rc;
+use std::sync::mpsc;
+
+use futures_util::StreamExt;
+use slint::VecModel;
+use tracing::{info, warn};
+
+slint::include_modules!();
+
+enum ToolkitCommand {
+    Open(u8),
+    Answer(u8),
+    Leave(bool),
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Modem",
+    default_service = "org.mobileos.Modem",
+    default_path = "/org/mobileos/Modem"
+)]
+trait Modem {
+    fn open_toolkit_item(&self, item: u8) -> zbus::Result<()>;
+    fn answer_toolkit(&self, item: u8) -> zbus::Result<()>;
+    fn leave_toolkit(&self, back: bool) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn toolkit_menu(&self) -> zbus::Result<(String, Vec<(u8, String)>)>;
+
+    #[zbus(property)]
+    fn toolkit_prompt(&self) -> zbus::Result<(String, String, Vec<(u8, String)>)>;
+}
+
+fn items(items: Vec<(u8, String)>) -> slint::ModelRc<ToolkitItem> {
+    let items: Vec<ToolkitItem> = items
+        .into_iter()
+        .map(|(id, label)| ToolkitItem {
+            id: id.into(),
+            label: label.into(),
+        })
+        .collect();
+    Rc::new(VecModel::from(items)).into()
+}
+
+/// Send `command` for an item the UI passed up, which came from the SIM as a u8.
+fn send_item(tx: &mpsc::Sender<ToolkitCommand>, item: i32, command: fn(u8) -> ToolkitCommand) {
+    if let Ok(item) = u8::try_from(item) {
+        let _ = tx.send(command(item));
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    info!("starting SIM toolkit");
+
+    let window = SimToolkitWindow::new()?;
+    let (cmd_tx, cmd_rx) = mpsc::channel::<ToolkitCommand>();
+
+    let tx = cmd_tx.clone();
+    window.on_item_opened(move |item| send_item(&tx, item, ToolkitCommand::Open));
+
+    let tx = cmd_tx.clone();
+    window.on_prompt_answered(move |item| send_item(&tx, item, ToolkitCommand::Answer));
+
+    let tx = cmd_tx;
+    window.on_prompt_left(move |back| {
+        let _ = tx.send(ToolkitCommand::Leave(back));
+    });
+
+    // Background tokio thread for D-Bus communication
+    let weak = window.as_weak();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let conn = match zbus::Connection::system().await {
+                Ok(c) => c,
+                Err(e) => {
+                    info!("D-Bus not available: {e}");
+                    return;
+                }
+            };
+
+            let proxy = match ModemProxy::new(&conn).await {
+                Ok(p) => p,
+                Err(e) => {
+                    info!("modem service not available: {e}");
+                    return;
+                }
+            };
+
+            let menu = proxy.clone();
+            let weak_menu = weak.clone();
+            tokio::spawn(async move {
+                let mut changes = menu.receive_toolkit_menu_changed().await;
+                while let Some(change) = changes.next().await {
+                    let Ok((title, menu)) = change.get().await else {
+                        continue;
+                    };
+                    let weak = weak_menu.clone();
+                    let _ = slint::invoke_from_event_loop(move || {
+                        if let Some(w) = weak.upgrade() {
+                            if !title.is_empty() {
+                                w.set_menu_title(title.into());
+                            }
+                            w.set_menu_items(items(menu));
+                        }
+                    });
+                }
+            });
+
+            let prompts = proxy.clone();
+            let weak_prompts = weak.clone();
+            tokio::spawn(async move {
+                let mut changes = prompts.receive_toolkit_prompt_changed().await;
+                while let Some(change) = changes.next().await {
+                    let Ok((kind, text, choices)) = change.get().await else {
+                        continue;
+                    };
+                    let weak = weak_prompts.clone();
+                    let _ = slint::invoke_from_event_loop(move || {
+                        if let Some(w) = weak.upgrade() {
+                            w.set_prompt_kind(kind.into());
+                            w.set_prompt_text(text.into());
+                            w.set_prompt_items(items(choices));
+                        }
+                    });
+                }
+            });
+
+            while let Ok(cmd) = cmd_rx.recv() {
+                let result = match cmd {
+                    ToolkitCommand::Open(item) => {
+                        info!(item, "opening SIM menu item");
+                        proxy.open_toolkit_item(item).await
+                    }
+                    ToolkitCommand::Answer(item) => proxy.answer_toolkit(item).await,
+                    ToolkitCommand::Leave(back) => proxy.leave_toolkit(back).await,
+                };
+                let status = match result {
+                    Ok(()) => String::new(),
+                    Err(e) => {
+                        warn!(error = %e, "SIM toolkit request failed");
+                        "The SIM didn't take that. Try again.".to_string()
+                    }
+                };
+                let weak = weak.clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(w) = weak.upgrade() {
+                        w.set_status(status.into());
+                    }
+                });
+            }
+        });
+    });
+
+    info!("SIM toolkit running");
+    window.run()?;
+
+    Ok(())
+}
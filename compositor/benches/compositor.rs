@@ -0,0 +1,158 @@
+// ABOUTME: Criterion benchmarks for the compositor: composition, damage tracking, touch, layers.
+// ABOUTME: Runs the headless backend in-process against synthetic clients; see support/.
+
+mod support;
+
+use std::time::{Duration, Instant};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use smithay::backend::input::TouchSlot;
+use smithay::backend::renderer::damage::OutputDamageTracker;
+use smithay::backend::renderer::test::{DummyFramebuffer, DummyRenderer};
+use smithay::desktop::layer_map_for_output;
+use smithay::desktop::space::space_render_elements;
+
+use support::{Harness, Spec};
+
+const CLEAR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+const WINDOW_SIZE: (i32, i32) = (360, 360);
+
+/// Build the render elements for the whole output and draw them through a damage tracker.
+/// With `age` 0 everything is redrawn; otherwise only what changed since `age` frames ago.
+fn render(harness: &Harness, tracker: &mut OutputDamageTracker, age: usize) -> usize {
+    let mut renderer = DummyRenderer::new();
+    let elements =
+        space_render_elements(&mut renderer, [&harness.state.space], &harness.output, 1.0)
+            .expect("failed to collect render elements");
+    let result = tracker
+        .render_output(&mut renderer, &mut DummyFramebuffer, age, &elements, CLEAR)
+        .expect("failed to render output");
+    result.damage.map_or(0, |damage| damage.len())
+}
+
+fn windows(count: usize) -> (Harness, support::SyntheticClient) {
+    let mut harness = Harness::new();
+    let client = harness.connect(Spec {
+        windows: count,
+        window_size: WINDOW_SIZE,
+        layers: 0,
+    });
+    harness.tile_windows(4, (WINDOW_SIZE.0 / 2, WINDOW_SIZE.1 / 2));
+    harness.dispatch();
+    (harness, client)
+}
+
+/// Full redraw of the output as the number of mapped surfaces grows.
+fn composition(c: &mut Criterion) {
+    let mut group = c.benchmark_group("composition");
+    for count in [1, 4, 16, 64] {
+        let (harness, _client) = windows(count);
+        let mut tracker = OutputDamageTracker::from_output(&harness.output);
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::new("surfaces", count), &count, |b, _| {
+            b.iter(|| render(&harness, &mut tracker, 0));
+        });
+    }
+    group.finish();
+}
+
+/// How cheap frames get when little or nothing changed, compared to a full redraw.
+fn damage(c: &mut Criterion) {
+    let mut group = c.benchmark_group("damage");
+    let (mut harness, client) = windows(16);
+    let mut tracker = OutputDamageTracker::from_output(&harness.output);
+    render(&harness, &mut tracker, 0);
+
+    group.bench_function("idle", |b| {
+        b.iter(|| render(&harness, &mut tracker, 1));
+    });
+
+    // Only the render is timed; the commit and its roundtrip happen outside the measurement
+    group.bench_function("single-surface", |b| {
+        b.iter_custom(|iterations| {
+            let mut total = Duration::ZERO;
+            for _ in 0..iterations {
+                client.damage(0, (0, 0, 32, 32));
+                harness.wait_for(&client);
+                let start = Instant::now();
+                render(&harness, &mut tracker, 1);
+                total += start.elapsed();
+            }
+            total
+        });
+    });
+
+    group.bench_function("full-redraw", |b| {
+        b.iter(|| render(&harness, &mut tracker, 0));
+    });
+    group.finish();
+}
+
+/// One swipe across a window: down, motion events, up, each followed by a frame.
+fn touch(c: &mut Criterion) {
+    const MOTIONS: u32 = 10;
+    /// Swipes between letting the client read its events, well within a socket buffer.
+    const SYNC_EVERY: u64 = 32;
+
+    let mut group = c.benchmark_group("touch");
+    let (mut harness, client) = windows(4);
+    let slot = TouchSlot::from(Some(0));
+    let mut time = 0u32;
+
+    group.throughput(Throughput::Elements(u64::from(MOTIONS) + 2));
+    group.bench_function("swipe", |b| {
+        b.iter_custom(|iterations| {
+            let mut total = Duration::ZERO;
+            for n in 0..iterations {
+                let state = &mut harness.state;
+                time = time.wrapping_add(16);
+                let start = Instant::now();
+                state.touch_down_at(slot, (20.0, 20.0).into(), time);
+                state.touch_frame();
+                for i in 1..=MOTIONS {
+                    let pos = (20.0 + f64::from(i) * 10.0, 20.0 + f64::from(i) * 5.0);
+                    state.touch_motion_at(slot, pos.into(), time + i);
+                    state.touch_frame();
+                }
+                state.touch_up(slot, time + MOTIONS + 1);
+                state.touch_frame();
+                let _ = state.display_handle.flush_clients();
+                total += start.elapsed();
+
+                if n % SYNC_EVERY == SYNC_EVERY - 1 {
+                    client.sync();
+                    harness.wait_for(&client);
+                }
+            }
+            total
+        });
+    });
+    group.finish();
+    // Swipes that land on no surface would only measure the hit test
+    client.sync();
+    harness.wait_for(&client);
+    assert!(client.touch_downs() > 0, "swipes missed the windows");
+}
+
+/// Recomputing the usable area with layer surfaces claiming exclusive zones on every edge.
+fn layers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("layers");
+    for count in [1, 4, 16] {
+        let mut harness = Harness::new();
+        let _client = harness.connect(Spec {
+            windows: 0,
+            window_size: WINDOW_SIZE,
+            layers: count,
+        });
+        harness.map_layers();
+
+        group.bench_with_input(BenchmarkId::new("arrange", count), &count, |b, _| {
+            b.iter(|| layer_map_for_output(&harness.output).arrange());
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, composition, damage, touch, layers);
+criterion_main!(benches);
@@ -0,0 +1,320 @@
+// ABOUTME: Synthetic Wayland clients for the compositor benchmarks and client tests.
+// ABOUTME: Each runs on its own thread, creating shm-backed toplevels and layer surfaces on request.
+
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread::JoinHandle;
+
+use rustix::fs::{MemfdFlags, ftruncate, memfd_create};
+use wayland_client::globals::{GlobalListContents, registry_queue_init};
+use wayland_client::protocol::{
+    wl_buffer, wl_compositor, wl_registry, wl_seat, wl_shm, wl_shm_pool, wl_surface, wl_touch,
+};
+use wayland_client::{Connection, Dispatch, EventQueue, QueueHandle, delegate_noop};
+use wayland_protocols::xdg::shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base};
+use wayland_protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
+
+use zwlr_layer_surface_v1::Anchor;
+
+/// What one synthetic client puts on screen.
+#[derive(Debug, Clone, Copy)]
+pub struct Spec {
+    /// Number of xdg toplevels, each showing one buffer.
+    pub windows: usize,
+    pub window_size: (i32, i32),
+    /// Number of layer-shell surfaces with exclusive zones, cycling through the edges.
+    pub layers: usize,
+}
+
+/// Thickness of each layer surface, which is also its exclusive zone.
+pub const LAYER_THICKNESS: i32 = 48;
+
+enum Command {
+    /// Repaint a rectangle of surface `index` (windows first, then layers) and commit.
+    Damage {
+        index: usize,
+        rect: (i32, i32, i32, i32),
+    },
+    /// Read everything the compositor sent so far.
+    Sync,
+}
+
+/// Handle to a running synthetic client. Requests are acknowledged once the compositor
+/// has processed them, which needs the compositor's event loop to be dispatched meanwhile.
+pub struct SyntheticClient {
+    commands: Option<mpsc::Sender<Command>>,
+    acks: mpsc::Receiver<()>,
+    touch_downs: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SyntheticClient {
+    /// Start a client on `socket`. It signals `acked` once all its surfaces are mapped.
+    pub fn spawn(socket: UnixStream, spec: Spec) -> Self {
+        let (commands, command_rx) = mpsc::channel();
+        let (ack_tx, acks) = mpsc::channel();
+        let touch_downs = Arc::new(AtomicUsize::new(0));
+        let state = State {
+            touch_downs: touch_downs.clone(),
+            ..Default::default()
+        };
+        let thread = std::thread::spawn(move || run(socket, spec, state, command_rx, ack_tx));
+        Self {
+            commands: Some(commands),
+            acks,
+            touch_downs,
+            thread: Some(thread),
+        }
+    }
+
+    /// Ask the client to repaint part of one of its surfaces.
+    pub fn damage(&self, index: usize, rect: (i32, i32, i32, i32)) {
+        let _ = self
+            .commands
+            .as_ref()
+            .unwrap()
+            .send(Command::Damage { index, rect });
+    }
+
+    /// Ask the client to catch up on events, e.g. after a burst of input.
+    pub fn sync(&self) {
+        let _ = self.commands.as_ref().unwrap().send(Command::Sync);
+    }
+
+    /// How many touch points went down on the client's surfaces, up to its last
+    /// acknowledged request.
+    pub fn touch_downs(&self) -> usize {
+        self.touch_downs.load(Ordering::Relaxed)
+    }
+
+    /// Whether the client finished a request since the last call.
+    pub fn acked(&self) -> bool {
+        match self.acks.try_recv() {
+            Ok(()) => true,
+            Err(mpsc::TryRecvError::Empty) => false,
+            Err(mpsc::TryRecvError::Disconnected) => panic!("synthetic client died"),
+        }
+    }
+}
+
+impl Drop for SyntheticClient {
+    fn drop(&mut self) {
+        // Closing the channel ends the client loop
+        self.commands.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Surface {
+    surface: wl_surface::WlSurface,
+    buffer: wl_buffer::WlBuffer,
+}
+
+#[derive(Default)]
+struct State {
+    configured: usize,
+    touch_downs: Arc<AtomicUsize>,
+}
+
+fn run(
+    socket: UnixStream,
+    spec: Spec,
+    mut state: State,
+    commands: mpsc::Receiver<Command>,
+    acks: mpsc::Sender<()>,
+) {
+    let conn = Connection::from_socket(socket).expect("failed to connect synthetic client");
+    let (globals, mut queue) = registry_queue_init::<State>(&conn).expect("failed to read globals");
+    let qh = queue.handle();
+
+    let compositor: wl_compositor::WlCompositor =
+        globals.bind(&qh, 4..=6, ()).expect("no wl_compositor");
+    let shm: wl_shm::WlShm = globals.bind(&qh, 1..=1, ()).expect("no wl_shm");
+    let wm_base: xdg_wm_base::XdgWmBase = globals.bind(&qh, 1..=5, ()).expect("no xdg_wm_base");
+    let layer_shell: zwlr_layer_shell_v1::ZwlrLayerShellV1 = globals
+        .bind(&qh, 1..=4, ())
+        .expect("no zwlr_layer_shell_v1");
+    let seat: wl_seat::WlSeat = globals.bind(&qh, 1..=7, ()).expect("no wl_seat");
+    let _touch = seat.get_touch(&qh, ());
+
+    // Create every role first, then wait for all the initial configures in one go
+    let mut sizes = Vec::new();
+    let mut surfaces = Vec::new();
+    for _ in 0..spec.windows {
+        let surface = compositor.create_surface(&qh, ());
+        let xdg = wm_base.get_xdg_surface(&surface, &qh, ());
+        let toplevel = xdg.get_toplevel(&qh, ());
+        toplevel.set_title("bench".to_string());
+        surface.commit();
+        surfaces.push(surface);
+        sizes.push(spec.window_size);
+    }
+    for i in 0..spec.layers {
+        let surface = compositor.create_surface(&qh, ());
+        let layer = layer_shell.get_layer_surface(
+            &surface,
+            None,
+            zwlr_layer_shell_v1::Layer::Top,
+            "bench".to_string(),
+            &qh,
+            (),
+        );
+        let (anchor, size) = match i % 4 {
+            0 => (Anchor::Top, (200, LAYER_THICKNESS)),
+            1 => (Anchor::Bottom, (200, LAYER_THICKNESS)),
+            2 => (Anchor::Left, (LAYER_THICKNESS, 200)),
+            _ => (Anchor::Right, (LAYER_THICKNESS, 200)),
+        };
+        layer.set_anchor(anchor);
+        layer.set_size(size.0 as u32, size.1 as u32);
+        layer.set_exclusive_zone(LAYER_THICKNESS);
+        surface.commit();
+        surfaces.push(surface);
+        sizes.push(size);
+    }
+    while state.configured < surfaces.len() {
+        queue
+            .blocking_dispatch(&mut state)
+            .expect("synthetic client lost connection");
+    }
+
+    let surfaces: Vec<Surface> = surfaces
+        .into_iter()
+        .zip(sizes)
+        .map(|(surface, (width, height))| {
+            let buffer = shm_buffer(&shm, &qh, width, height);
+            surface.attach(Some(&buffer), 0, 0);
+            surface.damage_buffer(0, 0, width, height);
+            surface.commit();
+            Surface { surface, buffer }
+        })
+        .collect();
+    roundtrip(&mut queue, &mut state);
+    let _ = acks.send(());
+
+    while let Ok(command) = commands.recv() {
+        match command {
+            Command::Damage { index, rect } => {
+                let Surface { surface, buffer } = &surfaces[index];
+                surface.attach(Some(buffer), 0, 0);
+                surface.damage_buffer(rect.0, rect.1, rect.2, rect.3);
+                surface.commit();
+            }
+            Command::Sync => {}
+        }
+        roundtrip(&mut queue, &mut state);
+        let _ = acks.send(());
+    }
+}
+
+fn roundtrip(queue: &mut EventQueue<State>, state: &mut State) {
+    queue
+        .roundtrip(state)
+        .expect("synthetic client lost connection");
+}
+
+/// An opaque grey ARGB buffer in a pool of its own.
+fn shm_buffer(
+    shm: &wl_shm::WlShm,
+    qh: &QueueHandle<State>,
+    width: i32,
+    height: i32,
+) -> wl_buffer::WlBuffer {
+    let stride = width * 4;
+    let size = stride * height;
+    let fd = memfd_create("bench-buffer", MemfdFlags::CLOEXEC).expect("memfd_create failed");
+    ftruncate(&fd, size as u64).expect("ftruncate failed");
+    rustix::io::write(&fd, &vec![0x80; size as usize]).expect("failed to fill buffer");
+
+    let pool = shm.create_pool(fd.as_fd(), size, qh, ());
+    let buffer = pool.create_buffer(0, width, height, stride, wl_shm::Format::Argb8888, qh, ());
+    pool.destroy();
+    buffer
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<xdg_wm_base::XdgWmBase, ()> for State {
+    fn event(
+        _: &mut Self,
+        wm_base: &xdg_wm_base::XdgWmBase,
+        event: xdg_wm_base::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            wm_base.pong(serial);
+        }
+    }
+}
+
+impl Dispatch<xdg_surface::XdgSurface, ()> for State {
+    fn event(
+        state: &mut Self,
+        xdg: &xdg_surface::XdgSurface,
+        event: xdg_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            xdg.ack_configure(serial);
+            state.configured += 1;
+        }
+    }
+}
+
+impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        layer: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_layer_surface_v1::Event::Configure { serial, .. } = event {
+            layer.ack_configure(serial);
+            state.configured += 1;
+        }
+    }
+}
+
+impl Dispatch<wl_touch::WlTouch, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &wl_touch::WlTouch,
+        event: wl_touch::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_touch::Event::Down { .. } = event {
+            state.touch_downs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+delegate_noop!(State: wl_compositor::WlCompositor);
+delegate_noop!(State: wl_shm_pool::WlShmPool);
+delegate_noop!(State: zwlr_layer_shell_v1::ZwlrLayerShellV1);
+delegate_noop!(State: ignore wl_shm::WlShm);
+delegate_noop!(State: ignore wl_seat::WlSeat);
+delegate_noop!(State: ignore wl_buffer::WlBuffer);
+delegate_noop!(State: ignore wl_surface::WlSurface);
+delegate_noop!(State: ignore xdg_toplevel::XdgToplevel);
@@ -0,0 +1,105 @@
+// ABOUTME: Harness for benchmarks and client tests: a headless compositor driven in-process.
+// ABOUTME: Clients connect over socketpairs, so no listening socket or real display is involved.
+
+pub mod client;
+
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mos_compositor::headless;
+use mos_compositor::state::{ClientState, Compositor};
+use smithay::desktop::{LayerSurface, layer_map_for_output};
+use smithay::output::Output;
+use smithay::reexports::calloop::EventLoop;
+use smithay::reexports::wayland_server::Display;
+
+pub use client::{Spec, SyntheticClient};
+
+/// Give up on a client that has not finished a request within this long.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Harness {
+    pub event_loop: EventLoop<'static, Compositor>,
+    pub state: Compositor,
+    pub output: Output,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        // The compositor always opens a listening socket, which needs a runtime directory
+        if std::env::var_os("XDG_RUNTIME_DIR").is_none() {
+            let dir = std::env::temp_dir().join(format!("mos-bench-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).expect("failed to create runtime directory");
+            // SAFETY: benchmarks set this up before any client thread exists
+            unsafe { std::env::set_var("XDG_RUNTIME_DIR", &dir) };
+        }
+
+        let mut event_loop: EventLoop<Compositor> =
+            EventLoop::try_new().expect("failed to create event loop");
+        let display: Display<Compositor> = Display::new().expect("failed to create display");
+        let mut state = Compositor::new(&mut event_loop, display);
+        headless::init_headless(&mut event_loop, &mut state).expect("failed to start headless");
+        let output = state
+            .space
+            .outputs()
+            .next()
+            .expect("headless backend maps an output")
+            .clone();
+
+        Self {
+            event_loop,
+            state,
+            output,
+        }
+    }
+
+    /// Connect a synthetic client and wait until all of its surfaces are mapped.
+    pub fn connect(&mut self, spec: Spec) -> SyntheticClient {
+        let (server, client) = UnixStream::pair().expect("failed to create socketpair");
+        self.state
+            .display_handle
+            .insert_client(server, Arc::new(ClientState::default()))
+            .expect("failed to insert client");
+        let client = SyntheticClient::spawn(client, spec);
+        self.wait_for(&client);
+        client
+    }
+
+    /// Run the event loop until `client` acknowledges its last request.
+    pub fn wait_for(&mut self, client: &SyntheticClient) {
+        let deadline = Instant::now() + CLIENT_TIMEOUT;
+        while !client.acked() {
+            assert!(Instant::now() < deadline, "synthetic client stalled");
+            self.dispatch();
+        }
+        self.dispatch();
+    }
+
+    pub fn dispatch(&mut self) {
+        self.event_loop
+            .dispatch(Some(Duration::from_millis(1)), &mut self.state)
+            .expect("event loop dispatch failed");
+        self.state.space.refresh();
+        let _ = self.state.display_handle.flush_clients();
+    }
+
+    /// Spread the mapped windows over a grid so they overlap the way a busy session would.
+    pub fn tile_windows(&mut self, columns: i32, step: (i32, i32)) {
+        let windows: Vec<_> = self.state.space.elements().cloned().collect();
+        for (i, window) in windows.into_iter().enumerate() {
+            let i = i as i32;
+            let location = ((i % columns) * step.0, (i / columns) * step.1);
+            self.state.space.map_element(window, location, false);
+        }
+    }
+
+    /// Put every layer surface the clients created on the output's layer map.
+    pub fn map_layers(&mut self) {
+        let mut map = layer_map_for_output(&self.output);
+        for surface in self.state.layer_shell_state.layer_surfaces() {
+            let layer = LayerSurface::new(surface, "bench".to_string());
+            map.map_layer(&layer).expect("failed to map layer surface");
+        }
+    }
+}
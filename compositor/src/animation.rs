@@ -0,0 +1,249 @@
+// ABOUTME: Window animations: apps scale and fade in as they open, out as they are closed, and settle in when switched to.
+// ABOUTME: The backends draw windows through here each frame, scaled and faded as each animation has it at the frame's time.
+
+use std::time::Duration;
+
+use smithay::backend::renderer::element::AsRenderElements;
+use smithay::backend::renderer::element::surface::WaylandSurfaceRenderElement;
+use smithay::backend::renderer::element::utils::RescaleRenderElement;
+use smithay::backend::renderer::{ImportAll, Renderer, Texture};
+use smithay::desktop::{Space, Window};
+use smithay::output::Output;
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use smithay::utils::IsAlive;
+use tracing::warn;
+
+use crate::state::Compositor;
+
+const OPEN_DURATION: Duration = Duration::from_millis(220);
+const CLOSE_DURATION: Duration = Duration::from_millis(160);
+const SWITCH_DURATION: Duration = Duration::from_millis(200);
+
+/// How long a closed window stays hidden for its app to go away. An app that is still
+/// there after it, say to ask about unsaved work, comes back.
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+/// A window's render element, scaled about its middle.
+pub type AnimatedElement<R> = RescaleRenderElement<WaylandSurfaceRenderElement<R>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// The window was mapped: it grows and fades in.
+    Open,
+    /// The window is being closed: it shrinks and fades out.
+    Close,
+    /// The window was switched to: it settles in from a little smaller.
+    Switch,
+}
+
+impl Kind {
+    fn duration(self) -> Duration {
+        match self {
+            Kind::Open => OPEN_DURATION,
+            Kind::Close => CLOSE_DURATION,
+            Kind::Switch => SWITCH_DURATION,
+        }
+    }
+
+    /// Scale and opacity `t` of the way through, 0 to 1 after easing.
+    fn look(self, t: f64) -> (f64, f32) {
+        let (scale, alpha) = match self {
+            Kind::Open => (lerp(0.9, 1.0, t), t),
+            Kind::Close => (lerp(1.0, 0.9, t), 1.0 - t),
+            Kind::Switch => (lerp(0.95, 1.0, t), lerp(0.7, 1.0, t)),
+        };
+        (scale, alpha as f32)
+    }
+}
+
+fn lerp(from: f64, to: f64, t: f64) -> f64 {
+    from * (1.0 - t) + to * t
+}
+
+/// Fast at first and settling at the end, as things come to rest.
+pub fn ease_out(t: f64) -> f64 {
+    1.0 - (1.0 - t.clamp(0.0, 1.0)).powi(3)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Animation {
+    kind: Kind,
+    /// Frame time of the first frame the window was drawn in, which for a new window
+    /// can be some frames after it was mapped.
+    started: Option<Duration>,
+}
+
+impl Animation {
+    pub fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            started: None,
+        }
+    }
+
+    /// Scale and opacity at frame time `now`.
+    pub fn look(&self, now: Duration) -> (f64, f32) {
+        let t = match self.started {
+            Some(started) => {
+                now.saturating_sub(started).as_secs_f64() / self.kind.duration().as_secs_f64()
+            }
+            None => 0.0,
+        };
+        self.kind.look(ease_out(t))
+    }
+
+    /// Whether the window is back to drawing as it is by `now`. A closed window is kept
+    /// hidden until its app has had time to go.
+    pub fn is_over(&self, now: Duration) -> bool {
+        let lasts = match self.kind {
+            Kind::Close => self.kind.duration() + CLOSE_GRACE,
+            kind => kind.duration(),
+        };
+        self.started.is_some_and(|started| now >= started + lasts)
+    }
+}
+
+/// The windows being animated.
+#[derive(Default)]
+pub struct Animations {
+    running: Vec<(Window, Animation)>,
+}
+
+impl Animations {
+    /// Animate `window`, replacing what it was doing.
+    pub fn start(&mut self, window: &Window, kind: Kind) {
+        self.running.retain(|(w, _)| w != window);
+        self.running.push((window.clone(), Animation::new(kind)));
+    }
+
+    fn is_closing(&self, window: &Window) -> bool {
+        self.running
+            .iter()
+            .any(|(w, a)| w == window && a.kind == Kind::Close)
+    }
+
+    /// Render elements for the windows of `space` on `output`, topmost first, as they
+    /// look at frame time `now`. Animations start with the first frame their window is
+    /// drawn in, and are dropped when over.
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        space: &Space<Window>,
+        output: &Output,
+        now: Duration,
+    ) -> Vec<AnimatedElement<R>>
+    where
+        R: Renderer + ImportAll,
+        R::TextureId: Clone + Texture + 'static,
+    {
+        self.running
+            .retain(|(window, animation)| window.alive() && !animation.is_over(now));
+        for (window, animation) in &mut self.running {
+            if animation.started.is_none() && !window.bbox().is_empty() {
+                animation.started = Some(now);
+            }
+        }
+
+        let Some(area) = space.output_geometry(output) else {
+            return Vec::new();
+        };
+        let scale = output.current_scale().fractional_scale();
+        space
+            .elements()
+            .rev()
+            .flat_map(|window| {
+                let Some(location) = space.element_location(window) else {
+                    return Vec::new();
+                };
+                let (zoom, alpha) = self
+                    .running
+                    .iter()
+                    .find(|(w, _)| w == window)
+                    .map_or((1.0, 1.0), |(_, animation)| animation.look(now));
+                let geometry = window.geometry();
+                let middle = (location - area.loc).to_f64()
+                    + geometry.size.to_f64().downscale(2.0).to_point();
+                let middle = middle.to_physical_precise_round(scale);
+                let origin = (location - geometry.loc - area.loc).to_physical_precise_round(scale);
+                window
+                    .render_elements::<WaylandSurfaceRenderElement<R>>(
+                        renderer,
+                        origin,
+                        scale.into(),
+                        alpha,
+                    )
+                    .into_iter()
+                    .map(|element| RescaleRenderElement::from_element(element, middle, zoom))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl Compositor {
+    /// Shrink and fade `window` out, then ask its app to close it.
+    pub fn animate_close(&mut self, window: &Window) {
+        self.animations.start(window, Kind::Close);
+        let closing = window.clone();
+        let inserted =
+            self.handle
+                .insert_source(Timer::from_duration(CLOSE_DURATION), move |_, _, state| {
+                    // Switched back to while going, so it stays
+                    if state.animations.is_closing(&closing)
+                        && let Some(toplevel) = closing.toplevel()
+                    {
+                        toplevel.send_close();
+                    }
+                    TimeoutAction::Drop
+                });
+        if let Err(e) = inserted {
+            warn!(error = %e, "failed to time a window's close, closing it now");
+            if let Some(toplevel) = window.toplevel() {
+                toplevel.send_close();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn easing_settles_at_the_end() {
+        assert_eq!(ease_out(0.0), 0.0);
+        assert_eq!(ease_out(1.0), 1.0);
+        assert_eq!(ease_out(2.0), 1.0);
+        assert!(ease_out(0.5) > 0.5);
+    }
+
+    #[test]
+    fn windows_open_from_their_first_frame() {
+        let mut open = Animation::new(Kind::Open);
+        // Not drawn yet, so it hasn't started
+        assert_eq!(open.look(500 * MS), (0.9, 0.0));
+        assert!(!open.is_over(500 * MS));
+
+        open.started = Some(1000 * MS);
+        let (scale, alpha) = open.look(1000 * MS + OPEN_DURATION / 2);
+        assert!(scale > 0.95 && scale < 1.0);
+        assert!(alpha > 0.5 && alpha < 1.0);
+        assert_eq!(open.look(1000 * MS + OPEN_DURATION), (1.0, 1.0));
+        assert!(open.is_over(1000 * MS + OPEN_DURATION));
+    }
+
+    #[test]
+    fn closed_windows_stay_hidden_for_their_app_to_go() {
+        let close = Animation {
+            kind: Kind::Close,
+            started: Some(Duration::ZERO),
+        };
+        assert_eq!(close.look(Duration::ZERO), (1.0, 1.0));
+        assert_eq!(close.look(CLOSE_DURATION), (0.9, 0.0));
+        assert_eq!(close.look(CLOSE_DURATION + CLOSE_GRACE / 2), (0.9, 0.0));
+        assert!(!close.is_over(CLOSE_DURATION + CLOSE_GRACE / 2));
+        assert!(close.is_over(CLOSE_DURATION + CLOSE_GRACE));
+    }
+}
@@ -0,0 +1,155 @@
+// ABOUTME: Voice assistant hook: a long press of Home, or a dedicated key, asks for the assistant.
+// ABOUTME: Signals AssistantRequested with the foreground app, for whichever assistant is registered to answer.
+
+use smithay::input::keyboard::{Keysym, xkb};
+use tracing::{info, warn};
+
+use crate::dbus::{self, window_app_id};
+use crate::state::Compositor;
+
+/// Names the key that calls the assistant, as an XKB keysym name such as
+/// "XF86Search". Home, the default, calls it on a long press; any other key at once.
+pub const KEY_VAR: &str = "MOS_ASSISTANT_KEY";
+
+/// How long Home must be held, in milliseconds, to call the assistant.
+pub const LONG_PRESS: u32 = 600;
+
+const HOME: Keysym = Keysym::XF86_HomePage;
+
+pub struct Assistant {
+    key: Keysym,
+    /// When Home went down, while it is held.
+    pressed_at: Option<u32>,
+}
+
+impl Default for Assistant {
+    fn default() -> Self {
+        Self {
+            key: HOME,
+            pressed_at: None,
+        }
+    }
+}
+
+impl Assistant {
+    /// The assistant key named in `MOS_ASSISTANT_KEY`, or Home.
+    pub fn from_env() -> Self {
+        let Ok(name) = std::env::var(KEY_VAR) else {
+            return Self::default();
+        };
+        let key = xkb::keysym_from_name(&name, xkb::KEYSYM_NO_FLAGS);
+        if key == Keysym::NoSymbol {
+            warn!(key = %name, "unknown assistant key, using a long press of Home");
+            return Self::default();
+        }
+        info!(key = %name, "assistant key");
+        Self {
+            key,
+            pressed_at: None,
+        }
+    }
+
+    /// Whether `keysym` is a dedicated assistant key, kept from clients. Home is left to
+    /// the shell, which sees short presses as usual.
+    pub fn consumes(&self, keysym: Keysym) -> bool {
+        keysym == self.key && self.key != HOME
+    }
+
+    /// Follow `keysym` going down or up at `time`. Returns true when that calls the
+    /// assistant: as a dedicated key goes down, or as Home comes up after a long press.
+    pub fn called(&mut self, keysym: Keysym, pressed: bool, time: u32) -> bool {
+        if keysym != self.key {
+            return false;
+        }
+        if self.key != HOME {
+            return pressed;
+        }
+        if pressed {
+            self.pressed_at.get_or_insert(time);
+            return false;
+        }
+        self.pressed_at
+            .take()
+            .is_some_and(|pressed_at| time.wrapping_sub(pressed_at) >= LONG_PRESS)
+    }
+}
+
+impl Compositor {
+    /// Watch for the assistant key. Returns true when it must not reach clients.
+    pub fn assistant_key(&mut self, keysym: Keysym, pressed: bool, time: u32) -> bool {
+        // Neither a locked phone nor a pinned app lets anything else be opened
+        if self.assistant.called(keysym, pressed, time)
+            && !self.is_locked()
+            && self.pinned.is_none()
+        {
+            self.request_assistant();
+        }
+        self.assistant.consumes(keysym)
+    }
+
+    /// The app the user is looking at: the topmost window, bubbles aside, or none on
+    /// the home screen, where the shell is on top.
+    pub fn foreground_app_id(&self) -> Option<String> {
+        let top = self
+            .space
+            .elements()
+            .rev()
+            .find(|w| w.alive() && !self.is_bubble(w))?;
+        if self.shell_window().as_ref() == Some(top) {
+            return None;
+        }
+        window_app_id(top)
+    }
+
+    /// Signal AssistantRequested with the foreground app id, empty on the home screen.
+    /// The assistant registered with the shell answers it; it takes a screenshot itself,
+    /// if the user allowed it to.
+    fn request_assistant(&mut self) {
+        let app_id = self.foreground_app_id().unwrap_or_default();
+        info!(app = %app_id, "assistant requested");
+        if let Some(bus) = &self.bus
+            && let Err(e) = bus.emit_signal(
+                None::<zbus::names::BusName<'_>>,
+                dbus::OBJECT_PATH,
+                dbus::INTERFACE,
+                "AssistantRequested",
+                &(app_id.as_str(),),
+            )
+        {
+            warn!(error = %e, "failed to ask for the assistant");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_press_of_home_calls_the_assistant() {
+        let mut assistant = Assistant::default();
+        assert!(!assistant.called(HOME, true, 1000));
+        assert!(!assistant.called(HOME, false, 1200));
+
+        assert!(!assistant.called(HOME, true, 2000));
+        assert!(!assistant.called(Keysym::a, true, 2100));
+        assert!(assistant.called(HOME, false, 2000 + LONG_PRESS));
+        // A release without its press, as when Home was held before startup
+        assert!(!assistant.called(HOME, false, 9000));
+        assert!(!assistant.consumes(HOME));
+    }
+
+    #[test]
+    fn dedicated_key_calls_the_assistant_at_once() {
+        let mut assistant = Assistant {
+            key: Keysym::XF86_Search,
+            pressed_at: None,
+        };
+        assert!(assistant.called(Keysym::XF86_Search, true, 0));
+        assert!(!assistant.called(Keysym::XF86_Search, false, 10));
+        assert!(assistant.consumes(Keysym::XF86_Search));
+        assert!(!assistant.called(HOME, true, 20));
+        assert!(!assistant.called(HOME, false, 20 + LONG_PRESS));
+        assert!(!assistant.consumes(HOME));
+    }
+}
@@ -0,0 +1,348 @@
+// ABOUTME: Bubbles: small floating windows, like chat heads or picture-in-picture controls, kept above apps.
+// ABOUTME: Dragged around by touch and tucked against an edge; the shell decides when they show and their order.
+
+use smithay::backend::input::TouchSlot;
+use smithay::desktop::Window;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::utils::{Logical, Point, Rectangle, Size};
+use smithay::wayland::compositor::with_states;
+use smithay::wayland::shell::xdg::SurfaceCachedState;
+use tracing::info;
+
+use crate::dbus::window_app_id;
+use crate::state::Compositor;
+
+/// An app asks for a bubble by giving a toplevel its app id with this suffix, such as
+/// org.mobileos.Messages.bubble. Its maximum size hint is the size it would like.
+pub const BUBBLE_SUFFIX: &str = ".bubble";
+
+/// The size of a bubble that didn't ask for one: a chat head.
+pub const DEFAULT_SIZE: (i32, i32) = (96, 96);
+
+/// The least and most a bubble may take, so it can't cover the app underneath.
+pub const MIN_SIZE: (i32, i32) = (48, 48);
+pub const MAX_SIZE: (i32, i32) = (480, 320);
+
+/// How far a touch has to move before it drags the bubble instead of reaching the app.
+const DRAG_THRESHOLD: f64 = 12.0;
+
+/// Gap between a bubble and the edge it rests against, and between new bubbles.
+const MARGIN: i32 = 8;
+
+/// How much of a tucked-away bubble still shows, for a finger to pull it back out.
+const PEEK: i32 = 16;
+
+struct Bubble {
+    window: Window,
+    loc: Point<i32, Logical>,
+    tucked: bool,
+}
+
+/// A touch on a bubble, which turns into a drag once it moves far enough.
+struct Grab {
+    slot: TouchSlot,
+    window: Window,
+    start: Point<f64, Logical>,
+    origin: Point<i32, Logical>,
+    dragging: bool,
+}
+
+/// The bubble layer, above every app window.
+#[derive(Default)]
+pub struct Bubbles {
+    /// Bottom to top.
+    stack: Vec<Bubble>,
+    /// The shell hides them, as while the screen is locked.
+    hidden: bool,
+    grab: Option<Grab>,
+}
+
+/// The size a bubble gets for the one it asked for, where 0 means no preference.
+pub fn bubble_size(requested: Size<i32, Logical>) -> Size<i32, Logical> {
+    let fit = |requested: i32, default: i32, min: i32, max: i32| {
+        if requested <= 0 {
+            default
+        } else {
+            requested.clamp(min, max)
+        }
+    };
+    (
+        fit(requested.w, DEFAULT_SIZE.0, MIN_SIZE.0, MAX_SIZE.0),
+        fit(requested.h, DEFAULT_SIZE.1, MIN_SIZE.1, MAX_SIZE.1),
+    )
+        .into()
+}
+
+/// Where a bubble `size` big comes to rest in `area` after being let go at `loc`: against
+/// the nearer side, and tucked away there if its middle was off the screen. Returns the
+/// place and whether it is tucked away.
+pub fn settle(
+    area: Rectangle<i32, Logical>,
+    size: Size<i32, Logical>,
+    loc: Point<i32, Logical>,
+) -> (Point<i32, Logical>, bool) {
+    let left = area.loc.x;
+    let right = area.loc.x + area.size.w;
+    let middle = loc.x + size.w / 2;
+    let tucked = middle < left || middle > right;
+
+    let x = match (middle < left + area.size.w / 2, tucked) {
+        (true, true) => left - size.w + PEEK,
+        (true, false) => left + MARGIN,
+        (false, true) => right - PEEK,
+        (false, false) => right - size.w - MARGIN,
+    };
+    let bottom = (area.loc.y + area.size.h - size.h).max(area.loc.y);
+    let y = loc.y.clamp(area.loc.y, bottom);
+    ((x, y).into(), tucked)
+}
+
+/// The maximum size hint of `window`'s toplevel.
+fn requested_size(window: &Window) -> Size<i32, Logical> {
+    let Some(toplevel) = window.toplevel() else {
+        return Size::default();
+    };
+    with_states(toplevel.wl_surface(), |states| {
+        states
+            .cached_state
+            .get::<SurfaceCachedState>()
+            .current()
+            .max_size
+    })
+}
+
+impl Compositor {
+    pub fn is_bubble(&self, window: &Window) -> bool {
+        self.bubbles.stack.iter().any(|b| &b.window == window)
+    }
+
+    /// Make `window` a bubble if its app id asks for one, sizing it for its first configure.
+    /// New bubbles line up down the right edge.
+    pub fn adopt_bubble(&mut self, window: &Window) -> bool {
+        let Some(app_id) = window_app_id(window).filter(|id| id.ends_with(BUBBLE_SUFFIX)) else {
+            return false;
+        };
+        let size = bubble_size(requested_size(window));
+        if let Some(toplevel) = window.toplevel() {
+            toplevel.with_pending_state(|state| state.size = Some(size));
+        }
+
+        let area = self.bubble_area().unwrap_or_default();
+        let below = self.bubbles.stack.len() as i32 * (DEFAULT_SIZE.1 + MARGIN);
+        let wanted = (
+            area.loc.x + area.size.w - size.w,
+            area.loc.y + MARGIN + below,
+        )
+            .into();
+        let (loc, tucked) = settle(area, size, wanted);
+
+        info!(app_id, ?size, "new bubble");
+        self.bubbles.stack.push(Bubble {
+            window: window.clone(),
+            loc,
+            tucked,
+        });
+        self.restack_bubbles();
+        true
+    }
+
+    /// The app ids of the bubbles, without the suffix, topmost first.
+    pub fn bubble_app_ids(&self) -> Vec<String> {
+        self.bubbles
+            .stack
+            .iter()
+            .rev()
+            .filter_map(|b| window_app_id(&b.window))
+            .filter_map(|id| Some(id.strip_suffix(BUBBLE_SUFFIX)?.to_string()))
+            .collect()
+    }
+
+    /// Put the bubble of `app_id` on top of the others. False if it has none.
+    pub fn raise_bubble(&mut self, app_id: &str) -> bool {
+        let wanted = format!("{app_id}{BUBBLE_SUFFIX}");
+        let Some(i) = self
+            .bubbles
+            .stack
+            .iter()
+            .position(|b| window_app_id(&b.window).as_deref() == Some(&wanted))
+        else {
+            return false;
+        };
+        let bubble = self.bubbles.stack.remove(i);
+        self.bubbles.stack.push(bubble);
+        self.restack_bubbles();
+        true
+    }
+
+    pub fn set_bubbles_hidden(&mut self, hidden: bool) {
+        if self.bubbles.hidden != hidden {
+            info!(hidden, "bubbles hidden by the shell");
+            self.bubbles.hidden = hidden;
+            self.restack_bubbles();
+        }
+    }
+
+    /// Put the bubbles back above everything else, or take them off the screen while the
+    /// shell hides them or an app is pinned. Called whenever an app window is raised.
+    pub fn restack_bubbles(&mut self) {
+        let shown = !self.bubbles.hidden && self.pinned.is_none();
+        for bubble in &self.bubbles.stack {
+            if shown {
+                self.space
+                    .map_element(bubble.window.clone(), bubble.loc, false);
+            } else {
+                self.space.unmap_elem(&bubble.window);
+            }
+        }
+    }
+
+    pub fn bubble_closed(&mut self, surface: &WlSurface) {
+        let closed = |window: &Window| window.toplevel().is_some_and(|t| t.wl_surface() == surface);
+        self.bubbles.stack.retain(|b| !closed(&b.window));
+        if self
+            .bubbles
+            .grab
+            .as_ref()
+            .is_some_and(|g| closed(&g.window))
+        {
+            self.bubbles.grab = None;
+        }
+    }
+
+    /// Watch a touch that lands on a bubble, in case it turns into a drag. A tucked-away
+    /// bubble is pulled out straight away, so that touch never reaches the app. Returns
+    /// true when the touch must not reach clients.
+    pub fn bubble_touch_down(&mut self, slot: TouchSlot, pos: Point<f64, Logical>) -> bool {
+        if self.bubbles.grab.is_some() || self.bubbles.hidden || self.pinned.is_some() {
+            return false;
+        }
+        let Some(bubble) = self.bubbles.stack.iter().rev().find(|b| {
+            self.space
+                .element_geometry(&b.window)
+                .is_some_and(|geo| geo.to_f64().contains(pos))
+        }) else {
+            return false;
+        };
+        let dragging = bubble.tucked;
+        self.bubbles.grab = Some(Grab {
+            slot,
+            window: bubble.window.clone(),
+            start: pos,
+            origin: bubble.loc,
+            dragging,
+        });
+        dragging
+    }
+
+    pub fn bubble_touch_motion(&mut self, slot: TouchSlot, pos: Point<f64, Logical>) -> bool {
+        let Some(grab) = self.bubbles.grab.as_mut().filter(|g| g.slot == slot) else {
+            return false;
+        };
+        let moved = pos - grab.start;
+        if !grab.dragging {
+            if moved.x.hypot(moved.y) < DRAG_THRESHOLD {
+                return false;
+            }
+            // The app saw the start of this touch; it must forget it
+            grab.dragging = true;
+            if let Some(touch) = self.seat.get_touch() {
+                touch.cancel(self);
+            }
+        }
+
+        let Some(grab) = self.bubbles.grab.as_ref() else {
+            return false;
+        };
+        let loc = grab.origin + moved.to_i32_round();
+        let window = grab.window.clone();
+        if let Some(bubble) = self.bubbles.stack.iter_mut().find(|b| b.window == window) {
+            bubble.loc = loc;
+        }
+        self.space.map_element(window, loc, false);
+        true
+    }
+
+    /// Let go of a dragged bubble, settling it against the nearer edge.
+    pub fn bubble_touch_up(&mut self, slot: TouchSlot) -> bool {
+        if !self.bubbles.grab.as_ref().is_some_and(|g| g.slot == slot) {
+            return false;
+        }
+        let Some(grab) = self.bubbles.grab.take().filter(|g| g.dragging) else {
+            return false;
+        };
+        let area = self.bubble_area().unwrap_or_default();
+        let Some(geo) = self.space.element_geometry(&grab.window) else {
+            return true;
+        };
+        let (loc, tucked) = settle(area, geo.size, geo.loc);
+        if let Some(bubble) = self
+            .bubbles
+            .stack
+            .iter_mut()
+            .find(|b| b.window == grab.window)
+        {
+            bubble.loc = loc;
+            bubble.tucked = tucked;
+        }
+        self.space.map_element(grab.window, loc, false);
+        true
+    }
+
+    fn bubble_area(&self) -> Option<Rectangle<i32, Logical>> {
+        let output = self.space.outputs().next()?;
+        self.space.output_geometry(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+
+    use super::*;
+
+    #[test]
+    fn bubbles_keep_to_their_size_limits() {
+        assert_eq!(bubble_size((0, 0).into()), DEFAULT_SIZE.into());
+        assert_eq!(bubble_size((320, 180).into()), (320, 180).into());
+        assert_eq!(bubble_size((10, 2000).into()), (48, 320).into());
+        assert_eq!(bubble_size((200, 0).into()), (200, 96).into());
+    }
+
+    #[test]
+    fn let_go_bubbles_settle_against_an_edge() {
+        let area = Rectangle::new((0, 0).into(), (720, 1440).into());
+        let size = Size::from((96, 96));
+
+        assert_eq!(
+            settle(area, size, (100, 500).into()),
+            ((8, 500).into(), false)
+        );
+        assert_eq!(
+            settle(area, size, (500, 500).into()),
+            ((616, 500).into(), false)
+        );
+        assert_eq!(
+            settle(area, size, (-60, 2000).into()),
+            ((-80, 1344).into(), true)
+        );
+        assert_eq!(
+            settle(area, size, (680, -50).into()),
+            ((704, 0).into(), true)
+        );
+    }
+
+    #[test]
+    fn touches_pass_through_without_bubbles() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+
+        let slot = TouchSlot::from(None);
+        assert!(!state.bubble_touch_down(slot, (10.0, 10.0).into()));
+        assert!(!state.bubble_touch_motion(slot, (200.0, 10.0).into()));
+        assert!(!state.bubble_touch_up(slot));
+        assert!(state.bubble_app_ids().is_empty());
+        assert!(!state.raise_bubble("org.mobileos.Messages"));
+    }
+}
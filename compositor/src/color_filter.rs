@@ -0,0 +1,282 @@
+// ABOUTME: Accessibility color filters: inverted colors, grayscale, and corrections for red-green color blindness; bedtime grays the screen too.
+// ABOUTME: The DRM backend applies them on the CRTC, through its color transform matrix and gamma ramp.
+
+use std::fmt;
+use std::str::FromStr;
+
+use tracing::{info, warn};
+
+use crate::state::Compositor;
+
+pub type Matrix = [[f64; 3]; 3];
+
+pub const IDENTITY: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Rec. 709 luma, the same in every channel.
+const GRAYSCALE: Matrix = [
+    [0.2126, 0.7152, 0.0722],
+    [0.2126, 0.7152, 0.0722],
+    [0.2126, 0.7152, 0.0722],
+];
+
+/// How colors look without working L or M cones (Machado et al. 2009, full severity).
+const PROTANOPIA_SIMULATION: Matrix = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+];
+const DEUTERANOPIA_SIMULATION: Matrix = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.011820, 0.042940, 0.968881],
+];
+
+/// Where daltonizing moves the color difference that is lost: out of red, into green
+/// and blue, which are still told apart.
+const ERROR_SHIFT: Matrix = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorFilter {
+    #[default]
+    Invert,
+    Grayscale,
+    Protanopia,
+    Deuteranopia,
+}
+
+impl ColorFilter {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ColorFilter::Invert => "invert",
+            ColorFilter::Grayscale => "grayscale",
+            ColorFilter::Protanopia => "protanopia",
+            ColorFilter::Deuteranopia => "deuteranopia",
+        }
+    }
+
+    /// The color transform matrix, applied to linear RGB.
+    pub fn matrix(self) -> Matrix {
+        match self {
+            ColorFilter::Invert => IDENTITY,
+            ColorFilter::Grayscale => GRAYSCALE,
+            ColorFilter::Protanopia => daltonize(&PROTANOPIA_SIMULATION),
+            ColorFilter::Deuteranopia => daltonize(&DEUTERANOPIA_SIMULATION),
+        }
+    }
+
+    /// Whether each channel is flipped, which a matrix can't do; the gamma ramp does.
+    pub fn inverts(self) -> bool {
+        self == ColorFilter::Invert
+    }
+}
+
+impl fmt::Display for ColorFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ColorFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "invert" => Ok(ColorFilter::Invert),
+            "grayscale" => Ok(ColorFilter::Grayscale),
+            "protanopia" => Ok(ColorFilter::Protanopia),
+            "deuteranopia" => Ok(ColorFilter::Deuteranopia),
+            _ => anyhow::bail!("unknown color filter '{s}'"),
+        }
+    }
+}
+
+/// The filter the user chose, and whether it is on.
+#[derive(Default)]
+pub struct ColorFilters {
+    chosen: ColorFilter,
+    enabled: bool,
+    /// Bedtime mode turns the screen gray, to make it less of a draw.
+    bedtime: bool,
+}
+
+impl ColorFilters {
+    /// The filter on the display. One chosen for accessibility wins over bedtime's
+    /// grayscale, as the user needs it to see.
+    fn active(&self) -> Option<ColorFilter> {
+        if self.enabled {
+            Some(self.chosen)
+        } else {
+            self.bedtime.then_some(ColorFilter::Grayscale)
+        }
+    }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+/// The correction for a kind of color blindness, from how it is simulated: what the viewer
+/// can't see, I - S, is shifted into channels they can, and added back.
+fn daltonize(simulation: &Matrix) -> Matrix {
+    let mut lost = IDENTITY;
+    for (i, row) in lost.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell -= simulation[i][j];
+        }
+    }
+    let mut out = multiply(&ERROR_SHIFT, &lost);
+    for (i, row) in out.iter_mut().enumerate() {
+        row[i] += 1.0;
+    }
+    out
+}
+
+/// `matrix` as the kernel's drm_color_ctm: row-major S31.32 fixed point, with the sign
+/// in the top bit rather than two's complement.
+pub fn ctm(matrix: &Matrix) -> [u64; 9] {
+    let mut out = [0; 9];
+    for (cell, value) in out.iter_mut().zip(matrix.iter().flatten()) {
+        let magnitude = (value.abs() * (1u64 << 32) as f64).round() as u64;
+        *cell = magnitude | if *value < 0.0 { 1 << 63 } else { 0 };
+    }
+    out
+}
+
+/// A linear gamma ramp of `len` steps, running backwards when `inverted`.
+pub fn gamma_ramp(len: usize, inverted: bool) -> Vec<u16> {
+    let step = |i: usize| (i * u16::MAX as usize / len.saturating_sub(1).max(1)) as u16;
+    (0..len)
+        .map(|i| if inverted { step(len - 1 - i) } else { step(i) })
+        .collect()
+}
+
+impl Compositor {
+    pub fn color_filter(&self) -> ColorFilter {
+        self.color_filters.chosen
+    }
+
+    pub fn color_filter_enabled(&self) -> bool {
+        self.color_filters.enabled
+    }
+
+    /// Choose `filter` and turn it on, as from accessibility settings.
+    pub fn set_color_filter(&mut self, filter: ColorFilter) {
+        self.color_filters.chosen = filter;
+        self.color_filters.enabled = true;
+        self.apply_color_filter();
+    }
+
+    /// Turn the chosen filter on or off, as from its quick-settings tile.
+    pub fn set_color_filter_enabled(&mut self, enabled: bool) {
+        if self.color_filters.enabled != enabled {
+            self.color_filters.enabled = enabled;
+            self.apply_color_filter();
+        }
+    }
+
+    pub fn bedtime(&self) -> bool {
+        self.color_filters.bedtime
+    }
+
+    /// Start or end bedtime mode, as the wellbeing service's schedule says.
+    pub fn set_bedtime(&mut self, on: bool) {
+        if self.color_filters.bedtime != on {
+            self.color_filters.bedtime = on;
+            self.apply_color_filter();
+        }
+    }
+
+    fn apply_color_filter(&mut self) {
+        let active = self.color_filters.active();
+        info!(filter = ?active.map(ColorFilter::as_str), "color filter");
+        let Some(drm) = self.drm.as_ref() else {
+            warn!("color filters need the DRM backend");
+            return;
+        };
+        if let Err(e) = crate::udev::set_color_filter(drm, active) {
+            warn!(error = %e, "failed to apply color filter");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(matrix: &Matrix, rgb: [f64; 3]) -> [f64; 3] {
+        let mut out = [0.0; 3];
+        for (i, channel) in out.iter_mut().enumerate() {
+            *channel = (0..3).map(|k| matrix[i][k] * rgb[k]).sum();
+        }
+        out
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for filter in [
+            ColorFilter::Invert,
+            ColorFilter::Grayscale,
+            ColorFilter::Protanopia,
+            ColorFilter::Deuteranopia,
+        ] {
+            assert_eq!(filter.as_str().parse::<ColorFilter>().unwrap(), filter);
+        }
+        assert!("sepia".parse::<ColorFilter>().is_err());
+    }
+
+    #[test]
+    fn filters_leave_grays_alone() {
+        for filter in [
+            ColorFilter::Grayscale,
+            ColorFilter::Protanopia,
+            ColorFilter::Deuteranopia,
+        ] {
+            let out = apply(&filter.matrix(), [0.5, 0.5, 0.5]);
+            for channel in out {
+                assert!(
+                    (channel - 0.5).abs() < 0.01,
+                    "{filter} moved gray to {out:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn grayscale_evens_out_channels() {
+        let [r, g, b] = apply(&ColorFilter::Grayscale.matrix(), [1.0, 0.0, 0.0]);
+        assert_eq!((r, g, b), (0.2126, 0.2126, 0.2126));
+    }
+
+    #[test]
+    fn bedtime_grays_the_screen_unless_a_filter_is_on() {
+        let mut filters = ColorFilters::default();
+        assert_eq!(filters.active(), None);
+        filters.bedtime = true;
+        assert_eq!(filters.active(), Some(ColorFilter::Grayscale));
+        filters.enabled = true;
+        assert_eq!(filters.active(), Some(ColorFilter::Invert));
+    }
+
+    #[test]
+    fn ctm_is_sign_magnitude_fixed_point() {
+        let mut matrix = IDENTITY;
+        matrix[0][1] = -0.5;
+        let ctm = ctm(&matrix);
+        assert_eq!(ctm[0], 1 << 32);
+        assert_eq!(ctm[1], (1 << 63) | (1 << 31));
+        assert_eq!(ctm[2], 0);
+    }
+
+    #[test]
+    fn inverted_ramp_runs_backwards() {
+        assert_eq!(gamma_ramp(3, false), vec![0, 32767, 65535]);
+        assert_eq!(gamma_ramp(3, true), vec![65535, 32767, 0]);
+        assert!(gamma_ramp(0, true).is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+// ABOUTME: The mouse cursor: the image its client set over the pointer, or a built-in arrow, drawn topmost where the pointer is.
+// ABOUTME: Shown once a mouse moves and hidden at the next touch; the DRM compositor puts it on a cursor plane where there is one.
+
+use smithay::backend::allocator::Fourcc;
+use smithay::backend::renderer::element::Kind;
+use smithay::backend::renderer::element::memory::{
+    MemoryRenderBuffer, MemoryRenderBufferRenderElement,
+};
+use smithay::backend::renderer::element::surface::render_elements_from_surface_tree;
+use smithay::backend::renderer::{ImportAll, ImportMem, Renderer};
+use smithay::desktop::utils::send_frames_surface_tree;
+use smithay::input::pointer::{CursorImageStatus, CursorImageSurfaceData};
+use smithay::output::Output;
+use smithay::utils::{IsAlive, Logical, Point, Scale, Transform};
+use smithay::wayland::compositor::with_states;
+use tracing::warn;
+
+use crate::render::OutputRenderElements;
+use crate::state::Compositor;
+
+/// Width and height of the built-in arrow, in pixels.
+pub const ARROW_SIZE: i32 = 24;
+
+/// Pixels of the built-in arrow, `size` by `size` in ARGB8888: white with a black
+/// outline, its tip at the top left corner, which is where the pointer is.
+pub fn arrow(size: i32) -> Vec<u8> {
+    // A triangle with its tip at the origin, its left edge straight down and its right
+    // edge at 45 degrees, closed by a line from the bottom of the left edge
+    let l = size.max(1) - 1;
+    let c = l * 2 / 3;
+    let inside = |x: i32, y: i32| x >= 0 && x <= y && c * (y - l) + (l - c) * x <= 0;
+    let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let pixel: [u8; 4] = if !inside(x, y) {
+                [0, 0, 0, 0]
+            } else if [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                .iter()
+                .any(|(dx, dy)| !inside(x + dx, y + dy))
+            {
+                [0, 0, 0, 0xff]
+            } else {
+                [0xff, 0xff, 0xff, 0xff]
+            };
+            pixels.extend_from_slice(&pixel);
+        }
+    }
+    pixels
+}
+
+pub struct Cursor {
+    /// What the client under the pointer asked to show.
+    pub status: CursorImageStatus,
+    /// Whether a mouse moved since the last touch. Touches don't want a cursor.
+    pub visible: bool,
+    arrow: MemoryRenderBuffer,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self {
+            status: CursorImageStatus::default_named(),
+            visible: false,
+            arrow: MemoryRenderBuffer::from_slice(
+                &arrow(ARROW_SIZE),
+                Fourcc::Argb8888,
+                (ARROW_SIZE, ARROW_SIZE),
+                1,
+                Transform::Normal,
+                None,
+            ),
+        }
+    }
+}
+
+impl Cursor {
+    /// What to draw of the cursor with the pointer at `pointer`, in global coordinates,
+    /// on an output at `scale`. Named cursors are all drawn as the arrow, as there is no
+    /// cursor theme to look them up in.
+    pub fn render_elements<R>(
+        &self,
+        renderer: &mut R,
+        pointer: Point<f64, Logical>,
+        scale: f64,
+    ) -> Vec<OutputRenderElements<R>>
+    where
+        R: Renderer + ImportAll + ImportMem,
+        R::TextureId: Send + Clone + 'static,
+    {
+        if !self.visible {
+            return Vec::new();
+        }
+        match &self.status {
+            CursorImageStatus::Hidden => Vec::new(),
+            CursorImageStatus::Surface(surface) if surface.alive() => {
+                let hotspot = with_states(surface, |states| {
+                    states
+                        .data_map
+                        .get::<CursorImageSurfaceData>()
+                        .map(|data| data.lock().unwrap().hotspot)
+                        .unwrap_or_default()
+                });
+                render_elements_from_surface_tree(
+                    renderer,
+                    surface,
+                    (pointer - hotspot.to_f64()).to_physical_precise_round(scale),
+                    Scale::from(scale),
+                    1.0,
+                    Kind::Cursor,
+                )
+                .into_iter()
+                .map(OutputRenderElements::Surface)
+                .collect()
+            }
+            _ => match MemoryRenderBufferRenderElement::from_buffer(
+                renderer,
+                pointer.to_physical(scale),
+                &self.arrow,
+                None,
+                None,
+                None,
+                Kind::Cursor,
+            ) {
+                Ok(element) => vec![OutputRenderElements::Cursor(element)],
+                Err(e) => {
+                    warn!(error = ?e, "failed to upload the cursor");
+                    Vec::new()
+                }
+            },
+        }
+    }
+}
+
+impl Compositor {
+    /// Where the pointer is, in global coordinates.
+    pub fn pointer_location(&self) -> Point<f64, Logical> {
+        self.seat.get_pointer().unwrap().current_location()
+    }
+
+    /// Send frame callbacks to the cursor surface, if a client set one, so that animated
+    /// cursors move on.
+    pub fn send_cursor_frames(&self, output: &Output) {
+        if let CursorImageStatus::Surface(surface) = &self.cursor.status
+            && self.cursor.visible
+        {
+            send_frames_surface_tree(
+                surface,
+                output,
+                self.start_time.elapsed(),
+                Some(std::time::Duration::ZERO),
+                |_, _| Some(output.clone()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alpha(pixels: &[u8], size: i32, x: i32, y: i32) -> u8 {
+        pixels[((y * size + x) * 4 + 3) as usize]
+    }
+
+    #[test]
+    fn the_arrow_points_from_the_top_left() {
+        let pixels = arrow(ARROW_SIZE);
+        assert_eq!(pixels.len(), (ARROW_SIZE * ARROW_SIZE * 4) as usize);
+        assert_eq!(alpha(&pixels, ARROW_SIZE, 0, 0), 0xff);
+        assert_eq!(alpha(&pixels, ARROW_SIZE, ARROW_SIZE - 1, 0), 0);
+        assert_eq!(
+            alpha(&pixels, ARROW_SIZE, ARROW_SIZE - 1, ARROW_SIZE - 1),
+            0
+        );
+
+        // White inside its black outline
+        let inner = ((8 * ARROW_SIZE + 3) * 4) as usize;
+        assert_eq!(pixels[inner..inner + 4], [0xff, 0xff, 0xff, 0xff]);
+        let edge = ((8 * ARROW_SIZE) * 4) as usize;
+        assert_eq!(pixels[edge..edge + 4], [0, 0, 0, 0xff]);
+    }
+}
@@ -0,0 +1,590 @@
+// ABOUTME: org.mobileos.Compositor on the system bus, for the shell's task switcher, and org.mobileos.Display.
+// ABOUTME: Lists the open apps and brings up or closes them, pins one, splits the screen, stacks bubbles, runs the accessibility aids, tells idleness, and turns the screen and the panel.
+
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use smithay::desktop::Window;
+use smithay::reexports::calloop::LoopHandle;
+use smithay::reexports::calloop::channel::{self, Event};
+use smithay::utils::{Logical, Rectangle};
+use smithay::wayland::compositor::with_states;
+use smithay::wayland::shell::xdg::XdgToplevelSurfaceData;
+use tracing::warn;
+use zbus::message::Header;
+
+use crate::color_filter::ColorFilter;
+use crate::state::{Compositor, SHELL_EXE};
+use crate::switch_access::SwitchMode;
+
+const BUS_NAME: &str = "org.mobileos.Compositor";
+pub const OBJECT_PATH: &str = "/org/mobileos/Compositor";
+pub const INTERFACE: &str = "org.mobileos.Compositor";
+
+const DISPLAY_BUS_NAME: &str = "org.mobileos.Display";
+const DISPLAY_OBJECT_PATH: &str = "/org/mobileos/Display";
+
+/// How long a D-Bus call waits for the event loop to answer.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+enum Request {
+    Apps(mpsc::Sender<Vec<String>>),
+    Toplevels(mpsc::Sender<Vec<(String, String)>>),
+    Activate(String, mpsc::Sender<bool>),
+    Close(String, mpsc::Sender<bool>),
+    PinnedApp(mpsc::Sender<String>),
+    Pin(String, mpsc::Sender<bool>),
+    Unpin,
+    CancelUnpin,
+    CanSplit(mpsc::Sender<bool>),
+    SplitApps(mpsc::Sender<Vec<String>>),
+    Split(String, mpsc::Sender<bool>),
+    Unsplit,
+    Bubbles(mpsc::Sender<Vec<String>>),
+    RaiseBubble(String, mpsc::Sender<bool>),
+    SetBubblesHidden(bool),
+    Magnified(mpsc::Sender<bool>),
+    SetMagnified(bool),
+    ColorFilter(mpsc::Sender<(ColorFilter, bool)>),
+    SetColorFilter(ColorFilter),
+    SetColorFilterEnabled(bool),
+    Bedtime(mpsc::Sender<bool>),
+    SetBedtime(bool),
+    ForegroundApp(mpsc::Sender<String>),
+    IdleThresholds(mpsc::Sender<Vec<u32>>),
+    SetIdleThresholds(Vec<u32>, mpsc::Sender<Result<(), String>>),
+    IdleTime(mpsc::Sender<u32>),
+    SwitchAccess(mpsc::Sender<SwitchMode>),
+    SetSwitchAccess(SwitchMode),
+    SetFocusables(String, Vec<Rectangle<i32, Logical>>),
+    Rotation(mpsc::Sender<(u32, bool)>),
+    SetRotation(u32),
+    SetRotationLocked(bool),
+    Powered(mpsc::Sender<bool>),
+    SetPowered(bool),
+}
+
+struct CompositorService {
+    requests: channel::Sender<Request>,
+    /// The executable allowed to pin and unpin.
+    shell: PathBuf,
+}
+
+impl CompositorService {
+    fn new(requests: channel::Sender<Request>, shell: impl Into<PathBuf>) -> Self {
+        Self {
+            requests,
+            shell: shell.into(),
+        }
+    }
+
+    /// Refuse the call unless it comes from the shell, going by the caller's executable.
+    /// A pinned app that could unpin itself would skip the PIN.
+    async fn check_shell(
+        &self,
+        header: &Header<'_>,
+        connection: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let sender = header
+            .sender()
+            .ok_or_else(|| zbus::fdo::Error::AccessDenied("caller unknown".to_string()))?;
+        let pid = zbus::fdo::DBusProxy::new(connection)
+            .await?
+            .get_connection_unix_process_id(sender.clone().into())
+            .await?;
+        let exe = std::fs::read_link(format!("/proc/{pid}/exe")).unwrap_or_default();
+        if exe != self.shell {
+            let exe = exe.display();
+            warn!(pid, exe = %exe, "refused pinning from outside the shell");
+            return Err(zbus::fdo::Error::AccessDenied(
+                "only the shell may pin and unpin apps".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Hand `request` to the event loop and wait for its answer.
+    fn ask<T>(&self, request: impl FnOnce(mpsc::Sender<T>) -> Request) -> zbus::fdo::Result<T> {
+        let (reply, answer) = mpsc::channel();
+        self.send(request(reply))?;
+        answer
+            .recv_timeout(REPLY_TIMEOUT)
+            .map_err(|_| zbus::fdo::Error::Failed("compositor did not answer".into()))
+    }
+
+    /// Hand `request` to the event loop without waiting for it.
+    fn send(&self, request: Request) -> zbus::fdo::Result<()> {
+        self.requests
+            .send(request)
+            .map_err(|_| zbus::fdo::Error::Failed("compositor is shutting down".into()))
+    }
+}
+
+#[zbus::interface(name = "org.mobileos.Compositor")]
+impl CompositorService {
+    /// The app ids of the open windows, topmost first.
+    #[zbus(property)]
+    fn apps(&self) -> zbus::fdo::Result<Vec<String>> {
+        self.ask(Request::Apps)
+    }
+
+    /// The open apps as (app id, window title), topmost first, for an app switcher.
+    #[zbus(property)]
+    fn toplevels(&self) -> zbus::fdo::Result<Vec<(String, String)>> {
+        self.ask(Request::Toplevels)
+    }
+
+    /// Bring up the window of `app_id` and give it the keyboard.
+    fn activate(&self, app_id: &str) -> zbus::fdo::Result<()> {
+        if !self.ask(|reply| Request::Activate(app_id.to_string(), reply))? {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "can't bring up app '{app_id}'"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Ask the window of `app_id` to close, as its own close button would.
+    fn close(&self, app_id: &str) -> zbus::fdo::Result<()> {
+        if !self.ask(|reply| Request::Close(app_id.to_string(), reply))? {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "can't close app '{app_id}'"
+            )));
+        }
+        Ok(())
+    }
+
+    /// The pinned app, or "" while none is.
+    #[zbus(property)]
+    fn pinned_app(&self) -> zbus::fdo::Result<String> {
+        self.ask(Request::PinnedApp)
+    }
+
+    /// Pin the window of `app_id`: it is raised and gets all input, and navigation and the
+    /// power menu are off, until the shell calls Unpin.
+    async fn pin(
+        &self,
+        app_id: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        self.check_shell(&header, connection).await?;
+        if !self.ask(|reply| Request::Pin(app_id.to_string(), reply))? {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "no window for app '{app_id}'"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Called by the shell once the PIN is entered.
+    async fn unpin(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        self.check_shell(&header, connection).await?;
+        self.send(Request::Unpin)
+    }
+
+    /// The PIN prompt was dismissed; the pinned app comes back up.
+    async fn cancel_unpin(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        self.check_shell(&header, connection).await?;
+        self.send(Request::CancelUnpin)
+    }
+
+    /// Whether the display is wide enough for two apps side by side.
+    #[zbus(property)]
+    fn can_split(&self) -> zbus::fdo::Result<bool> {
+        self.ask(Request::CanSplit)
+    }
+
+    /// The apps in split view, left then right, or none outside it.
+    #[zbus(property)]
+    fn split_apps(&self) -> zbus::fdo::Result<Vec<String>> {
+        self.ask(Request::SplitApps)
+    }
+
+    /// Show `app_id` beside the app on top, each taking a side of the screen.
+    fn split(&self, app_id: &str) -> zbus::fdo::Result<()> {
+        if !self.ask(|reply| Request::Split(app_id.to_string(), reply))? {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "can't show '{app_id}' in split view"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Leave split view; both apps go back to the whole screen.
+    fn unsplit(&self) -> zbus::fdo::Result<()> {
+        self.send(Request::Unsplit)
+    }
+
+    /// The apps showing a bubble, topmost first.
+    #[zbus(property)]
+    fn bubbles(&self) -> zbus::fdo::Result<Vec<String>> {
+        self.ask(Request::Bubbles)
+    }
+
+    /// Put the bubble of `app_id` above the others.
+    fn raise_bubble(&self, app_id: &str) -> zbus::fdo::Result<()> {
+        if !self.ask(|reply| Request::RaiseBubble(app_id.to_string(), reply))? {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "no bubble for app '{app_id}'"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Hide the bubbles, or show them again. The shell hides them while the screen is locked.
+    fn set_bubbles_hidden(&self, hidden: bool) -> zbus::fdo::Result<()> {
+        self.send(Request::SetBubblesHidden(hidden))
+    }
+
+    /// Whether the screen magnifier is on.
+    #[zbus(property)]
+    fn magnified(&self) -> zbus::fdo::Result<bool> {
+        self.ask(Request::Magnified)
+    }
+
+    /// Turn the screen magnifier on, zoomed around the middle of the screen, or off. For
+    /// accessibility settings; users also triple-tap or hold both volume keys.
+    fn set_magnified(&self, on: bool) -> zbus::fdo::Result<()> {
+        self.send(Request::SetMagnified(on))
+    }
+
+    /// The chosen color filter: invert, grayscale, protanopia or deuteranopia.
+    #[zbus(property)]
+    fn color_filter(&self) -> zbus::fdo::Result<String> {
+        let (filter, _) = self.ask(Request::ColorFilter)?;
+        Ok(filter.to_string())
+    }
+
+    /// Whether the chosen color filter is on.
+    #[zbus(property)]
+    fn color_filter_enabled(&self) -> zbus::fdo::Result<bool> {
+        let (_, enabled) = self.ask(Request::ColorFilter)?;
+        Ok(enabled)
+    }
+
+    /// Choose a color filter and turn it on. For accessibility settings.
+    fn set_color_filter(&self, name: &str) -> zbus::fdo::Result<()> {
+        let filter = name
+            .parse()
+            .map_err(|e: anyhow::Error| zbus::fdo::Error::InvalidArgs(e.to_string()))?;
+        self.send(Request::SetColorFilter(filter))
+    }
+
+    /// Turn the chosen color filter on or off. For the quick-settings tile.
+    fn set_color_filter_enabled(&self, enabled: bool) -> zbus::fdo::Result<()> {
+        self.send(Request::SetColorFilterEnabled(enabled))
+    }
+
+    /// Whether bedtime mode has the screen in grayscale.
+    #[zbus(property)]
+    fn bedtime(&self) -> zbus::fdo::Result<bool> {
+        self.ask(Request::Bedtime)
+    }
+
+    /// Start or end bedtime mode. For the wellbeing service, on its schedule.
+    fn set_bedtime(&self, on: bool) -> zbus::fdo::Result<()> {
+        self.send(Request::SetBedtime(on))
+    }
+
+    /// The app the user is spending time in, or "" on the home screen, while locked or
+    /// with the screen off. ForegroundChanged says when it changes.
+    #[zbus(property)]
+    fn foreground_app(&self) -> zbus::fdo::Result<String> {
+        self.ask(Request::ForegroundApp)
+    }
+
+    /// Seconds without input after which IdlePassed is signalled with each threshold's
+    /// index. Active is signalled at the next input after any has passed.
+    #[zbus(property)]
+    fn idle_thresholds(&self) -> zbus::fdo::Result<Vec<u32>> {
+        self.ask(Request::IdleThresholds)
+    }
+
+    /// Replace the idle thresholds, which must not go down or be 0. For the power
+    /// service, which dims, locks and turns the screen off as they pass.
+    fn set_idle_thresholds(&self, thresholds: Vec<u32>) -> zbus::fdo::Result<()> {
+        self.ask(|reply| Request::SetIdleThresholds(thresholds, reply))?
+            .map_err(zbus::fdo::Error::InvalidArgs)
+    }
+
+    /// Seconds since the user last touched the screen or pressed a key.
+    #[zbus(property)]
+    fn idle_time(&self) -> zbus::fdo::Result<u32> {
+        self.ask(Request::IdleTime)
+    }
+
+    /// How switch access is used: "off", "one" switch that activates while the highlight
+    /// moves on by itself, or "two", one to move it and one to activate.
+    #[zbus(property)]
+    fn switch_access(&self) -> zbus::fdo::Result<String> {
+        Ok(self.ask(Request::SwitchAccess)?.to_string())
+    }
+
+    /// Turn switch access on for one or two switches, or off. For accessibility settings.
+    fn set_switch_access(&self, mode: &str) -> zbus::fdo::Result<()> {
+        let mode = mode
+            .parse()
+            .map_err(|e: anyhow::Error| zbus::fdo::Error::InvalidArgs(e.to_string()))?;
+        self.send(Request::SetSwitchAccess(mode))
+    }
+
+    /// Report the controls the window of `app_id` shows, as (x, y, width, height) in its
+    /// surface coordinates, in the order switch access should scan them. Apps report
+    /// again whenever their layout changes; an empty list withdraws them.
+    fn set_focusables(
+        &self,
+        app_id: &str,
+        regions: Vec<(i32, i32, i32, i32)>,
+    ) -> zbus::fdo::Result<()> {
+        if let Some(region) = regions.iter().find(|(_, _, w, h)| *w <= 0 || *h <= 0) {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "region {region:?} is empty"
+            )));
+        }
+        let regions = regions
+            .into_iter()
+            .map(|(x, y, w, h)| Rectangle::new((x, y).into(), (w, h).into()))
+            .collect();
+        self.send(Request::SetFocusables(app_id.to_string(), regions))
+    }
+}
+
+/// org.mobileos.Display, served by the compositor as it owns the output.
+struct DisplayService(CompositorService);
+
+#[zbus::interface(name = "org.mobileos.Display")]
+impl DisplayService {
+    /// How far the phone is turned clockwise from upright, in degrees: 0, 90, 180 or
+    /// 270. The picture turns back as far. Unless RotationLocked, it follows the
+    /// accelerometer.
+    #[zbus(property)]
+    fn rotation(&self) -> zbus::fdo::Result<u32> {
+        let (degrees, _) = self.0.ask(Request::Rotation)?;
+        Ok(degrees)
+    }
+
+    #[zbus(property)]
+    fn set_rotation(&mut self, degrees: u32) -> zbus::fdo::Result<()> {
+        if degrees % 90 != 0 || degrees >= 360 {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "{degrees} is not 0, 90, 180 or 270"
+            )));
+        }
+        self.0.send(Request::SetRotation(degrees))
+    }
+
+    /// Whether the screen stays as it is however the phone is turned. For the
+    /// quick-settings tile.
+    #[zbus(property)]
+    fn rotation_locked(&self) -> zbus::fdo::Result<bool> {
+        let (_, locked) = self.0.ask(Request::Rotation)?;
+        Ok(locked)
+    }
+
+    #[zbus(property)]
+    fn set_rotation_locked(&mut self, locked: bool) -> zbus::fdo::Result<()> {
+        self.0.send(Request::SetRotationLocked(locked))
+    }
+
+    /// Whether the panel is on. The power service turns it off once the phone is left
+    /// alone, and while it is held to an ear on a call; nothing is drawn until it is
+    /// back on.
+    #[zbus(property)]
+    fn powered(&self) -> zbus::fdo::Result<bool> {
+        self.0.ask(Request::Powered)
+    }
+
+    #[zbus(property)]
+    fn set_powered(&mut self, on: bool) -> zbus::fdo::Result<()> {
+        self.0.send(Request::SetPowered(on))
+    }
+}
+
+/// Take org.mobileos.Compositor and org.mobileos.Display on the system bus. Calls come
+/// into the event loop through a channel, since the compositor state lives there.
+pub fn serve(handle: &LoopHandle<'_, Compositor>, state: &mut Compositor) -> anyhow::Result<()> {
+    let (requests, receiver) = channel::channel::<Request>();
+    handle
+        .insert_source(receiver, |event, _, state| {
+            if let Event::Msg(request) = event {
+                state.handle_bus_request(request);
+            }
+        })
+        .map_err(|e| anyhow::anyhow!("failed to insert D-Bus request source: {e}"))?;
+
+    let display = DisplayService(CompositorService::new(requests.clone(), SHELL_EXE));
+    let connection = zbus::blocking::connection::Builder::system()?
+        .name(BUS_NAME)?
+        .name(DISPLAY_BUS_NAME)?
+        .serve_at(OBJECT_PATH, CompositorService::new(requests, SHELL_EXE))?
+        .serve_at(DISPLAY_OBJECT_PATH, display)?
+        .build()?;
+    state.bus = Some(connection);
+    Ok(())
+}
+
+/// The app id a toplevel set, if any.
+pub fn window_app_id(window: &Window) -> Option<String> {
+    let toplevel = window.toplevel()?;
+    with_states(toplevel.wl_surface(), |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()?
+            .lock()
+            .unwrap()
+            .app_id
+            .clone()
+    })
+}
+
+impl Compositor {
+    fn handle_bus_request(&mut self, request: Request) {
+        match request {
+            Request::Apps(reply) => {
+                let _ = reply.send(self.app_ids());
+            }
+            Request::Toplevels(reply) => {
+                let _ = reply.send(self.toplevels());
+            }
+            Request::Activate(app_id, reply) => {
+                let _ = reply.send(self.activate(&app_id));
+            }
+            Request::Close(app_id, reply) => {
+                let _ = reply.send(self.close(&app_id));
+            }
+            Request::PinnedApp(reply) => {
+                let _ = reply.send(self.pinned_app_id().unwrap_or_default());
+            }
+            Request::Pin(app_id, reply) => {
+                let _ = reply.send(self.pin(&app_id));
+            }
+            Request::Unpin => self.unpin(),
+            Request::CancelUnpin => self.cancel_unpin(),
+            Request::CanSplit(reply) => {
+                let _ = reply.send(self.can_split());
+            }
+            Request::SplitApps(reply) => {
+                let _ = reply.send(self.split_app_ids());
+            }
+            Request::Split(app_id, reply) => {
+                let _ = reply.send(self.split(&app_id));
+            }
+            Request::Unsplit => self.unsplit(),
+            Request::Bubbles(reply) => {
+                let _ = reply.send(self.bubble_app_ids());
+            }
+            Request::RaiseBubble(app_id, reply) => {
+                let _ = reply.send(self.raise_bubble(&app_id));
+            }
+            Request::SetBubblesHidden(hidden) => self.set_bubbles_hidden(hidden),
+            Request::Magnified(reply) => {
+                let _ = reply.send(self.magnified());
+            }
+            Request::SetMagnified(on) => {
+                if on != self.magnified() {
+                    self.toggle_magnifier();
+                }
+            }
+            Request::ColorFilter(reply) => {
+                let _ = reply.send((self.color_filter(), self.color_filter_enabled()));
+            }
+            Request::SetColorFilter(filter) => self.set_color_filter(filter),
+            Request::SetColorFilterEnabled(enabled) => self.set_color_filter_enabled(enabled),
+            Request::Bedtime(reply) => {
+                let _ = reply.send(self.bedtime());
+            }
+            Request::SetBedtime(on) => self.set_bedtime(on),
+            Request::ForegroundApp(reply) => {
+                let _ = reply.send(self.app_on_screen());
+            }
+            Request::IdleThresholds(reply) => {
+                let _ = reply.send(self.idle.thresholds().to_vec());
+            }
+            Request::SetIdleThresholds(thresholds, reply) => {
+                let set = self.idle.set_thresholds(thresholds);
+                let _ = reply.send(set.map_err(|e| e.to_string()));
+            }
+            Request::IdleTime(reply) => {
+                let _ = reply.send(self.idle.idle_time(std::time::Instant::now()));
+            }
+            Request::SwitchAccess(reply) => {
+                let _ = reply.send(self.switch_mode());
+            }
+            Request::SetSwitchAccess(mode) => self.set_switch_mode(mode),
+            Request::SetFocusables(app_id, regions) => self.set_focusables(&app_id, regions),
+            Request::Rotation(reply) => {
+                let _ = reply.send((self.rotation(), self.rotation_locked()));
+            }
+            Request::SetRotation(degrees) => self.set_rotation(degrees),
+            Request::SetRotationLocked(locked) => self.set_rotation_locked(locked),
+            Request::Powered(reply) => {
+                let _ = reply.send(self.display_on());
+            }
+            Request::SetPowered(on) => self.set_display_on(on),
+        }
+    }
+
+    /// Bubbles are left out; they are listed on their own.
+    pub fn app_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .space
+            .elements()
+            .filter(|w| !self.is_bubble(w))
+            .filter_map(window_app_id)
+            .collect();
+        ids.reverse();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[zbus::proxy(
+        interface = "org.mobileos.Compositor",
+        default_path = "/org/mobileos/Compositor"
+    )]
+    trait Pinning {
+        fn unpin(&self) -> zbus::Result<()>;
+        fn cancel_unpin(&self) -> zbus::Result<()>;
+    }
+
+    #[test]
+    fn only_the_shell_may_unpin() {
+        let (requests, _receiver) = channel::channel::<Request>();
+        let service = CompositorService::new(requests, SHELL_EXE);
+        let conn = zbus::blocking::connection::Builder::session()
+            .unwrap()
+            .serve_at(OBJECT_PATH, service)
+            .unwrap()
+            .build()
+            .unwrap();
+        let name = conn.unique_name().unwrap().to_owned();
+
+        let client = zbus::blocking::Connection::session().unwrap();
+        let proxy = PinningProxyBlocking::builder(&client)
+            .destination(name)
+            .unwrap()
+            .build()
+            .unwrap();
+        for call in [proxy.unpin(), proxy.cancel_unpin()] {
+            match call {
+                Err(zbus::Error::MethodError(name, _, _)) => {
+                    assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.AccessDenied")
+                }
+                other => panic!("expected AccessDenied, got {other:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+// ABOUTME: Turns the panel off and on for org.mobileos.Display: the power service's idle timeout and calls.
+// ABOUTME: Nothing is drawn while it is off, and touches are dropped so a cheek on the glass presses nothing.
+
+use tracing::{info, warn};
+
+use crate::state::Compositor;
+use crate::udev;
+
+impl Compositor {
+    pub fn display_on(&self) -> bool {
+        !self.display_off
+    }
+
+    /// Turn the panel off, or back on. While it is off no frames are drawn and clients
+    /// get no frame callbacks, so they stop drawing too.
+    pub fn set_display_on(&mut self, on: bool) {
+        if self.display_on() == on {
+            return;
+        }
+        info!(on, "turning the display");
+        self.display_off = !on;
+        if !on && let Some(touch) = self.seat.get_touch() {
+            touch.cancel(self);
+        }
+        self.update_app_on_screen();
+
+        let Some(drm) = self.drm.as_mut() else {
+            return;
+        };
+        if let Err(e) = udev::set_dpms(drm, on) {
+            warn!(error = %e, "failed to set display power");
+        }
+        if on {
+            // Frames are only drawn on vblank, and there was none while it was off
+            udev::render_frame(self);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+
+    use crate::state::Compositor;
+
+    #[test]
+    fn the_display_turns_off_and_on() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+        assert!(state.display_on());
+
+        state.set_display_on(false);
+        assert!(!state.display_on());
+        state.set_display_on(true);
+        assert!(state.display_on());
+    }
+}
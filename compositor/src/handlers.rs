@@ -1,27 +1,35 @@
 // ABOUTME: Wayland protocol handler implementations for the compositor.
-// ABOUTME: Delegates wl_compositor, xdg_shell, wlr_layer_shell, shm, seat, data_device, and output protocols.
+// ABOUTME: Delegates the core, shell, session-lock, foreign-toplevel and text-input/IME protocols.
 
 use std::os::unix::io::OwnedFd;
 
 use smithay::delegate_compositor;
 use smithay::delegate_data_device;
+use smithay::delegate_foreign_toplevel_list;
+use smithay::delegate_idle_notify;
+use smithay::delegate_input_method_manager;
 use smithay::delegate_layer_shell;
 use smithay::delegate_output;
 use smithay::delegate_seat;
+use smithay::delegate_session_lock;
 use smithay::delegate_shm;
+use smithay::delegate_text_input_manager;
+use smithay::delegate_virtual_keyboard_manager;
 use smithay::delegate_xdg_shell;
-use smithay::desktop::Window;
+use smithay::desktop::{PopupKind, PopupManager, Window};
 use smithay::input::pointer::CursorImageStatus;
 use smithay::input::{Seat, SeatHandler, SeatState};
 use smithay::reexports::wayland_server::protocol::wl_buffer;
 use smithay::reexports::wayland_server::protocol::wl_seat;
 use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
 use smithay::reexports::wayland_server::{Client, Resource};
-use smithay::utils::Serial;
+use smithay::utils::{Logical, Rectangle, Serial};
 use smithay::wayland::buffer::BufferHandler;
 use smithay::wayland::compositor::{
-    get_parent, is_sync_subsurface, CompositorClientState, CompositorHandler, CompositorState,
+    get_parent, is_sync_subsurface, with_states, CompositorClientState, CompositorHandler,
+    CompositorState,
 };
+use smithay::wayland::input_method::{InputMethodHandler, PopupSurface as InputMethodPopup};
 use smithay::wayland::output::OutputHandler;
 use smithay::wayland::selection::data_device::{
     set_data_device_focus, ClientDndGrabHandler, DataDeviceHandler, DataDeviceState,
@@ -30,11 +38,13 @@ use smithay::wayland::selection::data_device::{
 use smithay::wayland::selection::SelectionHandler;
 use smithay::wayland::shell::xdg::{
     PopupSurface, PositionerState, ToplevelSurface, XdgShellHandler, XdgShellState,
+    XdgToplevelSurfaceData,
 };
 use smithay::wayland::shell::wlr_layer::{Layer, LayerSurface, WlrLayerShellHandler, WlrLayerShellState};
 use smithay::wayland::shm::{ShmHandler, ShmState};
-use tracing::info;
+use tracing::warn;
 
+use crate::animation;
 use crate::state::{ClientState, Compositor};
 
 impl CompositorHandler for Compositor {
@@ -62,6 +72,35 @@ impl CompositorHandler for Compositor {
                 window.on_commit();
             }
         }
+
+        // Clients may not attach a buffer until their toplevel has been configured once
+        if let Some(window) = self
+            .space
+            .elements()
+            .find(|w| w.toplevel().unwrap().wl_surface() == surface)
+            .cloned()
+        {
+            let initial_configure_sent = with_states(surface, |states| {
+                states
+                    .data_map
+                    .get::<XdgToplevelSurfaceData>()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .initial_configure_sent
+            });
+            if !initial_configure_sent {
+                // The app id is set by now, so it is known whether a bubble is wanted
+                if !self.adopt_bubble(&window) {
+                    self.maximize(&window);
+                }
+                self.animations.start(&window, animation::Kind::Open);
+                window.toplevel().unwrap().send_configure();
+                self.update_app_on_screen();
+            }
+        }
+
+        self.layer_committed(surface);
     }
 }
 
@@ -84,12 +123,15 @@ impl SeatHandler for Compositor {
         &mut self.seat_state
     }
 
-    fn cursor_image(&mut self, _seat: &Seat<Self>, _image: CursorImageStatus) {}
+    fn cursor_image(&mut self, _seat: &Seat<Self>, image: CursorImageStatus) {
+        self.cursor.status = image;
+    }
 
     fn focus_changed(&mut self, seat: &Seat<Self>, focused: Option<&WlSurface>) {
         let dh = &self.display_handle;
         let client = focused.and_then(|s| dh.get_client(s.id()).ok());
         set_data_device_focus(dh, seat, client);
+        self.update_app_on_screen();
     }
 }
 
@@ -99,8 +141,27 @@ impl XdgShellHandler for Compositor {
     }
 
     fn new_toplevel(&mut self, surface: ToplevelSurface) {
+        self.toplevel_opened(&surface);
         let window = Window::new_wayland_window(surface);
         self.space.map_element(window, (0, 0), false);
+        self.restack_bubbles();
+    }
+
+    fn title_changed(&mut self, surface: ToplevelSurface) {
+        self.toplevel_changed(&surface);
+    }
+
+    fn app_id_changed(&mut self, surface: ToplevelSurface) {
+        self.toplevel_changed(&surface);
+    }
+
+    fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
+        self.toplevel_closed(&surface);
+        self.split_window_closed(surface.wl_surface());
+        // Before bubble_closed, while a closing bubble is still known as one
+        self.window_closed(surface.wl_surface());
+        self.bubble_closed(surface.wl_surface());
+        self.update_app_on_screen();
     }
 
     fn new_popup(&mut self, surface: PopupSurface, _positioner: PositionerState) {
@@ -148,12 +209,41 @@ impl WlrLayerShellHandler for Compositor {
     fn new_layer_surface(
         &mut self,
         surface: LayerSurface,
-        _output: Option<smithay::reexports::wayland_server::protocol::wl_output::WlOutput>,
+        output: Option<smithay::reexports::wayland_server::protocol::wl_output::WlOutput>,
         _layer: Layer,
         namespace: String,
     ) {
-        info!(namespace, "new layer surface");
-        surface.send_configure();
+        self.layer_opened(surface, output, namespace);
+    }
+
+    fn layer_destroyed(&mut self, surface: LayerSurface) {
+        self.layer_closed(&surface);
+    }
+}
+
+/// The on-screen keyboard is an input method; its popups, such as candidate lists, sit
+/// by the text field being typed in.
+impl InputMethodHandler for Compositor {
+    fn new_popup(&mut self, surface: InputMethodPopup) {
+        if let Err(e) = self.popups.track_popup(PopupKind::from(surface)) {
+            warn!(error = %e, "failed to track input method popup");
+        }
+    }
+
+    fn popup_repositioned(&mut self, _surface: InputMethodPopup) {}
+
+    fn dismiss_popup(&mut self, surface: InputMethodPopup) {
+        if let Some(parent) = surface.get_parent().map(|parent| parent.surface.clone()) {
+            let _ = PopupManager::dismiss_popup(&parent, &PopupKind::from(surface));
+        }
+    }
+
+    fn parent_geometry(&self, parent: &WlSurface) -> Rectangle<i32, Logical> {
+        self.space
+            .elements()
+            .find(|w| w.toplevel().is_some_and(|t| t.wl_surface() == parent))
+            .map(|w| w.geometry())
+            .unwrap_or_default()
     }
 }
 
@@ -164,5 +254,11 @@ delegate_shm!(Compositor);
 delegate_seat!(Compositor);
 delegate_xdg_shell!(Compositor);
 delegate_layer_shell!(Compositor);
+delegate_session_lock!(Compositor);
 delegate_data_device!(Compositor);
 delegate_output!(Compositor);
+delegate_text_input_manager!(Compositor);
+delegate_input_method_manager!(Compositor);
+delegate_virtual_keyboard_manager!(Compositor);
+delegate_idle_notify!(Compositor);
+delegate_foreign_toplevel_list!(Compositor);
@@ -0,0 +1,78 @@
+// ABOUTME: Headless backend with a virtual output and no rendering.
+// ABOUTME: Drives frame callbacks on a timer so recorded client sessions can be replayed anywhere.
+
+use std::time::Duration;
+
+use smithay::output::{Mode, Output, PhysicalProperties, Subpixel};
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use smithay::reexports::calloop::EventLoop;
+use smithay::utils::Transform;
+use tracing::info;
+
+use crate::state::Compositor;
+
+/// Portrait phone-sized output, matching the QEMU virtio-gpu default.
+const OUTPUT_SIZE: (i32, i32) = (720, 1440);
+
+const FRAME_INTERVAL: Duration = Duration::from_micros(16_667);
+
+pub fn init_headless(
+    event_loop: &mut EventLoop<Compositor>,
+    state: &mut Compositor,
+) -> anyhow::Result<()> {
+    let mode = Mode {
+        size: OUTPUT_SIZE.into(),
+        refresh: 60_000,
+    };
+
+    let output = Output::new(
+        "headless".to_string(),
+        PhysicalProperties {
+            size: (0, 0).into(),
+            subpixel: Subpixel::Unknown,
+            make: "MobileOS".into(),
+            model: "Headless".into(),
+        },
+    );
+
+    let _global = output.create_global::<Compositor>(&state.display_handle);
+    output.change_current_state(Some(mode), Some(Transform::Normal), None, Some((0, 0).into()));
+    output.set_preferred(mode);
+    state.space.map_output(&output, (0, 0));
+
+    info!(size = ?mode.size, "headless output created");
+
+    event_loop
+        .handle()
+        .insert_source(Timer::from_duration(FRAME_INTERVAL), move |_, _, state| {
+            if !state.display_on() {
+                // Clients hold off drawing until the display is back on
+            } else if state.is_locked() {
+                // Nothing is drawn, so the session is as hidden as it gets
+                state.confirm_lock();
+                state.send_lock_frames(&output);
+            } else {
+                state.send_layer_frames(&output);
+                state.space.elements().for_each(|window| {
+                    window.send_frame(
+                        &output,
+                        state.start_time.elapsed(),
+                        Some(Duration::ZERO),
+                        |_, _| Some(output.clone()),
+                    );
+                });
+            }
+
+            // Nothing is drawn to copy
+            state.screencopy.fail_output(&output);
+
+            state.space.refresh();
+            state.popups.cleanup();
+            let _ = state.display_handle.flush_clients();
+
+            TimeoutAction::ToDuration(FRAME_INTERVAL)
+        })
+        .map_err(|e| anyhow::anyhow!("failed to insert frame timer: {e}"))?;
+
+    Ok(())
+}
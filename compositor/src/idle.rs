@@ -0,0 +1,192 @@
+// ABOUTME: Idle detection: when input last came, ext-idle-notify-v1 for clients, and idle thresholds on the bus.
+// ABOUTME: The power service sets the thresholds, and dims, locks and turns the screen off as IdlePassed goes by each.
+
+use std::time::{Duration, Instant};
+
+use smithay::reexports::calloop::LoopHandle;
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use smithay::wayland::idle_notify::{IdleNotifierHandler, IdleNotifierState};
+use tracing::{info, warn};
+
+use crate::dbus;
+use crate::state::Compositor;
+
+/// How often the time since the last input is checked against the thresholds.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When input last came, and which of the thresholds have gone by since.
+pub struct Idle {
+    last_input: Instant,
+    /// Seconds without input at which IdlePassed is signalled, in order.
+    thresholds: Vec<u32>,
+    /// How many of the thresholds have gone by since the last input.
+    passed: usize,
+}
+
+impl Default for Idle {
+    fn default() -> Self {
+        Self {
+            last_input: Instant::now(),
+            thresholds: Vec::new(),
+            passed: 0,
+        }
+    }
+}
+
+impl Idle {
+    /// Input came at `now`. Says whether the user had been idle past a threshold.
+    fn input(&mut self, now: Instant) -> bool {
+        self.last_input = now;
+        std::mem::take(&mut self.passed) > 0
+    }
+
+    /// The thresholds that went by up to `now` since last checked, by index.
+    fn check(&mut self, now: Instant) -> std::ops::Range<usize> {
+        let idle = now.duration_since(self.last_input);
+        let from = self.passed;
+        while self
+            .thresholds
+            .get(self.passed)
+            .is_some_and(|&seconds| idle >= Duration::from_secs(seconds.into()))
+        {
+            self.passed += 1;
+        }
+        from..self.passed
+    }
+
+    /// Seconds since the last input.
+    pub fn idle_time(&self, now: Instant) -> u32 {
+        now.duration_since(self.last_input)
+            .as_secs()
+            .try_into()
+            .unwrap_or(u32::MAX)
+    }
+
+    pub fn thresholds(&self) -> &[u32] {
+        &self.thresholds
+    }
+
+    /// Replace the thresholds, in seconds, which must not go down or be 0. They count
+    /// from the last input, so ones already gone by are signalled at the next check.
+    pub fn set_thresholds(&mut self, thresholds: Vec<u32>) -> anyhow::Result<()> {
+        if thresholds.contains(&0) {
+            anyhow::bail!("an idle threshold of 0 seconds would pass at every input");
+        }
+        if thresholds.windows(2).any(|pair| pair[0] > pair[1]) {
+            anyhow::bail!("idle thresholds {thresholds:?} are out of order");
+        }
+        info!(?thresholds, "idle thresholds set");
+        self.thresholds = thresholds;
+        self.passed = 0;
+        Ok(())
+    }
+}
+
+impl Compositor {
+    /// Input came from the user: clients watching for idleness are told, and the
+    /// thresholds start over.
+    pub fn notify_activity(&mut self) {
+        self.idle_notifier_state.notify_activity(&self.seat);
+        if self.idle.input(Instant::now()) {
+            self.signal_active();
+        }
+    }
+
+    /// Signal IdlePassed for the thresholds that have gone by without input.
+    fn check_idle(&mut self) {
+        for threshold in self.idle.check(Instant::now()) {
+            info!(
+                threshold,
+                seconds = self.idle.thresholds[threshold],
+                "idle threshold passed"
+            );
+            self.signal_idle_passed(threshold as u32);
+        }
+    }
+
+    fn signal_idle_passed(&self, threshold: u32) {
+        let Some(bus) = &self.bus else {
+            return;
+        };
+        let sent = bus.emit_signal(
+            None::<zbus::names::BusName<'_>>,
+            dbus::OBJECT_PATH,
+            dbus::INTERFACE,
+            "IdlePassed",
+            &(threshold,),
+        );
+        if let Err(e) = sent {
+            warn!(error = %e, "failed to signal an idle threshold");
+        }
+    }
+
+    fn signal_active(&self) {
+        let Some(bus) = &self.bus else {
+            return;
+        };
+        let sent = bus.emit_signal(
+            None::<zbus::names::BusName<'_>>,
+            dbus::OBJECT_PATH,
+            dbus::INTERFACE,
+            "Active",
+            &(),
+        );
+        if let Err(e) = sent {
+            warn!(error = %e, "failed to signal the user is back");
+        }
+    }
+}
+
+impl IdleNotifierHandler for Compositor {
+    fn idle_notifier_state(&mut self) -> &mut IdleNotifierState<Self> {
+        &mut self.idle_notifier_state
+    }
+}
+
+/// Check for idle thresholds going by, once a second.
+pub fn watch_idle(handle: &LoopHandle<'_, Compositor>) -> anyhow::Result<()> {
+    handle
+        .insert_source(Timer::from_duration(CHECK_INTERVAL), |_, _, state| {
+            state.check_idle();
+            TimeoutAction::ToDuration(CHECK_INTERVAL)
+        })
+        .map_err(|e| anyhow::anyhow!("failed to insert idle timer: {e}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds_pass_in_order_until_input() {
+        let start = Instant::now();
+        let mut idle = Idle::default();
+        idle.input(start);
+        idle.set_thresholds(vec![20, 28, 30]).unwrap();
+        let at = |seconds| start + Duration::from_secs(seconds);
+
+        assert_eq!(idle.check(at(10)), 0..0);
+        assert_eq!(idle.check(at(20)), 0..1);
+        assert_eq!(idle.check(at(25)), 1..1);
+        assert_eq!(idle.check(at(40)), 1..3);
+        assert_eq!(idle.check(at(50)), 3..3);
+        assert_eq!(idle.idle_time(at(50)), 50);
+
+        assert!(idle.input(at(51)));
+        assert!(!idle.input(at(52)));
+        assert_eq!(idle.check(at(60)), 0..0);
+        assert_eq!(idle.check(at(72)), 0..1);
+    }
+
+    #[test]
+    fn thresholds_must_make_sense() {
+        let mut idle = Idle::default();
+        assert!(idle.set_thresholds(vec![0, 10]).is_err());
+        assert!(idle.set_thresholds(vec![30, 10]).is_err());
+        idle.set_thresholds(vec![10, 10]).unwrap();
+        assert_eq!(idle.thresholds(), [10, 10]);
+        idle.set_thresholds(Vec::new()).unwrap();
+        assert_eq!(idle.check(Instant::now() + Duration::from_secs(60)), 0..0);
+    }
+}
@@ -2,13 +2,16 @@
 // ABOUTME: Routes backend input events to the appropriate Wayland seat devices.
 
 use smithay::backend::input::{
-    AbsolutePositionEvent, Event, InputEvent, KeyboardKeyEvent, PointerButtonEvent, TouchEvent,
+    AbsolutePositionEvent, Event, InputEvent, KeyState, KeyboardKeyEvent, PointerButtonEvent,
+    TouchEvent, TouchSlot,
 };
 use smithay::input::keyboard::FilterResult;
 use smithay::input::pointer::{ButtonEvent, MotionEvent};
 use smithay::input::touch;
-use smithay::utils::SERIAL_COUNTER;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::utils::{Logical, Point, SERIAL_COUNTER};
 
+use crate::layers::{ABOVE_WINDOWS, BELOW_WINDOWS};
 use crate::state::Compositor;
 
 impl Compositor {
@@ -16,6 +19,20 @@ impl Compositor {
         &mut self,
         event: InputEvent<I>,
     ) {
+        if (self.in_pocket || !self.display_on())
+            && matches!(
+                event,
+                InputEvent::TouchDown { .. }
+                    | InputEvent::TouchMotion { .. }
+                    | InputEvent::TouchUp { .. }
+                    | InputEvent::TouchFrame { .. }
+                    | InputEvent::TouchCancel { .. }
+            )
+        {
+            return;
+        }
+        self.notify_activity();
+
         match event {
             InputEvent::Keyboard { event } => self.on_keyboard::<I>(event),
             InputEvent::PointerMotionAbsolute { event } => {
@@ -25,10 +42,7 @@ impl Compositor {
             InputEvent::TouchDown { event } => self.on_touch_down::<I>(event),
             InputEvent::TouchMotion { event } => self.on_touch_motion::<I>(event),
             InputEvent::TouchUp { event } => self.on_touch_up::<I>(event),
-            InputEvent::TouchFrame { .. } => {
-                let touch = self.seat.get_touch().unwrap();
-                touch.frame(self);
-            }
+            InputEvent::TouchFrame { .. } => self.touch_frame(),
             _ => {}
         }
     }
@@ -37,9 +51,11 @@ impl Compositor {
         &mut self,
         event: I::KeyboardKeyEvent,
     ) {
+        self.keep_lock_focus();
         let serial = SERIAL_COUNTER.next_serial();
         let time = Event::time_msec(&event);
         let keyboard = self.seat.get_keyboard().unwrap();
+        let pressed = event.state() == KeyState::Pressed;
 
         keyboard.input::<(), _>(
             self,
@@ -47,7 +63,17 @@ impl Compositor {
             event.state(),
             serial,
             time,
-            |_, _, _| FilterResult::Forward,
+            |state, _, keysym| {
+                if state.magnifier_key(keysym.modified_sym(), pressed)
+                    || state.switch_access_key(keysym.modified_sym(), pressed, time)
+                    || state.assistant_key(keysym.modified_sym(), pressed, time)
+                    || state.intercepts_key(keysym.modified_sym())
+                {
+                    FilterResult::Intercept(())
+                } else {
+                    FilterResult::Forward
+                }
+            },
         );
     }
 
@@ -55,21 +81,12 @@ impl Compositor {
         &mut self,
         event: I::PointerMotionAbsoluteEvent,
     ) {
-        let output = self.space.outputs().next().cloned();
-        let output_geo = output
-            .as_ref()
-            .map(|o| self.space.output_geometry(o).unwrap());
-
-        if let Some(geo) = output_geo {
-            let pos = event.position_transformed(geo.size);
+        if let Some(pos) = self.absolute_position(&event) {
+            let pos = self.unmagnify(pos);
+            self.cursor.visible = true;
             let serial = SERIAL_COUNTER.next_serial();
 
-            let under = self.space.element_under(pos);
-            let surface_under = under.and_then(|(window, loc)| {
-                window
-                    .surface_under(pos - loc.to_f64(), smithay::desktop::WindowSurfaceType::ALL)
-                    .map(|(s, p)| (s, p.to_f64() + loc.to_f64()))
-            });
+            let surface_under = self.surface_under(pos);
 
             let pointer = self.seat.get_pointer().unwrap();
             pointer.motion(
@@ -82,11 +99,19 @@ impl Compositor {
                 },
             );
 
+            if self.is_locked() {
+                self.keep_lock_focus();
+                return;
+            }
             let keyboard = self.seat.get_keyboard().unwrap();
             let focus = self
-                .space
-                .element_under(pos)
-                .and_then(|(w, _)| w.toplevel().map(|t| t.wl_surface().clone()));
+                .layer_focus_under(pos)
+                .or_else(|| {
+                    self.space
+                        .element_under(pos)
+                        .and_then(|(w, _)| w.toplevel().map(|t| t.wl_surface().clone()))
+                })
+                .filter(|surface| self.accepts_input(surface));
             keyboard.set_focus(self, focus, serial);
         }
     }
@@ -113,33 +138,8 @@ impl Compositor {
         &mut self,
         event: I::TouchDownEvent,
     ) {
-        let output = self.space.outputs().next().cloned();
-        let output_geo = output
-            .as_ref()
-            .map(|o| self.space.output_geometry(o).unwrap());
-
-        if let Some(geo) = output_geo {
-            let pos = event.position_transformed(geo.size);
-            let serial = SERIAL_COUNTER.next_serial();
-
-            let under = self.space.element_under(pos);
-            let focus = under.and_then(|(window, loc)| {
-                window
-                    .surface_under(pos - loc.to_f64(), smithay::desktop::WindowSurfaceType::ALL)
-                    .map(|(s, p)| (s, p.to_f64() + loc.to_f64()))
-            });
-
-            let touch_handle = self.seat.get_touch().unwrap();
-            touch_handle.down(
-                self,
-                focus,
-                &touch::DownEvent {
-                    slot: event.slot(),
-                    location: pos,
-                    serial,
-                    time: event.time_msec(),
-                },
-            );
+        if let Some(pos) = self.absolute_position(&event) {
+            self.touch_down_at(event.slot(), pos, event.time_msec());
         }
     }
 
@@ -147,48 +147,132 @@ impl Compositor {
         &mut self,
         event: I::TouchMotionEvent,
     ) {
-        let output = self.space.outputs().next().cloned();
-        let output_geo = output
-            .as_ref()
-            .map(|o| self.space.output_geometry(o).unwrap());
+        if let Some(pos) = self.absolute_position(&event) {
+            self.touch_motion_at(event.slot(), pos, event.time_msec());
+        }
+    }
+
+    fn on_touch_up<I: smithay::backend::input::InputBackend>(
+        &mut self,
+        event: I::TouchUpEvent,
+    ) {
+        self.touch_up(event.slot(), event.time_msec());
+    }
 
-        if let Some(geo) = output_geo {
-            let pos = event.position_transformed(geo.size);
+    /// Map a backend touch or absolute pointer position onto the first output, if there
+    /// is one.
+    fn absolute_position<I: smithay::backend::input::InputBackend>(
+        &self,
+        event: &impl AbsolutePositionEvent<I>,
+    ) -> Option<Point<f64, Logical>> {
+        let output = self.space.outputs().next()?;
+        let geo = self.space.output_geometry(output)?;
+        // The touchscreen is mounted with the panel, so its coordinates turn with the
+        // panel and with the picture on it
+        let transform = self.input_transform();
+        let panel = transform.invert().transform_size(geo.size);
+        Some(transform.transform_point_in(event.position_transformed(panel), &panel.to_f64()))
+    }
 
-            let under = self.space.element_under(pos);
-            let focus = under.and_then(|(window, loc)| {
+    /// The surface at `pos` that may take input, if any: a panel drawn over the apps, the
+    /// app window, or else a panel under them.
+    pub fn surface_under(&self, pos: Point<f64, Logical>) -> Option<(WlSurface, Point<f64, Logical>)> {
+        if self.is_locked() {
+            return self.lock_surface_under(pos);
+        }
+        self.layer_surface_under(pos, &ABOVE_WINDOWS)
+            .or_else(|| {
+                let (window, loc) = self.space.element_under(pos)?;
                 window
                     .surface_under(pos - loc.to_f64(), smithay::desktop::WindowSurfaceType::ALL)
                     .map(|(s, p)| (s, p.to_f64() + loc.to_f64()))
-            });
+            })
+            .or_else(|| self.layer_surface_under(pos, &BELOW_WINDOWS))
+            .filter(|(s, _)| self.accepts_input(s))
+    }
 
-            let touch_handle = self.seat.get_touch().unwrap();
-            touch_handle.motion(
-                self,
-                focus,
-                &touch::MotionEvent {
-                    slot: event.slot(),
-                    location: pos,
-                    time: event.time_msec(),
-                },
-            );
+    /// Start a touch point at `pos` on the screen, in logical coordinates.
+    pub fn touch_down_at(&mut self, slot: TouchSlot, pos: Point<f64, Logical>, time: u32) {
+        self.cursor.visible = false;
+        if self.magnifier_touch_down(slot, pos, time) {
+            return;
         }
+        let pos = self.unmagnify(pos);
+        // The lock screen gets every touch, gestures over the session included
+        if !self.is_locked()
+            && (self.pinned_touch_down(slot, pos)
+                || self.bubble_touch_down(slot, pos)
+                || self.split_touch_down(slot, pos))
+        {
+            return;
+        }
+        let serial = SERIAL_COUNTER.next_serial();
+        let focus = self.surface_under(pos);
+
+        let touch_handle = self.seat.get_touch().unwrap();
+        touch_handle.down(
+            self,
+            focus,
+            &touch::DownEvent {
+                slot,
+                location: pos,
+                serial,
+                time,
+            },
+        );
     }
 
-    fn on_touch_up<I: smithay::backend::input::InputBackend>(
-        &mut self,
-        event: I::TouchUpEvent,
-    ) {
+    pub fn touch_motion_at(&mut self, slot: TouchSlot, pos: Point<f64, Logical>, time: u32) {
+        if self.magnifier_touch_motion(slot, pos) {
+            return;
+        }
+        let pos = self.unmagnify(pos);
+        if !self.is_locked()
+            && (self.pinned_touch_motion(slot, pos)
+                || self.bubble_touch_motion(slot, pos)
+                || self.split_touch_motion(slot, pos))
+        {
+            return;
+        }
+        let focus = self.surface_under(pos);
+
+        let touch_handle = self.seat.get_touch().unwrap();
+        touch_handle.motion(
+            self,
+            focus,
+            &touch::MotionEvent {
+                slot,
+                location: pos,
+                time,
+            },
+        );
+    }
+
+    pub fn touch_up(&mut self, slot: TouchSlot, time: u32) {
+        if self.magnifier_touch_up(slot)
+            || (!self.is_locked()
+                && (self.pinned_touch_up(slot)
+                    || self.bubble_touch_up(slot)
+                    || self.split_touch_up(slot)))
+        {
+            return;
+        }
         let serial = SERIAL_COUNTER.next_serial();
         let touch_handle = self.seat.get_touch().unwrap();
 
         touch_handle.up(
             self,
             &touch::UpEvent {
-                slot: event.slot(),
+                slot,
                 serial,
-                time: event.time_msec(),
+                time,
             },
         );
     }
+
+    /// End a group of touch events that belong together.
+    pub fn touch_frame(&mut self) {
+        let touch = self.seat.get_touch().unwrap();
+        touch.frame(self);
+    }
 }
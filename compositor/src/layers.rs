@@ -0,0 +1,243 @@
+// ABOUTME: wlr-layer-shell panels, such as the status bar and on-screen keyboard, placed by their anchors and margins.
+// ABOUTME: Their exclusive zones take space from apps; panels drawn above apps get input first, and the keyboard if they ask.
+
+use smithay::backend::renderer::element::Kind;
+use smithay::backend::renderer::element::surface::{
+    WaylandSurfaceRenderElement, render_elements_from_surface_tree,
+};
+use smithay::backend::renderer::{ImportAll, Renderer};
+use smithay::desktop::{LayerSurface, WindowSurfaceType, layer_map_for_output};
+use smithay::output::Output;
+use smithay::reexports::wayland_server::protocol::wl_output::WlOutput;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::utils::{Logical, Point, SERIAL_COUNTER, Scale};
+use smithay::wayland::compositor::with_states;
+use smithay::wayland::shell::wlr_layer::{
+    KeyboardInteractivity, Layer, LayerSurface as WlrLayerSurface, LayerSurfaceData,
+};
+use tracing::{info, warn};
+
+use crate::state::Compositor;
+
+/// The layers drawn over app windows, topmost first.
+pub const ABOVE_WINDOWS: [Layer; 2] = [Layer::Overlay, Layer::Top];
+
+/// The layers drawn under app windows, topmost first.
+pub const BELOW_WINDOWS: [Layer; 2] = [Layer::Bottom, Layer::Background];
+
+/// Whether a panel on `layer` that asks for the keyboard with `interactivity` takes it
+/// from apps for as long as it is mapped. Panels under the apps never do.
+pub fn takes_keyboard(layer: Layer, interactivity: KeyboardInteractivity) -> bool {
+    interactivity == KeyboardInteractivity::Exclusive && ABOVE_WINDOWS.contains(&layer)
+}
+
+/// What to draw of the panels on `layers` of `output`, whose top left corner is at
+/// `location`, front to back.
+pub fn render_elements<R>(
+    renderer: &mut R,
+    output: &Output,
+    location: Point<i32, Logical>,
+    layers: &[Layer],
+) -> Vec<WaylandSurfaceRenderElement<R>>
+where
+    R: Renderer + ImportAll,
+    R::TextureId: Clone + 'static,
+{
+    let scale = output.current_scale().fractional_scale();
+    let map = layer_map_for_output(output);
+    let mut elements = Vec::new();
+    for layer in layers {
+        for surface in map.layers_on(*layer).rev() {
+            let Some(geometry) = map.layer_geometry(surface) else {
+                continue;
+            };
+            elements.extend(render_elements_from_surface_tree(
+                renderer,
+                surface.wl_surface(),
+                (location + geometry.loc).to_physical_precise_round(scale),
+                Scale::from(scale),
+                1.0,
+                Kind::Unspecified,
+            ));
+        }
+    }
+    elements
+}
+
+impl Compositor {
+    /// Put a new panel on the output its client asked for, or the first one. It is
+    /// configured once its client commits, as the protocol has it, and arranged with the
+    /// other panels there.
+    pub fn layer_opened(
+        &mut self,
+        surface: WlrLayerSurface,
+        output: Option<WlOutput>,
+        namespace: String,
+    ) {
+        let output = output
+            .as_ref()
+            .and_then(Output::from_resource)
+            .or_else(|| self.space.outputs().next().cloned());
+        let Some(output) = output else {
+            // Nowhere to show it; a configure keeps its client from waiting for one
+            warn!(namespace, "layer surface without an output");
+            surface.send_configure();
+            return;
+        };
+        info!(namespace, output = output.name(), "new layer surface");
+        let mut map = layer_map_for_output(&output);
+        if let Err(e) = map.map_layer(&LayerSurface::new(surface, namespace)) {
+            warn!(error = %e, "failed to map layer surface");
+        }
+    }
+
+    /// Arrange the panels on the output of `surface`, if it is a panel, now that it may
+    /// have changed its anchors, margins, size or exclusive zone. If apps get a different
+    /// area for it, they are fitted to it again.
+    pub fn layer_committed(&mut self, surface: &WlSurface) {
+        let Some(output) = self.layer_output(surface) else {
+            return;
+        };
+        let initial_configure_sent = with_states(surface, |states| {
+            states
+                .data_map
+                .get::<LayerSurfaceData>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .initial_configure_sent
+        });
+        let (zone_changed, focus) = {
+            let mut map = layer_map_for_output(&output);
+            let zone = map.non_exclusive_zone();
+            map.arrange();
+            let Some(layer) = map.layer_for_surface(surface, WindowSurfaceType::TOPLEVEL) else {
+                return;
+            };
+            if !initial_configure_sent {
+                layer.layer_surface().send_configure();
+            }
+            let focus = takes_keyboard(layer.layer(), layer.cached_state().keyboard_interactivity);
+            (map.non_exclusive_zone() != zone, focus)
+        };
+        if zone_changed && self.space.outputs().next() == Some(&output) {
+            self.arrange_windows();
+        }
+
+        let keyboard = self.seat.get_keyboard().unwrap();
+        let focused = keyboard.current_focus().as_ref() == Some(surface);
+        if focus && !focused && self.accepts_input(surface) {
+            keyboard.set_focus(self, Some(surface.clone()), SERIAL_COUNTER.next_serial());
+        } else if !focus && focused {
+            self.focus_top_window();
+        }
+    }
+
+    /// Take a closed panel off its output, giving its space back to apps, and its
+    /// keyboard focus back to the app on top.
+    pub fn layer_closed(&mut self, surface: &WlrLayerSurface) {
+        for output in self.space.outputs() {
+            let mut map = layer_map_for_output(output);
+            let layer = map
+                .layers()
+                .find(|layer| layer.layer_surface() == surface)
+                .cloned();
+            if let Some(layer) = layer {
+                map.unmap_layer(&layer);
+            }
+        }
+        self.arrange_windows();
+
+        let keyboard = self.seat.get_keyboard().unwrap();
+        if keyboard.current_focus().as_ref() == Some(surface.wl_surface()) {
+            self.focus_top_window();
+        }
+    }
+
+    /// The output whose layer map has the panel `surface`.
+    fn layer_output(&self, surface: &WlSurface) -> Option<Output> {
+        self.space
+            .outputs()
+            .find(|output| {
+                layer_map_for_output(output)
+                    .layer_for_surface(surface, WindowSurfaceType::TOPLEVEL)
+                    .is_some()
+            })
+            .cloned()
+    }
+
+    /// Give the keyboard back to the app on top, unless the session is locked.
+    fn focus_top_window(&mut self) {
+        if self.is_locked() {
+            return;
+        }
+        let focus = self
+            .top_window()
+            .and_then(|w| w.toplevel().map(|t| t.wl_surface().clone()));
+        let keyboard = self.seat.get_keyboard().unwrap();
+        keyboard.set_focus(self, focus, SERIAL_COUNTER.next_serial());
+    }
+
+    /// Send frame callbacks to the panels on `output`.
+    pub fn send_layer_frames(&self, output: &Output) {
+        for layer in layer_map_for_output(output).layers() {
+            layer.send_frame(
+                output,
+                self.start_time.elapsed(),
+                Some(std::time::Duration::ZERO),
+                |_, _| Some(output.clone()),
+            );
+        }
+    }
+
+    /// The panel surface on `layers` at `pos`, and where its origin is, topmost first.
+    pub fn layer_surface_under(
+        &self,
+        pos: Point<f64, Logical>,
+        layers: &[Layer],
+    ) -> Option<(WlSurface, Point<f64, Logical>)> {
+        let output = self.space.output_under(pos).next()?;
+        let origin = self.space.output_geometry(output)?.loc;
+        let map = layer_map_for_output(output);
+        let pos = pos - origin.to_f64();
+        layers.iter().find_map(|layer| {
+            let surface = map.layer_under(*layer, pos)?;
+            let loc = map.layer_geometry(surface)?.loc;
+            surface
+                .surface_under(pos - loc.to_f64(), WindowSurfaceType::ALL)
+                .map(|(s, p)| (s, (p + loc + origin).to_f64()))
+        })
+    }
+
+    /// The panel at `pos` that takes the keyboard when touched or pointed at, if any.
+    pub fn layer_focus_under(&self, pos: Point<f64, Logical>) -> Option<WlSurface> {
+        let output = self.space.output_under(pos).next()?;
+        let origin = self.space.output_geometry(output)?.loc;
+        let map = layer_map_for_output(output);
+        ABOVE_WINDOWS.iter().find_map(|layer| {
+            map.layer_under(*layer, pos - origin.to_f64())
+                .filter(|surface| surface.can_receive_keyboard_focus())
+                .map(|surface| surface.wl_surface().clone())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_panels_over_apps_hold_the_keyboard() {
+        assert!(takes_keyboard(Layer::Top, KeyboardInteractivity::Exclusive));
+        assert!(takes_keyboard(
+            Layer::Overlay,
+            KeyboardInteractivity::Exclusive
+        ));
+        assert!(!takes_keyboard(Layer::Top, KeyboardInteractivity::OnDemand));
+        assert!(!takes_keyboard(Layer::Top, KeyboardInteractivity::None));
+        assert!(!takes_keyboard(
+            Layer::Bottom,
+            KeyboardInteractivity::Exclusive
+        ));
+    }
+}
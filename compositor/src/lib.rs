@@ -0,0 +1,31 @@
+// ABOUTME: Library half of the MobileOS compositor, shared by the binary and the benchmarks.
+// ABOUTME: Exposes the compositor state and backends; protocol handlers and input routing hang off the state.
+
+pub mod animation;
+pub mod assistant;
+pub mod bubble;
+pub mod color_filter;
+pub mod cursor;
+pub mod dbus;
+pub mod display_power;
+mod handlers;
+pub mod headless;
+pub mod idle;
+mod input;
+pub mod layers;
+pub mod magnifier;
+pub mod notify;
+pub mod pinning;
+pub mod pocket;
+pub mod render;
+pub mod rotation;
+pub mod screen_time;
+pub mod screencopy;
+pub mod session_lock;
+pub mod split;
+pub mod state;
+pub mod switch_access;
+pub mod toplevels;
+pub mod udev;
+pub mod windows;
+pub mod winit;
@@ -0,0 +1,329 @@
+// ABOUTME: Screen magnifier: zooms the composited output around a point, for users with low vision.
+// ABOUTME: Triple-tap or both volume keys toggle it, two fingers pan; backends apply it while rendering.
+
+use smithay::backend::input::TouchSlot;
+use smithay::backend::renderer::element::utils::{
+    Relocate, RelocateRenderElement, RescaleRenderElement,
+};
+use smithay::input::keyboard::Keysym;
+use smithay::utils::{Logical, Point, Rectangle};
+use tracing::info;
+
+use crate::state::Compositor;
+
+/// How much the magnifier enlarges.
+pub const ZOOM: f64 = 2.0;
+
+/// Taps this close together in time, in milliseconds, count towards a triple-tap.
+const TAP_INTERVAL: u32 = 300;
+
+/// And this close together on the screen.
+const TAP_SLOP: f64 = 40.0;
+
+const TAPS: usize = 3;
+
+/// Holding both volume keys is the accessibility shortcut.
+const SHORTCUT_KEYS: [Keysym; 2] = [Keysym::XF86_AudioRaiseVolume, Keysym::XF86_AudioLowerVolume];
+
+/// A render element moved and scaled to where the magnifier shows it.
+pub type Magnified<E> = RelocateRenderElement<RescaleRenderElement<E>>;
+
+#[derive(Default)]
+pub struct Magnifier {
+    /// Top-left of the magnified part of the output, in global coordinates, while on.
+    view: Option<Point<f64, Logical>>,
+    /// Time and place of the taps so far towards a triple-tap.
+    taps: Vec<(u32, Point<f64, Logical>)>,
+    /// The touch that toggled the magnifier, kept from clients until it lifts.
+    toggling: Option<TouchSlot>,
+    /// Fingers on the screen while magnified, and where they are.
+    fingers: Vec<(TouchSlot, Point<f64, Logical>)>,
+    /// Two fingers are panning; clients see none of their touches.
+    panning: bool,
+    /// Which of the shortcut keys are held.
+    held: [bool; 2],
+}
+
+/// Keep a view of `area` zoomed by `zoom` from running off its edges.
+pub fn clamp_view(
+    area: Rectangle<i32, Logical>,
+    zoom: f64,
+    view: Point<f64, Logical>,
+) -> Point<f64, Logical> {
+    let area = area.to_f64();
+    let max_x = area.loc.x + area.size.w - area.size.w / zoom;
+    let max_y = area.loc.y + area.size.h - area.size.h / zoom;
+    (
+        view.x.clamp(area.loc.x, max_x),
+        view.y.clamp(area.loc.y, max_y),
+    )
+        .into()
+}
+
+/// The view that zooms `area` around `focus`, so what is under it stays there.
+pub fn view_at(
+    area: Rectangle<i32, Logical>,
+    zoom: f64,
+    focus: Point<f64, Logical>,
+) -> Point<f64, Logical> {
+    let from_corner = focus - area.loc.to_f64();
+    clamp_view(area, zoom, focus - from_corner.downscale(zoom))
+}
+
+/// The global position shown at `pos` on the screen showing `area` through `view`.
+pub fn to_global(
+    area: Rectangle<i32, Logical>,
+    zoom: f64,
+    view: Point<f64, Logical>,
+    pos: Point<f64, Logical>,
+) -> Point<f64, Logical> {
+    view + (pos - area.loc.to_f64()).downscale(zoom)
+}
+
+impl Magnifier {
+    pub fn is_on(&self) -> bool {
+        self.view.is_some()
+    }
+
+    /// Move and scale an output's render elements for the magnified view. `area` is the
+    /// output's place in the space and `scale` its scale; with the magnifier off, the
+    /// elements stay as they are.
+    pub fn transform<E>(
+        &self,
+        elements: Vec<E>,
+        area: Rectangle<i32, Logical>,
+        scale: f64,
+    ) -> Vec<Magnified<E>> {
+        let (zoom, offset) = match self.view {
+            Some(view) => (ZOOM, (view - area.loc.to_f64()).upscale(ZOOM)),
+            None => (1.0, Point::default()),
+        };
+        let offset = offset.to_physical(scale).to_i32_round();
+        elements
+            .into_iter()
+            .map(|element| {
+                RelocateRenderElement::from_element(
+                    RescaleRenderElement::from_element(element, (0, 0).into(), zoom),
+                    (-offset.x, -offset.y),
+                    Relocate::Relative,
+                )
+            })
+            .collect()
+    }
+}
+
+impl Compositor {
+    pub fn magnified(&self) -> bool {
+        self.magnifier.is_on()
+    }
+
+    /// Zoom in around `focus`, or back out.
+    pub fn set_magnified(&mut self, on: bool, focus: Point<f64, Logical>) {
+        if on == self.magnified() {
+            return;
+        }
+        let Some(area) = self.magnifier_area() else {
+            return;
+        };
+        info!(on, "screen magnifier");
+        self.magnifier.view = on.then(|| view_at(area, ZOOM, focus));
+    }
+
+    /// Toggle the magnifier around the middle of the screen, as for the accessibility
+    /// shortcut.
+    pub fn toggle_magnifier(&mut self) {
+        let Some(area) = self.magnifier_area() else {
+            return;
+        };
+        let middle = area.to_f64().loc + area.size.to_f64().downscale(2.0).to_point();
+        self.set_magnified(!self.magnified(), middle);
+    }
+
+    /// The global position shown at `pos` on the screen.
+    pub fn unmagnify(&self, pos: Point<f64, Logical>) -> Point<f64, Logical> {
+        match (self.magnifier.view, self.magnifier_area()) {
+            (Some(view), Some(area)) => to_global(area, ZOOM, view, pos),
+            _ => pos,
+        }
+    }
+
+    /// Follow the shortcut keys. Returns true when the key must not reach clients.
+    pub fn magnifier_key(&mut self, keysym: Keysym, pressed: bool) -> bool {
+        let Some(i) = SHORTCUT_KEYS.iter().position(|k| *k == keysym) else {
+            return false;
+        };
+        let was_held = self.magnifier.held == [true; 2];
+        self.magnifier.held[i] = pressed;
+        if pressed && !was_held && self.magnifier.held == [true; 2] {
+            self.toggle_magnifier();
+            return true;
+        }
+        false
+    }
+
+    /// Count triple-taps, and start a pan when a second finger lands while magnified.
+    /// `pos` is on the screen. Returns true when the touch must not reach clients.
+    pub fn magnifier_touch_down(
+        &mut self,
+        slot: TouchSlot,
+        pos: Point<f64, Logical>,
+        time: u32,
+    ) -> bool {
+        if self.magnified() {
+            self.magnifier.fingers.push((slot, pos));
+            if self.magnifier.fingers.len() == 2 && !self.magnifier.panning {
+                // The first finger's touch already reached the app; it must forget it
+                self.magnifier.panning = true;
+                if let Some(touch) = self.seat.get_touch() {
+                    touch.cancel(self);
+                }
+            }
+            if self.magnifier.panning {
+                self.magnifier.taps.clear();
+                return true;
+            }
+        }
+
+        let taps = &mut self.magnifier.taps;
+        let follows = taps
+            .last()
+            .is_some_and(|(last, _)| time.wrapping_sub(*last) <= TAP_INTERVAL)
+            && taps.first().is_some_and(|(_, first)| {
+                let d = pos - *first;
+                d.x.hypot(d.y) <= TAP_SLOP
+            });
+        if !follows {
+            taps.clear();
+        }
+        taps.push((time, pos));
+        if taps.len() < TAPS {
+            return false;
+        }
+
+        taps.clear();
+        self.magnifier.toggling = Some(slot);
+        self.magnifier.fingers.retain(|(s, _)| *s != slot);
+        self.set_magnified(!self.magnified(), pos);
+        true
+    }
+
+    /// Pan with two fingers: the view follows the point between them.
+    pub fn magnifier_touch_motion(&mut self, slot: TouchSlot, pos: Point<f64, Logical>) -> bool {
+        if self.magnifier.toggling == Some(slot) {
+            return true;
+        }
+        let before = midpoint(&self.magnifier.fingers);
+        let Some(finger) = self.magnifier.fingers.iter_mut().find(|(s, _)| *s == slot) else {
+            return false;
+        };
+        finger.1 = pos;
+        if !self.magnifier.panning {
+            return false;
+        }
+
+        let moved = midpoint(&self.magnifier.fingers) - before;
+        if let (Some(view), Some(area)) = (self.magnifier.view, self.magnifier_area()) {
+            self.magnifier.view = Some(clamp_view(area, ZOOM, view - moved.downscale(ZOOM)));
+        }
+        true
+    }
+
+    pub fn magnifier_touch_up(&mut self, slot: TouchSlot) -> bool {
+        if self.magnifier.toggling == Some(slot) {
+            self.magnifier.toggling = None;
+            return true;
+        }
+        self.magnifier.fingers.retain(|(s, _)| *s != slot);
+        if !self.magnifier.panning {
+            return false;
+        }
+        // The pan lasts until the last finger lifts, so the other doesn't turn into a touch
+        if self.magnifier.fingers.is_empty() {
+            self.magnifier.panning = false;
+        }
+        true
+    }
+
+    fn magnifier_area(&self) -> Option<Rectangle<i32, Logical>> {
+        let output = self.space.outputs().next()?;
+        self.space.output_geometry(output)
+    }
+}
+
+/// The point between the first two fingers.
+fn midpoint(fingers: &[(TouchSlot, Point<f64, Logical>)]) -> Point<f64, Logical> {
+    match fingers {
+        [(_, a), (_, b), ..] => (*a + *b).downscale(2.0),
+        [(_, a)] => *a,
+        [] => Point::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+
+    use super::*;
+    use crate::headless::init_headless;
+
+    fn area() -> Rectangle<i32, Logical> {
+        Rectangle::new((0, 0).into(), (720, 1440).into())
+    }
+
+    #[test]
+    fn zooming_keeps_the_focus_in_place() {
+        let focus = (200.0, 400.0).into();
+        let view = view_at(area(), ZOOM, focus);
+        assert_eq!(view, (100.0, 200.0).into());
+        assert_eq!(to_global(area(), ZOOM, view, focus), focus);
+    }
+
+    #[test]
+    fn view_stays_on_the_output() {
+        assert_eq!(view_at(area(), ZOOM, (0.0, 0.0).into()), (0.0, 0.0).into());
+        assert_eq!(
+            clamp_view(area(), ZOOM, (500.0, 1000.0).into()),
+            (360.0, 720.0).into()
+        );
+        assert_eq!(
+            clamp_view(area(), ZOOM, (-20.0, 300.0).into()),
+            (0.0, 300.0).into()
+        );
+    }
+
+    #[test]
+    fn triple_tap_toggles_and_two_fingers_pan() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+        init_headless(&mut event_loop, &mut state).unwrap();
+
+        let slot = TouchSlot::from(None);
+        let pos = (360.0, 720.0).into();
+        for time in [0, 150] {
+            assert!(!state.magnifier_touch_down(slot, pos, time));
+            assert!(!state.magnifier_touch_up(slot));
+        }
+        // Too slow: starts over
+        assert!(!state.magnifier_touch_down(slot, pos, 1000));
+        assert!(!state.magnifier_touch_up(slot));
+        assert!(!state.magnified());
+
+        assert!(!state.magnifier_touch_down(slot, pos, 1200));
+        assert!(!state.magnifier_touch_up(slot));
+        assert!(state.magnifier_touch_down(slot, pos, 1400));
+        assert!(state.magnifier_touch_up(slot));
+        assert!(state.magnified());
+        assert_eq!(state.unmagnify(pos), pos);
+
+        let (a, b) = (TouchSlot::from(Some(0)), TouchSlot::from(Some(1)));
+        assert!(!state.magnifier_touch_down(a, (300.0, 700.0).into(), 5000));
+        assert!(state.magnifier_touch_down(b, (420.0, 700.0).into(), 5010));
+        assert!(state.magnifier_touch_motion(a, (280.0, 600.0).into()));
+        assert!(state.magnifier_touch_motion(b, (400.0, 600.0).into()));
+        assert!(state.magnifier_touch_up(a));
+        assert!(state.magnifier_touch_up(b));
+        assert_eq!(state.unmagnify(pos), (370.0, 770.0).into());
+    }
+}
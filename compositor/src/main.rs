@@ -1,25 +1,22 @@
 // ABOUTME: Wayland compositor for MobileOS, built on smithay.
 // ABOUTME: Handles display output, window management, and touch input.
 
-mod handlers;
-mod input;
-mod state;
-mod udev;
-mod winit;
-
+use mos_compositor::state::Compositor;
+use mos_compositor::{dbus, headless, idle, notify, pocket, rotation, switch_access, udev, winit};
 use smithay::reexports::calloop::EventLoop;
 use smithay::reexports::wayland_server::Display;
-use tracing::info;
-
-use crate::state::Compositor;
+use tracing::{info, warn};
 
 enum Backend {
     Winit,
     Udev,
+    Headless,
 }
 
 fn select_backend() -> Backend {
-    if std::env::var("WAYLAND_DISPLAY").is_ok() || std::env::var("DISPLAY").is_ok() {
+    if std::env::var("MOS_BACKEND").is_ok_and(|b| b == "headless") {
+        Backend::Headless
+    } else if std::env::var("WAYLAND_DISPLAY").is_ok() || std::env::var("DISPLAY").is_ok() {
         Backend::Winit
     } else {
         Backend::Udev
@@ -40,6 +37,22 @@ fn main() -> anyhow::Result<()> {
 
     info!(socket = ?state.socket_name, "wayland socket ready");
 
+    if let Err(e) = pocket::watch_sensors(&event_loop.handle()) {
+        warn!(error = %e, "pocket detection unavailable");
+    }
+    if let Err(e) = rotation::watch_orientation(&event_loop.handle()) {
+        warn!(error = %e, "auto-rotate unavailable");
+    }
+    if let Err(e) = switch_access::start_scanning(&event_loop.handle()) {
+        warn!(error = %e, "single-switch scanning unavailable");
+    }
+    if let Err(e) = idle::watch_idle(&event_loop.handle()) {
+        warn!(error = %e, "idle thresholds unavailable, the screen won't time out");
+    }
+    if let Err(e) = dbus::serve(&event_loop.handle(), &mut state) {
+        warn!(error = %e, "app pinning and split view unavailable");
+    }
+
     match select_backend() {
         Backend::Winit => {
             info!("using winit backend (desktop development)");
@@ -49,10 +62,19 @@ fn main() -> anyhow::Result<()> {
             info!("using udev/DRM backend (hardware)");
             udev::init_udev(&mut event_loop, &mut state)?;
         }
+        Backend::Headless => {
+            info!("using headless backend (protocol replay and testing)");
+            headless::init_headless(&mut event_loop, &mut state)?;
+        }
     }
 
     // SAFETY: called before spawning any threads, single-threaded at this point
     unsafe { std::env::set_var("WAYLAND_DISPLAY", &state.socket_name) };
+    // Tell initd the Wayland socket is up, so the shell and apps can start
+    mos_notify::ready();
+    if let Err(e) = notify::feed_watchdog(&event_loop.handle()) {
+        warn!(error = %e, "initd's watchdog can't be fed");
+    }
 
     info!("entering event loop");
     event_loop.run(None, &mut state, |_| {})?;
@@ -0,0 +1,21 @@
+// ABOUTME: Keeps initd's watchdog fed from the compositor's event loop.
+// ABOUTME: A wedged event loop, as after a GPU hang, stops the pings and initd resets the phone.
+
+use smithay::reexports::calloop::LoopHandle;
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+
+use crate::state::Compositor;
+
+/// Ping initd's watchdog twice per timeout, from the event loop itself.
+pub fn feed_watchdog(handle: &LoopHandle<'_, Compositor>) -> anyhow::Result<()> {
+    let Some(interval) = mos_notify::watchdog_interval() else {
+        return Ok(());
+    };
+    handle
+        .insert_source(Timer::from_duration(interval), move |_, _, _| {
+            mos_notify::watchdog();
+            TimeoutAction::ToDuration(interval)
+        })
+        .map_err(|e| anyhow::anyhow!("failed to insert watchdog timer: {e}"))?;
+    Ok(())
+}
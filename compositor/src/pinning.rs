@@ -0,0 +1,238 @@
+// ABOUTME: Screen pinning: one app keeps the screen and all input until the PIN is entered.
+// ABOUTME: The shell's task switcher pins and unpins apps over org.mobileos.Compositor.
+
+use smithay::backend::input::TouchSlot;
+use smithay::desktop::Window;
+use smithay::input::keyboard::Keysym;
+use smithay::reexports::wayland_server::Resource;
+use smithay::reexports::wayland_server::backend::ClientId;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::utils::{IsAlive, Logical, Point, SERIAL_COUNTER};
+use tracing::{info, warn};
+
+use crate::dbus::{self, window_app_id};
+use crate::state::Compositor;
+
+/// The shell's process name; its window takes the PIN while an app is pinned.
+const SHELL_COMMAND: &str = "mos-shell";
+
+/// Swipes starting this close to the bottom of the screen ask to unpin.
+const EDGE_SIZE: f64 = 24.0;
+
+/// How far up the swipe has to go.
+const SWIPE_DISTANCE: f64 = 80.0;
+
+/// Keys that would take the user out of the pinned app, such as into the power menu.
+const SYSTEM_KEYS: &[Keysym] = &[Keysym::XF86_PowerOff, Keysym::XF86_HomePage];
+
+pub struct Pinning {
+    window: Window,
+    app_id: String,
+    /// The user swiped up to unpin, so the shell is up asking for the PIN.
+    unlocking: bool,
+    /// A swipe up from the bottom edge in progress: its touch slot and where it started.
+    swipe: Option<(TouchSlot, f64)>,
+}
+
+impl Compositor {
+    pub fn pinned_app_id(&self) -> Option<String> {
+        self.pinned.as_ref().map(|p| p.app_id.clone())
+    }
+
+    /// Pin the topmost window of `app_id`. False if it has none.
+    pub fn pin(&mut self, app_id: &str) -> bool {
+        let Some(window) = self
+            .space
+            .elements()
+            .rev()
+            .find(|w| window_app_id(w).as_deref() == Some(app_id))
+            .cloned()
+        else {
+            return false;
+        };
+        info!(app_id, "app pinned");
+        self.unsplit();
+        self.pinned = Some(Pinning {
+            window: window.clone(),
+            app_id: app_id.to_string(),
+            unlocking: false,
+            swipe: None,
+        });
+        self.bring_up(&window);
+        true
+    }
+
+    pub fn unpin(&mut self) {
+        if let Some(pinning) = self.pinned.take() {
+            info!(app_id = %pinning.app_id, "app unpinned");
+            self.restack_bubbles();
+        }
+    }
+
+    pub fn cancel_unpin(&mut self) {
+        let Some(pinning) = self.pinned.as_mut() else {
+            return;
+        };
+        pinning.unlocking = false;
+        let window = pinning.window.clone();
+        self.bring_up(&window);
+    }
+
+    /// Raise `window` and give it the keyboard, cancelling touches that went elsewhere.
+    pub fn bring_up(&mut self, window: &Window) {
+        self.space.raise_element(window, true);
+        if let Some(touch) = self.seat.get_touch() {
+            touch.cancel(self);
+        }
+        let focus = window.toplevel().map(|t| t.wl_surface().clone());
+        let keyboard = self.seat.get_keyboard().unwrap();
+        keyboard.set_focus(self, focus, SERIAL_COUNTER.next_serial());
+        self.restack_bubbles();
+    }
+
+    /// Whether input may go to `surface`. While the session is locked, only the lock
+    /// client's surfaces get any. While an app is pinned, only its own surfaces do, or
+    /// the shell's while it asks for the PIN.
+    pub fn accepts_input(&self, surface: &WlSurface) -> bool {
+        if self.is_locked() {
+            return self.is_lock_surface(surface);
+        }
+        let Some(pinning) = self.pinning() else {
+            return true;
+        };
+        let allowed = if pinning.unlocking {
+            self.shell_window()
+        } else {
+            Some(pinning.window.clone())
+        };
+        let Some(allowed) = allowed.and_then(|w| w.toplevel().map(|t| t.wl_surface().clone()))
+        else {
+            return false;
+        };
+        let client = |surface: &WlSurface| -> Option<ClientId> {
+            Some(self.display_handle.get_client(surface.id()).ok()?.id())
+        };
+        client(&allowed).is_some_and(|allowed| client(surface) == Some(allowed))
+    }
+
+    /// Whether `keysym` is kept from clients: system keys are, while an app is pinned.
+    pub fn intercepts_key(&self, keysym: Keysym) -> bool {
+        self.pinning().is_some() && SYSTEM_KEYS.contains(&keysym)
+    }
+
+    /// The pinned app, unless its window has gone, as when it crashed: then nothing is.
+    fn pinning(&self) -> Option<&Pinning> {
+        self.pinned.as_ref().filter(|p| p.window.alive())
+    }
+
+    pub fn shell_window(&self) -> Option<Window> {
+        self.space
+            .elements()
+            .find(|w| {
+                let Some(toplevel) = w.toplevel() else {
+                    return false;
+                };
+                let Ok(client) = self.display_handle.get_client(toplevel.wl_surface().id()) else {
+                    return false;
+                };
+                client
+                    .get_credentials(&self.display_handle)
+                    .ok()
+                    .and_then(|c| std::fs::read_to_string(format!("/proc/{}/comm", c.pid)).ok())
+                    .is_some_and(|comm| comm.trim() == SHELL_COMMAND)
+            })
+            .cloned()
+    }
+
+    /// Watch for the swipe up from the bottom edge that asks to unpin. Returns true when the
+    /// touch belongs to that gesture, and must not reach clients.
+    pub fn pinned_touch_down(&mut self, slot: TouchSlot, pos: Point<f64, Logical>) -> bool {
+        let bottom = self
+            .space
+            .outputs()
+            .next()
+            .and_then(|o| self.space.output_geometry(o))
+            .map(|geo| f64::from(geo.loc.y + geo.size.h));
+        let Some(pinning) = self.pinned.as_mut().filter(|p| !p.unlocking) else {
+            return false;
+        };
+        if bottom.is_some_and(|bottom| pos.y >= bottom - EDGE_SIZE) {
+            pinning.swipe = Some((slot, pos.y));
+            return true;
+        }
+        false
+    }
+
+    pub fn pinned_touch_motion(&mut self, slot: TouchSlot, pos: Point<f64, Logical>) -> bool {
+        let Some(pinning) = self.pinned.as_mut() else {
+            return false;
+        };
+        let Some((swipe_slot, start)) = pinning.swipe else {
+            return false;
+        };
+        if swipe_slot != slot {
+            return false;
+        }
+        if start - pos.y >= SWIPE_DISTANCE {
+            pinning.swipe = None;
+            self.request_unpin();
+        }
+        true
+    }
+
+    pub fn pinned_touch_up(&mut self, slot: TouchSlot) -> bool {
+        match self.pinned.as_mut() {
+            Some(pinning) if pinning.swipe.is_some_and(|(s, _)| s == slot) => {
+                pinning.swipe = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Raise the shell and signal UnpinRequested, so it asks for the PIN. Emitted straight on
+    /// the connection, as the event loop doesn't run the bus's executor.
+    fn request_unpin(&mut self) {
+        let Some(shell) = self.shell_window() else {
+            warn!("no shell window to ask for the PIN, staying pinned");
+            return;
+        };
+        if let Some(pinning) = self.pinned.as_mut() {
+            pinning.unlocking = true;
+        }
+        self.bring_up(&shell);
+        info!("unpin requested");
+        if let Some(bus) = &self.bus
+            && let Err(e) = bus.emit_signal(
+                None::<zbus::names::BusName<'_>>,
+                dbus::OBJECT_PATH,
+                dbus::INTERFACE,
+                "UnpinRequested",
+                &(),
+            )
+        {
+            warn!(error = %e, "failed to ask the shell for the PIN");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+
+    use super::*;
+
+    #[test]
+    fn nothing_is_pinned_without_a_window() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+
+        assert!(!state.pin("org.mobileos.Dialer"));
+        assert!(state.pinned.is_none());
+        assert!(state.app_ids().is_empty());
+        assert!(!state.intercepts_key(Keysym::XF86_PowerOff));
+        assert!(!state.pinned_touch_down(TouchSlot::from(None), (0.0, 10_000.0).into()));
+    }
+}
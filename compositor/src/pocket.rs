@@ -0,0 +1,90 @@
+// ABOUTME: Follows the sensors service's pocket state over D-Bus.
+// ABOUTME: While the phone is in a pocket, touches are dropped so nothing gets pressed by accident.
+
+use smithay::reexports::calloop::LoopHandle;
+use smithay::reexports::calloop::channel::{self, Event};
+use tracing::{info, warn};
+
+use crate::state::Compositor;
+
+#[zbus::proxy(
+    interface = "org.mobileos.Sensors",
+    default_service = "org.mobileos.Sensors",
+    default_path = "/org/mobileos/Sensors"
+)]
+trait Sensors {
+    #[zbus(property)]
+    fn in_pocket(&self) -> zbus::Result<bool>;
+}
+
+/// Watch the pocket state on a thread of its own and hand changes to the event loop.
+/// Without the sensors service the phone is never in a pocket.
+pub fn watch_sensors(handle: &LoopHandle<'_, Compositor>) -> anyhow::Result<()> {
+    let (sender, receiver) = channel::channel::<bool>();
+    handle
+        .insert_source(receiver, |event, _, state| {
+            if let Event::Msg(in_pocket) = event {
+                state.set_in_pocket(in_pocket);
+            }
+        })
+        .map_err(|e| anyhow::anyhow!("failed to insert pocket state source: {e}"))?;
+
+    std::thread::Builder::new()
+        .name("pocket".into())
+        .spawn(move || {
+            if let Err(e) = follow(&sender) {
+                warn!(error = %e, "lost the sensors service, pocket detection off");
+            }
+        })?;
+    Ok(())
+}
+
+fn follow(sender: &channel::Sender<bool>) -> zbus::Result<()> {
+    let conn = zbus::blocking::Connection::system()?;
+    let sensors = SensorsProxyBlocking::new(&conn)?;
+    // The service may not be up yet; its changes arrive once it is
+    let _ = sender.send(sensors.in_pocket().unwrap_or(false));
+    for change in sensors.receive_in_pocket_changed() {
+        let in_pocket = change.get()?;
+        if sender.send(in_pocket).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl Compositor {
+    /// Enter or leave pocket mode. Touches already down are cancelled on entering, so
+    /// clients don't act on the half of a gesture that started before.
+    pub fn set_in_pocket(&mut self, in_pocket: bool) {
+        if self.in_pocket == in_pocket {
+            return;
+        }
+        info!(in_pocket, "pocket state changed");
+        self.in_pocket = in_pocket;
+        if in_pocket && let Some(touch) = self.seat.get_touch() {
+            touch.cancel(self);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+
+    use crate::state::Compositor;
+
+    #[test]
+    fn pocket_mode_follows_the_sensors() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+        assert!(!state.in_pocket);
+
+        state.set_in_pocket(true);
+        assert!(state.in_pocket);
+        state.set_in_pocket(false);
+        assert!(!state.in_pocket);
+    }
+}
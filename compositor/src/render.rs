@@ -0,0 +1,18 @@
+// ABOUTME: Render elements the backends draw for an output: windows, layer-shell panels, overlays and the cursor.
+// ABOUTME: The overlays are drawn in global coordinates, so the magnifier zooms them with everything else.
+
+use smithay::backend::renderer::element::memory::MemoryRenderBufferRenderElement;
+use smithay::backend::renderer::element::render_elements;
+use smithay::backend::renderer::element::solid::SolidColorRenderElement;
+use smithay::backend::renderer::element::surface::WaylandSurfaceRenderElement;
+use smithay::backend::renderer::{ImportAll, ImportMem};
+
+use crate::animation::AnimatedElement;
+
+render_elements! {
+    pub OutputRenderElements<R> where R: ImportAll + ImportMem;
+    Window=AnimatedElement<R>,
+    Highlight=SolidColorRenderElement,
+    Surface=WaylandSurfaceRenderElement<R>,
+    Cursor=MemoryRenderBufferRenderElement<R>,
+}
@@ -0,0 +1,233 @@
+// ABOUTME: Turns the picture with the phone: to a rotation set over D-Bus, or as the accelerometer says.
+// ABOUTME: Auto-rotate follows the sensors service's orientation until the user locks rotation.
+
+use smithay::reexports::calloop::LoopHandle;
+use smithay::reexports::calloop::channel::{self, Event};
+use smithay::utils::Transform;
+use tracing::{info, warn};
+
+use crate::state::Compositor;
+
+#[zbus::proxy(
+    interface = "org.mobileos.Sensors",
+    default_service = "org.mobileos.Sensors",
+    default_path = "/org/mobileos/Sensors"
+)]
+trait Sensors {
+    #[zbus(property)]
+    fn orientation(&self) -> zbus::Result<String>;
+}
+
+#[derive(Debug, Default)]
+pub struct Rotation {
+    /// How far the phone is turned clockwise from upright, in degrees: 0, 90, 180 or 270.
+    /// The picture turns back as far, on top of how the panel is mounted.
+    degrees: u32,
+    /// The user locked rotation, so the accelerometer is ignored.
+    locked: bool,
+    /// How far the accelerometer last said the phone is turned, for when rotation is
+    /// unlocked.
+    sensed: u32,
+}
+
+/// `transform` turned a further `degrees`, a multiple of 90. Flipped transforms stay
+/// flipped.
+pub fn rotate(transform: Transform, degrees: u32) -> Transform {
+    const TURNS: [Transform; 4] = [
+        Transform::Normal,
+        Transform::_90,
+        Transform::_180,
+        Transform::_270,
+    ];
+    const FLIPPED: [Transform; 4] = [
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+    let (turns, at) = match TURNS.iter().position(|t| *t == transform) {
+        Some(at) => (TURNS, at),
+        None => (
+            FLIPPED,
+            FLIPPED.iter().position(|t| *t == transform).unwrap_or(0),
+        ),
+    };
+    turns[(at + degrees as usize / 90) % 4]
+}
+
+/// How far the phone is turned clockwise, for an orientation as the sensors service
+/// names it.
+fn orientation_degrees(orientation: &str) -> Option<u32> {
+    match orientation {
+        "normal" => Some(0),
+        "left-up" => Some(90),
+        "bottom-up" => Some(180),
+        "right-up" => Some(270),
+        _ => None,
+    }
+}
+
+/// Follow the orientation on a thread of its own and hand changes to the event loop.
+/// Without the sensors service the screen stays as it is set.
+pub fn watch_orientation(handle: &LoopHandle<'_, Compositor>) -> anyhow::Result<()> {
+    let (sender, receiver) = channel::channel::<u32>();
+    handle
+        .insert_source(receiver, |event, _, state| {
+            if let Event::Msg(degrees) = event {
+                state.orientation_sensed(degrees);
+            }
+        })
+        .map_err(|e| anyhow::anyhow!("failed to insert orientation source: {e}"))?;
+
+    std::thread::Builder::new()
+        .name("orientation".into())
+        .spawn(move || {
+            if let Err(e) = follow(&sender) {
+                warn!(error = %e, "lost the sensors service, auto-rotate off");
+            }
+        })?;
+    Ok(())
+}
+
+fn follow(sender: &channel::Sender<u32>) -> zbus::Result<()> {
+    let conn = zbus::blocking::Connection::system()?;
+    let sensors = SensorsProxyBlocking::new(&conn)?;
+    // The service may not be up yet; its changes arrive once it is
+    if let Some(degrees) = sensors
+        .orientation()
+        .ok()
+        .as_deref()
+        .and_then(orientation_degrees)
+    {
+        let _ = sender.send(degrees);
+    }
+    for change in sensors.receive_orientation_changed() {
+        let Some(degrees) = orientation_degrees(&change.get()?) else {
+            continue;
+        };
+        if sender.send(degrees).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl Compositor {
+    pub fn rotation(&self) -> u32 {
+        self.rotation.degrees
+    }
+
+    pub fn rotation_locked(&self) -> bool {
+        self.rotation.locked
+    }
+
+    /// Turn the picture for the phone turned `degrees` clockwise, rounded down to a
+    /// quarter turn. Unless rotation is locked, the accelerometer turns it again as
+    /// soon as the phone is.
+    pub fn set_rotation(&mut self, degrees: u32) {
+        let degrees = degrees % 360 / 90 * 90;
+        let turn = (degrees + 360 - self.rotation.degrees) % 360;
+        if turn == 0 {
+            return;
+        }
+        info!(degrees, "rotating the screen");
+        self.rotation.degrees = degrees;
+        if let Some(output) = self.space.outputs().next().cloned() {
+            let transform = rotate(output.current_transform(), turn);
+            output.change_current_state(None, Some(transform), None, None);
+        }
+        // Whatever was laid out for the output's old shape is laid out again
+        self.arrange_windows();
+        self.arrange_split();
+        self.fit_lock_surfaces();
+    }
+
+    /// Lock the rotation where it is, or unlock it to turn with the phone again.
+    pub fn set_rotation_locked(&mut self, locked: bool) {
+        info!(locked, "rotation lock changed");
+        self.rotation.locked = locked;
+        if !locked {
+            self.set_rotation(self.rotation.sensed);
+        }
+    }
+
+    /// The accelerometer says the phone is turned `degrees` clockwise.
+    pub fn orientation_sensed(&mut self, degrees: u32) {
+        self.rotation.sensed = degrees;
+        if !self.rotation.locked {
+            self.set_rotation(degrees);
+        }
+    }
+
+    /// How absolute positions from the touchscreen, which is mounted with the panel,
+    /// turn onto the output.
+    pub fn input_transform(&self) -> Transform {
+        let panel = self
+            .drm
+            .as_ref()
+            .map_or(Transform::Normal, |drm| drm.panel_transform);
+        rotate(panel, self.rotation.degrees)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::output::{Mode, Output, PhysicalProperties, Subpixel};
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+
+    use super::*;
+
+    #[test]
+    fn rotations_add_up() {
+        assert_eq!(rotate(Transform::Normal, 90), Transform::_90);
+        assert_eq!(rotate(Transform::_270, 180), Transform::_90);
+        assert_eq!(rotate(Transform::_90, 0), Transform::_90);
+        assert_eq!(rotate(Transform::Flipped180, 270), Transform::Flipped90);
+    }
+
+    #[test]
+    fn orientations_name_quarter_turns() {
+        assert_eq!(orientation_degrees("normal"), Some(0));
+        assert_eq!(orientation_degrees("left-up"), Some(90));
+        assert_eq!(orientation_degrees("right-up"), Some(270));
+        assert_eq!(orientation_degrees("undefined"), None);
+    }
+
+    #[test]
+    fn the_output_turns_with_the_phone_until_locked() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+        let output = Output::new(
+            "test".to_string(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "MobileOS".into(),
+                model: "Test".into(),
+            },
+        );
+        let mode = Mode {
+            size: (720, 1440).into(),
+            refresh: 60_000,
+        };
+        output.change_current_state(Some(mode), Some(Transform::Normal), None, None);
+        state.space.map_output(&output, (0, 0));
+        let size = |state: &Compositor| state.space.output_geometry(&output).unwrap().size;
+
+        state.orientation_sensed(90);
+        assert_eq!(output.current_transform(), Transform::_90);
+        assert_eq!(size(&state), (1440, 720).into());
+        assert_eq!(state.input_transform(), Transform::_90);
+
+        state.set_rotation_locked(true);
+        state.orientation_sensed(180);
+        assert_eq!(state.rotation(), 90);
+
+        state.set_rotation_locked(false);
+        assert_eq!(state.rotation(), 180);
+        assert_eq!(output.current_transform(), Transform::_180);
+        assert_eq!(size(&state), (720, 1440).into());
+    }
+}
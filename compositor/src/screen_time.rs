@@ -0,0 +1,75 @@
+// ABOUTME: Tells the wellbeing service which app is on screen and when the phone is unlocked, for screen time.
+// ABOUTME: Signals ForegroundChanged and Unlocked on org.mobileos.Compositor as they happen.
+
+use tracing::warn;
+
+use crate::dbus;
+use crate::state::Compositor;
+
+impl Compositor {
+    /// The app the user is spending time in: the foreground app, while the screen is on
+    /// and unlocked. Empty when there is none.
+    pub fn app_on_screen(&self) -> String {
+        if !self.display_on() || self.is_locked() {
+            return String::new();
+        }
+        self.foreground_app_id().unwrap_or_default()
+    }
+
+    /// Signal ForegroundChanged if the app on screen is not the one last signalled. Called
+    /// whenever focus, the lock or the display may have changed it. Signals are emitted
+    /// straight on the connection, as the event loop doesn't run the bus's executor.
+    pub fn update_app_on_screen(&mut self) {
+        let app_id = self.app_on_screen();
+        if app_id == self.on_screen {
+            return;
+        }
+        if let Some(bus) = &self.bus
+            && let Err(e) = bus.emit_signal(
+                None::<zbus::names::BusName<'_>>,
+                dbus::OBJECT_PATH,
+                dbus::INTERFACE,
+                "ForegroundChanged",
+                &(app_id.as_str(),),
+            )
+        {
+            warn!(error = %e, "failed to signal the app on screen");
+        }
+        self.on_screen = app_id;
+    }
+
+    /// Signal Unlocked, so unlocks can be counted.
+    pub fn signal_unlocked(&self) {
+        if let Some(bus) = &self.bus
+            && let Err(e) = bus.emit_signal(
+                None::<zbus::names::BusName<'_>>,
+                dbus::OBJECT_PATH,
+                dbus::INTERFACE,
+                "Unlocked",
+                &(),
+            )
+        {
+            warn!(error = %e, "failed to signal the unlock");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+
+    use crate::state::Compositor;
+
+    #[test]
+    fn nothing_is_on_screen_without_apps() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+
+        assert_eq!(state.app_on_screen(), "");
+        state.set_display_on(false);
+        state.update_app_on_screen();
+        assert_eq!(state.on_screen, "");
+    }
+}
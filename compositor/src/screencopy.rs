@@ -0,0 +1,418 @@
+// ABOUTME: wlr-screencopy-unstable-v1: clients copy what an output shows into an shm buffer of their own.
+// ABOUTME: Copies are made after the output's next frame, by drawing the same elements again off screen.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use smithay::backend::allocator::Fourcc;
+use smithay::backend::renderer::damage::OutputDamageTracker;
+use smithay::backend::renderer::element::RenderElement;
+use smithay::backend::renderer::gles::{GlesRenderer, GlesTexture};
+use smithay::backend::renderer::{Bind, ExportMem, Offscreen};
+use smithay::output::Output;
+use smithay::reexports::wayland_protocols_wlr::screencopy::v1::server::zwlr_screencopy_frame_v1::{
+    self, Flags, ZwlrScreencopyFrameV1,
+};
+use smithay::reexports::wayland_protocols_wlr::screencopy::v1::server::zwlr_screencopy_manager_v1::{
+    self, ZwlrScreencopyManagerV1,
+};
+use smithay::reexports::wayland_server::protocol::wl_buffer::WlBuffer;
+use smithay::reexports::wayland_server::protocol::wl_shm;
+use smithay::reexports::wayland_server::{
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+};
+use smithay::utils::{Clock, Logical, Monotonic, Physical, Rectangle, Size, Transform};
+use smithay::wayland::shm::{with_buffer_contents, with_buffer_contents_mut};
+use tracing::warn;
+
+use crate::state::Compositor;
+
+const VERSION: u32 = 3;
+
+/// The only format copies are made in, which every client can read.
+const FORMAT: wl_shm::Format = wl_shm::Format::Xrgb8888;
+
+/// Decides which clients see the global, since a copy shows everything on screen,
+/// the lock screen included.
+pub struct ScreencopyGlobalData {
+    filter: Box<dyn for<'c> Fn(&'c Client) -> bool + Send + Sync>,
+}
+
+/// Copies clients asked for, waiting for their output's next frame.
+pub struct Screencopy {
+    pending: Vec<PendingCopy>,
+    clock: Clock<Monotonic>,
+}
+
+struct PendingCopy {
+    frame: ZwlrScreencopyFrameV1,
+    buffer: WlBuffer,
+    output: Output,
+    region: Rectangle<i32, Physical>,
+    /// Asked for with copy_with_damage, so damage is sent before ready.
+    with_damage: bool,
+}
+
+/// What a frame copies: part or all of an output.
+pub struct FrameData {
+    output: Output,
+    region: Rectangle<i32, Physical>,
+    /// A frame is copied once.
+    used: AtomicBool,
+}
+
+impl Screencopy {
+    /// Serve the global to the clients `filter` accepts.
+    pub fn new<F>(display_handle: &DisplayHandle, filter: F) -> Self
+    where
+        F: for<'c> Fn(&'c Client) -> bool + Send + Sync + 'static,
+    {
+        let data = ScreencopyGlobalData {
+            filter: Box::new(filter),
+        };
+        display_handle.create_global::<Compositor, ZwlrScreencopyManagerV1, _>(VERSION, data);
+        Self {
+            pending: Vec::new(),
+            clock: Clock::new(),
+        }
+    }
+
+    fn take_pending(&mut self, output: &Output) -> Vec<PendingCopy> {
+        let (copies, rest) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .filter(|copy| copy.frame.is_alive() && copy.buffer.is_alive())
+            .partition(|copy| &copy.output == output);
+        self.pending = rest;
+        copies
+    }
+
+    /// Make the copies waiting on `output`, now `elements` have been drawn on it.
+    pub fn copy_output<E>(&mut self, renderer: &mut GlesRenderer, output: &Output, elements: &[E])
+    where
+        E: RenderElement<GlesRenderer>,
+    {
+        let copies = self.take_pending(output);
+        if copies.is_empty() {
+            return;
+        }
+        let Some(size) = output_size(output) else {
+            copies.iter().for_each(|copy| copy.frame.failed());
+            return;
+        };
+        let scale = output.current_scale().fractional_scale();
+        let pixels = match draw_offscreen(renderer, size, scale, elements) {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                warn!(error = %e, "failed to copy the screen");
+                copies.iter().for_each(|copy| copy.frame.failed());
+                return;
+            }
+        };
+        let time = Duration::from(self.clock.now());
+        for copy in copies {
+            copy.finish(&pixels, size, time);
+        }
+    }
+
+    /// Fail the copies waiting on `output`, which is never drawn.
+    pub fn fail_output(&mut self, output: &Output) {
+        for copy in self.take_pending(output) {
+            copy.frame.failed();
+        }
+    }
+}
+
+impl PendingCopy {
+    /// Fill the client's buffer with its region of `pixels`, the whole output of `size`
+    /// in rows of RGBA pixels, and tell it the copy is ready.
+    fn finish(self, pixels: &[u8], size: Size<i32, Physical>, time: Duration) {
+        let region = self.region;
+        // The output may have changed mode or scale since the frame was asked for
+        if !region_fits(region, size) || pixels.len() < (size.w * size.h * 4) as usize {
+            self.frame.failed();
+            return;
+        }
+        let width = size.w;
+        let filled = with_buffer_contents_mut(&self.buffer, |ptr, len, data| {
+            // SAFETY: the shm pool is mapped for `len` bytes for as long as this runs
+            let contents = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+            for row in 0..region.size.h {
+                let src = (((region.loc.y + row) * width + region.loc.x) * 4) as usize;
+                let dst = (data.offset + row * data.stride) as usize;
+                let src = &pixels[src..src + region.size.w as usize * 4];
+                let dst = &mut contents[dst..dst + region.size.w as usize * 4];
+                // wl_shm's XRGB8888 is little-endian, so blue comes first
+                for (to, from) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+                    to.copy_from_slice(&[from[2], from[1], from[0], 0xff]);
+                }
+            }
+        });
+        if let Err(e) = filled {
+            warn!(error = ?e, "screencopy buffer went away");
+            self.frame.failed();
+            return;
+        }
+
+        self.frame.flags(Flags::empty());
+        if self.with_damage {
+            self.frame
+                .damage(0, 0, region.size.w as u32, region.size.h as u32);
+        }
+        let secs = time.as_secs();
+        self.frame
+            .ready((secs >> 32) as u32, secs as u32, time.subsec_nanos());
+    }
+}
+
+/// The size of what `output` shows, upright.
+fn output_size(output: &Output) -> Option<Size<i32, Physical>> {
+    let mode = output.current_mode()?;
+    Some(output.current_transform().transform_size(mode.size))
+}
+
+/// Whether `region` still lies within an output of `size`.
+fn region_fits(region: Rectangle<i32, Physical>, size: Size<i32, Physical>) -> bool {
+    Rectangle::from_size(size).contains_rect(region)
+}
+
+/// Draw `elements` into a texture the size of the output and read it back, as rows of
+/// RGBA pixels from the top.
+fn draw_offscreen<E>(
+    renderer: &mut GlesRenderer,
+    size: Size<i32, Physical>,
+    scale: f64,
+    elements: &[E],
+) -> anyhow::Result<Vec<u8>>
+where
+    E: RenderElement<GlesRenderer>,
+{
+    let buffer_size = size.to_logical(1).to_buffer(1, Transform::Normal);
+    let mut texture: GlesTexture = renderer
+        .create_buffer(Fourcc::Abgr8888, buffer_size)
+        .map_err(|e| anyhow::anyhow!("failed to create a texture: {e}"))?;
+    let mut framebuffer = renderer
+        .bind(&mut texture)
+        .map_err(|e| anyhow::anyhow!("failed to bind the texture: {e}"))?;
+    let mut damage_tracker = OutputDamageTracker::new(size, scale, Transform::Normal);
+    damage_tracker
+        .render_output(
+            renderer,
+            &mut framebuffer,
+            0,
+            elements,
+            [0.0, 0.0, 0.0, 1.0],
+        )
+        .map_err(|e| anyhow::anyhow!("failed to draw the output: {e:?}"))?;
+    let mapping = renderer
+        .copy_framebuffer(
+            &framebuffer,
+            Rectangle::from_size(buffer_size),
+            Fourcc::Abgr8888,
+        )
+        .map_err(|e| anyhow::anyhow!("failed to read the output back: {e}"))?;
+    let pixels = renderer
+        .map_texture(&mapping)
+        .map_err(|e| anyhow::anyhow!("failed to map the output: {e}"))?;
+    Ok(pixels.to_vec())
+}
+
+/// The part of `output` a client asked for, in its logical coordinates, as physical
+/// pixels of the upright output. None if it covers none of it.
+fn physical_region(
+    output: &Output,
+    region: Option<Rectangle<i32, Logical>>,
+) -> Option<Rectangle<i32, Physical>> {
+    let size = output_size(output)?;
+    let whole = Rectangle::from_size(size);
+    let Some(region) = region else {
+        return Some(whole);
+    };
+    let scale = output.current_scale().fractional_scale();
+    let region = region.to_f64().to_physical(scale).to_i32_round();
+    region
+        .intersection(whole)
+        .filter(|r| r.size.w > 0 && r.size.h > 0)
+}
+
+impl GlobalDispatch<ZwlrScreencopyManagerV1, ScreencopyGlobalData> for Compositor {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrScreencopyManagerV1>,
+        _global_data: &ScreencopyGlobalData,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &ScreencopyGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl Dispatch<ZwlrScreencopyManagerV1, ()> for Compositor {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _manager: &ZwlrScreencopyManagerV1,
+        request: zwlr_screencopy_manager_v1::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let (frame, output, region) = match request {
+            zwlr_screencopy_manager_v1::Request::CaptureOutput { frame, output, .. } => {
+                (frame, output, None)
+            }
+            zwlr_screencopy_manager_v1::Request::CaptureOutputRegion {
+                frame,
+                output,
+                x,
+                y,
+                width,
+                height,
+                ..
+            } => (
+                frame,
+                output,
+                Some(Rectangle::new((x, y).into(), (width, height).into())),
+            ),
+            _ => return,
+        };
+
+        let copied = Output::from_resource(&output)
+            .and_then(|output| Some((physical_region(&output, region)?, output)));
+        let Some((region, output)) = copied else {
+            let frame = data_init.init(frame, None);
+            frame.failed();
+            return;
+        };
+        let frame = data_init.init(
+            frame,
+            Some(FrameData {
+                output,
+                region,
+                used: AtomicBool::new(false),
+            }),
+        );
+        let (width, height) = (region.size.w as u32, region.size.h as u32);
+        frame.buffer(FORMAT, width, height, width * 4);
+        if frame.version() >= 3 {
+            frame.buffer_done();
+        }
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, Option<FrameData>> for Compositor {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        frame: &ZwlrScreencopyFrameV1,
+        request: zwlr_screencopy_frame_v1::Request,
+        data: &Option<FrameData>,
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let (buffer, with_damage) = match request {
+            zwlr_screencopy_frame_v1::Request::Copy { buffer } => (buffer, false),
+            zwlr_screencopy_frame_v1::Request::CopyWithDamage { buffer } => (buffer, true),
+            _ => return,
+        };
+        // A frame that failed at capture has nothing to copy
+        let Some(data) = data else {
+            return;
+        };
+        if data.used.swap(true, Ordering::Relaxed) {
+            frame.post_error(
+                zwlr_screencopy_frame_v1::Error::AlreadyUsed,
+                "frame already copied",
+            );
+            return;
+        }
+        let size = data.region.size;
+        let fits = with_buffer_contents(&buffer, |_, _, info| {
+            info.format == FORMAT
+                && info.width == size.w
+                && info.height == size.h
+                && info.stride == size.w * 4
+        });
+        if !fits.unwrap_or(false) {
+            frame.post_error(
+                zwlr_screencopy_frame_v1::Error::InvalidBuffer,
+                "buffer does not match the one asked for",
+            );
+            return;
+        }
+
+        state.screencopy.pending.push(PendingCopy {
+            frame: frame.clone(),
+            buffer,
+            output: data.output.clone(),
+            region: data.region,
+            with_damage,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::output::{Mode, PhysicalProperties, Scale, Subpixel};
+
+    use super::*;
+
+    #[test]
+    fn regions_are_clipped_to_the_upright_output() {
+        // A landscape panel mounted in a portrait phone, at twice the scale
+        let output = Output::new(
+            "test".to_string(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "MobileOS".into(),
+                model: "Test".into(),
+            },
+        );
+        let mode = Mode {
+            size: (1440, 720).into(),
+            refresh: 60_000,
+        };
+        output.change_current_state(
+            Some(mode),
+            Some(Transform::_90),
+            Some(Scale::Integer(2)),
+            None,
+        );
+
+        assert_eq!(
+            physical_region(&output, None),
+            Some(Rectangle::new((0, 0).into(), (720, 1440).into()))
+        );
+        assert_eq!(
+            physical_region(
+                &output,
+                Some(Rectangle::new((10, 20).into(), (100, 1000).into()))
+            ),
+            Some(Rectangle::new((20, 40).into(), (200, 1400).into()))
+        );
+        assert_eq!(
+            physical_region(
+                &output,
+                Some(Rectangle::new((400, 0).into(), (10, 10).into()))
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn regions_from_an_earlier_mode_no_longer_fit() {
+        let region = Rectangle::new((20, 40).into(), (200, 1400).into());
+        assert!(region_fits(region, (720, 1440).into()));
+        // Switched to a smaller mode before the copy was made
+        assert!(!region_fits(region, (360, 720).into()));
+        assert!(!region_fits(
+            Rectangle::new((-1, 0).into(), (10, 10).into()),
+            (720, 1440).into()
+        ));
+    }
+}
@@ -0,0 +1,242 @@
+// ABOUTME: ext-session-lock-v1: a lock client covers every output, and nothing else is drawn or gets input.
+// ABOUTME: The session stays locked until that client unlocks it, even if it crashes meanwhile.
+
+use smithay::backend::renderer::element::Kind;
+use smithay::backend::renderer::element::surface::{
+    WaylandSurfaceRenderElement, render_elements_from_surface_tree,
+};
+use smithay::backend::renderer::{ImportAll, Renderer};
+use smithay::desktop::WindowSurfaceType;
+use smithay::desktop::utils::{send_frames_surface_tree, under_from_surface_tree};
+use smithay::output::Output;
+use smithay::reexports::wayland_server::protocol::wl_output::WlOutput;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::utils::{IsAlive, Logical, Point, SERIAL_COUNTER, Scale};
+use smithay::wayland::compositor::get_parent;
+use smithay::wayland::session_lock::{
+    LockSurface, SessionLockHandler, SessionLockManagerState, SessionLocker,
+};
+use tracing::{info, warn};
+
+use crate::state::Compositor;
+
+#[derive(Default)]
+pub struct SessionLock {
+    /// The lock asked for, to be confirmed once a frame without the session is out.
+    pending: Option<SessionLocker>,
+    locked: bool,
+    /// The lock client's surfaces, one per output.
+    surfaces: Vec<(LockSurface, Output)>,
+}
+
+impl SessionLock {
+    /// Whether the session is hidden: from the moment a lock is asked for, since a
+    /// confirmed lock has to have been drawn first.
+    pub fn is_locked(&self) -> bool {
+        self.locked || self.pending.is_some()
+    }
+
+    /// The lock surface on `output`, if the lock client has made one.
+    fn surface_on(&self, output: &Output) -> Option<&LockSurface> {
+        self.surfaces
+            .iter()
+            .find(|(surface, o)| o == output && surface.wl_surface().alive())
+            .map(|(surface, _)| surface)
+    }
+
+    /// What to draw on `output`, whose top left corner is at `location`, while locked:
+    /// its lock surface, or nothing until the lock client has made one.
+    pub fn render_elements<R>(
+        &self,
+        renderer: &mut R,
+        output: &Output,
+        location: Point<i32, Logical>,
+    ) -> Vec<WaylandSurfaceRenderElement<R>>
+    where
+        R: Renderer + ImportAll,
+        R::TextureId: Clone + 'static,
+    {
+        let Some(surface) = self.surface_on(output) else {
+            return Vec::new();
+        };
+        let scale = output.current_scale().fractional_scale();
+        render_elements_from_surface_tree(
+            renderer,
+            surface.wl_surface(),
+            location.to_physical_precise_round(scale),
+            Scale::from(scale),
+            1.0,
+            Kind::Unspecified,
+        )
+    }
+}
+
+impl Compositor {
+    pub fn is_locked(&self) -> bool {
+        self.session_lock.is_locked()
+    }
+
+    /// Confirm a pending lock to its client, once a frame has gone out with only lock
+    /// surfaces or nothing drawn.
+    pub fn confirm_lock(&mut self) {
+        if let Some(locker) = self.session_lock.pending.take() {
+            locker.lock();
+            self.session_lock.locked = true;
+            info!("session locked");
+        }
+    }
+
+    /// Send frame callbacks to the lock surface on `output`.
+    pub fn send_lock_frames(&self, output: &Output) {
+        if let Some(surface) = self.session_lock.surface_on(output) {
+            send_frames_surface_tree(
+                surface.wl_surface(),
+                output,
+                self.start_time.elapsed(),
+                Some(std::time::Duration::ZERO),
+                |_, _| Some(output.clone()),
+            );
+        }
+    }
+
+    /// The lock surface at `pos` and where its origin is, while locked. Everything else
+    /// is out of reach.
+    pub fn lock_surface_under(
+        &self,
+        pos: Point<f64, Logical>,
+    ) -> Option<(WlSurface, Point<f64, Logical>)> {
+        let (surface, output) = self
+            .session_lock
+            .surfaces
+            .iter()
+            .find(|(surface, output)| {
+                surface.wl_surface().alive()
+                    && self
+                        .space
+                        .output_geometry(output)
+                        .is_some_and(|geo| geo.to_f64().contains(pos))
+            })?;
+        let origin = self.space.output_geometry(output)?.loc;
+        under_from_surface_tree(surface.wl_surface(), pos, origin, WindowSurfaceType::ALL)
+            .map(|(s, p)| (s, p.to_f64()))
+    }
+
+    /// Whether `surface` is a lock surface or one of its subsurfaces, which alone take
+    /// input while locked.
+    pub fn is_lock_surface(&self, surface: &WlSurface) -> bool {
+        let mut root = surface.clone();
+        while let Some(parent) = get_parent(&root) {
+            root = parent;
+        }
+        self.session_lock
+            .surfaces
+            .iter()
+            .any(|(lock, _)| lock.wl_surface() == &root)
+    }
+
+    /// Keep the keyboard on a lock surface while locked, whatever else asked for it.
+    pub fn keep_lock_focus(&mut self) {
+        if !self.is_locked() {
+            return;
+        }
+        let keyboard = self.seat.get_keyboard().unwrap();
+        let focused = keyboard.current_focus();
+        if focused.as_ref().is_some_and(|s| self.is_lock_surface(s)) {
+            return;
+        }
+        let focus = self
+            .session_lock
+            .surfaces
+            .iter()
+            .map(|(surface, _)| surface.wl_surface())
+            .find(|surface| surface.alive())
+            .cloned();
+        keyboard.set_focus(self, focus, SERIAL_COUNTER.next_serial());
+    }
+}
+
+impl SessionLockHandler for Compositor {
+    fn lock_state(&mut self) -> &mut SessionLockManagerState {
+        &mut self.session_lock_state
+    }
+
+    /// Hide the session at once, and confirm the lock after the next frame. A lock
+    /// client that crashed leaves the session locked, and a new one may take over.
+    fn lock(&mut self, confirmation: SessionLocker) {
+        info!(relock = self.session_lock.locked, "session lock requested");
+        self.session_lock
+            .surfaces
+            .retain(|(surface, _)| surface.wl_surface().alive());
+        self.session_lock.pending = Some(confirmation);
+        if let Some(touch) = self.seat.get_touch() {
+            touch.cancel(self);
+        }
+        let keyboard = self.seat.get_keyboard().unwrap();
+        keyboard.set_focus(self, None, SERIAL_COUNTER.next_serial());
+    }
+
+    /// Only the lock client can unlock, when it is done authenticating the user.
+    fn unlock(&mut self) {
+        info!("session unlocked");
+        self.session_lock = SessionLock::default();
+        let focus = self
+            .space
+            .elements()
+            .last()
+            .and_then(|w| w.toplevel().map(|t| t.wl_surface().clone()));
+        let keyboard = self.seat.get_keyboard().unwrap();
+        keyboard.set_focus(self, focus, SERIAL_COUNTER.next_serial());
+        self.signal_unlocked();
+        self.update_app_on_screen();
+    }
+
+    /// Size the lock surfaces to cover their outputs again, as after the screen turns.
+    pub fn fit_lock_surfaces(&self) {
+        for (surface, output) in &self.session_lock.surfaces {
+            if let Some(geo) = self.space.output_geometry(output) {
+                surface.with_pending_state(|state| {
+                    state.size = Some((geo.size.w as u32, geo.size.h as u32).into());
+                });
+                surface.send_configure();
+            }
+        }
+    }
+
+    /// Size the lock surface to cover its output, and give it the keyboard.
+    fn new_surface(&mut self, surface: LockSurface, output: WlOutput) {
+        let Some(output) = Output::from_resource(&output) else {
+            warn!("lock surface for an unknown output");
+            return;
+        };
+        if let Some(geo) = self.space.output_geometry(&output) {
+            surface.with_pending_state(|state| {
+                state.size = Some((geo.size.w as u32, geo.size.h as u32).into());
+            });
+        }
+        surface.send_configure();
+        self.session_lock.surfaces.push((surface, output));
+        self.keep_lock_focus();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+
+    use super::*;
+
+    #[test]
+    fn session_starts_unlocked() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+
+        assert!(!state.is_locked());
+        assert!(state.lock_surface_under((10.0, 10.0).into()).is_none());
+        // Nothing to confirm, and the keyboard is left alone
+        state.confirm_lock();
+        state.keep_lock_focus();
+        assert!(!state.is_locked());
+    }
+}
@@ -0,0 +1,288 @@
+// ABOUTME: Split view: two apps side by side on outputs wide enough for it, as when docked.
+// ABOUTME: Dragging the handle between them shares out the width; the task switcher enters and leaves it.
+
+use smithay::backend::input::TouchSlot;
+use smithay::desktop::Window;
+use smithay::reexports::wayland_protocols::xdg::shell::server::xdg_toplevel;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::utils::{IsAlive, Logical, Point, Rectangle};
+use tracing::info;
+
+use crate::dbus::window_app_id;
+use crate::state::Compositor;
+
+/// Outputs narrower than this, in logical pixels, show one app at a time. A phone held
+/// upright is narrower; a tablet, or the monitor a phone is docked to, isn't.
+pub const MIN_SPLIT_WIDTH: i32 = 900;
+
+/// Width of the handle between the two apps.
+pub const HANDLE_WIDTH: i32 = 16;
+
+/// Touches this far to either side of the handle still grab it, as it is thin for a finger.
+const GRAB_MARGIN: f64 = 16.0;
+
+/// The least share of the width either app keeps.
+const MIN_SHARE: f64 = 0.25;
+
+const TILED: [xdg_toplevel::State; 4] = [
+    xdg_toplevel::State::TiledLeft,
+    xdg_toplevel::State::TiledRight,
+    xdg_toplevel::State::TiledTop,
+    xdg_toplevel::State::TiledBottom,
+];
+
+pub struct SplitView {
+    left: Window,
+    right: Window,
+    /// The left app's share of the width.
+    ratio: f64,
+    /// The touch dragging the handle, if one is.
+    drag: Option<TouchSlot>,
+}
+
+/// Where the left app, the handle and the right app go in `area`, with the left app taking
+/// `ratio` of the width.
+pub fn layout(area: Rectangle<i32, Logical>, ratio: f64) -> [Rectangle<i32, Logical>; 3] {
+    let ratio = ratio.clamp(MIN_SHARE, 1.0 - MIN_SHARE);
+    let left_width = (f64::from(area.size.w - HANDLE_WIDTH) * ratio).round() as i32;
+    let right_width = area.size.w - HANDLE_WIDTH - left_width;
+    let handle_x = area.loc.x + left_width;
+    [
+        Rectangle::new(area.loc, (left_width, area.size.h).into()),
+        Rectangle::new(
+            (handle_x, area.loc.y).into(),
+            (HANDLE_WIDTH, area.size.h).into(),
+        ),
+        Rectangle::new(
+            (handle_x + HANDLE_WIDTH, area.loc.y).into(),
+            (right_width, area.size.h).into(),
+        ),
+    ]
+}
+
+impl Compositor {
+    /// Whether two apps fit side by side. Never while one is pinned.
+    pub fn can_split(&self) -> bool {
+        self.pinned.is_none()
+            && self
+                .split_area()
+                .is_some_and(|a| a.size.w >= MIN_SPLIT_WIDTH)
+    }
+
+    /// The apps in split view, left then right.
+    pub fn split_app_ids(&self) -> Vec<String> {
+        self.split_view()
+            .map(|split| {
+                [&split.left, &split.right]
+                    .into_iter()
+                    .filter_map(window_app_id)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether `window` is one of the two in split view.
+    pub fn is_split(&self, window: &Window) -> bool {
+        self.split
+            .as_ref()
+            .is_some_and(|split| split.left == *window || split.right == *window)
+    }
+
+    /// Put the topmost window of `app_id` on the right, beside the app on top. False if
+    /// the output is too narrow, or there aren't two apps to show.
+    pub fn split(&mut self, app_id: &str) -> bool {
+        if !self.can_split() {
+            return false;
+        }
+        let shell = self.shell_window();
+        let Some(right) = self
+            .space
+            .elements()
+            .rev()
+            .find(|w| window_app_id(w).as_deref() == Some(app_id))
+            .cloned()
+        else {
+            return false;
+        };
+        let Some(left) = self
+            .space
+            .elements()
+            .rev()
+            .find(|w| {
+                **w != right
+                    && Some(*w) != shell.as_ref()
+                    && !self.is_bubble(w)
+                    && window_app_id(w).is_some()
+            })
+            .cloned()
+        else {
+            return false;
+        };
+
+        info!(left = ?window_app_id(&left), right = app_id, "entering split view");
+        // Swapping one of the apps keeps the handle where the user left it
+        let ratio = self.split.take().map_or(0.5, |split| split.ratio);
+        self.split = Some(SplitView {
+            left: left.clone(),
+            right: right.clone(),
+            ratio,
+            drag: None,
+        });
+        self.arrange_split();
+        self.space.raise_element(&left, false);
+        self.bring_up(&right);
+        true
+    }
+
+    /// Leave split view, maximizing both apps again.
+    pub fn unsplit(&mut self) {
+        let Some(split) = self.split.take() else {
+            return;
+        };
+        info!("leaving split view");
+        for window in [split.left, split.right].into_iter().filter(|w| w.alive()) {
+            if let Some(toplevel) = window.toplevel() {
+                toplevel.with_pending_state(|state| {
+                    for tiled in TILED {
+                        state.states.unset(tiled);
+                    }
+                });
+            }
+            self.maximize(&window);
+            if let Some(toplevel) = window.toplevel() {
+                toplevel.send_pending_configure();
+            }
+        }
+        self.restack_bubbles();
+    }
+
+    /// Leave split view when one of its apps closes, so the other gets the screen back.
+    pub fn split_window_closed(&mut self, surface: &WlSurface) {
+        let closed = self.split.as_ref().is_some_and(|split| {
+            [&split.left, &split.right]
+                .into_iter()
+                .any(|w| w.toplevel().is_some_and(|t| t.wl_surface() == surface))
+        });
+        if closed {
+            self.unsplit();
+        }
+    }
+
+    /// Size and place both apps for the current ratio, or leave split view if the output
+    /// has become too narrow for it, as when undocked.
+    pub fn arrange_split(&mut self) {
+        let Some(split) = self.split.as_ref() else {
+            return;
+        };
+        let Some(area) = self.split_area().filter(|a| a.size.w >= MIN_SPLIT_WIDTH) else {
+            self.unsplit();
+            return;
+        };
+        let [left, _, right] = layout(area, split.ratio);
+        let sides = [
+            (split.left.clone(), left, xdg_toplevel::State::TiledRight),
+            (split.right.clone(), right, xdg_toplevel::State::TiledLeft),
+        ];
+        for (window, rect, inner_edge) in sides {
+            if let Some(toplevel) = window.toplevel() {
+                toplevel.with_pending_state(|state| {
+                    state.size = Some(rect.size);
+                    state.states.unset(xdg_toplevel::State::Maximized);
+                    state.states.set(inner_edge);
+                    state.states.set(xdg_toplevel::State::TiledTop);
+                    state.states.set(xdg_toplevel::State::TiledBottom);
+                });
+                toplevel.send_pending_configure();
+            }
+            self.space.map_element(window, rect.loc, false);
+        }
+        self.restack_bubbles();
+    }
+
+    /// Start dragging the handle if the touch lands on it. Returns true when the touch is
+    /// the drag's, and must not reach clients.
+    pub fn split_touch_down(&mut self, slot: TouchSlot, pos: Point<f64, Logical>) -> bool {
+        let Some(area) = self.split_area() else {
+            return false;
+        };
+        let Some(split) = self.split.as_mut().filter(|s| s.drag.is_none()) else {
+            return false;
+        };
+        let [_, handle, _] = layout(area, split.ratio);
+        let center = f64::from(handle.loc.x) + f64::from(HANDLE_WIDTH) / 2.0;
+        if (pos.x - center).abs() <= f64::from(HANDLE_WIDTH) / 2.0 + GRAB_MARGIN {
+            split.drag = Some(slot);
+            return true;
+        }
+        false
+    }
+
+    pub fn split_touch_motion(&mut self, slot: TouchSlot, pos: Point<f64, Logical>) -> bool {
+        let Some(area) = self.split_area() else {
+            return false;
+        };
+        let Some(split) = self.split.as_mut().filter(|s| s.drag == Some(slot)) else {
+            return false;
+        };
+        let offset = pos.x - f64::from(area.loc.x) - f64::from(HANDLE_WIDTH) / 2.0;
+        split.ratio =
+            (offset / f64::from(area.size.w - HANDLE_WIDTH)).clamp(MIN_SHARE, 1.0 - MIN_SHARE);
+        self.arrange_split();
+        true
+    }
+
+    pub fn split_touch_up(&mut self, slot: TouchSlot) -> bool {
+        match self.split.as_mut() {
+            Some(split) if split.drag == Some(slot) => {
+                split.drag = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Split view, unless one of its windows has gone.
+    fn split_view(&self) -> Option<&SplitView> {
+        self.split
+            .as_ref()
+            .filter(|split| split.left.alive() && split.right.alive())
+    }
+
+    fn split_area(&self) -> Option<Rectangle<i32, Logical>> {
+        self.app_area()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+
+    use super::*;
+
+    #[test]
+    fn layout_shares_out_the_width() {
+        let area = Rectangle::new((0, 0).into(), (1016, 600).into());
+        let [left, handle, right] = layout(area, 0.5);
+        assert_eq!(left, Rectangle::new((0, 0).into(), (500, 600).into()));
+        assert_eq!(handle, Rectangle::new((500, 0).into(), (16, 600).into()));
+        assert_eq!(right, Rectangle::new((516, 0).into(), (500, 600).into()));
+
+        // Neither app can be squeezed out
+        let [left, _, right] = layout(area, 0.0);
+        assert_eq!(left.size.w, 250);
+        assert_eq!(right.size.w, 750);
+    }
+
+    #[test]
+    fn split_needs_a_wide_output_and_two_apps() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+
+        assert!(!state.can_split());
+        assert!(!state.split("org.mobileos.Dialer"));
+        assert!(state.split_app_ids().is_empty());
+        assert!(!state.split_touch_down(TouchSlot::from(None), (500.0, 300.0).into()));
+    }
+}
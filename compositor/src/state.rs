@@ -2,25 +2,58 @@
 // ABOUTME: Manages the Display, seat, space, and protocol globals lifecycle.
 
 use std::ffi::OsString;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use smithay::desktop::{PopupManager, Space, Window};
 use smithay::input::{Seat, SeatState};
 use smithay::reexports::calloop::generic::Generic;
-use smithay::reexports::calloop::{EventLoop, Interest, LoopSignal, Mode, PostAction};
+use smithay::reexports::calloop::{EventLoop, Interest, LoopHandle, LoopSignal, Mode, PostAction};
 use smithay::reexports::wayland_server::backend::{ClientData, ClientId, DisconnectReason};
-use smithay::reexports::wayland_server::{Display, DisplayHandle};
+use smithay::reexports::wayland_server::{Client, Display, DisplayHandle};
 use smithay::wayland::compositor::{CompositorClientState, CompositorState};
+use smithay::wayland::foreign_toplevel_list::ForeignToplevelListState;
+use smithay::wayland::idle_notify::IdleNotifierState;
+use smithay::wayland::input_method::InputMethodManagerState;
 use smithay::wayland::output::OutputManagerState;
 use smithay::wayland::selection::data_device::DataDeviceState;
+use smithay::wayland::session_lock::SessionLockManagerState;
 use smithay::wayland::shell::wlr_layer::WlrLayerShellState;
 use smithay::wayland::shell::xdg::XdgShellState;
 use smithay::wayland::shm::ShmState;
 use smithay::wayland::socket::ListeningSocketSource;
+use smithay::wayland::text_input::TextInputManagerState;
+use smithay::wayland::virtual_keyboard::VirtualKeyboardManagerState;
 use tracing::info;
 
+use crate::animation::Animations;
+use crate::assistant::Assistant;
+use crate::bubble::Bubbles;
+use crate::color_filter::ColorFilters;
+use crate::cursor::Cursor;
+use crate::idle::Idle;
+use crate::magnifier::Magnifier;
+use crate::pinning::Pinning;
+use crate::rotation::Rotation;
+use crate::screencopy::Screencopy;
+use crate::session_lock::SessionLock;
+use crate::split::SplitView;
+use crate::switch_access::SwitchAccess;
+use crate::toplevels::ForeignToplevels;
 use crate::udev::DrmState;
 
+/// The shell, the only client that may lock the session, and the only caller that may
+/// pin and unpin apps.
+pub const SHELL_EXE: &str = "/usr/bin/mos-shell";
+
+/// The on-screen keyboard, the only client that may act as the input method or type
+/// through a virtual keyboard.
+const KEYBOARD_EXE: &str = "/usr/bin/mos-keyboard";
+
+/// The screenshot service, the only client that may copy what is on screen.
+const SCREENSHOT_EXE: &str = "/usr/bin/mos-screenshot";
+
 pub struct Compositor {
     pub start_time: std::time::Instant,
     pub socket_name: OsString,
@@ -28,6 +61,8 @@ pub struct Compositor {
 
     pub space: Space<Window>,
     pub loop_signal: LoopSignal,
+    /// For timers started as the compositor runs.
+    pub handle: LoopHandle<'static, Compositor>,
 
     pub compositor_state: CompositorState,
     pub xdg_shell_state: XdgShellState,
@@ -36,16 +71,85 @@ pub struct Compositor {
     pub seat_state: SeatState<Compositor>,
     pub data_device_state: DataDeviceState,
     pub layer_shell_state: WlrLayerShellState,
+    pub session_lock_state: SessionLockManagerState,
+    pub text_input_state: TextInputManagerState,
+    pub input_method_state: InputMethodManagerState,
+    pub virtual_keyboard_state: VirtualKeyboardManagerState,
+    pub idle_notifier_state: IdleNotifierState<Compositor>,
+    pub foreign_toplevel_list_state: ForeignToplevelListState,
     pub popups: PopupManager,
+    /// Copies of the screen clients asked for, made as frames are drawn.
+    pub screencopy: Screencopy,
+    /// The open toplevels, as foreign-toplevel clients know them.
+    pub foreign_toplevels: ForeignToplevels,
 
     pub seat: Seat<Compositor>,
 
     pub drm: Option<DrmState>,
+
+    /// Windows opening, closing or being switched to.
+    pub animations: Animations,
+    /// The key that calls the voice assistant.
+    pub assistant: Assistant,
+    /// Floating app windows kept above the rest.
+    pub bubbles: Bubbles,
+    /// Accessibility color filter for the whole display.
+    pub color_filters: ColorFilters,
+    /// What the mouse pointer looks like, and whether it is shown.
+    pub cursor: Cursor,
+    /// The lock client holding the session, if it is locked.
+    pub session_lock: SessionLock,
+    /// Zooms the output for users with low vision.
+    pub magnifier: Magnifier,
+    /// The phone is in a pocket, so touches are dropped.
+    pub in_pocket: bool,
+    /// The panel is turned off, so nothing is drawn.
+    pub display_off: bool,
+    /// When input last came, for the power service's idle thresholds.
+    pub idle: Idle,
+    /// The app last signalled as on screen, for screen time.
+    pub on_screen: String,
+    /// How far the picture is turned to stay upright as the phone turns.
+    pub rotation: Rotation,
+    /// The app that has the screen to itself, if one is pinned.
+    pub pinned: Option<Pinning>,
+    /// Two apps side by side, on an output wide enough for it.
+    pub split: Option<SplitView>,
+    /// Scans and activates app controls with one or two buttons.
+    pub switch_access: SwitchAccess,
+    /// The system bus connection org.mobileos.Compositor is served on.
+    pub bus: Option<zbus::blocking::Connection>,
 }
 
 #[derive(Default)]
 pub struct ClientState {
     pub compositor_state: CompositorClientState,
+    /// The executable the client's process ran when it connected, which privileged
+    /// globals are only shown to the system clients they are meant for by.
+    pub exe: Option<PathBuf>,
+}
+
+impl ClientState {
+    /// State for the client connecting on `stream`.
+    fn connecting(stream: &UnixStream) -> Self {
+        let exe = rustix::net::sockopt::socket_peercred(stream)
+            .ok()
+            .and_then(|cred| std::fs::read_link(format!("/proc/{}/exe", cred.pid)).ok());
+        Self {
+            exe,
+            ..Default::default()
+        }
+    }
+}
+
+/// A global filter that shows the global only to clients running `exe`.
+fn only_for(exe: &'static str) -> impl Fn(&Client) -> bool + Send + Sync + 'static {
+    move |client| {
+        client
+            .get_data::<ClientState>()
+            .and_then(|data| data.exe.as_deref())
+            .is_some_and(|path| path == Path::new(exe))
+    }
 }
 
 impl ClientData for ClientState {
@@ -54,7 +158,7 @@ impl ClientData for ClientState {
 }
 
 impl Compositor {
-    pub fn new(event_loop: &mut EventLoop<Self>, display: Display<Self>) -> Self {
+    pub fn new(event_loop: &mut EventLoop<'static, Self>, display: Display<Self>) -> Self {
         let dh = display.handle();
 
         let compositor_state = CompositorState::new::<Self>(&dh);
@@ -63,7 +167,16 @@ impl Compositor {
         let output_manager_state = OutputManagerState::new_with_xdg_output::<Self>(&dh);
         let data_device_state = DataDeviceState::new::<Self>(&dh);
         let layer_shell_state = WlrLayerShellState::new::<Self>(&dh);
+        let session_lock_state = SessionLockManagerState::new::<Self, _>(&dh, only_for(SHELL_EXE));
+        let text_input_state = TextInputManagerState::new::<Self>(&dh);
+        let input_method_state =
+            InputMethodManagerState::new::<Self, _>(&dh, only_for(KEYBOARD_EXE));
+        let virtual_keyboard_state =
+            VirtualKeyboardManagerState::new::<Self, _>(&dh, only_for(KEYBOARD_EXE));
+        let idle_notifier_state = IdleNotifierState::new(&dh, event_loop.handle());
+        let foreign_toplevel_list_state = ForeignToplevelListState::new::<Self>(&dh);
         let popups = PopupManager::default();
+        let screencopy = Screencopy::new(&dh, only_for(SCREENSHOT_EXE));
 
         let mut seat_state = SeatState::new();
         let mut seat: Seat<Self> = seat_state.new_wl_seat(&dh, "seat0");
@@ -75,6 +188,7 @@ impl Compositor {
         let space = Space::default();
         let socket_name = Self::init_wayland_listener(display, event_loop);
         let loop_signal = event_loop.get_signal();
+        let handle = event_loop.handle();
 
         info!(socket = ?socket_name, "compositor initialized");
 
@@ -84,6 +198,7 @@ impl Compositor {
             display_handle: dh,
             space,
             loop_signal,
+            handle,
             compositor_state,
             xdg_shell_state,
             shm_state,
@@ -91,9 +206,33 @@ impl Compositor {
             seat_state,
             data_device_state,
             layer_shell_state,
+            session_lock_state,
+            text_input_state,
+            input_method_state,
+            virtual_keyboard_state,
+            idle_notifier_state,
+            foreign_toplevel_list_state,
             popups,
+            screencopy,
+            foreign_toplevels: ForeignToplevels::default(),
             seat,
             drm: None,
+            animations: Animations::default(),
+            assistant: Assistant::from_env(),
+            bubbles: Bubbles::default(),
+            color_filters: ColorFilters::default(),
+            cursor: Cursor::default(),
+            session_lock: SessionLock::default(),
+            magnifier: Magnifier::default(),
+            in_pocket: false,
+            display_off: false,
+            idle: Idle::default(),
+            on_screen: String::new(),
+            rotation: Rotation::default(),
+            pinned: None,
+            split: None,
+            switch_access: SwitchAccess::default(),
+            bus: None,
         }
     }
 
@@ -108,9 +247,10 @@ impl Compositor {
 
         handle
             .insert_source(listening_socket, move |client_stream, _, state| {
+                let client_state = ClientState::connecting(&client_stream);
                 state
                     .display_handle
-                    .insert_client(client_stream, Arc::new(ClientState::default()))
+                    .insert_client(client_stream, Arc::new(client_state))
                     .unwrap();
             })
             .expect("failed to insert wayland listener source");
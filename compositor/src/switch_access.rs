@@ -0,0 +1,329 @@
+// ABOUTME: Switch access: one or two buttons move a highlight over the controls apps report, and activate them.
+// ABOUTME: For users who can't use the touchscreen; the compositor taps the highlighted control for them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use smithay::backend::input::TouchSlot;
+use smithay::backend::renderer::element::solid::SolidColorRenderElement;
+use smithay::backend::renderer::element::{Id, Kind};
+use smithay::backend::renderer::utils::CommitCounter;
+use smithay::input::keyboard::Keysym;
+use smithay::input::touch;
+use smithay::reexports::calloop::LoopHandle;
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use smithay::utils::{Logical, Point, Rectangle, SERIAL_COUNTER};
+use tracing::info;
+
+use crate::dbus::window_app_id;
+use crate::state::Compositor;
+
+/// How long the highlight rests on each control when a single switch scans.
+const SCAN_INTERVAL: Duration = Duration::from_millis(1200);
+
+/// Width of the highlight around a control.
+const BORDER: i32 = 4;
+
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.6, 0.0, 1.0];
+
+/// Keys that move the highlight on with two switches. Bluetooth switches send these or
+/// the select keys; on the phone itself the volume keys are the switches.
+const NEXT_KEYS: [Keysym; 2] = [Keysym::Tab, Keysym::XF86_AudioLowerVolume];
+
+/// Keys that activate the highlighted control, and any switch key with one switch.
+const SELECT_KEYS: [Keysym; 3] = [Keysym::space, Keysym::Return, Keysym::XF86_AudioRaiseVolume];
+
+/// Taps on the highlighted control use this slot, clear of real fingers.
+const ACTIVATION_SLOT: u32 = u32::MAX;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchMode {
+    #[default]
+    Off,
+    /// The highlight moves on by itself; the switch activates.
+    One,
+    /// One switch moves the highlight on, the other activates.
+    Two,
+}
+
+impl SwitchMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SwitchMode::Off => "off",
+            SwitchMode::One => "one",
+            SwitchMode::Two => "two",
+        }
+    }
+}
+
+impl fmt::Display for SwitchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SwitchMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(SwitchMode::Off),
+            "one" => Ok(SwitchMode::One),
+            "two" => Ok(SwitchMode::Two),
+            _ => anyhow::bail!("unknown switch access mode '{s}'"),
+        }
+    }
+}
+
+pub struct SwitchAccess {
+    mode: SwitchMode,
+    /// The controls each app reported, by app id, in its window's surface coordinates
+    /// and in the order they are scanned.
+    focusables: HashMap<String, Vec<Rectangle<i32, Logical>>>,
+    /// Which of the scanned controls is highlighted.
+    current: Option<usize>,
+    /// The highlight's four sides, kept the same from frame to frame for damage tracking.
+    sides: [Id; 4],
+}
+
+impl Default for SwitchAccess {
+    fn default() -> Self {
+        Self {
+            mode: SwitchMode::Off,
+            focusables: HashMap::new(),
+            current: None,
+            sides: std::array::from_fn(|_| Id::new()),
+        }
+    }
+}
+
+/// The four sides of a border `width` wide just outside `target`: top, bottom, left, right.
+pub fn border(target: Rectangle<i32, Logical>, width: i32) -> [Rectangle<i32, Logical>; 4] {
+    let Rectangle { loc, size } = target;
+    [
+        Rectangle::new(
+            (loc.x - width, loc.y - width).into(),
+            (size.w + 2 * width, width).into(),
+        ),
+        Rectangle::new(
+            (loc.x - width, loc.y + size.h).into(),
+            (size.w + 2 * width, width).into(),
+        ),
+        Rectangle::new((loc.x - width, loc.y).into(), (width, size.h).into()),
+        Rectangle::new((loc.x + size.w, loc.y).into(), (width, size.h).into()),
+    ]
+}
+
+fn center(target: Rectangle<i32, Logical>) -> Point<f64, Logical> {
+    target.to_f64().loc + target.size.to_f64().downscale(2.0).to_point()
+}
+
+impl Compositor {
+    pub fn switch_mode(&self) -> SwitchMode {
+        self.switch_access.mode
+    }
+
+    pub fn set_switch_mode(&mut self, mode: SwitchMode) {
+        info!(mode = mode.as_str(), "switch access");
+        self.switch_access.mode = mode;
+        self.switch_access.current = None;
+    }
+
+    /// Record the controls `app_id` shows, in its window's surface coordinates and in
+    /// scan order. An app reports them again whenever its layout changes; none forgets it.
+    pub fn set_focusables(&mut self, app_id: &str, regions: Vec<Rectangle<i32, Logical>>) {
+        if regions.is_empty() {
+            self.switch_access.focusables.remove(app_id);
+        } else {
+            self.switch_access
+                .focusables
+                .insert(app_id.to_string(), regions);
+        }
+    }
+
+    /// The controls that can be highlighted, in global coordinates: those of the
+    /// windows topmost first, leaving out any another window covers.
+    fn switch_targets(&self) -> Vec<Rectangle<i32, Logical>> {
+        let mut targets = Vec::new();
+        for window in self.space.elements().rev() {
+            let Some(regions) =
+                window_app_id(window).and_then(|id| self.switch_access.focusables.get(&id))
+            else {
+                continue;
+            };
+            let Some(loc) = self.space.element_location(window) else {
+                continue;
+            };
+            let origin = loc - window.geometry().loc;
+            targets.extend(
+                regions
+                    .iter()
+                    .map(|region| Rectangle::new(origin + region.loc, region.size))
+                    .filter(|target| {
+                        self.space
+                            .element_under(center(*target))
+                            .is_some_and(|(under, _)| under == window)
+                    }),
+            );
+        }
+        targets
+    }
+
+    /// Follow the switches. Returns true when the key must not reach clients.
+    pub fn switch_access_key(&mut self, keysym: Keysym, pressed: bool, time: u32) -> bool {
+        let next = NEXT_KEYS.contains(&keysym);
+        if self.switch_access.mode == SwitchMode::Off || !(next || SELECT_KEYS.contains(&keysym)) {
+            return false;
+        }
+        if pressed {
+            match self.switch_access.mode {
+                SwitchMode::Two if next => self.switch_next(),
+                _ => self.switch_select(time),
+            }
+        }
+        true
+    }
+
+    /// Move the highlight to the next control, back to the first after the last.
+    fn switch_next(&mut self) {
+        let count = self.switch_targets().len();
+        self.switch_access.current = match self.switch_access.current {
+            _ if count == 0 => None,
+            Some(i) => Some((i + 1) % count),
+            None => Some(0),
+        };
+    }
+
+    /// Tap the highlighted control, after which scanning starts over. With nothing
+    /// highlighted, highlight the first control.
+    fn switch_select(&mut self, time: u32) {
+        let targets = self.switch_targets();
+        match self.switch_access.current.and_then(|i| targets.get(i)) {
+            Some(&target) => {
+                self.switch_access.current = None;
+                self.tap(center(target), time);
+            }
+            None => self.switch_access.current = (!targets.is_empty()).then_some(0),
+        }
+    }
+
+    /// A touch down and up at `pos`, for the surface there, as if the user tapped it.
+    fn tap(&mut self, pos: Point<f64, Logical>, time: u32) {
+        let Some(handle) = self.seat.get_touch() else {
+            return;
+        };
+        let slot = TouchSlot::from(Some(ACTIVATION_SLOT));
+        let focus = self.surface_under(pos);
+        handle.down(
+            self,
+            focus,
+            &touch::DownEvent {
+                slot,
+                location: pos,
+                serial: SERIAL_COUNTER.next_serial(),
+                time,
+            },
+        );
+        handle.frame(self);
+        handle.up(
+            self,
+            &touch::UpEvent {
+                slot,
+                serial: SERIAL_COUNTER.next_serial(),
+                time,
+            },
+        );
+        handle.frame(self);
+    }
+
+    /// The highlight around the highlighted control, for the output showing `area` at
+    /// `scale`; nothing while none is.
+    pub fn switch_highlight(
+        &self,
+        area: Rectangle<i32, Logical>,
+        scale: f64,
+    ) -> Vec<SolidColorRenderElement> {
+        let targets = self.switch_targets();
+        let Some(&target) = self.switch_access.current.and_then(|i| targets.get(i)) else {
+            return Vec::new();
+        };
+        border(target, BORDER)
+            .into_iter()
+            .zip(&self.switch_access.sides)
+            .map(|(side, id)| {
+                let side = Rectangle::new(side.loc - area.loc, side.size);
+                SolidColorRenderElement::new(
+                    id.clone(),
+                    side.to_physical_precise_round(scale),
+                    CommitCounter::default(),
+                    HIGHLIGHT_COLOR,
+                    Kind::Unspecified,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Move the highlight on by itself while a single switch is in use.
+pub fn start_scanning(handle: &LoopHandle<'_, Compositor>) -> anyhow::Result<()> {
+    handle
+        .insert_source(Timer::from_duration(SCAN_INTERVAL), |_, _, state| {
+            if state.switch_access.mode == SwitchMode::One {
+                state.switch_next();
+            }
+            TimeoutAction::ToDuration(SCAN_INTERVAL)
+        })
+        .map_err(|e| anyhow::anyhow!("failed to insert switch scanning timer: {e}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+
+    use super::*;
+    use crate::headless::init_headless;
+
+    #[test]
+    fn modes_round_trip() {
+        for mode in [SwitchMode::Off, SwitchMode::One, SwitchMode::Two] {
+            assert_eq!(mode.as_str().parse::<SwitchMode>().unwrap(), mode);
+        }
+        assert!("three".parse::<SwitchMode>().is_err());
+    }
+
+    #[test]
+    fn border_surrounds_the_target() {
+        let target = Rectangle::new((10, 20).into(), (100, 50).into());
+        let [top, bottom, left, right] = border(target, 4);
+        assert_eq!(top, Rectangle::new((6, 16).into(), (108, 4).into()));
+        assert_eq!(bottom, Rectangle::new((6, 70).into(), (108, 4).into()));
+        assert_eq!(left, Rectangle::new((6, 20).into(), (4, 50).into()));
+        assert_eq!(right, Rectangle::new((110, 20).into(), (4, 50).into()));
+    }
+
+    #[test]
+    fn switch_keys_are_only_taken_while_on() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+        init_headless(&mut event_loop, &mut state).unwrap();
+
+        assert!(!state.switch_access_key(Keysym::space, true, 0));
+
+        state.set_switch_mode(SwitchMode::Two);
+        // No window shows these controls, so there is nothing to highlight
+        state.set_focusables(
+            "gallery",
+            vec![Rectangle::new((0, 0).into(), (10, 10).into())],
+        );
+        assert!(state.switch_access_key(Keysym::Tab, true, 0));
+        assert!(state.switch_access_key(Keysym::Tab, false, 0));
+        assert!(state.switch_access_key(Keysym::space, true, 0));
+        assert!(!state.switch_access_key(Keysym::a, true, 0));
+        assert!(state.switch_highlight(Rectangle::default(), 1.0).is_empty());
+    }
+}
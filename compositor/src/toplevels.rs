@@ -0,0 +1,137 @@
+// ABOUTME: The open windows for app switchers: ext-foreign-toplevel-list-v1 for Wayland clients, and Toplevels on the bus.
+// ABOUTME: Follows each toplevel's title and app id as they change, and brings up or closes an app's window for the shell.
+
+use smithay::desktop::Window;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::wayland::compositor::with_states;
+use smithay::wayland::foreign_toplevel_list::{
+    ForeignToplevelHandle, ForeignToplevelListHandler, ForeignToplevelListState,
+};
+use smithay::wayland::shell::xdg::{ToplevelSurface, XdgToplevelSurfaceData};
+use tracing::info;
+
+use crate::animation::Kind;
+use crate::dbus::window_app_id;
+use crate::state::Compositor;
+
+/// The open toplevels as announced to foreign-toplevel clients, by surface.
+#[derive(Default)]
+pub struct ForeignToplevels {
+    handles: Vec<(WlSurface, ForeignToplevelHandle)>,
+}
+
+impl ForeignToplevels {
+    fn handle(&self, surface: &WlSurface) -> Option<&ForeignToplevelHandle> {
+        self.handles
+            .iter()
+            .find(|(s, _)| s == surface)
+            .map(|(_, handle)| handle)
+    }
+}
+
+/// The title and app id a toplevel set, empty where it set none.
+fn title_and_app_id(surface: &WlSurface) -> (String, String) {
+    with_states(surface, |states| {
+        let Some(data) = states.data_map.get::<XdgToplevelSurfaceData>() else {
+            return Default::default();
+        };
+        let data = data.lock().unwrap();
+        (
+            data.title.clone().unwrap_or_default(),
+            data.app_id.clone().unwrap_or_default(),
+        )
+    })
+}
+
+impl Compositor {
+    /// Announce a new toplevel to foreign-toplevel clients. Apps mostly set their
+    /// title and app id after this, which toplevel_changed passes on.
+    pub fn toplevel_opened(&mut self, surface: &ToplevelSurface) {
+        let (title, app_id) = title_and_app_id(surface.wl_surface());
+        let handle = self
+            .foreign_toplevel_list_state
+            .new_toplevel::<Self>(title, app_id);
+        self.foreign_toplevels
+            .handles
+            .push((surface.wl_surface().clone(), handle));
+    }
+
+    /// Tell foreign-toplevel clients a toplevel's title or app id changed.
+    pub fn toplevel_changed(&mut self, surface: &ToplevelSurface) {
+        let Some(handle) = self.foreign_toplevels.handle(surface.wl_surface()) else {
+            return;
+        };
+        let (title, app_id) = title_and_app_id(surface.wl_surface());
+        handle.send_title(&title);
+        handle.send_app_id(&app_id);
+        handle.send_done();
+    }
+
+    /// Tell foreign-toplevel clients a toplevel is gone.
+    pub fn toplevel_closed(&mut self, surface: &ToplevelSurface) {
+        let handles = &mut self.foreign_toplevels.handles;
+        if let Some(index) = handles.iter().position(|(s, _)| s == surface.wl_surface()) {
+            let (_, handle) = handles.remove(index);
+            self.foreign_toplevel_list_state.remove_toplevel(&handle);
+        }
+    }
+
+    /// The open apps with their window titles, topmost first. Bubbles are left out, as
+    /// in app_ids.
+    pub fn toplevels(&self) -> Vec<(String, String)> {
+        let mut toplevels: Vec<(String, String)> = self
+            .space
+            .elements()
+            .filter(|w| !self.is_bubble(w))
+            .filter_map(|w| {
+                let (title, app_id) = title_and_app_id(w.toplevel()?.wl_surface());
+                (!app_id.is_empty()).then_some((app_id, title))
+            })
+            .collect();
+        toplevels.reverse();
+        toplevels
+    }
+
+    fn app_window(&self, app_id: &str) -> Option<Window> {
+        self.space
+            .elements()
+            .rev()
+            .find(|w| window_app_id(w).as_deref() == Some(app_id))
+            .cloned()
+    }
+
+    /// Bring up the topmost window of `app_id`. False if it has none, or another app
+    /// is pinned.
+    pub fn activate(&mut self, app_id: &str) -> bool {
+        if self.pinned_app_id().is_some_and(|pinned| pinned != app_id) {
+            return false;
+        }
+        let Some(window) = self.app_window(app_id) else {
+            return false;
+        };
+        info!(app_id, "app activated");
+        self.bring_up(&window);
+        self.animations.start(&window, Kind::Switch);
+        true
+    }
+
+    /// Animate the topmost window of `app_id` out and ask it to close. False if it has
+    /// none, or an app is pinned, which only unpinning ends.
+    pub fn close(&mut self, app_id: &str) -> bool {
+        if self.pinned.is_some() {
+            return false;
+        }
+        let Some(window) = self.app_window(app_id) else {
+            return false;
+        };
+        info!(app_id, "app asked to close");
+        self.animate_close(&window);
+        true
+    }
+}
+
+impl ForeignToplevelListHandler for Compositor {
+    fn foreign_toplevel_list_state(&mut self) -> &mut ForeignToplevelListState {
+        &mut self.foreign_toplevel_list_state
+    }
+}
@@ -1,5 +1,5 @@
 // ABOUTME: DRM/udev backend for real hardware and QEMU virtio-gpu.
-// ABOUTME: Opens a libseat session, enumerates DRM devices, and drives the display via GBM/EGL/GLES.
+// ABOUTME: Opens a libseat session, enumerates DRM devices, drives the display via GBM/EGL/GLES, and reads input through libinput.
 
 use std::collections::HashSet;
 use std::path::Path;
@@ -9,25 +9,35 @@ use smithay::backend::drm::compositor::{DrmCompositor, FrameFlags};
 use smithay::backend::drm::exporter::gbm::GbmFramebufferExporter;
 use smithay::backend::drm::{DrmDevice, DrmDeviceFd, DrmEvent};
 use smithay::backend::egl::{EGLContext, EGLDisplay};
+use smithay::backend::input::InputEvent;
+use smithay::backend::libinput::{LibinputInputBackend, LibinputSessionInterface};
 use smithay::backend::renderer::gles::GlesRenderer;
 use smithay::backend::session::libseat::LibSeatSession;
-use smithay::backend::session::Session;
+use smithay::backend::session::{Event as SessionEvent, Session};
 use smithay::backend::udev::{UdevBackend, UdevEvent};
-use smithay::desktop::space::space_render_elements;
 use smithay::output::{Mode, Output, PhysicalProperties, Subpixel};
 use smithay::reexports::calloop::EventLoop;
+use smithay::reexports::input::{self, DeviceCapability, Libinput};
 use smithay::utils::{DeviceFd, Transform};
 use smithay_drm_extras::drm_scanner::{DrmScanEvent, DrmScanner};
 
+use drm::control::Device as ControlDevice;
 use drm_fourcc::{DrmFormat, DrmFourcc};
 use rustix::fs::OFlags;
 use tracing::{error, info, warn};
 
+use crate::color_filter::{self, ColorFilter};
+use crate::layers::{self, ABOVE_WINDOWS, BELOW_WINDOWS};
+use crate::render::OutputRenderElements;
 use crate::state::Compositor;
 
 const CLEAR_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
 const COLOR_FORMATS: &[DrmFourcc] = &[DrmFourcc::Argb8888, DrmFourcc::Xrgb8888];
 
+/// Values of a connector's DPMS property.
+const DPMS_ON: u64 = 0;
+const DPMS_OFF: u64 = 3;
+
 pub fn init_udev(
     event_loop: &mut EventLoop<Compositor>,
     state: &mut Compositor,
@@ -47,11 +57,46 @@ pub fn init_udev(
         }
     }
 
+    let mut libinput = Libinput::new_with_udev(LibinputSessionInterface::from(session.clone()));
+    libinput
+        .udev_assign_seat(&seat_name)
+        .map_err(|()| anyhow::anyhow!("failed to assign libinput to seat {seat_name}"))?;
+    let calibration = display_config().touch_calibration;
+
     let handle = event_loop.handle();
 
+    // Input devices come and go with libinput's own udev monitor
+    let backend = LibinputInputBackend::new(libinput.clone());
+    handle
+        .insert_source(backend, move |mut event, _, state| {
+            match &mut event {
+                InputEvent::DeviceAdded { device } => {
+                    info!(name = device.name(), "input device added");
+                    if let Some(matrix) = calibration {
+                        calibrate(device, matrix);
+                    }
+                }
+                InputEvent::DeviceRemoved { device } => {
+                    info!(name = device.name(), "input device removed");
+                }
+                _ => {}
+            }
+            state.process_input_event(event);
+        })
+        .map_err(|e| anyhow::anyhow!("failed to insert libinput source: {e}"))?;
+
+    // Devices are revoked while another session has the seat
     handle
-        .insert_source(notifier, |event, _, _state| {
+        .insert_source(notifier, move |event, _, _state| {
             info!(?event, "seat event");
+            match event {
+                SessionEvent::PauseSession => libinput.suspend(),
+                SessionEvent::ActivateSession => {
+                    if libinput.resume().is_err() {
+                        error!("failed to resume libinput");
+                    }
+                }
+            }
         })
         .map_err(|e| anyhow::anyhow!("failed to insert seat notifier: {e}"))?;
 
@@ -114,7 +159,10 @@ fn add_device(
         device_fd,
         renderer,
         scanner,
+        crtc: None,
+        connector: None,
         drm_compositor: None,
+        panel_transform: panel_transform(),
     });
 
     for event in scan_result {
@@ -237,7 +285,7 @@ fn add_connector(
     let _global = output.create_global::<Compositor>(&state.display_handle);
     output.change_current_state(
         Some(output_mode),
-        Some(Transform::Normal),
+        Some(drm.panel_transform),
         None,
         Some((0, 0).into()),
     );
@@ -258,17 +306,32 @@ fn add_connector(
     .map_err(|e| anyhow::anyhow!("failed to create DRM compositor: {e}"))?;
 
     drm.drm_compositor = Some(drm_compositor);
+    drm.crtc = Some(crtc);
+    drm.connector = Some(connector.handle());
 
     info!("DRM output configured");
     Ok(())
 }
 
+/// Draw the next frame on the panel. While the session is locked, only the lock client's
+/// surface is drawn, under the cursor.
 pub fn render_frame(state: &mut Compositor) {
     let output = match state.space.outputs().next().cloned() {
         Some(o) => o,
         None => return,
     };
 
+    if !state.display_on() {
+        // Nothing is drawn on a dark panel, so there is nothing to copy either
+        state.screencopy.fail_output(&output);
+        return;
+    }
+
+    let area = state.space.output_geometry(&output).unwrap_or_default();
+    let scale = output.current_scale().fractional_scale();
+    let highlight = state.switch_highlight(area, scale);
+    let pointer = state.pointer_location();
+
     let drm = match state.drm.as_mut() {
         Some(d) => d,
         None => return,
@@ -278,18 +341,37 @@ pub fn render_frame(state: &mut Compositor) {
         None => return,
     };
 
-    let elements = match space_render_elements(
-        &mut drm.renderer,
-        [&state.space],
-        &output,
-        1.0,
-    ) {
-        Ok(e) => e,
-        Err(e) => {
-            warn!("failed to collect render elements: {e}");
-            return;
-        }
+    let locked = state.session_lock.is_locked();
+    // The DRM compositor scans the cursor out from a cursor plane when it fits on one
+    let cursor = state
+        .cursor
+        .render_elements(&mut drm.renderer, pointer, scale);
+    let elements: Vec<OutputRenderElements<_>> = if locked {
+        let lock = state
+            .session_lock
+            .render_elements(&mut drm.renderer, &output, area.loc);
+        cursor
+            .into_iter()
+            .chain(lock.into_iter().map(OutputRenderElements::from))
+            .collect()
+    } else {
+        let above = layers::render_elements(&mut drm.renderer, &output, area.loc, &ABOVE_WINDOWS);
+        let windows = state.animations.render_elements(
+            &mut drm.renderer,
+            &state.space,
+            &output,
+            state.start_time.elapsed(),
+        );
+        let below = layers::render_elements(&mut drm.renderer, &output, area.loc, &BELOW_WINDOWS);
+        cursor
+            .into_iter()
+            .chain(highlight.into_iter().map(OutputRenderElements::from))
+            .chain(above.into_iter().map(OutputRenderElements::from))
+            .chain(windows.into_iter().map(OutputRenderElements::from))
+            .chain(below.into_iter().map(OutputRenderElements::from))
+            .collect()
     };
+    let elements = state.magnifier.transform(elements, area, scale);
 
     match drm_compositor.render_frame::<_, _>(
         &mut drm.renderer,
@@ -308,29 +390,125 @@ pub fn render_frame(state: &mut Compositor) {
             warn!("failed to render frame: {e}");
         }
     }
-
-    state.space.elements().for_each(|window| {
-        window.send_frame(
-            &output,
-            state.start_time.elapsed(),
-            Some(std::time::Duration::ZERO),
-            |_, _| Some(output.clone()),
-        );
-    });
+    state.screencopy.copy_output(&mut drm.renderer, &output, &elements);
+
+    state.send_cursor_frames(&output);
+    if locked {
+        // The frame just queued shows none of the session
+        state.confirm_lock();
+        state.send_lock_frames(&output);
+    } else {
+        state.send_layer_frames(&output);
+        state.space.elements().for_each(|window| {
+            window.send_frame(
+                &output,
+                state.start_time.elapsed(),
+                Some(std::time::Duration::ZERO),
+                |_, _| Some(output.clone()),
+            );
+        });
+    }
 
     state.space.refresh();
     state.popups.cleanup();
     let _ = state.display_handle.flush_clients();
 }
 
+/// Put `filter` on the display, or take it off with None: the CRTC's color transform
+/// matrix mixes the channels, and its gamma ramp runs backwards to invert them.
+/// How to turn the picture for a panel mounted `rotation` degrees clockwise, so it
+/// comes out upright.
+fn rotation_transform(rotation: u32) -> Transform {
+    match rotation {
+        90 => Transform::_90,
+        180 => Transform::_180,
+        270 => Transform::_270,
+        _ => Transform::Normal,
+    }
+}
+
+/// The panel's quirks from the device config, none for devices without one.
+fn display_config() -> mos_device::Display {
+    match mos_device::load_from_system() {
+        Ok(device) => device.map(|device| device.display).unwrap_or_default(),
+        Err(e) => {
+            warn!(error = %e, "failed to load device config, assuming an upright panel");
+            mos_device::Display::default()
+        }
+    }
+}
+
+/// The panel's rotation from the device config, for panels made for landscape.
+fn panel_transform() -> Transform {
+    rotation_transform(display_config().rotation)
+}
+
+/// Line a touchscreen's axes up with the panel's. Pointers and keys have none, and
+/// touchscreens libinput can't calibrate keep their own.
+fn calibrate(device: &mut input::Device, matrix: mos_device::CalibrationMatrix) {
+    if !device.has_capability(DeviceCapability::Touch) || !device.config_calibration_has_matrix() {
+        return;
+    }
+    match device.config_calibration_set_matrix(matrix) {
+        Ok(()) => info!(name = device.name(), ?matrix, "touchscreen calibrated"),
+        Err(e) => warn!(name = device.name(), error = ?e, "failed to calibrate touchscreen"),
+    }
+}
+
+pub fn set_color_filter(drm: &DrmState, filter: Option<ColorFilter>) -> anyhow::Result<()> {
+    let crtc = drm.crtc.ok_or_else(|| anyhow::anyhow!("no CRTC driving the display"))?;
+
+    let properties = drm.device.get_properties(crtc)?.as_hashmap(&drm.device)?;
+    let ctm = properties
+        .get("CTM")
+        .ok_or_else(|| anyhow::anyhow!("CRTC has no color transform matrix"))?
+        .handle();
+    let matrix = filter.map_or(color_filter::IDENTITY, ColorFilter::matrix);
+    let blob = drm.device.create_property_blob(&color_filter::ctm(&matrix))?;
+    drm.device.set_property(crtc, ctm, blob.into())?;
+
+    let len = drm.device.get_crtc(crtc)?.gamma_length() as usize;
+    let ramp = color_filter::gamma_ramp(len, filter.is_some_and(ColorFilter::inverts));
+    drm.device.set_gamma(crtc, &ramp, &ramp, &ramp)?;
+    Ok(())
+}
+
+/// Turn the panel off or on through its connector's DPMS property. Once it is back
+/// on, the next frame commits the whole display state again, as the kernel may have
+/// let the CRTC go while it was off.
+pub fn set_dpms(drm: &mut DrmState, on: bool) -> anyhow::Result<()> {
+    let connector = drm
+        .connector
+        .ok_or_else(|| anyhow::anyhow!("no connector driving the display"))?;
+
+    let properties = drm.device.get_properties(connector)?.as_hashmap(&drm.device)?;
+    let dpms = properties
+        .get("DPMS")
+        .ok_or_else(|| anyhow::anyhow!("connector has no DPMS property"))?
+        .handle();
+    drm.device
+        .set_property(connector, dpms, if on { DPMS_ON } else { DPMS_OFF })?;
+
+    if on && let Some(compositor) = drm.drm_compositor.as_mut() {
+        compositor.reset_state()?;
+    }
+    Ok(())
+}
+
 pub struct DrmState {
     pub device: DrmDevice,
     pub gbm_device: GbmDevice<DrmDeviceFd>,
     pub device_fd: DrmDeviceFd,
     pub renderer: GlesRenderer,
     pub scanner: DrmScanner,
+    /// The CRTC driving the display, once a connector is up.
+    pub crtc: Option<drm::control::crtc::Handle>,
+    /// The connector the panel hangs off, once it is up.
+    pub connector: Option<drm::control::connector::Handle>,
     pub drm_compositor:
         Option<DrmCompositor<GbmAllocator<DrmDeviceFd>, GbmFramebufferExporter<DrmDeviceFd>, (), DrmDeviceFd>>,
+    /// How the output is turned to make up for how the panel is mounted.
+    pub panel_transform: Transform,
 }
 
 #[cfg(test)]
@@ -348,4 +526,11 @@ mod tests {
             assert!((0.0..=1.0).contains(&c));
         }
     }
+
+    #[test]
+    fn panel_rotation_maps_to_transform() {
+        assert_eq!(rotation_transform(0), Transform::Normal);
+        assert_eq!(rotation_transform(90), Transform::_90);
+        assert_eq!(rotation_transform(270), Transform::_270);
+    }
 }
@@ -0,0 +1,160 @@
+// ABOUTME: Window management for a phone: every app window fills the screen but what panels like the status bar keep.
+// ABOUTME: Windows stack in the space, the one brought up last on top; closing it brings up the one below.
+
+use smithay::desktop::{Window, layer_map_for_output};
+use smithay::reexports::wayland_protocols::xdg::shell::server::xdg_toplevel;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::utils::{Logical, Rectangle};
+
+use crate::state::Compositor;
+
+/// Where apps go on an output at `geometry`, given the `zone` its layer-shell panels
+/// leave them, relative to the output. A layer map that was never arranged has no zone
+/// yet, and leaves them all of it.
+pub fn app_area(
+    geometry: Rectangle<i32, Logical>,
+    zone: Rectangle<i32, Logical>,
+) -> Rectangle<i32, Logical> {
+    if zone.is_empty() {
+        geometry
+    } else {
+        Rectangle::new(geometry.loc + zone.loc, zone.size)
+    }
+}
+
+impl Compositor {
+    /// The part of the output apps get: all of it but the exclusive zones of panels,
+    /// such as the status bar.
+    pub fn app_area(&self) -> Option<Rectangle<i32, Logical>> {
+        let output = self.space.outputs().next()?;
+        let geometry = self.space.output_geometry(output)?;
+        let zone = layer_map_for_output(output).non_exclusive_zone();
+        Some(app_area(geometry, zone))
+    }
+
+    /// Size `window` to the app area and put it there, telling its app it is maximized.
+    /// The configure goes out with the caller's.
+    pub fn maximize(&mut self, window: &Window) {
+        let Some(area) = self.app_area() else {
+            return;
+        };
+        if let Some(toplevel) = window.toplevel() {
+            toplevel.with_pending_state(|state| {
+                state.size = Some(area.size);
+                state.states.set(xdg_toplevel::State::Maximized);
+            });
+        }
+        self.space.map_element(window.clone(), area.loc, false);
+    }
+
+    /// Fit every app window to the app area again, as after the output turned or a
+    /// panel changed what it keeps. Split view and bubbles lay themselves out.
+    pub fn arrange_windows(&mut self) {
+        if let Some(output) = self.space.outputs().next() {
+            layer_map_for_output(output).arrange();
+        }
+        // Bottom to top, as mapping raises
+        let windows: Vec<Window> = self
+            .space
+            .elements()
+            .filter(|w| !self.is_bubble(w) && !self.is_split(w))
+            .cloned()
+            .collect();
+        for window in windows {
+            self.maximize(&window);
+            if let Some(toplevel) = window.toplevel() {
+                toplevel.send_pending_configure();
+            }
+        }
+        self.restack_bubbles();
+    }
+
+    /// The topmost app window, leaving out bubbles.
+    pub fn top_window(&self) -> Option<Window> {
+        self.space
+            .elements()
+            .rev()
+            .find(|w| !self.is_bubble(w))
+            .cloned()
+    }
+
+    /// Take a closed window off the stack. If it was on top, the one below comes up, so
+    /// the screen and keyboard go back to the app used before it.
+    pub fn window_closed(&mut self, surface: &WlSurface) {
+        let Some(window) = self
+            .space
+            .elements()
+            .find(|w| w.toplevel().is_some_and(|t| t.wl_surface() == surface))
+            .cloned()
+        else {
+            return;
+        };
+        let was_on_top = self.top_window().as_ref() == Some(&window);
+        self.space.unmap_elem(&window);
+        if was_on_top
+            && !self.is_locked()
+            && let Some(below) = self.top_window()
+        {
+            self.bring_up(&below);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::output::{Mode, Output, PhysicalProperties, Subpixel};
+    use smithay::reexports::calloop::EventLoop;
+    use smithay::reexports::wayland_server::Display;
+    use smithay::utils::Transform;
+
+    use super::*;
+
+    #[test]
+    fn panels_keep_their_zones_from_apps() {
+        let output = Rectangle::new((0, 0).into(), (720, 1440).into());
+        // A status bar 48 high along the top
+        let zone = Rectangle::new((0, 48).into(), (720, 1392).into());
+        assert_eq!(
+            app_area(output, zone),
+            Rectangle::new((0, 48).into(), (720, 1392).into())
+        );
+
+        let docked = Rectangle::new((720, 0).into(), (1920, 1080).into());
+        let zone = Rectangle::new((0, 0).into(), (1920, 1032).into());
+        assert_eq!(
+            app_area(docked, zone),
+            Rectangle::new((720, 0).into(), (1920, 1032).into())
+        );
+
+        assert_eq!(app_area(output, Rectangle::default()), output);
+    }
+
+    #[test]
+    fn apps_get_the_output_as_it_turns() {
+        let mut event_loop: EventLoop<Compositor> = EventLoop::try_new().unwrap();
+        let display: Display<Compositor> = Display::new().unwrap();
+        let mut state = Compositor::new(&mut event_loop, display);
+        assert_eq!(state.app_area(), None);
+        assert!(state.top_window().is_none());
+
+        let output = Output::new(
+            "test".to_string(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "MobileOS".into(),
+                model: "Test".into(),
+            },
+        );
+        let mode = Mode {
+            size: (720, 1440).into(),
+            refresh: 60_000,
+        };
+        output.change_current_state(Some(mode), Some(Transform::Normal), None, None);
+        state.space.map_output(&output, (0, 0));
+        assert_eq!(state.app_area().unwrap().size, (720, 1440).into());
+
+        state.set_rotation(90);
+        assert_eq!(state.app_area().unwrap().size, (1440, 720).into());
+    }
+}
@@ -1,7 +1,6 @@
 // ABOUTME: Winit backend for desktop development and testing.
 // ABOUTME: Opens a window on the host compositor and renders Wayland client surfaces into it.
 
-use smithay::backend::renderer::element::surface::WaylandSurfaceRenderElement;
 use smithay::backend::renderer::damage::OutputDamageTracker;
 use smithay::backend::renderer::gles::GlesRenderer;
 use smithay::backend::winit::{self, WinitEvent};
@@ -10,6 +9,8 @@ use smithay::reexports::calloop::EventLoop;
 use smithay::utils::Transform;
 use tracing::info;
 
+use crate::layers::{self, ABOVE_WINDOWS, BELOW_WINDOWS};
+use crate::render::OutputRenderElements;
 use crate::state::Compositor;
 
 pub fn init_winit(
@@ -66,40 +67,101 @@ pub fn init_winit(
                 WinitEvent::Input(event) => {
                     state.process_input_event(event);
                 }
+                WinitEvent::Redraw if !state.display_on() => {
+                    // A desktop window has no panel to turn off; it just stops drawing
+                    state.screencopy.fail_output(&output);
+                    backend.window().request_redraw();
+                }
                 WinitEvent::Redraw => {
                     let size = backend.window_size();
                     let damage = smithay::utils::Rectangle::from_size(size);
 
                     {
                         let (renderer, mut framebuffer) = backend.bind().unwrap();
-                        smithay::desktop::space::render_output::<
-                            _,
-                            WaylandSurfaceRenderElement<GlesRenderer>,
-                            _,
-                            _,
-                        >(
-                            &output,
-                            renderer,
-                            &mut framebuffer,
-                            1.0,
-                            0,
-                            [&state.space],
-                            &[],
-                            &mut damage_tracker,
-                            [0.1, 0.1, 0.1, 1.0],
-                        )
-                        .unwrap();
+                        let area = state.space.output_geometry(&output).unwrap_or_default();
+                        let scale = output.current_scale().fractional_scale();
+                        // The host draws no cursor over the window, so it is drawn here
+                        let mut elements =
+                            state
+                                .cursor
+                                .render_elements(renderer, state.pointer_location(), scale);
+                        if state.is_locked() {
+                            elements.extend(
+                                state
+                                    .session_lock
+                                    .render_elements(renderer, &output, area.loc)
+                                    .into_iter()
+                                    .map(OutputRenderElements::from),
+                            );
+                        } else {
+                            elements.extend(
+                                state
+                                    .switch_highlight(area, scale)
+                                    .into_iter()
+                                    .map(OutputRenderElements::from),
+                            );
+                            elements.extend(
+                                layers::render_elements(
+                                    renderer,
+                                    &output,
+                                    area.loc,
+                                    &ABOVE_WINDOWS,
+                                )
+                                .into_iter()
+                                .map(OutputRenderElements::from),
+                            );
+                            elements.extend(
+                                state
+                                    .animations
+                                    .render_elements(
+                                        renderer,
+                                        &state.space,
+                                        &output,
+                                        state.start_time.elapsed(),
+                                    )
+                                    .into_iter()
+                                    .map(OutputRenderElements::from),
+                            );
+                            elements.extend(
+                                layers::render_elements(
+                                    renderer,
+                                    &output,
+                                    area.loc,
+                                    &BELOW_WINDOWS,
+                                )
+                                .into_iter()
+                                .map(OutputRenderElements::from),
+                            );
+                        }
+                        let elements = state.magnifier.transform(elements, area, scale);
+                        damage_tracker
+                            .render_output(
+                                renderer,
+                                &mut framebuffer,
+                                0,
+                                &elements,
+                                [0.1, 0.1, 0.1, 1.0],
+                            )
+                            .unwrap();
+                        state.screencopy.copy_output(renderer, &output, &elements);
                     }
                     backend.submit(Some(&[damage])).unwrap();
 
-                    state.space.elements().for_each(|window| {
-                        window.send_frame(
-                            &output,
-                            state.start_time.elapsed(),
-                            Some(std::time::Duration::ZERO),
-                            |_, _| Some(output.clone()),
-                        );
-                    });
+                    state.send_cursor_frames(&output);
+                    if state.is_locked() {
+                        state.confirm_lock();
+                        state.send_lock_frames(&output);
+                    } else {
+                        state.send_layer_frames(&output);
+                        state.space.elements().for_each(|window| {
+                            window.send_frame(
+                                &output,
+                                state.start_time.elapsed(),
+                                Some(std::time::Duration::ZERO),
+                                |_, _| Some(output.clone()),
+                            );
+                        });
+                    }
 
                     state.space.refresh();
                     state.popups.cleanup();
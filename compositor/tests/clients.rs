@@ -0,0 +1,60 @@
+// ABOUTME: Tests of what only a real client shows: toplevels configured before their first
+// ABOUTME: buffer, and touches delivered to the surface under them. Uses the benchmark harness.
+
+#[path = "../benches/support/mod.rs"]
+mod support;
+
+use smithay::backend::input::TouchSlot;
+use smithay::desktop::layer_map_for_output;
+
+use support::{Harness, Spec};
+
+const WINDOW_SIZE: (i32, i32) = (360, 360);
+
+#[test]
+fn surfaces_are_configured_before_their_first_buffer() {
+    let mut harness = Harness::new();
+    // Connecting waits for every surface to be configured and to show a buffer
+    let client = harness.connect(Spec {
+        windows: 2,
+        window_size: WINDOW_SIZE,
+        layers: 1,
+    });
+    harness.map_layers();
+
+    assert_eq!(harness.state.space.elements().count(), 2);
+    for window in harness.state.space.elements() {
+        assert_eq!(window.bbox().size, WINDOW_SIZE.into());
+    }
+    assert_eq!(layer_map_for_output(&harness.output).layers().count(), 1);
+
+    // Later commits go through as they are
+    client.damage(0, (0, 0, 32, 32));
+    harness.wait_for(&client);
+    assert_eq!(harness.state.space.elements().count(), 2);
+}
+
+#[test]
+fn touches_go_to_the_window_under_them() {
+    let mut harness = Harness::new();
+    let client = harness.connect(Spec {
+        windows: 1,
+        window_size: WINDOW_SIZE,
+        layers: 0,
+    });
+    harness.tile_windows(1, (0, 0));
+    harness.dispatch();
+
+    let slot = TouchSlot::from(Some(0));
+    for (pos, time) in [((20.0, 20.0), 0), ((600.0, 1000.0), 100)] {
+        harness.state.touch_down_at(slot, pos.into(), time);
+        harness.state.touch_frame();
+        harness.state.touch_up(slot, time + 16);
+        harness.state.touch_frame();
+    }
+    client.sync();
+    harness.wait_for(&client);
+
+    // The second touch was on no window
+    assert_eq!(client.touch_downs(), 1);
+}
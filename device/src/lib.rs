@@ -0,0 +1,290 @@
+// ABOUTME: Per-device hardware configs from /etc/mos/devices, picked by the device tree's compatible strings.
+// ABOUTME: Lists the firmware a device needs, LED colours, audio routing, sensor mount matrices, panel rotation and touch calibration.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+
+pub const DEVICES_DIR: &str = "/etc/mos/devices";
+
+/// NUL-separated compatible strings of the machine, most specific first.
+pub const COMPATIBLE_PATH: &str = "/proc/device-tree/compatible";
+
+/// Where the kernel looks for firmware, in the order it looks.
+pub const FIRMWARE_DIRS: &[&str] = &["/lib/firmware/updates", "/lib/firmware"];
+
+/// Maps a sensor's axes onto the phone's, row by row, like a device tree `mount-matrix`.
+pub type MountMatrix = [[f64; 3]; 3];
+
+/// Maps a touchscreen's positions onto the panel, as libinput's calibration matrix: the
+/// top two rows of a 3x3 matrix, in fractions of the screen's width and height.
+pub type CalibrationMatrix = [f32; 6];
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Device {
+    /// What the device is called, for logs.
+    pub name: String,
+    /// Device tree compatible strings the config is for, e.g. "pine64,pinephone-1.2".
+    pub compatible: Vec<String>,
+    /// Firmware files the device's drivers load, relative to the firmware directories.
+    #[serde(default)]
+    pub firmware: Vec<String>,
+    /// Colours of indicator LEDs whose sysfs names don't give one, by name, e.g.
+    /// "lp5523:channel0" = "red".
+    #[serde(default)]
+    pub leds: HashMap<String, String>,
+    /// Audio routing file in /etc/mos/audio for the device's codec, without ".toml".
+    #[serde(default)]
+    pub audio: Option<String>,
+    #[serde(default)]
+    pub sensors: Sensors,
+    #[serde(default)]
+    pub display: Display,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sensors {
+    /// For accelerometers mounted askew whose device tree doesn't say so.
+    pub accelerometer_mount_matrix: Option<MountMatrix>,
+    pub magnetometer_mount_matrix: Option<MountMatrix>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Display {
+    /// Degrees clockwise the panel is mounted from upright: 0, 90, 180 or 270. Panels
+    /// made for landscape sit rotated in a portrait phone.
+    #[serde(default)]
+    pub rotation: u32,
+    /// For touchscreens whose axes don't line up with the panel's, and whose udev
+    /// rules don't set LIBINPUT_CALIBRATION_MATRIX.
+    #[serde(default)]
+    pub touch_calibration: Option<CalibrationMatrix>,
+}
+
+/// A reading along the sensor's axes, turned onto the phone's.
+pub fn apply_mount_matrix(matrix: &MountMatrix, vector: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row.iter().zip(vector).map(|(m, v)| m * v).sum())
+}
+
+/// Split the contents of /proc/device-tree/compatible.
+pub fn parse_compatible(content: &[u8]) -> Vec<String> {
+    content
+        .split(|&b| b == 0)
+        .filter(|s| !s.is_empty())
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .collect()
+}
+
+pub fn parse_device(content: &str) -> Result<Device> {
+    let device: Device = toml::from_str(content).context("failed to parse device config")?;
+    if device.compatible.is_empty() {
+        bail!("device '{}' lists no compatible strings", device.name);
+    }
+    if ![0, 90, 180, 270].contains(&device.display.rotation) {
+        bail!(
+            "device '{}': display rotation {} isn't 0, 90, 180 or 270",
+            device.name,
+            device.display.rotation
+        );
+    }
+    if let Some(matrix) = device.display.touch_calibration
+        && !matrix.iter().all(|v| v.is_finite())
+    {
+        bail!(
+            "device '{}': touch calibration isn't all numbers",
+            device.name
+        );
+    }
+    Ok(device)
+}
+
+/// The config in `dir` for a machine with `compatible` strings, most specific first:
+/// the one naming the earliest of them wins, so a config for one board revision beats
+/// one for the whole family.
+pub fn find(dir: &Path, compatible: &[String]) -> Result<Option<Device>> {
+    let mut paths: Vec<_> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+    paths.sort();
+
+    let mut devices = Vec::new();
+    for path in paths {
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        devices.push(parse_device(&content).with_context(|| format!("in {}", path.display()))?);
+    }
+    Ok(compatible.iter().find_map(|compatible| {
+        devices
+            .iter()
+            .position(|device| device.compatible.contains(compatible))
+            .map(|i| devices.swap_remove(i))
+    }))
+}
+
+/// The config for the machine this runs on, if there is one for it. Machines without a
+/// device tree have none.
+pub fn load_from_system() -> Result<Option<Device>> {
+    let compatible = match std::fs::read(COMPATIBLE_PATH) {
+        Ok(content) => parse_compatible(&content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {COMPATIBLE_PATH}")),
+    };
+    find(Path::new(DEVICES_DIR), &compatible)
+}
+
+impl Device {
+    /// The firmware files found in none of `dirs`.
+    pub fn missing_firmware(&self, dirs: &[&Path]) -> Vec<&str> {
+        self.firmware
+            .iter()
+            .filter(|file| !dirs.iter().any(|dir| dir.join(file).is_file()))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_device(dir: &Path, file: &str, name: &str, compatible: &[&str]) {
+        let compatible: Vec<String> = compatible.iter().map(|c| format!("\"{c}\"")).collect();
+        std::fs::write(
+            dir.join(file),
+            format!(
+                "name = \"{name}\"\ncompatible = [{}]\n",
+                compatible.join(", ")
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn compatible_strings_are_nul_separated() {
+        assert_eq!(
+            parse_compatible(b"pine64,pinephone-1.2\0allwinner,sun50i-a64\0"),
+            ["pine64,pinephone-1.2", "allwinner,sun50i-a64"]
+        );
+        assert!(parse_compatible(b"").is_empty());
+    }
+
+    #[test]
+    fn most_specific_config_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        write_device(
+            dir.path(),
+            "a64.toml",
+            "Allwinner A64",
+            &["allwinner,sun50i-a64"],
+        );
+        write_device(
+            dir.path(),
+            "pinephone.toml",
+            "PinePhone",
+            &["pine64,pinephone-1.1", "pine64,pinephone-1.2"],
+        );
+        std::fs::write(dir.path().join("README"), "not a config").unwrap();
+
+        let compatible = parse_compatible(b"pine64,pinephone-1.2\0allwinner,sun50i-a64\0");
+        let device = find(dir.path(), &compatible).unwrap().unwrap();
+        assert_eq!(device.name, "PinePhone");
+
+        let compatible = parse_compatible(b"pine64,pinetab\0allwinner,sun50i-a64\0");
+        let device = find(dir.path(), &compatible).unwrap().unwrap();
+        assert_eq!(device.name, "Allwinner A64");
+
+        let compatible = parse_compatible(b"linux,dummy-virt\0");
+        assert_eq!(find(dir.path(), &compatible).unwrap(), None);
+        assert_eq!(
+            find(&dir.path().join("missing"), &compatible).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn full_config_parses() {
+        let device = parse_device(
+            r#"
+            name = "Example"
+            compatible = ["vendor,phone"]
+            firmware = ["vendor/modem.bin"]
+            audio = "example"
+
+            [leds]
+            "lp5523:channel0" = "red"
+
+            [sensors]
+            accelerometer_mount_matrix = [[0, 1, 0], [-1, 0, 0], [0, 0, 1]]
+
+            [display]
+            rotation = 90
+            touch_calibration = [0, 1, 0, -1, 0, 1]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(device.leds["lp5523:channel0"], "red");
+        assert_eq!(device.audio.as_deref(), Some("example"));
+        assert_eq!(device.display.rotation, 90);
+        assert_eq!(
+            device.display.touch_calibration,
+            Some([0.0, 1.0, 0.0, -1.0, 0.0, 1.0])
+        );
+        let matrix = device.sensors.accelerometer_mount_matrix.unwrap();
+        assert_eq!(
+            apply_mount_matrix(&matrix, [1.0, 2.0, 3.0]),
+            [2.0, -1.0, 3.0]
+        );
+        assert_eq!(device.sensors.magnetometer_mount_matrix, None);
+    }
+
+    #[test]
+    fn bad_configs_are_rejected() {
+        let base = "name = \"Example\"\ncompatible = [\"vendor,phone\"]\n";
+        assert!(parse_device(base).is_ok());
+        assert!(parse_device("name = \"Example\"\ncompatible = []").is_err());
+        assert!(parse_device(&format!("{base}[display]\nrotation = 45")).is_err());
+        assert!(
+            parse_device(&format!(
+                "{base}[display]\ntouch_calibration = [1, 0, 0, 0, 1, nan]"
+            ))
+            .is_err()
+        );
+        assert!(
+            parse_device(&format!(
+                "{base}[display]\ntouch_calibration = [1, 0, 0, 0, 1]"
+            ))
+            .is_err()
+        );
+        assert!(parse_device(&format!("{base}firmwre = []")).is_err());
+    }
+
+    #[test]
+    fn missing_firmware_is_reported() {
+        let updates = tempfile::tempdir().unwrap();
+        let firmware = tempfile::tempdir().unwrap();
+        std::fs::create_dir(firmware.path().join("rtl_bt")).unwrap();
+        std::fs::write(firmware.path().join("rtl_bt/rtl8723cs_xx_fw.bin"), "fw").unwrap();
+        std::fs::write(updates.path().join("anx7688-fw.bin"), "fw").unwrap();
+
+        let device = Device {
+            firmware: vec![
+                "rtl_bt/rtl8723cs_xx_fw.bin".to_string(),
+                "anx7688-fw.bin".to_string(),
+                "ov5640_af.bin".to_string(),
+            ],
+            ..Default::default()
+        };
+        let dirs = [updates.path(), firmware.path()];
+        assert_eq!(device.missing_firmware(&dirs), ["ov5640_af.bin"]);
+    }
+}
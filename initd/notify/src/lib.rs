@@ -0,0 +1,60 @@
+// ABOUTME: The service side of initd's readiness protocol: datagrams to the socket named in NOTIFY_SOCKET.
+// ABOUTME: Notify services say READY=1 once up, and those initd watches send WATCHDOG=1 to stay alive.
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use tracing::warn;
+
+/// Set by initd to the socket of a notify service.
+pub const SOCKET_ENV: &str = "NOTIFY_SOCKET";
+
+/// Set by initd to how long it waits between WATCHDOG=1 pings, in microseconds.
+pub const WATCHDOG_ENV: &str = "WATCHDOG_USEC";
+
+/// Send `message` to initd, if it started us as a notify service.
+pub fn send(message: &[u8]) -> io::Result<()> {
+    match std::env::var_os(SOCKET_ENV) {
+        Some(path) => UnixDatagram::unbound()?.send_to(message, path).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// Tell initd we are up, e.g. our bus name is taken, so services ordered after us can
+/// start.
+pub fn ready() {
+    if let Err(e) = send(b"READY=1") {
+        warn!(error = %e, "failed to notify readiness");
+    }
+}
+
+/// Ping initd's watchdog.
+pub fn watchdog() {
+    if let Err(e) = send(b"WATCHDOG=1") {
+        warn!(error = %e, "failed to ping watchdog");
+    }
+}
+
+/// How often to ping the watchdog: twice per timeout initd gave, if it watches us at all.
+pub fn watchdog_interval() -> Option<Duration> {
+    interval(std::env::var(WATCHDOG_ENV).ok().as_deref())
+}
+
+fn interval(usec: Option<&str>) -> Option<Duration> {
+    let usec: u64 = usec?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec) / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_is_pinged_twice_per_timeout() {
+        assert_eq!(interval(Some("10000000")), Some(Duration::from_secs(5)));
+        assert_eq!(interval(Some("0")), None);
+        assert_eq!(interval(Some("soon")), None);
+        assert_eq!(interval(None), None);
+    }
+}
@@ -0,0 +1,56 @@
+// ABOUTME: Per-service resource accounting: CPU time used and memory resident, read from /proc.
+// ABOUTME: Lets `mosctl top` and the settings app show which daemon is burning the battery.
+
+use std::time::Duration;
+
+use crate::oom;
+
+/// What a service's process has used so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    /// User and system time of the process and of the children it has waited for.
+    pub cpu: Duration,
+    pub rss_bytes: u64,
+}
+
+/// CPU time in clock ticks from the contents of /proc/<pid>/stat: utime, stime, cutime
+/// and cstime, the 14th to 17th fields. Counted after the command name, which is in
+/// parentheses and may itself hold spaces and parentheses.
+pub fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    // The fields after the name start with the 3rd, the state
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    fields
+        .get(11..15)?
+        .iter()
+        .map(|field| field.parse::<u64>().ok())
+        .sum()
+}
+
+/// What process `pid` has used, or `None` once it is gone.
+pub fn read(pid: u32) -> Option<Usage> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let ticks = parse_cpu_ticks(&stat)?;
+    let per_second = rustix::param::clock_ticks_per_second().max(1);
+    Some(Usage {
+        cpu: Duration::from_millis(ticks * 1000 / per_second),
+        rss_bytes: oom::resident_pages(pid) * rustix::param::page_size() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_time_counts_waited_for_children() {
+        let stat = "1234 (mos (net) d) S 1 1234 1234 0 -1 4194560 500 0 0 0 \
+                    120 30 7 3 20 0 1 0 100 10000000 1500 18446744073709551615";
+        assert_eq!(parse_cpu_ticks(stat), Some(160));
+        assert_eq!(parse_cpu_ticks("1234 (sh) S 1 1234"), None);
+
+        let usage = read(std::process::id()).unwrap();
+        assert!(usage.rss_bytes > 0);
+        assert_eq!(read(u32::MAX), None);
+    }
+}
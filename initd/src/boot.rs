@@ -0,0 +1,205 @@
+// ABOUTME: Boot-time profile: when each service was first spawned, ready, and failed.
+// ABOUTME: Gives `mosctl boot-analyze` its blame list and critical chain.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::config::ServiceConfig;
+use crate::dependency;
+
+/// The first run of a service, in time since init started.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Timeline {
+    /// Everything it started after: what it requires and what it wants.
+    pub depends_on: Vec<String>,
+    pub spawned: Option<Duration>,
+    pub ready: Option<Duration>,
+    /// The first failure and why, e.g. a crash or a missed start timeout.
+    pub failure: Option<(Duration, String)>,
+}
+
+impl Timeline {
+    /// How long the service took from spawning to becoming ready.
+    pub fn startup(&self) -> Option<Duration> {
+        Some(self.ready?.saturating_sub(self.spawned?))
+    }
+}
+
+pub struct BootProfile {
+    start: Instant,
+    /// Steps of init itself, such as loading the service configs, in order.
+    phases: Vec<(&'static str, Duration)>,
+    services: BTreeMap<String, Timeline>,
+}
+
+impl BootProfile {
+    pub fn new(start: Instant) -> Self {
+        Self {
+            start,
+            phases: Vec::new(),
+            services: BTreeMap::new(),
+        }
+    }
+
+    pub fn mark(&mut self, phase: &'static str, at: Instant) {
+        let since = self.since_start(at);
+        self.phases.push((phase, since));
+    }
+
+    /// Only the first spawn counts; restarts after boot don't move the timeline.
+    pub fn spawned(&mut self, config: &ServiceConfig, at: Instant) {
+        let since = self.since_start(at);
+        let timeline = self.services.entry(config.name.clone()).or_default();
+        if timeline.spawned.is_none() {
+            timeline.depends_on = dependency::edges(config)
+                .map(|(dep, _)| dep.to_string())
+                .collect();
+            timeline.spawned = Some(since);
+        }
+    }
+
+    pub fn ready(&mut self, name: &str, at: Instant) {
+        let since = self.since_start(at);
+        let timeline = self.services.entry(name.to_string()).or_default();
+        timeline.ready.get_or_insert(since);
+    }
+
+    pub fn failed(&mut self, name: &str, reason: &str, at: Instant) {
+        let since = self.since_start(at);
+        let timeline = self.services.entry(name.to_string()).or_default();
+        timeline
+            .failure
+            .get_or_insert_with(|| (since, reason.to_string()));
+    }
+
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Timeline> {
+        self.services.get(name)
+    }
+
+    pub fn services(&self) -> impl Iterator<Item = (&str, &Timeline)> {
+        self.services
+            .iter()
+            .map(|(name, timeline)| (name.as_str(), timeline))
+    }
+
+    /// When the last service to become ready did so.
+    pub fn finished(&self) -> Option<Duration> {
+        self.services.values().filter_map(|t| t.ready).max()
+    }
+
+    /// Services by how long they took to become ready, slowest first.
+    pub fn blame(&self) -> Vec<(&str, Duration)> {
+        let mut blame: Vec<(&str, Duration)> = self
+            .services()
+            .filter_map(|(name, timeline)| Some((name, timeline.startup()?)))
+            .collect();
+        blame.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        blame
+    }
+
+    /// The chain of services that held up the end of boot: the last one to become
+    /// ready, then whichever of its dependencies became ready last, and so on. The
+    /// last service comes first.
+    pub fn critical_chain(&self) -> Vec<&str> {
+        let mut chain = Vec::new();
+        let mut current = self
+            .services()
+            .filter_map(|(name, timeline)| Some((name, timeline.ready?)))
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+            .map(|(name, _)| name);
+        while let Some(name) = current {
+            chain.push(name);
+            current = self.services[name]
+                .depends_on
+                .iter()
+                .filter_map(|dep| {
+                    let (dep, timeline) = self.services.get_key_value(dep)?;
+                    Some((dep.as_str(), timeline.ready?))
+                })
+                .filter(|(dep, _)| !chain.contains(dep))
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+                .map(|(dep, _)| dep);
+        }
+        chain
+    }
+
+    fn since_start(&self, at: Instant) -> Duration {
+        at.saturating_duration_since(self.start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, depends_on: &[&str]) -> ServiceConfig {
+        ServiceConfig {
+            name: name.to_string(),
+            exec: "true".to_string(),
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn blames_slow_services_and_finds_the_critical_chain() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut profile = BootProfile::new(start);
+        profile.mark("configs loaded", ms(5));
+
+        profile.spawned(&service("dbus", &[]), ms(10));
+        profile.ready("dbus", ms(60));
+        profile.spawned(&service("power", &["dbus"]), ms(60));
+        profile.spawned(&service("sensors", &["dbus"]), ms(60));
+        profile.ready("sensors", ms(100));
+        profile.ready("power", ms(400));
+        profile.spawned(&service("leds", &["dbus", "power"]), ms(400));
+        profile.ready("leds", ms(450));
+        profile.spawned(&service("modem", &["dbus"]), ms(60));
+        profile.failed("modem", "exited with exit status: 1", ms(70));
+
+        // A restart later on doesn't count
+        profile.spawned(&service("power", &["dbus"]), ms(5000));
+        profile.ready("power", ms(5100));
+        profile.failed("modem", "restarted too often", ms(900));
+
+        assert_eq!(
+            profile.phases(),
+            [("configs loaded", Duration::from_millis(5))]
+        );
+        assert_eq!(profile.finished(), Some(Duration::from_millis(450)));
+        assert_eq!(
+            profile.blame(),
+            [
+                ("power", Duration::from_millis(340)),
+                ("dbus", Duration::from_millis(50)),
+                ("leds", Duration::from_millis(50)),
+                ("sensors", Duration::from_millis(40)),
+            ]
+        );
+        assert_eq!(profile.critical_chain(), ["leds", "power", "dbus"]);
+
+        let modem = profile.get("modem").unwrap();
+        assert_eq!(modem.ready, None);
+        assert_eq!(
+            modem.failure,
+            Some((
+                Duration::from_millis(70),
+                "exited with exit status: 1".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn empty_profile_has_no_chain() {
+        let profile = BootProfile::new(Instant::now());
+        assert_eq!(profile.finished(), None);
+        assert!(profile.blame().is_empty());
+        assert!(profile.critical_chain().is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+// ABOUTME: Boot-loop protection: a count of boots that never came up, kept on /data.
+// ABOUTME: Past a few in a row, the phone boots the rescue target instead of the usual one.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+
+/// Holds how many boots in a row have started without the boot target staying up.
+/// Removing it, e.g. over the developer bridge, leaves safe mode on the next boot.
+pub const COUNT_PATH: &str = "/data/boot-count";
+
+/// Boots in a row that may fail before the next one is a safe-mode boot.
+pub const MAX_FAILED_BOOTS: u32 = 3;
+
+/// How long the boot target has to stay up before the boot counts as good.
+pub const STABLE: Duration = Duration::from_secs(120);
+
+/// The count kept at `path`, zero when there is none or it can't be read.
+pub fn read(path: &Path) -> u32 {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| content.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Write `count` to `path` and flush it to the disk, since the next thing to happen
+/// may be the phone resetting.
+fn write(path: &Path, count: u32) -> std::io::Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(format!("{count}\n").as_bytes())?;
+    file.sync_all()
+}
+
+/// Whether a boot with `count` boots not seen through, itself included, is one too many.
+pub fn is_boot_loop(count: u32) -> bool {
+    count > MAX_FAILED_BOOTS
+}
+
+pub struct BootCount {
+    path: PathBuf,
+    safe_mode: bool,
+    /// When the boot target came up, while it has stayed up since.
+    up_since: Option<Instant>,
+    /// Set once the boot has counted as good, or would have in safe mode.
+    settled: bool,
+}
+
+impl BootCount {
+    /// Count this boot at `path`, and go into safe mode if it follows too many boots
+    /// that didn't come up.
+    pub fn start(path: &Path) -> Self {
+        let count = read(path).saturating_add(1);
+        if let Err(e) = write(path, count) {
+            warn!(path = %path.display(), error = %e, "failed to save the boot count");
+        }
+        let safe_mode = is_boot_loop(count);
+        if safe_mode {
+            error!(
+                count,
+                max = MAX_FAILED_BOOTS,
+                "too many boots in a row failed to come up, booting in safe mode"
+            );
+        } else {
+            info!(count, "boot counted");
+        }
+        Self {
+            path: path.to_path_buf(),
+            safe_mode,
+            up_since: None,
+            settled: false,
+        }
+    }
+
+    /// Whether this boot brings up the rescue target in place of the selected one.
+    pub fn safe_mode(&self) -> bool {
+        self.safe_mode
+    }
+
+    /// Note whether the boot target is `up`: every service of it settled and no rescue
+    /// mode. Once it has been up for `STABLE`, the count is cleared, except in safe mode,
+    /// which lasts until the count is removed by hand.
+    pub fn check(&mut self, now: Instant, up: bool) {
+        if self.settled {
+            return;
+        }
+        if !up {
+            self.up_since = None;
+            return;
+        }
+        let since = *self.up_since.get_or_insert(now);
+        if now < since + STABLE {
+            return;
+        }
+        self.settled = true;
+        if self.safe_mode {
+            info!(
+                "safe mode is up; remove {} to boot normally",
+                self.path.display()
+            );
+            return;
+        }
+        match write(&self.path, 0) {
+            Ok(()) => info!("boot target stayed up, boot count cleared"),
+            Err(e) => {
+                error!(path = %self.path.display(), error = %e, "failed to clear the boot count")
+            }
+        }
+    }
+
+    /// When the boot target will have been up long enough to clear the count.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.up_since
+            .filter(|_| !self.settled)
+            .map(|since| since + STABLE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boots_that_stay_up_clear_the_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("boot-count");
+
+        let mut count = BootCount::start(&path);
+        assert!(!count.safe_mode());
+        assert_eq!(read(&path), 1);
+
+        // Going down before it is stable starts the wait over
+        let start = Instant::now();
+        count.check(start, true);
+        count.check(start + STABLE / 2, false);
+        assert_eq!(count.next_deadline(), None);
+        count.check(start + STABLE, true);
+        assert_eq!(count.next_deadline(), Some(start + STABLE * 2));
+        count.check(start + STABLE * 2 - Duration::from_secs(1), true);
+        assert_eq!(read(&path), 1);
+
+        count.check(start + STABLE * 2, true);
+        assert_eq!(read(&path), 0);
+        assert_eq!(count.next_deadline(), None);
+    }
+
+    #[test]
+    fn too_many_failed_boots_mean_safe_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("boot-count");
+
+        for _ in 0..MAX_FAILED_BOOTS {
+            assert!(!BootCount::start(&path).safe_mode());
+        }
+        let mut count = BootCount::start(&path);
+        assert!(count.safe_mode());
+
+        // Safe mode coming up doesn't make the next boot a normal one
+        let start = Instant::now();
+        count.check(start, true);
+        count.check(start + STABLE, true);
+        assert_eq!(read(&path), MAX_FAILED_BOOTS + 1);
+
+        std::fs::write(&path, "garbage").unwrap();
+        assert!(!BootCount::start(&path).safe_mode());
+        assert_eq!(read(&path), 1);
+    }
+}
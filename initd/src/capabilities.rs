@@ -0,0 +1,188 @@
+// ABOUTME: Capability bounding for services that need a few root powers but not all of them.
+// ABOUTME: Drops every capability a service didn't list before exec, keeping the listed ones across the user switch.
+
+use anyhow::{bail, Result};
+
+/// Where the kernel says which capability number is its highest.
+const CAP_LAST_CAP_PATH: &str = "/proc/sys/kernel/cap_last_cap";
+
+/// _LINUX_CAPABILITY_VERSION_3: 64-bit sets, passed as two 32-bit halves.
+const CAPABILITY_VERSION: u32 = 0x2008_0522;
+
+/// Capability names in kernel order, so each one's index is its number.
+const NAMES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// The number of a capability, by name, as in "CAP_NET_ADMIN".
+pub fn number(name: &str) -> Result<u32> {
+    match NAMES.iter().position(|n| *n == name) {
+        Some(n) => Ok(n as u32),
+        None => bail!("unknown capability '{name}'"),
+    }
+}
+
+/// The capabilities a service keeps; every other one is gone for good once it execs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    keep: u64,
+    last_cap: u32,
+}
+
+impl Capabilities {
+    /// Resolve capability names against what the running kernel knows of.
+    pub fn resolve(names: &[String]) -> Result<Self> {
+        let last_cap = std::fs::read_to_string(CAP_LAST_CAP_PATH)
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(NAMES.len() as u32 - 1);
+        Self::from_names(names, last_cap)
+    }
+
+    fn from_names(names: &[String], last_cap: u32) -> Result<Self> {
+        let mut keep = 0;
+        for name in names {
+            keep |= 1 << number(name)?;
+        }
+        Ok(Self { keep, last_cap })
+    }
+
+    fn keeps(&self, cap: u32) -> bool {
+        cap < 64 && self.keep & (1 << cap) != 0
+    }
+
+    /// Drop everything else from the bounding set, and hold on to the permitted set
+    /// through a switch away from root. Runs between fork and exec while still root, so
+    /// it only makes raw syscalls.
+    pub fn bound(&self) -> std::io::Result<()> {
+        for cap in (0..=self.last_cap).filter(|&cap| !self.keeps(cap)) {
+            // SAFETY: prctl with these options takes no pointers.
+            check(unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) })?;
+        }
+        // SAFETY: as above.
+        check(unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1 as libc::c_ulong, 0, 0, 0) })
+    }
+
+    /// Narrow the process to the kept capabilities and raise them as ambient, so they
+    /// survive exec for a service that isn't root. Runs after the user switch.
+    pub fn raise(&self) -> std::io::Result<()> {
+        let header = CapHeader {
+            version: CAPABILITY_VERSION,
+            pid: 0,
+        };
+        let mut data = [CapData::default(); 2];
+        for (half, data) in data.iter_mut().enumerate() {
+            let bits = (self.keep >> (32 * half)) as u32;
+            *data = CapData {
+                effective: bits,
+                permitted: bits,
+                inheritable: bits,
+            };
+        }
+        // SAFETY: capset only reads the header and the two data structs, which outlive it.
+        check(unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } as libc::c_int)?;
+
+        for cap in (0..=self.last_cap).filter(|&cap| self.keeps(cap)) {
+            // SAFETY: prctl with these options takes no pointers.
+            check(unsafe {
+                libc::prctl(
+                    libc::PR_CAP_AMBIENT,
+                    libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong,
+                    cap as libc::c_ulong,
+                    0,
+                    0,
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn check(ret: libc::c_int) -> std::io::Result<()> {
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_resolve_to_kernel_numbers() {
+        assert_eq!(number("CAP_CHOWN").unwrap(), 0);
+        assert_eq!(number("CAP_NET_ADMIN").unwrap(), 12);
+        assert_eq!(number("CAP_SYS_ADMIN").unwrap(), 21);
+        assert_eq!(number("CAP_CHECKPOINT_RESTORE").unwrap(), 40);
+        assert!(number("CAP_FLY").is_err());
+        assert!(number("net_admin").is_err());
+    }
+
+    #[test]
+    fn keeps_only_listed_capabilities() {
+        let names = vec!["CAP_NET_ADMIN".to_string(), "CAP_NET_RAW".to_string()];
+        let caps = Capabilities::from_names(&names, 40).unwrap();
+        assert!(caps.keeps(12));
+        assert!(caps.keeps(13));
+        assert!(!caps.keeps(0));
+        assert!(!caps.keeps(21));
+
+        let none = Capabilities::from_names(&[], 40).unwrap();
+        assert!((0..=40).all(|cap| !none.keeps(cap)));
+    }
+}
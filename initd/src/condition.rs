@@ -0,0 +1,265 @@
+// ABOUTME: Start conditions for services: paths, the kernel command line, and virtualization.
+// ABOUTME: A service whose conditions don't hold is skipped instead of started.
+
+use std::path::Path;
+
+use crate::config::Conditions;
+
+/// The facts conditions are checked against, besides the filesystem.
+pub struct Host {
+    pub cmdline: String,
+    /// The hypervisor init runs under, or `None` on real hardware.
+    pub virtualization: Option<String>,
+}
+
+impl Host {
+    pub fn detect() -> Self {
+        Self {
+            cmdline: std::fs::read_to_string("/proc/cmdline").unwrap_or_default(),
+            virtualization: detect_virtualization(Path::new("/")),
+        }
+    }
+}
+
+/// The first condition that doesn't hold, described for the log, or `None` when the
+/// service may start.
+pub fn unmet(conditions: &Conditions) -> Option<String> {
+    if conditions.is_empty() {
+        return None;
+    }
+    unmet_on(conditions, &Host::detect())
+}
+
+fn unmet_on(conditions: &Conditions, host: &Host) -> Option<String> {
+    let checks = [
+        (
+            "path_exists",
+            &conditions.path_exists,
+            holds(&conditions.path_exists, |path| Path::new(path).exists()),
+        ),
+        (
+            "file_not_empty",
+            &conditions.file_not_empty,
+            holds(&conditions.file_not_empty, |path| {
+                std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() > 0)
+            }),
+        ),
+        (
+            "kernel_cmdline",
+            &conditions.kernel_cmdline,
+            holds(&conditions.kernel_cmdline, |param| {
+                cmdline_has(&host.cmdline, param)
+            }),
+        ),
+        (
+            "virtualization",
+            &conditions.virtualization,
+            holds(&conditions.virtualization, |kind| {
+                virtualization_is(host.virtualization.as_deref(), kind)
+            }),
+        ),
+    ];
+
+    checks
+        .into_iter()
+        .find(|(_, _, holds)| !holds)
+        .map(|(name, entries, _)| format!("{name} = {entries:?}"))
+}
+
+/// Whether any entry of a condition list passes `check`, or a `!` entry fails it. An
+/// empty list always holds.
+fn holds(entries: &[String], check: impl Fn(&str) -> bool) -> bool {
+    entries.is_empty()
+        || entries.iter().any(|entry| match entry.strip_prefix('!') {
+            Some(entry) => !check(entry),
+            None => check(entry),
+        })
+}
+
+/// Whether the command line has `param`: `name=value` matches exactly, a bare `name`
+/// also matches any value.
+fn cmdline_has(cmdline: &str, param: &str) -> bool {
+    cmdline.split_whitespace().any(|p| {
+        p == param || (!param.contains('=') && p.split_once('=').is_some_and(|(n, _)| n == param))
+    })
+}
+
+fn virtualization_is(detected: Option<&str>, kind: &str) -> bool {
+    match (kind, detected) {
+        ("vm", detected) => detected.is_some(),
+        ("none", detected) => detected.is_none(),
+        (kind, Some(detected)) => kind == detected,
+        (_, None) => false,
+    }
+}
+
+/// Strings in the DMI vendor or product name that identify a hypervisor. KVM comes
+/// before QEMU since QEMU with KVM acceleration reports both.
+const DMI_HYPERVISORS: &[(&str, &str)] = &[
+    ("KVM", "kvm"),
+    ("QEMU", "qemu"),
+    ("VMware", "vmware"),
+    ("VirtualBox", "virtualbox"),
+    ("innotek", "virtualbox"),
+    ("Xen", "xen"),
+    ("Microsoft Corporation Virtual Machine", "microsoft"),
+    ("Amazon EC2", "amazon"),
+    ("Google Compute Engine", "google"),
+];
+
+/// Identify the hypervisor from the files under `root`: the Xen hypervisor node, the
+/// device tree of QEMU's virt machine, DMI strings, and the x86 hypervisor CPU flag.
+/// A virtual machine that can't be named is reported as `vm`.
+fn detect_virtualization(root: &Path) -> Option<String> {
+    let read = |path: &str| std::fs::read_to_string(root.join(path)).ok();
+
+    if let Some(kind) = read("sys/hypervisor/type") {
+        let kind = kind.trim();
+        if !kind.is_empty() {
+            return Some(kind.to_string());
+        }
+    }
+
+    // Device tree strings are NUL separated
+    if read("proc/device-tree/compatible")
+        .is_some_and(|c| c.split('\0').any(|c| c == "linux,dummy-virt"))
+    {
+        return Some("qemu".to_string());
+    }
+
+    let dmi = [
+        "sys/class/dmi/id/sys_vendor",
+        "sys/class/dmi/id/product_name",
+    ]
+    .map(|path| read(path).unwrap_or_default().trim().to_string())
+    .join(" ");
+    if let Some((_, kind)) = DMI_HYPERVISORS.iter().find(|(s, _)| dmi.contains(s)) {
+        return Some(kind.to_string());
+    }
+
+    let cpuinfo = read("proc/cpuinfo").unwrap_or_default();
+    let flagged = cpuinfo
+        .lines()
+        .filter(|line| line.starts_with("flags"))
+        .any(|line| line.split_whitespace().any(|flag| flag == "hypervisor"));
+    flagged.then(|| "vm".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(cmdline: &str, virtualization: Option<&str>) -> Host {
+        Host {
+            cmdline: cmdline.to_string(),
+            virtualization: virtualization.map(str::to_string),
+        }
+    }
+
+    fn list(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn empty_conditions_always_hold() {
+        assert_eq!(unmet(&Conditions::default()), None);
+    }
+
+    #[test]
+    fn any_listed_path_is_enough() {
+        let dir = tempfile::tempdir().unwrap();
+        let qmi = dir.path().join("cdc-wdm0");
+        std::fs::write(&qmi, "").unwrap();
+        let tty = dir.path().join("ttyUSB0").display().to_string();
+        let hardware = host("", None);
+
+        let mut conditions = Conditions {
+            path_exists: list(&[&tty]),
+            ..Default::default()
+        };
+        assert!(
+            unmet_on(&conditions, &hardware)
+                .unwrap()
+                .starts_with("path_exists")
+        );
+
+        conditions.path_exists.push(qmi.display().to_string());
+        assert_eq!(unmet_on(&conditions, &hardware), None);
+
+        // Present but empty
+        conditions.file_not_empty = list(&[&qmi.display().to_string()]);
+        assert!(unmet_on(&conditions, &hardware).is_some());
+        std::fs::write(&qmi, "x").unwrap();
+        assert_eq!(unmet_on(&conditions, &hardware), None);
+
+        conditions.path_exists = list(&[&format!("!{tty}")]);
+        assert_eq!(unmet_on(&conditions, &hardware), None);
+    }
+
+    #[test]
+    fn kernel_cmdline_flags_and_values() {
+        let booted = host("console=ttyAMA0 mos.target=charging quiet", None);
+        let check = |entries: &[&str]| {
+            let conditions = Conditions {
+                kernel_cmdline: list(entries),
+                ..Default::default()
+            };
+            unmet_on(&conditions, &booted).is_none()
+        };
+
+        assert!(check(&["quiet"]));
+        assert!(check(&["mos.target"]));
+        assert!(check(&["mos.target=charging"]));
+        assert!(!check(&["mos.target=graphical"]));
+        assert!(!check(&["quie"]));
+        assert!(check(&["!mos.nomodem"]));
+        assert!(!check(&["!quiet"]));
+        assert!(check(&["splash", "quiet"]));
+    }
+
+    #[test]
+    fn virtualization_matches_kind() {
+        assert!(virtualization_is(Some("qemu"), "vm"));
+        assert!(virtualization_is(Some("qemu"), "qemu"));
+        assert!(!virtualization_is(Some("qemu"), "kvm"));
+        assert!(!virtualization_is(Some("qemu"), "none"));
+        assert!(virtualization_is(None, "none"));
+        assert!(!virtualization_is(None, "vm"));
+
+        let conditions = Conditions {
+            virtualization: list(&["!vm"]),
+            ..Default::default()
+        };
+        assert_eq!(unmet_on(&conditions, &host("", None)), None);
+        assert!(unmet_on(&conditions, &host("", Some("kvm"))).is_some());
+    }
+
+    #[test]
+    fn detects_hypervisors_from_system_files() {
+        let root = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = root.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+
+        write("proc/cpuinfo", "processor\t: 0\nflags\t\t: fpu vme sse2\n");
+        assert_eq!(detect_virtualization(root.path()), None);
+
+        write("proc/cpuinfo", "flags\t\t: fpu hypervisor sse2\n");
+        assert_eq!(detect_virtualization(root.path()).as_deref(), Some("vm"));
+
+        write("sys/class/dmi/id/sys_vendor", "QEMU\n");
+        write(
+            "sys/class/dmi/id/product_name",
+            "Standard PC (Q35 + ICH9, 2009)\n",
+        );
+        assert_eq!(detect_virtualization(root.path()).as_deref(), Some("qemu"));
+
+        write("proc/device-tree/compatible", "linux,dummy-virt\0");
+        assert_eq!(detect_virtualization(root.path()).as_deref(), Some("qemu"));
+
+        write("sys/hypervisor/type", "xen\n");
+        assert_eq!(detect_virtualization(root.path()).as_deref(), Some("xen"));
+    }
+}
@@ -1,11 +1,21 @@
 // ABOUTME: Service configuration parsing for the init system.
 // ABOUTME: Reads TOML service files and produces typed ServiceConfig values.
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::Path;
 
+use crate::capabilities;
+use crate::oom;
+
+/// Ends a template's name, as in "getty@", and splits an instance's name, as in
+/// "getty@ttyS0", into the template and the instance argument.
+const TEMPLATE_SEPARATOR: char = '@';
+
+/// Replaced with the instance argument throughout a template.
+const INSTANCE_SPECIFIER: &str = "%i";
+
 #[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum RestartPolicy {
@@ -21,22 +31,298 @@ pub enum ServiceType {
     #[default]
     Simple,
     Oneshot,
+    /// Ready only once the service sends `READY=1` to its NOTIFY_SOCKET.
+    Notify,
+}
+
+/// Where a service's stdout/stderr go.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputMode {
+    /// Captured line by line into the init journal.
+    #[default]
+    Journal,
+    /// Inherited from init, i.e. written straight to the kernel console.
+    Console,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// What init does when a service doesn't become ready within its start timeout.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureAction {
+    /// Keep booting without the service.
+    #[default]
+    Ignore,
+    /// Start a shell on the console so the failure can be investigated.
+    RescueShell,
+    /// Stop everything and reboot.
+    Reboot,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct ServiceConfig {
     pub name: String,
     pub exec: String,
     #[serde(default)]
     pub args: Vec<String>,
+    /// Services this one requires: it starts after them, and fails or stops with them.
     #[serde(default)]
     pub depends_on: Vec<String>,
+    /// Services this one starts after if they are starting too, but runs without.
+    #[serde(default)]
+    pub wants: Vec<String>,
+    /// Other names `depends_on` and boot targets may use for the service, such as
+    /// "serial-console". One name or a list.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub alias: Vec<String>,
+    /// Services stopped before this one starts, such as the boot splash, which has to
+    /// let go of the display before the compositor can take it.
+    #[serde(default)]
+    pub conflicts: Vec<String>,
     #[serde(default)]
     pub restart: RestartPolicy,
     #[serde(default)]
     pub service_type: ServiceType,
+    /// Ready once this path exists rather than as soon as it's spawned, for daemons
+    /// that can't notify, such as dbus-daemon creating its socket. Removed before each
+    /// start, so one left over from an earlier run doesn't count.
+    #[serde(default)]
+    pub ready_path: Option<String>,
     #[serde(default)]
     pub environment: HashMap<String, String>,
+    /// File of KEY=VALUE lines read at each start, overriding `environment`. A leading
+    /// `-` makes it optional. Variables from either can be used as `$VAR` or `${VAR}` in
+    /// `exec` and `args`; write `$$` for a literal `$`.
+    #[serde(default)]
+    pub environment_file: Option<String>,
+    #[serde(default)]
+    pub output: OutputMode,
+    /// Name of a seccomp profile from /etc/mos/seccomp to apply before exec.
+    #[serde(default)]
+    pub seccomp: Option<String>,
+    /// Account to run as, by name or uid. Services run as root when unset.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Primary group, by name or gid. Defaults to the user's login group.
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Well-known names the service owns on the system bus, such as "org.mobileos.Audio".
+    /// Only its `user` may own them; the bus refuses any name no service declares. One
+    /// name or a list.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub bus_name: Vec<String>,
+    /// Seconds a notify service may take to report ready, or a oneshot service to
+    /// finish, before it is killed.
+    #[serde(default)]
+    pub start_timeout_sec: Option<u64>,
+    /// Escalation when the service misses its start timeout.
+    #[serde(default)]
+    pub on_failure: FailureAction,
+    /// Seconds a notify service may go without sending WATCHDOG=1 once it is ready,
+    /// passed to it in WATCHDOG_USEC. A service that misses it is hung, and is killed
+    /// with SIGABRT so it dumps core and restarts as `restart` says.
+    #[serde(default)]
+    pub watchdog_sec: Option<u64>,
+    /// Rather than killing the service when it hangs, stop petting the hardware
+    /// watchdog so the whole phone resets. For services such as the compositor whose
+    /// hang usually means the hardware under them is wedged.
+    #[serde(default)]
+    pub watchdog_reset: bool,
+    /// Periodic probe that the service still does its job, for daemons that can wedge
+    /// without exiting or missing a watchdog ping.
+    #[serde(default)]
+    pub health: Option<HealthConfig>,
+    /// Milliseconds to wait before the first restart; doubles with each consecutive one.
+    #[serde(default)]
+    pub restart_delay_ms: Option<u64>,
+    /// Window for `restart_burst`, in seconds. A run lasting at least this long counts
+    /// as stable and resets the backoff.
+    #[serde(default)]
+    pub restart_window_sec: Option<u64>,
+    /// Restarts allowed within `restart_window_sec` before init gives up on the service.
+    #[serde(default)]
+    pub restart_burst: Option<u32>,
+    /// Shell command run before the service is sent SIGTERM, with its pid in MAINPID.
+    #[serde(default)]
+    pub exec_stop: Option<String>,
+    /// Seconds to wait for `exec_stop`, and for the service to exit after SIGTERM,
+    /// before resorting to SIGKILL. While the hardware watchdog is armed, it is cut
+    /// short when stops would otherwise keep init from petting it.
+    #[serde(default)]
+    pub stop_timeout_sec: Option<u64>,
+    /// Run the service on a schedule instead of at boot.
+    #[serde(default)]
+    pub timer: Option<TimerConfig>,
+    /// Checked each time the service would start; if they don't hold, it is skipped.
+    #[serde(default)]
+    pub conditions: Conditions,
+    /// The phone is unusable without this service: if it fails for good, init stops
+    /// the UI and drops into rescue mode.
+    #[serde(default)]
+    pub critical: bool,
+    /// Mount points from /etc/fstab, e.g. "/data", the service can't run without. It
+    /// fails to start if they aren't mounted. One path or a list.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub requires_mount: Vec<String>,
+    /// Give the service an empty /tmp of its own, gone when it exits.
+    #[serde(default)]
+    pub private_tmp: bool,
+    /// Hide /home, /root and /run/user from the service.
+    #[serde(default)]
+    pub protect_home: bool,
+    /// Keep the service and anything it runs from gaining privileges through exec, as
+    /// with setuid binaries.
+    #[serde(default)]
+    pub no_new_privileges: bool,
+    /// Absolute paths the service sees read-only, such as "/etc" or "/data/media".
+    #[serde(default)]
+    pub readonly_paths: Vec<String>,
+    /// Capabilities the service keeps, such as "CAP_NET_ADMIN"; all others are dropped
+    /// before exec. Kept across the switch to `user`. Unset leaves them as they are.
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
+    /// How willing the kernel OOM killer is to pick the service, from -1000 (never) to
+    /// 1000. Unset keeps init's, except for expendable services.
+    #[serde(default)]
+    pub oom_score_adj: Option<i32>,
+    /// A background app init may kill to relieve sustained memory pressure, before the
+    /// kernel OOM killer has to pick something.
+    #[serde(default)]
+    pub expendable: bool,
+    /// Stop the service with SIGSTOP just before the phone suspends and continue it once
+    /// it resumes, so a background app doesn't wake into the middle of work it was doing.
+    #[serde(default)]
+    pub freeze_on_sleep: bool,
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+/// Requirements on the system for a service to start. A list holds if any of its
+/// entries does, and every list that is set must hold. An entry starting with `!`
+/// holds when its check fails.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct Conditions {
+    /// Paths, e.g. device nodes, that exist.
+    #[serde(default)]
+    pub path_exists: Vec<String>,
+    /// Files that exist and aren't empty.
+    #[serde(default)]
+    pub file_not_empty: Vec<String>,
+    /// Kernel command line parameters: a bare `name` matches with or without a value,
+    /// `name=value` only that value.
+    #[serde(default)]
+    pub kernel_cmdline: Vec<String>,
+    /// `vm` for any virtual machine, `none` for real hardware, or a hypervisor such
+    /// as `qemu`, `kvm`, or `xen`.
+    #[serde(default)]
+    pub virtualization: Vec<String>,
+}
+
+impl Conditions {
+    pub fn is_empty(&self) -> bool {
+        self.path_exists.is_empty()
+            && self.file_not_empty.is_empty()
+            && self.kernel_cmdline.is_empty()
+            && self.virtualization.is_empty()
+    }
+}
+
+/// How to tell that a running service is healthy. A probe runs every `interval_sec`
+/// once the service is ready; after `failure_threshold` failures in a row the service
+/// is killed with SIGABRT, even though its process is still alive, and restarts as
+/// `restart` says. Exactly one of `exec` and `dbus_name` is set.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct HealthConfig {
+    /// Shell command run as the service's user, with its environment; the service is
+    /// healthy if it exits 0.
+    #[serde(default)]
+    pub exec: Option<String>,
+    /// Bus name the service owns, pinged with org.freedesktop.DBus.Peer.Ping on the
+    /// system bus. A daemon that still holds its name but no longer answers fails it.
+    #[serde(default)]
+    pub dbus_name: Option<String>,
+    #[serde(default = "default_health_interval")]
+    pub interval_sec: u64,
+    /// Seconds a probe may take before it is killed and counted as failed.
+    #[serde(default = "default_health_timeout")]
+    pub timeout_sec: u64,
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+}
+
+fn default_health_interval() -> u64 {
+    30
+}
+
+fn default_health_timeout() -> u64 {
+    5
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+/// When a timer starts its service. A timer fires at whichever of its triggers comes
+/// first; a service that is still running when it fires again is left alone.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct TimerConfig {
+    /// Seconds after init started to run the service for the first time.
+    #[serde(default)]
+    pub on_boot_sec: Option<u64>,
+    /// Seconds from one run starting to the next.
+    #[serde(default)]
+    pub interval_sec: Option<u64>,
+    /// Wall-clock schedule, in UTC.
+    #[serde(default)]
+    pub calendar: Option<Calendar>,
+}
+
+/// A wall-clock schedule: `hourly`, `daily`, `weekly` (Mondays), or `HH:MM` for a
+/// time of day. Times are UTC since the RTC and NTP both keep UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Calendar {
+    Hourly,
+    /// Seconds past midnight.
+    Daily(u64),
+    Weekly,
+}
+
+impl TryFrom<String> for Calendar {
+    type Error = String;
+
+    fn try_from(spec: String) -> std::result::Result<Self, Self::Error> {
+        match spec.as_str() {
+            "hourly" => return Ok(Calendar::Hourly),
+            "daily" => return Ok(Calendar::Daily(0)),
+            "weekly" => return Ok(Calendar::Weekly),
+            _ => {}
+        }
+        let time = spec
+            .split_once(':')
+            .and_then(|(h, m)| Some((h.parse::<u64>().ok()?, m.parse::<u64>().ok()?)))
+            .filter(|&(h, m)| h < 24 && m < 60);
+        match time {
+            Some((h, m)) => Ok(Calendar::Daily(h * 3600 + m * 60)),
+            None => Err(format!(
+                "invalid calendar '{spec}': expected hourly, daily, weekly, or HH:MM"
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -47,43 +333,204 @@ struct ServiceFile {
 pub fn parse_service(toml_str: &str) -> Result<ServiceConfig> {
     let file: ServiceFile = toml::from_str(toml_str)
         .context("failed to parse service config")?;
+    if let Some(timer) = &file.service.timer
+        && timer.on_boot_sec.is_none()
+        && timer.interval_sec.is_none()
+        && timer.calendar.is_none()
+    {
+        bail!("timer of service '{}' never fires", file.service.name);
+    }
+    let service = &file.service;
+    if let Some(health) = &service.health {
+        if health.exec.is_some() == health.dbus_name.is_some() {
+            bail!(
+                "health check of service '{}' needs one of exec and dbus_name",
+                service.name
+            );
+        }
+        if health.interval_sec == 0 || health.failure_threshold == 0 {
+            bail!(
+                "health check of service '{}' needs a non-zero interval_sec and failure_threshold",
+                service.name
+            );
+        }
+    }
+    if let Some(name) = service.bus_name.iter().find(|n| !is_bus_name(n)) {
+        bail!("bus name '{name}' of service '{}' is invalid", service.name);
+    }
+    if let Some(path) = service.readonly_paths.iter().find(|p| !p.starts_with('/')) {
+        bail!(
+            "read-only path '{path}' of service '{}' is not absolute",
+            service.name
+        );
+    }
+    for name in service.capabilities.iter().flatten() {
+        capabilities::number(name).with_context(|| format!("service '{}'", service.name))?;
+    }
+    if let Some(adj) = service.oom_score_adj
+        && !(oom::MIN_SCORE_ADJ..=oom::MAX_SCORE_ADJ).contains(&adj)
+    {
+        bail!(
+            "oom_score_adj {adj} of service '{}' is outside -1000..=1000",
+            service.name
+        );
+    }
     Ok(file.service)
 }
 
-pub fn load_services_from_dir(dir: &Path) -> Result<Vec<ServiceConfig>> {
-    let mut services = Vec::new();
+/// Whether `name` is a well-known D-Bus name: dot-separated elements of letters, digits,
+/// `_` and `-`, at least two of them, none starting with a digit.
+fn is_bus_name(name: &str) -> bool {
+    let elements: Vec<&str> = name.split('.').collect();
+    name.len() <= 255
+        && elements.len() >= 2
+        && elements.iter().all(|e| {
+            e.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                && e.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        })
+}
 
-    if !dir.exists() {
-        return Ok(services);
+/// The instance of a template for `arg`: `source` is the template's file, named e.g.
+/// "getty@", and the instance is "getty@ttyS0", with every `%i` in it replaced by `arg`.
+pub fn instantiate(source: &str, arg: &str) -> Result<ServiceConfig> {
+    if arg.is_empty()
+        || !arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+    {
+        bail!("invalid instance argument '{arg}'");
+    }
+    let mut service = parse_service(&source.replace(INSTANCE_SPECIFIER, arg))?;
+    if !service.name.ends_with(TEMPLATE_SEPARATOR) {
+        bail!("service '{}' is not a template", service.name);
     }
+    service.name.push_str(arg);
+    Ok(service)
+}
 
-    let mut entries: Vec<_> = std::fs::read_dir(dir)
-        .with_context(|| format!("failed to read service directory: {}", dir.display()))?
-        .filter_map(|e| e.ok())
-        .filter(|e| {
-            e.path()
-                .extension()
-                .is_some_and(|ext| ext == "toml")
-        })
+/// Instantiate the templates that dependencies name, e.g. "getty@ttyS0" for a template
+/// "getty@", including those the new instances depend on in turn.
+fn instantiate_dependencies(
+    services: &mut Vec<ServiceConfig>,
+    templates: &HashMap<String, String>,
+) -> Result<()> {
+    let mut known: HashSet<String> = services
+        .iter()
+        .flat_map(|s| std::iter::once(&s.name).chain(&s.alias))
+        .cloned()
         .collect();
+    let mut next = 0;
+    while next < services.len() {
+        let wanted: Vec<String> = services[next]
+            .depends_on
+            .iter()
+            .chain(&services[next].wants)
+            .filter(|dep| !known.contains(*dep))
+            .cloned()
+            .collect();
+        for dep in wanted {
+            let Some((template, arg)) = dep.split_once(TEMPLATE_SEPARATOR) else {
+                continue;
+            };
+            let Some(source) = templates.get(&format!("{template}{TEMPLATE_SEPARATOR}")) else {
+                continue;
+            };
+            let instance = instantiate(source, arg)
+                .with_context(|| format!("failed to instantiate '{dep}'"))?;
+            known.extend(
+                std::iter::once(&instance.name)
+                    .chain(&instance.alias)
+                    .cloned(),
+            );
+            services.push(instance);
+        }
+        next += 1;
+    }
+    Ok(())
+}
 
-    entries.sort_by_key(|e| e.file_name());
+/// Rewrite dependencies on aliases to the names of the services they stand for. An alias
+/// can't be another service's name, or stand for two services.
+fn resolve_aliases(services: &mut [ServiceConfig]) -> Result<()> {
+    let names: HashSet<String> = services.iter().map(|s| s.name.clone()).collect();
+    let mut aliases: HashMap<String, String> = HashMap::new();
+    for service in services.iter() {
+        for alias in &service.alias {
+            if names.contains(alias) {
+                bail!(
+                    "alias '{alias}' of service '{}' is already a service name",
+                    service.name
+                );
+            }
+            if let Some(other) = aliases.insert(alias.clone(), service.name.clone()) {
+                bail!(
+                    "alias '{alias}' is used by both '{other}' and '{}'",
+                    service.name
+                );
+            }
+        }
+    }
+    for service in services.iter_mut() {
+        for dep in service.depends_on.iter_mut().chain(&mut service.wants) {
+            if let Some(name) = aliases.get(dep) {
+                *dep = name.clone();
+            }
+        }
+    }
+    Ok(())
+}
 
-    for entry in entries {
-        let path = entry.path();
+/// Load every service in `dirs`, in file name order. A file in a later directory
+/// replaces the one of the same name in an earlier directory.
+///
+/// A template, such as "getty@" from `getty@.toml`, doesn't run itself; it runs as the
+/// instances that dependencies name, and those named by files such as
+/// `getty@ttyS0.toml`, usually a symlink to the template. Dependencies on aliases come
+/// back as the services' own names.
+pub fn load_services_from_dirs(dirs: &[&Path]) -> Result<Vec<ServiceConfig>> {
+    let mut services = Vec::new();
+    let mut templates = HashMap::new();
+
+    let mut files = BTreeMap::new();
+    for dir in dirs.iter().filter(|dir| dir.exists()) {
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("failed to read service directory: {}", dir.display()))?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "toml"));
+        files.extend(entries.map(|e| (e.file_name(), e.path())));
+    }
+
+    for path in files.into_values() {
         let content = std::fs::read_to_string(&path)
             .with_context(|| format!("failed to read {}", path.display()))?;
         let config = parse_service(&content)
             .with_context(|| format!("failed to parse {}", path.display()))?;
-        services.push(config);
+        if !config.name.ends_with(TEMPLATE_SEPARATOR) {
+            services.push(config);
+            continue;
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        match stem.split_once(TEMPLATE_SEPARATOR) {
+            Some((_, arg)) if !arg.is_empty() => services.push(
+                instantiate(&content, arg)
+                    .with_context(|| format!("failed to instantiate {}", path.display()))?,
+            ),
+            _ => {
+                templates.insert(config.name, content);
+            }
+        }
     }
 
+    instantiate_dependencies(&mut services, &templates)?;
+    resolve_aliases(&mut services)?;
     Ok(services)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn parse_minimal_service() {
@@ -101,6 +548,29 @@ mod tests {
         assert_eq!(svc.restart, RestartPolicy::OnFailure);
         assert_eq!(svc.service_type, ServiceType::Simple);
         assert!(svc.environment.is_empty());
+        assert!(svc.environment_file.is_none());
+        assert_eq!(svc.output, OutputMode::Journal);
+        assert!(svc.seccomp.is_none());
+        assert!(svc.user.is_none());
+        assert!(svc.group.is_none());
+        assert!(svc.start_timeout_sec.is_none());
+        assert_eq!(svc.on_failure, FailureAction::Ignore);
+        assert!(svc.restart_delay_ms.is_none());
+        assert!(svc.restart_window_sec.is_none());
+        assert!(svc.restart_burst.is_none());
+        assert!(!svc.critical);
+        assert!(svc.requires_mount.is_empty());
+        assert!(svc.exec_stop.is_none());
+        assert!(svc.stop_timeout_sec.is_none());
+        assert!(!svc.private_tmp);
+        assert!(!svc.protect_home);
+        assert!(!svc.no_new_privileges);
+        assert!(svc.readonly_paths.is_empty());
+        assert!(svc.capabilities.is_none());
+        assert!(svc.oom_score_adj.is_none());
+        assert!(!svc.expendable);
+        assert!(!svc.freeze_on_sleep);
+        assert!(svc.timer.is_none());
     }
 
     #[test]
@@ -111,8 +581,12 @@ mod tests {
             exec = "/usr/bin/mos-compositor"
             args = ["--backend", "drm"]
             depends_on = ["udevd", "dbus"]
+            conflicts = ["splash"]
             restart = "always"
             service_type = "simple"
+            seccomp = "media"
+            user = "compositor"
+            group = "video"
 
             [service.environment]
             XDG_RUNTIME_DIR = "/run"
@@ -124,9 +598,13 @@ mod tests {
         assert_eq!(svc.exec, "/usr/bin/mos-compositor");
         assert_eq!(svc.args, vec!["--backend", "drm"]);
         assert_eq!(svc.depends_on, vec!["udevd", "dbus"]);
+        assert_eq!(svc.conflicts, vec!["splash"]);
         assert_eq!(svc.restart, RestartPolicy::Always);
         assert_eq!(svc.service_type, ServiceType::Simple);
         assert_eq!(svc.environment.get("XDG_RUNTIME_DIR").unwrap(), "/run");
+        assert_eq!(svc.seccomp.as_deref(), Some("media"));
+        assert_eq!(svc.user.as_deref(), Some("compositor"));
+        assert_eq!(svc.group.as_deref(), Some("video"));
     }
 
     #[test]
@@ -145,6 +623,235 @@ mod tests {
         assert_eq!(svc.restart, RestartPolicy::Never);
     }
 
+    #[test]
+    fn parse_notify_service() {
+        let toml = r#"
+            [service]
+            name = "power"
+            exec = "/usr/bin/mos-power"
+            service_type = "notify"
+        "#;
+
+        let svc = parse_service(toml).unwrap();
+        assert_eq!(svc.service_type, ServiceType::Notify);
+    }
+
+    #[test]
+    fn parse_start_timeout_and_failure_action() {
+        let toml = r#"
+            [service]
+            name = "compositor"
+            exec = "/usr/bin/mos-compositor"
+            service_type = "notify"
+            start_timeout_sec = 15
+            on_failure = "rescue-shell"
+        "#;
+
+        let svc = parse_service(toml).unwrap();
+        assert_eq!(svc.start_timeout_sec, Some(15));
+        assert_eq!(svc.on_failure, FailureAction::RescueShell);
+
+        let reboot = toml.replace("rescue-shell", "reboot");
+        assert_eq!(parse_service(&reboot).unwrap().on_failure, FailureAction::Reboot);
+        let bogus = toml.replace("rescue-shell", "panic");
+        assert!(parse_service(&bogus).is_err());
+    }
+
+    #[test]
+    fn parse_restart_backoff() {
+        let toml = r#"
+            [service]
+            name = "compositor"
+            exec = "/usr/bin/mos-compositor"
+            restart = "always"
+            restart_delay_ms = 250
+            restart_window_sec = 30
+            restart_burst = 8
+        "#;
+
+        let svc = parse_service(toml).unwrap();
+        assert_eq!(svc.restart_delay_ms, Some(250));
+        assert_eq!(svc.restart_window_sec, Some(30));
+        assert_eq!(svc.restart_burst, Some(8));
+    }
+
+    #[test]
+    fn parse_stop_settings() {
+        let toml = r#"
+            [service]
+            name = "messages"
+            exec = "/usr/bin/mos-messages"
+            exec_stop = "/usr/bin/mos-messages --flush"
+            stop_timeout_sec = 5
+        "#;
+
+        let svc = parse_service(toml).unwrap();
+        assert_eq!(svc.exec_stop.as_deref(), Some("/usr/bin/mos-messages --flush"));
+        assert_eq!(svc.stop_timeout_sec, Some(5));
+    }
+
+    #[test]
+    fn parse_environment_file() {
+        let toml = r#"
+            [service]
+            name = "compositor"
+            exec = "/usr/bin/mos-compositor"
+            args = ["--output", "${PANEL}"]
+            environment_file = "-/etc/mos/env/compositor.env"
+        "#;
+
+        let svc = parse_service(toml).unwrap();
+        assert_eq!(
+            svc.environment_file.as_deref(),
+            Some("-/etc/mos/env/compositor.env")
+        );
+    }
+
+    #[test]
+    fn parse_timer() {
+        let toml = r#"
+            [service]
+            name = "logrotate"
+            exec = "/usr/bin/mos-logrotate"
+            service_type = "oneshot"
+
+            [service.timer]
+            on_boot_sec = 300
+            interval_sec = 3600
+            calendar = "03:30"
+        "#;
+
+        let timer = parse_service(toml).unwrap().timer.unwrap();
+        assert_eq!(timer.on_boot_sec, Some(300));
+        assert_eq!(timer.interval_sec, Some(3600));
+        assert_eq!(timer.calendar, Some(Calendar::Daily(3 * 3600 + 30 * 60)));
+    }
+
+    #[test]
+    fn parse_calendar_specs() {
+        let parse = |spec: &str| Calendar::try_from(spec.to_string());
+        assert_eq!(parse("hourly"), Ok(Calendar::Hourly));
+        assert_eq!(parse("daily"), Ok(Calendar::Daily(0)));
+        assert_eq!(parse("weekly"), Ok(Calendar::Weekly));
+        assert_eq!(parse("23:59"), Ok(Calendar::Daily(23 * 3600 + 59 * 60)));
+        assert!(parse("24:00").is_err());
+        assert!(parse("12:60").is_err());
+        assert!(parse("noon").is_err());
+        assert!(parse("12").is_err());
+    }
+
+    #[test]
+    fn timer_without_triggers_is_rejected() {
+        let toml = r#"
+            [service]
+            name = "logrotate"
+            exec = "/usr/bin/mos-logrotate"
+
+            [service.timer]
+        "#;
+
+        assert!(parse_service(toml).is_err());
+    }
+
+    #[test]
+    fn parse_conditions() {
+        let toml = r#"
+            [service]
+            name = "modem"
+            exec = "/usr/bin/mos-modem"
+
+            [service.conditions]
+            path_exists = ["/dev/ttyUSB0", "/dev/cdc-wdm0"]
+            kernel_cmdline = ["!mos.nomodem"]
+        "#;
+
+        let conditions = parse_service(toml).unwrap().conditions;
+        assert_eq!(conditions.path_exists, ["/dev/ttyUSB0", "/dev/cdc-wdm0"]);
+        assert_eq!(conditions.kernel_cmdline, ["!mos.nomodem"]);
+        assert!(conditions.virtualization.is_empty());
+        assert!(!conditions.is_empty());
+
+        let minimal = "[service]\nname = \"x\"\nexec = \"/bin/true\"\n";
+        assert!(parse_service(minimal).unwrap().conditions.is_empty());
+    }
+
+    #[test]
+    fn parse_console_output() {
+        let toml = r#"
+            [service]
+            name = "console"
+            exec = "/bin/sh"
+            output = "console"
+        "#;
+
+        let svc = parse_service(toml).unwrap();
+        assert_eq!(svc.output, OutputMode::Console);
+    }
+
+    #[test]
+    fn parse_sandbox_options() {
+        let toml = r#"
+            [service]
+            name = "audio"
+            exec = "/usr/bin/mos-audio"
+            private_tmp = true
+            protect_home = true
+            no_new_privileges = true
+            readonly_paths = ["/etc", "/usr"]
+        "#;
+
+        let svc = parse_service(toml).unwrap();
+        assert!(svc.private_tmp);
+        assert!(svc.protect_home);
+        assert!(svc.no_new_privileges);
+        assert_eq!(svc.readonly_paths, ["/etc", "/usr"]);
+
+        let relative = r#"
+            [service]
+            name = "audio"
+            exec = "/usr/bin/mos-audio"
+            readonly_paths = ["etc"]
+        "#;
+        assert!(parse_service(relative).is_err());
+    }
+
+    #[test]
+    fn parse_capabilities() {
+        let toml = r#"
+            [service]
+            name = "network"
+            exec = "/usr/bin/mos-network"
+            capabilities = ["CAP_NET_ADMIN", "CAP_NET_RAW"]
+        "#;
+        let svc = parse_service(toml).unwrap();
+        assert_eq!(
+            svc.capabilities.as_deref(),
+            Some(&["CAP_NET_ADMIN".to_string(), "CAP_NET_RAW".to_string()][..])
+        );
+
+        let unknown = toml.replace("CAP_NET_RAW", "CAP_TELEPORT");
+        let err = parse_service(&unknown).unwrap_err();
+        assert!(format!("{err:#}").contains("unknown capability 'CAP_TELEPORT'"));
+    }
+
+    #[test]
+    fn parse_oom_policy() {
+        let toml = r#"
+            [service]
+            name = "gallery"
+            exec = "/usr/bin/mos-gallery"
+            oom_score_adj = 500
+            expendable = true
+        "#;
+        let svc = parse_service(toml).unwrap();
+        assert_eq!(svc.oom_score_adj, Some(500));
+        assert!(svc.expendable);
+
+        let out_of_range = toml.replace("500", "1001");
+        let err = parse_service(&out_of_range).unwrap_err();
+        assert!(err.to_string().contains("outside -1000..=1000"));
+    }
+
     #[test]
     fn parse_invalid_toml_fails() {
         let toml = "this is not valid toml {{{{";
@@ -188,15 +895,348 @@ mod tests {
         // Non-toml file should be ignored
         std::fs::write(dir.path().join("readme.txt"), "ignore me").unwrap();
 
-        let services = load_services_from_dir(dir.path()).unwrap();
+        let services = load_services_from_dirs(&[dir.path()]).unwrap();
         assert_eq!(services.len(), 2);
         assert_eq!(services[0].name, "console");
         assert_eq!(services[1].name, "logger");
     }
 
+    const GETTY: &str = r#"
+        [service]
+        name = "getty@"
+        exec = "/sbin/getty"
+        args = ["-L", "115200", "%i"]
+        depends_on = ["console"]
+        alias = "getty-%i"
+    "#;
+
+    #[test]
+    fn templates_run_as_their_instances() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("01-console.toml"),
+            r#"
+                [service]
+                name = "console"
+                exec = "/bin/sh"
+            "#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("02-getty@.toml"), GETTY).unwrap();
+        std::os::unix::fs::symlink("02-getty@.toml", dir.path().join("03-getty@ttyS0.toml"))
+            .unwrap();
+        std::fs::write(
+            dir.path().join("04-modem.toml"),
+            r#"
+                [service]
+                name = "modem"
+                exec = "/usr/bin/mos-modem"
+                depends_on = ["getty@ttyUSB2"]
+            "#,
+        )
+        .unwrap();
+
+        let services = load_services_from_dirs(&[dir.path()]).unwrap();
+        let names: Vec<&str> = services.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["console", "getty@ttyS0", "modem", "getty@ttyUSB2"]);
+        assert_eq!(services[1].args, ["-L", "115200", "ttyS0"]);
+        assert_eq!(services[1].alias, ["getty-ttyS0"]);
+        assert_eq!(services[3].args, ["-L", "115200", "ttyUSB2"]);
+    }
+
+    #[test]
+    fn instance_arguments_are_checked() {
+        assert_eq!(instantiate(GETTY, "ttyMSM0").unwrap().name, "getty@ttyMSM0");
+        assert!(instantiate(GETTY, "").is_err());
+        assert!(instantiate(GETTY, "tty\"").is_err());
+        assert!(instantiate(GETTY, "../tty").is_err());
+
+        let plain = "[service]\nname = \"console\"\nexec = \"/bin/sh\"";
+        assert!(instantiate(plain, "ttyS0").is_err());
+    }
+
+    #[test]
+    fn dependencies_on_aliases_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("01-console.toml"),
+            r#"
+                [service]
+                name = "console"
+                exec = "/bin/sh"
+                alias = ["serial-console", "tty"]
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("02-logger.toml"),
+            r#"
+                [service]
+                name = "logger"
+                exec = "/usr/bin/logger"
+                depends_on = ["serial-console"]
+                wants = ["tty"]
+            "#,
+        )
+        .unwrap();
+
+        let services = load_services_from_dirs(&[dir.path()]).unwrap();
+        assert_eq!(services[1].depends_on, ["console"]);
+        assert_eq!(services[1].wants, ["console"]);
+
+        // An alias may not shadow another service
+        std::fs::write(
+            dir.path().join("03-tty.toml"),
+            r#"
+                [service]
+                name = "tty"
+                exec = "/bin/sh"
+            "#,
+        )
+        .unwrap();
+        assert!(load_services_from_dirs(&[dir.path()]).is_err());
+    }
+
     #[test]
     fn load_from_nonexistent_dir_returns_empty() {
-        let services = load_services_from_dir(Path::new("/nonexistent/path")).unwrap();
+        let services = load_services_from_dirs(&[Path::new("/nonexistent/path")]).unwrap();
         assert!(services.is_empty());
     }
+
+    #[test]
+    fn later_directories_override_earlier_ones() {
+        let etc = tempfile::tempdir().unwrap();
+        let run = tempfile::tempdir().unwrap();
+        let service =
+            |name: &str, exec: &str| format!("[service]\nname = \"{name}\"\nexec = \"{exec}\"\n");
+        let write = |dir: &tempfile::TempDir, file: &str, content: String| {
+            std::fs::write(dir.path().join(file), content).unwrap();
+        };
+        write(&etc, "10-power.toml", service("power", "/usr/bin/mos-power"));
+        write(&etc, "11-audio.toml", service("audio", "/usr/bin/mos-audio"));
+        write(&run, "11-audio.toml", service("audio", "/tmp/mos-audio"));
+        write(&run, "05-debug.toml", service("debug", "/bin/sh"));
+
+        let services = load_services_from_dirs(&[etc.path(), run.path()]).unwrap();
+        let loaded: Vec<(&str, &str)> = services
+            .iter()
+            .map(|s| (s.name.as_str(), s.exec.as_str()))
+            .collect();
+        assert_eq!(
+            loaded,
+            [
+                ("debug", "/bin/sh"),
+                ("power", "/usr/bin/mos-power"),
+                ("audio", "/tmp/mos-audio"),
+            ]
+        );
+    }
+
+    /// A config using every field, as a starting point for mutation.
+    const FULL_SERVICE: &str = r#"
+[service]
+name = "network"
+exec = "/usr/bin/mos-network"
+args = ["--verbose"]
+depends_on = ["dbus"]
+wants = ["modem"]
+alias = ["connman"]
+conflicts = ["splash"]
+restart = "always"
+service_type = "notify"
+ready_path = "/run/network.sock"
+output = "console"
+seccomp = "service"
+user = "network"
+group = "network"
+bus_name = "org.mobileos.Network"
+start_timeout_sec = 30
+on_failure = "rescue-shell"
+watchdog_sec = 10
+watchdog_reset = true
+restart_delay_ms = 100
+restart_window_sec = 60
+restart_burst = 5
+exec_stop = "/usr/bin/mos-network --save"
+stop_timeout_sec = 5
+environment_file = "-/etc/mos/env/network.env"
+critical = true
+requires_mount = "/data"
+private_tmp = true
+protect_home = true
+no_new_privileges = true
+readonly_paths = ["/etc"]
+capabilities = ["CAP_NET_ADMIN"]
+oom_score_adj = -500
+expendable = true
+freeze_on_sleep = true
+
+[service.environment]
+RUST_LOG = "info"
+
+[service.timer]
+on_boot_sec = 60
+interval_sec = 3600
+calendar = "daily"
+
+[service.health]
+dbus_name = "org.mobileos.Network"
+interval_sec = 30
+timeout_sec = 5
+failure_threshold = 3
+
+[service.conditions]
+path_exists = ["/sys/class/net/wlan0"]
+file_not_empty = ["/etc/mos/network.toml"]
+kernel_cmdline = ["!mos.nonetwork"]
+virtualization = ["none"]
+"#;
+
+    #[test]
+    fn full_service_parses() {
+        let svc = parse_service(FULL_SERVICE).unwrap();
+        assert_eq!(svc.name, "network");
+        assert_eq!(svc.stop_timeout_sec, Some(5));
+        assert!(svc.critical);
+        assert_eq!(svc.requires_mount, ["/data"]);
+        assert_eq!(svc.bus_name, ["org.mobileos.Network"]);
+        assert!(svc.freeze_on_sleep);
+        let health = svc.health.unwrap();
+        assert_eq!(health.dbus_name.as_deref(), Some("org.mobileos.Network"));
+        assert_eq!(health.failure_threshold, 3);
+    }
+
+    #[test]
+    fn health_check_needs_exactly_one_probe() {
+        let base = "[service]\nname = \"network\"\nexec = \"/usr/bin/mos-network\"\n";
+        let health = parse_service(&format!("{base}[service.health]\nexec = \"true\""))
+            .unwrap()
+            .health
+            .unwrap();
+        assert_eq!(health.interval_sec, 30);
+        assert_eq!(health.timeout_sec, 5);
+
+        assert!(parse_service(&format!("{base}[service.health]\ninterval_sec = 10")).is_err());
+        assert!(
+            parse_service(&format!(
+                "{base}[service.health]\nexec = \"true\"\ndbus_name = \"org.mobileos.Network\""
+            ))
+            .is_err()
+        );
+        assert!(
+            parse_service(&format!(
+                "{base}[service.health]\nexec = \"true\"\nfailure_threshold = 0"
+            ))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn bus_names_must_be_well_formed() {
+        let base = "[service]\nname = \"audio\"\nexec = \"/usr/bin/mos-audio\"\n";
+        let svc = parse_service(&format!(
+            "{base}bus_name = [\"org.mobileos.Audio\", \"org.mobileos.Mixer\"]"
+        ))
+        .unwrap();
+        assert_eq!(svc.bus_name, ["org.mobileos.Audio", "org.mobileos.Mixer"]);
+
+        for bad in ["audio", "org..Audio", "org.2audio", "org.mobileos.Audio\\\"/>"] {
+            assert!(parse_service(&format!("{base}bus_name = \"{bad}\"")).is_err(), "{bad}");
+        }
+    }
+
+    const KEYS: &[&str] = &[
+        "name",
+        "exec",
+        "args",
+        "depends_on",
+        "wants",
+        "alias",
+        "conflicts",
+        "restart",
+        "service_type",
+        "ready_path",
+        "environment",
+        "environment_file",
+        "output",
+        "seccomp",
+        "user",
+        "group",
+        "bus_name",
+        "start_timeout_sec",
+        "on_failure",
+        "watchdog_sec",
+        "watchdog_reset",
+        "health",
+        "restart_delay_ms",
+        "restart_window_sec",
+        "restart_burst",
+        "exec_stop",
+        "stop_timeout_sec",
+        "timer",
+        "conditions",
+        "critical",
+        "requires_mount",
+        "private_tmp",
+        "protect_home",
+        "no_new_privileges",
+        "readonly_paths",
+        "capabilities",
+        "oom_score_adj",
+        "expendable",
+        "freeze_on_sleep",
+        "unknown",
+    ];
+
+    const VALUES: &[&str] = &[
+        "\"x\"",
+        "\"always\"",
+        "\"\"",
+        "0",
+        "-1",
+        "18446744073709551616",
+        "1.5",
+        "nan",
+        "true",
+        "[]",
+        "[\"a\", 1]",
+        "{}",
+        "{ a = \"b\" }",
+        "1979-05-27T07:32:00Z",
+    ];
+
+    proptest! {
+        #[test]
+        fn arbitrary_text_never_panics(text in "\\PC*") {
+            let _ = parse_service(&text);
+        }
+
+        #[test]
+        fn mutated_config_never_panics(
+            edits in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..8),
+            truncate in any::<prop::sample::Index>(),
+        ) {
+            let mut bytes = FULL_SERVICE.as_bytes().to_vec();
+            for (at, byte) in edits {
+                let i = at.index(bytes.len());
+                bytes[i] = byte;
+            }
+            bytes.truncate(truncate.index(bytes.len() + 1));
+            let _ = parse_service(&String::from_utf8_lossy(&bytes));
+        }
+
+        #[test]
+        fn mistyped_fields_never_panic(
+            fields in prop::collection::vec(
+                (prop::sample::select(KEYS), prop::sample::select(VALUES)),
+                0..10,
+            )
+        ) {
+            let mut toml = String::from("[service]\n");
+            for (key, value) in fields {
+                toml.push_str(&format!("{key} = {value}\n"));
+            }
+            let _ = parse_service(&toml);
+        }
+    }
 }
@@ -0,0 +1,735 @@
+// ABOUTME: Control socket for querying and commanding the init system.
+// ABOUTME: Serves one-line text requests from mosctl over a Unix socket under /run/mos.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::{info, warn};
+
+use crate::boot::BootProfile;
+use crate::inhibit::Inhibitors;
+use crate::rescue::Rescue;
+use crate::rootfs::{RootMode, Rootfs};
+use crate::service::ServiceManager;
+use crate::shutdown::{RebootMode, RebootRequest};
+use crate::sleep::SleepRequest;
+use crate::timer::Timers;
+
+pub const CONTROL_SOCKET: &str = "/run/mos/initctl";
+
+/// How long a client may take to send its request line, or to take in the response,
+/// before init gives up on it and goes back to its main loop.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Init state a control request may inspect or change.
+pub struct Context<'a> {
+    pub manager: &'a mut ServiceManager,
+    pub rootfs: &'a mut Rootfs,
+    pub timers: &'a Timers,
+    pub inhibitors: &'a mut Inhibitors,
+    pub rescue: &'a Rescue,
+    /// Set by a `reboot` request for the main loop to carry out.
+    pub reboot: &'a mut Option<RebootRequest>,
+    /// Set by a `suspend` request, likewise.
+    pub sleep: &'a mut Option<SleepRequest>,
+}
+
+pub struct ControlServer {
+    listener: UnixListener,
+}
+
+impl ControlServer {
+    pub fn bind(path: &Path) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // A socket left over from a previous boot stage would make bind() fail
+        let _ = std::fs::remove_file(path);
+
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        info!(path = %path.display(), "control socket listening");
+
+        Ok(Self { listener })
+    }
+
+    /// Serve every connection that is currently pending, without blocking.
+    pub fn poll(&self, ctx: &mut Context) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = serve_client(stream, ctx) {
+                        warn!(error = %e, "control client error");
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!(error = %e, "failed to accept control connection");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Readable whenever a client is waiting to be accepted, for the main loop to poll on.
+impl AsFd for ControlServer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.listener.as_fd()
+    }
+}
+
+fn serve_client(stream: UnixStream, ctx: &mut Context) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = handle_request(line.trim(), ctx);
+    (&stream).write_all(response.as_bytes())
+}
+
+/// Execute a single control request and return the text to send back.
+/// Failures are reported as a response starting with `error:`.
+pub fn handle_request(request: &str, ctx: &mut Context) -> String {
+    let mut words = request.split_whitespace();
+    let Some(command) = words.next() else {
+        return "error: empty request\n".to_string();
+    };
+    let args: Vec<&str> = words.collect();
+
+    match (command, args.as_slice()) {
+        ("status", []) => status(ctx.manager),
+        ("logs", ["--kernel"]) => kernel_logs(ctx.manager, None),
+        ("logs", ["--kernel", lines]) => match lines.parse() {
+            Ok(n) => kernel_logs(ctx.manager, Some(n)),
+            Err(_) => format!("error: invalid line count '{lines}'\n"),
+        },
+        ("logs", [name]) => logs(ctx.manager, name, None),
+        ("logs", [name, lines]) => match lines.parse() {
+            Ok(n) => logs(ctx.manager, name, Some(n)),
+            Err(_) => format!("error: invalid line count '{lines}'\n"),
+        },
+        ("timers", []) => timers(ctx.timers),
+        ("top", []) => top(ctx.manager),
+        ("boot-analyze", []) => boot_analyze(ctx.manager.boot_profile()),
+        ("boot-progress", []) => {
+            let (settled, total) = ctx.manager.boot_progress();
+            format!("{settled} {total}\n")
+        }
+        ("dumps", []) => dumps(Path::new(mos_coredump::DUMP_DIR)),
+        ("dev-mode", []) => dev_mode_status(ctx.rootfs),
+        ("dev-mode", ["on"]) => set_dev_mode(ctx.rootfs, RootMode::Overlay),
+        ("dev-mode", ["off"]) => set_dev_mode(ctx.rootfs, RootMode::ReadOnly),
+        ("inhibit", [seconds, who, reason @ ..]) => match seconds.parse() {
+            Ok(seconds) => inhibit(ctx.inhibitors, who, &reason.join(" "), seconds),
+            Err(_) => format!("error: invalid delay '{seconds}'\n"),
+        },
+        ("uninhibit", [id]) => match id.parse() {
+            Ok(id) => uninhibit(ctx.inhibitors, id),
+            Err(_) => format!("error: invalid inhibitor id '{id}'\n"),
+        },
+        ("inhibitors", []) => inhibitors(ctx.inhibitors),
+        ("rescue", []) => match ctx.rescue.reason() {
+            Some(reason) => format!("rescue: {reason}\n"),
+            None => "rescue: off\n".to_string(),
+        },
+        ("reboot", []) => request_reboot(ctx.reboot, RebootMode::Normal, &[]),
+        ("reboot", [mode, reason @ ..]) => match mode.parse() {
+            Ok(mode) => request_reboot(ctx.reboot, mode, reason),
+            Err(e) => format!("error: {e}\n"),
+        },
+        ("suspend", []) => request_suspend(ctx.sleep),
+        _ => format!("error: unknown request '{request}'\n"),
+    }
+}
+
+fn status(manager: &ServiceManager) -> String {
+    let mut out = String::new();
+    for name in manager.service_names() {
+        let state = manager.state(name);
+        match manager.pid(name) {
+            Some(pid) => out.push_str(&format!("{name} {state:?} pid={pid}\n")),
+            None => out.push_str(&format!("{name} {state:?}\n")),
+        }
+    }
+    out
+}
+
+fn logs(manager: &ServiceManager, name: &str, limit: Option<usize>) -> String {
+    if !manager.service_names().contains(&name) {
+        return format!("error: unknown service '{name}'\n");
+    }
+
+    manager
+        .journal()
+        .entries(name, limit)
+        .iter()
+        .map(|entry| format!("{entry}\n"))
+        .collect()
+}
+
+/// Kernel messages and the output of every service, in the order they happened.
+fn kernel_logs(manager: &ServiceManager, limit: Option<usize>) -> String {
+    manager
+        .journal()
+        .merged(limit)
+        .iter()
+        .map(|entry| format!("{entry}\n"))
+        .collect()
+}
+
+/// One line per running service, the one that used the most CPU time first: that time,
+/// and the memory it has resident.
+fn top(manager: &ServiceManager) -> String {
+    let mut usage = manager.usage();
+    usage.sort_by(|a, b| b.1.cpu.cmp(&a.1.cpu).then_with(|| a.0.cmp(&b.0)));
+    usage
+        .iter()
+        .map(|(name, usage)| {
+            format!(
+                "{name} cpu={:.2}s rss={}K\n",
+                usage.cpu.as_secs_f64(),
+                usage.rss_bytes / 1024
+            )
+        })
+        .collect()
+}
+
+/// One line per timer: seconds until it next fires and since it last did.
+fn timers(timers: &Timers) -> String {
+    let (now, wall) = (Instant::now(), SystemTime::now());
+    let mut out = String::new();
+    for timer in timers.iter() {
+        let next = match timer.next_due(now, wall) {
+            Some(due) => format!("{}s", due.saturating_duration_since(now).as_secs()),
+            None => "never".to_string(),
+        };
+        let last = match timer.last_fired() {
+            Some(at) => {
+                let ago = wall.duration_since(at).unwrap_or_default();
+                format!("{}s ago", ago.as_secs())
+            }
+            None => "never".to_string(),
+        };
+        out.push_str(&format!("{} next={next} last={last}\n", timer.name()));
+    }
+    out
+}
+
+/// How boot went, like systemd-analyze: the init phases, services by how long they took
+/// to become ready, the chain that held up the end of boot, and what failed. Times are
+/// since init started; "+" is how long a service took to become ready.
+fn boot_analyze(profile: &BootProfile) -> String {
+    let seconds = |d: Duration| format!("{:.3}s", d.as_secs_f64());
+    let mut out = String::new();
+
+    let starting: Vec<&str> = profile
+        .services()
+        .filter(|(_, t)| t.spawned.is_some() && t.ready.is_none() && t.failure.is_none())
+        .map(|(name, _)| name)
+        .collect();
+    match profile.finished() {
+        _ if !starting.is_empty() => out.push_str(&format!(
+            "startup not finished, waiting for: {}\n",
+            starting.join(" ")
+        )),
+        Some(at) => out.push_str(&format!("startup finished in {}\n", seconds(at))),
+        None => out.push_str("no service became ready\n"),
+    }
+
+    for (phase, at) in profile.phases() {
+        out.push_str(&format!("{} {phase}\n", seconds(*at)));
+    }
+
+    out.push_str("\nblame:\n");
+    for (name, startup) in profile.blame() {
+        out.push_str(&format!("  {} {name}\n", seconds(startup)));
+    }
+
+    out.push_str("\ncritical chain:\n");
+    for name in profile.critical_chain() {
+        let Some(timeline) = profile.get(name) else {
+            continue;
+        };
+        out.push_str(&format!(
+            "  {name} @{} +{}\n",
+            seconds(timeline.ready.unwrap_or_default()),
+            seconds(timeline.startup().unwrap_or_default())
+        ));
+    }
+
+    let failures: Vec<_> = profile
+        .services()
+        .filter_map(|(name, t)| Some((name, t.failure.as_ref()?)))
+        .collect();
+    if !failures.is_empty() {
+        out.push_str("\nfailed:\n");
+        for (name, (at, reason)) in failures {
+            out.push_str(&format!("  {name} @{} {reason}\n", seconds(*at)));
+        }
+    }
+    out
+}
+
+/// One line per stored core dump, oldest first.
+fn dumps(dir: &Path) -> String {
+    let dumps = match mos_coredump::list(dir) {
+        Ok(dumps) => dumps,
+        Err(e) => return format!("error: {e:#}\n"),
+    };
+    dumps
+        .iter()
+        .map(|dump| {
+            let signal = match mos_coredump::signal_name(dump.signal) {
+                Some(name) => name.to_string(),
+                None => dump.signal.to_string(),
+            };
+            format!(
+                "{} service={} pid={} signal={signal} time={} size={}{}\n",
+                dump.name(),
+                dump.service.as_deref().unwrap_or("-"),
+                dump.pid,
+                dump.timestamp,
+                dump.size,
+                if dump.truncated { " truncated" } else { "" }
+            )
+        })
+        .collect()
+}
+
+fn dev_mode_status(rootfs: &Rootfs) -> String {
+    let active = match rootfs.active_mode() {
+        Some(RootMode::Overlay) => "on",
+        Some(RootMode::ReadOnly) => "off",
+        None => "unmanaged",
+    };
+    let configured = match rootfs.configured_mode() {
+        RootMode::Overlay => "on",
+        RootMode::ReadOnly => "off",
+    };
+    if rootfs.active_mode() == Some(rootfs.configured_mode()) {
+        format!("dev-mode: {active}\n")
+    } else {
+        format!("dev-mode: {active} (next boot: {configured})\n")
+    }
+}
+
+fn set_dev_mode(rootfs: &mut Rootfs, mode: RootMode) -> String {
+    match rootfs.set_mode(mode) {
+        Ok(()) => dev_mode_status(rootfs),
+        Err(e) => format!("error: {e:#}\n"),
+    }
+}
+
+/// Replies with the id to release the inhibitor with.
+fn inhibit(inhibitors: &mut Inhibitors, who: &str, reason: &str, seconds: u64) -> String {
+    match inhibitors.inhibit(who, reason, Duration::from_secs(seconds)) {
+        Some(id) => format!("{id}\n"),
+        None => "error: shutting down\n".to_string(),
+    }
+}
+
+fn uninhibit(inhibitors: &mut Inhibitors, id: u64) -> String {
+    match inhibitors.uninhibit(id) {
+        Some(_) => String::new(),
+        None => format!("error: no inhibitor {id}\n"),
+    }
+}
+
+fn request_reboot(
+    pending: &mut Option<RebootRequest>,
+    mode: RebootMode,
+    reason: &[&str],
+) -> String {
+    let reason = match reason {
+        [] => "requested over the control socket".to_string(),
+        words => words.join(" "),
+    };
+    info!(mode = mode.as_str(), reason = %reason, "reboot requested");
+    *pending = Some(RebootRequest { mode, reason });
+    format!("rebooting into {}\n", mode.as_str())
+}
+
+/// A suspend already under way is left to carry on.
+fn request_suspend(pending: &mut Option<SleepRequest>) -> String {
+    if pending.is_none() {
+        info!("suspend requested");
+        *pending = Some(SleepRequest::new(Instant::now()));
+    }
+    "suspending\n".to_string()
+}
+
+fn inhibitors(inhibitors: &Inhibitors) -> String {
+    inhibitors
+        .iter()
+        .map(|holder| {
+            format!(
+                "{} {} delay={}s {}\n",
+                holder.id,
+                holder.who,
+                holder.delay.as_secs(),
+                holder.reason
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ServiceConfig, TimerConfig};
+    use crate::journal::{KERNEL, Stream};
+
+    fn sleeper(name: &str) -> ServiceConfig {
+        ServiceConfig {
+            name: name.to_string(),
+            exec: "sleep".to_string(),
+            args: vec!["10".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn handle(request: &str, manager: &mut ServiceManager) -> String {
+        let mut rootfs = Rootfs::default();
+        let timers = Timers::new(Instant::now());
+        handle_request(
+            request,
+            &mut Context {
+                manager,
+                rootfs: &mut rootfs,
+                timers: &timers,
+                inhibitors: &mut Inhibitors::new(),
+                rescue: &Rescue::new(),
+                reboot: &mut None,
+                sleep: &mut None,
+            },
+        )
+    }
+
+    #[test]
+    fn status_lists_services() {
+        let mut mgr = ServiceManager::new();
+        mgr.start_service(sleeper("alpha")).unwrap();
+
+        let response = handle("status", &mut mgr);
+        assert!(response.starts_with("alpha Running pid="));
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn top_lists_running_services() {
+        let mut mgr = ServiceManager::new();
+        mgr.start_service(sleeper("alpha")).unwrap();
+        mgr.start_service(sleeper("beta")).unwrap();
+
+        let response = handle("top", &mut mgr);
+        let names: Vec<&str> = response
+            .lines()
+            .map(|line| line.split_whitespace().next().unwrap())
+            .collect();
+        // Neither has used a measurable amount of CPU, so they are in name order
+        assert_eq!(names, ["alpha", "beta"]);
+        assert!(response.contains(" cpu=0.00s rss="));
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn logs_returns_journal_entries() {
+        let mut mgr = ServiceManager::new();
+        mgr.start_service(sleeper("beta")).unwrap();
+        mgr.journal().record("beta", Stream::Stdout, "one");
+        mgr.journal().record("beta", Stream::Stdout, "two");
+
+        let all = handle("logs beta", &mut mgr);
+        assert_eq!(all.lines().count(), 2);
+
+        let last = handle("logs beta 1", &mut mgr);
+        assert!(last.trim_end().ends_with("beta[stdout]: two"));
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn kernel_logs_merge_with_service_output() {
+        let mut mgr = ServiceManager::new();
+        let journal = mgr.journal();
+        journal.record_at(Duration::from_secs(2), "beta", Stream::Stdout, "ready");
+        journal.record_at(
+            Duration::from_secs(1),
+            KERNEL,
+            Stream::Kernel(6),
+            "usb 1-1: new device",
+        );
+
+        let response = handle("logs --kernel", &mut mgr);
+        let lines: Vec<_> = response.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("kernel[info]: usb 1-1: new device"));
+        assert!(lines[1].ends_with("beta[stdout]: ready"));
+
+        assert_eq!(handle("logs --kernel 1", &mut mgr).lines().count(), 1);
+        assert!(handle("logs --kernel many", &mut mgr).starts_with("error:"));
+    }
+
+    #[test]
+    fn logs_for_unknown_service_is_an_error() {
+        let mut mgr = ServiceManager::new();
+        let response = handle("logs ghost", &mut mgr);
+        assert!(response.starts_with("error:"));
+    }
+
+    #[test]
+    fn unknown_and_empty_requests_are_errors() {
+        let mut mgr = ServiceManager::new();
+        assert!(handle("frobnicate", &mut mgr).starts_with("error:"));
+        assert!(handle("", &mut mgr).starts_with("error:"));
+        assert!(handle("logs beta many", &mut mgr).starts_with("error:"));
+    }
+
+    #[test]
+    fn boot_analyze_reports_blame_and_chain() {
+        let mut mgr = ServiceManager::new();
+        mgr.mark_boot_phase("service configs loaded");
+        let mut bus = sleeper("dbus");
+        bus.args = vec!["1".to_string()];
+        mgr.start_service(bus).unwrap();
+        let mut failing = sleeper("modem");
+        failing.exec = "false".to_string();
+        failing.args = Vec::new();
+        failing.depends_on = vec!["dbus".to_string()];
+        mgr.start_service(failing).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        mgr.reap();
+
+        let response = handle("boot-analyze", &mut mgr);
+        let lines: Vec<&str> = response.lines().collect();
+        assert!(lines[0].starts_with("startup finished in 0."), "{response}");
+        assert!(lines[1].ends_with("s service configs loaded"));
+        assert!(response.contains("\nblame:\n  0.000s dbus\n"), "{response}");
+        assert!(response.contains("\ncritical chain:\n  "), "{response}");
+        assert!(response.contains("\nfailed:\n  modem @0."), "{response}");
+        assert!(
+            response.ends_with(" exited with exit status: 1\n"),
+            "{response}"
+        );
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn boot_progress_reports_settled_of_total() {
+        let mut mgr = ServiceManager::new();
+        assert_eq!(handle("boot-progress", &mut mgr), "0 0\n");
+
+        mgr.start_service(sleeper("dbus")).unwrap();
+        mgr.enqueue(vec![sleeper("compositor")]);
+        assert_eq!(handle("boot-progress", &mut mgr), "1 2\n");
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn dumps_lists_stored_cores() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(dumps(&dir.path().join("missing")), "");
+
+        let dump = mos_coredump::Dump {
+            service: Some("modem".to_string()),
+            command: "mos-modem".to_string(),
+            pid: 42,
+            signal: 11,
+            timestamp: 1700000000,
+            size: 0,
+            truncated: false,
+        };
+        mos_coredump::store(dir.path(), dump, &b"core"[..], 1024, 1 << 20).unwrap();
+        let response = dumps(dir.path());
+        assert!(
+            response.starts_with(
+                "1700000000-modem-42 service=modem pid=42 signal=SIGSEGV time=1700000000 size="
+            ),
+            "{response}"
+        );
+    }
+
+    #[test]
+    fn dev_mode_reports_unmanaged_root() {
+        let mut mgr = ServiceManager::new();
+        assert_eq!(handle("dev-mode", &mut mgr), "dev-mode: unmanaged (next boot: off)\n");
+        assert!(handle("dev-mode on", &mut mgr).starts_with("error:"));
+        assert!(handle("dev-mode maybe", &mut mgr).starts_with("error:"));
+    }
+
+    #[test]
+    fn rescue_reports_failure_reason() {
+        let mut mgr = ServiceManager::new();
+        assert_eq!(handle("rescue", &mut mgr), "rescue: off\n");
+
+        let mut rescue = Rescue::new();
+        let keep = std::collections::HashSet::new();
+        rescue.enter(&mut mgr, "compositor", "restarted too often", &keep, sleeper("sh"));
+        let timers = Timers::new(Instant::now());
+        let response = handle_request(
+            "rescue",
+            &mut Context {
+                manager: &mut mgr,
+                rootfs: &mut Rootfs::default(),
+                timers: &timers,
+                inhibitors: &mut Inhibitors::new(),
+                rescue: &rescue,
+                reboot: &mut None,
+                sleep: &mut None,
+            },
+        );
+        assert_eq!(response, "rescue: compositor: restarted too often\n");
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn timers_lists_schedules() {
+        let mut timers = Timers::new(Instant::now());
+        timers.load(vec![ServiceConfig {
+            name: "logrotate".to_string(),
+            exec: "true".to_string(),
+            timer: Some(TimerConfig {
+                on_boot_sec: Some(3600),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }]);
+
+        let response = handle_request(
+            "timers",
+            &mut Context {
+                manager: &mut ServiceManager::new(),
+                rootfs: &mut Rootfs::default(),
+                timers: &timers,
+                inhibitors: &mut Inhibitors::new(),
+                rescue: &Rescue::new(),
+                reboot: &mut None,
+                sleep: &mut None,
+            },
+        );
+        assert!(response.starts_with("logrotate next=35"));
+        assert!(response.ends_with(" last=never\n"));
+    }
+
+    #[test]
+    fn inhibitors_are_taken_and_released() {
+        let timers = Timers::new(Instant::now());
+        let mut inhibitors = Inhibitors::new();
+        let mut request = |line: &str| {
+            handle_request(
+                line,
+                &mut Context {
+                    manager: &mut ServiceManager::new(),
+                    rootfs: &mut Rootfs::default(),
+                    timers: &timers,
+                    inhibitors: &mut inhibitors,
+                    rescue: &Rescue::new(),
+                    reboot: &mut None,
+                    sleep: &mut None,
+                },
+            )
+        };
+
+        assert_eq!(request("inhibit 30 ota installing update 2.1"), "1\n");
+        assert_eq!(request("inhibitors"), "1 ota delay=30s installing update 2.1\n");
+        assert!(request("inhibit soon ota").starts_with("error:"));
+        assert!(request("inhibit 30").starts_with("error:"));
+
+        assert_eq!(request("uninhibit 1"), "");
+        assert!(request("uninhibit 1").starts_with("error:"));
+        assert_eq!(request("inhibitors"), "");
+    }
+
+    #[test]
+    fn reboot_is_left_for_the_main_loop() {
+        let timers = Timers::new(Instant::now());
+        let mut reboot = None;
+        let mut request = |line: &str| {
+            handle_request(
+                line,
+                &mut Context {
+                    manager: &mut ServiceManager::new(),
+                    rootfs: &mut Rootfs::default(),
+                    timers: &timers,
+                    inhibitors: &mut Inhibitors::new(),
+                    rescue: &Rescue::new(),
+                    reboot: &mut reboot,
+                    sleep: &mut None,
+                },
+            )
+        };
+
+        assert!(request("reboot fastboot").starts_with("error:"));
+        assert_eq!(request("reboot recovery factory reset"), "rebooting into recovery\n");
+        assert_eq!(request("reboot"), "rebooting into normal\n");
+        assert_eq!(
+            reboot,
+            Some(RebootRequest {
+                mode: RebootMode::Normal,
+                reason: "requested over the control socket".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn suspend_is_left_for_the_main_loop() {
+        let timers = Timers::new(Instant::now());
+        let mut sleep = None;
+        let mut request = |line: &str| {
+            handle_request(
+                line,
+                &mut Context {
+                    manager: &mut ServiceManager::new(),
+                    rootfs: &mut Rootfs::default(),
+                    timers: &timers,
+                    inhibitors: &mut Inhibitors::new(),
+                    rescue: &Rescue::new(),
+                    reboot: &mut None,
+                    sleep: &mut sleep,
+                },
+            )
+        };
+
+        assert!(request("suspend now").starts_with("error:"));
+        assert_eq!(request("suspend"), "suspending\n");
+        assert!(sleep.is_some());
+    }
+
+    #[test]
+    fn serves_requests_over_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("initctl");
+        let server = ControlServer::bind(&path).unwrap();
+        let mut mgr = ServiceManager::new();
+        let mut rootfs = Rootfs::default();
+        let timers = Timers::new(Instant::now());
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"frobnicate\n").unwrap();
+        server.poll(&mut Context {
+            manager: &mut mgr,
+            rootfs: &mut rootfs,
+            timers: &timers,
+            inhibitors: &mut Inhibitors::new(),
+            rescue: &Rescue::new(),
+            reboot: &mut None,
+            sleep: &mut None,
+        });
+
+        let mut response = String::new();
+        BufReader::new(client).read_line(&mut response).unwrap();
+        assert!(response.starts_with("error: unknown request"));
+    }
+}
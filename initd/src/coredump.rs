@@ -0,0 +1,68 @@
+// ABOUTME: Registers mos-coredump as the kernel's core dump handler at boot.
+// ABOUTME: Crashing services' cores are piped to it instead of being written in place.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+
+pub const PROC_SYS_KERNEL: &str = "/proc/sys/kernel";
+
+/// The handler binary; without it, cores are left to the kernel default.
+const HANDLER: &str = "/usr/bin/mos-coredump";
+
+/// How many handlers may run at once. Non-zero also makes the kernel keep the crashing
+/// process's /proc entry until its handler is done, which the handler reads.
+const CORE_PIPE_LIMIT: &str = "4";
+
+/// Point core_pattern under `proc_sys_kernel` at the handler, if it is installed.
+pub fn register(proc_sys_kernel: &Path, handler: &Path) {
+    if !handler.exists() {
+        warn!(handler = %handler.display(), "core dump handler not installed");
+        return;
+    }
+    match write_settings(proc_sys_kernel) {
+        Ok(()) => info!(
+            pattern = mos_coredump::CORE_PATTERN,
+            "core dump handler registered"
+        ),
+        Err(e) => warn!(error = %e, "failed to register core dump handler"),
+    }
+}
+
+pub fn register_from_system() {
+    register(Path::new(PROC_SYS_KERNEL), Path::new(HANDLER));
+}
+
+fn write_settings(proc_sys_kernel: &Path) -> Result<()> {
+    for (name, value) in [
+        ("core_pipe_limit", CORE_PIPE_LIMIT),
+        ("core_pattern", mos_coredump::CORE_PATTERN),
+    ] {
+        let path = proc_sys_kernel.join(name);
+        std::fs::write(&path, value)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_only_an_installed_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = dir.path().join("mos-coredump");
+
+        register(dir.path(), &handler);
+        assert!(!dir.path().join("core_pattern").exists());
+
+        std::fs::write(&handler, "").unwrap();
+        register(dir.path(), &handler);
+        let pattern = std::fs::read_to_string(dir.path().join("core_pattern")).unwrap();
+        assert_eq!(pattern, "|/usr/bin/mos-coredump %P %s %t %e");
+        let limit = std::fs::read_to_string(dir.path().join("core_pipe_limit")).unwrap();
+        assert_eq!(limit, "4");
+    }
+}
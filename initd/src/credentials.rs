@@ -0,0 +1,191 @@
+// ABOUTME: User and group resolution for services that drop root before exec.
+// ABOUTME: Looks accounts up in /etc/passwd and /etc/group and applies them in the forked child.
+
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+
+const PASSWD_PATH: &str = "/etc/passwd";
+const GROUP_PATH: &str = "/etc/group";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups, always including `gid`.
+    pub groups: Vec<u32>,
+    /// Login name and home directory when running as a named user.
+    pub user: Option<(String, PathBuf)>,
+}
+
+impl Credentials {
+    /// Resolve `user` and `group` against the system account databases.
+    pub fn resolve(user: Option<&str>, group: Option<&str>) -> Result<Self> {
+        let passwd = std::fs::read_to_string(PASSWD_PATH)
+            .with_context(|| format!("failed to read {PASSWD_PATH}"))?;
+        let groups = std::fs::read_to_string(GROUP_PATH)
+            .with_context(|| format!("failed to read {GROUP_PATH}"))?;
+        resolve_from(&passwd, &groups, user, group)
+    }
+
+    /// Switch to these credentials. Runs between fork and exec, so it only makes raw
+    /// syscalls; supplementary groups and gid must change while we are still root.
+    pub fn apply(&self) -> std::io::Result<()> {
+        // SAFETY: setgroups/setgid/setuid are async-signal-safe and `groups` outlives the call.
+        unsafe {
+            if libc::setgroups(self.groups.len(), self.groups.as_ptr()) != 0
+                || libc::setgid(self.gid) != 0
+                || libc::setuid(self.uid) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+struct PasswdEntry<'a> {
+    name: &'a str,
+    uid: u32,
+    gid: u32,
+    home: &'a str,
+}
+
+struct GroupEntry<'a> {
+    name: &'a str,
+    gid: u32,
+    members: Vec<&'a str>,
+}
+
+fn resolve_from(
+    passwd: &str,
+    group_db: &str,
+    user: Option<&str>,
+    group: Option<&str>,
+) -> Result<Credentials> {
+    let users: Vec<PasswdEntry> = passwd.lines().filter_map(parse_passwd_line).collect();
+    let groups: Vec<GroupEntry> = group_db.lines().filter_map(parse_group_line).collect();
+
+    let account = match user {
+        Some(name) => Some(
+            users
+                .iter()
+                .find(|u| u.name == name || name.parse() == Ok(u.uid))
+                .with_context(|| format!("unknown user '{name}'"))?,
+        ),
+        None => None,
+    };
+
+    let gid = match (group, &account) {
+        (Some(name), _) => match groups.iter().find(|g| g.name == name) {
+            Some(g) => g.gid,
+            None => match name.parse() {
+                Ok(gid) => gid,
+                Err(_) => bail!("unknown group '{name}'"),
+            },
+        },
+        (None, Some(account)) => account.gid,
+        (None, None) => 0,
+    };
+
+    let mut supplementary = vec![gid];
+    if let Some(account) = &account {
+        for g in &groups {
+            if g.members.contains(&account.name) && !supplementary.contains(&g.gid) {
+                supplementary.push(g.gid);
+            }
+        }
+    }
+
+    Ok(Credentials {
+        uid: account.as_ref().map_or(0, |a| a.uid),
+        gid,
+        groups: supplementary,
+        user: account.map(|a| (a.name.to_string(), PathBuf::from(a.home))),
+    })
+}
+
+/// `name:password:uid:gid:gecos:home:shell`
+fn parse_passwd_line(line: &str) -> Option<PasswdEntry<'_>> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() < 7 {
+        return None;
+    }
+    Some(PasswdEntry {
+        name: fields[0],
+        uid: fields[2].parse().ok()?,
+        gid: fields[3].parse().ok()?,
+        home: fields[5],
+    })
+}
+
+/// `name:password:gid:member,member,...`
+fn parse_group_line(line: &str) -> Option<GroupEntry<'_>> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() < 4 {
+        return None;
+    }
+    Some(GroupEntry {
+        name: fields[0],
+        gid: fields[2].parse().ok()?,
+        members: fields[3].split(',').filter(|m| !m.is_empty()).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "\
+root:x:0:0:root:/root:/bin/sh
+audio:x:102:102:MobileOS audio:/var/empty:/bin/false
+broken line
+";
+
+    const GROUP: &str = "\
+root:x:0:
+audio:x:102:
+snd:x:29:audio,modem
+dialout:x:20:modem
+";
+
+    #[test]
+    fn user_gets_primary_and_member_groups() {
+        let creds = resolve_from(PASSWD, GROUP, Some("audio"), None).unwrap();
+        assert_eq!(creds.uid, 102);
+        assert_eq!(creds.gid, 102);
+        assert_eq!(creds.groups, vec![102, 29]);
+        assert_eq!(
+            creds.user,
+            Some(("audio".to_string(), PathBuf::from("/var/empty")))
+        );
+    }
+
+    #[test]
+    fn explicit_group_overrides_primary() {
+        let creds = resolve_from(PASSWD, GROUP, Some("audio"), Some("dialout")).unwrap();
+        assert_eq!(creds.gid, 20);
+        assert_eq!(creds.groups, vec![20, 29]);
+    }
+
+    #[test]
+    fn group_only_keeps_root_uid() {
+        let creds = resolve_from(PASSWD, GROUP, None, Some("snd")).unwrap();
+        assert_eq!(creds.uid, 0);
+        assert_eq!(creds.gid, 29);
+        assert_eq!(creds.groups, vec![29]);
+        assert_eq!(creds.user, None);
+    }
+
+    #[test]
+    fn numeric_ids_are_accepted() {
+        let creds = resolve_from(PASSWD, GROUP, Some("102"), Some("500")).unwrap();
+        assert_eq!(creds.uid, 102);
+        assert_eq!(creds.gid, 500);
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        assert!(resolve_from(PASSWD, GROUP, Some("nobody"), None).is_err());
+        assert!(resolve_from(PASSWD, GROUP, None, Some("wheel")).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+// ABOUTME: The system D-Bus bus, run by initd as the "dbus" service: its generated config and address.
+// ABOUTME: Every service finds the bus through DBUS_SYSTEM_BUS_ADDRESS instead of a path of its own.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use tracing::{error, info};
+
+use crate::config::ServiceConfig;
+
+/// Where the dbus service's `--config-file` points.
+pub const CONFIG_PATH: &str = "/run/dbus/system.conf";
+
+/// The dbus service's `ready_path`: services that depend on it start once it exists.
+pub const SOCKET_PATH: &str = "/run/dbus/system_bus_socket";
+
+pub const ADDRESS_ENV: &str = "DBUS_SYSTEM_BUS_ADDRESS";
+
+/// Sends the ping of health probes and init's own signals, since init has no bus
+/// connection of its own.
+pub const DBUS_SEND: &str = "/usr/bin/dbus-send";
+
+/// Object path and interface of the signals init sends.
+pub const INIT_PATH: &str = "/org/mobileos/Init";
+pub const INIT_INTERFACE: &str = "org.mobileos.Init";
+
+/// Policy files that narrow down what a service may own or call, installed by packages.
+const POLICY_DIR: &str = "/etc/dbus-1/system.d";
+
+pub fn address(socket: &Path) -> String {
+    format!("unix:path={}", socket.display())
+}
+
+/// The dbus-daemon config for a system bus listening on `socket`. Anyone may connect
+/// and call, but a well-known name may only be owned by the account of the service in
+/// `services` that declares it as its `bus_name`, or as a policy file says.
+pub fn config(socket: &Path, services: &[ServiceConfig]) -> String {
+    let owners: String = services
+        .iter()
+        .flat_map(|service| {
+            let user = service.user.as_deref().unwrap_or("root");
+            service.bus_name.iter().map(move |name| {
+                format!("  <policy user=\"{user}\">\n    <allow own=\"{name}\"/>\n  </policy>\n")
+            })
+        })
+        .collect();
+    format!(
+        r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-BUS Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>system</type>
+  <listen>unix:path={socket}</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow user="*"/>
+    <deny own="*"/>
+    <allow send_destination="*"/>
+  </policy>
+{owners}  <includedir>{POLICY_DIR}</includedir>
+</busconfig>
+"#,
+        socket = socket.display()
+    )
+}
+
+/// Write the bus config for `socket` and `services` to `path`, creating its directory.
+pub fn write_config(path: &Path, socket: &Path, services: &[ServiceConfig]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    std::fs::write(path, config(socket, services))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+pub fn write_config_from_system(services: &[ServiceConfig]) {
+    match write_config(Path::new(CONFIG_PATH), Path::new(SOCKET_PATH), services) {
+        Ok(()) => info!(config = CONFIG_PATH, "system bus config written"),
+        Err(e) => error!(error = %e, "failed to write system bus config"),
+    }
+}
+
+/// The dbus-send command that sends init's signal `member` with `args`, given the way
+/// dbus-send takes them, e.g. "boolean:true".
+pub fn signal_command(member: &str, args: &[&str]) -> Command {
+    let mut cmd = Command::new(DBUS_SEND);
+    cmd.arg("--system")
+        .arg("--type=signal")
+        .arg(INIT_PATH)
+        .arg(format!("{INIT_INTERFACE}.{member}"))
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    cmd
+}
+
+/// Send init's signal `member` on the system bus, waiting until it is sent so it goes
+/// out ahead of whatever init does next.
+pub fn broadcast(member: &str, args: &[&str]) -> Result<()> {
+    let status = signal_command(member, args)
+        .status()
+        .with_context(|| format!("failed to run {DBUS_SEND}"))?;
+    if !status.success() {
+        bail!("{DBUS_SEND} exited with {status}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_listens_on_the_socket_it_is_given() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dbus/system.conf");
+        let socket = dir.path().join("dbus/system_bus_socket");
+
+        write_config(&path, &socket, &[]).unwrap();
+        let config = std::fs::read_to_string(&path).unwrap();
+        assert!(config.contains("<type>system</type>"));
+        assert!(config.contains(&format!("<listen>{}</listen>", address(&socket))));
+    }
+
+    #[test]
+    fn only_a_services_own_account_may_own_its_bus_name() {
+        let services = [
+            ServiceConfig {
+                name: "audio".to_string(),
+                user: Some("audio".to_string()),
+                bus_name: vec!["org.mobileos.Audio".to_string()],
+                ..Default::default()
+            },
+            ServiceConfig {
+                name: "power".to_string(),
+                bus_name: vec!["org.mobileos.Power".to_string()],
+                ..Default::default()
+            },
+            ServiceConfig {
+                name: "seatd".to_string(),
+                ..Default::default()
+            },
+        ];
+        let config = config(Path::new(SOCKET_PATH), &services);
+        assert!(config.contains("<deny own=\"*\"/>"));
+        assert!(!config.contains("<allow own=\"*\"/>"));
+        assert!(config.contains(
+            "<policy user=\"audio\">\n    <allow own=\"org.mobileos.Audio\"/>\n  </policy>"
+        ));
+        assert!(config.contains(
+            "<policy user=\"root\">\n    <allow own=\"org.mobileos.Power\"/>\n  </policy>"
+        ));
+        assert_eq!(config.matches("<allow own=").count(), 2);
+    }
+
+    #[test]
+    fn signals_come_from_init() {
+        let cmd = signal_command("PrepareForSleep", &["boolean:true"]);
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "--system",
+                "--type=signal",
+                "/org/mobileos/Init",
+                "org.mobileos.Init.PrepareForSleep",
+                "boolean:true"
+            ]
+        );
+    }
+}
@@ -1,17 +1,39 @@
-// ABOUTME: Dependency resolver for service startup ordering.
-// ABOUTME: Topological sort that computes a valid start sequence respecting depends_on.
+// ABOUTME: Dependency resolver for service startup and shutdown ordering.
+// ABOUTME: Topological sort that computes a valid start sequence respecting depends_on and wants.
 
 use anyhow::{bail, Result};
 use std::collections::{HashMap, HashSet, VecDeque};
+use tracing::warn;
 
 use crate::config::ServiceConfig;
 
+/// How a service depends on another it names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// From `depends_on`: the other service must exist, and its failing or stopping
+    /// takes this one down with it.
+    Requires,
+    /// From `wants`: only orders startup, and only if the other service is there.
+    Wants,
+}
+
+/// The services `service` names, with how it depends on each.
+pub fn edges(service: &ServiceConfig) -> impl Iterator<Item = (&str, Edge)> {
+    let requires = service
+        .depends_on
+        .iter()
+        .map(|dep| (dep.as_str(), Edge::Requires));
+    let wants = service.wants.iter().map(|dep| (dep.as_str(), Edge::Wants));
+    requires.chain(wants)
+}
+
 /// Compute a valid start order for services using Kahn's topological sort.
-/// Returns service names in the order they should be started.
+/// Returns service names in the order they should be started. Both kinds of edge order
+/// startup; wanted services that aren't there are left out.
 pub fn resolve_start_order(services: &[ServiceConfig]) -> Result<Vec<String>> {
-    let names: HashSet<&str> = services.iter().map(|s| s.name.as_str()).collect();
+    let names: HashSet<&str> = services.iter().map(|svc| svc.name.as_str()).collect();
 
-    // Validate all dependencies refer to known services
+    // Validate all requirements refer to known services
     for svc in services {
         for dep in &svc.depends_on {
             if !names.contains(dep.as_str()) {
@@ -24,18 +46,44 @@ pub fn resolve_start_order(services: &[ServiceConfig]) -> Result<Vec<String>> {
         }
     }
 
+    sort(&services.iter().collect::<Vec<_>>())
+}
+
+/// The order to stop `services` in: the start order reversed, so services stop before
+/// what they depend on. Dependencies on services not among them are left out. Should
+/// they somehow form a cycle, they stop in the order given.
+pub fn resolve_stop_order(services: &[&ServiceConfig]) -> Vec<String> {
+    match sort(services) {
+        Ok(mut order) => {
+            order.reverse();
+            order
+        }
+        Err(e) => {
+            warn!(error = %e, "can't order services for stopping");
+            services.iter().map(|svc| svc.name.clone()).collect()
+        }
+    }
+}
+
+/// Kahn's topological sort over both kinds of edge, leaving out edges to services not
+/// in `services`. Ties go in name order.
+fn sort(services: &[&ServiceConfig]) -> Result<Vec<String>> {
+    let mut names: HashSet<&str> = HashSet::new();
+    for svc in services {
+        if !names.insert(svc.name.as_str()) {
+            bail!("service '{}' is defined more than once", svc.name);
+        }
+    }
+
     // Build adjacency list and in-degree count
     let mut in_degree: HashMap<&str, usize> = HashMap::new();
     let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
 
     for svc in services {
         in_degree.entry(svc.name.as_str()).or_insert(0);
-        for dep in &svc.depends_on {
+        for (dep, _) in edges(svc).filter(|(dep, _)| names.contains(dep)) {
             *in_degree.entry(svc.name.as_str()).or_insert(0) += 1;
-            dependents
-                .entry(dep.as_str())
-                .or_default()
-                .push(svc.name.as_str());
+            dependents.entry(dep).or_default().push(svc.name.as_str());
         }
     }
 
@@ -85,6 +133,7 @@ pub fn resolve_start_order(services: &[ServiceConfig]) -> Result<Vec<String>> {
 mod tests {
     use super::*;
     use crate::config::parse_service;
+    use proptest::prelude::*;
 
     fn svc(name: &str, deps: &[&str]) -> ServiceConfig {
         let deps_toml = if deps.is_empty() {
@@ -175,6 +224,42 @@ mod tests {
         assert!(err.to_string().contains("unknown service"));
     }
 
+    #[test]
+    fn wants_order_startup_but_may_be_missing() {
+        let mut shell = svc("shell", &["compositor"]);
+        shell.wants = vec!["audio".to_string(), "nonexistent".to_string()];
+        let services = vec![shell, svc("compositor", &[]), svc("audio", &[])];
+        let order = resolve_start_order(&services).unwrap();
+        assert_eq!(order, vec!["audio", "compositor", "shell"]);
+
+        let mut audio = svc("audio", &[]);
+        audio.wants = vec!["shell".to_string()];
+        let mut shell = svc("shell", &[]);
+        shell.wants = vec!["audio".to_string()];
+        let err = resolve_start_order(&[audio, shell]).unwrap_err();
+        assert!(err.to_string().contains("circular dependency"));
+    }
+
+    #[test]
+    fn services_stop_before_what_they_depend_on() {
+        let mut shell = svc("shell", &["compositor"]);
+        shell.wants = vec!["audio".to_string()];
+        let services = [
+            svc("dbus", &[]),
+            svc("compositor", &["dbus", "seatd"]),
+            shell,
+            svc("audio", &["dbus"]),
+        ];
+        let services: Vec<&ServiceConfig> = services.iter().collect();
+        let order = resolve_stop_order(&services);
+        // seatd isn't running, so it doesn't matter
+        assert_eq!(order, vec!["shell", "compositor", "audio", "dbus"]);
+
+        let cycle = [svc("a", &["b"]), svc("b", &["a"])];
+        let order = resolve_stop_order(&cycle.iter().collect::<Vec<_>>());
+        assert_eq!(order, vec!["a", "b"]);
+    }
+
     #[test]
     fn complex_graph() {
         // network depends on dbus
@@ -199,4 +284,106 @@ mod tests {
         assert!(shell_pos > comp_pos);
         assert!(shell_pos > net_pos);
     }
+
+    #[test]
+    fn duplicate_service_detected() {
+        let services = vec![svc("dbus", &[]), svc("dbus", &[])];
+        let err = resolve_start_order(&services).unwrap_err();
+        assert!(err.to_string().contains("more than once"));
+    }
+
+    /// Services named s0..s{n}, each depending on a random subset of the others.
+    fn graph(max: usize) -> impl Strategy<Value = Vec<ServiceConfig>> {
+        (1..=max)
+            .prop_flat_map(|n| prop::collection::vec(prop::collection::vec(0..n, 0..4), n))
+            .prop_map(|deps| {
+                deps.iter()
+                    .enumerate()
+                    .map(|(i, deps)| ServiceConfig {
+                        name: format!("s{i}"),
+                        depends_on: deps.iter().map(|d| format!("s{d}")).collect(),
+                        ..Default::default()
+                    })
+                    .collect()
+            })
+    }
+
+    /// Independent cycle check by depth-first search, to hold the resolver to.
+    fn has_cycle(services: &[ServiceConfig]) -> bool {
+        fn visit<'a>(
+            name: &'a str,
+            deps: &HashMap<&'a str, &'a [String]>,
+            visiting: &mut HashSet<&'a str>,
+            done: &mut HashSet<&'a str>,
+        ) -> bool {
+            if done.contains(name) {
+                return false;
+            }
+            if !visiting.insert(name) {
+                return true;
+            }
+            let cyclic = deps[name]
+                .iter()
+                .any(|dep| visit(dep, deps, visiting, done));
+            visiting.remove(name);
+            done.insert(name);
+            cyclic
+        }
+
+        let deps: HashMap<&str, &[String]> = services
+            .iter()
+            .map(|s| (s.name.as_str(), s.depends_on.as_slice()))
+            .collect();
+        let mut visiting = HashSet::new();
+        let mut done = HashSet::new();
+        services
+            .iter()
+            .any(|s| visit(&s.name, &deps, &mut visiting, &mut done))
+    }
+
+    proptest! {
+        #[test]
+        fn dependencies_start_before_dependents(services in graph(12)) {
+            match resolve_start_order(&services) {
+                Ok(order) => {
+                    prop_assert!(!has_cycle(&services));
+                    prop_assert_eq!(order.len(), services.len());
+                    let position: HashMap<&str, usize> =
+                        order.iter().enumerate().map(|(i, n)| (n.as_str(), i)).collect();
+                    for svc in &services {
+                        for dep in &svc.depends_on {
+                            prop_assert!(position[dep.as_str()] < position[svc.name.as_str()]);
+                        }
+                    }
+                }
+                Err(e) => {
+                    prop_assert!(has_cycle(&services));
+                    prop_assert!(e.to_string().contains("circular dependency"));
+                }
+            }
+        }
+
+        #[test]
+        fn arbitrary_graphs_never_panic(
+            services in prop::collection::vec(
+                (
+                    "[a-d]",
+                    prop::collection::vec("[a-e]", 0..4),
+                    prop::collection::vec("[a-e]", 0..4),
+                ),
+                0..8,
+            )
+        ) {
+            let services: Vec<ServiceConfig> = services
+                .into_iter()
+                .map(|(name, depends_on, wants)| ServiceConfig {
+                    name,
+                    depends_on,
+                    wants,
+                    ..Default::default()
+                })
+                .collect();
+            let _ = resolve_start_order(&services);
+        }
+    }
 }
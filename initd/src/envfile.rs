@@ -0,0 +1,191 @@
+// ABOUTME: Environment files for services, read at start so secrets stay out of unit files.
+// ABOUTME: Parses KEY=VALUE lines and expands $VAR references in a service's exec and args.
+
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Load the environment file named by a service's `environment_file`. A leading `-`
+/// marks the file optional: if it doesn't exist no variables are added.
+pub fn load(spec: &str) -> Result<Vec<(String, String)>> {
+    let (optional, path) = match spec.strip_prefix('-') {
+        Some(path) => (true, path),
+        None => (false, spec),
+    };
+
+    let content = match std::fs::read_to_string(Path::new(path)) {
+        Ok(content) => content,
+        Err(e) if optional && e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read environment file {path}")),
+    };
+    parse(&content).with_context(|| format!("failed to parse environment file {path}"))
+}
+
+/// Parse `KEY=VALUE` lines. Blank lines and `#` comments are skipped and an `export `
+/// prefix is allowed, so the same file can be sourced from a shell. Values may be
+/// single-quoted (taken literally) or double-quoted (with `\` escapes).
+pub fn parse(content: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("line {}: expected KEY=VALUE", number + 1);
+        };
+        let key = key.trim();
+        if !is_valid_name(key) {
+            bail!("line {}: invalid variable name '{key}'", number + 1);
+        }
+        let value = unquote(value.trim()).with_context(|| format!("line {}", number + 1))?;
+        vars.push((key.to_string(), value));
+    }
+
+    Ok(vars)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn unquote(value: &str) -> Result<String> {
+    if let Some(inner) = value.strip_prefix('\'') {
+        return match inner.strip_suffix('\'') {
+            Some(inner) => Ok(inner.to_string()),
+            None => bail!("unterminated single quote"),
+        };
+    }
+    let Some(inner) = value.strip_prefix('"') else {
+        return Ok(value.to_string());
+    };
+    let Some(inner) = inner.strip_suffix('"') else {
+        bail!("unterminated double quote");
+    };
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    Ok(out)
+}
+
+/// Replace `$VAR` and `${VAR}` with values from `vars`; `$$` is a literal `$`.
+/// Variables that aren't defined are left as written, so `sh -c` arguments can still
+/// refer to ones init sets itself, like NOTIFY_SOCKET.
+pub fn expand(text: &str, vars: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(at) = rest.find('$') {
+        out.push_str(&rest[..at]);
+        let after = &rest[at + 1..];
+
+        if let Some(after) = after.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+        } else if let Some(braced) = after.strip_prefix('{')
+            && let Some(end) = braced.find('}')
+        {
+            match vars.get(&braced[..end]) {
+                Some(value) => out.push_str(value),
+                None => out.push_str(&rest[at..at + end + 3]),
+            }
+            rest = &braced[end + 1..];
+        } else {
+            let len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            match vars.get(&after[..len]) {
+                Some(value) if len > 0 => out.push_str(value),
+                _ => out.push_str(&rest[at..at + len + 1]),
+            }
+            rest = &after[len..];
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_assignments_comments_and_quotes() {
+        let content = r#"
+            # machine-specific settings
+            PANEL=dsi-1
+            export API_TOKEN='s3cr$t "raw"'
+            GREETING="hello\tworld \"quoted\""
+            EMPTY=
+        "#;
+
+        let vars = parse(content).unwrap();
+        assert_eq!(
+            vars,
+            [
+                ("PANEL".to_string(), "dsi-1".to_string()),
+                ("API_TOKEN".to_string(), "s3cr$t \"raw\"".to_string()),
+                (
+                    "GREETING".to_string(),
+                    "hello\tworld \"quoted\"".to_string()
+                ),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        assert!(parse("JUST_A_NAME").is_err());
+        assert!(parse("1BAD=x").is_err());
+        assert!(parse("BAD-NAME=x").is_err());
+        assert!(parse("OPEN=\"never closed").is_err());
+        assert!(parse("OPEN='never closed").is_err());
+    }
+
+    #[test]
+    fn optional_files_may_be_missing() {
+        assert!(load("/nonexistent/compositor.env").is_err());
+        assert!(load("-/nonexistent/compositor.env").unwrap().is_empty());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("compositor.env");
+        std::fs::write(&path, "PANEL=dsi-1\n").unwrap();
+        let vars = load(&format!("-{}", path.display())).unwrap();
+        assert_eq!(vars, [("PANEL".to_string(), "dsi-1".to_string())]);
+    }
+
+    #[test]
+    fn expands_variables() {
+        let vars = HashMap::from([
+            ("PANEL".to_string(), "dsi-1".to_string()),
+            ("SCALE".to_string(), "2".to_string()),
+        ]);
+
+        assert_eq!(expand("--output=$PANEL", &vars), "--output=dsi-1");
+        assert_eq!(expand("${SCALE}x", &vars), "2x");
+        assert_eq!(expand("$MISSING-${MISSING}", &vars), "$MISSING-${MISSING}");
+        assert_eq!(expand("cost: $$5 $", &vars), "cost: $5 $");
+        assert_eq!(expand("${unterminated", &vars), "${unterminated");
+    }
+}
@@ -0,0 +1,49 @@
+// ABOUTME: Checks at boot that the firmware listed in the device's config is installed.
+// ABOUTME: A driver missing its firmware fails quietly, so the gap is logged before anything needs it.
+
+use std::path::Path;
+
+use mos_device::Device;
+use tracing::{error, info};
+
+/// Log each firmware file `device` needs that none of `dirs` has. Returns how many.
+pub fn check(device: &Device, dirs: &[&Path]) -> usize {
+    let missing = device.missing_firmware(dirs);
+    for file in &missing {
+        error!(device = %device.name, firmware = file, "firmware missing");
+    }
+    missing.len()
+}
+
+/// Find this machine's device config and check its firmware.
+pub fn check_from_system() {
+    match mos_device::load_from_system() {
+        Ok(Some(device)) => {
+            let dirs: Vec<&Path> = mos_device::FIRMWARE_DIRS.iter().map(Path::new).collect();
+            if check(&device, &dirs) == 0 {
+                info!(device = %device.name, "device firmware present");
+            }
+        }
+        Ok(None) => info!("no device config for this machine"),
+        Err(e) => error!(error = %e, "failed to load device config"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_missing_firmware() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("anx7688-fw.bin"), "fw").unwrap();
+        let device = Device {
+            name: "PinePhone".to_string(),
+            firmware: vec!["anx7688-fw.bin".to_string(), "ov5640_af.bin".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(check(&device, &[dir.path()]), 1);
+        assert_eq!(check(&Device::default(), &[dir.path()]), 0);
+    }
+}
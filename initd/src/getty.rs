@@ -0,0 +1,303 @@
+// ABOUTME: Login prompts on the configured terminals and the kernel console, kept by initd itself.
+// ABOUTME: Unlike services, a getty is started again whenever its session ends, with no restart limit.
+
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::target;
+
+pub const CONFIG_PATH: &str = "/etc/mos/getty.toml";
+
+pub const DEV_DIR: &str = "/dev";
+
+/// A getty that exits sooner than this after starting is failing rather than done with
+/// a session, and waits `RESPAWN_DELAY` before it is started again.
+const MIN_SESSION: Duration = Duration::from_secs(5);
+
+const RESPAWN_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GettyConfig {
+    /// Terminals to keep a login prompt on, by their name in /dev, e.g. "tty1".
+    #[serde(default)]
+    pub ttys: Vec<String>,
+    /// Also keep one on the terminal the kernel command line's last `console=` names.
+    #[serde(default = "default_kernel_console")]
+    pub kernel_console: bool,
+    #[serde(default = "default_program")]
+    pub program: String,
+    /// Line speed for serial terminals.
+    #[serde(default = "default_baud")]
+    pub baud: u32,
+    /// Program run straight away instead of asking for a login, e.g. "/bin/sh" on
+    /// development images whose root account has no password.
+    #[serde(default)]
+    pub login: Option<String>,
+}
+
+impl Default for GettyConfig {
+    fn default() -> Self {
+        Self {
+            ttys: Vec::new(),
+            kernel_console: default_kernel_console(),
+            program: default_program(),
+            baud: default_baud(),
+            login: None,
+        }
+    }
+}
+
+fn default_kernel_console() -> bool {
+    true
+}
+
+fn default_program() -> String {
+    "/sbin/getty".to_string()
+}
+
+fn default_baud() -> u32 {
+    115200
+}
+
+#[derive(Debug, Deserialize)]
+struct GettyFile {
+    getty: GettyConfig,
+}
+
+pub fn parse_config(toml_str: &str) -> Result<GettyConfig> {
+    let file: GettyFile = toml::from_str(toml_str).context("failed to parse getty config")?;
+    Ok(file.getty)
+}
+
+/// The terminal the kernel console is on, from the last `console=` on `cmdline`, e.g.
+/// "ttyS0" for `console=ttyS0,115200n8`. `tty0`, the active virtual terminal, is tty1.
+pub fn kernel_console(cmdline: &str) -> Option<String> {
+    let console = cmdline
+        .split_whitespace()
+        .rev()
+        .find_map(|param| param.strip_prefix("console="))?;
+    let tty = console.split(',').next().unwrap_or(console);
+    match tty {
+        "" => None,
+        "tty0" => Some("tty1".to_string()),
+        tty => Some(tty.to_string()),
+    }
+}
+
+/// The terminals `config` puts a getty on, without repeats.
+pub fn ttys(config: &GettyConfig, cmdline: &str) -> Vec<String> {
+    let console = config
+        .kernel_console
+        .then(|| kernel_console(cmdline))
+        .flatten();
+    let mut ttys: Vec<String> = Vec::new();
+    for tty in config.ttys.iter().cloned().chain(console) {
+        if !ttys.contains(&tty) {
+            ttys.push(tty);
+        }
+    }
+    ttys
+}
+
+struct Getty {
+    tty: String,
+    child: Option<Child>,
+    started: Instant,
+    /// When to start it again, once it has exited.
+    respawn_at: Option<Instant>,
+}
+
+pub struct Gettys {
+    config: GettyConfig,
+    dev_dir: PathBuf,
+    gettys: Vec<Getty>,
+}
+
+impl Gettys {
+    /// Gettys for `ttys` under `dev_dir`, started by the first `supervise`. Terminals
+    /// that don't exist, such as tty1 on a board without a display, are left out.
+    pub fn new(config: GettyConfig, ttys: Vec<String>, dev_dir: &Path) -> Self {
+        let now = Instant::now();
+        let gettys = ttys
+            .into_iter()
+            .filter(|tty| {
+                let exists = dev_dir.join(tty).exists();
+                if !exists {
+                    warn!(tty = %tty, "no such terminal, not starting a getty on it");
+                }
+                exists
+            })
+            .map(|tty| Getty {
+                tty,
+                child: None,
+                started: now,
+                respawn_at: Some(now),
+            })
+            .collect();
+        Self {
+            config,
+            dev_dir: dev_dir.to_path_buf(),
+            gettys,
+        }
+    }
+
+    /// Gettys as the config at `path` and the kernel command line ask, with the
+    /// defaults if there is no config.
+    pub fn from_system(path: &Path) -> Self {
+        let config = match std::fs::read_to_string(path) {
+            Ok(content) => parse_config(&content).unwrap_or_else(|e| {
+                warn!(error = %e, "invalid getty config, using defaults");
+                GettyConfig::default()
+            }),
+            Err(_) => GettyConfig::default(),
+        };
+        let cmdline = std::fs::read_to_string(target::CMDLINE_PATH).unwrap_or_default();
+        let ttys = ttys(&config, &cmdline);
+        Self::new(config, ttys, Path::new(DEV_DIR))
+    }
+
+    /// Note the gettys that exited and start those that are due. A getty reaped by the
+    /// orphan reaper before its own check counts as exited all the same.
+    pub fn supervise(&mut self, now: Instant) {
+        for getty in &mut self.gettys {
+            if let Some(child) = getty.child.as_mut() {
+                match child.try_wait() {
+                    Ok(None) => continue,
+                    Ok(Some(status)) => info!(tty = %getty.tty, status = %status, "getty exited"),
+                    Err(_) => info!(tty = %getty.tty, "getty exited"),
+                }
+                getty.child = None;
+                let failing = now.saturating_duration_since(getty.started) < MIN_SESSION;
+                if failing {
+                    warn!(tty = %getty.tty, "getty exited right away, waiting before the next one");
+                }
+                getty.respawn_at = Some(if failing { now + RESPAWN_DELAY } else { now });
+            }
+
+            if getty.respawn_at.is_some_and(|at| now >= at) {
+                getty.respawn_at = None;
+                getty.started = now;
+                match command(&self.config, &self.dev_dir, &getty.tty).spawn() {
+                    Ok(child) => {
+                        info!(tty = %getty.tty, pid = child.id(), "getty started");
+                        getty.child = Some(child);
+                    }
+                    Err(e) => {
+                        warn!(tty = %getty.tty, error = %e, "failed to start getty");
+                        getty.respawn_at = Some(now + RESPAWN_DELAY);
+                    }
+                }
+            }
+        }
+    }
+
+    /// When the main loop next has to wake up to start a getty.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.gettys
+            .iter()
+            .filter_map(|getty| getty.respawn_at)
+            .min()
+    }
+
+    /// Kill every getty and stop starting them, for shutdown or to leave the console to
+    /// the rescue shell.
+    pub fn stop(&mut self) {
+        for getty in self.gettys.drain(..) {
+            if let Some(mut child) = getty.child {
+                let _ = child.kill();
+                let _ = child.wait();
+                info!(tty = %getty.tty, "getty stopped");
+            }
+        }
+    }
+}
+
+/// The getty for `tty`, which opens the terminal and starts a session on it itself.
+fn command(config: &GettyConfig, dev_dir: &Path, tty: &str) -> Command {
+    let mut cmd = Command::new(&config.program);
+    if let Some(login) = &config.login {
+        cmd.args(["-n", "-l", login]);
+    }
+    cmd.arg("-L")
+        .arg(config.baud.to_string())
+        .arg(dev_dir.join(tty))
+        .arg("vt100")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_console_is_the_last_one() {
+        assert_eq!(
+            kernel_console("console=tty0 console=ttyS0,115200n8 quiet").as_deref(),
+            Some("ttyS0")
+        );
+        assert_eq!(kernel_console("console=tty0").as_deref(), Some("tty1"));
+        assert_eq!(kernel_console("quiet"), None);
+    }
+
+    #[test]
+    fn config_lists_ttys_once() {
+        let config = parse_config("[getty]\nttys = [\"tty1\", \"ttyS0\"]").unwrap();
+        assert_eq!(config.program, "/sbin/getty");
+        assert_eq!(ttys(&config, "console=ttyS0"), ["tty1", "ttyS0"]);
+        assert_eq!(
+            ttys(&config, "console=ttyAMA0"),
+            ["tty1", "ttyS0", "ttyAMA0"]
+        );
+
+        let config = parse_config("[getty]\nkernel_console = false").unwrap();
+        assert!(ttys(&config, "console=ttyS0").is_empty());
+        assert!(parse_config("[getty]\nttys = \"tty1\"").is_err());
+    }
+
+    #[test]
+    fn getty_is_started_again_when_it_exits() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("ttyS0"), "").unwrap();
+        let config = GettyConfig {
+            program: "true".to_string(),
+            ..Default::default()
+        };
+        let mut gettys = Gettys::new(
+            config,
+            vec!["ttyS0".to_string(), "tty1".to_string()],
+            dir.path(),
+        );
+        assert_eq!(gettys.gettys.len(), 1);
+
+        let start = Instant::now();
+        gettys.supervise(start);
+        assert!(gettys.gettys[0].child.is_some());
+        assert_eq!(gettys.next_deadline(), None);
+
+        std::thread::sleep(Duration::from_millis(100));
+        // Exiting right after starting waits out the respawn delay
+        gettys.supervise(start);
+        assert!(gettys.gettys[0].child.is_none());
+        assert_eq!(gettys.next_deadline(), Some(start + RESPAWN_DELAY));
+
+        // A session that lasted is followed by a new getty straight away
+        gettys.supervise(start + RESPAWN_DELAY);
+        std::thread::sleep(Duration::from_millis(100));
+        let later = start + RESPAWN_DELAY + MIN_SESSION;
+        gettys.supervise(later);
+        assert!(gettys.gettys[0].child.is_some());
+        assert_eq!(gettys.gettys[0].started, later);
+
+        gettys.stop();
+        assert_eq!(gettys.next_deadline(), None);
+    }
+}
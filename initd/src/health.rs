@@ -0,0 +1,256 @@
+// ABOUTME: Health probes of running services: a command or a D-Bus ping, run periodically.
+// ABOUTME: Catches daemons that wedge while alive, such as one still holding its bus name.
+
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tracing::{error, warn};
+
+use crate::config::HealthConfig;
+use crate::dbus::DBUS_SEND;
+
+/// The command that probes a service as `health` says, without the service's
+/// environment or credentials.
+pub fn command(health: &HealthConfig) -> Command {
+    let mut cmd = match (&health.exec, &health.dbus_name) {
+        (Some(exec), _) => {
+            let mut cmd = Command::new("/bin/sh");
+            cmd.arg("-c").arg(exec);
+            cmd
+        }
+        (None, name) => {
+            let mut cmd = Command::new(DBUS_SEND);
+            cmd.arg("--system")
+                .arg("--print-reply")
+                .arg(format!("--reply-timeout={}", health.timeout_sec * 1000))
+                .arg(format!("--dest={}", name.as_deref().unwrap_or_default()))
+                .arg("/")
+                .arg("org.freedesktop.DBus.Peer.Ping");
+            cmd
+        }
+    };
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    cmd
+}
+
+struct Probe {
+    child: Child,
+    started: Instant,
+    /// How it exited, when the orphan reaper got to it before `HealthCheck::check`.
+    status: Option<ExitStatus>,
+}
+
+impl Drop for Probe {
+    /// A probe still running when its service goes away is killed with it.
+    fn drop(&mut self) {
+        if self.status.is_none() && matches!(self.child.try_wait(), Ok(None)) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// The health probes of one run of a service.
+pub struct HealthCheck {
+    service: String,
+    config: HealthConfig,
+    probe: Option<Probe>,
+    /// When the next probe starts, once the last one is done.
+    next: Instant,
+    /// Failed probes in a row.
+    failures: u32,
+}
+
+impl HealthCheck {
+    /// Probes for `service`, the first one interval from `now`.
+    pub fn new(service: &str, config: HealthConfig, now: Instant) -> Self {
+        let next = now + Duration::from_secs(config.interval_sec);
+        Self {
+            service: service.to_string(),
+            config,
+            probe: None,
+            next,
+            failures: 0,
+        }
+    }
+
+    pub fn probe_pid(&self) -> Option<u32> {
+        self.probe.as_ref().map(|probe| probe.child.id())
+    }
+
+    /// Note how the running probe exited, for when the orphan reaper reaped it.
+    pub fn reaped(&mut self, status: ExitStatus) {
+        if let Some(probe) = self.probe.as_mut() {
+            probe.status = Some(status);
+        }
+    }
+
+    /// Collect the probe that finished or ran out of time, and start the next one if it
+    /// is due, from `command`. Returns whether the service has now failed
+    /// `failure_threshold` probes in a row.
+    pub fn check(&mut self, now: Instant, command: impl FnOnce() -> Result<Command>) -> bool {
+        if let Some(result) = self.finish(now) {
+            self.next = now + Duration::from_secs(self.config.interval_sec);
+            match result {
+                Ok(()) => self.failures = 0,
+                Err(reason) => {
+                    self.failures += 1;
+                    warn!(
+                        service = %self.service,
+                        failures = self.failures,
+                        threshold = self.config.failure_threshold,
+                        reason = %reason,
+                        "health probe failed"
+                    );
+                }
+            }
+        }
+        if self.failures >= self.config.failure_threshold {
+            return true;
+        }
+
+        if self.probe.is_none() && now >= self.next {
+            match command().and_then(|mut cmd| Ok(cmd.spawn()?)) {
+                Ok(child) => {
+                    self.probe = Some(Probe {
+                        child,
+                        started: now,
+                        status: None,
+                    })
+                }
+                Err(e) => {
+                    error!(service = %self.service, error = %e, "failed to start health probe");
+                    self.next = now + Duration::from_secs(self.config.interval_sec);
+                }
+            }
+        }
+        false
+    }
+
+    /// How the running probe did, once it has exited or overrun its timeout.
+    fn finish(&mut self, now: Instant) -> Option<Result<(), String>> {
+        let probe = self.probe.as_mut()?;
+        let status = match probe.status {
+            Some(status) => Some(status),
+            None => probe.child.try_wait().ok().flatten(),
+        };
+        let result = match status {
+            Some(status) if status.success() => Ok(()),
+            Some(status) => Err(format!("exited with {status}")),
+            None if now >= probe.started + Duration::from_secs(self.config.timeout_sec) => {
+                Err("timed out".to_string())
+            }
+            None => return None,
+        };
+        // A probe that timed out is killed as it is dropped
+        self.probe = None;
+        Some(result)
+    }
+
+    /// When the running probe times out, or the next one is due.
+    pub fn next_deadline(&self) -> Instant {
+        match &self.probe {
+            Some(probe) => probe.started + Duration::from_secs(self.config.timeout_sec),
+            None => self.next,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exec_check(exec: &str) -> HealthConfig {
+        HealthConfig {
+            exec: Some(exec.to_string()),
+            dbus_name: None,
+            interval_sec: 30,
+            timeout_sec: 5,
+            failure_threshold: 2,
+        }
+    }
+
+    /// Wait for the running probe to exit, as the main loop does for SIGCHLD.
+    fn wait_for_probe(check: &mut HealthCheck) {
+        while check
+            .probe
+            .as_mut()
+            .is_some_and(|probe| matches!(probe.child.try_wait(), Ok(None)))
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn failures_in_a_row_make_a_service_unhealthy() {
+        let config = exec_check("test -e \"$PROBE_FILE\"");
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("ok");
+        let probe = || {
+            let mut cmd = command(&config);
+            cmd.env("PROBE_FILE", &marker);
+            Ok(cmd)
+        };
+        let interval = Duration::from_secs(30);
+        let start = Instant::now();
+        let mut check = HealthCheck::new("network", config.clone(), start);
+
+        // Nothing runs before the first interval is up
+        assert!(!check.check(start, || unreachable!()));
+        assert_eq!(check.next_deadline(), start + interval);
+
+        let mut now = start + interval;
+        assert!(!check.check(now, probe));
+        wait_for_probe(&mut check);
+        assert!(!check.check(now, || unreachable!()));
+        assert_eq!(check.failures, 1);
+
+        // A probe that passes starts the count over
+        std::fs::write(&marker, "").unwrap();
+        now += interval;
+        check.check(now, probe);
+        wait_for_probe(&mut check);
+        assert!(!check.check(now, || unreachable!()));
+        assert_eq!(check.failures, 0);
+
+        std::fs::remove_file(&marker).unwrap();
+        for unhealthy in [false, true] {
+            now += interval;
+            check.check(now, probe);
+            wait_for_probe(&mut check);
+            assert_eq!(check.check(now, || unreachable!()), unhealthy);
+        }
+    }
+
+    #[test]
+    fn stuck_probe_times_out() {
+        let config = exec_check("exec sleep 10");
+        let created = Instant::now();
+        let mut check = HealthCheck::new("network", config.clone(), created);
+        let start = created + Duration::from_secs(30);
+
+        assert!(!check.check(start, || Ok(command(&config))));
+        assert_eq!(check.next_deadline(), start + Duration::from_secs(5));
+        assert!(!check.check(start + Duration::from_secs(5), || unreachable!()));
+        assert_eq!(check.failures, 1);
+        assert!(check.probe.is_none());
+    }
+
+    #[test]
+    fn dbus_probe_pings_the_name() {
+        let config = HealthConfig {
+            exec: None,
+            dbus_name: Some("org.mobileos.Network".to_string()),
+            ..exec_check("")
+        };
+        let cmd = command(&config);
+        assert_eq!(cmd.get_program(), DBUS_SEND);
+        let args: Vec<_> = cmd.get_args().collect();
+        assert!(args.contains(&"--dest=org.mobileos.Network".as_ref()));
+        assert!(args.contains(&"--reply-timeout=5000".as_ref()));
+        assert_eq!(args.last().unwrap(), &"org.freedesktop.DBus.Peer.Ping");
+    }
+}
@@ -0,0 +1,114 @@
+// ABOUTME: Watches the service directories with inotify, so service files dropped in at runtime are picked up.
+// ABOUTME: The main loop polls the watch with its other fds and reloads services when a file changes.
+
+use anyhow::{Context, Result};
+use rustix::fs::inotify::{self, CreateFlags, ReadFlags, WatchFlags};
+use std::mem::MaybeUninit;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::path::Path;
+use tracing::error;
+
+/// A file written and closed, created (all a new symlink gets), moved in or out, or
+/// deleted. Deleting matters for timers, which stop being scheduled.
+fn watched() -> WatchFlags {
+    WatchFlags::CLOSE_WRITE
+        | WatchFlags::CREATE
+        | WatchFlags::MOVED_TO
+        | WatchFlags::MOVED_FROM
+        | WatchFlags::DELETE
+}
+
+pub struct ServiceWatch {
+    fd: OwnedFd,
+}
+
+impl ServiceWatch {
+    /// Watch `dirs`, which must exist.
+    pub fn new(dirs: &[&Path]) -> Result<Self> {
+        let fd = inotify::init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK)
+            .context("failed to create inotify instance")?;
+        for dir in dirs {
+            inotify::add_watch(&fd, *dir, watched())
+                .with_context(|| format!("failed to watch {}", dir.display()))?;
+        }
+        Ok(Self { fd })
+    }
+
+    /// Read every pending event. Returns whether any was about a service file, or the
+    /// kernel dropped events and one may have been.
+    pub fn take_changed(&self) -> bool {
+        let mut buf = [MaybeUninit::uninit(); 4096];
+        let mut reader = inotify::Reader::new(&self.fd, &mut buf);
+        let mut changed = false;
+        // Fails with EAGAIN once drained
+        while let Ok(event) = reader.next() {
+            changed |= event.events().contains(ReadFlags::QUEUE_OVERFLOW)
+                || event
+                    .file_name()
+                    .is_some_and(|name| name.to_bytes().ends_with(b".toml"));
+        }
+        changed
+    }
+}
+
+impl AsFd for ServiceWatch {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// Watch the service directories, creating `runtime_dir` first since it lives on /run.
+/// Without a watch, service files added at runtime wait for SIGUSR1.
+pub fn watch_from_system(dirs: &[&Path], runtime_dir: &Path) -> Option<ServiceWatch> {
+    if let Err(e) = std::fs::create_dir_all(runtime_dir) {
+        error!(
+            path = %runtime_dir.display(),
+            error = %e,
+            "failed to create runtime service directory"
+        );
+    }
+    let dirs: Vec<&Path> = dirs.iter().copied().filter(|dir| dir.exists()).collect();
+    match ServiceWatch::new(&dirs) {
+        Ok(watch) => Some(watch),
+        Err(e) => {
+            error!(error = %e, "failed to watch service directories");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_service_files_count_as_changes() {
+        let etc = tempfile::tempdir().unwrap();
+        let run = tempfile::tempdir().unwrap();
+        let watch = ServiceWatch::new(&[etc.path(), run.path()]).unwrap();
+        assert!(!watch.take_changed());
+
+        std::fs::write(etc.path().join("readme.txt"), "ignore me").unwrap();
+        assert!(!watch.take_changed());
+
+        std::fs::write(run.path().join("20-debug.toml"), "[service]").unwrap();
+        assert!(watch.take_changed());
+        // Drained by the first call
+        assert!(!watch.take_changed());
+
+        std::fs::remove_file(run.path().join("20-debug.toml")).unwrap();
+        assert!(watch.take_changed());
+    }
+
+    #[test]
+    fn missing_directories_are_skipped() {
+        let etc = tempfile::tempdir().unwrap();
+        let run = etc.path().join("run/mos/services");
+        let missing = Path::new("/nonexistent/services");
+
+        let watch = watch_from_system(&[etc.path(), missing, &run], &run).unwrap();
+        assert!(run.is_dir());
+        std::fs::write(run.join("20-debug.toml"), "[service]").unwrap();
+        assert!(watch.take_changed());
+    }
+}
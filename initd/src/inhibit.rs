@@ -0,0 +1,195 @@
+// ABOUTME: Shutdown inhibitors: apps mid-way through work that mustn't be cut off hold one.
+// ABOUTME: A shutdown or suspend waits for the holders to let go, but each only for the delay it asked for.
+
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+/// The longest one inhibitor may hold a shutdown back.
+pub const MAX_DELAY: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inhibitor {
+    pub id: u64,
+    pub who: String,
+    pub reason: String,
+    /// How long a shutdown waits for this holder.
+    pub delay: Duration,
+}
+
+#[derive(Default)]
+pub struct Inhibitors {
+    next_id: u64,
+    holders: Vec<Inhibitor>,
+    /// When shutdown was first held back, once it has been requested.
+    shutdown_since: Option<Instant>,
+}
+
+impl Inhibitors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take an inhibitor and return its id for releasing it. Refused once a shutdown is
+    /// under way, so holders can't keep extending it.
+    pub fn inhibit(&mut self, who: &str, reason: &str, delay: Duration) -> Option<u64> {
+        if self.shutdown_since.is_some() {
+            return None;
+        }
+        self.next_id += 1;
+        let delay = delay.min(MAX_DELAY);
+        info!(
+            id = self.next_id,
+            who,
+            reason,
+            delay_sec = delay.as_secs(),
+            "shutdown inhibited"
+        );
+        self.holders.push(Inhibitor {
+            id: self.next_id,
+            who: who.to_string(),
+            reason: reason.to_string(),
+            delay,
+        });
+        Some(self.next_id)
+    }
+
+    pub fn uninhibit(&mut self, id: u64) -> Option<Inhibitor> {
+        let i = self.holders.iter().position(|h| h.id == id)?;
+        let holder = self.holders.remove(i);
+        info!(id, who = %holder.who, "shutdown inhibitor released");
+        Some(holder)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Inhibitor> {
+        self.holders.iter()
+    }
+
+    /// Whether a requested shutdown still has to wait. The first call logs who holds it
+    /// back; holders whose delay ran out are dropped, and logged, on the way.
+    pub fn delays_shutdown(&mut self, now: Instant) -> bool {
+        let since = match self.shutdown_since {
+            Some(since) => since,
+            None => {
+                for holder in &self.holders {
+                    warn!(
+                        who = %holder.who,
+                        reason = %holder.reason,
+                        delay_sec = holder.delay.as_secs(),
+                        "shutdown delayed"
+                    );
+                }
+                *self.shutdown_since.insert(now)
+            }
+        };
+        self.holders.retain(|holder| {
+            let waiting = now < since + holder.delay;
+            if !waiting {
+                warn!(
+                    who = %holder.who,
+                    reason = %holder.reason,
+                    "shutdown no longer waiting for inhibitor"
+                );
+            }
+            waiting
+        });
+        !self.holders.is_empty()
+    }
+
+    /// Whether a suspend asked for at `since` still has to wait. Unlike a shutdown, it
+    /// leaves the holders whose delay ran out in place, for after the phone resumes.
+    pub fn delays_sleep(&self, since: Instant, now: Instant) -> bool {
+        self.holders.iter().any(|holder| now < since + holder.delay)
+    }
+
+    /// When the next holder's delay runs out for a suspend asked for at `since`.
+    pub fn sleep_deadline(&self, since: Instant, now: Instant) -> Option<Instant> {
+        self.holders
+            .iter()
+            .map(|holder| since + holder.delay)
+            .filter(|deadline| now < *deadline)
+            .min()
+    }
+
+    /// When the next holder's delay runs out during a shutdown.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let since = self.shutdown_since?;
+        self.holders.iter().map(|holder| since + holder.delay).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_waits_for_holders() {
+        let mut inhibitors = Inhibitors::new();
+        let now = Instant::now();
+        assert_eq!(inhibitors.next_deadline(), None);
+
+        let ota = inhibitors
+            .inhibit("ota", "installing update", Duration::from_secs(60))
+            .unwrap();
+        let sms = inhibitors
+            .inhibit("messages", "sending", Duration::from_secs(5))
+            .unwrap();
+        assert_ne!(ota, sms);
+        assert_eq!(inhibitors.iter().count(), 2);
+
+        assert!(inhibitors.delays_shutdown(now));
+        assert_eq!(
+            inhibitors.next_deadline(),
+            Some(now + Duration::from_secs(5))
+        );
+        assert!(
+            inhibitors
+                .inhibit("late", "", Duration::from_secs(1))
+                .is_none()
+        );
+
+        // Messages ran out of time, the update is still going
+        assert!(inhibitors.delays_shutdown(now + Duration::from_secs(5)));
+        assert_eq!(inhibitors.iter().map(|h| h.id).collect::<Vec<_>>(), [ota]);
+
+        assert_eq!(inhibitors.uninhibit(ota).unwrap().who, "ota");
+        assert!(inhibitors.uninhibit(ota).is_none());
+        assert!(!inhibitors.delays_shutdown(now + Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let mut inhibitors = Inhibitors::new();
+        inhibitors.inhibit("greedy", "", Duration::from_secs(86400));
+        assert_eq!(inhibitors.iter().next().unwrap().delay, MAX_DELAY);
+
+        let now = Instant::now();
+        assert!(inhibitors.delays_shutdown(now));
+        assert!(!inhibitors.delays_shutdown(now + MAX_DELAY));
+    }
+
+    #[test]
+    fn suspend_keeps_holders_that_ran_out() {
+        let mut inhibitors = Inhibitors::new();
+        let now = Instant::now();
+        inhibitors.inhibit("messages", "sending", Duration::from_secs(5));
+        inhibitors.inhibit("sync", "", Duration::from_secs(10));
+
+        assert!(inhibitors.delays_sleep(now, now));
+        assert_eq!(
+            inhibitors.sleep_deadline(now, now + Duration::from_secs(5)),
+            Some(now + Duration::from_secs(10))
+        );
+        assert!(!inhibitors.delays_sleep(now, now + Duration::from_secs(10)));
+        assert_eq!(inhibitors.iter().count(), 2);
+
+        // The next suspend waits for them again
+        let later = now + Duration::from_secs(60);
+        assert!(inhibitors.delays_sleep(later, later));
+        assert!(
+            inhibitors
+                .inhibit("late", "", Duration::from_secs(1))
+                .is_some()
+        );
+    }
+}
@@ -0,0 +1,230 @@
+// ABOUTME: Booting from an initramfs: mount the real root the kernel command line names and switch to it.
+// ABOUTME: Done right after the early mounts, so /etc and the services are read from the real root.
+
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use tracing::{error, info, warn};
+
+use crate::mount::{self, FstabEntry};
+use crate::target;
+
+/// Where the real root is mounted before it becomes /.
+pub const SYSROOT: &str = "/sysroot";
+
+/// How long to wait for the root device to show up, unless `rootdelay=` says otherwise.
+const DEFAULT_ROOT_DELAY: Duration = Duration::from_secs(10);
+
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Filesystem types the kernel unpacks an initramfs into.
+const INITRAMFS_TYPES: &[&str] = &["rootfs", "ramfs", "tmpfs"];
+
+/// Whether / is an initramfs, from the contents of /proc/self/mountinfo, where the type
+/// follows the " - " separator.
+pub fn is_initramfs(mountinfo: &str) -> bool {
+    mountinfo.lines().any(|line| {
+        let Some((mount, fs)) = line.split_once(" - ") else {
+            return false;
+        };
+        mount.split_whitespace().nth(4) == Some("/")
+            && fs
+                .split_whitespace()
+                .next()
+                .is_some_and(|fstype| INITRAMFS_TYPES.contains(&fstype))
+    })
+}
+
+/// The real root as the kernel command line names it: `root=` a device path or a
+/// `LABEL=`, `UUID=`, `PARTUUID=` or `PARTLABEL=` tag, with `rootfstype=` (ext4 when
+/// left out), `rootflags=` and `ro` or `rw` (read-only by default, as the kernel does).
+/// `None` when there is no `root=`.
+pub fn parse_root(cmdline: &str) -> Option<FstabEntry> {
+    let param = |name: &str| {
+        cmdline
+            .split_whitespace()
+            .rev()
+            .find_map(|word| word.strip_prefix(name))
+    };
+    let source = param("root=").filter(|root| !root.is_empty())?;
+    let writable = cmdline
+        .split_whitespace()
+        .rev()
+        .find(|word| *word == "ro" || *word == "rw")
+        == Some("rw");
+
+    let mut options = vec![if writable { "rw" } else { "ro" }.to_string()];
+    if let Some(flags) = param("rootflags=") {
+        options.extend(flags.split(',').filter(|f| !f.is_empty()).map(String::from));
+    }
+    Some(FstabEntry {
+        source: source.to_string(),
+        target: PathBuf::from(SYSROOT),
+        fstype: param("rootfstype=").unwrap_or("ext4").to_string(),
+        options,
+        pass: 0,
+    })
+}
+
+/// How long to wait for the root device: `rootdelay=` seconds, or the default.
+pub fn root_delay(cmdline: &str) -> Duration {
+    cmdline
+        .split_whitespace()
+        .rev()
+        .find_map(|word| word.strip_prefix("rootdelay="))
+        .and_then(|secs| secs.parse().ok())
+        .map_or(DEFAULT_ROOT_DELAY, Duration::from_secs)
+}
+
+/// Wait up to `timeout` for `device` to appear, as a slow eMMC or USB disk may not be
+/// probed yet when init starts.
+fn wait_for_device(device: &Path, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    while !device.exists() {
+        if Instant::now() >= deadline {
+            bail!("{} did not appear within {timeout:?}", device.display());
+        }
+        std::thread::sleep(DEVICE_POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// Delete everything under `dir` on the filesystem `dev`, except `keep`, without
+/// crossing into other mounts. Frees the memory the initramfs holds once it is left.
+pub fn remove_contents(dir: &Path, dev: u64, keep: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path == keep {
+            continue;
+        }
+        let Ok(meta) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if meta.dev() != dev {
+            continue;
+        }
+        let removed = if meta.is_dir() {
+            remove_contents(&path, dev, keep);
+            std::fs::remove_dir(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        if let Err(e) = removed {
+            warn!(path = %path.display(), error = %e, "failed to free initramfs file");
+        }
+    }
+}
+
+/// Make `new_root` the root: move the early mounts into it, free the initramfs, move
+/// it over / and chroot into it, as busybox's switch_root does.
+fn switch_root(new_root: &Path) -> Result<()> {
+    for early in mount::early_targets() {
+        let target = new_root.join(early.trim_start_matches('/'));
+        let _ = std::fs::create_dir_all(&target);
+        if let Err(e) = rustix::mount::mount_move(early, &target) {
+            warn!(mount = early, error = %e, "failed to move mount into the new root");
+        }
+    }
+
+    let dev = std::fs::metadata("/")
+        .context("failed to stat the initramfs")?
+        .dev();
+    std::env::set_current_dir(new_root)
+        .with_context(|| format!("failed to enter {}", new_root.display()))?;
+    remove_contents(Path::new("/"), dev, new_root);
+
+    rustix::mount::mount_move(".", "/").context("failed to move the new root over /")?;
+    rustix::process::chroot(".").context("failed to chroot into the new root")?;
+    std::env::set_current_dir("/").context("failed to enter the new root")?;
+    Ok(())
+}
+
+/// Mount the real root `root` names and switch to it.
+fn mount_and_switch(root: &FstabEntry, delay: Duration) -> Result<()> {
+    wait_for_device(&root.device(), delay)?;
+    mount::mount_entry(root)?;
+    switch_root(&root.target)
+}
+
+/// When running from an initramfs with a `root=` on the kernel command line, switch to
+/// that root. Init carries on from the initramfs if the root can't be mounted, so its
+/// getty or rescue shell can be used to find out why.
+pub fn switch_root_from_system() {
+    let mountinfo = std::fs::read_to_string(mount::MOUNTINFO_PATH).unwrap_or_default();
+    if !is_initramfs(&mountinfo) {
+        return;
+    }
+    let cmdline = std::fs::read_to_string(target::CMDLINE_PATH).unwrap_or_default();
+    let Some(root) = parse_root(&cmdline) else {
+        info!("running from an initramfs without root=, staying in it");
+        return;
+    };
+
+    info!(root = %root.source, fstype = %root.fstype, "switching from the initramfs to the root");
+    match mount_and_switch(&root, root_delay(&cmdline)) {
+        Ok(()) => info!(root = %root.source, "switched root"),
+        Err(e) => error!(
+            root = %root.source,
+            error = %e,
+            "failed to switch root, staying in the initramfs"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initramfs_is_told_from_a_disk_root() {
+        let initramfs = "1 1 0:2 / / rw - rootfs rootfs rw\n\
+                         15 1 0:5 / /dev rw,nosuid - devtmpfs devtmpfs rw\n";
+        let disk = "22 1 179:2 / / ro,relatime shared:1 - ext4 /dev/mmcblk0p2 ro\n\
+                    25 22 0:21 / /run rw - tmpfs tmpfs rw\n";
+        assert!(is_initramfs(initramfs));
+        assert!(!is_initramfs(disk));
+        assert!(!is_initramfs(""));
+    }
+
+    #[test]
+    fn root_comes_from_the_command_line() {
+        let root = parse_root("console=ttyS0 root=LABEL=mos-root rootfstype=erofs ro").unwrap();
+        assert_eq!(root.device(), Path::new("/dev/disk/by-label/mos-root"));
+        assert_eq!(root.fstype, "erofs");
+        assert_eq!(root.options, ["ro"]);
+        assert_eq!(root.target, Path::new(SYSROOT));
+
+        let root = parse_root("root=/dev/vda2 rw rootflags=noatime,data=ordered").unwrap();
+        assert_eq!(root.fstype, "ext4");
+        assert_eq!(root.options, ["rw", "noatime", "data=ordered"]);
+
+        assert_eq!(parse_root("console=ttyS0 quiet"), None);
+        assert_eq!(root_delay("rootdelay=30"), Duration::from_secs(30));
+        assert_eq!(root_delay(""), DEFAULT_ROOT_DELAY);
+    }
+
+    #[test]
+    fn initramfs_is_emptied_but_for_the_new_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("etc/mos")).unwrap();
+        std::fs::write(root.join("etc/mos/rootfs.toml"), "").unwrap();
+        std::fs::write(root.join("init"), "").unwrap();
+        std::os::unix::fs::symlink("/init", root.join("linuxrc")).unwrap();
+        std::fs::create_dir_all(root.join("sysroot/etc")).unwrap();
+
+        let dev = std::fs::metadata(root).unwrap().dev();
+        remove_contents(root, dev, &root.join("sysroot"));
+        let left: Vec<_> = std::fs::read_dir(root)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["sysroot"]);
+        assert!(root.join("sysroot/etc").exists());
+    }
+}
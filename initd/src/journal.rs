@@ -0,0 +1,367 @@
+// ABOUTME: Service output journal for the init system.
+// ABOUTME: Captures child stdout/stderr and kernel messages, tags lines with source and timestamp, and keeps bounded logs.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rustix::time::{clock_gettime, ClockId};
+use tracing::warn;
+
+pub const LOG_DIR: &str = "/run/mos/log";
+
+/// The name kernel messages are kept under, alongside the services.
+pub const KERNEL: &str = "kernel";
+
+/// Lines kept in memory per service.
+const RING_CAPACITY: usize = 1000;
+
+/// On-disk log size at which `<service>.log` is rotated to `<service>.log.1`.
+const MAX_FILE_SIZE: u64 = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    /// A kernel message, with its syslog priority (0 = emerg to 7 = debug).
+    Kernel(u8),
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stream::Stdout => f.write_str("stdout"),
+            Stream::Stderr => f.write_str("stderr"),
+            Stream::Kernel(priority) => f.write_str(match priority {
+                0 => "emerg",
+                1 => "alert",
+                2 => "crit",
+                3 => "err",
+                4 => "warning",
+                5 => "notice",
+                6 => "info",
+                _ => "debug",
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Time since boot (CLOCK_MONOTONIC), matching kernel log timestamps.
+    pub timestamp: Duration,
+    pub service: String,
+    pub stream: Stream,
+    pub line: String,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:>5}.{:06}] {}[{}]: {}",
+            self.timestamp.as_secs(),
+            self.timestamp.subsec_micros(),
+            self.service,
+            self.stream,
+            self.line
+        )
+    }
+}
+
+struct Inner {
+    dir: Option<PathBuf>,
+    rings: HashMap<String, VecDeque<Entry>>,
+    files: HashMap<String, File>,
+}
+
+/// Shared handle to the journal. Cloning is cheap; all clones write to the same store.
+#[derive(Clone)]
+pub struct Journal {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Journal {
+    /// Create a journal. With `dir` set, every line is also appended to `<dir>/<service>.log`.
+    pub fn new(dir: Option<&Path>) -> Self {
+        if let Some(dir) = dir
+            && let Err(e) = std::fs::create_dir_all(dir)
+        {
+            warn!(dir = %dir.display(), error = %e, "failed to create log directory");
+        }
+
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                dir: dir.map(Path::to_path_buf),
+                rings: HashMap::new(),
+                files: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn record(&self, service: &str, stream: Stream, line: &str) {
+        self.record_at(monotonic_now(), service, stream, line);
+    }
+
+    /// Record a line stamped by its source rather than on arrival, as kernel messages are.
+    pub fn record_at(&self, timestamp: Duration, service: &str, stream: Stream, line: &str) {
+        let entry = Entry {
+            timestamp,
+            service: service.to_string(),
+            stream,
+            line: line.to_string(),
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.write_to_file(&entry);
+
+        let ring = inner.rings.entry(service.to_string()).or_default();
+        if ring.len() == RING_CAPACITY {
+            ring.pop_front();
+        }
+        ring.push_back(entry);
+    }
+
+    /// Return the most recent entries for a service, oldest first.
+    pub fn entries(&self, service: &str, limit: Option<usize>) -> Vec<Entry> {
+        let inner = self.inner.lock().unwrap();
+        let Some(ring) = inner.rings.get(service) else {
+            return Vec::new();
+        };
+        let skip = limit.map_or(0, |n| ring.len().saturating_sub(n));
+        ring.iter().skip(skip).cloned().collect()
+    }
+
+    /// Return the most recent entries of every service and the kernel, interleaved by
+    /// timestamp, oldest first: the boot as a whole.
+    pub fn merged(&self, limit: Option<usize>) -> Vec<Entry> {
+        let inner = self.inner.lock().unwrap();
+        let mut entries: Vec<Entry> = inner.rings.values().flatten().cloned().collect();
+        // Stable, so lines from one source keep their order on equal timestamps
+        entries.sort_by_key(|e| e.timestamp);
+        let skip = limit.map_or(0, |n| entries.len().saturating_sub(n));
+        entries.split_off(skip)
+    }
+
+    /// Take the child's piped stdout/stderr and forward each line into the journal.
+    pub fn attach(&self, service: &str, child: &mut Child) {
+        if let Some(stdout) = child.stdout.take() {
+            self.spawn_reader(service, Stream::Stdout, stdout);
+        }
+        if let Some(stderr) = child.stderr.take() {
+            self.spawn_reader(service, Stream::Stderr, stderr);
+        }
+    }
+
+    fn spawn_reader<R: Read + Send + 'static>(&self, service: &str, stream: Stream, pipe: R) {
+        let journal = self.clone();
+        let service = service.to_string();
+        let spawned = std::thread::Builder::new()
+            .name(format!("log-{service}"))
+            .spawn(move || {
+                let mut reader = BufReader::new(pipe);
+                let mut buf = Vec::new();
+                loop {
+                    buf.clear();
+                    match reader.read_until(b'\n', &mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => {
+                            let line = String::from_utf8_lossy(&buf);
+                            journal.record(&service, stream, line.trim_end_matches(['\n', '\r']));
+                        }
+                    }
+                }
+            });
+
+        if let Err(e) = spawned {
+            warn!(error = %e, "failed to spawn log reader thread");
+        }
+    }
+}
+
+impl Inner {
+    fn write_to_file(&mut self, entry: &Entry) {
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+        let path = dir.join(format!("{}.log", entry.service));
+
+        let needs_rotation = self
+            .files
+            .get(&entry.service)
+            .and_then(|f| f.metadata().ok())
+            .is_some_and(|m| m.len() >= MAX_FILE_SIZE);
+        if needs_rotation {
+            self.files.remove(&entry.service);
+            let _ = std::fs::rename(&path, dir.join(format!("{}.log.1", entry.service)));
+        }
+
+        if !self.files.contains_key(&entry.service) {
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => {
+                    self.files.insert(entry.service.clone(), file);
+                }
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "failed to open log file");
+                    return;
+                }
+            }
+        }
+
+        if let Some(file) = self.files.get_mut(&entry.service) {
+            let _ = writeln!(file, "{entry}");
+        }
+    }
+}
+
+fn monotonic_now() -> Duration {
+    let ts = clock_gettime(ClockId::Monotonic);
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn records_and_returns_entries_in_order() {
+        let journal = Journal::new(None);
+        journal.record("svc", Stream::Stdout, "first");
+        journal.record("svc", Stream::Stderr, "second");
+        journal.record("other", Stream::Stdout, "unrelated");
+
+        let entries = journal.entries("svc", None);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].line, "first");
+        assert_eq!(entries[1].line, "second");
+        assert_eq!(entries[1].stream, Stream::Stderr);
+    }
+
+    #[test]
+    fn limit_returns_most_recent() {
+        let journal = Journal::new(None);
+        for i in 0..10 {
+            journal.record("svc", Stream::Stdout, &i.to_string());
+        }
+
+        let entries = journal.entries("svc", Some(3));
+        let lines: Vec<_> = entries.iter().map(|e| e.line.as_str()).collect();
+        assert_eq!(lines, vec!["7", "8", "9"]);
+    }
+
+    #[test]
+    fn ring_drops_oldest_when_full() {
+        let journal = Journal::new(None);
+        for i in 0..RING_CAPACITY + 5 {
+            journal.record("svc", Stream::Stdout, &i.to_string());
+        }
+
+        let entries = journal.entries("svc", None);
+        assert_eq!(entries.len(), RING_CAPACITY);
+        assert_eq!(entries[0].line, "5");
+    }
+
+    #[test]
+    fn unknown_service_has_no_entries() {
+        let journal = Journal::new(None);
+        assert!(journal.entries("missing", None).is_empty());
+    }
+
+    #[test]
+    fn entry_format_includes_service_and_stream() {
+        let entry = Entry {
+            timestamp: Duration::from_micros(12_345_678),
+            service: "modem".to_string(),
+            stream: Stream::Stderr,
+            line: "no SIM".to_string(),
+        };
+        assert_eq!(entry.to_string(), "[   12.345678] modem[stderr]: no SIM");
+    }
+
+    #[test]
+    fn kernel_entries_show_their_priority() {
+        let entry = Entry {
+            timestamp: Duration::from_micros(1_500),
+            service: KERNEL.to_string(),
+            stream: Stream::Kernel(4),
+            line: "EXT4-fs: mounted".to_string(),
+        };
+        assert_eq!(
+            entry.to_string(),
+            "[    0.001500] kernel[warning]: EXT4-fs: mounted"
+        );
+    }
+
+    #[test]
+    fn merged_interleaves_sources_by_timestamp() {
+        let journal = Journal::new(None);
+        journal.record_at(Duration::from_millis(30), "modem", Stream::Stdout, "up");
+        journal.record_at(
+            Duration::from_millis(10),
+            KERNEL,
+            Stream::Kernel(6),
+            "booting",
+        );
+        journal.record_at(Duration::from_millis(20), KERNEL, Stream::Kernel(6), "usb");
+        journal.record_at(Duration::from_millis(40), KERNEL, Stream::Kernel(3), "oops");
+
+        let lines: Vec<_> = journal.merged(None).into_iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec!["booting", "usb", "up", "oops"]);
+
+        let last: Vec<_> = journal
+            .merged(Some(2))
+            .into_iter()
+            .map(|e| e.line)
+            .collect();
+        assert_eq!(last, vec!["up", "oops"]);
+    }
+
+    #[test]
+    fn writes_log_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(Some(dir.path()));
+        journal.record("audio", Stream::Stdout, "ready");
+
+        let content = std::fs::read_to_string(dir.path().join("audio.log")).unwrap();
+        assert!(content.contains("audio[stdout]: ready"));
+    }
+
+    #[test]
+    fn rotates_log_file_when_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(Some(dir.path()));
+        let line = "x".repeat(1024);
+        for _ in 0..(MAX_FILE_SIZE / 1024 + 2) {
+            journal.record("chatty", Stream::Stdout, &line);
+        }
+
+        assert!(dir.path().join("chatty.log.1").exists());
+        let current = std::fs::metadata(dir.path().join("chatty.log")).unwrap();
+        assert!(current.len() < MAX_FILE_SIZE);
+    }
+
+    #[test]
+    fn captures_child_output() {
+        let journal = Journal::new(None);
+        let mut child = Command::new("sh")
+            .args(["-c", "echo hello; echo oops >&2"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        journal.attach("echoer", &mut child);
+        child.wait().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let entries = journal.entries("echoer", None);
+        assert!(entries.iter().any(|e| e.line == "hello" && e.stream == Stream::Stdout));
+        assert!(entries.iter().any(|e| e.line == "oops" && e.stream == Stream::Stderr));
+    }
+}
@@ -0,0 +1,166 @@
+// ABOUTME: Kernel message forwarding for the init system.
+// ABOUTME: Tails /dev/kmsg, parses each record's priority and timestamp, and merges it into the journal.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::journal::{Journal, KERNEL, Stream};
+
+pub const KMSG_PATH: &str = "/dev/kmsg";
+
+/// /dev/kmsg hands out one record per read, and fails reads too small for it.
+const READ_BUFFER: usize = 8192;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Syslog priority, 0 (emerg) to 7 (debug).
+    pub priority: u8,
+    /// Time since boot the kernel stamped it with.
+    pub timestamp: Duration,
+    pub message: String,
+}
+
+/// Parse a record header line: "<prefix>,<seq>,<usec>,<flags>[,...];<message>", where the
+/// prefix holds the facility and the priority in its low three bits. Continuation lines,
+/// which start with a space, carry device metadata and aren't records.
+pub fn parse_record(line: &str) -> Option<Record> {
+    let (header, message) = line.split_once(';')?;
+    let mut fields = header.split(',');
+    let prefix: u32 = fields.next()?.parse().ok()?;
+    let _sequence: u64 = fields.next()?.parse().ok()?;
+    let usec: u64 = fields.next()?.parse().ok()?;
+
+    Some(Record {
+        priority: (prefix & 7) as u8,
+        timestamp: Duration::from_micros(usec),
+        message: unescape(message),
+    })
+}
+
+/// The kernel writes unprintable bytes in messages as \xNN.
+fn unescape(message: &str) -> String {
+    let bytes = message.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && bytes.get(i + 1) == Some(&b'x')
+            && let Some(byte) = message
+                .get(i + 2..i + 4)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Forward every record read from `source` into the journal until it runs out. Reading
+/// /dev/kmsg starts at the oldest record still buffered, so the journal gets the boot
+/// from the start, then blocks for new ones.
+pub fn forward<R: Read>(mut source: R, journal: &Journal) {
+    let mut buf = vec![0; READ_BUFFER];
+    loop {
+        let n = match source.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            // Records were overwritten before we got to them; the next read resumes after
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => continue,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                warn!(error = %e, "failed to read kernel messages");
+                break;
+            }
+        };
+
+        let chunk = String::from_utf8_lossy(&buf[..n]);
+        for record in chunk
+            .lines()
+            .filter(|l| !l.starts_with(' '))
+            .filter_map(parse_record)
+        {
+            journal.record_at(
+                record.timestamp,
+                KERNEL,
+                Stream::Kernel(record.priority),
+                &record.message,
+            );
+        }
+    }
+}
+
+/// Tail `path` on a thread of its own for as long as init runs.
+pub fn spawn_reader(path: &Path, journal: Journal) {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "failed to open kernel log");
+            return;
+        }
+    };
+
+    let spawned = std::thread::Builder::new()
+        .name("kmsg".to_string())
+        .spawn(move || forward(file, &journal));
+    if let Err(e) = spawned {
+        warn!(error = %e, "failed to spawn kernel log reader thread");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_priority_and_timestamp() {
+        let record = parse_record("6,339,5140900,-;NET: Registered protocol family 10").unwrap();
+        assert_eq!(
+            record,
+            Record {
+                priority: 6,
+                timestamp: Duration::from_micros(5_140_900),
+                message: "NET: Registered protocol family 10".to_string(),
+            }
+        );
+
+        // Facility lives above the priority bits: user.err
+        let record = parse_record("11,340,5200000,-,caller=T1;mosd: oops").unwrap();
+        assert_eq!(record.priority, 3);
+    }
+
+    #[test]
+    fn unescapes_messages() {
+        let record = parse_record("4,1,10,-;tab\\x09here \\xzz").unwrap();
+        assert_eq!(record.message, "tab\there \\xzz");
+    }
+
+    #[test]
+    fn rejects_malformed_records() {
+        assert!(parse_record(" SUBSYSTEM=usb").is_none());
+        assert!(parse_record("no header here").is_none());
+        assert!(parse_record("x,1,10,-;bad prefix").is_none());
+    }
+
+    #[test]
+    fn forwards_records_into_the_journal() {
+        let kmsg = "6,1,1000,-;Linux version 6.1\n \
+                    SUBSYSTEM=cpu\n\
+                    3,2,2000,-;mmc0: timeout\n";
+        let journal = Journal::new(None);
+        forward(kmsg.as_bytes(), &journal);
+
+        let entries = journal.entries(KERNEL, None);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].line, "Linux version 6.1");
+        assert_eq!(entries[1].stream, Stream::Kernel(3));
+        assert_eq!(entries[1].timestamp, Duration::from_millis(2));
+    }
+}
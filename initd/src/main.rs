@@ -1,22 +1,63 @@
 // ABOUTME: MobileOS init system (PID 1).
 // ABOUTME: Mounts filesystems, starts services, and supervises the process tree.
 
+mod accounting;
+mod boot;
+mod bootcount;
+mod capabilities;
+mod condition;
 mod config;
+mod control;
+mod coredump;
+mod credentials;
+mod dbus;
 mod dependency;
+mod envfile;
+mod firmware;
+mod getty;
+mod health;
+mod hotplug;
+mod inhibit;
+mod initramfs;
+mod journal;
+mod kmsg;
 mod logging;
+mod modules;
 mod mount;
+mod notify;
+mod oom;
+mod panic;
+mod reaper;
+mod rescue;
+mod rootfs;
+mod sandbox;
+mod seccomp;
 mod service;
 mod shutdown;
 mod signals;
+mod sleep;
+mod storage;
+mod sysinit;
+mod target;
+mod timer;
+mod watchdog;
 
+use rustix::event::{poll, PollFd, PollFlags, Timespec};
+use rustix::io::Errno;
 use rustix::process::getpid;
+use std::os::fd::{AsFd, BorrowedFd};
 use std::path::Path;
+use std::time::Instant;
 use tracing::{error, info, warn};
 
 const SERVICES_DIR: &str = "/etc/mos/services";
+/// Services added at runtime, and overrides of those in `SERVICES_DIR` by file name.
+const RUNTIME_SERVICES_DIR: &str = "/run/mos/services";
 
 fn main() {
+    let boot_start = Instant::now();
     logging::init();
+    panic::install_hook();
 
     let pid = getpid();
     info!(pid = pid.as_raw_nonzero().get(), "MobileOS init starting");
@@ -30,32 +71,85 @@ fn main() {
     };
 
     mount::mount_early_filesystems();
+    // From an initramfs, the real root takes its place before anything is read from /etc
+    initramfs::switch_root_from_system();
+    // Before rootfs, whose developer overlays live on /data
+    mount::mount_fstab(Path::new(mount::FSTAB_PATH));
+    storage::init_from_system(Path::new(storage::CONFIG_PATH));
+    coredump::register_from_system();
+    // Once /lib/firmware is there, and before the services whose drivers need it
+    firmware::check_from_system();
+    let mut watchdog = watchdog::open_from_system();
+    let mut pressure = oom::open_from_system();
+    let mut rootfs = rootfs::Rootfs::init(Path::new(rootfs::CONFIG_PATH));
+    // Once developer overlays are up, so edits to /etc/hostname and /etc/sysctl.d count,
+    // and before the bus, which reads the machine id
+    sysinit::init_from_system();
+    // Listening before the devices are walked, so none plugged in meanwhile is missed
+    let uevents = modules::monitor_from_system();
+    // Once firmware and developer overlays are up, and before the network service and
+    // compositor, whose Wi-Fi and GPU drivers these are
+    modules::load_from_system();
+    // Once /data is mounted, and before the target is picked
+    let mut boot_count = bootcount::BootCount::start(Path::new(bootcount::COUNT_PATH));
+    let boot_target = if boot_count.safe_mode() {
+        rescue::RESCUE_TARGET.to_string()
+    } else {
+        target::select_from_system()
+    };
 
-    // Create runtime dirs and set D-Bus session bus address for child services
-    let _ = std::fs::create_dir_all("/run/dbus");
+    // Every service is told where to find the system bus
     // SAFETY: init is single-threaded at this point (before spawning any services)
     unsafe {
-        std::env::set_var("DBUS_SESSION_BUS_ADDRESS", "unix:path=/run/dbus/session_bus_socket");
+        std::env::set_var(
+            dbus::ADDRESS_ENV,
+            dbus::address(Path::new(dbus::SOCKET_PATH)),
+        );
     }
 
-    let mut manager = service::ServiceManager::new();
+    let journal = journal::Journal::new(Some(Path::new(journal::LOG_DIR)));
+    kmsg::spawn_reader(Path::new(kmsg::KMSG_PATH), journal.clone());
+    let seccomp_profiles = match seccomp::load_profiles_from_dir(Path::new(seccomp::PROFILES_DIR)) {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            error!(error = %e, "failed to load seccomp profiles");
+            std::collections::HashMap::new()
+        }
+    };
+    let mut manager = service::ServiceManager::new()
+        .with_journal(journal)
+        .with_seccomp_profiles(seccomp_profiles)
+        .with_notify_dir(Path::new(notify::NOTIFY_DIR))
+        .with_orphan_reaping()
+        .with_boot_start(boot_start);
+
+    let mut timers = timer::Timers::new(Instant::now());
+    let mut inhibitors = inhibit::Inhibitors::new();
+    let mut rescue = rescue::Rescue::new();
+    let mut reboot: Option<shutdown::RebootRequest> = None;
+    let mut sleep: Option<sleep::SleepRequest> = None;
+    let mut gettys = getty::Gettys::from_system(Path::new(getty::CONFIG_PATH));
+
+    let control = match control::ControlServer::bind(Path::new(control::CONTROL_SOCKET)) {
+        Ok(c) => Some(c),
+        Err(e) => {
+            error!(error = %e, "failed to bind control socket");
+            None
+        }
+    };
+
+    // Watched before loading, so files dropped in meanwhile are not missed
+    let watch = hotplug::watch_from_system(&service_dirs(), Path::new(RUNTIME_SERVICES_DIR));
 
     // Load and start services
-    match config::load_services_from_dir(Path::new(SERVICES_DIR)) {
+    let configs = load_configs(&boot_target);
+    manager.mark_boot_phase("service configs loaded");
+    // The dbus service runs the system bus from this config, which lets each service own
+    // only the bus names it declares
+    dbus::write_config_from_system(configs.as_deref().unwrap_or_default());
+    match configs {
         Ok(configs) if configs.is_empty() => {
-            warn!("no service configs found in {}, spawning fallback shell", SERVICES_DIR);
-            let fallback = config::ServiceConfig {
-                name: "console".to_string(),
-                exec: "/bin/sh".to_string(),
-                args: Vec::new(),
-                depends_on: Vec::new(),
-                restart: config::RestartPolicy::Always,
-                service_type: config::ServiceType::Simple,
-                environment: std::collections::HashMap::new(),
-            };
-            if let Err(e) = manager.start_service(fallback) {
-                error!(error = %e, "failed to start fallback shell");
-            }
+            warn!("no service configs found in {SERVICES_DIR}, only the gettys run");
         }
         Ok(configs) => {
             info!(count = configs.len(), "loaded service configs");
@@ -64,16 +158,20 @@ fn main() {
                 Ok(order) => {
                     info!(order = ?order, "resolved start order");
 
-                    let config_map: std::collections::HashMap<&str, &config::ServiceConfig> =
-                        configs.iter().map(|c| (c.name.as_str(), c)).collect();
+                    let mut config_map: std::collections::HashMap<String, config::ServiceConfig> =
+                        configs.into_iter().map(|c| (c.name.clone(), c)).collect();
+
+                    // Timer services start when their timer fires rather than at boot
+                    let (timed, boot): (Vec<_>, Vec<_>) = order
+                        .iter()
+                        .filter_map(|name| config_map.remove(name))
+                        .partition(|c| c.timer.is_some());
+                    timers.load(timed);
 
-                    for name in &order {
-                        if let Some(config) = config_map.get(name.as_str()) {
-                            if let Err(e) = manager.start_service((*config).clone()) {
-                                error!(service = %name, error = %e, "failed to start service");
-                            }
-                        }
-                    }
+                    // Dependents of notify and oneshot services start from the main loop
+                    // once those are ready or have finished
+                    manager.enqueue(boot);
+                    manager.start_pending();
                 }
                 Err(e) => {
                     error!(error = %e, "failed to resolve service dependencies");
@@ -87,25 +185,340 @@ fn main() {
 
     info!("entering main loop");
 
+    // Services with `watchdog_reset` that stopped answering their watchdog. While
+    // there are any, the hardware watchdog isn't petted and resets the phone.
+    let mut hung: Vec<String> = Vec::new();
+
     // Main event loop — PID 1 must never exit
     loop {
-        if signals.is_shutdown_requested() {
-            shutdown::perform_shutdown(&mut manager);
-            // If reboot syscall fails, just loop forever
-            loop {
-                std::thread::sleep(std::time::Duration::from_secs(60));
-            }
+        signals.clear_wakeups();
+        if let Some(watchdog) = watchdog.as_mut()
+            && hung.is_empty()
+        {
+            watchdog.pet_if_due(Instant::now());
+        }
+        // Stopping services holds up this loop, so the stops in one pass share a
+        // deadline that leaves time to pet the watchdog again
+        manager.limit_stopping(
+            watchdog
+                .as_ref()
+                .map(|watchdog| Instant::now() + watchdog.stop_budget()),
+        );
+
+        // Ahead of the shutdown check, so a reboot asked for here starts its inhibitor
+        // delay in this same pass
+        if let Some(ref control) = control {
+            panic::contain("control socket", || {
+                control.poll(&mut control::Context {
+                    manager: &mut manager,
+                    rootfs: &mut rootfs,
+                    timers: &timers,
+                    inhibitors: &mut inhibitors,
+                    rescue: &rescue,
+                    reboot: &mut reboot,
+                    sleep: &mut sleep,
+                })
+            });
+        }
+
+        let halt_requested = signals.is_shutdown_requested() || reboot.is_some();
+        if halt_requested && !inhibitors.delays_shutdown(Instant::now()) {
+            halt(&mut manager, &mut gettys, &mut watchdog, reboot.take());
         }
 
         if signals.take_child_exited() {
             manager.reap();
         }
+        gettys.supervise(Instant::now());
+        manager.restart_due();
+        timers.fire_due(&mut manager);
+
+        manager.poll_notifications();
+        for (name, action) in manager.check_start_timeouts() {
+            escalate(&mut manager, &mut gettys, &mut watchdog, &name, action);
+        }
+        hung = manager.check_watchdogs();
+        manager.check_health();
+        if let Some(ref mut pressure) = pressure
+            && pressure.take_kill(Instant::now())
+            && manager.kill_expendable().is_none()
+        {
+            warn!("memory pressure is sustained, but no expendable service is running");
+        }
+        // Also unblocks services that only wanted one that gave up without becoming
+        // ready, and fails those that required it
+        manager.start_pending();
+        for (name, reason) in manager.take_critical_failures() {
+            let keep = rescue::services_to_keep(Path::new(target::TARGETS_DIR), &name);
+            rescue.enter(&mut manager, &name, &reason, &keep, console_shell("rescue"));
+            // The rescue shell gets the console to itself
+            gettys.stop();
+        }
+        if let Some(request) = sleep.as_mut()
+            && request.advance(
+                &mut manager,
+                &inhibitors,
+                Path::new(sleep::POWER_STATE_PATH),
+                Instant::now(),
+            )
+        {
+            sleep = None;
+        }
+        let (settled, total) = manager.boot_progress();
+        boot_count.check(
+            Instant::now(),
+            settled == total && rescue.reason().is_none(),
+        );
+
+        let reload_requested = signals.take_reload_requested();
+        if reload_requested {
+            info!("reload requested (SIGUSR1)");
+        }
+        let files_changed = watch
+            .as_ref()
+            .is_some_and(hotplug::ServiceWatch::take_changed);
+        if reload_requested || files_changed {
+            reload_services(&mut manager, &mut timers, &boot_target);
+        }
+        if let Some(uevents) = uevents.as_ref() {
+            uevents.load_drivers();
+        }
+
+        let readable: Vec<BorrowedFd<'_>> = [
+            Some(signals.as_fd()),
+            control.as_ref().map(AsFd::as_fd),
+            watch.as_ref().map(AsFd::as_fd),
+            uevents.as_ref().map(AsFd::as_fd),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let deadlines = timers
+            .next_deadline()
+            .into_iter()
+            .chain(inhibitors.next_deadline())
+            .chain(gettys.next_deadline())
+            .chain(
+                watchdog
+                    .as_ref()
+                    .filter(|_| hung.is_empty())
+                    .map(watchdog::Watchdog::next_deadline),
+            )
+            .chain(boot_count.next_deadline())
+            .chain(
+                sleep
+                    .as_ref()
+                    .and_then(|request| request.next_deadline(&inhibitors, Instant::now())),
+            );
+        wait_for_events(&readable, &manager, deadlines, pressure.as_mut());
+    }
+}
+
+fn service_dirs() -> [&'static Path; 2] {
+    [Path::new(SERVICES_DIR), Path::new(RUNTIME_SERVICES_DIR)]
+}
+
+/// Load the services of the boot target `target` from the service directories.
+fn load_configs(target: &str) -> anyhow::Result<Vec<config::ServiceConfig>> {
+    panic::contain("service configs", || {
+        config::load_services_from_dirs(&service_dirs())
+    })
+    .unwrap_or_else(|| Err(anyhow::anyhow!("panicked while loading service configs")))
+    .map(|configs| apply_target(configs, target))
+}
 
-        if signals.take_reload_requested() {
-            info!("reload requested (SIGUSR1) — not yet implemented");
+/// Narrow the services to those of the boot target `name`. Without any targets
+/// configured, or when it can't be resolved, every service starts.
+fn apply_target(configs: Vec<config::ServiceConfig>, name: &str) -> Vec<config::ServiceConfig> {
+    let targets = match target::load_targets_from_dir(Path::new(target::TARGETS_DIR)) {
+        Ok(targets) if targets.is_empty() => return configs,
+        Ok(targets) => targets,
+        Err(e) => {
+            error!(error = %e, "failed to load targets, starting all services");
+            return configs;
         }
+    };
 
-        // Sleep briefly to avoid busy-waiting
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    match target::services_in(name, &targets) {
+        Ok(wanted) => {
+            info!(target = %name, "booting target");
+            target::filter_services(configs, &wanted)
+        }
+        Err(e) => {
+            error!(
+                target = %name,
+                error = %e,
+                "failed to resolve boot target, starting all services"
+            );
+            configs
+        }
+    }
+}
+
+/// Re-read the service directories. Timers are reloaded, keeping the schedules of those
+/// that didn't change, and services of the boot target init hasn't seen yet are started.
+/// Changes to services it already knows apply on next boot. On any error the current
+/// timers stay in place.
+fn reload_services(
+    manager: &mut service::ServiceManager,
+    timers: &mut timer::Timers,
+    target: &str,
+) {
+    let configs = match load_configs(target) {
+        Ok(configs) => configs,
+        Err(e) => {
+            error!(error = %e, "failed to reload service configs");
+            return;
+        }
+    };
+    let order = match dependency::resolve_start_order(&configs) {
+        Ok(order) => order,
+        Err(e) => {
+            error!(error = %e, "failed to resolve service dependencies, starting no new services");
+            Vec::new()
+        }
+    };
+
+    let known: std::collections::HashSet<String> = manager
+        .service_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let mut config_map: std::collections::HashMap<String, config::ServiceConfig> =
+        configs.into_iter().map(|c| (c.name.clone(), c)).collect();
+    let timed: Vec<_> = config_map
+        .extract_if(|_, c| c.timer.is_some())
+        .map(|(_, c)| c)
+        .collect();
+    info!(count = timed.len(), "reloaded timers");
+    timers.load(timed);
+
+    let added: Vec<_> = order
+        .iter()
+        .filter(|name| !known.contains(*name))
+        .filter_map(|name| config_map.remove(name))
+        .collect();
+    for config in &added {
+        info!(service = %config.name, "new service");
+    }
+    manager.enqueue(added);
+    manager.start_pending();
+}
+
+/// Sleep until one of the `readable` fds is: signals, control clients, changes to the
+/// service files. Or until a notify service sends a notification, or the manager's next
+/// start timeout or restart, or the first of `deadlines` is due: a timer, an inhibitor
+/// holding back shutdown or a suspend running out, a getty to start, petting the
+/// watchdog, or the boot count to clear. Memory pressure wakes it too, and is noted on the monitor.
+fn wait_for_events(
+    readable: &[BorrowedFd<'_>],
+    manager: &service::ServiceManager,
+    deadlines: impl Iterator<Item = Instant>,
+    pressure: Option<&mut oom::PressureMonitor>,
+) {
+    let mut fds: Vec<PollFd<'_>> = readable
+        .iter()
+        .map(|fd| PollFd::new(fd, PollFlags::IN))
+        .collect();
+    fds.extend(
+        manager
+            .notify_sockets()
+            .map(|socket| PollFd::new(socket, PollFlags::IN)),
+    );
+    // The kernel reports a pressure event as POLLPRI, once
+    let pressure_fd = pressure.as_ref().map(|monitor| {
+        fds.push(PollFd::new(&**monitor, PollFlags::PRI));
+        fds.len() - 1
+    });
+
+    // Past deadlines give a zero timeout; unrepresentably distant ones wait forever
+    let deadline = manager.next_deadline().into_iter().chain(deadlines).min();
+    let timeout = deadline.and_then(|deadline| {
+        Timespec::try_from(deadline.saturating_duration_since(Instant::now())).ok()
+    });
+
+    match poll(&mut fds, timeout.as_ref()) {
+        Ok(_) | Err(Errno::INTR) => {}
+        Err(e) => {
+            error!(error = %e, "poll failed");
+            // Don't spin if polling keeps failing
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+
+    let fired = pressure_fd.is_some_and(|i| fds[i].revents().contains(PollFlags::PRI));
+    drop(fds);
+    if fired && let Some(monitor) = pressure {
+        monitor.fired(Instant::now());
+    }
+}
+
+/// A shell on the kernel console, for when a critical service fails for good.
+fn console_shell(name: &str) -> config::ServiceConfig {
+    config::ServiceConfig {
+        name: name.to_string(),
+        exec: "/bin/sh".to_string(),
+        restart: config::RestartPolicy::Always,
+        output: config::OutputMode::Console,
+        ..Default::default()
+    }
+}
+
+/// Stop everything and power off, or reboot if `request` asks to. The hardware
+/// watchdog is closed first, so it can't reset the phone halfway through.
+fn halt(
+    manager: &mut service::ServiceManager,
+    gettys: &mut getty::Gettys,
+    watchdog: &mut Option<watchdog::Watchdog>,
+    request: Option<shutdown::RebootRequest>,
+) -> ! {
+    if let Some(watchdog) = watchdog.take() {
+        watchdog.close();
+    }
+    // With the watchdog gone, services get their whole stop timeouts
+    manager.limit_stopping(None);
+    gettys.stop();
+    match request {
+        Some(request) => {
+            shutdown::perform_reboot_with_reason(manager, request.mode, &request.reason)
+        }
+        None => shutdown::perform_shutdown(manager),
+    }
+    // If reboot syscall fails, just loop forever
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(60));
+    }
+}
+
+/// Apply a service's `on_failure` policy after it missed its start timeout.
+fn escalate(
+    manager: &mut service::ServiceManager,
+    gettys: &mut getty::Gettys,
+    watchdog: &mut Option<watchdog::Watchdog>,
+    name: &str,
+    action: config::FailureAction,
+) {
+    match action {
+        config::FailureAction::Ignore => {
+            warn!(service = %name, "continuing boot without service");
+        }
+        config::FailureAction::RescueShell => {
+            if manager.state("rescue") == service::ServiceState::Running {
+                return;
+            }
+            warn!(service = %name, "starting rescue shell on console");
+            gettys.stop();
+            if let Err(e) = manager.start_service(console_shell("rescue")) {
+                error!(error = %e, "failed to start rescue shell");
+            }
+        }
+        config::FailureAction::Reboot => {
+            warn!(service = %name, "rebooting after service failure");
+            let request = shutdown::RebootRequest {
+                mode: shutdown::RebootMode::Normal,
+                reason: format!("{name} missed its start timeout"),
+            };
+            halt(manager, gettys, watchdog, Some(request));
+        }
     }
 }
@@ -0,0 +1,297 @@
+// ABOUTME: Kernel module loading: the modules listed in /etc/mos/modules.d, and drivers for devices.
+// ABOUTME: Devices found at boot and hotplugged later are matched to drivers by modprobe from their modalias.
+
+use std::collections::BTreeSet;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use rustix::net::netlink::{self, SocketAddrNetlink};
+use rustix::net::{AddressFamily, RecvFlags, SocketFlags, SocketType};
+use tracing::{error, info, warn};
+
+pub const MODULES_DIR: &str = "/etc/mos/modules.d";
+
+pub const MODPROBE: &str = "/sbin/modprobe";
+
+/// Every device the kernel knows, each with a `modalias` file if a driver could bind it.
+pub const SYS_DEVICES: &str = "/sys/devices";
+
+/// The multicast group the kernel sends uevents to.
+const KERNEL_UEVENTS: u32 = 1;
+
+/// Largest uevent the kernel sends.
+const UEVENT_MAX: usize = 8192;
+
+/// The modules to load from the contents of a modules.d file: one per line, with any
+/// parameters after its name. Blank lines and `#` comments are skipped.
+pub fn parse_modules(content: &str) -> Vec<Vec<&str>> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.split_whitespace().collect())
+        .collect()
+}
+
+/// The modules listed in every `*.conf` file in `dir`, files in name order.
+pub fn load_modules_from_dir(dir: &Path) -> Result<Vec<Vec<String>>> {
+    let mut paths: Vec<_> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "conf"))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+    paths.sort();
+
+    let mut modules = Vec::new();
+    for path in paths {
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        modules.extend(
+            parse_modules(&content)
+                .into_iter()
+                .map(|words| words.into_iter().map(String::from).collect()),
+        );
+    }
+    Ok(modules)
+}
+
+/// The modaliases of every device under `dir`, without duplicates. Symlinks such as
+/// `subsystem` and `driver` are not followed, as they lead back into the tree.
+pub fn find_modaliases(dir: &Path) -> BTreeSet<String> {
+    let mut aliases = BTreeSet::new();
+    collect_modaliases(dir, &mut aliases);
+    aliases
+}
+
+fn collect_modaliases(dir: &Path, aliases: &mut BTreeSet<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            collect_modaliases(&entry.path(), aliases);
+        } else if file_type.is_file() && entry.file_name() == "modalias" {
+            let alias = std::fs::read_to_string(entry.path()).unwrap_or_default();
+            let alias = alias.trim();
+            if !alias.is_empty() {
+                aliases.insert(alias.to_string());
+            }
+        }
+    }
+}
+
+/// The modalias of a device the kernel announces in `uevent`, a datagram of NUL
+/// separated fields after an `action@devpath` header. Only added devices have one
+/// worth loading a driver for.
+pub fn parse_uevent(uevent: &[u8]) -> Option<String> {
+    let mut fields = uevent
+        .split(|&b| b == 0)
+        .filter_map(|field| std::str::from_utf8(field).ok());
+    let header = fields.next()?;
+    if !header.starts_with("add@") {
+        return None;
+    }
+    fields
+        .find_map(|field| field.strip_prefix("MODALIAS="))
+        .filter(|alias| !alias.is_empty())
+        .map(String::from)
+}
+
+/// modprobe run quietly, its output going to init's.
+fn modprobe() -> Command {
+    let mut cmd = Command::new(MODPROBE);
+    cmd.stdin(Stdio::null());
+    cmd
+}
+
+/// Load one module listed in modules.d, with its parameters.
+fn load_module(module: &[String]) -> Result<()> {
+    let status = modprobe()
+        .args(module)
+        .status()
+        .with_context(|| format!("failed to run {MODPROBE}"))?;
+    if !status.success() {
+        bail!("{MODPROBE} exited with {status}");
+    }
+    Ok(())
+}
+
+/// The modprobe that loads whatever drivers match `aliases`. Blacklisted modules are
+/// left alone, and aliases no module matches are not an error, as most devices are
+/// driven by built-in drivers or need none.
+fn modprobe_aliases<'a>(aliases: impl IntoIterator<Item = &'a String>) -> Command {
+    let mut cmd = modprobe();
+    cmd.arg("-a").arg("-b").arg("-q").args(aliases);
+    cmd
+}
+
+/// Load the modules listed in `dir`, then the drivers for the devices under `devices`.
+/// Waits for modprobe, so the drivers are bound before the services that need them.
+pub fn load(dir: &Path, devices: &Path) {
+    match load_modules_from_dir(dir) {
+        Ok(modules) => {
+            for module in &modules {
+                if let Err(e) = load_module(module) {
+                    error!(module = %module[0], error = %e, "failed to load module");
+                }
+            }
+            if !modules.is_empty() {
+                info!(count = modules.len(), "listed modules loaded");
+            }
+        }
+        Err(e) => error!(error = %e, "failed to read module lists"),
+    }
+
+    let aliases = find_modaliases(devices);
+    if aliases.is_empty() {
+        return;
+    }
+    match modprobe_aliases(&aliases).status() {
+        Ok(status) if status.success() => info!(devices = aliases.len(), "device drivers loaded"),
+        Ok(status) => warn!(status = %status, "some device drivers failed to load"),
+        Err(e) => error!(error = %e, "failed to run {MODPROBE}"),
+    }
+}
+
+pub fn load_from_system() {
+    load(Path::new(MODULES_DIR), Path::new(SYS_DEVICES));
+}
+
+/// The kernel's uevents, for loading drivers for devices plugged in after boot.
+pub struct UeventMonitor {
+    fd: OwnedFd,
+}
+
+impl UeventMonitor {
+    pub fn open() -> Result<Self> {
+        let fd = rustix::net::socket_with(
+            AddressFamily::NETLINK,
+            SocketType::DGRAM,
+            SocketFlags::CLOEXEC | SocketFlags::NONBLOCK,
+            Some(netlink::KOBJECT_UEVENT),
+        )
+        .context("failed to create uevent socket")?;
+        rustix::net::bind(&fd, &SocketAddrNetlink::new(0, KERNEL_UEVENTS))
+            .context("failed to bind uevent socket")?;
+        Ok(Self { fd })
+    }
+
+    /// Read every pending uevent. Returns the modaliases of the devices added.
+    pub fn take_modaliases(&self) -> Vec<String> {
+        let mut buf = [0u8; UEVENT_MAX];
+        let mut aliases = Vec::new();
+        // Fails with EAGAIN once drained
+        while let Ok((len, _)) = rustix::net::recv(&self.fd, &mut buf, RecvFlags::DONTWAIT) {
+            aliases.extend(parse_uevent(&buf[..len]));
+        }
+        aliases
+    }
+
+    /// Start loading the drivers for the devices added since the last call. modprobe is
+    /// not waited for; the orphan reaper collects it.
+    pub fn load_drivers(&self) {
+        let aliases = self.take_modaliases();
+        if aliases.is_empty() {
+            return;
+        }
+        info!(aliases = ?aliases, "loading drivers for hotplugged devices");
+        if let Err(e) = modprobe_aliases(&aliases).spawn() {
+            error!(error = %e, "failed to run {MODPROBE}");
+        }
+    }
+}
+
+impl AsFd for UeventMonitor {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// Listen for uevents, or log why not and go without hotplugged drivers.
+pub fn monitor_from_system() -> Option<UeventMonitor> {
+    match UeventMonitor::open() {
+        Ok(monitor) => Some(monitor),
+        Err(e) => {
+            error!(error = %e, "failed to listen for uevents");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_lists_are_read_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("20-wifi.conf"),
+            "# Wi-Fi\nbrcmfmac roamoff=1\n\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("10-gpu.conf"), "  lima\npanfrost\n").unwrap();
+        std::fs::write(dir.path().join("README"), "not-a-module\n").unwrap();
+
+        let modules = load_modules_from_dir(dir.path()).unwrap();
+        assert_eq!(
+            modules,
+            [
+                vec!["lima"],
+                vec!["panfrost"],
+                vec!["brcmfmac", "roamoff=1"]
+            ]
+        );
+        assert!(
+            load_modules_from_dir(&dir.path().join("missing"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn devices_are_found_by_modalias() {
+        let dir = tempfile::tempdir().unwrap();
+        let wifi = dir.path().join("platform/soc/mmc1/mmc1:0001/mmc1:0001:1");
+        let gpu = dir.path().join("platform/soc/1c40000.gpu");
+        std::fs::create_dir_all(&wifi).unwrap();
+        std::fs::create_dir_all(&gpu).unwrap();
+        std::fs::write(wifi.join("modalias"), "sdio:c00v02D0d4345\n").unwrap();
+        std::fs::write(gpu.join("modalias"), "of:NgpuT(null)Carm,mali-400\n").unwrap();
+        std::fs::write(dir.path().join("platform/soc/modalias"), "\n").unwrap();
+        // A loop back up the tree, as sysfs has plenty of
+        std::os::unix::fs::symlink(dir.path(), gpu.join("subsystem")).unwrap();
+
+        let aliases: Vec<_> = find_modaliases(dir.path()).into_iter().collect();
+        assert_eq!(
+            aliases,
+            ["of:NgpuT(null)Carm,mali-400", "sdio:c00v02D0d4345"]
+        );
+    }
+
+    #[test]
+    fn added_devices_have_a_modalias() {
+        let added = b"add@/devices/platform/soc/1c19000.usb/usb1/1-1\0ACTION=add\0\
+                      SUBSYSTEM=usb\0MODALIAS=usb:v0BDAp8179d0000\0SEQNUM=1234\0";
+        assert_eq!(parse_uevent(added).as_deref(), Some("usb:v0BDAp8179d0000"));
+
+        let removed = b"remove@/devices/platform/soc/1c19000.usb/usb1/1-1\0ACTION=remove\0\
+                        MODALIAS=usb:v0BDAp8179d0000\0";
+        assert_eq!(parse_uevent(removed), None);
+        assert_eq!(
+            parse_uevent(b"add@/devices/virtual/net/lo\0ACTION=add\0"),
+            None
+        );
+        // What udev rebroadcasts on its own group
+        assert_eq!(parse_uevent(b"libudev\0\xfe\xed\xca\xfe"), None);
+    }
+}
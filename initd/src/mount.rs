@@ -1,9 +1,13 @@
-// ABOUTME: Early filesystem mounting for the init system.
-// ABOUTME: Mounts /proc, /sys, /dev, /tmp, /run before services start.
+// ABOUTME: Filesystem mounting for the init system: /proc, /sys, /dev, /tmp, /run early,
+// ABOUTME: then the filesystems in /etc/fstab, checked with fsck, before services start.
 
+use anyhow::{bail, Context, Result};
 use rustix::mount::{mount, MountFlags};
 use std::ffi::{CStr, CString};
-use tracing::{error, info};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use tracing::{error, info, warn};
 
 struct MountPoint {
     source: &'static str,
@@ -45,6 +49,11 @@ const EARLY_MOUNTS: &[MountPoint] = &[
     },
 ];
 
+/// Where the early filesystems go, for moving them along when the root is switched.
+pub fn early_targets() -> impl Iterator<Item = &'static str> {
+    EARLY_MOUNTS.iter().map(|mp| mp.target)
+}
+
 pub fn mount_early_filesystems() {
     for mp in EARLY_MOUNTS {
         let _ = std::fs::create_dir_all(mp.target);
@@ -58,3 +67,349 @@ pub fn mount_early_filesystems() {
         }
     }
 }
+
+pub const FSTAB_PATH: &str = "/etc/fstab";
+
+/// Where the kernel lists what is mounted, for services' `requires_mount`.
+pub const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+pub const FSCK: &str = "/sbin/fsck";
+
+/// One line of /etc/fstab.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FstabEntry {
+    /// A device path, or `UUID=`, `LABEL=`, `PARTUUID=` or `PARTLABEL=` a partition.
+    pub source: String,
+    pub target: PathBuf,
+    pub fstype: String,
+    pub options: Vec<String>,
+    /// Order of the fsck pass; 0 skips checking.
+    pub pass: u32,
+}
+
+impl FstabEntry {
+    fn has_option(&self, option: &str) -> bool {
+        self.options.iter().any(|o| o == option)
+    }
+
+    /// The device to mount and check, with partition tags resolved through /dev/disk.
+    pub fn device(&self) -> PathBuf {
+        let tagged = [
+            ("UUID=", "by-uuid"),
+            ("LABEL=", "by-label"),
+            ("PARTUUID=", "by-partuuid"),
+            ("PARTLABEL=", "by-partlabel"),
+        ];
+        for (tag, dir) in tagged {
+            if let Some(value) = self.source.strip_prefix(tag) {
+                return Path::new("/dev/disk").join(dir).join(value);
+            }
+        }
+        PathBuf::from(&self.source)
+    }
+
+    /// Mount flags for the generic options, and the rest joined as the data string the
+    /// filesystem parses itself. Options meant for init, like `noauto`, are dropped.
+    fn mount_options(&self) -> (MountFlags, String) {
+        let mut flags = MountFlags::empty();
+        let mut data = Vec::new();
+        for option in &self.options {
+            let (flag, set) = match option.as_str() {
+                "ro" => (MountFlags::RDONLY, true),
+                "rw" => (MountFlags::RDONLY, false),
+                "nosuid" => (MountFlags::NOSUID, true),
+                "suid" => (MountFlags::NOSUID, false),
+                "nodev" => (MountFlags::NODEV, true),
+                "dev" => (MountFlags::NODEV, false),
+                "noexec" => (MountFlags::NOEXEC, true),
+                "exec" => (MountFlags::NOEXEC, false),
+                "sync" => (MountFlags::SYNCHRONOUS, true),
+                "async" => (MountFlags::SYNCHRONOUS, false),
+                "dirsync" => (MountFlags::DIRSYNC, true),
+                "noatime" => (MountFlags::NOATIME, true),
+                "nodiratime" => (MountFlags::NODIRATIME, true),
+                "relatime" => (MountFlags::RELATIME, true),
+                "strictatime" => (MountFlags::STRICTATIME, true),
+                "lazytime" => (MountFlags::LAZYTIME, true),
+                "defaults" | "auto" | "noauto" | "nofail" | "user" | "nouser" | "_netdev" => {
+                    continue;
+                }
+                other if other.starts_with("x-") => continue,
+                other => {
+                    data.push(other);
+                    continue;
+                }
+            };
+            flags.set(flag, set);
+        }
+        (flags, data.join(","))
+    }
+}
+
+/// Parse fstab: whitespace-separated source, target, type, options, dump and pass, with
+/// `\040` and the like for spaces in paths. The last three may be left out.
+pub fn parse_fstab(content: &str) -> Result<Vec<FstabEntry>> {
+    let mut entries = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<String> = line.split_whitespace().map(unescape).collect();
+        let [source, target, fstype, rest @ ..] = fields.as_slice() else {
+            bail!(
+                "line {}: expected at least source, target and type",
+                number + 1
+            );
+        };
+        if rest.len() > 3 {
+            bail!("line {}: too many fields", number + 1);
+        }
+        let options = rest.first().map_or("defaults", String::as_str);
+        let pass = match rest.get(2) {
+            Some(pass) => pass
+                .parse()
+                .with_context(|| format!("line {}: invalid fsck pass '{pass}'", number + 1))?,
+            None => 0,
+        };
+        entries.push(FstabEntry {
+            source: source.clone(),
+            target: PathBuf::from(target),
+            fstype: fstype.clone(),
+            options: options.split(',').map(String::from).collect(),
+            pass,
+        });
+    }
+    Ok(entries)
+}
+
+/// Decode the octal escapes fstab and mountinfo use for whitespace and backslashes.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|digits| digits.iter().all(|d| (b'0'..=b'7').contains(d)))
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok());
+        match (bytes[i], octal) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The entries init mounts at boot, parents before the mounts inside them. The root is
+/// left to rootfs, swap isn't mounted, and `noauto` entries are left alone.
+fn boot_entries(entries: &[FstabEntry]) -> Vec<&FstabEntry> {
+    let mut boot: Vec<&FstabEntry> = entries
+        .iter()
+        .filter(|e| e.target != Path::new("/") && e.fstype != "swap" && !e.has_option("noauto"))
+        .collect();
+    boot.sort_by_key(|e| e.target.components().count());
+    boot
+}
+
+/// Check and mount the filesystems listed in fstab, before any service starts. A
+/// failure is logged and the rest still mounted; services that need the mount fail to
+/// start through their `requires_mount`.
+pub fn mount_fstab(path: &Path) {
+    let entries = match std::fs::read_to_string(path) {
+        Ok(content) => match parse_fstab(&content) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "invalid fstab");
+                return;
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!(path = %path.display(), error = %e, "failed to read fstab");
+            return;
+        }
+    };
+
+    for entry in boot_entries(&entries) {
+        let target = entry.target.display();
+        let result = check_filesystem(Path::new(FSCK), entry).and_then(|()| mount_entry(entry));
+        match result {
+            Ok(()) => info!(target = %target, fstype = %entry.fstype, "mounted"),
+            Err(e) if entry.has_option("nofail") => {
+                warn!(target = %target, error = %e, "optional mount failed")
+            }
+            Err(e) => error!(target = %target, error = %e, "mount failed"),
+        }
+    }
+}
+
+/// Run fsck on entries with a pass number. Errors it corrected are fine; ones it
+/// couldn't correct keep the filesystem from being mounted. Without fsck installed,
+/// filesystems are mounted unchecked.
+pub fn check_filesystem(fsck: &Path, entry: &FstabEntry) -> Result<()> {
+    if entry.pass == 0 {
+        return Ok(());
+    }
+    let device = entry.device();
+    let status = match Command::new(fsck)
+        .arg("-a")
+        .arg("-t")
+        .arg(&entry.fstype)
+        .arg(&device)
+        .status()
+    {
+        Ok(status) => status,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!(device = %device.display(), "fsck not installed, mounting unchecked");
+            return Ok(());
+        }
+        Err(e) => return Err(e).context("failed to run fsck"),
+    };
+    fsck_verdict(&device, status)
+}
+
+/// fsck exits with 1 when it corrected errors, and 2 or 3 when it also wants a reboot;
+/// anything higher means errors are left.
+fn fsck_verdict(device: &Path, status: ExitStatus) -> Result<()> {
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(code @ 1..=3) => {
+            warn!(device = %device.display(), code, "fsck corrected filesystem errors");
+            Ok(())
+        }
+        _ => bail!("fsck of {} failed ({status})", device.display()),
+    }
+}
+
+pub fn mount_entry(entry: &FstabEntry) -> Result<()> {
+    let (flags, data) = entry.mount_options();
+    std::fs::create_dir_all(&entry.target)
+        .with_context(|| format!("failed to create {}", entry.target.display()))?;
+    let source = CString::new(entry.device().into_os_string().into_vec())?;
+    let fstype = CString::new(entry.fstype.as_str())?;
+    let data = CString::new(data)?;
+    let data = (!data.is_empty()).then_some(data.as_c_str());
+    mount(&source, &entry.target, &fstype, flags, data).with_context(|| {
+        format!(
+            "failed to mount {} on {}",
+            entry.source,
+            entry.target.display()
+        )
+    })
+}
+
+/// Whether something is mounted at `target`, per the kernel's mountinfo at `mountinfo`.
+pub fn is_mounted(mountinfo: &Path, target: &Path) -> bool {
+    let Ok(content) = std::fs::read_to_string(mountinfo) else {
+        return false;
+    };
+    content
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(4))
+        .any(|point| Path::new(&unescape(point)) == target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fstab() {
+        let fstab = "\
+# <source> <target> <type> <options> <dump> <pass>
+UUID=0a1b  /data  ext4  noatime,nodev,nosuid,errors=remount-ro  0  2
+
+/dev/mmcblk0p5 /data/media\\040card vfat ro,x-mos.label=card,utf8
+tmpfs /var/tmp tmpfs
+/dev/zram0 none swap defaults 0 0
+";
+        let entries = parse_fstab(fstab).unwrap();
+        assert_eq!(entries.len(), 4);
+
+        let data = &entries[0];
+        assert_eq!(data.device(), Path::new("/dev/disk/by-uuid/0a1b"));
+        assert_eq!(data.pass, 2);
+        let (flags, options) = data.mount_options();
+        assert_eq!(
+            flags,
+            MountFlags::NOATIME | MountFlags::NODEV | MountFlags::NOSUID
+        );
+        assert_eq!(options, "errors=remount-ro");
+
+        let card = &entries[1];
+        assert_eq!(card.target, Path::new("/data/media card"));
+        assert_eq!(
+            card.mount_options(),
+            (MountFlags::RDONLY, "utf8".to_string())
+        );
+        assert_eq!(entries[2].options, ["defaults"]);
+        assert_eq!(
+            entries[2].mount_options(),
+            (MountFlags::empty(), String::new())
+        );
+
+        assert!(parse_fstab("/dev/sda1 /mnt").is_err());
+        assert!(parse_fstab("/dev/sda1 /mnt ext4 defaults 0 x").is_err());
+        assert!(parse_fstab("/dev/sda1 /mnt ext4 defaults 0 1 extra").is_err());
+    }
+
+    #[test]
+    fn mounts_parents_first() {
+        let fstab = "\
+/dev/sda3 /data/media ext4 defaults
+/dev/sda1 / ext4 defaults
+/dev/sda2 /data ext4 defaults
+/dev/sda4 /mnt/usb vfat noauto
+/dev/zram0 none swap defaults
+";
+        let entries = parse_fstab(fstab).unwrap();
+        let targets: Vec<&Path> = boot_entries(&entries)
+            .iter()
+            .map(|e| e.target.as_path())
+            .collect();
+        assert_eq!(targets, [Path::new("/data"), Path::new("/data/media")]);
+    }
+
+    #[test]
+    fn fsck_failures_block_the_mount() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut entry = parse_fstab("/dev/sda2 /data ext4 defaults 0 2")
+            .unwrap()
+            .remove(0);
+        let device = entry.device();
+        let exited = |code: i32| ExitStatus::from_raw(code << 8);
+        assert!(fsck_verdict(&device, exited(0)).is_ok());
+        assert!(fsck_verdict(&device, exited(1)).is_ok());
+        assert!(fsck_verdict(&device, exited(4)).is_err());
+        assert!(fsck_verdict(&device, ExitStatus::from_raw(9)).is_err());
+
+        assert!(check_filesystem(Path::new("true"), &entry).is_ok());
+        assert!(check_filesystem(Path::new("/nonexistent/fsck"), &entry).is_ok());
+        entry.pass = 0;
+        assert!(check_filesystem(Path::new("false"), &entry).is_ok());
+    }
+
+    #[test]
+    fn reads_mountinfo() {
+        let dir = tempfile::tempdir().unwrap();
+        let mountinfo = dir.path().join("mountinfo");
+        std::fs::write(
+            &mountinfo,
+            "22 1 8:2 / / rw,relatime - ext4 /dev/sda2 rw\n\
+             30 22 8:3 / /data/media\\040card rw - vfat /dev/sda3 rw\n",
+        )
+        .unwrap();
+        assert!(is_mounted(&mountinfo, Path::new("/")));
+        assert!(is_mounted(&mountinfo, Path::new("/data/media card")));
+        assert!(!is_mounted(&mountinfo, Path::new("/data")));
+        assert!(!is_mounted(&dir.path().join("missing"), Path::new("/")));
+    }
+}
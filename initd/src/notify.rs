@@ -0,0 +1,137 @@
+// ABOUTME: Readiness notification for notify-type services.
+// ABOUTME: Gives each service a datagram socket, passed in NOTIFY_SOCKET, on which it reports READY=1 and WATCHDOG=1.
+
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+pub const NOTIFY_DIR: &str = "/run/mos/notify";
+
+/// Largest datagram we accept; notifications are a few short KEY=VALUE lines.
+const MAX_DATAGRAM: usize = 4096;
+
+/// What a service told us in one datagram.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// The service has finished starting up (`READY=1`).
+    pub ready: bool,
+    /// The service is alive and well, as its watchdog asks (`WATCHDOG=1`).
+    pub watchdog: bool,
+    /// Free-form status text (`STATUS=...`).
+    pub status: Option<String>,
+}
+
+/// Parse newline-separated `KEY=VALUE` assignments, ignoring keys we don't know.
+pub fn parse(datagram: &[u8]) -> Notification {
+    let mut notification = Notification::default();
+    for line in String::from_utf8_lossy(datagram).lines() {
+        match line.split_once('=') {
+            Some(("READY", "1")) => notification.ready = true,
+            Some(("WATCHDOG", "1")) => notification.watchdog = true,
+            Some(("STATUS", status)) => notification.status = Some(status.to_string()),
+            _ => {}
+        }
+    }
+    notification
+}
+
+/// One service's notification socket. Per-service sockets mean the sender is known
+/// without checking peer credentials.
+pub struct NotifySocket {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl NotifySocket {
+    /// Bind `<dir>/<service>.sock`, replacing a socket left by a previous instance.
+    pub fn bind(dir: &Path, service: &str) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{service}.sock"));
+        let _ = std::fs::remove_file(&path);
+
+        let socket = UnixDatagram::bind(&path)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read every pending notification without blocking.
+    pub fn receive(&self) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+        let mut buf = [0u8; MAX_DATAGRAM];
+        loop {
+            match self.socket.recv(&mut buf) {
+                Ok(n) => notifications.push(parse(&buf[..n])),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!(path = %self.path.display(), error = %e, "failed to read notification");
+                    break;
+                }
+            }
+        }
+        notifications
+    }
+}
+
+/// Readable whenever a notification is waiting, for the main loop to poll on.
+impl AsFd for NotifySocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ready_and_status() {
+        let n = parse(b"STATUS=acquired bus name\nREADY=1\n");
+        assert!(n.ready);
+        assert!(!n.watchdog);
+        assert_eq!(n.status.as_deref(), Some("acquired bus name"));
+    }
+
+    #[test]
+    fn parse_ignores_unknown_and_malformed_lines() {
+        let n = parse(b"ERRNO=2\nREADY=0\ngarbage");
+        assert_eq!(n, Notification::default());
+    }
+
+    #[test]
+    fn parse_watchdog_pings() {
+        assert!(parse(b"WATCHDOG=1").watchdog);
+        assert!(!parse(b"WATCHDOG=trigger").watchdog);
+    }
+
+    #[test]
+    fn socket_receives_datagrams() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = NotifySocket::bind(dir.path(), "power").unwrap();
+        assert_eq!(socket.path(), dir.path().join("power.sock"));
+        assert!(socket.receive().is_empty());
+
+        let client = UnixDatagram::unbound().unwrap();
+        client.send_to(b"STATUS=starting", socket.path()).unwrap();
+        client.send_to(b"READY=1", socket.path()).unwrap();
+
+        let received = socket.receive();
+        assert_eq!(received.len(), 2);
+        assert!(!received[0].ready);
+        assert!(received[1].ready);
+    }
+
+    #[test]
+    fn rebinding_replaces_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = NotifySocket::bind(dir.path(), "power").unwrap();
+        drop(first);
+        assert!(NotifySocket::bind(dir.path(), "power").is_ok());
+    }
+}
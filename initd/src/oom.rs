@@ -0,0 +1,204 @@
+// ABOUTME: Out-of-memory policy: each service's oom_score_adj, and a memory pressure monitor.
+// ABOUTME: Under sustained pressure init kills expendable services itself, before the kernel OOM killer picks the compositor.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use tracing::{error, info};
+
+pub const PSI_PATH: &str = "/proc/pressure/memory";
+
+pub const MIN_SCORE_ADJ: i32 = -1000;
+pub const MAX_SCORE_ADJ: i32 = 1000;
+
+/// The score of expendable services that don't set one, so the kernel goes for them
+/// first too.
+pub const EXPENDABLE_SCORE_ADJ: i32 = 900;
+
+/// PSI trigger: some task stalled on memory for 150ms of a 1s window. The kernel wants
+/// the terminating NUL written too.
+const TRIGGER: &[u8] = b"some 150000 1000000\0";
+
+/// The trigger fires at most once per window; this many firings this close together
+/// count as sustained pressure.
+const SUSTAINED_EVENTS: usize = 3;
+const SUSTAINED_WINDOW: Duration = Duration::from_secs(5);
+
+/// After a kill, the memory it frees gets this long to show before the next one.
+const KILL_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// Set the calling process's oom_score_adj to `value`, already formatted. Runs between
+/// fork and exec, so it only makes raw syscalls; lowering the score needs root, so this
+/// goes before the switch to the service's user.
+pub fn set_score_adj(value: &[u8]) -> std::io::Result<()> {
+    // SAFETY: open only reads the C string; write reads `value`, which outlives it.
+    unsafe {
+        let fd = libc::open(
+            c"/proc/self/oom_score_adj".as_ptr(),
+            libc::O_WRONLY | libc::O_CLOEXEC,
+        );
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(fd, value.as_ptr().cast(), value.len());
+        let result = if written < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+        libc::close(fd);
+        result
+    }
+}
+
+/// Resident pages of process `pid`, or 0 if it can't be read.
+pub fn resident_pages(pid: u32) -> u64 {
+    std::fs::read_to_string(format!("/proc/{pid}/statm"))
+        .ok()
+        .and_then(|statm| statm.split_whitespace().nth(1)?.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Watches the kernel's memory pressure stall information for sustained pressure.
+pub struct PressureMonitor {
+    file: File,
+    /// When the trigger fired, within the last `SUSTAINED_WINDOW`.
+    events: VecDeque<Instant>,
+    last_kill: Option<Instant>,
+}
+
+impl PressureMonitor {
+    /// Set up a pressure trigger on `path`. Returns `None` if the kernel has no PSI.
+    pub fn open(path: &Path) -> Result<Option<Self>> {
+        let mut file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to open {}", path.display())),
+        };
+        file.write_all(TRIGGER)
+            .context("failed to set memory pressure trigger")?;
+        Ok(Some(Self::with_file(file)))
+    }
+
+    fn with_file(file: File) -> Self {
+        Self {
+            file,
+            events: VecDeque::new(),
+            last_kill: None,
+        }
+    }
+
+    /// Note that the trigger fired. The main loop polls the monitor for POLLPRI along
+    /// with its other fds, and the kernel clears the event once it is seen.
+    pub fn fired(&mut self, now: Instant) {
+        self.events.push_back(now);
+    }
+
+    /// Whether pressure has lasted long enough that something has to be killed. Once it
+    /// has, it must last as long again, and the cooldown pass, before the next kill.
+    pub fn take_kill(&mut self, now: Instant) -> bool {
+        while self
+            .events
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > SUSTAINED_WINDOW)
+        {
+            self.events.pop_front();
+        }
+        if self.events.len() < SUSTAINED_EVENTS
+            || self
+                .last_kill
+                .is_some_and(|t| now.duration_since(t) < KILL_COOLDOWN)
+        {
+            return false;
+        }
+        self.events.clear();
+        self.last_kill = Some(now);
+        true
+    }
+}
+
+impl AsFd for PressureMonitor {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+/// Watch system memory pressure. Kernels without PSI, and failures to set the trigger,
+/// leave expendable services to the kernel OOM killer.
+pub fn open_from_system() -> Option<PressureMonitor> {
+    match PressureMonitor::open(Path::new(PSI_PATH)) {
+        Ok(Some(monitor)) => Some(monitor),
+        Ok(None) => {
+            info!("no memory pressure information, leaving it to the OOM killer");
+            None
+        }
+        Err(e) => {
+            error!(error = %e, "failed to watch memory pressure");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor() -> PressureMonitor {
+        PressureMonitor::with_file(tempfile::tempfile().unwrap())
+    }
+
+    #[test]
+    fn only_sustained_pressure_kills() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut pressure = monitor();
+
+        // Spread out too far to count as sustained
+        for secs in [0, 3, 6, 9] {
+            pressure.fired(at(secs));
+            assert!(!pressure.take_kill(at(secs)));
+        }
+
+        pressure.fired(at(10));
+        pressure.fired(at(11));
+        assert!(pressure.take_kill(at(11)));
+        // The events that led to the kill don't count again
+        assert!(!pressure.take_kill(at(12)));
+    }
+
+    #[test]
+    fn kills_wait_out_the_cooldown() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut pressure = monitor();
+
+        for secs in [0, 1, 2] {
+            pressure.fired(at(secs));
+        }
+        assert!(pressure.take_kill(at(2)));
+
+        for secs in [3, 4, 5] {
+            pressure.fired(at(secs));
+        }
+        assert!(!pressure.take_kill(at(5)));
+        pressure.fired(at(7));
+        assert!(pressure.take_kill(at(7)));
+    }
+
+    #[test]
+    fn resident_pages_of_running_process() {
+        assert!(resident_pages(std::process::id()) > 0);
+        assert_eq!(resident_pages(u32::MAX), 0);
+    }
+}
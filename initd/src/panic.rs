@@ -0,0 +1,109 @@
+// ABOUTME: Panic containment for PID 1, where unwinding out of main would panic the kernel.
+// ABOUTME: Records panics to the console and pstore, and falls back to a minimal recovery loop.
+
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::process::Command;
+use std::time::Duration;
+
+use tracing::error;
+
+use crate::reaper;
+
+/// pstore's userspace message device: what is written here survives a reboot under
+/// /sys/fs/pstore, so a panic can be read back after the watchdog resets the device.
+pub const PMSG_DEVICE: &str = "/dev/pmsg0";
+
+/// How often the recovery loop reaps children and checks on its shell.
+const RECOVERY_INTERVAL: Duration = Duration::from_millis(500);
+
+thread_local! {
+    /// Depth of `contain` calls on this thread; panics inside one unwind normally.
+    static CONTAINED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Install the hook that records every panic and, for an uncontained panic on init's
+/// main thread, enters the recovery loop instead of unwinding. Other threads, like the
+/// journal readers, just die after the panic is recorded.
+pub fn install_hook() {
+    panic::set_hook(Box::new(|info| {
+        let report = format!("initd: {info}\n{}\n", Backtrace::force_capture());
+        let _ = std::io::stderr().write_all(report.as_bytes());
+
+        if CONTAINED.with(Cell::get) > 0 {
+            return;
+        }
+        let _ = std::fs::OpenOptions::new()
+            .write(true)
+            .open(PMSG_DEVICE)
+            .and_then(|mut pmsg| pmsg.write_all(report.as_bytes()));
+
+        if std::process::id() == 1 && std::thread::current().name() == Some("main") {
+            recovery_loop();
+        }
+    }));
+}
+
+/// Run a non-critical subsystem, turning a panic in it into `None` instead of taking
+/// down init.
+pub fn contain<T>(subsystem: &str, f: impl FnOnce() -> T) -> Option<T> {
+    CONTAINED.with(|depth| depth.set(depth.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CONTAINED.with(|depth| depth.set(depth.get() - 1));
+
+    match result {
+        Ok(value) => Some(value),
+        Err(_) => {
+            error!(subsystem, "subsystem panicked, continuing without it");
+            None
+        }
+    }
+}
+
+/// Last resort after init's own state can no longer be trusted. PID 1 must never exit,
+/// so keep a shell on the console and keep reaping orphans until someone reboots.
+fn recovery_loop() -> ! {
+    let _ = writeln!(
+        std::io::stderr(),
+        "initd: entering recovery mode; services are no longer supervised"
+    );
+
+    let mut shell: Option<u32> = None;
+    loop {
+        if reaper::reap_all()
+            .iter()
+            .any(|(pid, _)| shell == Some(*pid))
+        {
+            shell = None;
+        }
+
+        if shell.is_none() {
+            match Command::new("/bin/sh").spawn() {
+                Ok(child) => shell = Some(child.id()),
+                Err(e) => {
+                    let _ = writeln!(std::io::stderr(), "initd: failed to start shell: {e}");
+                }
+            }
+        }
+
+        std::thread::sleep(RECOVERY_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contain_passes_values_through() {
+        assert_eq!(contain("test", || 42), Some(42));
+    }
+
+    #[test]
+    fn contain_catches_panics() {
+        assert_eq!(contain("test", || -> u32 { panic!("boom") }), None);
+        assert_eq!(CONTAINED.with(Cell::get), 0);
+    }
+}
@@ -0,0 +1,21 @@
+// ABOUTME: Reaping of every exited child of PID 1, services and orphans alike.
+// ABOUTME: Orphaned grandchildren are reparented to init and would otherwise stay zombies.
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use rustix::process::{WaitOptions, wait};
+
+/// Reap every child that has exited so far, without blocking. Returns their pids and
+/// exit statuses.
+pub fn reap_all() -> Vec<(u32, ExitStatus)> {
+    let mut reaped = Vec::new();
+    // Stops with ECHILD once there are no children left at all
+    while let Ok(Some((pid, status))) = wait(WaitOptions::NOHANG) {
+        reaped.push((
+            pid.as_raw_nonzero().get() as u32,
+            ExitStatus::from_raw(status.as_raw()),
+        ));
+    }
+    reaped
+}
@@ -0,0 +1,142 @@
+// ABOUTME: Rescue mode, entered when a critical service such as the compositor fails for good.
+// ABOUTME: Stops everything outside the early target and leaves a shell on the console.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use tracing::{error, warn};
+
+use crate::config::ServiceConfig;
+use crate::service::{ServiceManager, ServiceState};
+use crate::target;
+
+/// The target whose services keep running in rescue mode: the system bus and the
+/// developer bridge.
+pub const RESCUE_TARGET: &str = "early";
+
+/// Name of the shell service started on the console.
+const SHELL: &str = "rescue";
+
+#[derive(Default)]
+pub struct Rescue {
+    /// Which service failed and why, once rescue mode is entered.
+    reason: Option<String>,
+}
+
+impl Rescue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    /// Enter rescue mode after `service` failed for good: stop the services outside
+    /// `keep` and start `shell`. Failures after the first are only logged.
+    pub fn enter(
+        &mut self,
+        manager: &mut ServiceManager,
+        service: &str,
+        reason: &str,
+        keep: &HashSet<String>,
+        shell: ServiceConfig,
+    ) {
+        if self.reason.is_some() {
+            warn!(service = %service, reason = %reason, "critical service failed in rescue mode");
+            return;
+        }
+        error!(
+            service = %service,
+            reason = %reason,
+            "critical service failed, entering rescue mode"
+        );
+        self.reason = Some(format!("{service}: {reason}"));
+
+        manager.stop_except(keep);
+        if manager.state(SHELL) != ServiceState::Running
+            && let Err(e) = manager.start_service(ServiceConfig {
+                name: SHELL.to_string(),
+                ..shell
+            })
+        {
+            error!(error = %e, "failed to start rescue shell");
+        }
+    }
+}
+
+/// The services of the rescue target, less the one that failed. Without targets, or if
+/// the rescue target can't be resolved, nothing but the shell is kept.
+pub fn services_to_keep(targets_dir: &Path, failed: &str) -> HashSet<String> {
+    let mut keep = match target::load_targets_from_dir(targets_dir) {
+        Ok(targets) => target::services_in(RESCUE_TARGET, &targets).unwrap_or_else(|e| {
+            warn!(error = %e, "rescue target unavailable, stopping every service");
+            HashSet::new()
+        }),
+        Err(e) => {
+            warn!(error = %e, "failed to load targets, stopping every service");
+            HashSet::new()
+        }
+    };
+    keep.remove(failed);
+    keep.insert(SHELL.to_string());
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sleeper(name: &str) -> ServiceConfig {
+        ServiceConfig {
+            name: name.to_string(),
+            exec: "sleep".to_string(),
+            args: vec!["60".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_the_early_target() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("early.toml"),
+            "[target]\nname = \"early\"\nservices = [\"console\", \"dbus\", \"bridge\"]\n",
+        )
+        .unwrap();
+
+        let keep = services_to_keep(dir.path(), "dbus");
+        let expected = ["console", "bridge", "rescue"].map(String::from);
+        assert_eq!(keep, HashSet::from(expected));
+
+        let keep = services_to_keep(&dir.path().join("missing"), "compositor");
+        assert_eq!(keep, HashSet::from(["rescue".to_string()]));
+    }
+
+    #[test]
+    fn enters_once_and_starts_a_shell() {
+        let mut manager = ServiceManager::new();
+        manager.start_service(sleeper("bridge")).unwrap();
+        manager.start_service(sleeper("shell")).unwrap();
+        let keep = HashSet::from(["bridge".to_string(), SHELL.to_string()]);
+
+        let mut rescue = Rescue::new();
+        assert_eq!(rescue.reason(), None);
+        rescue.enter(
+            &mut manager,
+            "compositor",
+            "restarted too often",
+            &keep,
+            sleeper("console"),
+        );
+        assert_eq!(rescue.reason(), Some("compositor: restarted too often"));
+        assert_eq!(manager.state("shell"), ServiceState::Finished);
+        assert_eq!(manager.state("bridge"), ServiceState::Running);
+        assert_eq!(manager.state(SHELL), ServiceState::Running);
+
+        rescue.enter(&mut manager, "dbus", "exited", &keep, sleeper("console"));
+        assert_eq!(rescue.reason(), Some("compositor: restarted too often"));
+
+        manager.stop_all();
+    }
+}
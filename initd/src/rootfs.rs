@@ -0,0 +1,353 @@
+// ABOUTME: Root filesystem mode handling for the init system.
+// ABOUTME: Keeps / read-only in production and overlays writable layers on it in developer mode.
+
+use anyhow::{Context, Result};
+use rustix::fs::{openat, renameat, statvfs, Mode, OFlags, StatVfsMountFlags};
+use rustix::mount::{mount, mount_remount, unmount, MountFlags, UnmountFlags};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+pub const CONFIG_PATH: &str = "/etc/mos/rootfs.toml";
+
+/// Exists while developer mode is on, for services such as the device bridge to make
+/// a `path_exists` condition of.
+pub const DEVELOPER_MODE_PATH: &str = "/run/mos/developer-mode";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RootMode {
+    /// The root filesystem is mounted read-only.
+    #[default]
+    ReadOnly,
+    /// The root stays read-only underneath, with persistent writable overlays on top.
+    Overlay,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RootfsConfig {
+    #[serde(default)]
+    pub mode: RootMode,
+    /// Writable storage holding the overlay upper and work directories.
+    #[serde(default = "default_overlay_dir")]
+    pub overlay_dir: PathBuf,
+    /// System directories made writable in overlay mode.
+    #[serde(default = "default_overlay_paths")]
+    pub overlay_paths: Vec<PathBuf>,
+}
+
+impl Default for RootfsConfig {
+    fn default() -> Self {
+        Self {
+            mode: RootMode::default(),
+            overlay_dir: default_overlay_dir(),
+            overlay_paths: default_overlay_paths(),
+        }
+    }
+}
+
+fn default_overlay_dir() -> PathBuf {
+    PathBuf::from("/data/overlay")
+}
+
+fn default_overlay_paths() -> Vec<PathBuf> {
+    vec![PathBuf::from("/etc"), PathBuf::from("/usr")]
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RootfsFile {
+    rootfs: RootfsConfig,
+}
+
+pub fn parse_config(toml_str: &str) -> Result<RootfsConfig> {
+    let file: RootfsFile = toml::from_str(toml_str).context("failed to parse rootfs config")?;
+    Ok(file.rootfs)
+}
+
+fn render_config(config: &RootfsConfig) -> Result<String> {
+    toml::to_string(&RootfsFile {
+        rootfs: config.clone(),
+    })
+    .context("failed to serialize rootfs config")
+}
+
+#[derive(Default)]
+pub struct Rootfs {
+    config: RootfsConfig,
+    /// Directory containing the config file on the lower root, opened before any overlay
+    /// could hide it so the mode can still be switched back from developer mode.
+    config_dir: Option<OwnedFd>,
+    config_name: PathBuf,
+    /// Mode actually applied this boot; `None` if the root was left as the kernel mounted it.
+    active: Option<RootMode>,
+}
+
+impl Rootfs {
+    /// Load the rootfs config and apply it. Without a config file the root is left untouched.
+    pub fn init(path: &Path) -> Self {
+        let config_name = PathBuf::from(path.file_name().unwrap_or_default());
+        let config_dir = path.parent().and_then(|dir| {
+            openat(
+                rustix::fs::CWD,
+                dir,
+                OFlags::RDONLY | OFlags::DIRECTORY | OFlags::CLOEXEC,
+                Mode::empty(),
+            )
+            .map_err(|e| warn!(dir = %dir.display(), error = %e, "failed to open rootfs config directory"))
+            .ok()
+        });
+
+        let config = match std::fs::read_to_string(path) {
+            Ok(content) => match parse_config(&content) {
+                Ok(config) => Some(config),
+                Err(e) => {
+                    warn!(error = %e, "invalid rootfs config, using read-only defaults");
+                    Some(RootfsConfig::default())
+                }
+            },
+            Err(_) => None,
+        };
+
+        let Some(config) = config else {
+            info!("no rootfs config, leaving root filesystem as mounted");
+            return Self {
+                config: RootfsConfig::default(),
+                config_dir,
+                config_name,
+                active: None,
+            };
+        };
+
+        let active = apply(&config);
+        if active == RootMode::Overlay {
+            mark_developer_mode(Path::new(DEVELOPER_MODE_PATH));
+        }
+        Self {
+            config,
+            config_dir,
+            config_name,
+            active: Some(active),
+        }
+    }
+
+    pub fn active_mode(&self) -> Option<RootMode> {
+        self.active
+    }
+
+    pub fn configured_mode(&self) -> RootMode {
+        self.config.mode
+    }
+
+    /// Persist a new mode for the next boot, briefly making the root writable if needed.
+    pub fn set_mode(&mut self, mode: RootMode) -> Result<()> {
+        let dir = self
+            .config_dir
+            .as_ref()
+            .context("rootfs config directory is not available")?;
+
+        let mut config = self.config.clone();
+        config.mode = mode;
+
+        let readonly = is_readonly(Path::new("/"));
+        if readonly {
+            mount_remount("/", MountFlags::empty(), "").context("failed to remount / read-write")?;
+        }
+        let result = write_config(dir, &self.config_name, &config);
+        if readonly && let Err(e) = mount_remount("/", MountFlags::RDONLY, "") {
+            warn!(error = %e, "failed to remount / read-only");
+        }
+        result?;
+
+        info!(mode = ?mode, "rootfs mode will change on next boot");
+        self.config = config;
+        Ok(())
+    }
+}
+
+fn mark_developer_mode(path: &Path) {
+    let marked = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(path, ""));
+    if let Err(e) = marked {
+        warn!(path = %path.display(), error = %e, "failed to mark developer mode");
+    }
+}
+
+/// Apply the configured mode, falling back to plain read-only if the overlays can't be set up.
+fn apply(config: &RootfsConfig) -> RootMode {
+    let mut mode = config.mode;
+
+    if mode == RootMode::Overlay {
+        match mount_overlays(config) {
+            Ok(()) => warn!("developer mode: system directories are writable"),
+            Err(e) => {
+                warn!(error = %e, "failed to set up overlays, falling back to read-only");
+                mode = RootMode::ReadOnly;
+            }
+        }
+    }
+
+    match mount_remount("/", MountFlags::RDONLY, "") {
+        Ok(()) => info!(mode = ?mode, "root filesystem mounted read-only"),
+        Err(e) => warn!(error = %e, "failed to remount / read-only"),
+    }
+
+    mode
+}
+
+fn mount_overlays(config: &RootfsConfig) -> Result<()> {
+    let mut mounted: Vec<&Path> = Vec::new();
+
+    for lower in &config.overlay_paths {
+        if let Err(e) = mount_overlay(&config.overlay_dir, lower) {
+            for path in mounted {
+                let _ = unmount(path, UnmountFlags::DETACH);
+            }
+            return Err(e);
+        }
+        info!(path = %lower.display(), "overlay mounted");
+        mounted.push(lower);
+    }
+
+    Ok(())
+}
+
+fn mount_overlay(overlay_dir: &Path, lower: &Path) -> Result<()> {
+    let layer = overlay_dir.join(layer_name(lower));
+    let upper = layer.join("upper");
+    let work = layer.join("work");
+    std::fs::create_dir_all(&upper)
+        .with_context(|| format!("failed to create {}", upper.display()))?;
+    std::fs::create_dir_all(&work)
+        .with_context(|| format!("failed to create {}", work.display()))?;
+
+    let options = CString::new(format!(
+        "lowerdir={},upperdir={},workdir={}",
+        lower.display(),
+        upper.display(),
+        work.display()
+    ))?;
+
+    mount(c"overlay", lower, c"overlay", MountFlags::empty(), Some(options.as_c_str()))
+        .with_context(|| format!("failed to mount overlay on {}", lower.display()))
+}
+
+/// Name of the per-directory layer under the overlay dir, e.g. `/usr/local` -> `usr-local`.
+fn layer_name(path: &Path) -> String {
+    path.to_string_lossy().trim_matches('/').replace('/', "-")
+}
+
+fn is_readonly(path: &Path) -> bool {
+    statvfs(path).is_ok_and(|s| s.f_flag.contains(StatVfsMountFlags::RDONLY))
+}
+
+/// Atomically replace the config file inside `dir`.
+fn write_config(dir: &OwnedFd, name: &Path, config: &RootfsConfig) -> Result<()> {
+    let tmp = name.with_extension("toml.tmp");
+    let fd = openat(
+        dir,
+        &tmp,
+        OFlags::WRONLY | OFlags::CREATE | OFlags::TRUNC | OFlags::CLOEXEC,
+        Mode::from_raw_mode(0o644),
+    )
+    .context("failed to create rootfs config")?;
+
+    let mut file = File::from(fd);
+    file.write_all(render_config(config)?.as_bytes())?;
+    file.sync_all()?;
+
+    renameat(dir, &tmp, dir, name).context("failed to replace rootfs config")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_defaults_to_read_only() {
+        let config = parse_config("[rootfs]").unwrap();
+        assert_eq!(config, RootfsConfig::default());
+        assert_eq!(config.mode, RootMode::ReadOnly);
+        assert_eq!(config.overlay_dir, PathBuf::from("/data/overlay"));
+    }
+
+    #[test]
+    fn parse_overlay_mode() {
+        let config = parse_config(
+            r#"
+            [rootfs]
+            mode = "overlay"
+            overlay_dir = "/data/dev"
+            overlay_paths = ["/usr"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.mode, RootMode::Overlay);
+        assert_eq!(config.overlay_dir, PathBuf::from("/data/dev"));
+        assert_eq!(config.overlay_paths, vec![PathBuf::from("/usr")]);
+    }
+
+    #[test]
+    fn parse_unknown_mode_fails() {
+        assert!(parse_config("[rootfs]\nmode = \"writable\"").is_err());
+    }
+
+    #[test]
+    fn render_round_trips() {
+        let config = RootfsConfig {
+            mode: RootMode::Overlay,
+            ..Default::default()
+        };
+        let rendered = render_config(&config).unwrap();
+        assert_eq!(parse_config(&rendered).unwrap(), config);
+    }
+
+    #[test]
+    fn layer_names_are_flat() {
+        assert_eq!(layer_name(Path::new("/usr")), "usr");
+        assert_eq!(layer_name(Path::new("/usr/local/")), "usr-local");
+    }
+
+    #[test]
+    fn write_config_replaces_file_in_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rootfs.toml");
+        std::fs::write(&path, "[rootfs]\nmode = \"read-only\"\n").unwrap();
+
+        let fd = openat(
+            rustix::fs::CWD,
+            dir.path(),
+            OFlags::RDONLY | OFlags::DIRECTORY,
+            Mode::empty(),
+        )
+        .unwrap();
+        let config = RootfsConfig {
+            mode: RootMode::Overlay,
+            ..Default::default()
+        };
+        write_config(&fd, Path::new("rootfs.toml"), &config).unwrap();
+
+        let written = parse_config(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written.mode, RootMode::Overlay);
+        assert!(!dir.path().join("rootfs.toml.tmp").exists());
+    }
+
+    #[test]
+    fn unmanaged_root_has_no_active_mode() {
+        let rootfs = Rootfs::default();
+        assert_eq!(rootfs.active_mode(), None);
+        assert_eq!(rootfs.configured_mode(), RootMode::ReadOnly);
+    }
+
+    #[test]
+    fn set_mode_without_config_dir_fails() {
+        let mut rootfs = Rootfs::default();
+        assert!(rootfs.set_mode(RootMode::Overlay).is_err());
+    }
+}
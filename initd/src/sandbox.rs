@@ -0,0 +1,183 @@
+// ABOUTME: Sandboxing for services: a private mount namespace, a /tmp of their own, and no new privileges.
+// ABOUTME: Built from the service config before fork and applied in the child, ahead of the credential switch.
+
+use anyhow::{Context, Result};
+use std::ffi::CString;
+use std::path::Path;
+use std::ptr;
+
+use crate::config::ServiceConfig;
+
+/// Directories `protect_home` hides, where they exist.
+const HOME_DIRS: &[&str] = &["/home", "/root", "/run/user"];
+
+/// What a service is confined with. Paths are turned into C strings up front, since
+/// nothing may allocate between fork and exec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sandbox {
+    /// A fresh tmpfs on /tmp.
+    private_tmp: bool,
+    /// Emptied, read-only tmpfs over each of these.
+    hidden: Vec<CString>,
+    /// Bind-mounted read-only onto themselves.
+    readonly: Vec<CString>,
+    no_new_privileges: bool,
+}
+
+impl Sandbox {
+    /// The sandbox `config` asks for, or None when it asks for none.
+    pub fn from_config(config: &ServiceConfig) -> Result<Option<Self>> {
+        if !config.private_tmp
+            && !config.protect_home
+            && !config.no_new_privileges
+            && config.readonly_paths.is_empty()
+        {
+            return Ok(None);
+        }
+
+        let hidden = if config.protect_home {
+            HOME_DIRS
+                .iter()
+                .filter(|dir| Path::new(dir).is_dir())
+                .map(|dir| c_path(dir))
+                .collect::<Result<_>>()?
+        } else {
+            Vec::new()
+        };
+        let readonly = config
+            .readonly_paths
+            .iter()
+            .map(|path| c_path(path))
+            .collect::<Result<_>>()?;
+
+        Ok(Some(Self {
+            private_tmp: config.private_tmp,
+            hidden,
+            readonly,
+            no_new_privileges: config.no_new_privileges,
+        }))
+    }
+
+    fn needs_namespace(&self) -> bool {
+        self.private_tmp || !self.hidden.is_empty() || !self.readonly.is_empty()
+    }
+
+    /// Confine the calling process. Runs between fork and exec, so it only makes raw
+    /// syscalls; mounting needs root, so this goes before the switch to the service's user.
+    pub fn apply(&self) -> std::io::Result<()> {
+        if self.needs_namespace() {
+            // SAFETY: unshare takes no pointers; mount only reads the C strings, which
+            // outlive the calls.
+            unsafe {
+                check(libc::unshare(libc::CLONE_NEWNS))?;
+                // Keep our mounts from propagating back to init's namespace
+                check(libc::mount(
+                    ptr::null(),
+                    c"/".as_ptr(),
+                    ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    ptr::null(),
+                ))?;
+
+                if self.private_tmp {
+                    check(libc::mount(
+                        c"tmpfs".as_ptr(),
+                        c"/tmp".as_ptr(),
+                        c"tmpfs".as_ptr(),
+                        libc::MS_NOSUID | libc::MS_NODEV,
+                        c"mode=1777".as_ptr().cast(),
+                    ))?;
+                }
+                for dir in &self.hidden {
+                    check(libc::mount(
+                        c"tmpfs".as_ptr(),
+                        dir.as_ptr(),
+                        c"tmpfs".as_ptr(),
+                        libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                        c"mode=0755".as_ptr().cast(),
+                    ))?;
+                }
+                for path in &self.readonly {
+                    check(libc::mount(
+                        path.as_ptr(),
+                        path.as_ptr(),
+                        ptr::null(),
+                        libc::MS_BIND | libc::MS_REC,
+                        ptr::null(),
+                    ))?;
+                    check(libc::mount(
+                        ptr::null(),
+                        path.as_ptr(),
+                        ptr::null(),
+                        libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                        ptr::null(),
+                    ))?;
+                }
+            }
+        }
+
+        if self.no_new_privileges {
+            // SAFETY: prctl with these options takes no pointers.
+            check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1 as libc::c_ulong, 0, 0, 0) })?;
+        }
+        Ok(())
+    }
+}
+
+fn c_path(path: &str) -> Result<CString> {
+    CString::new(path).with_context(|| format!("invalid path '{path}'"))
+}
+
+fn check(ret: libc::c_int) -> std::io::Result<()> {
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    #[test]
+    fn nothing_asked_is_no_sandbox() {
+        let config = ServiceConfig::default();
+        assert_eq!(Sandbox::from_config(&config).unwrap(), None);
+    }
+
+    #[test]
+    fn readonly_paths_need_a_namespace() {
+        let config = ServiceConfig {
+            readonly_paths: vec!["/etc".to_string()],
+            ..Default::default()
+        };
+        let sandbox = Sandbox::from_config(&config).unwrap().unwrap();
+        assert!(sandbox.needs_namespace());
+        assert_eq!(sandbox.readonly, vec![c"/etc".to_owned()]);
+
+        let config = ServiceConfig {
+            no_new_privileges: true,
+            ..Default::default()
+        };
+        let sandbox = Sandbox::from_config(&config).unwrap().unwrap();
+        assert!(!sandbox.needs_namespace());
+    }
+
+    #[test]
+    fn no_new_privileges_reaches_the_child() {
+        let config = ServiceConfig {
+            no_new_privileges: true,
+            ..Default::default()
+        };
+        let sandbox = Sandbox::from_config(&config).unwrap().unwrap();
+        let mut cmd = Command::new("grep");
+        cmd.args(["-q", "NoNewPrivs:[[:space:]]*1", "/proc/self/status"]);
+        // SAFETY: Sandbox::apply only issues prctl here.
+        unsafe {
+            cmd.pre_exec(move || sandbox.apply());
+        }
+        assert!(cmd.status().unwrap().success());
+    }
+}
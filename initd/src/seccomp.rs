@@ -0,0 +1,769 @@
+// ABOUTME: Seccomp syscall filtering for services.
+// ABOUTME: Loads named allow-list profiles from TOML and compiles them into BPF programs applied before exec.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use tracing::warn;
+
+pub const PROFILES_DIR: &str = "/etc/mos/seccomp";
+
+/// Longest `extends` chain followed before assuming a cycle.
+const MAX_EXTENDS_DEPTH: usize = 8;
+
+/// Kernel limit on classic BPF program length (BPF_MAXINSNS).
+const MAX_INSTRUCTIONS: usize = 4096;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+compile_error!("seccomp filters are only implemented for x86_64 and aarch64");
+
+/// Syscall numbers at or above this belong to the x32 ABI, which profiles never allow.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Offsets into `struct seccomp_data`.
+const DATA_NR_OFFSET: u32 = 0;
+const DATA_ARCH_OFFSET: u32 = 4;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterMode {
+    /// Disallowed syscalls kill the process.
+    #[default]
+    Enforce,
+    /// Disallowed syscalls are allowed but logged by the kernel, for developing profiles.
+    Log,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub mode: FilterMode,
+    /// Another profile whose allow list is included in this one.
+    #[serde(default)]
+    pub extends: Option<String>,
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProfileFile {
+    profile: Profile,
+}
+
+pub fn parse_profile(toml_str: &str) -> Result<Profile> {
+    let file: ProfileFile = toml::from_str(toml_str).context("failed to parse seccomp profile")?;
+    Ok(file.profile)
+}
+
+pub fn load_profiles_from_dir(dir: &Path) -> Result<HashMap<String, Profile>> {
+    let mut profiles = HashMap::new();
+
+    if !dir.exists() {
+        return Ok(profiles);
+    }
+
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read seccomp directory: {}", dir.display()))?;
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "toml") {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let profile =
+            parse_profile(&content).with_context(|| format!("failed to parse {}", path.display()))?;
+        profiles.insert(profile.name.clone(), profile);
+    }
+
+    Ok(profiles)
+}
+
+/// A compiled BPF program ready to be installed in a child process.
+pub struct Filter {
+    program: Vec<libc::sock_filter>,
+}
+
+impl Filter {
+    /// Compile the named profile, following its `extends` chain.
+    pub fn compile(name: &str, profiles: &HashMap<String, Profile>) -> Result<Self> {
+        let leaf = profiles
+            .get(name)
+            .with_context(|| format!("unknown seccomp profile '{name}'"))?;
+
+        let mut numbers = BTreeSet::new();
+        let mut current = Some(leaf);
+        let mut depth = 0;
+        while let Some(profile) = current {
+            depth += 1;
+            if depth > MAX_EXTENDS_DEPTH {
+                bail!("seccomp profile '{name}' has an extends cycle or is nested too deeply");
+            }
+            for syscall in &profile.allow {
+                match syscall_number(syscall) {
+                    Lookup::Number(nr) => {
+                        numbers.insert(nr as u32);
+                    }
+                    Lookup::OtherArch => {}
+                    Lookup::Unknown => {
+                        warn!(profile = %profile.name, syscall = %syscall, "unknown syscall in seccomp profile");
+                    }
+                }
+            }
+            current = match &profile.extends {
+                Some(parent) => Some(profiles.get(parent).with_context(|| {
+                    format!("seccomp profile '{}' extends unknown profile '{parent}'", profile.name)
+                })?),
+                None => None,
+            };
+        }
+
+        // The filter is installed before exec, so exec itself must always pass.
+        numbers.insert(libc::SYS_execve as u32);
+
+        let program = build_program(&numbers, leaf.mode);
+        if program.len() > MAX_INSTRUCTIONS {
+            bail!("seccomp profile '{name}' compiles to {} instructions", program.len());
+        }
+
+        Ok(Self { program })
+    }
+
+    /// Install the filter into the calling process. Only async-signal-safe calls are made,
+    /// so this may run between fork and exec.
+    pub fn apply(&self) -> std::io::Result<()> {
+        let prog = libc::sock_fprog {
+            len: self.program.len() as libc::c_ushort,
+            filter: self.program.as_ptr() as *mut libc::sock_filter,
+        };
+
+        // SAFETY: prctl with these options only reads `prog`, which outlives the call.
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1 as libc::c_ulong, 0, 0, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER as libc::c_ulong,
+                &prog as *const libc::sock_fprog,
+            ) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn build_program(numbers: &BTreeSet<u32>, mode: FilterMode) -> Vec<libc::sock_filter> {
+    let deny = match mode {
+        FilterMode::Enforce => libc::SECCOMP_RET_KILL_PROCESS,
+        FilterMode::Log => libc::SECCOMP_RET_LOG,
+    };
+
+    let mut program = vec![
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, DATA_ARCH_OFFSET),
+        jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, AUDIT_ARCH, 1, 0),
+        stmt(libc::BPF_RET | libc::BPF_K, deny),
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, DATA_NR_OFFSET),
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        program.push(jump(libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K, X32_SYSCALL_BIT, 0, 1));
+        program.push(stmt(libc::BPF_RET | libc::BPF_K, deny));
+    }
+
+    for &nr in numbers {
+        program.push(jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, nr, 0, 1));
+        program.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    }
+
+    program.push(stmt(libc::BPF_RET | libc::BPF_K, deny));
+    program
+}
+
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+enum Lookup {
+    Number(libc::c_long),
+    /// A real syscall that this architecture does not have.
+    OtherArch,
+    Unknown,
+}
+
+fn syscall_number(name: &str) -> Lookup {
+    if let Some(&(_, nr)) = SYSCALLS.iter().find(|(n, _)| *n == name) {
+        return Lookup::Number(nr);
+    }
+    match LEGACY_SYSCALLS.iter().find(|(n, _)| *n == name) {
+        Some(&(_, Some(nr))) => Lookup::Number(nr),
+        Some(&(_, None)) => Lookup::OtherArch,
+        None => Lookup::Unknown,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+macro_rules! legacy {
+    ($nr:ident) => {
+        Some(libc::$nr)
+    };
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+macro_rules! legacy {
+    ($nr:ident) => {
+        None
+    };
+}
+
+/// Syscalls present on every supported architecture.
+const SYSCALLS: &[(&str, libc::c_long)] = &[
+    ("accept", libc::SYS_accept),
+    ("accept4", libc::SYS_accept4),
+    ("acct", libc::SYS_acct),
+    ("add_key", libc::SYS_add_key),
+    ("adjtimex", libc::SYS_adjtimex),
+    ("bind", libc::SYS_bind),
+    ("bpf", libc::SYS_bpf),
+    ("brk", libc::SYS_brk),
+    ("capget", libc::SYS_capget),
+    ("capset", libc::SYS_capset),
+    ("chdir", libc::SYS_chdir),
+    ("chroot", libc::SYS_chroot),
+    ("clock_adjtime", libc::SYS_clock_adjtime),
+    ("clock_getres", libc::SYS_clock_getres),
+    ("clock_gettime", libc::SYS_clock_gettime),
+    ("clock_nanosleep", libc::SYS_clock_nanosleep),
+    ("clock_settime", libc::SYS_clock_settime),
+    ("clone", libc::SYS_clone),
+    ("clone3", libc::SYS_clone3),
+    ("close", libc::SYS_close),
+    ("close_range", libc::SYS_close_range),
+    ("connect", libc::SYS_connect),
+    ("copy_file_range", libc::SYS_copy_file_range),
+    ("delete_module", libc::SYS_delete_module),
+    ("dup", libc::SYS_dup),
+    ("dup3", libc::SYS_dup3),
+    ("epoll_create1", libc::SYS_epoll_create1),
+    ("epoll_ctl", libc::SYS_epoll_ctl),
+    ("epoll_pwait", libc::SYS_epoll_pwait),
+    ("epoll_pwait2", libc::SYS_epoll_pwait2),
+    ("eventfd2", libc::SYS_eventfd2),
+    ("execve", libc::SYS_execve),
+    ("execveat", libc::SYS_execveat),
+    ("exit", libc::SYS_exit),
+    ("exit_group", libc::SYS_exit_group),
+    ("faccessat", libc::SYS_faccessat),
+    ("faccessat2", libc::SYS_faccessat2),
+    ("fallocate", libc::SYS_fallocate),
+    ("fanotify_init", libc::SYS_fanotify_init),
+    ("fanotify_mark", libc::SYS_fanotify_mark),
+    ("fchdir", libc::SYS_fchdir),
+    ("fchmod", libc::SYS_fchmod),
+    ("fchmodat", libc::SYS_fchmodat),
+    ("fchown", libc::SYS_fchown),
+    ("fchownat", libc::SYS_fchownat),
+    ("fcntl", libc::SYS_fcntl),
+    ("fdatasync", libc::SYS_fdatasync),
+    ("fgetxattr", libc::SYS_fgetxattr),
+    ("finit_module", libc::SYS_finit_module),
+    ("flistxattr", libc::SYS_flistxattr),
+    ("flock", libc::SYS_flock),
+    ("fremovexattr", libc::SYS_fremovexattr),
+    ("fsconfig", libc::SYS_fsconfig),
+    ("fsetxattr", libc::SYS_fsetxattr),
+    ("fsmount", libc::SYS_fsmount),
+    ("fsopen", libc::SYS_fsopen),
+    ("fspick", libc::SYS_fspick),
+    ("fstat", libc::SYS_fstat),
+    ("fstatfs", libc::SYS_fstatfs),
+    ("fsync", libc::SYS_fsync),
+    ("ftruncate", libc::SYS_ftruncate),
+    ("futex", libc::SYS_futex),
+    ("futex_waitv", libc::SYS_futex_waitv),
+    ("get_mempolicy", libc::SYS_get_mempolicy),
+    ("get_robust_list", libc::SYS_get_robust_list),
+    ("getcpu", libc::SYS_getcpu),
+    ("getcwd", libc::SYS_getcwd),
+    ("getdents64", libc::SYS_getdents64),
+    ("getegid", libc::SYS_getegid),
+    ("geteuid", libc::SYS_geteuid),
+    ("getgid", libc::SYS_getgid),
+    ("getgroups", libc::SYS_getgroups),
+    ("getitimer", libc::SYS_getitimer),
+    ("getpeername", libc::SYS_getpeername),
+    ("getpgid", libc::SYS_getpgid),
+    ("getpid", libc::SYS_getpid),
+    ("getppid", libc::SYS_getppid),
+    ("getpriority", libc::SYS_getpriority),
+    ("getrandom", libc::SYS_getrandom),
+    ("getresgid", libc::SYS_getresgid),
+    ("getresuid", libc::SYS_getresuid),
+    ("getrlimit", libc::SYS_getrlimit),
+    ("getrusage", libc::SYS_getrusage),
+    ("getsid", libc::SYS_getsid),
+    ("getsockname", libc::SYS_getsockname),
+    ("getsockopt", libc::SYS_getsockopt),
+    ("gettid", libc::SYS_gettid),
+    ("gettimeofday", libc::SYS_gettimeofday),
+    ("getuid", libc::SYS_getuid),
+    ("getxattr", libc::SYS_getxattr),
+    ("init_module", libc::SYS_init_module),
+    ("inotify_add_watch", libc::SYS_inotify_add_watch),
+    ("inotify_init1", libc::SYS_inotify_init1),
+    ("inotify_rm_watch", libc::SYS_inotify_rm_watch),
+    ("io_cancel", libc::SYS_io_cancel),
+    ("io_destroy", libc::SYS_io_destroy),
+    ("io_getevents", libc::SYS_io_getevents),
+    ("io_setup", libc::SYS_io_setup),
+    ("io_submit", libc::SYS_io_submit),
+    ("io_uring_enter", libc::SYS_io_uring_enter),
+    ("io_uring_register", libc::SYS_io_uring_register),
+    ("io_uring_setup", libc::SYS_io_uring_setup),
+    ("ioctl", libc::SYS_ioctl),
+    ("ioprio_get", libc::SYS_ioprio_get),
+    ("ioprio_set", libc::SYS_ioprio_set),
+    ("kcmp", libc::SYS_kcmp),
+    ("kexec_file_load", libc::SYS_kexec_file_load),
+    ("kexec_load", libc::SYS_kexec_load),
+    ("keyctl", libc::SYS_keyctl),
+    ("kill", libc::SYS_kill),
+    ("landlock_add_rule", libc::SYS_landlock_add_rule),
+    ("landlock_create_ruleset", libc::SYS_landlock_create_ruleset),
+    ("landlock_restrict_self", libc::SYS_landlock_restrict_self),
+    ("lgetxattr", libc::SYS_lgetxattr),
+    ("linkat", libc::SYS_linkat),
+    ("listen", libc::SYS_listen),
+    ("listxattr", libc::SYS_listxattr),
+    ("llistxattr", libc::SYS_llistxattr),
+    ("lremovexattr", libc::SYS_lremovexattr),
+    ("lseek", libc::SYS_lseek),
+    ("lsetxattr", libc::SYS_lsetxattr),
+    ("madvise", libc::SYS_madvise),
+    ("mbind", libc::SYS_mbind),
+    ("membarrier", libc::SYS_membarrier),
+    ("memfd_create", libc::SYS_memfd_create),
+    ("memfd_secret", libc::SYS_memfd_secret),
+    ("migrate_pages", libc::SYS_migrate_pages),
+    ("mincore", libc::SYS_mincore),
+    ("mkdirat", libc::SYS_mkdirat),
+    ("mknodat", libc::SYS_mknodat),
+    ("mlock", libc::SYS_mlock),
+    ("mlock2", libc::SYS_mlock2),
+    ("mlockall", libc::SYS_mlockall),
+    ("mmap", libc::SYS_mmap),
+    ("mount", libc::SYS_mount),
+    ("mount_setattr", libc::SYS_mount_setattr),
+    ("move_mount", libc::SYS_move_mount),
+    ("move_pages", libc::SYS_move_pages),
+    ("mprotect", libc::SYS_mprotect),
+    ("mq_getsetattr", libc::SYS_mq_getsetattr),
+    ("mq_notify", libc::SYS_mq_notify),
+    ("mq_open", libc::SYS_mq_open),
+    ("mq_timedreceive", libc::SYS_mq_timedreceive),
+    ("mq_timedsend", libc::SYS_mq_timedsend),
+    ("mq_unlink", libc::SYS_mq_unlink),
+    ("mremap", libc::SYS_mremap),
+    ("mseal", libc::SYS_mseal),
+    ("msgctl", libc::SYS_msgctl),
+    ("msgget", libc::SYS_msgget),
+    ("msgrcv", libc::SYS_msgrcv),
+    ("msgsnd", libc::SYS_msgsnd),
+    ("msync", libc::SYS_msync),
+    ("munlock", libc::SYS_munlock),
+    ("munlockall", libc::SYS_munlockall),
+    ("munmap", libc::SYS_munmap),
+    ("name_to_handle_at", libc::SYS_name_to_handle_at),
+    ("nanosleep", libc::SYS_nanosleep),
+    ("newfstatat", libc::SYS_newfstatat),
+    ("open_by_handle_at", libc::SYS_open_by_handle_at),
+    ("open_tree", libc::SYS_open_tree),
+    ("openat", libc::SYS_openat),
+    ("openat2", libc::SYS_openat2),
+    ("perf_event_open", libc::SYS_perf_event_open),
+    ("personality", libc::SYS_personality),
+    ("pidfd_getfd", libc::SYS_pidfd_getfd),
+    ("pidfd_open", libc::SYS_pidfd_open),
+    ("pidfd_send_signal", libc::SYS_pidfd_send_signal),
+    ("pipe2", libc::SYS_pipe2),
+    ("pivot_root", libc::SYS_pivot_root),
+    ("pkey_alloc", libc::SYS_pkey_alloc),
+    ("pkey_free", libc::SYS_pkey_free),
+    ("pkey_mprotect", libc::SYS_pkey_mprotect),
+    ("ppoll", libc::SYS_ppoll),
+    ("prctl", libc::SYS_prctl),
+    ("pread64", libc::SYS_pread64),
+    ("preadv", libc::SYS_preadv),
+    ("preadv2", libc::SYS_preadv2),
+    ("prlimit64", libc::SYS_prlimit64),
+    ("process_madvise", libc::SYS_process_madvise),
+    ("process_mrelease", libc::SYS_process_mrelease),
+    ("process_vm_readv", libc::SYS_process_vm_readv),
+    ("process_vm_writev", libc::SYS_process_vm_writev),
+    ("pselect6", libc::SYS_pselect6),
+    ("ptrace", libc::SYS_ptrace),
+    ("pwrite64", libc::SYS_pwrite64),
+    ("pwritev", libc::SYS_pwritev),
+    ("pwritev2", libc::SYS_pwritev2),
+    ("quotactl", libc::SYS_quotactl),
+    ("quotactl_fd", libc::SYS_quotactl_fd),
+    ("read", libc::SYS_read),
+    ("readahead", libc::SYS_readahead),
+    ("readlinkat", libc::SYS_readlinkat),
+    ("readv", libc::SYS_readv),
+    ("reboot", libc::SYS_reboot),
+    ("recvfrom", libc::SYS_recvfrom),
+    ("recvmmsg", libc::SYS_recvmmsg),
+    ("recvmsg", libc::SYS_recvmsg),
+    ("remap_file_pages", libc::SYS_remap_file_pages),
+    ("removexattr", libc::SYS_removexattr),
+    ("renameat", libc::SYS_renameat),
+    ("renameat2", libc::SYS_renameat2),
+    ("request_key", libc::SYS_request_key),
+    ("restart_syscall", libc::SYS_restart_syscall),
+    ("rseq", libc::SYS_rseq),
+    ("rt_sigaction", libc::SYS_rt_sigaction),
+    ("rt_sigpending", libc::SYS_rt_sigpending),
+    ("rt_sigprocmask", libc::SYS_rt_sigprocmask),
+    ("rt_sigqueueinfo", libc::SYS_rt_sigqueueinfo),
+    ("rt_sigreturn", libc::SYS_rt_sigreturn),
+    ("rt_sigsuspend", libc::SYS_rt_sigsuspend),
+    ("rt_sigtimedwait", libc::SYS_rt_sigtimedwait),
+    ("rt_tgsigqueueinfo", libc::SYS_rt_tgsigqueueinfo),
+    ("sched_get_priority_max", libc::SYS_sched_get_priority_max),
+    ("sched_get_priority_min", libc::SYS_sched_get_priority_min),
+    ("sched_getaffinity", libc::SYS_sched_getaffinity),
+    ("sched_getattr", libc::SYS_sched_getattr),
+    ("sched_getparam", libc::SYS_sched_getparam),
+    ("sched_getscheduler", libc::SYS_sched_getscheduler),
+    ("sched_rr_get_interval", libc::SYS_sched_rr_get_interval),
+    ("sched_setaffinity", libc::SYS_sched_setaffinity),
+    ("sched_setattr", libc::SYS_sched_setattr),
+    ("sched_setparam", libc::SYS_sched_setparam),
+    ("sched_setscheduler", libc::SYS_sched_setscheduler),
+    ("sched_yield", libc::SYS_sched_yield),
+    ("seccomp", libc::SYS_seccomp),
+    ("semctl", libc::SYS_semctl),
+    ("semget", libc::SYS_semget),
+    ("semop", libc::SYS_semop),
+    ("semtimedop", libc::SYS_semtimedop),
+    ("sendmmsg", libc::SYS_sendmmsg),
+    ("sendmsg", libc::SYS_sendmsg),
+    ("sendto", libc::SYS_sendto),
+    ("set_mempolicy", libc::SYS_set_mempolicy),
+    ("set_mempolicy_home_node", libc::SYS_set_mempolicy_home_node),
+    ("set_robust_list", libc::SYS_set_robust_list),
+    ("set_tid_address", libc::SYS_set_tid_address),
+    ("setdomainname", libc::SYS_setdomainname),
+    ("setfsgid", libc::SYS_setfsgid),
+    ("setfsuid", libc::SYS_setfsuid),
+    ("setgid", libc::SYS_setgid),
+    ("setgroups", libc::SYS_setgroups),
+    ("sethostname", libc::SYS_sethostname),
+    ("setitimer", libc::SYS_setitimer),
+    ("setns", libc::SYS_setns),
+    ("setpgid", libc::SYS_setpgid),
+    ("setpriority", libc::SYS_setpriority),
+    ("setregid", libc::SYS_setregid),
+    ("setresgid", libc::SYS_setresgid),
+    ("setresuid", libc::SYS_setresuid),
+    ("setreuid", libc::SYS_setreuid),
+    ("setrlimit", libc::SYS_setrlimit),
+    ("setsid", libc::SYS_setsid),
+    ("setsockopt", libc::SYS_setsockopt),
+    ("settimeofday", libc::SYS_settimeofday),
+    ("setuid", libc::SYS_setuid),
+    ("setxattr", libc::SYS_setxattr),
+    ("shmat", libc::SYS_shmat),
+    ("shmctl", libc::SYS_shmctl),
+    ("shmdt", libc::SYS_shmdt),
+    ("shmget", libc::SYS_shmget),
+    ("shutdown", libc::SYS_shutdown),
+    ("sigaltstack", libc::SYS_sigaltstack),
+    ("signalfd4", libc::SYS_signalfd4),
+    ("socket", libc::SYS_socket),
+    ("socketpair", libc::SYS_socketpair),
+    ("splice", libc::SYS_splice),
+    ("statfs", libc::SYS_statfs),
+    ("statx", libc::SYS_statx),
+    ("swapoff", libc::SYS_swapoff),
+    ("swapon", libc::SYS_swapon),
+    ("symlinkat", libc::SYS_symlinkat),
+    ("sync", libc::SYS_sync),
+    ("sync_file_range", libc::SYS_sync_file_range),
+    ("syncfs", libc::SYS_syncfs),
+    ("sysinfo", libc::SYS_sysinfo),
+    ("syslog", libc::SYS_syslog),
+    ("tee", libc::SYS_tee),
+    ("tgkill", libc::SYS_tgkill),
+    ("timer_create", libc::SYS_timer_create),
+    ("timer_delete", libc::SYS_timer_delete),
+    ("timer_getoverrun", libc::SYS_timer_getoverrun),
+    ("timer_gettime", libc::SYS_timer_gettime),
+    ("timer_settime", libc::SYS_timer_settime),
+    ("timerfd_create", libc::SYS_timerfd_create),
+    ("timerfd_gettime", libc::SYS_timerfd_gettime),
+    ("timerfd_settime", libc::SYS_timerfd_settime),
+    ("times", libc::SYS_times),
+    ("tkill", libc::SYS_tkill),
+    ("truncate", libc::SYS_truncate),
+    ("umask", libc::SYS_umask),
+    ("umount2", libc::SYS_umount2),
+    ("uname", libc::SYS_uname),
+    ("unlinkat", libc::SYS_unlinkat),
+    ("unshare", libc::SYS_unshare),
+    ("userfaultfd", libc::SYS_userfaultfd),
+    ("utimensat", libc::SYS_utimensat),
+    ("vhangup", libc::SYS_vhangup),
+    ("vmsplice", libc::SYS_vmsplice),
+    ("wait4", libc::SYS_wait4),
+    ("waitid", libc::SYS_waitid),
+    ("write", libc::SYS_write),
+    ("writev", libc::SYS_writev),
+];
+
+/// Legacy syscalls that x86_64 still provides but aarch64 replaced with *at variants.
+/// Listed everywhere so one profile can name them portably; `None` where absent.
+const LEGACY_SYSCALLS: &[(&str, Option<libc::c_long>)] = &[
+    ("access", legacy!(SYS_access)),
+    ("alarm", legacy!(SYS_alarm)),
+    ("arch_prctl", legacy!(SYS_arch_prctl)),
+    ("chmod", legacy!(SYS_chmod)),
+    ("chown", legacy!(SYS_chown)),
+    ("creat", legacy!(SYS_creat)),
+    ("dup2", legacy!(SYS_dup2)),
+    ("epoll_create", legacy!(SYS_epoll_create)),
+    ("epoll_wait", legacy!(SYS_epoll_wait)),
+    ("eventfd", legacy!(SYS_eventfd)),
+    ("fadvise64", legacy!(SYS_fadvise64)),
+    ("fchmodat2", legacy!(SYS_fchmodat2)),
+    ("fork", legacy!(SYS_fork)),
+    ("futimesat", legacy!(SYS_futimesat)),
+    ("get_thread_area", legacy!(SYS_get_thread_area)),
+    ("getdents", legacy!(SYS_getdents)),
+    ("getpgrp", legacy!(SYS_getpgrp)),
+    ("inotify_init", legacy!(SYS_inotify_init)),
+    ("ioperm", legacy!(SYS_ioperm)),
+    ("iopl", legacy!(SYS_iopl)),
+    ("lchown", legacy!(SYS_lchown)),
+    ("link", legacy!(SYS_link)),
+    ("lstat", legacy!(SYS_lstat)),
+    ("mkdir", legacy!(SYS_mkdir)),
+    ("mknod", legacy!(SYS_mknod)),
+    ("modify_ldt", legacy!(SYS_modify_ldt)),
+    ("open", legacy!(SYS_open)),
+    ("pause", legacy!(SYS_pause)),
+    ("pipe", legacy!(SYS_pipe)),
+    ("poll", legacy!(SYS_poll)),
+    ("readlink", legacy!(SYS_readlink)),
+    ("rename", legacy!(SYS_rename)),
+    ("rmdir", legacy!(SYS_rmdir)),
+    ("select", legacy!(SYS_select)),
+    ("sendfile", legacy!(SYS_sendfile)),
+    ("set_thread_area", legacy!(SYS_set_thread_area)),
+    ("signalfd", legacy!(SYS_signalfd)),
+    ("stat", legacy!(SYS_stat)),
+    ("symlink", legacy!(SYS_symlink)),
+    ("sysfs", legacy!(SYS_sysfs)),
+    ("time", legacy!(SYS_time)),
+    ("unlink", legacy!(SYS_unlink)),
+    ("ustat", legacy!(SYS_ustat)),
+    ("utime", legacy!(SYS_utime)),
+    ("utimes", legacy!(SYS_utimes)),
+    ("vfork", legacy!(SYS_vfork)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::process::Command;
+
+    fn profiles(list: &[&str]) -> HashMap<String, Profile> {
+        list.iter()
+            .map(|toml| {
+                let p = parse_profile(toml).unwrap();
+                (p.name.clone(), p)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_profile_defaults_to_enforce() {
+        let p = parse_profile(
+            r#"
+            [profile]
+            name = "default"
+            allow = ["read", "write"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(p.name, "default");
+        assert_eq!(p.mode, FilterMode::Enforce);
+        assert_eq!(p.allow, vec!["read", "write"]);
+        assert!(p.extends.is_none());
+    }
+
+    #[test]
+    fn parse_log_mode_and_extends() {
+        let p = parse_profile(
+            r#"
+            [profile]
+            name = "network"
+            mode = "log"
+            extends = "default"
+            allow = ["bind"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(p.mode, FilterMode::Log);
+        assert_eq!(p.extends.as_deref(), Some("default"));
+    }
+
+    #[test]
+    fn compile_includes_parent_syscalls() {
+        let set = profiles(&[
+            r#"
+            [profile]
+            name = "default"
+            allow = ["read"]
+        "#,
+            r#"
+            [profile]
+            name = "network"
+            extends = "default"
+            allow = ["bind"]
+        "#,
+        ]);
+
+        let base = Filter::compile("default", &set).unwrap();
+        let net = Filter::compile("network", &set).unwrap();
+        // Each allowed syscall adds a compare and a return
+        assert_eq!(net.program.len(), base.program.len() + 2);
+    }
+
+    #[test]
+    fn compile_unknown_profile_fails() {
+        assert!(Filter::compile("missing", &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn compile_detects_extends_cycle() {
+        let set = profiles(&[
+            r#"
+            [profile]
+            name = "a"
+            extends = "b"
+        "#,
+            r#"
+            [profile]
+            name = "b"
+            extends = "a"
+        "#,
+        ]);
+        assert!(Filter::compile("a", &set).is_err());
+    }
+
+    #[test]
+    fn unknown_syscall_names_are_skipped() {
+        let set = profiles(&[r#"
+            [profile]
+            name = "typo"
+            allow = ["not_a_syscall"]
+        "#]);
+        let filter = Filter::compile("typo", &set).unwrap();
+        let empty = Filter::compile(
+            "empty",
+            &profiles(&[r#"
+            [profile]
+            name = "empty"
+        "#]),
+        )
+        .unwrap();
+        assert_eq!(filter.program.len(), empty.program.len());
+    }
+
+    #[test]
+    fn legacy_syscalls_are_known_on_every_arch() {
+        assert!(!matches!(syscall_number("open"), Lookup::Unknown));
+        assert!(matches!(syscall_number("openat"), Lookup::Number(_)));
+        assert!(matches!(syscall_number("bogus"), Lookup::Unknown));
+    }
+
+    #[test]
+    fn program_ends_with_deny_action() {
+        let numbers = BTreeSet::from([libc::SYS_read as u32]);
+        let enforce = build_program(&numbers, FilterMode::Enforce);
+        let log = build_program(&numbers, FilterMode::Log);
+        assert_eq!(enforce.last().unwrap().k, libc::SECCOMP_RET_KILL_PROCESS);
+        assert_eq!(log.last().unwrap().k, libc::SECCOMP_RET_LOG);
+    }
+
+    /// Every syscall in the table except the ones `mkdir` needs to create a directory.
+    fn everything_but_mkdir() -> HashMap<String, Profile> {
+        let allow = SYSCALLS
+            .iter()
+            .map(|(n, _)| *n)
+            .chain(LEGACY_SYSCALLS.iter().map(|(n, _)| *n))
+            .filter(|n| *n != "mkdir" && *n != "mkdirat")
+            .map(str::to_string)
+            .collect();
+        HashMap::from([(
+            "nomkdir".to_string(),
+            Profile {
+                name: "nomkdir".to_string(),
+                mode: FilterMode::Enforce,
+                extends: None,
+                allow,
+            },
+        )])
+    }
+
+    #[test]
+    fn enforced_filter_kills_disallowed_syscall() {
+        let dir = tempfile::tempdir().unwrap();
+        let filter = Filter::compile("nomkdir", &everything_but_mkdir()).unwrap();
+
+        let mut cmd = Command::new("mkdir");
+        cmd.arg(dir.path().join("blocked"));
+        unsafe {
+            cmd.pre_exec(move || filter.apply());
+        }
+        let status = cmd.status().unwrap();
+
+        assert_eq!(status.signal(), Some(libc::SIGSYS));
+        assert!(!dir.path().join("blocked").exists());
+    }
+
+    #[test]
+    fn enforced_filter_allows_listed_syscalls() {
+        let filter = Filter::compile("nomkdir", &everything_but_mkdir()).unwrap();
+
+        let mut cmd = Command::new("true");
+        unsafe {
+            cmd.pre_exec(move || filter.apply());
+        }
+        assert!(cmd.status().unwrap().success());
+    }
+}
@@ -1,99 +1,610 @@
 // ABOUTME: Service lifecycle manager for the init system.
 // ABOUTME: Spawns, tracks, and supervises child processes based on service configs.
 
-use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::process::{Child, Command};
-use tracing::{error, info, warn};
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use rustix::process::{kill_process, Pid, Signal};
+use tracing::{debug, error, info, warn};
 
-use crate::config::{RestartPolicy, ServiceConfig, ServiceType};
+use crate::accounting::{self, Usage};
+use crate::boot::BootProfile;
+use crate::capabilities::Capabilities;
+use crate::condition;
+use crate::config::{FailureAction, OutputMode, RestartPolicy, ServiceConfig, ServiceType};
+use crate::credentials::Credentials;
+use crate::dependency;
+use crate::envfile;
+use crate::health::{self, HealthCheck};
+use crate::journal::Journal;
+use crate::mount;
+use crate::notify::NotifySocket;
+use crate::oom;
+use crate::reaper;
+use crate::sandbox::Sandbox;
+use crate::seccomp::{self, Profile};
+
+/// How often to look for the `ready_path` of services still starting.
+const READY_PATH_POLL_INTERVAL: Duration = Duration::from_millis(20);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ServiceState {
     Stopped,
+    /// Queued until its dependencies are ready.
+    Pending,
+    /// Running, but a notify service that hasn't reported ready yet, one whose
+    /// `ready_path` hasn't appeared, or a oneshot service that hasn't finished.
+    Starting,
     Running,
+    /// Exited and waiting out its restart delay.
+    Restarting,
     Finished,
+    /// Not started because its conditions didn't hold.
+    Skipped,
+    /// Killed for not becoming ready within its start timeout, given up on after
+    /// restarting too often, or a oneshot service that exited with an error.
     Failed,
 }
 
+/// Restart bookkeeping carried from one run of a service to the next.
+#[derive(Debug, Default, Clone)]
+struct RestartHistory {
+    /// Restarts since the service last ran stably; the exponent of the backoff.
+    consecutive: u32,
+    /// When recent restarts were due, for the burst limit.
+    recent: VecDeque<Instant>,
+}
+
 struct RunningService {
     config: ServiceConfig,
     child: Child,
-    restart_count: u32,
+    history: RestartHistory,
+    notify: Option<NotifySocket>,
+    ready: bool,
+    started: Instant,
+    /// When it last sent WATCHDOG=1, or became ready.
+    pinged: Instant,
+    /// It missed its watchdog and hasn't pinged since.
+    hung: bool,
+    /// Its `health` probes, until it fails them.
+    health: Option<HealthCheck>,
 }
 
 pub struct ServiceManager {
     running: HashMap<String, RunningService>,
     finished: HashMap<String, ServiceConfig>,
+    failed: HashSet<String>,
+    /// Services whose conditions didn't hold at their last start. Like finished
+    /// services, their configs are kept in `finished`.
+    skipped: HashSet<String>,
+    /// Services waiting on dependencies, in start order.
+    pending: Vec<ServiceConfig>,
+    /// Exited services waiting for their restart delay to pass.
+    restarts: Vec<ScheduledRestart>,
+    journal: Journal,
+    seccomp_profiles: HashMap<String, Profile>,
+    notify_dir: Option<PathBuf>,
+    reap_orphans: bool,
+    /// Critical services that failed for good, with why, until the main loop takes them.
+    critical_failures: Vec<(String, String)>,
+    boot: BootProfile,
+    /// Stops cut their grace periods short to be done by this, so they don't hold the
+    /// main loop past petting the hardware watchdog.
+    stop_deadline: Option<Instant>,
+}
+
+struct ScheduledRestart {
+    config: ServiceConfig,
+    history: RestartHistory,
+    due: Instant,
 }
 
-const MAX_RESTART_COUNT: u32 = 5;
+/// Restart settings for services that don't set their own.
+const DEFAULT_RESTART_DELAY: Duration = Duration::from_millis(100);
+const DEFAULT_RESTART_WINDOW: Duration = Duration::from_secs(60);
+const DEFAULT_RESTART_BURST: u32 = 10;
+
+/// The backoff stops doubling here, so a service that keeps crashing is still retried.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+
+/// Grace period before SIGKILL for services that don't set `stop_timeout_sec`.
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a stopping process is checked for exit.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Start timeout for notify services that don't set `start_timeout_sec`. Oneshot
+/// services may run as long as they need unless they set one.
+const DEFAULT_START_TIMEOUT: Duration = Duration::from_secs(90);
 
 impl ServiceManager {
+    /// Create a manager whose journal is kept in memory only.
     pub fn new() -> Self {
         Self {
             running: HashMap::new(),
             finished: HashMap::new(),
+            failed: HashSet::new(),
+            skipped: HashSet::new(),
+            pending: Vec::new(),
+            restarts: Vec::new(),
+            journal: Journal::new(None),
+            seccomp_profiles: HashMap::new(),
+            notify_dir: None,
+            reap_orphans: false,
+            critical_failures: Vec::new(),
+            boot: BootProfile::new(Instant::now()),
+            stop_deadline: None,
         }
     }
 
-    pub fn start_service(&mut self, config: ServiceConfig) -> Result<()> {
-        let name = config.name.clone();
-        info!(service = %name, exec = %config.exec, "starting service");
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    pub fn with_seccomp_profiles(mut self, profiles: HashMap<String, Profile>) -> Self {
+        self.seccomp_profiles = profiles;
+        self
+    }
 
-        let mut cmd = Command::new(&config.exec);
-        cmd.args(&config.args);
+    /// Directory for notify services' readiness sockets; notify services can't start without one.
+    pub fn with_notify_dir(mut self, dir: &Path) -> Self {
+        self.notify_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Have `reap` collect every exited child of the process, not just services, so
+    /// orphans reparented to PID 1 don't linger as zombies. Only for init itself: it
+    /// would steal the children of anything else in the process.
+    pub fn with_orphan_reaping(mut self) -> Self {
+        self.reap_orphans = true;
+        self
+    }
+
+    /// Time the boot profile from `start`, when init itself started, rather than from
+    /// when the manager was created.
+    pub fn with_boot_start(mut self, start: Instant) -> Self {
+        self.boot = BootProfile::new(start);
+        self
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn boot_profile(&self) -> &BootProfile {
+        &self.boot
+    }
+
+    /// Record a step of init itself, such as loading the service configs, in the boot profile.
+    pub fn mark_boot_phase(&mut self, phase: &'static str) {
+        self.boot.mark(phase, Instant::now());
+    }
 
-        for (key, val) in &config.environment {
-            cmd.env(key, val);
+    pub fn start_service(&mut self, config: ServiceConfig) -> Result<()> {
+        let name = config.name.clone();
+        let Some(config) = self.check_conditions(config) else {
+            return Ok(());
+        };
+        for other in &config.conflicts {
+            if self.running.contains_key(other) {
+                info!(service = %name, conflict = %other, "stopping conflicting service");
+                self.stop_service(other)?;
+            }
         }
+        info!(service = %name, exec = %config.exec, "starting service");
 
-        let child = cmd
-            .spawn()
+        let (child, notify) = self
+            .spawn(&config)
             .with_context(|| format!("failed to start service '{}'", name))?;
 
         info!(service = %name, pid = child.id(), "service started");
 
+        // Dependents of a oneshot service wait for it to finish successfully
+        let ready = notify.is_none()
+            && config.ready_path.is_none()
+            && config.service_type != ServiceType::Oneshot;
+        let now = Instant::now();
+        self.boot.spawned(&config, now);
+        if ready {
+            self.boot.ready(&name, now);
+        }
+        self.failed.remove(&name);
+        self.skipped.remove(&name);
+        let health = config
+            .health
+            .clone()
+            .map(|health| HealthCheck::new(&name, health, now));
         self.running.insert(
             name,
             RunningService {
                 config,
                 child,
-                restart_count: 0,
+                history: RestartHistory::default(),
+                ready,
+                notify,
+                started: now,
+                pinged: now,
+                hung: false,
+                health,
             },
         );
 
         Ok(())
     }
 
+    /// Pass the config back if the service's conditions hold. Otherwise it is marked
+    /// skipped, which doesn't hold back its dependents, and is checked again the next
+    /// time something starts it.
+    fn check_conditions(&mut self, config: ServiceConfig) -> Option<ServiceConfig> {
+        let Some(reason) = condition::unmet(&config.conditions) else {
+            return Some(config);
+        };
+        info!(service = %config.name, condition = %reason, "condition not met, skipping service");
+        self.failed.remove(&config.name);
+        self.skipped.insert(config.name.clone());
+        self.finished.insert(config.name.clone(), config);
+        None
+    }
+
+    /// Queue services, already in dependency order, for `start_pending`.
+    pub fn enqueue(&mut self, configs: Vec<ServiceConfig>) {
+        self.pending.extend(configs);
+    }
+
+    /// Start every queued service whose dependencies have settled. A dependency holds
+    /// its dependents back while it is queued itself or running but not yet ready. A
+    /// service that requires one that failed fails too, without starting.
+    pub fn start_pending(&mut self) {
+        let mut i = 0;
+        while i < self.pending.len() {
+            if let Some(dep) = self.pending[i]
+                .depends_on
+                .iter()
+                .find(|dep| self.failed.contains(*dep))
+                .cloned()
+            {
+                self.fail_dependents(&dep);
+                continue;
+            }
+            if dependency::edges(&self.pending[i]).any(|(dep, _)| self.holds_back_dependents(dep)) {
+                i += 1;
+                continue;
+            }
+
+            let config = self.pending.remove(i);
+            let (name, critical) = (config.name.clone(), config.critical);
+            if let Err(e) = self.start_service(config) {
+                error!(service = %name, error = %e, "failed to start service");
+                self.boot.failed(&name, &format!("{e:#}"), Instant::now());
+                if critical {
+                    self.critical_failures
+                        .push((name.clone(), format!("{e:#}")));
+                }
+                self.fail_dependents(&name);
+            }
+        }
+    }
+
+    fn holds_back_dependents(&self, name: &str) -> bool {
+        self.pending.iter().any(|c| c.name == name)
+            || self.running.get(name).is_some_and(|svc| !svc.ready)
+    }
+
+    /// Whether `name` is running, waiting to restart, or queued.
+    fn is_active(&self, name: &str) -> bool {
+        self.running.contains_key(name)
+            || self.restarts.iter().any(|r| r.config.name == name)
+            || self.pending.iter().any(|c| c.name == name)
+    }
+
+    /// The active services that require `name`.
+    fn dependents_of(&self, name: &str) -> Vec<String> {
+        self.running
+            .values()
+            .map(|svc| &svc.config)
+            .chain(self.restarts.iter().map(|r| &r.config))
+            .chain(&self.pending)
+            .filter(|config| config.depends_on.iter().any(|dep| dep == name))
+            .map(|config| config.name.clone())
+            .collect()
+    }
+
+    /// Take down the services that require `name`, which failed: running ones are
+    /// stopped and queued ones won't start, and they all count as failed too. Services
+    /// that only want `name` carry on.
+    fn fail_dependents(&mut self, name: &str) {
+        let reason = format!("requires '{name}', which failed");
+        for dependent in self.dependents_of(name) {
+            // Already taken down through another dependency
+            if !self.is_active(&dependent) {
+                continue;
+            }
+            error!(service = %dependent, dependency = %name, "dependency failed");
+            self.fail_dependents(&dependent);
+            if let Some(i) = self.pending.iter().position(|c| c.name == dependent) {
+                let config = self.pending.remove(i);
+                self.finished.insert(dependent.clone(), config);
+            } else {
+                let _ = self.stop_service(&dependent);
+            }
+            self.boot.failed(&dependent, &reason, Instant::now());
+            if self.finished.get(&dependent).is_some_and(|c| c.critical) {
+                self.critical_failures
+                    .push((dependent.clone(), reason.clone()));
+            }
+            self.failed.insert(dependent);
+        }
+    }
+
+    /// Stop the services that require `name`, which is stopping, and drop those
+    /// still waiting to start.
+    fn stop_dependents(&mut self, name: &str) {
+        for dependent in self.dependents_of(name) {
+            if let Some(i) = self.pending.iter().position(|c| c.name == dependent) {
+                info!(service = %dependent, dependency = %name, "not starting service");
+                let config = self.pending.remove(i);
+                self.finished.insert(dependent.clone(), config);
+                self.stop_dependents(&dependent);
+            } else {
+                let _ = self.stop_service(&dependent);
+            }
+        }
+    }
+
+    /// Read readiness notifications from notify services, and look for the
+    /// `ready_path` of those waiting on one. Returns the services that became ready.
+    pub fn poll_notifications(&mut self) -> Vec<String> {
+        let mut became_ready = Vec::new();
+
+        for (name, svc) in &mut self.running {
+            if !svc.ready
+                && let Some(path) = &svc.config.ready_path
+                && Path::new(path).exists()
+            {
+                info!(service = %name, path = %path, "service ready");
+                svc.ready = true;
+                svc.pinged = Instant::now();
+                self.boot.ready(name, svc.pinged);
+                became_ready.push(name.clone());
+            }
+            let Some(socket) = &svc.notify else {
+                continue;
+            };
+            for notification in socket.receive() {
+                if let Some(status) = notification.status {
+                    info!(service = %name, status = %status, "service status");
+                }
+                if notification.ready && !svc.ready {
+                    info!(service = %name, "service ready");
+                    svc.ready = true;
+                    svc.pinged = Instant::now();
+                    self.boot.ready(name, svc.pinged);
+                    became_ready.push(name.clone());
+                }
+                if notification.watchdog {
+                    if svc.hung {
+                        info!(service = %name, "service responds again");
+                    }
+                    svc.pinged = Instant::now();
+                    svc.hung = false;
+                }
+            }
+        }
+
+        became_ready
+    }
+
+    /// Kill services that have been starting for longer than their start timeout.
+    /// Returns each one with the escalation its config asks for.
+    pub fn check_start_timeouts(&mut self) -> Vec<(String, FailureAction)> {
+        let expired: Vec<String> = self
+            .running
+            .iter()
+            .filter(|(_, svc)| {
+                !svc.ready
+                    && start_timeout(&svc.config).is_some_and(|t| svc.started.elapsed() >= t)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let mut failures = Vec::new();
+        for name in expired {
+            let action = self.running[&name].config.on_failure;
+            error!(service = %name, action = ?action, "service did not become ready in time");
+            self.failed.insert(name.clone());
+            self.fail_dependents(&name);
+            let _ = self.stop_service(&name);
+            let reason = "did not become ready in time".to_string();
+            self.boot.failed(&name, &reason, Instant::now());
+            if self.finished.get(&name).is_some_and(|config| config.critical) {
+                self.critical_failures.push((name.clone(), reason));
+            }
+            failures.push((name, action));
+        }
+        failures
+    }
+
+    /// Deal with ready services that went longer than their `watchdog_sec` without
+    /// sending WATCHDOG=1: kill them with SIGABRT, or leave those with `watchdog_reset`
+    /// to the hardware watchdog. Returns the hung services of that kind; the hardware
+    /// watchdog must not be petted while there are any.
+    pub fn check_watchdogs(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        let mut hung = Vec::new();
+        for (name, svc) in &mut self.running {
+            let expired = watchdog_timeout(&svc.config).is_some_and(|t| now >= svc.pinged + t);
+            if !svc.ready || !expired {
+                continue;
+            }
+            let pid = svc.child.id();
+            if svc.config.watchdog_reset {
+                if !svc.hung {
+                    error!(
+                        service = %name,
+                        pid,
+                        "service hung, leaving the phone to the hardware watchdog"
+                    );
+                }
+                hung.push(name.clone());
+            } else if !svc.hung {
+                error!(service = %name, pid, "service hung, aborting it");
+                if let Err(e) = kill_process(Pid::from_child(&svc.child), Signal::ABORT) {
+                    error!(service = %name, error = %e, "failed to abort hung service");
+                }
+            }
+            svc.hung = true;
+        }
+        hung
+    }
+
+    /// Run the health probes of ready services that are due, and kill with SIGABRT those
+    /// that failed `failure_threshold` probes in a row, even though they are still
+    /// running, so they restart as `restart` says. Returns the services killed.
+    pub fn check_health(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        let mut unhealthy = Vec::new();
+        for (name, svc) in &mut self.running {
+            if !svc.ready {
+                continue;
+            }
+            let Some(check) = svc.health.as_mut() else {
+                continue;
+            };
+            if !check.check(now, || probe_command(&svc.config)) {
+                continue;
+            }
+            error!(service = %name, pid = svc.child.id(), "service unhealthy, aborting it");
+            if let Err(e) = kill_process(Pid::from_child(&svc.child), Signal::ABORT) {
+                error!(service = %name, error = %e, "failed to abort unhealthy service");
+            }
+            svc.health = None;
+            unhealthy.push(name.clone());
+        }
+        unhealthy
+    }
+
+    /// Stop the running services with `freeze_on_sleep` with SIGSTOP, for the phone to
+    /// suspend. Returns the ones stopped, for `thaw` once it resumes.
+    pub fn freeze(&mut self) -> Vec<String> {
+        let mut frozen = Vec::new();
+        for (name, svc) in &self.running {
+            if !svc.config.freeze_on_sleep {
+                continue;
+            }
+            match kill_process(Pid::from_child(&svc.child), Signal::STOP) {
+                Ok(()) => frozen.push(name.clone()),
+                Err(e) => warn!(service = %name, error = %e, "failed to freeze service"),
+            }
+        }
+        if !frozen.is_empty() {
+            info!(services = ?frozen, "froze services for suspend");
+        }
+        frozen
+    }
+
+    /// Continue the services `freeze` stopped.
+    pub fn thaw(&mut self, frozen: &[String]) {
+        for name in frozen {
+            let Some(svc) = self.running.get(name) else {
+                continue;
+            };
+            if let Err(e) = kill_process(Pid::from_child(&svc.child), Signal::CONT) {
+                error!(service = %name, error = %e, "failed to thaw service");
+            }
+        }
+    }
+
+    /// The earliest moment a start timeout expires, a watchdog runs out, a health probe
+    /// is due or times out, or a scheduled restart is due, so the main loop knows when
+    /// to wake up without a signal.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let timeouts = self
+            .running
+            .values()
+            .filter(|svc| !svc.ready)
+            .filter_map(|svc| start_timeout(&svc.config).map(|t| svc.started + t));
+        let watchdogs = self
+            .running
+            .values()
+            .filter(|svc| svc.ready && !svc.hung)
+            .filter_map(|svc| watchdog_timeout(&svc.config).map(|t| svc.pinged + t));
+        let ready_paths = self
+            .running
+            .values()
+            .find(|svc| !svc.ready && svc.config.ready_path.is_some())
+            .map(|_| Instant::now() + READY_PATH_POLL_INTERVAL);
+        let probes = self
+            .running
+            .values()
+            .filter(|svc| svc.ready)
+            .filter_map(|svc| svc.health.as_ref().map(HealthCheck::next_deadline));
+        let restarts = self.restarts.iter().map(|r| r.due);
+        timeouts
+            .chain(watchdogs)
+            .chain(probes)
+            .chain(ready_paths)
+            .chain(restarts)
+            .min()
+    }
+
+    /// Readiness sockets of running notify services, for the main loop to poll on.
+    pub fn notify_sockets(&self) -> impl Iterator<Item = &NotifySocket> {
+        self.running.values().filter_map(|svc| svc.notify.as_ref())
+    }
+
     pub fn state(&self, name: &str) -> ServiceState {
-        if self.running.contains_key(name) {
-            ServiceState::Running
+        if let Some(svc) = self.running.get(name) {
+            if svc.ready {
+                ServiceState::Running
+            } else {
+                ServiceState::Starting
+            }
+        } else if self.restarts.iter().any(|r| r.config.name == name) {
+            ServiceState::Restarting
+        } else if self.skipped.contains(name) {
+            ServiceState::Skipped
+        } else if self.failed.contains(name) {
+            ServiceState::Failed
         } else if self.finished.contains_key(name) {
             ServiceState::Finished
+        } else if self.pending.iter().any(|c| c.name == name) {
+            ServiceState::Pending
         } else {
             ServiceState::Stopped
         }
     }
 
+    /// How far boot has got: the services that became ready, finished, failed or were
+    /// skipped, and the total including those still starting or waiting to.
+    pub fn boot_progress(&self) -> (usize, usize) {
+        let settled = self
+            .boot
+            .services()
+            .filter(|(_, timeline)| timeline.ready.is_some() || timeline.failure.is_some())
+            .count();
+        let started = self.boot.services().count();
+        (
+            settled + self.skipped.len(),
+            started + self.skipped.len() + self.pending.len(),
+        )
+    }
+
     pub fn running_count(&self) -> usize {
         self.running.len()
     }
 
-    /// Check all running services for exits. Returns names of services that exited.
+    /// Check all running services for exits, reaping orphans as well if enabled.
+    /// Returns names of services that exited.
     pub fn reap(&mut self) -> Vec<String> {
         let mut exited = Vec::new();
 
         for (name, svc) in &mut self.running {
             match svc.child.try_wait() {
-                Ok(Some(status)) => {
-                    if status.success() {
-                        info!(service = %name, "service exited successfully");
-                    } else {
-                        warn!(service = %name, status = ?status, "service exited with error");
-                    }
-                    exited.push((name.clone(), status.success()));
-                }
+                Ok(Some(status)) => exited.push((name.clone(), status)),
                 Ok(None) => {} // still running
                 Err(e) => {
                     error!(service = %name, error = %e, "failed to check service status");
@@ -101,10 +612,29 @@ impl ServiceManager {
             }
         }
 
+        // Services that exit after their try_wait above turn up here too
+        if self.reap_orphans {
+            let reaped = self.take_probes(reaper::reap_all());
+            exited.extend(self.match_services(reaped));
+        }
+
         let mut exited_names = Vec::new();
 
-        for (name, success) in exited {
+        for (name, status) in exited {
+            let success = status.success();
+            if success {
+                info!(service = %name, "service exited successfully");
+            } else {
+                warn!(service = %name, status = ?status, "service exited with error");
+            }
+
             let svc = self.running.remove(&name).unwrap();
+            if success && svc.config.service_type == ServiceType::Oneshot {
+                self.boot.ready(&name, Instant::now());
+            } else if !success {
+                self.boot
+                    .failed(&name, &format!("exited with {status}"), Instant::now());
+            }
             let should_restart = match (&svc.config.restart, &svc.config.service_type) {
                 (_, ServiceType::Oneshot) => false,
                 (RestartPolicy::Always, _) => true,
@@ -112,28 +642,23 @@ impl ServiceManager {
                 (RestartPolicy::Never, _) => false,
             };
 
-            if should_restart && svc.restart_count < MAX_RESTART_COUNT {
-                info!(
-                    service = %name,
-                    restart_count = svc.restart_count + 1,
-                    "restarting service"
-                );
-                match self.spawn_with_count(&svc.config, svc.restart_count + 1) {
-                    Ok(()) => {}
-                    Err(e) => {
-                        error!(service = %name, error = %e, "failed to restart service");
-                        self.finished.insert(name.clone(), svc.config);
-                    }
-                }
+            if should_restart {
+                self.schedule_restart(svc);
             } else {
-                if should_restart {
-                    error!(
-                        service = %name,
-                        max = MAX_RESTART_COUNT,
-                        "service exceeded max restart count"
-                    );
+                let oneshot = svc.config.service_type == ServiceType::Oneshot;
+                if oneshot && !success {
+                    error!(service = %name, "oneshot service failed");
+                    self.failed.insert(name.clone());
+                }
+                if !success {
+                    self.note_failure(&svc.config, &format!("exited with {status}"));
                 }
                 self.finished.insert(name.clone(), svc.config);
+                if !success {
+                    self.fail_dependents(&name);
+                } else if !oneshot {
+                    self.stop_dependents(&name);
+                }
             }
 
             exited_names.push(name);
@@ -142,43 +667,315 @@ impl ServiceManager {
         exited_names
     }
 
-    fn spawn_with_count(&mut self, config: &ServiceConfig, restart_count: u32) -> Result<()> {
-        let name = config.name.clone();
+    /// Hand reaped health probes to their checks, which can no longer wait for them
+    /// themselves. Returns the other processes.
+    fn take_probes(&mut self, reaped: Vec<(u32, ExitStatus)>) -> Vec<(u32, ExitStatus)> {
+        reaped
+            .into_iter()
+            .filter(|&(pid, status)| {
+                let check = self
+                    .running
+                    .values_mut()
+                    .filter_map(|svc| svc.health.as_mut())
+                    .find(|check| check.probe_pid() == Some(pid));
+                match check {
+                    Some(check) => {
+                        check.reaped(status);
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .collect()
+    }
+
+    /// Pick the services out of reaped processes; the rest were orphans and are dropped.
+    fn match_services(&self, reaped: Vec<(u32, ExitStatus)>) -> Vec<(String, ExitStatus)> {
+        reaped
+            .into_iter()
+            .filter_map(|(pid, status)| {
+                let service = self
+                    .running
+                    .iter()
+                    .find(|(_, svc)| svc.child.id() == pid)
+                    .map(|(name, _)| name.clone());
+                if service.is_none() {
+                    debug!(pid, status = ?status, "reaped orphaned process");
+                }
+                service.map(|name| (name, status))
+            })
+            .collect()
+    }
+
+    /// Queue an exited service for restart after its backoff delay, unless it has
+    /// already used up its restart burst.
+    fn schedule_restart(&mut self, svc: RunningService) {
+        let config = svc.config;
+        let mut history = svc.history;
+        let window = config
+            .restart_window_sec
+            .map_or(DEFAULT_RESTART_WINDOW, Duration::from_secs);
+        let burst = config.restart_burst.unwrap_or(DEFAULT_RESTART_BURST);
+
+        if svc.started.elapsed() >= window {
+            history.consecutive = 0;
+        }
+        let now = Instant::now();
+        history
+            .recent
+            .retain(|&due| now.saturating_duration_since(due) < window);
+
+        if history.recent.len() >= burst as usize {
+            error!(
+                service = %config.name,
+                burst,
+                window_sec = window.as_secs(),
+                "service restarted too often, giving up"
+            );
+            self.note_failure(&config, "restarted too often");
+            let name = config.name.clone();
+            self.failed.insert(name.clone());
+            self.finished.insert(name.clone(), config);
+            self.fail_dependents(&name);
+            return;
+        }
+
+        let delay = restart_delay(&config, history.consecutive);
+        info!(
+            service = %config.name,
+            attempt = history.consecutive + 1,
+            delay_ms = delay.as_millis() as u64,
+            "scheduling restart"
+        );
+        let due = now + delay;
+        history.consecutive += 1;
+        history.recent.push_back(due);
+        self.restarts.push(ScheduledRestart {
+            config,
+            history,
+            due,
+        });
+    }
+
+    /// Remember that a service failed for good, if it is one the phone can't do without.
+    fn note_failure(&mut self, config: &ServiceConfig, reason: &str) {
+        if config.critical {
+            self.critical_failures
+                .push((config.name.clone(), reason.to_string()));
+        }
+    }
+
+    /// Critical services that failed for good since the last call, with the reason.
+    pub fn take_critical_failures(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.critical_failures)
+    }
+
+    /// Restart every service whose restart delay has passed.
+    pub fn restart_due(&mut self) {
+        let now = Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.restarts)
+            .into_iter()
+            .partition(|r| r.due <= now);
+        self.restarts = waiting;
+
+        for restart in due {
+            let name = restart.config.name.clone();
+            info!(service = %name, attempt = restart.history.consecutive, "restarting service");
+            if let Err(e) = self.respawn(&restart.config, restart.history) {
+                error!(service = %name, error = %e, "failed to restart service");
+                self.boot.failed(&name, &format!("{e:#}"), Instant::now());
+                self.note_failure(&restart.config, &format!("{e:#}"));
+                self.finished.insert(name.clone(), restart.config);
+                self.fail_dependents(&name);
+            }
+        }
+    }
 
-        let mut cmd = Command::new(&config.exec);
-        cmd.args(&config.args);
-        for (key, val) in &config.environment {
-            cmd.env(key, val);
+    fn respawn(&mut self, config: &ServiceConfig, history: RestartHistory) -> Result<()> {
+        let name = config.name.clone();
+        // The device a service needs may have gone away since it last started
+        if self.check_conditions(config.clone()).is_none() {
+            return Ok(());
         }
 
-        let child = cmd
-            .spawn()
+        let (child, notify) = self
+            .spawn(config)
             .with_context(|| format!("failed to restart service '{}'", name))?;
 
         info!(service = %name, pid = child.id(), "service restarted");
 
+        let ready = notify.is_none() && config.ready_path.is_none();
+        let now = Instant::now();
+        self.boot.spawned(config, now);
+        if ready {
+            self.boot.ready(&name, now);
+        }
+        let health = config
+            .health
+            .clone()
+            .map(|health| HealthCheck::new(&name, health, now));
         self.running.insert(
             name,
             RunningService {
                 config: config.clone(),
                 child,
-                restart_count,
+                history,
+                ready,
+                notify,
+                started: now,
+                pinged: now,
+                hung: false,
+                health,
             },
         );
 
         Ok(())
     }
 
-    /// Stop a service by sending SIGTERM, then SIGKILL after timeout.
+    /// Spawn the service process, routing its output according to `config.output`,
+    /// setting its OOM score, sandboxing it, dropping to its user/group and capabilities and confining it with
+    /// its seccomp profile, if any.
+    /// Notify services also get the readiness socket they were handed in NOTIFY_SOCKET.
+    fn spawn(&self, config: &ServiceConfig) -> Result<(Child, Option<NotifySocket>)> {
+        if let Some(path) = config
+            .requires_mount
+            .iter()
+            .find(|path| !mount::is_mounted(Path::new(mount::MOUNTINFO_PATH), Path::new(path)))
+        {
+            bail!("required mount {path} is not mounted");
+        }
+        if let Some(path) = &config.ready_path {
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("failed to remove stale {path}"));
+                }
+                _ => {}
+            }
+        }
+        let env = service_environment(config)?;
+        let mut cmd = Command::new(envfile::expand(&config.exec, &env));
+        cmd.args(config.args.iter().map(|arg| envfile::expand(arg, &env)));
+        cmd.envs(&env);
+
+        if config.output == OutputMode::Journal {
+            cmd.stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+        }
+
+        let notify = match config.service_type {
+            ServiceType::Notify => {
+                let dir = self
+                    .notify_dir
+                    .as_deref()
+                    .context("no notify socket directory configured")?;
+                let socket = NotifySocket::bind(dir, &config.name)
+                    .context("failed to create notify socket")?;
+                cmd.env(mos_notify::SOCKET_ENV, socket.path());
+                if let Some(timeout) = watchdog_timeout(config) {
+                    cmd.env(mos_notify::WATCHDOG_ENV, timeout.as_micros().to_string());
+                }
+                Some(socket)
+            }
+            _ => None,
+        };
+
+        // Expendable services are the kernel OOM killer's first pick, as they are init's
+        let oom_score_adj = config
+            .oom_score_adj
+            .or(config.expendable.then_some(oom::EXPENDABLE_SCORE_ADJ));
+        if let Some(adj) = oom_score_adj {
+            let value = adj.to_string();
+            // SAFETY: oom::set_score_adj only issues open/write/close.
+            unsafe {
+                cmd.pre_exec(move || oom::set_score_adj(value.as_bytes()));
+            }
+        }
+
+        // Ahead of the credential switch, since mounting needs root
+        if let Some(sandbox) = Sandbox::from_config(config)? {
+            // SAFETY: Sandbox::apply only issues unshare/mount/prctl.
+            unsafe {
+                cmd.pre_exec(move || sandbox.apply());
+            }
+        }
+
+        // Bounded while still root, then raised again once running as the service user
+        let capabilities = config
+            .capabilities
+            .as_deref()
+            .map(Capabilities::resolve)
+            .transpose()?;
+        if let Some(caps) = capabilities.clone() {
+            // SAFETY: Capabilities::bound only issues prctl calls.
+            unsafe {
+                cmd.pre_exec(move || caps.bound());
+            }
+        }
+
+        if config.user.is_some() || config.group.is_some() {
+            let creds = Credentials::resolve(config.user.as_deref(), config.group.as_deref())?;
+            if let Some((ref user, ref home)) = creds.user {
+                cmd.env("USER", user).env("HOME", home);
+            }
+            // Sending to a Unix socket needs write access to it
+            if let Some(ref socket) = notify {
+                std::os::unix::fs::chown(socket.path(), Some(creds.uid), Some(creds.gid))
+                    .context("failed to hand notify socket to service user")?;
+            }
+            // SAFETY: Credentials::apply only issues setgroups/setgid/setuid.
+            unsafe {
+                cmd.pre_exec(move || creds.apply());
+            }
+        }
+
+        if let Some(caps) = capabilities {
+            // SAFETY: Capabilities::raise only issues capset and prctl calls.
+            unsafe {
+                cmd.pre_exec(move || caps.raise());
+            }
+        }
+
+        // Registered after the credential switch: pre_exec hooks run in order, and the
+        // filter need not allow the set*id calls.
+        if let Some(ref profile) = config.seccomp {
+            let filter = seccomp::Filter::compile(profile, &self.seccomp_profiles)?;
+            // SAFETY: Filter::apply only issues prctl calls, which are async-signal-safe.
+            unsafe {
+                cmd.pre_exec(move || filter.apply());
+            }
+        }
+
+        let mut child = cmd.spawn()?;
+        self.journal.attach(&config.name, &mut child);
+        Ok((child, notify))
+    }
+
+    /// Have stops from now on be done by `deadline`, killing what hasn't exited by
+    /// then, or give them their full stop timeouts again with `None`.
+    pub fn limit_stopping(&mut self, deadline: Option<Instant>) {
+        self.stop_deadline = deadline;
+    }
+
+    /// Stop a service: run its `exec_stop` command if it has one, then send SIGTERM and
+    /// escalate to SIGKILL if it hasn't exited within its stop timeout. The services
+    /// that require it are stopped first.
     pub fn stop_service(&mut self, name: &str) -> Result<()> {
+        if self.running.contains_key(name) || self.restarts.iter().any(|r| r.config.name == name) {
+            self.stop_dependents(name);
+        }
         if let Some(mut svc) = self.running.remove(name) {
-            info!(service = %name, pid = svc.child.id(), "stopping service");
+            let pid = svc.child.id();
+            info!(service = %name, pid, "stopping service");
 
-            // Send SIGTERM
-            let _ = svc.child.kill();
+            if let Some(ref command) = svc.config.exec_stop
+                && let Err(e) =
+                    self.run_exec_stop(&svc.config, command, pid, self.stop_timeout(&svc.config))
+            {
+                warn!(service = %name, error = %e, "exec_stop failed");
+            }
 
-            // Wait briefly for exit
-            match svc.child.wait() {
+            match terminate(&mut svc.child, self.stop_timeout(&svc.config)) {
                 Ok(status) => {
                     info!(service = %name, status = ?status, "service stopped");
                 }
@@ -188,75 +985,313 @@ impl ServiceManager {
             }
 
             self.finished.insert(name.to_string(), svc.config);
+        } else if let Some(i) = self.restarts.iter().position(|r| r.config.name == name) {
+            info!(service = %name, "cancelling scheduled restart");
+            let restart = self.restarts.remove(i);
+            self.finished.insert(name.to_string(), restart.config);
         }
 
         Ok(())
     }
 
-    /// Stop all running services.
-    pub fn stop_all(&mut self) {
-        let names: Vec<String> = self.running.keys().cloned().collect();
-        for name in names {
-            let _ = self.stop_service(&name);
+    /// A service's stop timeout, cut short to end by the stop deadline if there is one.
+    fn stop_timeout(&self, config: &ServiceConfig) -> Duration {
+        let timeout = config
+            .stop_timeout_sec
+            .map_or(DEFAULT_STOP_TIMEOUT, Duration::from_secs);
+        match self.stop_deadline {
+            Some(deadline) => timeout.min(deadline.saturating_duration_since(Instant::now())),
+            None => timeout,
         }
     }
 
-    pub fn running_service_names(&self) -> Vec<&str> {
-        self.running.keys().map(|s| s.as_str()).collect()
+    /// CPU time and memory of the running services' main processes, by name.
+    pub fn usage(&self) -> Vec<(String, Usage)> {
+        self.running
+            .iter()
+            .filter_map(|(name, svc)| Some((name.clone(), accounting::read(svc.child.id())?)))
+            .collect()
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
 
-    fn simple_service(name: &str, exec: &str) -> ServiceConfig {
-        ServiceConfig {
-            name: name.to_string(),
-            exec: exec.to_string(),
-            args: Vec::new(),
-            depends_on: Vec::new(),
-            restart: RestartPolicy::Never,
-            service_type: ServiceType::Simple,
-            environment: HashMap::new(),
+    /// Kill the expendable service using the most memory, to relieve memory pressure.
+    /// There is no time for a graceful stop, and it isn't restarted; whatever launched it
+    /// starts it again when it is wanted. Returns its name.
+    pub fn kill_expendable(&mut self) -> Option<String> {
+        let name = self
+            .running
+            .iter()
+            .filter(|(_, svc)| svc.config.expendable)
+            .max_by_key(|(_, svc)| oom::resident_pages(svc.child.id()))
+            .map(|(name, _)| name.clone())?;
+        let mut svc = self.running.remove(&name)?;
+        warn!(
+            service = %name,
+            pid = svc.child.id(),
+            "killing expendable service under memory pressure"
+        );
+        if let Err(e) = svc.child.kill().and_then(|()| svc.child.wait()) {
+            error!(service = %name, error = %e, "failed to kill expendable service");
         }
+        self.finished.insert(name.clone(), svc.config);
+        self.stop_dependents(&name);
+        Some(name)
     }
 
-    #[test]
-    fn new_manager_is_empty() {
-        let mgr = ServiceManager::new();
-        assert_eq!(mgr.running_count(), 0);
-    }
+    /// Run a service's `exec_stop` command through the shell, as the service's user and
+    /// with its environment, giving it up to `timeout` to finish.
+    fn run_exec_stop(
+        &self,
+        config: &ServiceConfig,
+        command: &str,
+        pid: u32,
+        timeout: Duration,
+    ) -> Result<()> {
+        info!(service = %config.name, command = %command, "running exec_stop");
 
-    #[test]
-    fn start_real_process() {
-        let mut mgr = ServiceManager::new();
-        let svc = simple_service("sleeper", "sleep");
-        let mut svc = svc;
-        svc.args = vec!["10".to_string()];
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c")
+            .arg(command)
+            .envs(service_environment(config)?)
+            .env("MAINPID", pid.to_string());
 
-        mgr.start_service(svc).unwrap();
-        assert_eq!(mgr.running_count(), 1);
-        assert_eq!(mgr.state("sleeper"), ServiceState::Running);
+        if config.output == OutputMode::Journal {
+            cmd.stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+        }
 
-        // Clean up
-        mgr.stop_all();
-    }
+        if config.user.is_some() || config.group.is_some() {
+            let creds = Credentials::resolve(config.user.as_deref(), config.group.as_deref())?;
+            // SAFETY: Credentials::apply only issues setgroups/setgid/setuid.
+            unsafe {
+                cmd.pre_exec(move || creds.apply());
+            }
+        }
 
-    #[test]
-    fn start_nonexistent_binary_fails() {
-        let mut mgr = ServiceManager::new();
-        let svc = simple_service("broken", "/nonexistent/binary/path");
+        let mut child = cmd.spawn().context("failed to run exec_stop")?;
+        self.journal.attach(&config.name, &mut child);
 
-        assert!(mgr.start_service(svc).is_err());
-        assert_eq!(mgr.running_count(), 0);
+        match wait_timeout(&mut child, timeout)? {
+            Some(status) if !status.success() => {
+                warn!(service = %config.name, status = ?status, "exec_stop exited with error");
+            }
+            Some(_) => {}
+            None => {
+                warn!(service = %config.name, "exec_stop timed out, killing it");
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+
+        Ok(())
     }
 
-    #[test]
-    fn reap_detects_exit() {
-        let mut mgr = ServiceManager::new();
-        let svc = simple_service("quick", "true");
+    /// Running services and those waiting to restart, in the order to stop them:
+    /// each before the services it depends on.
+    fn stop_order(&self) -> Vec<String> {
+        let active: Vec<&ServiceConfig> = self
+            .running
+            .values()
+            .map(|svc| &svc.config)
+            .chain(self.restarts.iter().map(|r| &r.config))
+            .collect();
+        dependency::resolve_stop_order(&active)
+    }
+
+    /// Stop all running services and cancel scheduled restarts, in reverse dependency
+    /// order, so clients don't see the services they talk to go away first.
+    pub fn stop_all(&mut self) {
+        for name in self.stop_order() {
+            let _ = self.stop_service(&name);
+        }
+    }
+
+    /// Stop every service not in `keep`, cancel their restarts, and drop those still
+    /// waiting to start.
+    pub fn stop_except(&mut self, keep: &HashSet<String>) {
+        for name in self.stop_order() {
+            if !keep.contains(&name) {
+                let _ = self.stop_service(&name);
+            }
+        }
+
+        let (kept, dropped): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|config| keep.contains(&config.name));
+        self.pending = kept;
+        for config in dropped {
+            info!(service = %config.name, "not starting service");
+            self.finished.insert(config.name.clone(), config);
+        }
+    }
+
+    pub fn running_service_names(&self) -> Vec<&str> {
+        self.running.keys().map(|s| s.as_str()).collect()
+    }
+
+    /// Names of every service the manager has seen, running or not, sorted.
+    pub fn service_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .running
+            .keys()
+            .chain(self.finished.keys())
+            .map(|s| s.as_str())
+            .chain(self.pending.iter().map(|c| c.name.as_str()))
+            .chain(self.restarts.iter().map(|r| r.config.name.as_str()))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn pid(&self, name: &str) -> Option<u32> {
+        self.running.get(name).map(|svc| svc.child.id())
+    }
+}
+
+/// The variables a service runs with: its `environment` table, overridden by its
+/// environment file, which is re-read on every start.
+fn service_environment(config: &ServiceConfig) -> Result<HashMap<String, String>> {
+    let mut env = config.environment.clone();
+    if let Some(ref spec) = config.environment_file {
+        env.extend(envfile::load(spec)?);
+    }
+    Ok(env)
+}
+
+/// A service's health probe, run as its user and with its environment.
+fn probe_command(config: &ServiceConfig) -> Result<Command> {
+    let health = config
+        .health
+        .as_ref()
+        .context("service has no health check")?;
+    let mut cmd = health::command(health);
+    cmd.envs(service_environment(config)?);
+    if config.user.is_some() || config.group.is_some() {
+        let creds = Credentials::resolve(config.user.as_deref(), config.group.as_deref())?;
+        // SAFETY: Credentials::apply only issues setgroups/setgid/setuid.
+        unsafe {
+            cmd.pre_exec(move || creds.apply());
+        }
+    }
+    Ok(cmd)
+}
+
+/// How long a service may take to become ready, if it is timed at all.
+fn start_timeout(config: &ServiceConfig) -> Option<Duration> {
+    match (&config.service_type, config.start_timeout_sec) {
+        (_, Some(secs)) => Some(Duration::from_secs(secs)),
+        (ServiceType::Oneshot, None) => None,
+        (_, None) => Some(DEFAULT_START_TIMEOUT),
+    }
+}
+
+/// How long a ready notify service may go between WATCHDOG=1 pings, if it is watched.
+fn watchdog_timeout(config: &ServiceConfig) -> Option<Duration> {
+    match config.service_type {
+        ServiceType::Notify => config.watchdog_sec.map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+/// Backoff before a service's next restart: its base delay, doubled for every
+/// consecutive restart so far, up to MAX_RESTART_DELAY.
+fn restart_delay(config: &ServiceConfig, consecutive: u32) -> Duration {
+    let base = config
+        .restart_delay_ms
+        .map_or(DEFAULT_RESTART_DELAY, Duration::from_millis);
+    base.saturating_mul(2u32.saturating_pow(consecutive))
+        .min(MAX_RESTART_DELAY)
+}
+
+/// Send SIGTERM, wait up to `timeout` for the process to exit, then SIGKILL it.
+fn terminate(child: &mut Child, timeout: Duration) -> std::io::Result<ExitStatus> {
+    // It may already be gone, e.g. stopped by its exec_stop command
+    if let Some(status) = child.try_wait()? {
+        return Ok(status);
+    }
+
+    kill_process(Pid::from_child(child), Signal::TERM)?;
+    if let Some(status) = wait_timeout(child, timeout)? {
+        return Ok(status);
+    }
+
+    warn!(
+        pid = child.id(),
+        timeout_sec = timeout.as_secs(),
+        "process ignored SIGTERM, sending SIGKILL"
+    );
+    child.kill()?;
+    child.wait()
+}
+
+/// Wait for a child to exit, giving up after `timeout`.
+fn wait_timeout(child: &mut Child, timeout: Duration) -> std::io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep(STOP_POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::config::HealthConfig;
+
+    fn simple_service(name: &str, exec: &str) -> ServiceConfig {
+        ServiceConfig {
+            name: name.to_string(),
+            exec: exec.to_string(),
+            args: Vec::new(),
+            depends_on: Vec::new(),
+            restart: RestartPolicy::Never,
+            service_type: ServiceType::Simple,
+            environment: HashMap::new(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn new_manager_is_empty() {
+        let mgr = ServiceManager::new();
+        assert_eq!(mgr.running_count(), 0);
+    }
+
+    #[test]
+    fn start_real_process() {
+        let mut mgr = ServiceManager::new();
+        let svc = simple_service("sleeper", "sleep");
+        let mut svc = svc;
+        svc.args = vec!["10".to_string()];
+
+        mgr.start_service(svc).unwrap();
+        assert_eq!(mgr.running_count(), 1);
+        assert_eq!(mgr.state("sleeper"), ServiceState::Running);
+
+        // Clean up
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn start_nonexistent_binary_fails() {
+        let mut mgr = ServiceManager::new();
+        let svc = simple_service("broken", "/nonexistent/binary/path");
+
+        assert!(mgr.start_service(svc).is_err());
+        assert_eq!(mgr.running_count(), 0);
+    }
+
+    #[test]
+    fn reap_detects_exit() {
+        let mut mgr = ServiceManager::new();
+        let svc = simple_service("quick", "true");
 
         mgr.start_service(svc).unwrap();
         assert_eq!(mgr.running_count(), 1);
@@ -270,6 +1305,24 @@ mod tests {
         assert_eq!(mgr.state("quick"), ServiceState::Finished);
     }
 
+    #[test]
+    fn reaped_processes_are_matched_to_services() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("sleeper", "sleep");
+        svc.args = vec!["10".to_string()];
+        mgr.start_service(svc).unwrap();
+        let pid = mgr.pid("sleeper").unwrap();
+
+        let failed = ExitStatus::from_raw(1 << 8);
+        let orphan = ExitStatus::from_raw(0);
+        let matched = mgr.match_services(vec![(pid + 100_000, orphan), (pid, failed)]);
+        assert_eq!(matched, vec![("sleeper".to_string(), failed)]);
+
+        mgr.stop_all();
+    }
+
     #[test]
     fn restart_on_failure_triggers_for_failing_service() {
         let mut mgr = ServiceManager::new();
@@ -281,20 +1334,152 @@ mod tests {
             restart: RestartPolicy::OnFailure,
             service_type: ServiceType::Simple,
             environment: HashMap::new(),
+            ..Default::default()
         };
 
         mgr.start_service(svc).unwrap();
         std::thread::sleep(std::time::Duration::from_millis(100));
 
         let exited = mgr.reap();
-        // Service should have been restarted, so it's back in running
         assert!(exited.contains(&"failing".to_string()));
+        assert_eq!(mgr.state("failing"), ServiceState::Restarting);
+
+        // Service should be restarted once its delay has passed, so it's back in running
+        std::thread::sleep(DEFAULT_RESTART_DELAY);
+        mgr.restart_due();
         assert_eq!(mgr.state("failing"), ServiceState::Running);
 
         // Clean up
         mgr.stop_all();
     }
 
+    fn crashing_service(name: &str) -> ServiceConfig {
+        let mut svc = simple_service(name, "false");
+        svc.restart = RestartPolicy::Always;
+        svc.restart_delay_ms = Some(0);
+        svc
+    }
+
+    /// Let the service exit, reap it and restart it if it is due.
+    fn crash_and_restart(mgr: &mut ServiceManager) {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        mgr.reap();
+        mgr.restart_due();
+    }
+
+    #[test]
+    fn restart_delay_doubles_up_to_cap() {
+        let mut svc = simple_service("compositor", "true");
+        svc.restart_delay_ms = Some(100);
+        assert_eq!(restart_delay(&svc, 0), Duration::from_millis(100));
+        assert_eq!(restart_delay(&svc, 3), Duration::from_millis(800));
+        assert_eq!(restart_delay(&svc, 40), MAX_RESTART_DELAY);
+
+        svc.restart_delay_ms = None;
+        assert_eq!(restart_delay(&svc, 0), DEFAULT_RESTART_DELAY);
+    }
+
+    #[test]
+    fn restarts_wait_for_backoff() {
+        let mut mgr = ServiceManager::new();
+        let mut svc = crashing_service("compositor");
+        svc.restart_delay_ms = Some(60_000);
+
+        mgr.start_service(svc).unwrap();
+        crash_and_restart(&mut mgr);
+        assert_eq!(mgr.state("compositor"), ServiceState::Restarting);
+        assert_eq!(mgr.pid("compositor"), None);
+        assert_eq!(mgr.service_names(), vec!["compositor"]);
+
+        mgr.stop_service("compositor").unwrap();
+        assert_eq!(mgr.state("compositor"), ServiceState::Finished);
+    }
+
+    #[test]
+    fn crash_loop_gives_up_after_burst() {
+        let mut mgr = ServiceManager::new();
+        let mut svc = crashing_service("compositor");
+        svc.restart_burst = Some(2);
+
+        mgr.start_service(svc).unwrap();
+        crash_and_restart(&mut mgr);
+        crash_and_restart(&mut mgr);
+        assert_eq!(mgr.state("compositor"), ServiceState::Running);
+
+        crash_and_restart(&mut mgr);
+        assert_eq!(mgr.state("compositor"), ServiceState::Failed);
+        assert_eq!(mgr.running_count(), 0);
+    }
+
+    #[test]
+    fn critical_services_report_permanent_failure() {
+        let mut mgr = ServiceManager::new();
+        let mut compositor = crashing_service("compositor");
+        compositor.restart_burst = Some(1);
+        compositor.critical = true;
+        let mut helper = crashing_service("helper");
+        helper.restart_burst = Some(1);
+
+        mgr.start_service(compositor).unwrap();
+        mgr.start_service(helper).unwrap();
+        crash_and_restart(&mut mgr);
+        assert!(mgr.take_critical_failures().is_empty());
+
+        crash_and_restart(&mut mgr);
+        assert_eq!(
+            mgr.take_critical_failures(),
+            [("compositor".to_string(), "restarted too often".to_string())]
+        );
+        assert!(mgr.take_critical_failures().is_empty());
+
+        let mut dbus = simple_service("dbus", "/nonexistent/dbus-daemon");
+        dbus.critical = true;
+        mgr.enqueue(vec![dbus]);
+        mgr.start_pending();
+        let failures = mgr.take_critical_failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "dbus");
+    }
+
+    #[test]
+    fn stop_except_keeps_only_the_listed_services() {
+        let mut mgr = ServiceManager::new();
+        for name in ["console", "shell"] {
+            let mut svc = simple_service(name, "sleep");
+            svc.args = vec!["60".to_string()];
+            mgr.start_service(svc).unwrap();
+        }
+        let mut pending = simple_service("dialer", "sleep");
+        pending.depends_on = vec!["shell-ready".to_string()];
+        mgr.enqueue(vec![simple_service("shell-ready", "true"), pending]);
+
+        mgr.stop_except(&HashSet::from(["console".to_string()]));
+        assert_eq!(mgr.state("console"), ServiceState::Running);
+        assert_eq!(mgr.state("shell"), ServiceState::Finished);
+        assert_ne!(mgr.state("dialer"), ServiceState::Pending);
+        assert_ne!(mgr.state("shell-ready"), ServiceState::Pending);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn stable_run_resets_backoff() {
+        let mut mgr = ServiceManager::new();
+        let mut svc = crashing_service("compositor");
+        svc.restart_window_sec = Some(0);
+        svc.restart_burst = Some(1);
+
+        // Every run outlasts a zero-second window, so the service is never given up on
+        mgr.start_service(svc).unwrap();
+        for _ in 0..3 {
+            crash_and_restart(&mut mgr);
+            assert_eq!(mgr.state("compositor"), ServiceState::Running);
+            assert_eq!(mgr.running["compositor"].history.consecutive, 1);
+        }
+
+        mgr.stop_all();
+    }
+
     #[test]
     fn no_restart_for_successful_on_failure_policy() {
         let mut mgr = ServiceManager::new();
@@ -306,6 +1491,7 @@ mod tests {
             restart: RestartPolicy::OnFailure,
             service_type: ServiceType::Simple,
             environment: HashMap::new(),
+            ..Default::default()
         };
 
         mgr.start_service(svc).unwrap();
@@ -327,6 +1513,7 @@ mod tests {
             restart: RestartPolicy::Always,
             service_type: ServiceType::Oneshot,
             environment: HashMap::new(),
+            ..Default::default()
         };
 
         mgr.start_service(svc).unwrap();
@@ -351,6 +1538,223 @@ mod tests {
         assert_eq!(mgr.running_count(), 0);
     }
 
+    #[test]
+    fn stop_sends_sigterm_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("signal");
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("messages", "sh");
+        svc.args = vec![
+            "-c".to_string(),
+            format!(
+                "trap 'echo term > {}; exit 0' TERM; while true; do sleep 0.05; done",
+                out.display()
+            ),
+        ];
+
+        mgr.start_service(svc).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        mgr.stop_service("messages").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&out).unwrap().trim(), "term");
+        assert_eq!(mgr.state("messages"), ServiceState::Finished);
+    }
+
+    #[test]
+    fn stop_escalates_to_sigkill() {
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("stubborn", "sh");
+        svc.args = vec![
+            "-c".to_string(),
+            "trap '' TERM; while true; do sleep 0.05; done".to_string(),
+        ];
+        svc.stop_timeout_sec = Some(0);
+
+        mgr.start_service(svc).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        mgr.stop_service("stubborn").unwrap();
+
+        assert_eq!(mgr.state("stubborn"), ServiceState::Finished);
+        assert_eq!(mgr.running_count(), 0);
+    }
+
+    #[test]
+    fn stops_share_the_deadline() {
+        let mut mgr = ServiceManager::new();
+        for name in ["stubborn", "obstinate"] {
+            let mut svc = simple_service(name, "sh");
+            svc.args = vec![
+                "-c".to_string(),
+                "trap '' TERM; while true; do sleep 0.05; done".to_string(),
+            ];
+            mgr.start_service(svc).unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let start = Instant::now();
+        mgr.limit_stopping(Some(start + Duration::from_millis(200)));
+        mgr.stop_service("stubborn").unwrap();
+        mgr.stop_service("obstinate").unwrap();
+
+        assert_eq!(mgr.running_count(), 0);
+        assert!(start.elapsed() < DEFAULT_STOP_TIMEOUT / 2);
+    }
+
+    #[test]
+    fn exec_stop_runs_with_main_pid() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("mainpid");
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("network", "sleep");
+        svc.args = vec!["10".to_string()];
+        svc.exec_stop = Some(format!("echo $MAINPID > {}", out.display()));
+
+        mgr.start_service(svc).unwrap();
+        let pid = mgr.pid("network").unwrap();
+        mgr.stop_service("network").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&out).unwrap().trim(), pid.to_string());
+        assert_eq!(mgr.state("network"), ServiceState::Finished);
+    }
+
+    #[test]
+    fn stop_all_stops_dependents_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("stopped");
+        let mut mgr = ServiceManager::new();
+        let service = |name: &str, depends_on: &[&str], wants: &[&str]| {
+            let mut svc = simple_service(name, "sleep");
+            svc.args = vec!["10".to_string()];
+            svc.depends_on = depends_on.iter().map(|d| d.to_string()).collect();
+            svc.wants = wants.iter().map(|d| d.to_string()).collect();
+            svc.exec_stop = Some(format!("echo {name} >> {}", out.display()));
+            svc
+        };
+        // Started the wrong way round, as a reload might
+        mgr.start_service(service("dbus", &[], &[])).unwrap();
+        mgr.start_service(service("shell", &[], &["compositor"])).unwrap();
+        mgr.start_service(service("compositor", &["dbus"], &[])).unwrap();
+
+        mgr.stop_all();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "shell\ncompositor\ndbus\n"
+        );
+    }
+
+    #[test]
+    fn oom_score_adj_is_set_before_exec() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("gallery", "sh");
+        svc.service_type = ServiceType::Oneshot;
+        svc.expendable = true;
+        svc.args = vec![
+            "-c".to_string(),
+            format!("cat /proc/self/oom_score_adj > {}", out.display()),
+        ];
+        mgr.start_service(svc).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        mgr.reap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap().trim(), "900");
+    }
+
+    #[test]
+    fn memory_pressure_kills_only_expendable_services() {
+        let mut mgr = ServiceManager::new();
+        let mut gallery = simple_service("gallery", "sleep");
+        gallery.args = vec!["60".to_string()];
+        gallery.restart = RestartPolicy::Always;
+        gallery.expendable = true;
+        let mut compositor = simple_service("compositor", "sleep");
+        compositor.args = vec!["60".to_string()];
+        mgr.start_service(gallery).unwrap();
+        mgr.start_service(compositor).unwrap();
+
+        assert_eq!(mgr.kill_expendable().as_deref(), Some("gallery"));
+        assert_eq!(mgr.kill_expendable(), None);
+        mgr.reap();
+        mgr.restart_due();
+        assert_eq!(mgr.state("gallery"), ServiceState::Finished);
+        assert_eq!(mgr.state("compositor"), ServiceState::Running);
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn conflicting_service_is_stopped_first() {
+        let mut mgr = ServiceManager::new();
+        let mut splash = simple_service("splash", "sleep");
+        splash.args = vec!["60".to_string()];
+        splash.restart = RestartPolicy::Always;
+        mgr.start_service(splash).unwrap();
+
+        let mut compositor = simple_service("compositor", "sleep");
+        compositor.args = vec!["60".to_string()];
+        compositor.conflicts = vec!["splash".to_string(), "greeter".to_string()];
+        mgr.start_service(compositor).unwrap();
+
+        assert_eq!(mgr.state("splash"), ServiceState::Finished);
+        assert_eq!(mgr.state("compositor"), ServiceState::Running);
+        mgr.reap();
+        mgr.restart_due();
+        assert_eq!(mgr.state("splash"), ServiceState::Finished);
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn boot_progress_counts_settled_services() {
+        let mut mgr = ServiceManager::new();
+        let mut dbus = simple_service("dbus", "sleep");
+        dbus.args = vec!["60".to_string()];
+        let mut shell = simple_service("shell", "sleep");
+        shell.depends_on = vec!["compositor".to_string()];
+        let mut compositor = simple_service("compositor", "sleep");
+        compositor.service_type = ServiceType::Oneshot;
+        compositor.args = vec!["60".to_string()];
+        mgr.enqueue(vec![dbus, compositor, shell]);
+        assert_eq!(mgr.boot_progress(), (0, 3));
+
+        // The oneshot holds back the shell until it finishes
+        mgr.start_pending();
+        assert_eq!(mgr.boot_progress(), (1, 3));
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn environment_file_overrides_and_expands() {
+        let dir = tempfile::tempdir().unwrap();
+        let env_path = dir.path().join("compositor.env");
+        let out = dir.path().join("out");
+        std::fs::write(&env_path, format!("PANEL=dsi-1\nOUT={}\n", out.display())).unwrap();
+
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("compositor", "sh");
+        svc.service_type = ServiceType::Oneshot;
+        svc.environment = HashMap::from([("PANEL".to_string(), "hdmi".to_string())]);
+        svc.environment_file = Some(env_path.display().to_string());
+        // $PANEL is expanded by init, $$PANEL by the shell from the environment
+        svc.args = vec!["-c".to_string(), "echo $PANEL $$PANEL > ${OUT}".to_string()];
+
+        mgr.start_service(svc).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        mgr.reap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap().trim(), "dsi-1 dsi-1");
+    }
+
+    #[test]
+    fn missing_environment_file_fails_start_unless_optional() {
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("compositor", "true");
+        svc.environment_file = Some("/nonexistent/compositor.env".to_string());
+        assert!(mgr.start_service(svc.clone()).is_err());
+
+        svc.environment_file = Some("-/nonexistent/compositor.env".to_string());
+        mgr.start_service(svc).unwrap();
+        mgr.stop_all();
+    }
+
     #[test]
     fn service_environment_is_passed() {
         let mut mgr = ServiceManager::new();
@@ -365,6 +1769,7 @@ mod tests {
             environment: HashMap::from([
                 ("MY_VAR".to_string(), "hello".to_string()),
             ]),
+            ..Default::default()
         };
 
         mgr.start_service(svc).unwrap();
@@ -372,4 +1777,464 @@ mod tests {
         mgr.reap();
         assert_eq!(mgr.state("envtest"), ServiceState::Finished);
     }
+
+    fn send_ready(dir: &Path, name: &str) {
+        let client = std::os::unix::net::UnixDatagram::unbound().unwrap();
+        client
+            .send_to(b"READY=1", dir.join(format!("{name}.sock")))
+            .unwrap();
+    }
+
+    #[test]
+    fn notify_service_is_starting_until_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mgr = ServiceManager::new().with_notify_dir(dir.path());
+        let mut svc = simple_service("power", "sleep");
+        svc.args = vec!["10".to_string()];
+        svc.service_type = ServiceType::Notify;
+
+        mgr.start_service(svc).unwrap();
+        assert_eq!(mgr.state("power"), ServiceState::Starting);
+        assert!(mgr.poll_notifications().is_empty());
+
+        send_ready(dir.path(), "power");
+        assert_eq!(mgr.poll_notifications(), vec!["power"]);
+        assert_eq!(mgr.state("power"), ServiceState::Running);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn notify_service_gets_socket_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("socket-path");
+        let mut mgr = ServiceManager::new().with_notify_dir(dir.path());
+        let mut svc = simple_service("power", "sh");
+        svc.args = vec![
+            "-c".to_string(),
+            format!("echo \"$NOTIFY_SOCKET\" > {}", out.display()),
+        ];
+        svc.service_type = ServiceType::Notify;
+
+        mgr.start_service(svc).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let path = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(path.trim(), dir.path().join("power.sock").to_str().unwrap());
+    }
+
+    #[test]
+    fn service_is_starting_until_its_ready_path_appears() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("bus_socket");
+        std::fs::write(&socket, "left over").unwrap();
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("dbus", "sleep");
+        svc.args = vec!["10".to_string()];
+        svc.ready_path = Some(socket.to_str().unwrap().to_string());
+
+        mgr.start_service(svc).unwrap();
+        assert!(!socket.exists());
+        assert_eq!(mgr.state("dbus"), ServiceState::Starting);
+        assert!(mgr.poll_notifications().is_empty());
+        assert!(mgr.next_deadline().is_some());
+
+        std::fs::write(&socket, "").unwrap();
+        assert_eq!(mgr.poll_notifications(), vec!["dbus"]);
+        assert_eq!(mgr.state("dbus"), ServiceState::Running);
+        assert_eq!(mgr.next_deadline(), None);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn notify_service_without_socket_dir_fails() {
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("power", "true");
+        svc.service_type = ServiceType::Notify;
+        assert!(mgr.start_service(svc).is_err());
+    }
+
+    #[test]
+    fn dependents_wait_for_readiness() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mgr = ServiceManager::new().with_notify_dir(dir.path());
+        let mut power = simple_service("power", "sleep");
+        power.args = vec!["10".to_string()];
+        power.service_type = ServiceType::Notify;
+        let mut shell = simple_service("shell", "sleep");
+        shell.args = vec!["10".to_string()];
+        shell.depends_on = vec!["power".to_string()];
+
+        mgr.enqueue(vec![power, shell]);
+        mgr.start_pending();
+        assert_eq!(mgr.state("power"), ServiceState::Starting);
+        assert_eq!(mgr.state("shell"), ServiceState::Pending);
+        assert_eq!(mgr.service_names(), vec!["power", "shell"]);
+
+        send_ready(dir.path(), "power");
+        mgr.poll_notifications();
+        mgr.start_pending();
+        assert_eq!(mgr.state("shell"), ServiceState::Running);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn dependents_wait_for_oneshot_to_finish() {
+        let mut mgr = ServiceManager::new();
+        let mut hostname = simple_service("hostname", "sleep");
+        hostname.args = vec!["0.1".to_string()];
+        hostname.service_type = ServiceType::Oneshot;
+        let mut shell = simple_service("shell", "sleep");
+        shell.args = vec!["10".to_string()];
+        shell.depends_on = vec!["hostname".to_string()];
+
+        mgr.enqueue(vec![hostname, shell]);
+        mgr.start_pending();
+        assert_eq!(mgr.state("hostname"), ServiceState::Starting);
+        assert_eq!(mgr.state("shell"), ServiceState::Pending);
+        // Oneshots without a start timeout may take as long as they need
+        assert!(mgr.check_start_timeouts().is_empty());
+
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        mgr.reap();
+        mgr.start_pending();
+        assert_eq!(mgr.state("hostname"), ServiceState::Finished);
+        assert_eq!(mgr.state("shell"), ServiceState::Running);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn failed_oneshot_fails_dependents() {
+        let mut mgr = ServiceManager::new();
+        let mut fsck = simple_service("fsck", "false");
+        fsck.service_type = ServiceType::Oneshot;
+        let mut shell = simple_service("shell", "sleep");
+        shell.args = vec!["10".to_string()];
+        shell.depends_on = vec!["fsck".to_string()];
+
+        mgr.enqueue(vec![fsck, shell]);
+        mgr.start_pending();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        mgr.reap();
+        mgr.start_pending();
+
+        assert_eq!(mgr.state("fsck"), ServiceState::Failed);
+        assert_eq!(mgr.state("shell"), ServiceState::Failed);
+    }
+
+    #[test]
+    fn start_timeout_kills_and_escalates() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mgr = ServiceManager::new().with_notify_dir(dir.path());
+        let mut svc = simple_service("compositor", "sleep");
+        svc.args = vec!["10".to_string()];
+        svc.service_type = ServiceType::Notify;
+        svc.start_timeout_sec = Some(0);
+        svc.on_failure = FailureAction::RescueShell;
+
+        mgr.start_service(svc).unwrap();
+        let failures = mgr.check_start_timeouts();
+        assert_eq!(failures, vec![("compositor".to_string(), FailureAction::RescueShell)]);
+        assert_eq!(mgr.state("compositor"), ServiceState::Failed);
+        assert_eq!(mgr.running_count(), 0);
+    }
+
+    #[test]
+    fn ready_services_are_not_timed_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mgr = ServiceManager::new().with_notify_dir(dir.path());
+        let mut power = simple_service("power", "sleep");
+        power.args = vec!["10".to_string()];
+        power.service_type = ServiceType::Notify;
+        power.start_timeout_sec = Some(0);
+        let mut plain = simple_service("plain", "sleep");
+        plain.args = vec!["10".to_string()];
+        plain.start_timeout_sec = Some(0);
+
+        mgr.start_service(power).unwrap();
+        mgr.start_service(plain).unwrap();
+        send_ready(dir.path(), "power");
+        mgr.poll_notifications();
+
+        assert!(mgr.check_start_timeouts().is_empty());
+        assert_eq!(mgr.running_count(), 2);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn hung_service_is_aborted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mgr = ServiceManager::new().with_notify_dir(dir.path());
+        // No core dump left behind by the abort
+        let mut svc = simple_service("sensors", "sh");
+        svc.args = vec!["-c".to_string(), "ulimit -c 0; exec sleep 10".to_string()];
+        svc.service_type = ServiceType::Notify;
+        svc.watchdog_sec = Some(0);
+
+        mgr.start_service(svc).unwrap();
+        // Not watched until ready
+        assert!(mgr.check_watchdogs().is_empty());
+        assert_eq!(mgr.state("sensors"), ServiceState::Starting);
+
+        send_ready(dir.path(), "sensors");
+        mgr.poll_notifications();
+        assert!(mgr.check_watchdogs().is_empty());
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(mgr.reap(), ["sensors"]);
+    }
+
+    #[test]
+    fn unhealthy_service_is_aborted() {
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("network", "sh");
+        svc.args = vec!["-c".to_string(), "ulimit -c 0; exec sleep 10".to_string()];
+        svc.health = Some(HealthConfig {
+            exec: Some("exit 1".to_string()),
+            dbus_name: None,
+            interval_sec: 0,
+            timeout_sec: 5,
+            failure_threshold: 2,
+        });
+
+        mgr.start_service(svc).unwrap();
+        let mut unhealthy = Vec::new();
+        for _ in 0..100 {
+            unhealthy = mgr.check_health();
+            if !unhealthy.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert_eq!(unhealthy, ["network"]);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(mgr.reap(), ["network"]);
+    }
+
+    #[test]
+    fn only_services_that_ask_are_frozen_for_sleep() {
+        let mut mgr = ServiceManager::new();
+        let mut gallery = simple_service("gallery", "sleep");
+        gallery.args = vec!["10".to_string()];
+        gallery.freeze_on_sleep = true;
+        let mut modem = simple_service("modem", "sleep");
+        modem.args = vec!["10".to_string()];
+        mgr.start_service(gallery).unwrap();
+        mgr.start_service(modem).unwrap();
+
+        let (gallery, modem) = (mgr.pid("gallery").unwrap(), mgr.pid("modem").unwrap());
+        // The process state is the field after the parenthesized command name
+        let state = |pid: u32| {
+            let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+            stat.rsplit_once(") ").unwrap().1.chars().next().unwrap()
+        };
+        let frozen = mgr.freeze();
+        assert_eq!(frozen, ["gallery"]);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(state(gallery), 'T');
+        assert_ne!(state(modem), 'T');
+
+        mgr.thaw(&frozen);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_ne!(state(gallery), 'T');
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn hung_reset_service_holds_back_the_hardware_watchdog() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mgr = ServiceManager::new().with_notify_dir(dir.path());
+        let mut svc = simple_service("compositor", "sleep");
+        svc.args = vec!["10".to_string()];
+        svc.service_type = ServiceType::Notify;
+        svc.watchdog_sec = Some(0);
+        svc.watchdog_reset = true;
+
+        mgr.start_service(svc).unwrap();
+        send_ready(dir.path(), "compositor");
+        mgr.poll_notifications();
+        assert_eq!(mgr.check_watchdogs(), ["compositor"]);
+        // Left running, and still hung on the next pass
+        assert_eq!(mgr.check_watchdogs(), ["compositor"]);
+        assert_eq!(mgr.state("compositor"), ServiceState::Running);
+        assert_eq!(mgr.next_deadline(), None);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn next_deadline_covers_timeouts_and_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mgr = ServiceManager::new().with_notify_dir(dir.path());
+        assert_eq!(mgr.next_deadline(), None);
+
+        let mut power = simple_service("power", "sleep");
+        power.args = vec!["10".to_string()];
+        power.service_type = ServiceType::Notify;
+        power.start_timeout_sec = Some(30);
+        let before = Instant::now();
+        mgr.start_service(power).unwrap();
+        let deadline = mgr.next_deadline().unwrap();
+        assert!(deadline >= before + Duration::from_secs(30));
+        assert_eq!(mgr.notify_sockets().count(), 1);
+
+        let mut crashing = simple_service("crashing", "false");
+        crashing.restart = RestartPolicy::Always;
+        crashing.restart_delay_ms = Some(1000);
+        mgr.start_service(crashing).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        mgr.reap();
+        assert!(mgr.next_deadline().unwrap() < deadline);
+
+        send_ready(dir.path(), "power");
+        mgr.poll_notifications();
+        mgr.stop_all();
+        assert_eq!(mgr.next_deadline(), None);
+    }
+
+    #[test]
+    fn failed_want_does_not_hold_back_dependents() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mgr = ServiceManager::new().with_notify_dir(dir.path());
+        let mut power = simple_service("power", "sleep");
+        power.args = vec!["10".to_string()];
+        power.service_type = ServiceType::Notify;
+        power.start_timeout_sec = Some(0);
+        let mut shell = simple_service("shell", "sleep");
+        shell.args = vec!["10".to_string()];
+        shell.wants = vec!["power".to_string()];
+
+        mgr.enqueue(vec![power, shell]);
+        mgr.start_pending();
+        assert_eq!(mgr.state("shell"), ServiceState::Pending);
+
+        mgr.check_start_timeouts();
+        mgr.start_pending();
+        assert_eq!(mgr.state("shell"), ServiceState::Running);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn failed_requirement_fails_dependents() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mgr = ServiceManager::new().with_notify_dir(dir.path());
+        let mut power = simple_service("power", "sleep");
+        power.args = vec!["10".to_string()];
+        power.service_type = ServiceType::Notify;
+        power.start_timeout_sec = Some(0);
+        let mut leds = simple_service("leds", "sleep");
+        leds.args = vec!["10".to_string()];
+        leds.depends_on = vec!["power".to_string()];
+        let mut shell = simple_service("shell", "sleep");
+        shell.args = vec!["10".to_string()];
+        shell.depends_on = vec!["leds".to_string()];
+        shell.critical = true;
+
+        mgr.enqueue(vec![power, leds, shell]);
+        mgr.start_pending();
+        mgr.check_start_timeouts();
+        mgr.start_pending();
+
+        assert_eq!(mgr.state("power"), ServiceState::Failed);
+        assert_eq!(mgr.state("leds"), ServiceState::Failed);
+        assert_eq!(mgr.state("shell"), ServiceState::Failed);
+        let failures = mgr.take_critical_failures();
+        assert_eq!(
+            failures,
+            [("shell".to_string(), "requires 'leds', which failed".to_string())]
+        );
+    }
+
+    #[test]
+    fn stopping_a_service_stops_what_requires_it() {
+        let mut mgr = ServiceManager::new();
+        let mut dbus = simple_service("dbus", "sleep");
+        dbus.args = vec!["10".to_string()];
+        let mut power = simple_service("power", "sleep");
+        power.args = vec!["10".to_string()];
+        power.depends_on = vec!["dbus".to_string()];
+        let mut audio = simple_service("audio", "sleep");
+        audio.args = vec!["10".to_string()];
+        audio.wants = vec!["dbus".to_string()];
+
+        mgr.enqueue(vec![dbus, power, audio]);
+        mgr.start_pending();
+        assert_eq!(mgr.state("power"), ServiceState::Running);
+
+        mgr.stop_service("dbus").unwrap();
+        assert_eq!(mgr.state("dbus"), ServiceState::Finished);
+        assert_eq!(mgr.state("power"), ServiceState::Finished);
+        assert_eq!(mgr.state("audio"), ServiceState::Running);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn simple_dependencies_start_together() {
+        let mut mgr = ServiceManager::new();
+        let mut dbus = simple_service("dbus", "sleep");
+        dbus.args = vec!["10".to_string()];
+        let mut power = simple_service("power", "sleep");
+        power.args = vec!["10".to_string()];
+        power.depends_on = vec!["dbus".to_string()];
+
+        mgr.enqueue(vec![dbus, power]);
+        mgr.start_pending();
+        assert_eq!(mgr.state("dbus"), ServiceState::Running);
+        assert_eq!(mgr.state("power"), ServiceState::Running);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn unmet_conditions_skip_without_holding_back_dependents() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = dir.path().join("ttyUSB0");
+        let mut mgr = ServiceManager::new();
+        let mut modem = simple_service("modem", "sleep");
+        modem.args = vec!["10".to_string()];
+        modem.conditions.path_exists = vec![device.display().to_string()];
+        let mut dialer = simple_service("dialer", "sleep");
+        dialer.args = vec!["10".to_string()];
+        dialer.depends_on = vec!["modem".to_string()];
+
+        mgr.enqueue(vec![modem.clone(), dialer]);
+        mgr.start_pending();
+        assert_eq!(mgr.state("modem"), ServiceState::Skipped);
+        assert_eq!(mgr.state("dialer"), ServiceState::Running);
+        assert_eq!(mgr.service_names(), ["dialer", "modem"]);
+
+        // Checked again on the next start
+        std::fs::write(&device, "").unwrap();
+        mgr.start_service(modem).unwrap();
+        assert_eq!(mgr.state("modem"), ServiceState::Running);
+
+        mgr.stop_all();
+    }
+
+    #[test]
+    fn unknown_user_fails_to_start() {
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("nobody-home", "true");
+        svc.user = Some("no-such-user-mobileos".to_string());
+
+        assert!(mgr.start_service(svc).is_err());
+        assert_eq!(mgr.running_count(), 0);
+    }
+
+    #[test]
+    fn missing_mount_fails_to_start() {
+        let mut mgr = ServiceManager::new();
+        let mut svc = simple_service("media", "true");
+        svc.requires_mount = vec!["/".to_string(), "/no/such/mount".to_string()];
+        let err = mgr.start_service(svc.clone()).unwrap_err();
+        assert!(format!("{err:#}").contains("/no/such/mount is not mounted"));
+
+        svc.requires_mount.pop();
+        mgr.start_service(svc).unwrap();
+        mgr.stop_all();
+    }
 }
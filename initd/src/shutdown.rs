@@ -1,18 +1,66 @@
 // ABOUTME: Shutdown and reboot handling for the init system.
 // ABOUTME: Stops services in reverse order, unmounts filesystems, and halts/reboots.
 
+use std::ffi::CString;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::bail;
 use rustix::mount::{unmount, UnmountFlags};
 use rustix::system::{reboot, RebootCommand};
 use tracing::{error, info, warn};
 
+use crate::panic::PMSG_DEVICE;
 use crate::service::ServiceManager;
 
 const UNMOUNT_ORDER: &[&str] = &["/run", "/tmp", "/dev", "/sys", "/proc"];
 
+/// What the device boots into after a reboot. Anything but a normal boot is handed to
+/// the bootloader as the RESTART2 argument, which the kernel's reboot-mode driver
+/// stores where the bootloader looks for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootMode {
+    Normal,
+    Bootloader,
+    Recovery,
+}
+
+impl RebootMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Bootloader => "bootloader",
+            Self::Recovery => "recovery",
+        }
+    }
+}
+
+impl FromStr for RebootMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "normal" => Ok(Self::Normal),
+            "bootloader" => Ok(Self::Bootloader),
+            "recovery" => Ok(Self::Recovery),
+            _ => bail!("unknown reboot mode '{s}'"),
+        }
+    }
+}
+
+/// A reboot asked for while init runs, carried out by the main loop once no inhibitor
+/// holds it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebootRequest {
+    pub mode: RebootMode,
+    pub reason: String,
+}
+
 pub fn perform_shutdown(manager: &mut ServiceManager) {
     info!("initiating shutdown");
 
-    info!("stopping all services");
+    info!("stopping all services, dependents first");
     manager.stop_all();
 
     unmount_filesystems();
@@ -23,20 +71,53 @@ pub fn perform_shutdown(manager: &mut ServiceManager) {
     }
 }
 
-pub fn perform_reboot(manager: &mut ServiceManager) {
-    info!("initiating reboot");
+/// Reboot into `mode`, leaving `reason` in pstore so the next boot can tell why the
+/// last one ended.
+pub fn perform_reboot_with_reason(manager: &mut ServiceManager, mode: RebootMode, reason: &str) {
+    info!(mode = mode.as_str(), reason, "initiating reboot");
+    if let Err(e) = record_reason(Path::new(PMSG_DEVICE), mode, reason) {
+        warn!(error = %e, "failed to record reboot reason");
+    }
 
-    info!("stopping all services");
+    info!("stopping all services, dependents first");
     manager.stop_all();
 
     unmount_filesystems();
 
     info!("rebooting");
-    if let Err(e) = reboot(RebootCommand::Restart) {
+    if let Err(e) = restart(mode) {
         error!(error = %e, "reboot syscall failed");
     }
 }
 
+fn record_reason(pmsg: &Path, mode: RebootMode, reason: &str) -> std::io::Result<()> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(pmsg)?
+        .write_all(format!("initd: reboot mode={} reason={reason}\n", mode.as_str()).as_bytes())
+}
+
+fn restart(mode: RebootMode) -> std::io::Result<()> {
+    if mode == RebootMode::Normal {
+        return reboot(RebootCommand::Restart).map_err(Into::into);
+    }
+    let arg = CString::new(mode.as_str()).expect("reboot modes have no NUL bytes");
+    // SAFETY: RESTART2 only reads the NUL-terminated string, which outlives the call
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_reboot,
+            libc::LINUX_REBOOT_MAGIC1,
+            libc::LINUX_REBOOT_MAGIC2,
+            libc::LINUX_REBOOT_CMD_RESTART2,
+            arg.as_ptr(),
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
 fn unmount_filesystems() {
     for target in UNMOUNT_ORDER {
         match unmount(*target, UnmountFlags::DETACH) {
@@ -45,3 +126,31 @@ fn unmount_filesystems() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_reboot_modes() {
+        for mode in [RebootMode::Normal, RebootMode::Bootloader, RebootMode::Recovery] {
+            assert_eq!(mode.as_str().parse::<RebootMode>().unwrap(), mode);
+        }
+        assert!("fastboot".parse::<RebootMode>().is_err());
+    }
+
+    #[test]
+    fn records_reason_in_pstore() {
+        let dir = tempfile::tempdir().unwrap();
+        let pmsg = dir.path().join("pmsg0");
+        // Without pstore there is nowhere to keep it
+        assert!(record_reason(&pmsg, RebootMode::Recovery, "factory reset").is_err());
+
+        std::fs::write(&pmsg, "").unwrap();
+        record_reason(&pmsg, RebootMode::Recovery, "factory reset").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&pmsg).unwrap(),
+            "initd: reboot mode=recovery reason=factory reset\n"
+        );
+    }
+}
@@ -1,8 +1,12 @@
 // ABOUTME: Signal handling for PID 1.
-// ABOUTME: Registers handlers for SIGCHLD, SIGTERM, SIGINT, and SIGUSR1.
+// ABOUTME: Flags SIGCHLD, SIGTERM, SIGINT, and SIGUSR1, and wakes the main loop through a self-pipe.
 
 use signal_hook::consts::{SIGCHLD, SIGINT, SIGTERM, SIGUSR1};
 use signal_hook::flag;
+use signal_hook::low_level::pipe;
+use std::io::Read;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixStream;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
@@ -10,6 +14,9 @@ pub struct SignalState {
     pub shutdown_requested: Arc<AtomicBool>,
     pub child_exited: Arc<AtomicBool>,
     pub reload_requested: Arc<AtomicBool>,
+    /// Written to by every handler after it sets its flag, so the main loop can sleep
+    /// in poll() until a signal arrives.
+    wakeup: UnixStream,
 }
 
 impl SignalState {
@@ -23,13 +30,28 @@ impl SignalState {
         flag::register(SIGCHLD, Arc::clone(&child_exited))?;
         flag::register(SIGUSR1, Arc::clone(&reload_requested))?;
 
+        // Registered after the flags: handlers run in registration order
+        let (wakeup, waker) = UnixStream::pair()?;
+        wakeup.set_nonblocking(true)?;
+        for signal in [SIGTERM, SIGINT, SIGCHLD, SIGUSR1] {
+            pipe::register(signal, waker.try_clone()?)?;
+        }
+
         Ok(Self {
             shutdown_requested,
             child_exited,
             reload_requested,
+            wakeup,
         })
     }
 
+    /// Empty the wakeup pipe. Call before checking the flags, so a signal arriving in
+    /// between still wakes the next poll.
+    pub fn clear_wakeups(&self) {
+        let mut buf = [0u8; 64];
+        while matches!((&self.wakeup).read(&mut buf), Ok(n) if n > 0) {}
+    }
+
     pub fn is_shutdown_requested(&self) -> bool {
         self.shutdown_requested.load(Ordering::Relaxed)
     }
@@ -43,6 +65,12 @@ impl SignalState {
     }
 }
 
+impl AsFd for SignalState {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.wakeup.as_fd()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
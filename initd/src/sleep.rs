@@ -0,0 +1,131 @@
+// ABOUTME: Suspend to RAM, asked for by the power service: announced on the bus, then carried out.
+// ABOUTME: Waits for inhibitors, freezes the services that ask for it, and tells everyone on resume.
+
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use tracing::{error, info, warn};
+
+use crate::dbus;
+use crate::inhibit::Inhibitors;
+use crate::service::ServiceManager;
+
+/// Writing `SLEEP_STATE` here suspends the phone, and returns once it has resumed.
+pub const POWER_STATE_PATH: &str = "/sys/power/state";
+
+/// Suspend to RAM.
+const SLEEP_STATE: &str = "mem";
+
+/// Sent with `true` before the phone suspends. Services that have something to do first,
+/// such as saving state or closing a connection, hold an inhibitor while awake and let
+/// go of it when they have done it.
+const PREPARE_FOR_SLEEP: &str = "PrepareForSleep";
+
+/// Sent once the phone is awake again, so services can re-sync their clocks and network.
+const RESUMED: &str = "Resumed";
+
+/// A suspend asked for while init runs, carried out by the main loop once no inhibitor
+/// holds it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SleepRequest {
+    since: Instant,
+    announced: bool,
+}
+
+impl SleepRequest {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            since: now,
+            announced: false,
+        }
+    }
+
+    /// Announce the suspend the first time round, and once no inhibitor delays it any
+    /// longer, suspend by writing to `state_path`. Returns whether the request is done:
+    /// the phone has been asleep and woken up, or failed to go to sleep.
+    pub fn advance(
+        &mut self,
+        manager: &mut ServiceManager,
+        inhibitors: &Inhibitors,
+        state_path: &Path,
+        now: Instant,
+    ) -> bool {
+        if !self.announced {
+            self.announced = true;
+            for holder in inhibitors.iter() {
+                warn!(
+                    who = %holder.who,
+                    reason = %holder.reason,
+                    delay_sec = holder.delay.as_secs(),
+                    "suspend delayed"
+                );
+            }
+            if let Err(e) = dbus::broadcast(PREPARE_FOR_SLEEP, &["boolean:true"]) {
+                warn!(error = %e, "failed to announce suspend");
+            }
+        }
+        if inhibitors.delays_sleep(self.since, now) {
+            return false;
+        }
+
+        let frozen = manager.freeze();
+        info!("suspending");
+        let slept = suspend(state_path);
+        manager.thaw(&frozen);
+        match slept {
+            Ok(()) => info!("resumed"),
+            Err(e) => error!(error = %e, "failed to suspend"),
+        }
+        if let Err(e) = dbus::broadcast(RESUMED, &[]) {
+            warn!(error = %e, "failed to announce resume");
+        }
+        true
+    }
+
+    /// When the next inhibitor holding back the suspend runs out, or now if it is yet
+    /// to be announced.
+    pub fn next_deadline(&self, inhibitors: &Inhibitors, now: Instant) -> Option<Instant> {
+        if !self.announced {
+            return Some(now);
+        }
+        inhibitors.sleep_deadline(self.since, now)
+    }
+}
+
+/// Suspend to RAM, returning once the phone has resumed.
+fn suspend(state_path: &Path) -> Result<()> {
+    std::fs::write(state_path, SLEEP_STATE)
+        .with_context(|| format!("failed to write {}", state_path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn suspend_waits_for_inhibitors() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("state");
+        std::fs::write(&state, "").unwrap();
+        let mut manager = ServiceManager::new();
+        let mut inhibitors = Inhibitors::new();
+        inhibitors.inhibit("messages", "sending", Duration::from_secs(5));
+
+        let now = Instant::now();
+        let mut request = SleepRequest::new(now);
+        assert_eq!(request.next_deadline(&inhibitors, now), Some(now));
+        assert!(!request.advance(&mut manager, &inhibitors, &state, now));
+        assert_eq!(
+            request.next_deadline(&inhibitors, now),
+            Some(now + Duration::from_secs(5))
+        );
+        assert_eq!(std::fs::read_to_string(&state).unwrap(), "");
+
+        let later = now + Duration::from_secs(5);
+        assert!(request.advance(&mut manager, &inhibitors, &state, later));
+        assert_eq!(std::fs::read_to_string(&state).unwrap(), "mem");
+    }
+}
@@ -0,0 +1,292 @@
+// ABOUTME: Storage init: finds the data partition by name, formats it on first boot, and
+// ABOUTME: mounts it at /data with the standard directory layout before services start.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{error, info, warn};
+
+use crate::mount::{self, FstabEntry};
+
+pub const CONFIG_PATH: &str = "/etc/mos/storage.toml";
+
+pub const DATA_DIR: &str = "/data";
+
+/// Block devices and their partition names, readable before anything populates /dev/disk.
+const SYS_BLOCK: &str = "/sys/class/block";
+
+/// Where mkfs.ext4 and mkfs.f2fs are installed.
+const MKFS_DIR: &str = "/sbin";
+
+/// Directories every install has under /data, and their modes.
+const LAYOUT: &[(&str, u32)] = &[("apps", 0o755), ("services", 0o755), ("logs", 0o750)];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filesystem {
+    #[default]
+    Ext4,
+    F2fs,
+}
+
+impl Filesystem {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Ext4 => "ext4",
+            Self::F2fs => "f2fs",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StorageConfig {
+    /// The GPT partition name of the data partition; also the filesystem label.
+    #[serde(default = "default_partition")]
+    pub partition: String,
+    /// What a blank partition is formatted as. One already formatted is mounted as it is.
+    #[serde(default)]
+    pub filesystem: Filesystem,
+    #[serde(default = "default_options")]
+    pub options: Vec<String>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            partition: default_partition(),
+            filesystem: Filesystem::default(),
+            options: default_options(),
+        }
+    }
+}
+
+fn default_partition() -> String {
+    "userdata".to_string()
+}
+
+fn default_options() -> Vec<String> {
+    ["noatime", "nodev", "nosuid"].map(String::from).to_vec()
+}
+
+#[derive(Debug, Deserialize)]
+struct StorageFile {
+    data: StorageConfig,
+}
+
+pub fn parse_config(toml_str: &str) -> Result<StorageConfig> {
+    let file: StorageFile = toml::from_str(toml_str).context("failed to parse storage config")?;
+    Ok(file.data)
+}
+
+/// Set up /data from the config at `path`, with the defaults if there is none. Errors are
+/// logged; services that need /data fail to start through their `requires_mount`.
+pub fn init_from_system(path: &Path) {
+    let config = match std::fs::read_to_string(path) {
+        Ok(content) => match parse_config(&content) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!(error = %e, "invalid storage config, using defaults");
+                StorageConfig::default()
+            }
+        },
+        Err(_) => StorageConfig::default(),
+    };
+
+    let data = Path::new(DATA_DIR);
+    if mount::is_mounted(Path::new(mount::MOUNTINFO_PATH), data) {
+        info!("/data already mounted from fstab");
+    } else if let Err(e) = mount_data(&config, Path::new(SYS_BLOCK), Path::new(MKFS_DIR), data) {
+        error!(error = %e, "failed to set up the data partition");
+        return;
+    }
+    if let Err(e) = create_layout(data) {
+        error!(error = %e, "failed to create the /data layout");
+    }
+}
+
+/// Find the data partition, format it if it is blank, then check and mount it.
+fn mount_data(
+    config: &StorageConfig,
+    sys_block: &Path,
+    mkfs_dir: &Path,
+    data: &Path,
+) -> Result<()> {
+    let Some(device) = find_partition(sys_block, &config.partition) else {
+        info!(partition = %config.partition, "no data partition, /data stays on the root");
+        return Ok(());
+    };
+
+    let filesystem = match probe_filesystem(&device)? {
+        Some(filesystem) => filesystem,
+        None => {
+            warn!(
+                device = %device.display(),
+                fstype = config.filesystem.as_str(),
+                "data partition is blank, formatting"
+            );
+            format(mkfs_dir, &device, config.filesystem, &config.partition)?;
+            config.filesystem
+        }
+    };
+
+    let entry = FstabEntry {
+        source: device.to_string_lossy().into_owned(),
+        target: data.to_path_buf(),
+        fstype: filesystem.as_str().to_string(),
+        options: config.options.clone(),
+        pass: 2,
+    };
+    mount::check_filesystem(Path::new(mount::FSCK), &entry)?;
+    mount::mount_entry(&entry)?;
+    info!(device = %device.display(), fstype = filesystem.as_str(), "data partition mounted");
+    Ok(())
+}
+
+/// The device node of the partition named `name`, from the PARTNAME in its uevent.
+fn find_partition(sys_block: &Path, name: &str) -> Option<PathBuf> {
+    let mut devices: Vec<PathBuf> = std::fs::read_dir(sys_block)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    devices.sort();
+    devices.iter().find_map(|dir| {
+        let uevent = std::fs::read_to_string(dir.join("uevent")).ok()?;
+        let field = |key: &str| {
+            uevent
+                .lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+        };
+        if field("PARTNAME")? != name {
+            return None;
+        }
+        Some(Path::new("/dev").join(field("DEVNAME")?))
+    })
+}
+
+/// Which filesystem `device` holds, going by the superblock magic. `None` for a blank
+/// partition; anything but ext2/3/4 or f2fs counts as blank too, since the data
+/// partition is ours alone.
+fn probe_filesystem(device: &Path) -> Result<Option<Filesystem>> {
+    let mut superblock = Vec::new();
+    std::fs::File::open(device)
+        .with_context(|| format!("failed to open {}", device.display()))?
+        .take(2048)
+        .read_to_end(&mut superblock)
+        .with_context(|| format!("failed to read {}", device.display()))?;
+
+    // Both superblocks start 1024 bytes in: ext's magic is 56 bytes into it, f2fs's first
+    if superblock.get(1080..1082) == Some(&[0x53, 0xef]) {
+        return Ok(Some(Filesystem::Ext4));
+    }
+    if superblock.get(1024..1028) == Some(&0xf2f5_2010u32.to_le_bytes()) {
+        return Ok(Some(Filesystem::F2fs));
+    }
+    Ok(None)
+}
+
+fn format(mkfs_dir: &Path, device: &Path, filesystem: Filesystem, label: &str) -> Result<()> {
+    let mkfs = mkfs_dir.join(format!("mkfs.{}", filesystem.as_str()));
+    let (force, label_flag) = match filesystem {
+        Filesystem::Ext4 => ("-F", "-L"),
+        Filesystem::F2fs => ("-f", "-l"),
+    };
+    let status = Command::new(&mkfs)
+        .args([force, label_flag, label])
+        .arg(device)
+        .status()
+        .with_context(|| format!("failed to run {}", mkfs.display()))?;
+    if !status.success() {
+        bail!("{} {} failed ({status})", mkfs.display(), device.display());
+    }
+    Ok(())
+}
+
+/// Create the standard directories under `data`. Existing ones get their mode reset.
+fn create_layout(data: &Path) -> Result<()> {
+    for (name, mode) in LAYOUT {
+        let dir = data.join(name);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(*mode))
+            .with_context(|| format!("failed to set the mode of {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_config() {
+        let config = parse_config("[data]\npartition = \"data\"\nfilesystem = \"f2fs\"\n").unwrap();
+        assert_eq!(config.partition, "data");
+        assert_eq!(config.filesystem, Filesystem::F2fs);
+        assert_eq!(config.options, ["noatime", "nodev", "nosuid"]);
+
+        assert_eq!(parse_config("[data]\n").unwrap(), StorageConfig::default());
+        assert!(parse_config("[data]\nfilesystem = \"btrfs\"\n").is_err());
+    }
+
+    #[test]
+    fn finds_partition_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        for (dev, uevent) in [
+            ("mmcblk0", "DEVNAME=mmcblk0\nDEVTYPE=disk\n"),
+            ("mmcblk0p1", "DEVNAME=mmcblk0p1\nPARTNAME=boot\n"),
+            ("mmcblk0p5", "DEVNAME=mmcblk0p5\nPARTNAME=userdata\n"),
+        ] {
+            std::fs::create_dir(dir.path().join(dev)).unwrap();
+            std::fs::write(dir.path().join(dev).join("uevent"), uevent).unwrap();
+        }
+
+        assert_eq!(
+            find_partition(dir.path(), "userdata"),
+            Some(PathBuf::from("/dev/mmcblk0p5"))
+        );
+        assert_eq!(find_partition(dir.path(), "system"), None);
+        assert_eq!(
+            find_partition(&dir.path().join("missing"), "userdata"),
+            None
+        );
+    }
+
+    #[test]
+    fn tells_blank_partitions_from_formatted_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image");
+
+        std::fs::write(&image, vec![0u8; 4096]).unwrap();
+        assert_eq!(probe_filesystem(&image).unwrap(), None);
+
+        let mut ext4 = vec![0u8; 4096];
+        ext4[1080..1082].copy_from_slice(&[0x53, 0xef]);
+        std::fs::write(&image, ext4).unwrap();
+        assert_eq!(probe_filesystem(&image).unwrap(), Some(Filesystem::Ext4));
+
+        let mut f2fs = vec![0u8; 4096];
+        f2fs[1024..1028].copy_from_slice(&[0x10, 0x20, 0xf5, 0xf2]);
+        std::fs::write(&image, f2fs).unwrap();
+        assert_eq!(probe_filesystem(&image).unwrap(), Some(Filesystem::F2fs));
+
+        assert!(probe_filesystem(&dir.path().join("missing")).is_err());
+        let no_mkfs = dir.path().join("sbin");
+        assert!(format(&no_mkfs, &image, Filesystem::Ext4, "userdata").is_err());
+    }
+
+    #[test]
+    fn creates_the_data_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("logs")).unwrap();
+
+        create_layout(dir.path()).unwrap();
+        for (name, mode) in LAYOUT {
+            let metadata = std::fs::metadata(dir.path().join(name)).unwrap();
+            assert!(metadata.is_dir());
+            assert_eq!(metadata.permissions().mode() & 0o777, *mode);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+// ABOUTME: Early-boot system setup: the hostname, a per-phone machine id, and kernel tunables.
+// ABOUTME: Done by initd itself before services start, rather than by oneshot shell scripts.
+
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use tracing::{error, info, warn};
+
+pub const HOSTNAME_PATH: &str = "/etc/hostname";
+
+pub const MACHINE_ID_PATH: &str = "/etc/machine-id";
+
+/// Where the machine id is kept across boots, since / is read-only. The image ships
+/// an empty /etc/machine-id for it to be bind-mounted over.
+pub const SAVED_MACHINE_ID_PATH: &str = "/data/machine-id";
+
+/// Holds this boot's machine id when /etc can't be written.
+const RUNTIME_MACHINE_ID_PATH: &str = "/run/machine-id";
+
+/// A fresh random UUID on every read.
+const UUID_PATH: &str = "/proc/sys/kernel/random/uuid";
+
+pub const SYSCTL_DIR: &str = "/etc/sysctl.d";
+
+pub const PROC_SYS: &str = "/proc/sys";
+
+const DEFAULT_HOSTNAME: &str = "mobileos";
+
+/// Longest hostname the kernel takes.
+const HOSTNAME_MAX: usize = 64;
+
+/// The hostname from the contents of /etc/hostname: its first line that isn't a
+/// comment, or the default when there is none or it is too long.
+pub fn parse_hostname(content: &str) -> String {
+    let name = content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'));
+    match name {
+        Some(name) if name.len() <= HOSTNAME_MAX && !name.contains(char::is_whitespace) => {
+            name.to_string()
+        }
+        Some(name) => {
+            warn!(hostname = name, "invalid hostname, using the default");
+            DEFAULT_HOSTNAME.to_string()
+        }
+        None => DEFAULT_HOSTNAME.to_string(),
+    }
+}
+
+fn set_hostname(path: &Path) {
+    let name = parse_hostname(&std::fs::read_to_string(path).unwrap_or_default());
+    match rustix::system::sethostname(name.as_bytes()) {
+        Ok(()) => info!(hostname = %name, "hostname set"),
+        Err(e) => error!(hostname = %name, error = %e, "failed to set hostname"),
+    }
+}
+
+/// A machine id is 32 lowercase hex digits, and not all zeros.
+pub fn is_valid_machine_id(id: &str) -> bool {
+    id.len() == 32
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        && id.bytes().any(|b| b != b'0')
+}
+
+fn read_machine_id(path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let id = content.trim();
+    is_valid_machine_id(id).then(|| id.to_string())
+}
+
+/// The machine id: the one in `etc` if the image or a previous boot put one there, else
+/// the one `saved`, else a new one from the kernel's random `uuid`, which is then saved.
+pub fn machine_id(etc: &Path, saved: &Path, uuid: &Path) -> Result<String> {
+    if let Some(id) = read_machine_id(etc).or_else(|| read_machine_id(saved)) {
+        return Ok(id);
+    }
+    let uuid = std::fs::read_to_string(uuid)
+        .with_context(|| format!("failed to read {}", uuid.display()))?;
+    let id: String = uuid.trim().chars().filter(|c| *c != '-').collect();
+    if !is_valid_machine_id(&id) {
+        bail!("kernel gave an unusable UUID '{}'", uuid.trim());
+    }
+    info!(machine_id = %id, "generated machine id");
+    if let Err(e) = std::fs::write(saved, format!("{id}\n")) {
+        warn!(
+            path = %saved.display(),
+            error = %e,
+            "failed to save machine id, it will change next boot"
+        );
+    }
+    Ok(id)
+}
+
+/// Put `id` in `etc`, or when / is read-only, in `runtime` bind-mounted over it.
+fn install_machine_id(id: &str, etc: &Path, runtime: &Path) -> Result<()> {
+    if read_machine_id(etc).as_deref() == Some(id) {
+        return Ok(());
+    }
+    let content = format!("{id}\n");
+    if std::fs::write(etc, &content).is_ok() {
+        return Ok(());
+    }
+    std::fs::write(runtime, &content)
+        .with_context(|| format!("failed to write {}", runtime.display()))?;
+    rustix::mount::mount_bind(runtime, etc).with_context(|| {
+        format!(
+            "failed to bind {} over {}",
+            runtime.display(),
+            etc.display()
+        )
+    })
+}
+
+/// One `key = value` line of a sysctl.d file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sysctl {
+    /// Dotted like "vm.swappiness", or a path under /proc/sys like "vm/swappiness".
+    pub key: String,
+    pub value: String,
+    /// Set by a leading "-": a key the kernel doesn't have is skipped quietly.
+    pub optional: bool,
+}
+
+impl Sysctl {
+    /// The file under `proc_sys` the value is written to.
+    fn path(&self, proc_sys: &Path) -> std::path::PathBuf {
+        if self.key.contains('/') {
+            proc_sys.join(self.key.trim_start_matches('/'))
+        } else {
+            proc_sys.join(self.key.replace('.', "/"))
+        }
+    }
+}
+
+/// The settings in a sysctl.d file, skipping comments and lines without a value.
+pub fn parse_sysctl(content: &str) -> Vec<Sysctl> {
+    let mut sysctls = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            warn!(line = number + 1, "sysctl line without '=', skipping");
+            continue;
+        };
+        let key = key.trim();
+        let (key, optional) = match key.strip_prefix('-') {
+            Some(key) => (key.trim(), true),
+            None => (key, false),
+        };
+        sysctls.push(Sysctl {
+            key: key.to_string(),
+            value: value.trim().to_string(),
+            optional,
+        });
+    }
+    sysctls
+}
+
+/// Apply the `*.conf` files in `dir`, in name order so later files override earlier
+/// ones, to `proc_sys`. Returns how many settings failed.
+pub fn apply_sysctls(dir: &Path, proc_sys: &Path) -> usize {
+    let mut paths: Vec<_> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+            .collect(),
+        Err(_) => return 0,
+    };
+    paths.sort();
+
+    let mut failed = 0;
+    for path in paths {
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "failed to read sysctl file");
+                failed += 1;
+                continue;
+            }
+        };
+        for sysctl in parse_sysctl(&content) {
+            // /proc/sys files can't be created, so neither are they here
+            let written = std::fs::OpenOptions::new()
+                .write(true)
+                .open(sysctl.path(proc_sys))
+                .and_then(|mut file| file.write_all(sysctl.value.as_bytes()));
+            match written {
+                Ok(()) => {}
+                Err(e) if sysctl.optional && e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    warn!(
+                        key = %sysctl.key,
+                        value = %sysctl.value,
+                        error = %e,
+                        "failed to apply sysctl"
+                    );
+                    failed += 1;
+                }
+            }
+        }
+    }
+    failed
+}
+
+/// Set the hostname, the machine id and the sysctls of this system.
+pub fn init_from_system() {
+    set_hostname(Path::new(HOSTNAME_PATH));
+
+    let etc = Path::new(MACHINE_ID_PATH);
+    let installed = machine_id(etc, Path::new(SAVED_MACHINE_ID_PATH), Path::new(UUID_PATH))
+        .and_then(|id| install_machine_id(&id, etc, Path::new(RUNTIME_MACHINE_ID_PATH)));
+    if let Err(e) = installed {
+        error!(error = %e, "failed to set up the machine id");
+    }
+
+    let failed = apply_sysctls(Path::new(SYSCTL_DIR), Path::new(PROC_SYS));
+    if failed > 0 {
+        warn!(failed, "some sysctls were not applied");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostname_is_the_first_line() {
+        assert_eq!(parse_hostname("# the phone\npinephone\n"), "pinephone");
+        assert_eq!(parse_hostname(""), DEFAULT_HOSTNAME);
+        assert_eq!(parse_hostname("two words"), DEFAULT_HOSTNAME);
+        assert_eq!(parse_hostname(&"x".repeat(65)), DEFAULT_HOSTNAME);
+    }
+
+    #[test]
+    fn machine_id_is_generated_once_and_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let etc = dir.path().join("machine-id");
+        let saved = dir.path().join("saved-machine-id");
+        let uuid = dir.path().join("uuid");
+        std::fs::write(&etc, "").unwrap();
+        std::fs::write(&uuid, "3f2504e0-4f89-41d3-9a0c-0305e82c3301\n").unwrap();
+
+        let id = machine_id(&etc, &saved, &uuid).unwrap();
+        assert_eq!(id, "3f2504e04f8941d39a0c0305e82c3301");
+        assert_eq!(std::fs::read_to_string(&saved).unwrap(), format!("{id}\n"));
+
+        // The next boot finds it saved, whatever the kernel's UUID is now
+        std::fs::write(&uuid, "6ba7b810-9dad-11d1-80b4-00c04fd430c8\n").unwrap();
+        assert_eq!(machine_id(&etc, &saved, &uuid).unwrap(), id);
+
+        install_machine_id(&id, &etc, &dir.path().join("runtime")).unwrap();
+        assert_eq!(std::fs::read_to_string(&etc).unwrap(), format!("{id}\n"));
+        assert!(!dir.path().join("runtime").exists());
+
+        assert!(!is_valid_machine_id(&"0".repeat(32)));
+        assert!(!is_valid_machine_id("3F2504E04F8941D39A0C0305E82C3301"));
+    }
+
+    #[test]
+    fn sysctls_are_applied_in_file_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let proc_sys = dir.path().join("proc/sys");
+        std::fs::create_dir_all(proc_sys.join("vm")).unwrap();
+        std::fs::create_dir_all(proc_sys.join("kernel")).unwrap();
+        std::fs::write(proc_sys.join("vm/swappiness"), "60").unwrap();
+        std::fs::write(proc_sys.join("kernel/panic"), "0").unwrap();
+
+        let sysctl_d = dir.path().join("sysctl.d");
+        std::fs::create_dir(&sysctl_d).unwrap();
+        std::fs::write(
+            sysctl_d.join("10-base.conf"),
+            "# base\nvm.swappiness = 10\nkernel/panic=5\n-net.ipv4.tcp_fastopen = 3\n",
+        )
+        .unwrap();
+        std::fs::write(
+            sysctl_d.join("20-local.conf"),
+            "; local\nvm.swappiness = 30\n",
+        )
+        .unwrap();
+        std::fs::write(sysctl_d.join("README"), "vm.swappiness = 99\n").unwrap();
+
+        assert_eq!(apply_sysctls(&sysctl_d, &proc_sys), 0);
+        let read = |key: &str| std::fs::read_to_string(proc_sys.join(key)).unwrap();
+        assert_eq!(read("vm/swappiness"), "30");
+        assert_eq!(read("kernel/panic"), "5");
+
+        std::fs::write(
+            sysctl_d.join("30-bad.conf"),
+            "vm.missing = 1\nnot a setting\n",
+        )
+        .unwrap();
+        assert_eq!(apply_sysctls(&sysctl_d, &proc_sys), 1);
+    }
+}
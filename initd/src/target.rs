@@ -0,0 +1,272 @@
+// ABOUTME: Boot targets: named groups of services that select what a boot brings up.
+// ABOUTME: Chosen by mos.target= on the kernel command line or /etc/mos/default-target.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::config::ServiceConfig;
+
+pub const TARGETS_DIR: &str = "/etc/mos/targets";
+pub const DEFAULT_TARGET_PATH: &str = "/etc/mos/default-target";
+pub const CMDLINE_PATH: &str = "/proc/cmdline";
+
+/// Target booted when neither the command line nor the default-target file names one.
+pub const DEFAULT_TARGET: &str = "graphical";
+
+/// Kernel command line parameter selecting the target, e.g. `mos.target=charging`.
+const CMDLINE_PARAM: &str = "mos.target=";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    pub name: String,
+    /// Targets whose services this one starts as well.
+    #[serde(default)]
+    pub includes: Vec<String>,
+    #[serde(default)]
+    pub services: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TargetFile {
+    target: Target,
+}
+
+pub fn parse_target(toml_str: &str) -> Result<Target> {
+    let file: TargetFile = toml::from_str(toml_str).context("failed to parse target")?;
+    Ok(file.target)
+}
+
+pub fn load_targets_from_dir(dir: &Path) -> Result<HashMap<String, Target>> {
+    let mut targets = HashMap::new();
+
+    if !dir.exists() {
+        return Ok(targets);
+    }
+
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read target directory: {}", dir.display()))?;
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "toml") {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let target = parse_target(&content)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        targets.insert(target.name.clone(), target);
+    }
+
+    Ok(targets)
+}
+
+/// Pick the target to boot: `mos.target=` on the kernel command line wins over the
+/// default-target file, which wins over `DEFAULT_TARGET`. A `.target` suffix is
+/// accepted and ignored, so `charging.target` and `charging` are the same target.
+pub fn select(cmdline: Option<&str>, default_file: Option<&str>) -> String {
+    let from_cmdline = cmdline.and_then(|line| {
+        line.split_whitespace()
+            .rev()
+            .find_map(|param| param.strip_prefix(CMDLINE_PARAM))
+    });
+    let from_file =
+        default_file.and_then(|content| content.lines().map(str::trim).find(|l| !l.is_empty()));
+
+    let name = from_cmdline.or(from_file).unwrap_or(DEFAULT_TARGET);
+    name.strip_suffix(".target").unwrap_or(name).to_string()
+}
+
+/// Read the boot target selection from the kernel command line and default-target file.
+pub fn select_from_system() -> String {
+    let cmdline = std::fs::read_to_string(CMDLINE_PATH).ok();
+    let default_file = std::fs::read_to_string(DEFAULT_TARGET_PATH).ok();
+    select(cmdline.as_deref(), default_file.as_deref())
+}
+
+/// Names of the services in a target, following its `includes`. Targets including each
+/// other are harmless; each is visited once.
+pub fn services_in(name: &str, targets: &HashMap<String, Target>) -> Result<HashSet<String>> {
+    let mut services = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![name];
+
+    while let Some(current) = stack.pop() {
+        if !visited.insert(current) {
+            continue;
+        }
+        let target = targets
+            .get(current)
+            .with_context(|| format!("unknown target '{current}'"))?;
+        services.extend(target.services.iter().cloned());
+        stack.extend(target.includes.iter().map(String::as_str));
+    }
+
+    Ok(services)
+}
+
+/// Keep the services a target starts, by name or alias, plus whatever they depend on so
+/// every start order stays resolvable. Services pulled in only as dependencies are
+/// logged, since the target probably ought to list them.
+pub fn filter_services(
+    configs: Vec<ServiceConfig>,
+    wanted: &HashSet<String>,
+) -> Vec<ServiceConfig> {
+    let by_name: HashMap<&str, &ServiceConfig> = configs
+        .iter()
+        .flat_map(|c| {
+            std::iter::once(&c.name)
+                .chain(&c.alias)
+                .map(move |n| (n.as_str(), c))
+        })
+        .collect();
+
+    let mut keep: HashSet<String> = HashSet::new();
+    let mut stack: Vec<&str> = wanted.iter().map(String::as_str).collect();
+    while let Some(name) = stack.pop() {
+        let Some(config) = by_name.get(name) else {
+            warn!(service = %name, "target lists unknown service");
+            continue;
+        };
+        if !keep.insert(config.name.clone()) {
+            continue;
+        }
+        if !wanted.contains(name) && !config.alias.iter().any(|a| wanted.contains(a)) {
+            info!(service = %name, "starting service outside the target as a dependency");
+        }
+        stack.extend(config.depends_on.iter().map(String::as_str));
+    }
+
+    configs
+        .into_iter()
+        .filter(|c| keep.contains(&c.name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(name: &str, includes: &[&str], services: &[&str]) -> (String, Target) {
+        let target = Target {
+            name: name.to_string(),
+            includes: includes.iter().map(|s| s.to_string()).collect(),
+            services: services.iter().map(|s| s.to_string()).collect(),
+        };
+        (name.to_string(), target)
+    }
+
+    fn service(name: &str, depends_on: &[&str]) -> ServiceConfig {
+        ServiceConfig {
+            name: name.to_string(),
+            exec: "true".to_string(),
+            depends_on: depends_on.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn boot_targets() -> HashMap<String, Target> {
+        HashMap::from([
+            target("early", &[], &["console", "dbus"]),
+            target("charging", &["early"], &["power"]),
+            target("graphical", &["charging"], &["compositor", "shell"]),
+        ])
+    }
+
+    #[test]
+    fn parse_target_file() {
+        let toml = r#"
+            [target]
+            name = "charging"
+            includes = ["early"]
+            services = ["power"]
+        "#;
+
+        let target = parse_target(toml).unwrap();
+        assert_eq!(target.name, "charging");
+        assert_eq!(target.includes, vec!["early"]);
+        assert_eq!(target.services, vec!["power"]);
+    }
+
+    #[test]
+    fn cmdline_overrides_default_file() {
+        assert_eq!(select(None, None), DEFAULT_TARGET);
+        assert_eq!(select(None, Some("\ncharging\n")), "charging");
+        assert_eq!(
+            select(
+                Some("console=ttyS0 mos.target=early.target quiet"),
+                Some("charging")
+            ),
+            "early"
+        );
+        assert_eq!(
+            select(Some("console=ttyS0 quiet"), Some("charging")),
+            "charging"
+        );
+    }
+
+    #[test]
+    fn includes_are_followed() {
+        let targets = boot_targets();
+        let charging = services_in("charging", &targets).unwrap();
+        assert_eq!(
+            charging,
+            HashSet::from(["console".into(), "dbus".into(), "power".into()])
+        );
+        assert_eq!(services_in("graphical", &targets).unwrap().len(), 5);
+        assert!(services_in("recovery", &targets).is_err());
+    }
+
+    #[test]
+    fn include_cycles_terminate() {
+        let mut targets = boot_targets();
+        targets.insert(
+            "early".into(),
+            target("early", &["graphical"], &["console"]).1,
+        );
+        assert_eq!(services_in("early", &targets).unwrap().len(), 4);
+        assert!(services_in("graphical", &targets).is_ok());
+
+        targets.insert("charging".into(), target("charging", &["missing"], &[]).1);
+        assert!(services_in("graphical", &targets).is_err());
+    }
+
+    #[test]
+    fn filter_keeps_target_services_and_their_dependencies() {
+        let configs = vec![
+            service("console", &[]),
+            service("dbus", &[]),
+            service("seatd", &[]),
+            service("power", &["dbus"]),
+            service("compositor", &["seatd"]),
+            service("shell", &["compositor"]),
+        ];
+
+        let charging = services_in("charging", &boot_targets()).unwrap();
+        let names: Vec<String> = filter_services(configs.clone(), &charging)
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, ["console", "dbus", "power"]);
+
+        let graphical = services_in("graphical", &boot_targets()).unwrap();
+        assert_eq!(filter_services(configs, &graphical).len(), 6);
+    }
+
+    #[test]
+    fn targets_may_list_aliases() {
+        let mut getty = service("getty@ttyS0", &["console"]);
+        getty.alias = vec!["serial-console".to_string()];
+        let configs = vec![service("console", &[]), getty, service("dbus", &[])];
+
+        let wanted = HashSet::from(["serial-console".to_string()]);
+        let names: Vec<String> = filter_services(configs, &wanted)
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, ["console", "getty@ttyS0"]);
+    }
+}
@@ -0,0 +1,408 @@
+// ABOUTME: Timer units that start services on a schedule instead of at boot.
+// ABOUTME: Tracks boot-relative, interval, and wall-clock triggers for periodic jobs.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::info;
+
+use crate::config::{Calendar, ServiceConfig, TimerConfig};
+use crate::service::{ServiceManager, ServiceState};
+
+const HOUR: u64 = 3600;
+const DAY: u64 = 24 * HOUR;
+
+/// Schedule of one timer service.
+pub struct Timer {
+    config: ServiceConfig,
+    /// The `on_boot_sec` trigger, until it has fired.
+    boot_due: Option<Instant>,
+    interval_due: Option<Instant>,
+    /// Kept in wall-clock time so that setting the clock, e.g. from NTP, moves it.
+    calendar_due: Option<SystemTime>,
+    last_fired: Option<SystemTime>,
+}
+
+impl Timer {
+    fn new(config: ServiceConfig, boot: Instant, wall: SystemTime) -> Self {
+        let spec = config.timer.clone().unwrap_or_default();
+        let interval = spec.interval_sec.map(Duration::from_secs);
+        Self {
+            boot_due: spec
+                .on_boot_sec
+                .map(|secs| boot + Duration::from_secs(secs)),
+            // With an on_boot_sec trigger the interval counts from the first run
+            interval_due: interval
+                .filter(|_| spec.on_boot_sec.is_none())
+                .map(|i| boot + i),
+            calendar_due: spec.calendar.map(|calendar| next_calendar(calendar, wall)),
+            last_fired: None,
+            config,
+        }
+    }
+
+    fn spec(&self) -> &TimerConfig {
+        self.config
+            .timer
+            .as_ref()
+            .expect("timer services have a timer")
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// When the timer fires next, or `None` if it never will again.
+    pub fn next_due(&self, now: Instant, wall: SystemTime) -> Option<Instant> {
+        let calendar = self
+            .calendar_due
+            .map(|due| now + due.duration_since(wall).unwrap_or_default());
+        [self.boot_due, self.interval_due, calendar]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    pub fn last_fired(&self) -> Option<SystemTime> {
+        self.last_fired
+    }
+
+    fn is_due(&self, now: Instant, wall: SystemTime) -> bool {
+        self.boot_due.is_some_and(|due| due <= now)
+            || self.interval_due.is_some_and(|due| due <= now)
+            || self.calendar_due.is_some_and(|due| due <= wall)
+    }
+
+    /// Move every trigger past the run starting now. Runs that were missed, for
+    /// instance while the clock was wrong, collapse into this one.
+    fn advance(&mut self, now: Instant, wall: SystemTime) {
+        let spec = self.spec().clone();
+        self.boot_due = self.boot_due.filter(|&due| due > now);
+        self.interval_due = spec
+            .interval_sec
+            .map(|secs| now + Duration::from_secs(secs));
+        self.calendar_due = spec.calendar.map(|calendar| next_calendar(calendar, wall));
+        self.last_fired = Some(wall);
+    }
+}
+
+pub struct Timers {
+    timers: Vec<Timer>,
+    boot: Instant,
+}
+
+impl Timers {
+    /// Timers whose boot-relative triggers count from `boot`.
+    pub fn new(boot: Instant) -> Self {
+        Self {
+            timers: Vec::new(),
+            boot,
+        }
+    }
+
+    /// Replace the set of timer services, e.g. after a reload. A timer whose schedule
+    /// is unchanged keeps its pending triggers and last run; only its service config is
+    /// updated.
+    pub fn load(&mut self, configs: Vec<ServiceConfig>) {
+        let wall = SystemTime::now();
+        let mut previous = std::mem::take(&mut self.timers);
+
+        for config in configs {
+            let kept = previous
+                .iter()
+                .position(|t| t.config.name == config.name)
+                .map(|i| previous.swap_remove(i));
+            let timer = match kept {
+                Some(mut timer) if timer.config.timer == config.timer => {
+                    timer.config = config;
+                    timer
+                }
+                Some(old) => Timer {
+                    last_fired: old.last_fired,
+                    ..Timer::new(config, self.boot, wall)
+                },
+                None => Timer::new(config, self.boot, wall),
+            };
+            self.timers.push(timer);
+        }
+
+        self.timers.sort_by(|a, b| a.name().cmp(b.name()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Timer> {
+        self.timers.iter()
+    }
+
+    /// The earliest moment any timer fires, so the main loop knows when to wake up.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let (now, wall) = (Instant::now(), SystemTime::now());
+        self.timers
+            .iter()
+            .filter_map(|t| t.next_due(now, wall))
+            .min()
+    }
+
+    /// Queue the services of timers that are due. A service still active from its
+    /// previous run is skipped rather than started twice.
+    pub fn fire_due(&mut self, manager: &mut ServiceManager) {
+        for config in self.take_due(Instant::now(), SystemTime::now()) {
+            match manager.state(&config.name) {
+                ServiceState::Pending
+                | ServiceState::Starting
+                | ServiceState::Running
+                | ServiceState::Restarting => {
+                    info!(service = %config.name, "timer fired but service is still active");
+                }
+                _ => {
+                    info!(service = %config.name, "timer fired");
+                    manager.enqueue(vec![config]);
+                }
+            }
+        }
+    }
+
+    fn take_due(&mut self, now: Instant, wall: SystemTime) -> Vec<ServiceConfig> {
+        self.timers
+            .iter_mut()
+            .filter(|t| t.is_due(now, wall))
+            .map(|t| {
+                t.advance(now, wall);
+                t.config.clone()
+            })
+            .collect()
+    }
+}
+
+/// The first moment strictly after `after` that matches `calendar`, in UTC.
+fn next_calendar(calendar: Calendar, after: SystemTime) -> SystemTime {
+    let secs = after
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let next = match calendar {
+        Calendar::Hourly => (secs / HOUR + 1) * HOUR,
+        Calendar::Daily(offset) => {
+            let today = secs - secs % DAY + offset;
+            if today > secs { today } else { today + DAY }
+        }
+        Calendar::Weekly => {
+            // 1970-01-01 was a Thursday, three days after a Monday
+            let days = secs / DAY;
+            (days + 7 - (days + 3) % 7) * DAY
+        }
+    };
+    UNIX_EPOCH + Duration::from_secs(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer_service(name: &str, timer: TimerConfig) -> ServiceConfig {
+        ServiceConfig {
+            name: name.to_string(),
+            exec: "true".to_string(),
+            timer: Some(timer),
+            ..Default::default()
+        }
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn calendar_finds_next_match() {
+        // 2024-01-03, a Wednesday, at 10:15:00 UTC
+        let wednesday = 1_704_276_900;
+        let midnight = wednesday - 10 * HOUR - 15 * 60;
+
+        assert_eq!(
+            next_calendar(Calendar::Hourly, at(wednesday)),
+            at(midnight + 11 * HOUR)
+        );
+        assert_eq!(
+            next_calendar(Calendar::Daily(0), at(wednesday)),
+            at(midnight + DAY)
+        );
+        assert_eq!(
+            next_calendar(Calendar::Daily(12 * HOUR), at(wednesday)),
+            at(midnight + 12 * HOUR)
+        );
+        assert_eq!(
+            next_calendar(Calendar::Weekly, at(wednesday)),
+            at(midnight + 5 * DAY)
+        );
+        // An exact match is in the past once it has fired
+        assert_eq!(
+            next_calendar(Calendar::Hourly, at(midnight)),
+            at(midnight + HOUR)
+        );
+    }
+
+    #[test]
+    fn boot_trigger_fires_once_then_interval_repeats() {
+        let boot = Instant::now();
+        let mut timers = Timers::new(boot);
+        timers.load(vec![timer_service(
+            "ntp",
+            TimerConfig {
+                on_boot_sec: Some(60),
+                interval_sec: Some(600),
+                calendar: None,
+            },
+        )]);
+        let wall = SystemTime::now();
+
+        assert!(
+            timers
+                .take_due(boot + Duration::from_secs(59), wall)
+                .is_empty()
+        );
+        assert_eq!(
+            timers.take_due(boot + Duration::from_secs(60), wall).len(),
+            1
+        );
+        assert!(
+            timers
+                .take_due(boot + Duration::from_secs(600), wall)
+                .is_empty()
+        );
+        assert_eq!(
+            timers.take_due(boot + Duration::from_secs(660), wall).len(),
+            1
+        );
+
+        let timer = timers.iter().next().unwrap();
+        assert_eq!(timer.last_fired(), Some(wall));
+        assert_eq!(
+            timer.next_due(boot, wall),
+            Some(boot + Duration::from_secs(1260))
+        );
+    }
+
+    #[test]
+    fn calendar_trigger_follows_wall_clock() {
+        let boot = Instant::now();
+        let mut timers = Timers::new(boot);
+        timers.load(vec![timer_service(
+            "battery-stats",
+            TimerConfig {
+                calendar: Some(Calendar::Hourly),
+                ..Default::default()
+            },
+        )]);
+        let due = timers.iter().next().unwrap().calendar_due.unwrap();
+
+        assert!(
+            timers
+                .take_due(boot, due - Duration::from_secs(1))
+                .is_empty()
+        );
+        // The clock jumped well past the hour, e.g. after NTP synced; run once
+        assert_eq!(
+            timers
+                .take_due(boot, due + Duration::from_secs(5 * HOUR))
+                .len(),
+            1
+        );
+        assert!(
+            timers
+                .take_due(boot, due + Duration::from_secs(5 * HOUR))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn reload_keeps_unchanged_schedules() {
+        let boot = Instant::now();
+        let spec = TimerConfig {
+            interval_sec: Some(3600),
+            ..Default::default()
+        };
+        let mut timers = Timers::new(boot);
+        timers.load(vec![timer_service("logrotate", spec.clone())]);
+        let wall = SystemTime::now();
+        let later = boot + Duration::from_secs(3600);
+        assert_eq!(timers.take_due(later, wall).len(), 1);
+
+        let mut updated = timer_service("logrotate", spec);
+        updated.args = vec!["--compress".to_string()];
+        timers.load(vec![
+            updated,
+            timer_service(
+                "ntp",
+                TimerConfig {
+                    on_boot_sec: Some(1),
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        let names: Vec<&str> = timers.iter().map(Timer::name).collect();
+        assert_eq!(names, ["logrotate", "ntp"]);
+        let logrotate = timers.iter().next().unwrap();
+        assert_eq!(logrotate.last_fired(), Some(wall));
+        assert_eq!(logrotate.config.args, ["--compress"]);
+        assert_eq!(
+            logrotate.next_due(later, wall),
+            Some(later + Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn changed_schedule_keeps_last_run() {
+        let boot = Instant::now();
+        let mut timers = Timers::new(boot);
+        timers.load(vec![timer_service(
+            "logrotate",
+            TimerConfig {
+                on_boot_sec: Some(0),
+                ..Default::default()
+            },
+        )]);
+        let wall = SystemTime::now();
+        assert_eq!(timers.take_due(boot, wall).len(), 1);
+
+        timers.load(vec![timer_service(
+            "logrotate",
+            TimerConfig {
+                interval_sec: Some(60),
+                ..Default::default()
+            },
+        )]);
+        let logrotate = timers.iter().next().unwrap();
+        assert_eq!(logrotate.last_fired(), Some(wall));
+        assert_eq!(
+            logrotate.next_due(boot, wall),
+            Some(boot + Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn active_service_is_not_started_twice() {
+        let mut timers = Timers::new(Instant::now());
+        let mut svc = timer_service(
+            "flush",
+            TimerConfig {
+                on_boot_sec: Some(0),
+                interval_sec: Some(0),
+                calendar: None,
+            },
+        );
+        svc.exec = "sleep".to_string();
+        svc.args = vec!["10".to_string()];
+        timers.load(vec![svc]);
+
+        let mut mgr = ServiceManager::new();
+        timers.fire_due(&mut mgr);
+        mgr.start_pending();
+        assert_eq!(mgr.state("flush"), ServiceState::Running);
+        let pid = mgr.pid("flush");
+
+        timers.fire_due(&mut mgr);
+        mgr.start_pending();
+        assert_eq!(mgr.pid("flush"), pid);
+
+        mgr.stop_all();
+    }
+}
@@ -0,0 +1,205 @@
+// ABOUTME: Hardware watchdog for PID 1: armed at boot and petted from the main loop.
+// ABOUTME: A hung kernel or a stuck init stops the petting, and the watchdog reboots the phone.
+
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use tracing::{error, info, warn};
+
+use crate::target;
+
+pub const WATCHDOG_PATH: &str = "/dev/watchdog";
+
+/// Timeout asked of the driver unless `mos.watchdog=` sets another.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Kernel command line parameter with the timeout in seconds; 0 leaves the watchdog off.
+const CMDLINE_PARAM: &str = "mos.watchdog=";
+
+/// The watchdog is petted this many times per timeout, so one slow pass through the
+/// main loop doesn't cost a reboot.
+const PETS_PER_TIMEOUT: u32 = 3;
+
+/// Writing this before closing disarms the watchdog ("magic close"). Closing without
+/// it leaves the watchdog running, so a crashed init still gets the device rebooted.
+const MAGIC_CLOSE: &[u8] = b"V";
+
+/// `_IOWR('W', 6, int)` from linux/watchdog.h: set the timeout in seconds; the driver
+/// writes back the timeout it actually uses.
+const WDIOC_SETTIMEOUT: u32 = 0xc004_5706;
+
+pub struct Watchdog {
+    file: File,
+    interval: Duration,
+    next_pet: Instant,
+}
+
+impl Watchdog {
+    /// Open and arm the watchdog at `path`. Returns `None` if the device doesn't exist.
+    pub fn open(path: &Path, timeout: Duration) -> Result<Option<Self>> {
+        let file = match std::fs::OpenOptions::new().write(true).open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to open {}", path.display())),
+        };
+
+        let requested = timeout.as_secs().clamp(1, i32::MAX as u64) as i32;
+        let timeout = match set_timeout(&file, requested) {
+            Ok(secs) if secs > 0 => Duration::from_secs(secs as u64),
+            Ok(_) => timeout,
+            Err(e) => {
+                warn!(error = %e, "watchdog timeout can't be set, assuming the requested one");
+                timeout
+            }
+        };
+        info!(timeout_sec = timeout.as_secs(), "hardware watchdog armed");
+
+        let interval = timeout / PETS_PER_TIMEOUT;
+        Ok(Some(Self {
+            file,
+            interval,
+            next_pet: Instant::now() + interval,
+        }))
+    }
+
+    /// Pet the watchdog if it is due.
+    pub fn pet_if_due(&mut self, now: Instant) {
+        if now < self.next_pet {
+            return;
+        }
+        if let Err(e) = self.file.write_all(b"\0") {
+            error!(error = %e, "failed to pet watchdog");
+        }
+        self.next_pet = now + self.interval;
+    }
+
+    /// When the main loop next has to wake up to pet the watchdog.
+    pub fn next_deadline(&self) -> Instant {
+        self.next_pet
+    }
+
+    /// How long one pass of the main loop may spend stopping services. A pass starts
+    /// at most one petting interval after the last pet, so this leaves another before
+    /// the watchdog fires.
+    pub fn stop_budget(&self) -> Duration {
+        self.interval
+    }
+
+    /// Disarm the watchdog for an orderly shutdown. Drivers built with NOWAYOUT ignore
+    /// this and keep running.
+    pub fn close(mut self) {
+        match self.file.write_all(MAGIC_CLOSE) {
+            Ok(()) => info!("hardware watchdog disarmed"),
+            Err(e) => warn!(error = %e, "failed to disarm watchdog"),
+        }
+    }
+}
+
+fn set_timeout(file: &File, secs: i32) -> std::io::Result<i32> {
+    let mut value: libc::c_int = secs;
+    // SAFETY: WDIOC_SETTIMEOUT reads an int and writes one back through the pointer,
+    // which stays valid for the call
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), WDIOC_SETTIMEOUT as _, &mut value) };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// The watchdog timeout from the kernel command line, `DEFAULT_TIMEOUT` if it isn't
+/// set there, or `None` when `mos.watchdog=0` turns the watchdog off.
+pub fn timeout_from_cmdline(cmdline: Option<&str>) -> Option<Duration> {
+    let param = cmdline.and_then(|line| {
+        line.split_whitespace()
+            .rev()
+            .find_map(|param| param.strip_prefix(CMDLINE_PARAM))
+    });
+    match param.map(str::parse::<u64>) {
+        None => Some(DEFAULT_TIMEOUT),
+        Some(Ok(0)) => None,
+        Some(Ok(secs)) => Some(Duration::from_secs(secs)),
+        Some(Err(_)) => {
+            warn!("invalid {CMDLINE_PARAM} on the kernel command line, using the default");
+            Some(DEFAULT_TIMEOUT)
+        }
+    }
+}
+
+/// Arm the system watchdog as the kernel command line asks. Devices without one, and
+/// failures to open it, leave init running without a watchdog.
+pub fn open_from_system() -> Option<Watchdog> {
+    let cmdline = std::fs::read_to_string(target::CMDLINE_PATH).ok();
+    let Some(timeout) = timeout_from_cmdline(cmdline.as_deref()) else {
+        info!("hardware watchdog disabled on the kernel command line");
+        return None;
+    };
+    match Watchdog::open(Path::new(WATCHDOG_PATH), timeout) {
+        Ok(Some(watchdog)) => Some(watchdog),
+        Ok(None) => {
+            info!("no hardware watchdog");
+            None
+        }
+        Err(e) => {
+            error!(error = %e, "failed to arm hardware watchdog");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_comes_from_cmdline() {
+        assert_eq!(timeout_from_cmdline(None), Some(DEFAULT_TIMEOUT));
+        assert_eq!(
+            timeout_from_cmdline(Some("console=ttyS0 quiet")),
+            Some(DEFAULT_TIMEOUT)
+        );
+        assert_eq!(
+            timeout_from_cmdline(Some("mos.watchdog=30 quiet")),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(timeout_from_cmdline(Some("mos.watchdog=0")), None);
+        assert_eq!(
+            timeout_from_cmdline(Some("mos.watchdog=soon")),
+            Some(DEFAULT_TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn missing_device_is_not_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let watchdog = Watchdog::open(&dir.path().join("watchdog"), DEFAULT_TIMEOUT).unwrap();
+        assert!(watchdog.is_none());
+    }
+
+    #[test]
+    fn pets_when_due_and_magic_closes() {
+        // A plain file stands in for the device; the timeout ioctl fails on it
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("watchdog");
+        std::fs::write(&path, "").unwrap();
+
+        let mut watchdog = Watchdog::open(&path, Duration::from_secs(30))
+            .unwrap()
+            .unwrap();
+        let start = Instant::now();
+        let first = watchdog.next_deadline();
+        assert!(first > start + Duration::from_secs(9) && first <= start + Duration::from_secs(10));
+
+        watchdog.pet_if_due(start);
+        assert!(std::fs::read(&path).unwrap().is_empty());
+
+        let due = watchdog.next_deadline();
+        watchdog.pet_if_due(due);
+        assert_eq!(watchdog.next_deadline(), due + Duration::from_secs(10));
+        watchdog.close();
+        assert_eq!(std::fs::read(&path).unwrap(), b"\0V");
+    }
+}
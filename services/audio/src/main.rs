@@ -1,16 +1,23 @@
 // ABOUTME: Audio routing D-Bus daemon for MobileOS.
 // ABOUTME: Exposes volume, mute state, and audio profile over org.mobileos.Audio.
 
+mod routing;
+
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::{Arc, Mutex};
 
-use tracing::info;
+use tracing::{info, warn};
 use zbus::{connection, interface};
 
+use routing::{Amixer, Router};
+
 struct AudioService {
     volume: Arc<AtomicU8>,
     muted: Arc<AtomicBool>,
     active_profile: Arc<Mutex<String>>,
+    /// Drives the codec's mixer, on devices with an audio routing file.
+    router: Option<Router>,
 }
 
 impl AudioService {
@@ -18,7 +25,26 @@ impl AudioService {
         Self {
             volume: Arc::new(AtomicU8::new(50)),
             muted: Arc::new(AtomicBool::new(false)),
-            active_profile: Arc::new(Mutex::new("speaker".to_string())),
+            active_profile: Arc::new(Mutex::new(routing::DEFAULT_ROUTE.to_string())),
+            router: None,
+        }
+    }
+
+    /// Route sound with `router`, starting on the default route.
+    fn with_router(mut self, router: Router) -> Self {
+        self.router = Some(router);
+        let profile = self.active_profile.lock().unwrap().clone();
+        let (volume, muted) = (self.volume(), self.muted());
+        self.apply(|router| router.switch(&profile, volume, muted));
+        self
+    }
+
+    /// Make a change on the mixer, if there is one.
+    fn apply(&mut self, change: impl FnOnce(&mut Router) -> anyhow::Result<()>) {
+        if let Some(router) = self.router.as_mut()
+            && let Err(e) = change(router)
+        {
+            warn!(error = %e, "failed to set mixer");
         }
     }
 }
@@ -34,6 +60,7 @@ impl AudioService {
     fn set_volume(&mut self, value: u8) {
         info!(volume = value, "setting volume");
         self.volume.store(value, Ordering::Relaxed);
+        self.apply(|router| router.set_volume(value));
     }
 
     #[zbus(property)]
@@ -45,6 +72,7 @@ impl AudioService {
     fn set_muted(&mut self, value: bool) {
         info!(muted = value, "setting mute state");
         self.muted.store(value, Ordering::Relaxed);
+        self.apply(|router| router.set_muted(value));
     }
 
     #[zbus(property)]
@@ -52,10 +80,24 @@ impl AudioService {
         self.active_profile.lock().unwrap().clone()
     }
 
+    /// One of "speaker", "earpiece", "headset" or "bluetooth". Devices with audio routing
+    /// refuse those their codec doesn't have.
     #[zbus(property)]
-    fn set_active_profile(&mut self, profile: String) {
+    fn set_active_profile(&mut self, profile: String) -> zbus::fdo::Result<()> {
+        if self
+            .router
+            .as_ref()
+            .is_some_and(|router| !router.has_route(&profile))
+        {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "no '{profile}' audio route on this device"
+            )));
+        }
         info!(profile = %profile, "setting audio profile");
+        let (volume, muted) = (self.volume(), self.muted());
+        self.apply(|router| router.switch(&profile, volume, muted));
         *self.active_profile.lock().unwrap() = profile;
+        Ok(())
     }
 }
 
@@ -70,20 +112,50 @@ async fn main() -> anyhow::Result<()> {
 
     info!("starting audio service");
 
-    let service = AudioService::new();
+    let service = match load_router() {
+        Some(router) => AudioService::new().with_router(router),
+        None => AudioService::new(),
+    };
 
-    let _connection = connection::Builder::session()?
+    let _connection = connection::Builder::system()?
         .name("org.mobileos.Audio")?
         .serve_at("/org/mobileos/Audio", service)?
         .build()
         .await?;
 
-    info!("audio service running on session bus");
+    info!("audio service running on system bus");
+    mos_notify::ready();
 
     std::future::pending::<()>().await;
     Ok(())
 }
 
+/// The mixer router for the audio routing file the device config names, if any.
+fn load_router() -> Option<Router> {
+    let name = match mos_device::load_from_system() {
+        Ok(device) => device.and_then(|device| device.audio),
+        Err(e) => {
+            warn!(error = %e, "failed to load device config");
+            None
+        }
+    };
+    let Some(name) = name else {
+        info!("no audio routing for this device, leaving the mixer alone");
+        return None;
+    };
+    match routing::load(Path::new(routing::ROUTING_DIR), &name) {
+        Ok(routing) => {
+            info!(routing = %name, card = %routing.card, "using audio routing");
+            let mixer = Amixer::new(&routing.card);
+            Some(Router::new(routing, Box::new(mixer)))
+        }
+        Err(e) => {
+            warn!(error = %e, "failed to load audio routing");
+            None
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use zbus::{connection, proxy, Connection};
@@ -0,0 +1,273 @@
+// ABOUTME: Per-device audio routing from /etc/mos/audio: the mixer controls that move sound between paths.
+// ABOUTME: Like ALSA UCM's enable and disable sequences, so a new phone needs a TOML file rather than code.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+
+pub const ROUTING_DIR: &str = "/etc/mos/audio";
+
+/// Where sound can go, by the names ActiveProfile takes.
+pub const ROUTES: &[&str] = &["speaker", "earpiece", "headset", "bluetooth"];
+
+/// The route sound takes at startup.
+pub const DEFAULT_ROUTE: &str = "speaker";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Routing {
+    /// ALSA card the controls are on, by its id in /proc/asound/cards.
+    pub card: String,
+    /// The codec's routes, by name. A phone without an earpiece, say, leaves it out.
+    #[serde(default)]
+    pub routes: HashMap<String, Route>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// Controls set, in order, when sound switches to this route.
+    #[serde(default)]
+    pub enable: Vec<Control>,
+    /// Controls set, in order, when sound switches away, before the next route's `enable`.
+    #[serde(default)]
+    pub disable: Vec<Control>,
+    /// Control the volume is written to, as a percentage.
+    #[serde(default)]
+    pub volume: Option<String>,
+    /// Switch control turned off to mute the route.
+    #[serde(default)]
+    pub mute: Option<String>,
+}
+
+/// A mixer control and the value to set it to, as `amixer cset` takes them, e.g.
+/// "Line Out Playback Switch" = "on".
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Control {
+    pub name: String,
+    pub value: String,
+}
+
+pub fn parse_routing(content: &str) -> Result<Routing> {
+    let routing: Routing = toml::from_str(content).context("failed to parse audio routing")?;
+    if let Some(name) = routing
+        .routes
+        .keys()
+        .find(|name| !ROUTES.contains(&name.as_str()))
+    {
+        bail!(
+            "unknown route '{name}', expected one of {}",
+            ROUTES.join(", ")
+        );
+    }
+    Ok(routing)
+}
+
+/// The routing file `name` in `dir`, as named by the device config.
+pub fn load(dir: &Path, name: &str) -> Result<Routing> {
+    let path = dir.join(format!("{name}.toml"));
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    parse_routing(&content).with_context(|| format!("in {}", path.display()))
+}
+
+/// Sets mixer controls on the sound card.
+pub trait Mixer: Send + Sync {
+    fn set(&mut self, control: &str, value: &str) -> Result<()>;
+}
+
+/// Sets controls with alsa-utils' amixer.
+pub struct Amixer {
+    card: String,
+}
+
+impl Amixer {
+    pub fn new(card: &str) -> Self {
+        Self {
+            card: card.to_string(),
+        }
+    }
+}
+
+impl Mixer for Amixer {
+    fn set(&mut self, control: &str, value: &str) -> Result<()> {
+        let status = Command::new("amixer")
+            .args([
+                "-q",
+                "-c",
+                &self.card,
+                "cset",
+                &format!("name={control}"),
+                value,
+            ])
+            .status()
+            .context("failed to run amixer")?;
+        if !status.success() {
+            bail!("amixer failed to set '{control}' to '{value}': {status}");
+        }
+        Ok(())
+    }
+}
+
+/// Applies a device's routing to its mixer, keeping track of the route in use.
+pub struct Router {
+    routing: Routing,
+    mixer: Box<dyn Mixer>,
+    active: Option<String>,
+}
+
+impl Router {
+    pub fn new(routing: Routing, mixer: Box<dyn Mixer>) -> Self {
+        Self {
+            routing,
+            mixer,
+            active: None,
+        }
+    }
+
+    pub fn has_route(&self, name: &str) -> bool {
+        self.routing.routes.contains_key(name)
+    }
+
+    /// Move sound to `name`, leaving the route in use first, and carry `volume` and
+    /// `muted` over to it.
+    pub fn switch(&mut self, name: &str, volume: u8, muted: bool) -> Result<()> {
+        let Some(route) = self.routing.routes.get(name) else {
+            bail!("no route '{name}' on this device");
+        };
+        if let Some(active) = self.active.take()
+            && let Some(previous) = self.routing.routes.get(&active)
+        {
+            set_all(self.mixer.as_mut(), &previous.disable)?;
+        }
+        set_all(self.mixer.as_mut(), &route.enable)?;
+        self.active = Some(name.to_string());
+        self.set_volume(volume)?;
+        self.set_muted(muted)
+    }
+
+    pub fn set_volume(&mut self, volume: u8) -> Result<()> {
+        match self.active_route().and_then(|route| route.volume.clone()) {
+            Some(control) => self.mixer.set(&control, &format!("{}%", volume.min(100))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn set_muted(&mut self, muted: bool) -> Result<()> {
+        match self.active_route().and_then(|route| route.mute.clone()) {
+            Some(control) => self.mixer.set(&control, if muted { "off" } else { "on" }),
+            None => Ok(()),
+        }
+    }
+
+    fn active_route(&self) -> Option<&Route> {
+        self.routing.routes.get(self.active.as_deref()?)
+    }
+}
+
+fn set_all(mixer: &mut dyn Mixer, controls: &[Control]) -> Result<()> {
+    for control in controls {
+        mixer.set(&control.name, &control.value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Records the controls set, for checking sequences.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    impl Mixer for Recorder {
+        fn set(&mut self, control: &str, value: &str) -> Result<()> {
+            self.0.lock().unwrap().push(format!("{control}={value}"));
+            Ok(())
+        }
+    }
+
+    const ROUTING: &str = r#"
+        card = "PinePhone"
+
+        [routes.speaker]
+        enable = [{ name = "Line Out Playback Switch", value = "on" }]
+        disable = [{ name = "Line Out Playback Switch", value = "off" }]
+        volume = "Line Out Playback Volume"
+
+        [routes.earpiece]
+        enable = [
+            { name = "Earpiece Source Playback Route", value = "Left Mixer" },
+            { name = "Earpiece Playback Switch", value = "on" },
+        ]
+        volume = "Earpiece Playback Volume"
+        mute = "Earpiece Playback Switch"
+    "#;
+
+    #[test]
+    fn switching_routes_runs_their_sequences() {
+        let mixer = Recorder::default();
+        let mut router = Router::new(parse_routing(ROUTING).unwrap(), Box::new(mixer.clone()));
+
+        router.switch("speaker", 50, false).unwrap();
+        assert_eq!(
+            mixer.take(),
+            [
+                "Line Out Playback Switch=on",
+                "Line Out Playback Volume=50%"
+            ]
+        );
+
+        router.switch("earpiece", 80, true).unwrap();
+        assert_eq!(
+            mixer.take(),
+            [
+                "Line Out Playback Switch=off",
+                "Earpiece Source Playback Route=Left Mixer",
+                "Earpiece Playback Switch=on",
+                "Earpiece Playback Volume=80%",
+                "Earpiece Playback Switch=off",
+            ]
+        );
+
+        router.set_volume(30).unwrap();
+        router.set_muted(false).unwrap();
+        assert_eq!(
+            mixer.take(),
+            [
+                "Earpiece Playback Volume=30%",
+                "Earpiece Playback Switch=on"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_routes_are_refused() {
+        let mixer = Recorder::default();
+        let mut router = Router::new(parse_routing(ROUTING).unwrap(), Box::new(mixer.clone()));
+
+        assert!(!router.has_route("headset"));
+        assert!(router.switch("headset", 50, false).is_err());
+        assert!(mixer.take().is_empty());
+    }
+
+    #[test]
+    fn bad_routing_is_rejected() {
+        assert!(parse_routing("card = \"PinePhone\"").is_ok());
+        assert!(parse_routing("[routes.speaker]").is_err());
+        assert!(parse_routing("card = \"PinePhone\"\n[routes.loudspeaker]").is_err());
+        assert!(parse_routing("card = \"PinePhone\"\n[routes.speaker]\nvolum = \"x\"").is_err());
+    }
+}
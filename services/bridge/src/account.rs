@@ -0,0 +1,118 @@
+// ABOUTME: The account bridge shells, file transfers and developer apps run under, instead of root.
+// ABOUTME: Looked up in /etc/passwd, and applied to each command the bridge starts and each file it moves.
+
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+const PASSWD_PATH: &str = "/etc/passwd";
+
+/// Who bridge shells and launched apps run as.
+pub const DEVELOPER_USER: &str = "developer";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+impl Account {
+    pub fn lookup(name: &str) -> Result<Self> {
+        let passwd = std::fs::read_to_string(PASSWD_PATH)
+            .with_context(|| format!("failed to read {PASSWD_PATH}"))?;
+        find(&passwd, name).with_context(|| format!("no account '{name}' in {PASSWD_PATH}"))
+    }
+
+    /// Create the home directory, on /data, owned by the account.
+    pub fn prepare_home(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.home)
+            .with_context(|| format!("failed to create {}", self.home.display()))?;
+        std::os::unix::fs::chown(&self.home, Some(self.uid), Some(self.gid))
+            .with_context(|| format!("failed to hand {} to {}", self.home.display(), self.name))
+    }
+
+    /// Run `cmd` as this account, in its home directory. Dropping to its uid also drops
+    /// root's supplementary groups.
+    pub fn apply(&self, cmd: &mut tokio::process::Command) {
+        cmd.uid(self.uid)
+            .gid(self.gid)
+            .env("USER", &self.name)
+            .env("HOME", &self.home)
+            .current_dir(&self.home);
+    }
+
+    /// Run the file operation `f` on a thread of its own that has dropped to this
+    /// account, so the kernel checks what it opens, creates or renames against the
+    /// account rather than root. The thread exits with its credentials afterwards.
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> io::Result<T> + Send + 'static,
+    {
+        let (uid, gid) = (self.uid, self.gid);
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        std::thread::spawn(move || {
+            let _ = tx.send(drop_thread_to(uid, gid).and_then(|()| f()));
+        });
+        Ok(rx.await.context("file transfer thread died")??)
+    }
+}
+
+/// Switch the calling thread, and only it, to `uid` and `gid` with no supplementary
+/// groups. libc's wrappers would switch every thread in the process, so this uses the
+/// raw syscalls, which the kernel applies per thread.
+fn drop_thread_to(uid: u32, gid: u32) -> io::Result<()> {
+    // SAFETY: these only change the calling thread's credentials; setgroups is given
+    // an empty list, so the null pointer is never read
+    let failed = unsafe {
+        libc::syscall(libc::SYS_setgroups, 0, std::ptr::null::<libc::gid_t>()) != 0
+            || libc::syscall(libc::SYS_setresgid, gid, gid, gid) != 0
+            || libc::syscall(libc::SYS_setresuid, uid, uid, uid) != 0
+    };
+    if failed {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The entry for `name` in passwd-formatted `passwd`.
+fn find(passwd: &str, name: &str) -> Option<Account> {
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() < 7 || fields[0] != name {
+            return None;
+        }
+        Some(Account {
+            name: name.to_string(),
+            uid: fields[2].parse().ok()?,
+            gid: fields[3].parse().ok()?,
+            home: PathBuf::from(fields[5]),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_accounts_by_name() {
+        let passwd = "root:x:0:0:root:/root:/bin/sh\n\
+                      developer:x:1000:1000:MobileOS developer:/data/developer:/bin/sh\n\
+                      broken:x:nope:1:::\n";
+        assert_eq!(
+            find(passwd, "developer"),
+            Some(Account {
+                name: "developer".to_string(),
+                uid: 1000,
+                gid: 1000,
+                home: PathBuf::from("/data/developer"),
+            })
+        );
+        assert_eq!(find(passwd, "broken"), None);
+        assert_eq!(find(passwd, "nobody"), None);
+    }
+}
@@ -0,0 +1,141 @@
+// ABOUTME: Host authorization for the device bridge.
+// ABOUTME: Remembers accepted host public keys and parks unknown hosts until the user answers the prompt.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tokio::sync::oneshot;
+use tracing::warn;
+
+pub const KEYS_PATH: &str = "/data/mosb/authorized_keys";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allow: bool,
+    /// Add the key to the authorized list so future connections skip the prompt.
+    pub remember: bool,
+}
+
+pub struct Authorizer {
+    path: PathBuf,
+    /// Authorized public keys mapped to the host name they were first seen with.
+    keys: Mutex<HashMap<String, String>>,
+    pending: Mutex<HashMap<String, oneshot::Sender<Decision>>>,
+}
+
+impl Authorizer {
+    /// Load authorized keys from `path`, one `<key> <host>` pair per line.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let keys = std::fs::read_to_string(&path)
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(|line| {
+                        let (key, host) = line.split_once(' ').unwrap_or((line, ""));
+                        (!key.is_empty()).then(|| (key.to_string(), host.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            path,
+            keys: Mutex::new(keys),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_authorized(&self, key: &str) -> bool {
+        self.keys.lock().unwrap().contains_key(key)
+    }
+
+    /// Host names of all remembered keys, sorted.
+    pub fn hosts(&self) -> Vec<String> {
+        let hosts: HashSet<String> = self.keys.lock().unwrap().values().cloned().collect();
+        let mut hosts: Vec<String> = hosts.into_iter().collect();
+        hosts.sort();
+        hosts
+    }
+
+    /// Register a pending prompt. The receiver resolves when `respond` is called.
+    pub fn request(&self, fingerprint: &str) -> oneshot::Receiver<Decision> {
+        let (tx, rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(fingerprint.to_string(), tx);
+        rx
+    }
+
+    /// Deliver the user's answer. Returns false if no prompt is waiting for this fingerprint.
+    pub fn respond(&self, fingerprint: &str, decision: Decision) -> bool {
+        match self.pending.lock().unwrap().remove(fingerprint) {
+            Some(tx) => tx.send(decision).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn remember(&self, key: &str, host: &str) {
+        self.keys
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), host.to_string());
+
+        if let Some(dir) = self.path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| writeln!(f, "{key} {host}"));
+        if let Err(e) = result {
+            warn!(path = %self.path.display(), error = %e, "failed to save authorized key");
+        }
+    }
+
+    pub fn revoke_all(&self) {
+        self.keys.lock().unwrap().clear();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remembered_keys_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys");
+
+        let auth = Authorizer::load(&path);
+        assert!(!auth.is_authorized("k1"));
+        auth.remember("k1", "laptop");
+
+        let reloaded = Authorizer::load(&path);
+        assert!(reloaded.is_authorized("k1"));
+        assert_eq!(reloaded.hosts(), vec!["laptop".to_string()]);
+
+        reloaded.revoke_all();
+        assert!(!reloaded.is_authorized("k1"));
+        assert!(!Authorizer::load(&path).is_authorized("k1"));
+    }
+
+    #[tokio::test]
+    async fn respond_resolves_pending_request() {
+        let auth = Authorizer::load("/nonexistent/keys");
+        let decision = Decision {
+            allow: true,
+            remember: false,
+        };
+
+        let rx = auth.request("AA:BB");
+        assert!(!auth.respond("CC:DD", decision));
+        assert!(auth.respond("AA:BB", decision));
+        assert_eq!(rx.await.unwrap(), decision);
+    }
+}
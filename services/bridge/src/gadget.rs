@@ -0,0 +1,66 @@
+// ABOUTME: USB gadget setup for the bridge's serial transport.
+// ABOUTME: Builds a CDC-ACM gadget through configfs and binds it to the first UDC.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use tracing::info;
+
+const GADGET_DIR: &str = "/sys/kernel/config/usb_gadget/mos";
+const UDC_CLASS_DIR: &str = "/sys/class/udc";
+
+/// Linux Foundation "Multifunction Composite Gadget" IDs, as used by most dev boards.
+const VENDOR_ID: &str = "0x1d6b";
+const PRODUCT_ID: &str = "0x0104";
+
+/// Create the gadget if needed and bind it. Afterwards the host sees /dev/ttyACM*
+/// and the device side is /dev/ttyGS0.
+pub fn setup() -> anyhow::Result<()> {
+    let gadget = Path::new(GADGET_DIR);
+    if !gadget.parent().is_some_and(Path::exists) {
+        bail!("configfs usb_gadget is not available");
+    }
+
+    fs::create_dir_all(gadget.join("strings/0x409"))?;
+    fs::create_dir_all(gadget.join("configs/c.1/strings/0x409"))?;
+    fs::create_dir_all(gadget.join("functions/acm.usb0"))?;
+
+    write(gadget, "idVendor", VENDOR_ID)?;
+    write(gadget, "idProduct", PRODUCT_ID)?;
+    write(gadget, "strings/0x409/manufacturer", "MobileOS")?;
+    write(gadget, "strings/0x409/product", "MobileOS Device Bridge")?;
+    write(gadget, "strings/0x409/serialnumber", &serial_number())?;
+    write(gadget, "configs/c.1/strings/0x409/configuration", "mosb")?;
+
+    let link = gadget.join("configs/c.1/acm.usb0");
+    if !link.exists() {
+        std::os::unix::fs::symlink(gadget.join("functions/acm.usb0"), &link)
+            .context("failed to link ACM function")?;
+    }
+
+    let udc = fs::read_dir(UDC_CLASS_DIR)
+        .context("failed to list UDCs")?
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .next()
+        .context("no USB device controller found")?;
+
+    let bound = fs::read_to_string(gadget.join("UDC")).unwrap_or_default();
+    if bound.trim() != udc {
+        write(gadget, "UDC", &udc)?;
+    }
+
+    info!(udc = %udc, "USB serial gadget bound");
+    Ok(())
+}
+
+fn write(gadget: &Path, attr: &str, value: &str) -> anyhow::Result<()> {
+    fs::write(gadget.join(attr), value).with_context(|| format!("failed to write gadget {attr}"))
+}
+
+fn serial_number() -> String {
+    fs::read_to_string("/etc/machine-id")
+        .map(|id| id.trim().chars().take(16).collect())
+        .unwrap_or_else(|_| "mobileos".to_string())
+}
@@ -0,0 +1,201 @@
+// ABOUTME: Runs developer-installed apps on behalf of the bridge.
+// ABOUTME: Keeps one instance per app, restarting it on relaunch and fanning its output out to sessions.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use mosb::protocol::{Message, CHUNK_SIZE};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+use tracing::info;
+
+use crate::account::Account;
+
+/// Output frames buffered per app for slow or briefly absent sessions.
+const OUTPUT_BACKLOG: usize = 256;
+
+/// How long to keep reading output after exit; children the app left behind may hold
+/// its pipes open indefinitely.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct RunningApp {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+pub struct Launcher {
+    apps_dir: PathBuf,
+    /// Who apps run as; `None` runs them as the bridge itself.
+    account: Option<Account>,
+    running: Mutex<HashMap<String, RunningApp>>,
+}
+
+impl Launcher {
+    pub fn new(apps_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            apps_dir: apps_dir.into(),
+            account: None,
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_account(mut self, account: Option<Account>) -> Self {
+        self.account = account;
+        self
+    }
+
+    pub fn apps_dir(&self) -> &PathBuf {
+        &self.apps_dir
+    }
+
+    /// Start an installed app, stopping any instance already running. The receiver yields
+    /// the app's `Data`/`Stderr` output and finally its `Exit` status.
+    pub async fn launch(&self, name: &str) -> anyhow::Result<broadcast::Receiver<Message>> {
+        let path = self.apps_dir.join(name).join(name);
+        if !path.exists() {
+            bail!("app '{name}' is not installed");
+        }
+
+        self.stop(name).await;
+
+        let mut cmd = tokio::process::Command::new(&path);
+        if let Some(account) = &self.account {
+            account.apply(&mut cmd);
+        }
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("failed to start {}", path.display()))?;
+        info!(app = %name, pid = child.id(), "app launched");
+
+        let (output, rx) = broadcast::channel(OUTPUT_BACKLOG);
+        let (stop, mut stopped) = oneshot::channel();
+        let mut stdout = tokio::spawn(forward(
+            child.stdout.take().unwrap(),
+            output.clone(),
+            Message::Data,
+        ));
+        let mut stderr = tokio::spawn(forward(
+            child.stderr.take().unwrap(),
+            output.clone(),
+            Message::Stderr,
+        ));
+
+        let app = name.to_string();
+        let task = tokio::spawn(async move {
+            let status = tokio::select! {
+                status = child.wait() => status,
+                _ = &mut stopped => {
+                    let _ = child.start_kill();
+                    child.wait().await
+                }
+            };
+            let drain = async {
+                let _ = (&mut stdout).await;
+                let _ = (&mut stderr).await;
+            };
+            if tokio::time::timeout(DRAIN_TIMEOUT, drain).await.is_err() {
+                stdout.abort();
+                stderr.abort();
+            }
+
+            let code = status.ok().and_then(|s| s.code()).unwrap_or(-1);
+            info!(app = %app, code, "app exited");
+            let _ = output.send(Message::Exit(code));
+        });
+
+        self.running
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), RunningApp { stop, task });
+        Ok(rx)
+    }
+
+    /// Stop a running app and wait for it to exit. Does nothing if it isn't running.
+    pub async fn stop(&self, name: &str) {
+        let app = self.running.lock().unwrap().remove(name);
+        if let Some(app) = app {
+            info!(app = %name, "stopping app");
+            let _ = app.stop.send(());
+            let _ = app.task.await;
+        }
+    }
+}
+
+async fn forward<R: AsyncRead + Unpin>(
+    mut pipe: R,
+    output: broadcast::Sender<Message>,
+    wrap: fn(Vec<u8>) -> Message,
+) {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        match pipe.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            // Nobody listening is fine; the app keeps running without a session attached
+            Ok(n) => {
+                let _ = output.send(wrap(buf[..n].to_vec()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn install_script(dir: &std::path::Path, name: &str, script: &str) {
+        let app_dir = dir.join(name);
+        std::fs::create_dir_all(&app_dir).unwrap();
+        let path = app_dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    async fn next(rx: &mut broadcast::Receiver<Message>) -> Message {
+        tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn launch_streams_output_and_exit() {
+        let dir = tempfile::tempdir().unwrap();
+        install_script(dir.path(), "hello", "echo hello; exit 4");
+        let launcher = Launcher::new(dir.path());
+
+        let mut rx = launcher.launch("hello").await.unwrap();
+        assert_eq!(next(&mut rx).await, Message::Data(b"hello\n".to_vec()));
+        assert_eq!(next(&mut rx).await, Message::Exit(4));
+    }
+
+    #[tokio::test]
+    async fn relaunch_stops_previous_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        install_script(dir.path(), "looper", "exec sleep 30");
+        let launcher = Launcher::new(dir.path());
+
+        let mut first = launcher.launch("looper").await.unwrap();
+        let mut second = launcher.launch("looper").await.unwrap();
+        assert_eq!(next(&mut first).await, Message::Exit(-1));
+
+        launcher.stop("looper").await;
+        assert_eq!(next(&mut second).await, Message::Exit(-1));
+    }
+
+    #[tokio::test]
+    async fn launching_missing_app_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let launcher = Launcher::new(dir.path());
+        assert!(launcher.launch("ghost").await.is_err());
+    }
+}
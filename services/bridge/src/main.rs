@@ -0,0 +1,351 @@
+// ABOUTME: Developer device bridge daemon for MobileOS (the device side of `mosb`).
+// ABOUTME: Serves bridge sessions over USB serial and loopback or USB TCP, with host prompts over org.mobileos.Bridge.
+
+mod account;
+mod auth;
+#[cfg(feature = "hardware")]
+mod gadget;
+mod launcher;
+mod session;
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use mosb::protocol::DEFAULT_PORT;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use zbus::message::Header;
+use zbus::object_server::SignalEmitter;
+use zbus::{connection, interface, Connection};
+
+use account::Account;
+use auth::{Authorizer, Decision};
+use session::Context;
+
+/// Device side of the USB CDC-ACM gadget function.
+const SERIAL_DEVICE: &str = "/dev/ttyGS0";
+
+/// Network interface of a USB gadget, where a host plugged in over USB reaches the bridge.
+const USB_INTERFACE: &str = "usb0";
+
+const APPS_DIR: &str = "/data/apps";
+
+/// The shell, which shows the prompts and is the only program that may answer them.
+const SHELL_EXE: &str = "/usr/bin/mos-shell";
+
+struct BridgeService {
+    auth: Arc<Authorizer>,
+    /// Executable of the one client allowed to call `Respond`.
+    responder: PathBuf,
+}
+
+impl BridgeService {
+    fn new(auth: Arc<Authorizer>, responder: impl Into<PathBuf>) -> Self {
+        Self {
+            auth,
+            responder: responder.into(),
+        }
+    }
+}
+
+#[interface(name = "org.mobileos.Bridge")]
+impl BridgeService {
+    /// Answer an authorization prompt raised by `AuthorizationRequested`. Only the shell
+    /// may; any other app answering would let itself grant a host root's reach.
+    async fn respond(
+        &self,
+        fingerprint: &str,
+        allow: bool,
+        remember: bool,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> zbus::fdo::Result<()> {
+        let sender = header
+            .sender()
+            .ok_or_else(|| zbus::fdo::Error::AccessDenied("caller unknown".to_string()))?;
+        let pid = zbus::fdo::DBusProxy::new(connection)
+            .await?
+            .get_connection_unix_process_id(sender.clone().into())
+            .await?;
+        let exe = std::fs::read_link(format!("/proc/{pid}/exe")).unwrap_or_default();
+        if exe != self.responder {
+            let exe = exe.display();
+            warn!(pid, exe = %exe, "refused a bridge prompt answer from outside the shell");
+            return Err(zbus::fdo::Error::AccessDenied(
+                "only the shell may answer bridge prompts".to_string(),
+            ));
+        }
+
+        info!(fingerprint, allow, remember, "authorization answered");
+        if self.auth.respond(fingerprint, Decision { allow, remember }) {
+            Ok(())
+        } else {
+            Err(zbus::fdo::Error::InvalidArgs(format!(
+                "no pending request for {fingerprint}"
+            )))
+        }
+    }
+
+    /// Forget every remembered host; they will be prompted again.
+    fn revoke_all(&self) {
+        info!("revoking all bridge hosts");
+        self.auth.revoke_all();
+    }
+
+    #[zbus(property)]
+    fn authorized_hosts(&self) -> Vec<String> {
+        self.auth.hosts()
+    }
+
+    #[zbus(signal)]
+    async fn authorization_requested(
+        emitter: &SignalEmitter<'_>,
+        host: &str,
+        fingerprint: &str,
+    ) -> zbus::Result<()>;
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    info!("starting bridge service");
+
+    // Shells and apps started over the bridge never run as root
+    let account = Account::lookup(account::DEVELOPER_USER)?;
+    account.prepare_home()?;
+
+    let auth = Arc::new(Authorizer::load(auth::KEYS_PATH));
+    let service = BridgeService::new(auth.clone(), SHELL_EXE);
+
+    let connection = connection::Builder::system()?
+        .name("org.mobileos.Bridge")?
+        .serve_at("/org/mobileos/Bridge", service)?
+        .build()
+        .await?;
+
+    let iface = connection
+        .object_server()
+        .interface::<_, BridgeService>("/org/mobileos/Bridge")
+        .await?;
+    let (prompt_tx, mut prompt_rx) = mpsc::unbounded_channel::<(String, String)>();
+    tokio::spawn(async move {
+        while let Some((host, fingerprint)) = prompt_rx.recv().await {
+            info!(host = %host, fingerprint = %fingerprint, "asking user to authorize host");
+            if let Err(e) =
+                BridgeService::authorization_requested(iface.signal_emitter(), &host, &fingerprint)
+                    .await
+            {
+                warn!(error = %e, "failed to emit authorization request");
+            }
+        }
+    });
+
+    let ctx = Arc::new(Context {
+        auth,
+        prompts: prompt_tx,
+        launcher: launcher::Launcher::new(APPS_DIR).with_account(Some(account.clone())),
+        account: Some(account),
+    });
+
+    #[cfg(feature = "hardware")]
+    if let Err(e) = gadget::setup() {
+        warn!(error = %e, "failed to configure USB gadget");
+    }
+    if Path::new(SERIAL_DEVICE).exists() {
+        tokio::spawn(serve_serial(ctx.clone()));
+    }
+
+    for address in listen_addresses() {
+        let listener = TcpListener::bind((address, DEFAULT_PORT)).await?;
+        info!(address = %address, port = DEFAULT_PORT, "bridge service listening");
+        tokio::spawn(serve_tcp(listener, ctx.clone()));
+    }
+    mos_notify::ready();
+
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+/// Loopback, for port forwards on the device, and the USB gadget's network interface
+/// if it has an address. Never Wi-Fi or mobile data, where anyone nearby could connect.
+fn listen_addresses() -> Vec<IpAddr> {
+    let mut addresses = vec![IpAddr::V4(Ipv4Addr::LOCALHOST)];
+    match nix::ifaddrs::getifaddrs() {
+        Ok(interfaces) => addresses.extend(
+            interfaces
+                .filter(|interface| interface.interface_name == USB_INTERFACE)
+                .filter_map(|interface| interface.address?.as_sockaddr_in().map(|a| a.ip()))
+                .map(IpAddr::V4),
+        ),
+        Err(e) => warn!(error = %e, "failed to list network interfaces"),
+    }
+    addresses
+}
+
+async fn serve_tcp(listener: TcpListener, ctx: Arc<Context>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                info!(peer = %peer, "bridge connection");
+                let (r, w) = stream.into_split();
+                tokio::spawn(session::serve(Box::new(r), Box::new(w), ctx.clone()));
+            }
+            Err(e) => error!(error = %e, "failed to accept bridge connection"),
+        }
+    }
+}
+
+/// Serve the USB serial link, reopening it whenever the host side goes away.
+async fn serve_serial(ctx: Arc<Context>) {
+    loop {
+        let opened = mosb::serial::open_raw(Path::new(SERIAL_DEVICE))
+            .and_then(|file| Ok((file.try_clone()?, file)));
+        match opened {
+            Ok((read, write)) => {
+                info!(device = SERIAL_DEVICE, "serving bridge over USB serial");
+                // Separate handles: a pending read on a tokio File would block writes
+                session::serve(
+                    Box::new(tokio::fs::File::from_std(read)),
+                    Box::new(tokio::fs::File::from_std(write)),
+                    ctx.clone(),
+                )
+                .await;
+            }
+            Err(e) => warn!(device = SERIAL_DEVICE, error = %e, "failed to open serial link"),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures_util::StreamExt;
+    use zbus::{connection, proxy, Connection};
+
+    use crate::auth::{Authorizer, Decision};
+
+    #[proxy(
+        interface = "org.mobileos.Bridge",
+        default_path = "/org/mobileos/Bridge"
+    )]
+    trait Bridge {
+        fn respond(&self, fingerprint: &str, allow: bool, remember: bool) -> zbus::Result<()>;
+        fn revoke_all(&self) -> zbus::Result<()>;
+
+        #[zbus(property)]
+        fn authorized_hosts(&self) -> zbus::Result<Vec<String>>;
+
+        #[zbus(signal)]
+        fn authorization_requested(&self, host: &str, fingerprint: &str) -> zbus::Result<()>;
+    }
+
+    /// A bridge that takes answers from this test process, as it would from the shell.
+    async fn start_test_service(auth: Arc<Authorizer>) -> (Connection, BridgeProxy<'static>) {
+        let exe = std::fs::read_link("/proc/self/exe").unwrap();
+        start(super::BridgeService::new(auth, exe)).await
+    }
+
+    async fn start(service: super::BridgeService) -> (Connection, BridgeProxy<'static>) {
+        let conn = connection::Builder::session()
+            .unwrap()
+            .serve_at("/org/mobileos/Bridge", service)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let name = conn.unique_name().unwrap().to_owned();
+
+        let client = Connection::session().await.unwrap();
+        let proxy = BridgeProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        (conn, proxy)
+    }
+
+    #[tokio::test]
+    async fn respond_without_pending_request_fails() {
+        let auth = Arc::new(Authorizer::load("/nonexistent/keys"));
+        let (_conn, proxy) = start_test_service(auth).await;
+        assert!(proxy.respond("AA:BB", true, false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn respond_delivers_decision() {
+        let auth = Arc::new(Authorizer::load("/nonexistent/keys"));
+        let (_conn, proxy) = start_test_service(auth.clone()).await;
+
+        let decision = auth.request("AA:BB");
+        proxy.respond("AA:BB", true, true).await.unwrap();
+        let decision = decision.await.unwrap();
+        assert!(decision.allow);
+        assert!(decision.remember);
+    }
+
+    #[tokio::test]
+    async fn only_the_shell_may_respond() {
+        let auth = Arc::new(Authorizer::load("/nonexistent/keys"));
+        let (_conn, proxy) = start(super::BridgeService::new(auth.clone(), super::SHELL_EXE)).await;
+
+        let _decision = auth.request("AA:BB");
+        match proxy.respond("AA:BB", true, true).await {
+            Err(zbus::Error::MethodError(name, _, _)) => {
+                assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.AccessDenied")
+            }
+            other => panic!("expected AccessDenied, got {other:?}"),
+        }
+        // Still waiting for the shell
+        assert!(auth.respond(
+            "AA:BB",
+            Decision {
+                allow: false,
+                remember: false
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn lists_and_revokes_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = Arc::new(Authorizer::load(dir.path().join("keys")));
+        auth.remember("k1", "laptop");
+        let (_conn, proxy) = start_test_service(auth.clone()).await;
+
+        assert_eq!(proxy.authorized_hosts().await.unwrap(), vec!["laptop"]);
+        proxy.revoke_all().await.unwrap();
+        assert!(!auth.is_authorized("k1"));
+    }
+
+    #[tokio::test]
+    async fn emits_authorization_requested() {
+        let auth = Arc::new(Authorizer::load("/nonexistent/keys"));
+        let (conn, proxy) = start_test_service(auth).await;
+        let mut requests = proxy.receive_authorization_requested().await.unwrap();
+
+        let iface = conn
+            .object_server()
+            .interface::<_, super::BridgeService>("/org/mobileos/Bridge")
+            .await
+            .unwrap();
+        super::BridgeService::authorization_requested(iface.signal_emitter(), "laptop", "AA:BB")
+            .await
+            .unwrap();
+
+        let signal = requests.next().await.unwrap();
+        let args = signal.args().unwrap();
+        assert_eq!(args.host(), &"laptop");
+        assert_eq!(args.fingerprint(), &"AA:BB");
+    }
+}
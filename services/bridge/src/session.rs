@@ -0,0 +1,792 @@
+// ABOUTME: Bridge session handling: have the host prove its key and authorize it, then run one command.
+// ABOUTME: Implements shell, file push/pull, log streaming, app install/launch, and port forwarding.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context as _};
+use mosb::keys::{self, fingerprint};
+use mosb::protocol::{parse_header, Message, CHUNK_SIZE, HEADER_LEN};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tracing::{info, warn};
+
+use crate::account::Account;
+use crate::auth::Authorizer;
+use crate::launcher::Launcher;
+
+/// Directory holding the per-service journal files written by initd.
+const LOG_DIR: &str = "/run/mos/log";
+
+/// How long an unknown host waits for the user to answer the prompt.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often `logs -f` checks for new journal output.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Arc<Mutex<Box<dyn AsyncWrite + Send + Unpin>>>;
+
+pub struct Context {
+    pub auth: Arc<Authorizer>,
+    /// Asks the device UI to show an authorization prompt for `(host, fingerprint)`.
+    pub prompts: mpsc::UnboundedSender<(String, String)>,
+    /// Starts installed apps; `install` puts binaries in its apps directory.
+    pub launcher: Launcher,
+    /// Who shells and file transfers run as; `None` runs them as the bridge itself.
+    pub account: Option<Account>,
+}
+
+/// Serve sessions on one connection until it closes. TCP connections carry a single
+/// session; a serial link carries sessions back to back.
+pub async fn serve(reader: Reader, writer: Box<dyn AsyncWrite + Send + Unpin>, ctx: Arc<Context>) {
+    let writer: Writer = Arc::new(Mutex::new(writer));
+    let (tx, mut rx) = mpsc::channel(16);
+    // Frames are read in their own task so commands can wait on several things at once
+    // without cancelling a half-read frame.
+    let reader_task = tokio::spawn(read_frames(reader, tx));
+
+    loop {
+        match session(&mut rx, &writer, &ctx).await {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                warn!(error = %e, "bridge session failed");
+                let _ = send(&writer, &Message::Error(format!("{e:#}"))).await;
+            }
+        }
+    }
+
+    reader_task.abort();
+}
+
+async fn read_frames(mut reader: Reader, tx: mpsc::Sender<Message>) {
+    let mut header = [0u8; HEADER_LEN];
+    loop {
+        if reader.read_exact(&mut header).await.is_err() {
+            return;
+        }
+        let Ok((tag, len)) = parse_header(&header) else {
+            warn!("invalid frame header, closing connection");
+            return;
+        };
+        let mut payload = vec![0u8; len];
+        if reader.read_exact(&mut payload).await.is_err() {
+            return;
+        }
+        match Message::decode(tag, &payload) {
+            Ok(message) => {
+                if tx.send(message).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => warn!(error = %e, "dropping malformed frame"),
+        }
+    }
+}
+
+async fn send(writer: &Writer, message: &Message) -> std::io::Result<()> {
+    let mut writer = writer.lock().await;
+    writer.write_all(&message.encode()).await?;
+    writer.flush().await
+}
+
+/// Run one session. Returns false once the connection is gone.
+async fn session(
+    rx: &mut mpsc::Receiver<Message>,
+    writer: &Writer,
+    ctx: &Context,
+) -> anyhow::Result<bool> {
+    // Skip frames left over from the previous session on a serial link
+    let (host, key) = loop {
+        match rx.recv().await {
+            Some(Message::Hello { host, public_key }) => break (host, public_key),
+            Some(_) => continue,
+            None => return Ok(false),
+        }
+    };
+
+    // A key sent in the clear proves nothing; the host must sign a nonce only this
+    // session has seen
+    let nonce = keys::nonce().context("failed to make a challenge")?;
+    send(writer, &Message::Challenge(nonce.to_vec())).await?;
+    let proven = match rx.recv().await {
+        Some(Message::Proof(signature)) => keys::verify(&key, &nonce, &signature),
+        Some(_) => false,
+        None => return Ok(false),
+    };
+    if !proven {
+        info!(host = %host, "bridge host failed to prove its key");
+        send(writer, &Message::Error("key proof failed".to_string())).await?;
+        return Ok(true);
+    }
+
+    if !authorize(ctx, &host, &key).await {
+        info!(host = %host, "bridge host not authorized");
+        send(writer, &Message::Error("not authorized on device".to_string())).await?;
+        return Ok(true);
+    }
+    send(writer, &Message::Ready).await?;
+
+    let Some(command) = rx.recv().await else {
+        return Ok(false);
+    };
+    info!(host = %host, command = ?command_name(&command), "bridge command");
+
+    match command {
+        Message::Shell { command } => shell(&command, ctx.account.as_ref(), rx, writer).await,
+        Message::Push { path, mode } => {
+            receive_file(PathBuf::from(path), mode, ctx.account.as_ref(), rx).await?;
+            send(writer, &Message::Done).await?;
+            Ok(true)
+        }
+        Message::Pull { path } => {
+            send_file(PathBuf::from(path), ctx.account.as_ref(), writer).await?;
+            Ok(true)
+        }
+        Message::Logs { service, follow } => logs(&service, follow, rx, writer).await,
+        Message::Install { name } => {
+            // Apps go in the bridge's own apps directory, so they are written as the bridge
+            let path = install_path(ctx.launcher.apps_dir(), &name)?;
+            receive_file(path.clone(), 0o755, None, rx).await?;
+            info!(app = %name, path = %path.display(), "app installed");
+            send(writer, &Message::Done).await?;
+            Ok(true)
+        }
+        Message::Forward { port } => forward(port, rx, writer).await,
+        Message::Launch { name } => launch(&ctx.launcher, &name, rx, writer).await,
+        other => bail!("unexpected message {other:?}"),
+    }
+}
+
+async fn authorize(ctx: &Context, host: &str, key: &str) -> bool {
+    if ctx.auth.is_authorized(key) {
+        return true;
+    }
+
+    let fp = fingerprint(key);
+    let decision = ctx.auth.request(&fp);
+    if ctx.prompts.send((host.to_string(), fp.clone())).is_err() {
+        return false;
+    }
+
+    match tokio::time::timeout(AUTH_TIMEOUT, decision).await {
+        Ok(Ok(decision)) if decision.allow => {
+            if decision.remember {
+                ctx.auth.remember(key, host);
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+fn command_name(message: &Message) -> &'static str {
+    match message {
+        Message::Shell { .. } => "shell",
+        Message::Push { .. } => "push",
+        Message::Pull { .. } => "pull",
+        Message::Logs { .. } => "logs",
+        Message::Install { .. } => "install",
+        Message::Forward { .. } => "forward",
+        Message::Launch { .. } => "launch",
+        _ => "invalid",
+    }
+}
+
+async fn shell(
+    command: &str,
+    account: Option<&Account>,
+    rx: &mut mpsc::Receiver<Message>,
+    writer: &Writer,
+) -> anyhow::Result<bool> {
+    let mut cmd = tokio::process::Command::new("/bin/sh");
+    if command.is_empty() {
+        cmd.arg("-i");
+    } else {
+        cmd.args(["-c", command]);
+    }
+    if let Some(account) = account {
+        account.apply(&mut cmd);
+    }
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to start shell")?;
+
+    let stdout = tokio::spawn(copy_out(child.stdout.take().unwrap(), writer.clone(), Message::Data));
+    let stderr = tokio::spawn(copy_out(child.stderr.take().unwrap(), writer.clone(), Message::Stderr));
+    let mut stdin = child.stdin.take();
+
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status?,
+            message = rx.recv() => match message {
+                Some(Message::Data(data)) => {
+                    if let Some(ref mut input) = stdin {
+                        let _ = input.write_all(&data).await;
+                    }
+                }
+                Some(Message::Eof) => stdin = None,
+                Some(_) => {}
+                None => return Ok(false),
+            },
+        }
+    };
+
+    let _ = stdout.await;
+    let _ = stderr.await;
+    send(writer, &Message::Exit(status.code().unwrap_or(-1))).await?;
+    Ok(true)
+}
+
+async fn copy_out<R: AsyncRead + Unpin>(mut pipe: R, writer: Writer, wrap: fn(Vec<u8>) -> Message) {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        match pipe.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => {
+                if send(&writer, &wrap(buf[..n].to_vec())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Receive `Data` frames into `path`, replacing it atomically once `Eof` arrives.
+/// Run the file operation `f` as `account`, or as the bridge itself without one.
+async fn file_op<T, F>(account: Option<&Account>, f: F) -> anyhow::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> std::io::Result<T> + Send + 'static,
+{
+    match account {
+        Some(account) => account.run(f).await,
+        None => Ok(tokio::task::spawn_blocking(f).await??),
+    }
+}
+
+async fn receive_file(
+    path: PathBuf,
+    mode: u32,
+    account: Option<&Account>,
+    rx: &mut mpsc::Receiver<Message>,
+) -> anyhow::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".mosb-part");
+    let tmp = PathBuf::from(tmp);
+
+    let created = {
+        let (path, tmp) = (path.clone(), tmp.clone());
+        file_op(account, move || {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::File::create(tmp)
+        })
+        .await
+    };
+    let mut file = tokio::fs::File::from_std(
+        created.with_context(|| format!("failed to create {}", path.display()))?,
+    );
+
+    loop {
+        match rx.recv().await {
+            Some(Message::Data(data)) => file.write_all(&data).await?,
+            Some(Message::Eof) => break,
+            Some(other) => {
+                let _ = tokio::fs::remove_file(&tmp).await;
+                bail!("unexpected message {other:?} during transfer");
+            }
+            None => {
+                let _ = tokio::fs::remove_file(&tmp).await;
+                bail!("connection closed during transfer");
+            }
+        }
+    }
+
+    file.sync_all().await?;
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(std::fs::Permissions::from_mode(mode))
+        .await?;
+    drop(file);
+    let target = path.clone();
+    file_op(account, move || std::fs::rename(tmp, target))
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+async fn send_file(
+    path: PathBuf,
+    account: Option<&Account>,
+    writer: &Writer,
+) -> anyhow::Result<()> {
+    let opened = {
+        let path = path.clone();
+        file_op(account, move || std::fs::File::open(path)).await
+    };
+    let mut file = tokio::fs::File::from_std(
+        opened.with_context(|| format!("failed to open {}", path.display()))?,
+    );
+
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        send(writer, &Message::Data(buf[..n].to_vec())).await?;
+    }
+    send(writer, &Message::Eof).await?;
+    Ok(())
+}
+
+async fn logs(
+    service: &str,
+    follow: bool,
+    rx: &mut mpsc::Receiver<Message>,
+    writer: &Writer,
+) -> anyhow::Result<bool> {
+    if !is_plain_name(service) {
+        bail!("invalid service name '{service}'");
+    }
+    let path = Path::new(LOG_DIR).join(format!("{service}.log"));
+
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .with_context(|| format!("no journal for service '{service}'"))?;
+    let mut offset = 0u64;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n > 0 {
+            offset += n as u64;
+            send(writer, &Message::Data(buf[..n].to_vec())).await?;
+            continue;
+        }
+        if !follow {
+            break;
+        }
+
+        tokio::select! {
+            message = rx.recv() => match message {
+                Some(Message::Eof) => break,
+                Some(_) => {}
+                None => return Ok(false),
+            },
+            _ = tokio::time::sleep(FOLLOW_INTERVAL) => {
+                // initd rotates the journal by renaming it; start over on the fresh file
+                let rotated = tokio::fs::metadata(&path).await.map(|m| m.len() < offset).unwrap_or(false);
+                if rotated && let Ok(fresh) = tokio::fs::File::open(&path).await {
+                    file = fresh;
+                    offset = 0;
+                }
+            }
+        }
+    }
+
+    send(writer, &Message::Eof).await?;
+    Ok(true)
+}
+
+async fn forward(port: u16, rx: &mut mpsc::Receiver<Message>, writer: &Writer) -> anyhow::Result<bool> {
+    let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("failed to connect to device port {port}"))?;
+    send(writer, &Message::Done).await?;
+
+    let (mut from_port, mut to_port) = stream.into_split();
+    let upstream = writer.clone();
+    let mut relay = tokio::spawn(async move {
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        loop {
+            match from_port.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if send(&upstream, &Message::Data(buf[..n].to_vec())).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let connected = loop {
+        tokio::select! {
+            _ = &mut relay => break true,
+            message = rx.recv() => match message {
+                Some(Message::Data(data)) => {
+                    if to_port.write_all(&data).await.is_err() {
+                        break true;
+                    }
+                }
+                Some(Message::Eof) => {
+                    let _ = to_port.shutdown().await;
+                }
+                Some(_) => {}
+                None => break false,
+            },
+        }
+    };
+
+    relay.abort();
+    if connected {
+        send(writer, &Message::Eof).await?;
+    }
+    Ok(connected)
+}
+
+/// Restart an app and relay its output. The host detaching leaves the app running.
+async fn launch(
+    launcher: &Launcher,
+    name: &str,
+    rx: &mut mpsc::Receiver<Message>,
+    writer: &Writer,
+) -> anyhow::Result<bool> {
+    if !is_plain_name(name) {
+        bail!("invalid app name '{name}'");
+    }
+    let mut output = launcher.launch(name).await?;
+
+    loop {
+        tokio::select! {
+            event = output.recv() => match event {
+                Ok(Message::Exit(code)) => {
+                    send(writer, &Message::Exit(code)).await?;
+                    return Ok(true);
+                }
+                Ok(message) => send(writer, &message).await?,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(app = %name, skipped, "dropped app output for slow host");
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(true),
+            },
+            message = rx.recv() => match message {
+                Some(Message::Eof) => {
+                    send(writer, &Message::Eof).await?;
+                    return Ok(true);
+                }
+                Some(_) => {}
+                None => return Ok(false),
+            },
+        }
+    }
+}
+
+fn install_path(apps_dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    if !is_plain_name(name) {
+        bail!("invalid app name '{name}'");
+    }
+    Ok(apps_dir.join(name).join(name))
+}
+
+/// Names used as path components must not escape their directory.
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && name != "."
+        && name != ".."
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mosb::keys::HostKey;
+    use mosb::protocol::{read_message, write_message};
+    use tokio::io::DuplexStream;
+
+    struct Device {
+        host: DuplexStream,
+        /// Authorized as "laptop".
+        trusted: HostKey,
+        auth: Arc<Authorizer>,
+        prompts: mpsc::UnboundedReceiver<(String, String)>,
+        _dir: tempfile::TempDir,
+        apps_dir: PathBuf,
+    }
+
+    fn start_device() -> Device {
+        start_device_as(None)
+    }
+
+    /// A device whose shells and transfers run as `account`.
+    fn start_device_as(account: Option<Account>) -> Device {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = Arc::new(Authorizer::load(dir.path().join("keys")));
+        let trusted = HostKey::generate().unwrap();
+        auth.remember(&trusted.public_key(), "laptop");
+        let (prompt_tx, prompts) = mpsc::unbounded_channel();
+        let apps_dir = dir.path().join("apps");
+
+        let ctx = Arc::new(Context {
+            auth: auth.clone(),
+            prompts: prompt_tx,
+            launcher: Launcher::new(&apps_dir),
+            account,
+        });
+        let (host, device) = tokio::io::duplex(CHUNK_SIZE * 4);
+        let (r, w) = tokio::io::split(device);
+        tokio::spawn(serve(Box::new(r), Box::new(w), ctx));
+
+        Device {
+            host,
+            trusted,
+            auth,
+            prompts,
+            _dir: dir,
+            apps_dir,
+        }
+    }
+
+    async fn write(stream: &mut DuplexStream, message: Message) {
+        let mut frame = Vec::new();
+        write_message(&mut frame, &message).unwrap();
+        stream.write_all(&frame).await.unwrap();
+    }
+
+    async fn read(stream: &mut DuplexStream) -> Message {
+        let mut header = [0u8; HEADER_LEN];
+        stream.read_exact(&mut header).await.unwrap();
+        let (_, len) = parse_header(&header).unwrap();
+        let mut frame = header.to_vec();
+        frame.resize(HEADER_LEN + len, 0);
+        stream.read_exact(&mut frame[HEADER_LEN..]).await.unwrap();
+        read_message(&mut frame.as_slice()).unwrap().unwrap()
+    }
+
+    /// Introduce the host holding `key` and answer the device's challenge.
+    async fn hello(stream: &mut DuplexStream, key: &HostKey) {
+        write(
+            stream,
+            Message::Hello {
+                host: "laptop".to_string(),
+                public_key: key.public_key(),
+            },
+        )
+        .await;
+        let Message::Challenge(nonce) = read(stream).await else {
+            panic!("no challenge");
+        };
+        write(stream, Message::Proof(key.sign(&nonce))).await;
+    }
+
+    #[tokio::test]
+    async fn shell_runs_command_and_reports_exit() {
+        let mut dev = start_device();
+        hello(&mut dev.host, &dev.trusted).await;
+        assert_eq!(read(&mut dev.host).await, Message::Ready);
+
+        write(
+            &mut dev.host,
+            Message::Shell {
+                command: "echo hi; exit 3".to_string(),
+            },
+        )
+        .await;
+        assert_eq!(read(&mut dev.host).await, Message::Data(b"hi\n".to_vec()));
+        assert_eq!(read(&mut dev.host).await, Message::Exit(3));
+    }
+
+    #[tokio::test]
+    async fn unknown_host_waits_for_prompt_answer() {
+        let mut dev = start_device();
+        let stranger = HostKey::generate().unwrap();
+        hello(&mut dev.host, &stranger).await;
+
+        let (host, fp) = dev.prompts.recv().await.unwrap();
+        assert_eq!(host, "laptop");
+        assert_eq!(fp, fingerprint(&stranger.public_key()));
+        dev.auth.respond(
+            &fp,
+            crate::auth::Decision {
+                allow: false,
+                remember: false,
+            },
+        );
+
+        assert!(matches!(read(&mut dev.host).await, Message::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn a_known_key_without_its_private_half_is_refused() {
+        let mut dev = start_device();
+        write(
+            &mut dev.host,
+            Message::Hello {
+                host: "laptop".to_string(),
+                public_key: dev.trusted.public_key(),
+            },
+        )
+        .await;
+        assert!(matches!(read(&mut dev.host).await, Message::Challenge(_)));
+        // A signature over another nonce, as an eavesdropper would have from an
+        // earlier session
+        let replayed = dev.trusted.sign(&keys::nonce().unwrap());
+        write(&mut dev.host, Message::Proof(replayed)).await;
+        assert!(matches!(read(&mut dev.host).await, Message::Error(_)));
+        assert!(dev.prompts.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn sessions_run_back_to_back_on_one_link() {
+        let mut dev = start_device();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pushed.txt");
+
+        hello(&mut dev.host, &dev.trusted).await;
+        assert_eq!(read(&mut dev.host).await, Message::Ready);
+        write(
+            &mut dev.host,
+            Message::Push {
+                path: path.to_string_lossy().into_owned(),
+                mode: 0o600,
+            },
+        )
+        .await;
+        write(&mut dev.host, Message::Data(b"payload".to_vec())).await;
+        write(&mut dev.host, Message::Eof).await;
+        assert_eq!(read(&mut dev.host).await, Message::Done);
+        assert_eq!(std::fs::read(&path).unwrap(), b"payload");
+
+        hello(&mut dev.host, &dev.trusted).await;
+        assert_eq!(read(&mut dev.host).await, Message::Ready);
+        write(
+            &mut dev.host,
+            Message::Pull {
+                path: path.to_string_lossy().into_owned(),
+            },
+        )
+        .await;
+        assert_eq!(read(&mut dev.host).await, Message::Data(b"payload".to_vec()));
+        assert_eq!(read(&mut dev.host).await, Message::Eof);
+    }
+
+    #[tokio::test]
+    async fn install_places_executable_in_apps_dir() {
+        let mut dev = start_device();
+        hello(&mut dev.host, &dev.trusted).await;
+        assert_eq!(read(&mut dev.host).await, Message::Ready);
+
+        write(
+            &mut dev.host,
+            Message::Install {
+                name: "hello".to_string(),
+            },
+        )
+        .await;
+        write(&mut dev.host, Message::Data(b"#!/bin/sh\n".to_vec())).await;
+        write(&mut dev.host, Message::Eof).await;
+        assert_eq!(read(&mut dev.host).await, Message::Done);
+
+        use std::os::unix::fs::PermissionsExt;
+        let meta = std::fs::metadata(dev.apps_dir.join("hello/hello")).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o755);
+    }
+
+    #[tokio::test]
+    async fn install_then_launch_streams_app_output() {
+        let mut dev = start_device();
+        hello(&mut dev.host, &dev.trusted).await;
+        assert_eq!(read(&mut dev.host).await, Message::Ready);
+        write(
+            &mut dev.host,
+            Message::Install {
+                name: "greeter".to_string(),
+            },
+        )
+        .await;
+        write(&mut dev.host, Message::Data(b"#!/bin/sh\necho hi\n".to_vec())).await;
+        write(&mut dev.host, Message::Eof).await;
+        assert_eq!(read(&mut dev.host).await, Message::Done);
+
+        hello(&mut dev.host, &dev.trusted).await;
+        assert_eq!(read(&mut dev.host).await, Message::Ready);
+        write(
+            &mut dev.host,
+            Message::Launch {
+                name: "greeter".to_string(),
+            },
+        )
+        .await;
+        assert_eq!(read(&mut dev.host).await, Message::Data(b"hi\n".to_vec()));
+        assert_eq!(read(&mut dev.host).await, Message::Exit(0));
+    }
+
+    #[tokio::test]
+    async fn pull_of_missing_file_is_an_error() {
+        let mut dev = start_device();
+        hello(&mut dev.host, &dev.trusted).await;
+        assert_eq!(read(&mut dev.host).await, Message::Ready);
+
+        write(
+            &mut dev.host,
+            Message::Pull {
+                path: "/nonexistent/file".to_string(),
+            },
+        )
+        .await;
+        assert!(matches!(read(&mut dev.host).await, Message::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn transfers_cannot_touch_what_the_account_cannot() {
+        let nobody = Account {
+            name: "nobody".to_string(),
+            uid: 65534,
+            gid: 65534,
+            home: PathBuf::from("/"),
+        };
+        let mut dev = start_device_as(Some(nobody));
+        // Owned by whoever runs the test, and closed to everyone else
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let secret = dir.path().join("shadow");
+        std::fs::write(&secret, b"root:hash").unwrap();
+        std::fs::set_permissions(&secret, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        hello(&mut dev.host, &dev.trusted).await;
+        assert_eq!(read(&mut dev.host).await, Message::Ready);
+        write(
+            &mut dev.host,
+            Message::Push {
+                path: secret.to_string_lossy().into_owned(),
+                mode: 0o644,
+            },
+        )
+        .await;
+        write(&mut dev.host, Message::Data(b"root::".to_vec())).await;
+        write(&mut dev.host, Message::Eof).await;
+        assert!(matches!(read(&mut dev.host).await, Message::Error(_)));
+        assert_eq!(std::fs::read(&secret).unwrap(), b"root:hash");
+
+        hello(&mut dev.host, &dev.trusted).await;
+        assert_eq!(read(&mut dev.host).await, Message::Ready);
+        write(
+            &mut dev.host,
+            Message::Pull {
+                path: secret.to_string_lossy().into_owned(),
+            },
+        )
+        .await;
+        assert!(matches!(read(&mut dev.host).await, Message::Error(_)));
+    }
+
+    #[test]
+    fn names_cannot_escape_their_directory() {
+        assert!(is_plain_name("mos-dialer"));
+        assert!(!is_plain_name("../etc"));
+        assert!(!is_plain_name(".."));
+        assert!(!is_plain_name("a/b"));
+        assert!(install_path(Path::new("/data/apps"), "x/../y").is_err());
+    }
+}
@@ -0,0 +1,306 @@
+// ABOUTME: What the indicator LED shows: pending notifications blink in the app's colour,
+// ABOUTME: charging and a full battery glow steadily. Colours come from config and user overrides.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+pub const CONFIG_PATH: &str = "/etc/mos/leds.toml";
+
+/// Colours the user picked per app, over the ones shipped in the config.
+pub const USER_COLORS_PATH: &str = "/data/leds/colors.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::new(0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    /// Parse "#rrggbb".
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = s
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.is_ascii())
+            .with_context(|| format!("invalid colour {s:?}, expected #rrggbb"))?;
+        let component = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .with_context(|| format!("invalid colour {s:?}, expected #rrggbb"))
+        };
+        Ok(Self::new(component(0)?, component(2)?, component(4)?))
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        color.to_string()
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Off,
+    Solid(Color),
+    Blink {
+        color: Color,
+        on_ms: u32,
+        off_ms: u32,
+    },
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::Solid(color) => write!(f, "solid {color}"),
+            Self::Blink {
+                color,
+                on_ms,
+                off_ms,
+            } => write!(f, "blink {color} {on_ms} {off_ms}"),
+        }
+    }
+}
+
+/// /etc/mos/leds.toml.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub charging: Color,
+    pub full: Color,
+    /// For apps without a colour of their own.
+    pub notification: Color,
+    pub blink_on_ms: u32,
+    pub blink_off_ms: u32,
+    pub apps: BTreeMap<String, Color>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            charging: Color::new(0xff, 0x00, 0x00),
+            full: Color::new(0x00, 0xff, 0x00),
+            notification: Color::new(0xff, 0xff, 0xff),
+            blink_on_ms: 500,
+            blink_off_ms: 2500,
+            apps: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config, falling back to the defaults when there is none.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                toml::from_str(&content).with_context(|| format!("invalid {}", path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UserColors {
+    #[serde(default)]
+    apps: BTreeMap<String, Color>,
+}
+
+pub struct Indicator {
+    config: Config,
+    user_path: PathBuf,
+    user: BTreeMap<String, Color>,
+    /// Apps with pending notifications, most recent last.
+    pending: Vec<String>,
+    charging: bool,
+    battery_level: u8,
+}
+
+impl Indicator {
+    /// Start from `config`, with the user's colours stored at `user_path`.
+    pub fn new(config: Config, user_path: PathBuf) -> Self {
+        let user = load_user_colors(&user_path).unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "ignoring stored LED colours");
+            BTreeMap::new()
+        });
+        Self {
+            config,
+            user_path,
+            user,
+            pending: Vec::new(),
+            charging: false,
+            battery_level: 0,
+        }
+    }
+
+    pub fn app_color(&self, app: &str) -> Color {
+        self.user
+            .get(app)
+            .or_else(|| self.config.apps.get(app))
+            .copied()
+            .unwrap_or(self.config.notification)
+    }
+
+    /// Give `app` its own colour and store it.
+    pub fn set_app_color(&mut self, app: &str, color: Color) -> Result<()> {
+        if app.is_empty() {
+            bail!("no app given");
+        }
+        let mut user = self.user.clone();
+        user.insert(app.to_string(), color);
+        save_user_colors(&self.user_path, &user)?;
+        self.user = user;
+        Ok(())
+    }
+
+    pub fn set_pending(&mut self, apps: Vec<String>) {
+        self.pending = apps;
+    }
+
+    pub fn set_power(&mut self, charging: bool, battery_level: u8) {
+        self.charging = charging;
+        self.battery_level = battery_level;
+    }
+
+    /// Notifications win over the charging state, so they aren't missed while plugged in.
+    pub fn pattern(&self) -> Pattern {
+        if let Some(app) = self.pending.last() {
+            Pattern::Blink {
+                color: self.app_color(app),
+                on_ms: self.config.blink_on_ms,
+                off_ms: self.config.blink_off_ms,
+            }
+        } else if self.charging && self.battery_level >= 100 {
+            Pattern::Solid(self.config.full)
+        } else if self.charging {
+            Pattern::Solid(self.config.charging)
+        } else {
+            Pattern::Off
+        }
+    }
+}
+
+fn load_user_colors(path: &Path) -> Result<BTreeMap<String, Color>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let colors: UserColors =
+        toml::from_str(&content).with_context(|| format!("invalid {}", path.display()))?;
+    Ok(colors.apps)
+}
+
+fn save_user_colors(path: &Path, apps: &BTreeMap<String, Color>) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let colors = UserColors { apps: apps.clone() };
+    let content = toml::to_string(&colors).context("failed to serialize LED colours")?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content).with_context(|| format!("failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colours() {
+        let color: Color = "#ff8000".parse().unwrap();
+        assert_eq!(color, Color::new(255, 128, 0));
+        assert_eq!(color.to_string(), "#ff8000");
+        for bad in ["ff8000", "#ff80", "#ff800g", "#ff80000", "#ffé00"] {
+            assert!(bad.parse::<Color>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn notifications_win_over_charging() {
+        let dir = tempfile::tempdir().unwrap();
+        let config: Config = toml::from_str(
+            "notification = \"#ffffff\"\nblink_on_ms = 200\n\n[apps]\nmessages = \"#00ff00\"\n",
+        )
+        .unwrap();
+        let mut indicator = Indicator::new(config, dir.path().join("colors.toml"));
+        assert_eq!(indicator.pattern(), Pattern::Off);
+
+        indicator.set_power(true, 40);
+        assert_eq!(indicator.pattern(), Pattern::Solid(Color::new(255, 0, 0)));
+        indicator.set_power(true, 100);
+        assert_eq!(indicator.pattern(), Pattern::Solid(Color::new(0, 255, 0)));
+
+        indicator.set_pending(vec!["dialer".to_string(), "messages".to_string()]);
+        assert_eq!(
+            indicator.pattern(),
+            Pattern::Blink {
+                color: Color::new(0, 255, 0),
+                on_ms: 200,
+                off_ms: 2500,
+            }
+        );
+        indicator.set_pending(vec!["messages".to_string(), "dialer".to_string()]);
+        assert_eq!(indicator.pattern().to_string(), "blink #ffffff 200 2500");
+
+        indicator.set_pending(Vec::new());
+        indicator.set_power(false, 100);
+        assert_eq!(indicator.pattern(), Pattern::Off);
+    }
+
+    #[test]
+    fn user_colours_are_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("leds/colors.toml");
+        let mut config = Config::default();
+        config
+            .apps
+            .insert("messages".to_string(), Color::new(0, 255, 0));
+
+        let mut indicator = Indicator::new(config.clone(), path.clone());
+        indicator
+            .set_app_color("messages", Color::new(0, 0, 255))
+            .unwrap();
+        indicator
+            .set_app_color("chat", Color::new(255, 0, 255))
+            .unwrap();
+        assert!(indicator.set_app_color("", Color::BLACK).is_err());
+
+        let indicator = Indicator::new(config, path);
+        assert_eq!(indicator.app_color("messages"), Color::new(0, 0, 255));
+        assert_eq!(indicator.app_color("chat"), Color::new(255, 0, 255));
+        assert_eq!(indicator.app_color("dialer"), Color::new(255, 255, 255));
+    }
+}
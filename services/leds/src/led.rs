@@ -0,0 +1,268 @@
+// ABOUTME: Indicator LEDs under /sys/class/leds: discovery by colour, and writing a pattern.
+// ABOUTME: Handles single-colour LEDs and multicolor class LEDs, blinking with the timer trigger.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::indicator::{Color, Pattern};
+
+pub const LEDS_DIR: &str = "/sys/class/leds";
+
+/// LED functions that aren't indicators and must be left alone.
+const NOT_INDICATORS: &[&str] = &[
+    "backlight",
+    "kbd_backlight",
+    "flash",
+    "torch",
+    "lcd-backlight",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    Red,
+    Green,
+    Blue,
+    White,
+}
+
+impl Channel {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "red" => Some(Self::Red),
+            "green" => Some(Self::Green),
+            "blue" => Some(Self::Blue),
+            "white" => Some(Self::White),
+            _ => None,
+        }
+    }
+
+    /// How much of `color` this channel shows, 0-255. White follows the brightest component.
+    fn level(self, color: Color) -> u8 {
+        match self {
+            Self::Red => color.r,
+            Self::Green => color.g,
+            Self::Blue => color.b,
+            Self::White => color.r.max(color.g).max(color.b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    Single(Channel),
+    /// A multicolor class LED, with its channels in multi_index order.
+    Multi(Vec<Channel>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Led {
+    path: PathBuf,
+    max_brightness: u32,
+    kind: Kind,
+}
+
+impl Led {
+    /// The sysfs name, e.g. "red:status" or "lp5562:rgb:indicator".
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Show `pattern`. Blinking is left to the kernel's timer trigger, which blinks at the
+    /// brightness set before it is selected.
+    pub fn apply(&self, pattern: &Pattern) -> Result<()> {
+        self.write("trigger", "none")?;
+        let (color, blink) = match *pattern {
+            Pattern::Off => return self.write("brightness", "0"),
+            Pattern::Solid(color) => (color, None),
+            Pattern::Blink {
+                color,
+                on_ms,
+                off_ms,
+            } => (color, Some((on_ms, off_ms))),
+        };
+
+        let brightness = match &self.kind {
+            Kind::Single(channel) => self.scale(channel.level(color)),
+            Kind::Multi(channels) => {
+                let intensity: Vec<String> = channels
+                    .iter()
+                    .map(|channel| self.scale(channel.level(color)).to_string())
+                    .collect();
+                self.write("multi_intensity", &intensity.join(" "))?;
+                if color == Color::BLACK {
+                    0
+                } else {
+                    self.max_brightness
+                }
+            }
+        };
+        self.write("brightness", &brightness.to_string())?;
+
+        if let Some((on_ms, off_ms)) = blink
+            && brightness > 0
+        {
+            self.write("trigger", "timer")?;
+            self.write("delay_on", &on_ms.to_string())?;
+            self.write("delay_off", &off_ms.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn scale(&self, level: u8) -> u32 {
+        (u32::from(level) * self.max_brightness).div_ceil(255)
+    }
+
+    fn write(&self, attribute: &str, value: &str) -> Result<()> {
+        let path = self.path.join(attribute);
+        std::fs::write(&path, value).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// The indicator LEDs in `dir`, sorted by name. LED names follow "device:color:function";
+/// those without a known colour, and backlights and camera flashes, are skipped. `colors`
+/// gives the colour of LEDs by name for devices whose names don't say.
+pub fn discover(dir: &Path, colors: &HashMap<String, String>) -> Vec<Led> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut leds: Vec<Led> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| probe(&path, colors))
+        .collect();
+    leds.sort_by_key(Led::name);
+    leds
+}
+
+fn probe(path: &Path, colors: &HashMap<String, String>) -> Option<Led> {
+    let name = path.file_name()?.to_str()?;
+    let parts: Vec<&str> = name.split(':').collect();
+    let function = match parts.as_slice() {
+        [_, _, function] | [_, function] => *function,
+        _ => "",
+    };
+    if NOT_INDICATORS.contains(&function) {
+        return None;
+    }
+
+    let kind = match std::fs::read_to_string(path.join("multi_index")) {
+        Ok(index) => Kind::Multi(
+            index
+                .split_whitespace()
+                .map(Channel::parse)
+                .collect::<Option<Vec<_>>>()?,
+        ),
+        Err(_) => Kind::Single(match colors.get(name) {
+            Some(color) => Channel::parse(color)?,
+            None => parts.iter().find_map(|part| Channel::parse(part))?,
+        }),
+    };
+    let max_brightness = std::fs::read_to_string(path.join("max_brightness"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Led {
+        path: path.to_path_buf(),
+        max_brightness,
+        kind,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_led(dir: &Path, name: &str, max: u32, multi_index: Option<&str>) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("max_brightness"), format!("{max}\n")).unwrap();
+        std::fs::write(path.join("brightness"), "0\n").unwrap();
+        if let Some(index) = multi_index {
+            std::fs::write(path.join("multi_index"), format!("{index}\n")).unwrap();
+        }
+        path
+    }
+
+    fn read(path: &Path, attribute: &str) -> String {
+        std::fs::read_to_string(path.join(attribute)).unwrap()
+    }
+
+    #[test]
+    fn discovers_indicators_only() {
+        let dir = tempfile::tempdir().unwrap();
+        fake_led(dir.path(), "red:status", 255, None);
+        fake_led(
+            dir.path(),
+            "lp5562:rgb:indicator",
+            255,
+            Some("red green blue"),
+        );
+        fake_led(dir.path(), "white:flash", 255, None);
+        fake_led(dir.path(), "lcd-backlight", 255, None);
+        fake_led(dir.path(), "mmc0::", 1, None);
+
+        let names: Vec<String> = discover(dir.path(), &HashMap::new())
+            .iter()
+            .map(Led::name)
+            .collect();
+        assert_eq!(names, ["lp5562:rgb:indicator", "red:status"]);
+        assert!(discover(&dir.path().join("missing"), &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn device_config_names_colours() {
+        let dir = tempfile::tempdir().unwrap();
+        fake_led(dir.path(), "lp5523:channel0", 255, None);
+        fake_led(dir.path(), "lp5523:channel1", 255, None);
+
+        let colors = HashMap::from([("lp5523:channel0".to_string(), "red".to_string())]);
+        let leds = discover(dir.path(), &colors);
+        assert_eq!(leds.len(), 1);
+        assert_eq!(leds[0].name(), "lp5523:channel0");
+        assert_eq!(leds[0].kind, Kind::Single(Channel::Red));
+    }
+
+    #[test]
+    fn writes_solid_and_blinking_colours() {
+        let dir = tempfile::tempdir().unwrap();
+        let red = fake_led(dir.path(), "red:status", 100, None);
+        let rgb = fake_led(dir.path(), "rgb:status", 255, Some("red green blue"));
+        let leds = discover(dir.path(), &HashMap::new());
+
+        let orange = Color::new(255, 128, 0);
+        for led in &leds {
+            led.apply(&Pattern::Solid(orange)).unwrap();
+        }
+        assert_eq!(read(&red, "brightness"), "100");
+        assert_eq!(read(&red, "trigger"), "none");
+        assert_eq!(read(&rgb, "multi_intensity"), "255 128 0");
+        assert_eq!(read(&rgb, "brightness"), "255");
+
+        let blink = Pattern::Blink {
+            color: Color::new(0, 0, 255),
+            on_ms: 500,
+            off_ms: 2500,
+        };
+        for led in &leds {
+            led.apply(&blink).unwrap();
+        }
+        // Blue isn't something the red LED can show
+        assert_eq!(read(&red, "brightness"), "0");
+        assert_eq!(read(&red, "trigger"), "none");
+        assert_eq!(read(&rgb, "multi_intensity"), "0 0 255");
+        assert_eq!(read(&rgb, "trigger"), "timer");
+        assert_eq!(read(&rgb, "delay_on"), "500");
+        assert_eq!(read(&rgb, "delay_off"), "2500");
+
+        for led in &leds {
+            led.apply(&Pattern::Off).unwrap();
+        }
+        assert_eq!(read(&rgb, "brightness"), "0");
+        assert_eq!(read(&rgb, "trigger"), "none");
+    }
+}
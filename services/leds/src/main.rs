@@ -0,0 +1,271 @@
+// ABOUTME: LED D-Bus daemon for MobileOS.
+// ABOUTME: Shows pending notifications and charging state on the indicator LEDs over org.mobileos.Leds.
+
+mod indicator;
+mod led;
+
+use std::path::{Path, PathBuf};
+
+use futures_util::StreamExt;
+use tracing::{info, warn};
+use zbus::object_server::{InterfaceRef, SignalEmitter};
+use zbus::{Connection, connection, interface, proxy};
+
+use indicator::{Color, Config, Indicator};
+use led::Led;
+
+struct LedsService {
+    leds: Vec<Led>,
+    indicator: Indicator,
+}
+
+impl LedsService {
+    fn new(leds: Vec<Led>, config: Config, user_colors: PathBuf) -> Self {
+        let service = Self {
+            leds,
+            indicator: Indicator::new(config, user_colors),
+        };
+        service.show();
+        service
+    }
+
+    /// Write the current pattern to every LED.
+    fn show(&self) {
+        let pattern = self.indicator.pattern();
+        for led in &self.leds {
+            if let Err(e) = led.apply(&pattern) {
+                warn!(led = %led.name(), error = %e, "failed to set LED");
+            }
+        }
+    }
+
+    async fn update(&self, emitter: &SignalEmitter<'_>) {
+        self.show();
+        if let Err(e) = self.pattern_changed(emitter).await {
+            warn!(error = %e, "failed to emit LED pattern");
+        }
+    }
+}
+
+fn failed(e: anyhow::Error) -> zbus::fdo::Error {
+    zbus::fdo::Error::Failed(format!("{e:#}"))
+}
+
+#[interface(name = "org.mobileos.Leds")]
+impl LedsService {
+    /// The sysfs names of the indicator LEDs being driven.
+    #[zbus(property)]
+    fn leds(&self) -> Vec<String> {
+        self.leds.iter().map(Led::name).collect()
+    }
+
+    /// What the LEDs show: "off", "solid #rrggbb" or "blink #rrggbb <on ms> <off ms>".
+    #[zbus(property)]
+    fn pattern(&self) -> String {
+        self.indicator.pattern().to_string()
+    }
+
+    /// Called by the notification daemon with the apps that have unread notifications,
+    /// most recent last. The LED blinks in the colour of the most recent one.
+    async fn set_pending_notifications(
+        &mut self,
+        apps: Vec<String>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) {
+        self.indicator.set_pending(apps);
+        self.update(&emitter).await;
+    }
+
+    async fn set_app_color(
+        &mut self,
+        app: &str,
+        color: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let color: Color = color.parse().map_err(failed)?;
+        self.indicator.set_app_color(app, color).map_err(failed)?;
+        info!(app, color = %color, "notification colour set");
+        self.update(&emitter).await;
+        Ok(())
+    }
+
+    /// The colour notifications from `app` blink in, as "#rrggbb".
+    fn app_color(&self, app: &str) -> String {
+        self.indicator.app_color(app).to_string()
+    }
+}
+
+#[proxy(
+    interface = "org.mobileos.Power",
+    default_service = "org.mobileos.Power",
+    default_path = "/org/mobileos/Power"
+)]
+trait Power {
+    #[zbus(property)]
+    fn battery_level(&self) -> zbus::Result<u8>;
+
+    #[zbus(property)]
+    fn charging(&self) -> zbus::Result<bool>;
+}
+
+/// Feed the charger state to the indicator and tell clients what it changed.
+async fn record_power(iface: &InterfaceRef<LedsService>, charging: bool, battery_level: u8) {
+    let mut service = iface.get_mut().await;
+    service.indicator.set_power(charging, battery_level);
+    service.update(iface.signal_emitter()).await;
+}
+
+/// Follow the power service's charging state and battery level.
+async fn watch_power(connection: Connection, iface: InterfaceRef<LedsService>) -> zbus::Result<()> {
+    let power = PowerProxy::new(&connection).await?;
+    let mut charging_changes = power.receive_charging_changed().await;
+    let mut level_changes = power.receive_battery_level_changed().await;
+    loop {
+        let charging = power.charging().await?;
+        let level = power.battery_level().await?;
+        record_power(&iface, charging, level).await;
+        tokio::select! {
+            Some(_) = charging_changes.next() => {}
+            Some(_) = level_changes.next() => {}
+            else => return Ok(()),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    info!("starting LED service");
+
+    let config = Config::load(Path::new(indicator::CONFIG_PATH)).unwrap_or_else(|e| {
+        warn!(error = %e, "using default LED colours");
+        Config::default()
+    });
+    let colors = match mos_device::load_from_system() {
+        Ok(device) => device.map(|device| device.leds).unwrap_or_default(),
+        Err(e) => {
+            warn!(error = %e, "failed to load device config");
+            Default::default()
+        }
+    };
+    let service = LedsService::new(
+        led::discover(Path::new(led::LEDS_DIR), &colors),
+        config,
+        PathBuf::from(indicator::USER_COLORS_PATH),
+    );
+    if service.leds.is_empty() {
+        info!("no indicator LEDs found");
+    }
+
+    let connection = connection::Builder::system()?
+        .name("org.mobileos.Leds")?
+        .serve_at("/org/mobileos/Leds", service)?
+        .build()
+        .await?;
+
+    let iface = connection
+        .object_server()
+        .interface::<_, LedsService>("/org/mobileos/Leds")
+        .await?;
+    tokio::spawn(async move {
+        if let Err(e) = watch_power(connection, iface).await {
+            warn!(error = %e, "lost the power service, charging state not shown");
+        }
+    });
+
+    info!("LED service running on system bus");
+    mos_notify::ready();
+
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use zbus::{Connection, connection, proxy};
+
+    use crate::indicator::Config;
+
+    #[proxy(interface = "org.mobileos.Leds", default_path = "/org/mobileos/Leds")]
+    trait Leds {
+        #[zbus(property)]
+        fn leds(&self) -> zbus::Result<Vec<String>>;
+
+        #[zbus(property)]
+        fn pattern(&self) -> zbus::Result<String>;
+
+        fn set_pending_notifications(&self, apps: &[&str]) -> zbus::Result<()>;
+        fn set_app_color(&self, app: &str, color: &str) -> zbus::Result<()>;
+        fn app_color(&self, app: &str) -> zbus::Result<String>;
+    }
+
+    #[tokio::test]
+    async fn shows_notifications_and_charging() {
+        let dir = tempfile::tempdir().unwrap();
+        let led = dir.path().join("sys/rgb:status");
+        std::fs::create_dir_all(&led).unwrap();
+        std::fs::write(led.join("max_brightness"), "255\n").unwrap();
+        std::fs::write(led.join("multi_index"), "red green blue\n").unwrap();
+        let read = |attribute: &str| std::fs::read_to_string(led.join(attribute)).unwrap();
+
+        let service = super::LedsService::new(
+            crate::led::discover(&dir.path().join("sys"), &Default::default()),
+            Config::default(),
+            dir.path().join("colors.toml"),
+        );
+        let conn = connection::Builder::session()
+            .unwrap()
+            .serve_at("/org/mobileos/Leds", service)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let iface = conn
+            .object_server()
+            .interface::<_, super::LedsService>("/org/mobileos/Leds")
+            .await
+            .unwrap();
+        let client = Connection::session().await.unwrap();
+        let proxy = LedsProxy::builder(&client)
+            .destination(conn.unique_name().unwrap().to_owned())
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(proxy.leds().await.unwrap(), ["rgb:status"]);
+        assert_eq!(proxy.pattern().await.unwrap(), "off");
+        assert_eq!(read("brightness"), "0");
+
+        super::record_power(&iface, true, 50).await;
+        assert_eq!(proxy.pattern().await.unwrap(), "solid #ff0000");
+        assert_eq!(read("multi_intensity"), "255 0 0");
+
+        proxy.set_app_color("messages", "#0000ff").await.unwrap();
+        assert!(proxy.set_app_color("messages", "blue").await.is_err());
+        assert_eq!(proxy.app_color("messages").await.unwrap(), "#0000ff");
+        assert!(Path::new(&dir.path().join("colors.toml")).exists());
+
+        proxy
+            .set_pending_notifications(&["messages"])
+            .await
+            .unwrap();
+        assert_eq!(proxy.pattern().await.unwrap(), "blink #0000ff 500 2500");
+        assert_eq!(read("multi_intensity"), "0 0 255");
+        assert_eq!(read("trigger"), "timer");
+
+        proxy.set_pending_notifications(&[]).await.unwrap();
+        super::record_power(&iface, true, 100).await;
+        assert_eq!(proxy.pattern().await.unwrap(), "solid #00ff00");
+        assert_eq!(read("trigger"), "none");
+    }
+}
@@ -0,0 +1,78 @@
+// ABOUTME: Who may read the IMEI, whose caller ID lookups reach the network, and who sets them.
+// ABOUTME: Callers are told apart by the uid the bus reports, looked up in /etc/passwd and /etc/group.
+
+use std::collections::HashSet;
+
+/// Accounts in this group, as members or by their login group, may read the IMEI.
+pub const GROUP: &str = "radio";
+
+/// Accounts in this group are granted the network, so a caller ID provider running
+/// under one is taken to look numbers up online.
+pub const NETWORK_GROUP: &str = "inet";
+
+/// Only the settings app, told apart by its executable, lets online caller ID
+/// providers see the numbers that call.
+pub const SETTINGS_EXE: &str = "/usr/bin/mos-settings";
+
+const PASSWD_PATH: &str = "/etc/passwd";
+const GROUP_PATH: &str = "/etc/group";
+
+/// Uids of root and of the accounts in `group`, from the contents of /etc/passwd and
+/// /etc/group.
+pub fn allowed_uids(passwd: &str, groups: &str, group: &str) -> HashSet<u32> {
+    let entry = groups
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() == 4 && fields[0] == group);
+    let gid = entry
+        .as_ref()
+        .and_then(|fields| fields[2].parse::<u32>().ok());
+    let members: Vec<&str> = entry
+        .as_ref()
+        .map(|fields| fields[3].split(',').filter(|m| !m.is_empty()).collect())
+        .unwrap_or_default();
+
+    let mut uids = HashSet::from([0]);
+    for fields in passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+    {
+        let [name, _, uid, login_gid, ..] = fields[..] else {
+            continue;
+        };
+        let Ok(uid) = uid.parse() else {
+            continue;
+        };
+        if members.contains(&name) || gid.is_some_and(|gid| login_gid.parse() == Ok(gid)) {
+            uids.insert(uid);
+        }
+    }
+    uids
+}
+
+/// The uids of root and of the accounts in `group` on this system. Only root is without
+/// the files.
+pub fn allowed_uids_from_system(group: &str) -> HashSet<u32> {
+    let passwd = std::fs::read_to_string(PASSWD_PATH).unwrap_or_default();
+    let groups = std::fs::read_to_string(GROUP_PATH).unwrap_or_default();
+    allowed_uids(&passwd, &groups, group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_members_and_login_group_are_allowed() {
+        let passwd = "root:x:0:0:root:/root:/bin/sh\n\
+                      modem:x:101:101::/var/empty:/bin/false\n\
+                      diag:x:110:106::/var/empty:/bin/false\n\
+                      audio:x:102:102::/var/empty:/bin/false\n";
+        let groups = "root:x:0:\nradio:x:106:modem\naudio:x:102:\n";
+        assert_eq!(
+            allowed_uids(passwd, groups, GROUP),
+            HashSet::from([0, 101, 110])
+        );
+        assert_eq!(allowed_uids(passwd, "", GROUP), HashSet::from([0]));
+    }
+}
@@ -0,0 +1,296 @@
+// ABOUTME: Carrier data settings: the bundled APN database keyed by MCC/MNC, and the user's own APN.
+// ABOUTME: Turns an APN into the AT commands defining the data bearer's PDP context, MMS settings included.
+
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+/// The carriers shipped with the image.
+pub const DATABASE_PATH: &str = "/etc/mos/apns.toml";
+
+/// An APN the user entered, which wins over the database until reset.
+pub const MANUAL_APN_PATH: &str = "/data/modem/apn.toml";
+
+const AUTH_TYPES: &[&str] = &["none", "pap", "chap"];
+const IP_TYPES: &[&str] = &["IP", "IPV6", "IPV4V6"];
+
+/// How a carrier's data bearer is set up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Apn {
+    /// Carrier name, for the settings page.
+    pub carrier: String,
+    pub apn: String,
+    pub username: String,
+    pub password: String,
+    /// "none", "pap" or "chap".
+    pub auth: String,
+    /// "IP", "IPV6" or "IPV4V6".
+    pub ip_type: String,
+    /// The carrier's MMS center URL and the proxy in front of it, as host:port, for
+    /// sending and fetching MMS over this bearer. Empty where the carrier has none.
+    pub mmsc: String,
+    pub mms_proxy: String,
+}
+
+impl Default for Apn {
+    fn default() -> Self {
+        Self {
+            carrier: String::new(),
+            apn: String::new(),
+            username: String::new(),
+            password: String::new(),
+            auth: "none".to_string(),
+            ip_type: "IPV4V6".to_string(),
+            mmsc: String::new(),
+            mms_proxy: String::new(),
+        }
+    }
+}
+
+impl Apn {
+    /// Check the APN can go into AT commands: they are quoted strings, so quotes and
+    /// control characters would break out of them.
+    pub fn validate(&self) -> Result<()> {
+        if self.apn.is_empty() || self.apn.len() > 100 {
+            bail!("an APN name is 1 to 100 characters");
+        }
+        if !self
+            .apn
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+        {
+            bail!("APN name '{}' has characters APNs can't have", self.apn);
+        }
+        for (what, value) in [
+            ("user name", &self.username),
+            ("password", &self.password),
+            ("MMSC", &self.mmsc),
+            ("MMS proxy", &self.mms_proxy),
+        ] {
+            if value.chars().any(|c| c == '"' || c.is_control()) {
+                bail!("the {what} can't have quotes or control characters");
+            }
+        }
+        if !AUTH_TYPES.contains(&self.auth.as_str()) {
+            bail!(
+                "authentication is one of {}, not '{}'",
+                AUTH_TYPES.join(", "),
+                self.auth
+            );
+        }
+        if !IP_TYPES.contains(&self.ip_type.as_str()) {
+            bail!(
+                "the IP type is one of {}, not '{}'",
+                IP_TYPES.join(", "),
+                self.ip_type
+            );
+        }
+        Ok(())
+    }
+
+    /// The commands defining PDP context 1 as this APN, and its authentication.
+    pub fn commands(&self) -> Vec<String> {
+        let define = format!("AT+CGDCONT=1,\"{}\",\"{}\"", self.ip_type, self.apn);
+        // 3GPP numbers the protocols none, PAP, CHAP
+        let protocol = match self.auth.as_str() {
+            "pap" => 1,
+            "chap" => 2,
+            _ => return vec![define, "AT+CGAUTH=1,0".to_string()],
+        };
+        let auth = format!(
+            "AT+CGAUTH=1,{protocol},\"{}\",\"{}\"",
+            self.username, self.password
+        );
+        vec![define, auth]
+    }
+
+    /// The APN saved at `path`, if the user entered one.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .map(Some)
+                .with_context(|| format!("failed to parse {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let text = toml::to_string(self).context("failed to serialize the APN")?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, text).with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))
+    }
+
+    /// Forget the APN saved at `path`, going back to the database's.
+    pub fn forget(path: &Path) -> Result<()> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Carrier {
+    /// Mobile country code, three digits.
+    mcc: String,
+    /// Mobile network code, two or three digits.
+    mnc: String,
+    #[serde(flatten)]
+    apn: Apn,
+}
+
+/// The bundled carriers, looked up by the network code at the start of the SIM's IMSI.
+#[derive(Debug, Default, Deserialize)]
+pub struct ApnDatabase {
+    #[serde(default, rename = "carrier")]
+    carriers: Vec<Carrier>,
+}
+
+impl ApnDatabase {
+    /// Read the database at `path`; an image without one has no carriers.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                Self::parse(&text).with_context(|| format!("failed to parse {}", path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let database: Self = toml::from_str(text)?;
+        for carrier in &database.carriers {
+            carrier
+                .apn
+                .validate()
+                .with_context(|| format!("carrier {}{}", carrier.mcc, carrier.mnc))?;
+        }
+        Ok(database)
+    }
+
+    /// The APN of the carrier that issued the SIM with `imsi`. MNCs are two digits in
+    /// some countries and three in others, so the longest match wins.
+    pub fn lookup(&self, imsi: &str) -> Option<&Apn> {
+        self.carriers
+            .iter()
+            .filter(|c| {
+                imsi.strip_prefix(c.mcc.as_str())
+                    .is_some_and(|rest| rest.starts_with(c.mnc.as_str()))
+            })
+            .max_by_key(|c| c.mnc.len())
+            .map(|c| &c.apn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATABASE: &str = r#"
+        [[carrier]]
+        mcc = "310"
+        mnc = "26"
+        carrier = "Short"
+        apn = "short"
+
+        [[carrier]]
+        mcc = "310"
+        mnc = "260"
+        carrier = "T-Mobile"
+        apn = "fast.t-mobile.com"
+        mmsc = "http://mms.msg.eng.t-mobile.com/mms/wapenc"
+
+        [[carrier]]
+        mcc = "234"
+        mnc = "15"
+        carrier = "Vodafone UK"
+        apn = "wap.vodafone.co.uk"
+        username = "wap"
+        password = "wap"
+        auth = "pap"
+        ip_type = "IP"
+    "#;
+
+    #[test]
+    fn looks_up_the_longest_network_code() {
+        let database = ApnDatabase::parse(DATABASE).unwrap();
+        let apn = database.lookup("310260123456789").unwrap();
+        assert_eq!(apn.carrier, "T-Mobile");
+        assert_eq!(apn.ip_type, "IPV4V6");
+        assert_eq!(database.lookup("310261123456789").unwrap().carrier, "Short");
+        assert_eq!(
+            database.lookup("234150000000000").unwrap().apn,
+            "wap.vodafone.co.uk"
+        );
+        assert!(database.lookup("208010000000000").is_none());
+        assert!(database.lookup("").is_none());
+
+        assert!(ApnDatabase::parse("[[carrier]]\nmcc = \"001\"\nmnc = \"01\"\n").is_err());
+    }
+
+    #[test]
+    fn apns_become_context_commands() {
+        let database = ApnDatabase::parse(DATABASE).unwrap();
+        assert_eq!(
+            database.lookup("310260123456789").unwrap().commands(),
+            [
+                "AT+CGDCONT=1,\"IPV4V6\",\"fast.t-mobile.com\"",
+                "AT+CGAUTH=1,0"
+            ]
+        );
+        assert_eq!(
+            database.lookup("234150000000000").unwrap().commands(),
+            [
+                "AT+CGDCONT=1,\"IP\",\"wap.vodafone.co.uk\"",
+                "AT+CGAUTH=1,1,\"wap\",\"wap\""
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_apns_that_break_out_of_commands() {
+        let apn = |name: &str, password: &str| Apn {
+            apn: name.to_string(),
+            password: password.to_string(),
+            ..Apn::default()
+        };
+        assert!(apn("internet", "").validate().is_ok());
+        assert!(apn("", "").validate().is_err());
+        assert!(apn("internet\"\r\nAT+CFUN=0", "").validate().is_err());
+        assert!(apn("internet", "a\"b").validate().is_err());
+        let kerberos = Apn {
+            auth: "kerberos".to_string(),
+            ..apn("internet", "")
+        };
+        assert!(kerberos.validate().is_err());
+    }
+
+    #[test]
+    fn manual_apns_are_kept_until_forgotten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modem").join("apn.toml");
+        assert_eq!(Apn::load(&path).unwrap(), None);
+
+        let apn = Apn {
+            apn: "internet".to_string(),
+            mmsc: "http://mms.example.net".to_string(),
+            ..Apn::default()
+        };
+        apn.save(&path).unwrap();
+        assert_eq!(Apn::load(&path).unwrap(), Some(apn));
+        Apn::forget(&path).unwrap();
+        assert_eq!(Apn::load(&path).unwrap(), None);
+        Apn::forget(&path).unwrap();
+    }
+}
@@ -0,0 +1,922 @@
+// ABOUTME: AT-command modem backend for simple USB modems without ModemManager.
+// ABOUTME: Talks to a serial port directly: commands, multiparty calls, SMS submission and status reports, the SIM toolkit, and unsolicited result codes.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use rustix::termios::{OptionalActions, SpecialCodeIndex, tcgetattr, tcsetattr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{Mutex, mpsc};
+use tracing::{debug, warn};
+
+use crate::apn::Apn;
+use crate::calls::{Call, CallState};
+use crate::pdu;
+
+/// How long a modem may take to answer an ordinary command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Submitting an SMS waits on the network, which can take much longer.
+const SMS_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long a port gets to answer `AT` while probing for the modem, in the tenths of
+/// a second that VTIME counts.
+const PROBE_TIMEOUT_DECISECONDS: u8 = 10;
+
+const BAUD_RATE: u32 = 115_200;
+/// Terminates the PDU after the `> ` prompt of AT+CMGS.
+const CTRL_Z: u8 = 0x1a;
+
+/// Setup run once the modem answers: no echo, numeric errors, PDU-mode SMS, caller id,
+/// registration changes, and new-SMS and status report notifications as URCs.
+const INIT_COMMANDS: &[&str] = &[
+    "ATE0",
+    "AT+CMEE=1",
+    "AT+CMGF=0",
+    "AT+CLIP=1",
+    "AT+CREG=1",
+    "AT+CNMI=2,1,0,2,0",
+];
+
+/// Unsolicited result codes: events the modem reports on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Urc {
+    /// An incoming call is ringing.
+    Ring,
+    /// Number of the incoming caller, from +CLIP.
+    CallerId(String),
+    /// The call ended, from NO CARRIER or BUSY outside of a command.
+    CallEnded,
+    /// A new SMS was stored at this index, from +CMTI.
+    NewSms(u32),
+    /// A status report on a sent SMS was stored at this index, from +CDSI.
+    StatusReport(u32),
+    /// Network registration status, from +CREG.
+    Registration(u8),
+    /// A proactive command from the SIM toolkit, in hex, from +CUSATP.
+    Toolkit(String),
+    /// The SIM ended its toolkit session, from +CUSATEND.
+    ToolkitEnded,
+}
+
+/// What the modem says it is, for the About page and diagnostics.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub imei: String,
+    /// Firmware revision, e.g. "EG25GGBR07A08M2G".
+    pub firmware: String,
+    /// Manufacturer and model, e.g. "Quectel EG25".
+    pub baseband: String,
+}
+
+/// What the reader task hands to the command in flight.
+#[derive(Debug)]
+enum Response {
+    Line(String),
+    /// The `> ` prompt of AT+CMGS, which has no line ending.
+    Prompt,
+}
+
+struct Channel {
+    writer: Box<dyn AsyncWrite + Send + Unpin>,
+    responses: mpsc::UnboundedReceiver<Response>,
+}
+
+/// A modem speaking AT commands over a byte stream, normally a serial port.
+pub struct AtModem {
+    /// Held for the duration of a command, so commands never interleave.
+    channel: Mutex<Channel>,
+    /// Whether a command is waiting for its final result, which decides whether a
+    /// NO CARRIER is that result or a URC.
+    busy: Arc<AtomicBool>,
+}
+
+impl AtModem {
+    /// Start reading from the modem. URCs are delivered on the returned receiver.
+    pub fn spawn(
+        reader: impl AsyncRead + Send + Unpin + 'static,
+        writer: impl AsyncWrite + Send + Unpin + 'static,
+    ) -> (Self, mpsc::UnboundedReceiver<Urc>) {
+        let (response_tx, responses) = mpsc::unbounded_channel();
+        let (urc_tx, urcs) = mpsc::unbounded_channel();
+        let busy = Arc::new(AtomicBool::new(false));
+        tokio::spawn(read_loop(reader, response_tx, urc_tx, busy.clone()));
+
+        let modem = Self {
+            channel: Mutex::new(Channel {
+                writer: Box::new(writer),
+                responses,
+            }),
+            busy,
+        };
+        (modem, urcs)
+    }
+
+    /// Open a serial port and start talking to the modem on it.
+    pub fn open(path: &Path) -> Result<(Self, mpsc::UnboundedReceiver<Urc>)> {
+        let port = open_port(path).with_context(|| format!("failed to open {}", path.display()))?;
+        // Separate handles: a pending read on a tokio File would block writes
+        let reader = tokio::fs::File::from_std(port.try_clone()?);
+        let writer = tokio::fs::File::from_std(port);
+        Ok(Self::spawn(reader, writer))
+    }
+
+    /// Run the setup commands every session needs.
+    pub async fn init(&self) -> Result<()> {
+        for command in INIT_COMMANDS {
+            self.command(command).await?;
+        }
+        Ok(())
+    }
+
+    /// Send a command and return its information lines once it ends in OK. Any other
+    /// final result, like ERROR or +CME ERROR, is returned as the error.
+    pub async fn command(&self, command: &str) -> Result<Vec<String>> {
+        let mut channel = self.channel.lock().await;
+        channel.start(command, &self.busy).await?;
+        let result = tokio::time::timeout(COMMAND_TIMEOUT, channel.finish(command)).await;
+        self.busy.store(false, Ordering::Relaxed);
+        result.map_err(|_| anyhow!("{command}: no response from modem"))?
+    }
+
+    /// Start a voice call. The trailing `;` of ATD makes it voice rather than data.
+    pub async fn dial(&self, number: &str) -> Result<()> {
+        let digits = number.strip_prefix('+').unwrap_or(number);
+        if digits.is_empty()
+            || !digits
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '*' || c == '#')
+        {
+            bail!("invalid phone number '{number}'");
+        }
+        self.command(&format!("ATD{number};")).await?;
+        Ok(())
+    }
+
+    /// End the current call, or reject a ringing one.
+    pub async fn hang_up(&self) -> Result<()> {
+        self.command("ATH").await?;
+        Ok(())
+    }
+
+    /// The calls the modem has up, ringing or on hold.
+    pub async fn calls(&self) -> Result<Vec<Call>> {
+        let lines = self.command("AT+CLCC").await?;
+        Ok(lines.iter().filter_map(|line| parse_clcc(line)).collect())
+    }
+
+    /// Hold the active call and take the waiting or held one.
+    pub async fn swap_calls(&self) -> Result<()> {
+        self.command("AT+CHLD=2").await?;
+        Ok(())
+    }
+
+    /// Join the held call to the active one in a conference.
+    pub async fn merge_calls(&self) -> Result<()> {
+        self.command("AT+CHLD=3").await?;
+        Ok(())
+    }
+
+    /// End call `index` and no other, which for a leg of a conference not every
+    /// network allows.
+    pub async fn release_call(&self, index: u8) -> Result<()> {
+        self.command(&format!("AT+CHLD=1{index}")).await?;
+        Ok(())
+    }
+
+    /// Bring the data bearer, PDP context 1, up or down.
+    pub async fn set_data(&self, enabled: bool) -> Result<()> {
+        self.command(&format!("AT+CGACT={},1", u8::from(enabled)))
+            .await?;
+        Ok(())
+    }
+
+    /// Define the data bearer, PDP context 1, as `apn`. Takes effect the next time the
+    /// bearer comes up.
+    pub async fn set_apn(&self, apn: &Apn) -> Result<()> {
+        apn.validate()?;
+        for command in apn.commands() {
+            self.command(&command).await?;
+        }
+        Ok(())
+    }
+
+    /// Submit an SMS in PDU mode, asking for a status report if `status_report`.
+    /// Returns the reference the modem gave it, which the report names it by.
+    pub async fn send_sms(&self, number: &str, text: &str, status_report: bool) -> Result<u8> {
+        let (pdu, length) = pdu::encode_submit(number, text, status_report)?;
+        let command = format!("AT+CMGS={length}");
+
+        let mut channel = self.channel.lock().await;
+        channel.start(&command, &self.busy).await?;
+        let result = tokio::time::timeout(SMS_TIMEOUT, async {
+            channel.wait_for_prompt(&command).await?;
+            let mut body = pdu.into_bytes();
+            body.push(CTRL_Z);
+            channel.writer.write_all(&body).await?;
+            channel.writer.flush().await?;
+            channel.finish(&command).await
+        })
+        .await;
+        self.busy.store(false, Ordering::Relaxed);
+        let lines = result.map_err(|_| anyhow!("{command}: no response from modem"))??;
+        lines
+            .iter()
+            .find_map(|line| line.strip_prefix("+CMGS:")?.trim().parse().ok())
+            .with_context(|| format!("{command}: no message reference"))
+    }
+
+    /// Read and delete a stored SMS, as announced by `Urc::NewSms`.
+    pub async fn take_sms(&self, index: u32) -> Result<pdu::Sms> {
+        pdu::decode_deliver(&self.take_stored(index).await?)
+    }
+
+    /// Read and delete a stored status report, as announced by `Urc::StatusReport`.
+    pub async fn take_status_report(&self, index: u32) -> Result<pdu::StatusReport> {
+        pdu::decode_status_report(&self.take_stored(index).await?)
+    }
+
+    /// The PDU stored at `index`, deleted from the modem's storage once read.
+    async fn take_stored(&self, index: u32) -> Result<String> {
+        let lines = self.command(&format!("AT+CMGR={index}")).await?;
+        // +CMGR: <stat>,[<alpha>],<length> followed by the PDU on its own line
+        let pdu = lines
+            .iter()
+            .skip_while(|line| !line.starts_with("+CMGR:"))
+            .nth(1)
+            .with_context(|| format!("no message at index {index}"))?
+            .clone();
+        if let Err(e) = self.command(&format!("AT+CMGD={index}")).await {
+            warn!(index, error = %e, "failed to delete SMS from modem storage");
+        }
+        Ok(pdu)
+    }
+
+    /// Signal strength in percent, or `None` while the modem doesn't know it.
+    pub async fn signal_strength(&self) -> Result<Option<u8>> {
+        let lines = self.command("AT+CSQ").await?;
+        Ok(lines.iter().find_map(|line| parse_csq(line)).flatten())
+    }
+
+    /// Whether the modem is registered with a network, at home or roaming, so messages
+    /// can go out.
+    pub async fn registered(&self) -> Result<bool> {
+        let lines = self.command("AT+CREG?").await?;
+        Ok(lines
+            .iter()
+            .find_map(|line| parse_creg(line))
+            .is_some_and(is_registered))
+    }
+
+    /// Name of the network operator the modem is registered with.
+    pub async fn operator(&self) -> Result<Option<String>> {
+        let lines = self.command("AT+COPS?").await?;
+        Ok(lines.iter().find_map(|line| parse_cops(line)))
+    }
+
+    /// IMEI, firmware revision and model, which don't change while the modem runs.
+    pub async fn identity(&self) -> Result<Identity> {
+        let imei = self.info("AT+CGSN", "+CGSN:").await?;
+        let firmware = self.info("AT+CGMR", "+CGMR:").await?;
+        let manufacturer = self.info("AT+CGMI", "+CGMI:").await?;
+        let model = self.info("AT+CGMM", "+CGMM:").await?;
+        let baseband = [manufacturer, model]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok(Identity {
+            imei,
+            firmware,
+            baseband,
+        })
+    }
+
+    /// The SIM's IMSI, whose first digits name the carrier that issued it.
+    pub async fn imsi(&self) -> Result<String> {
+        self.info("AT+CIMI", "+CIMI:").await
+    }
+
+    async fn info(&self, command: &str, prefix: &str) -> Result<String> {
+        let lines = self.command(command).await?;
+        Ok(parse_info(&lines, prefix).unwrap_or_default())
+    }
+
+    /// Have the SIM's proactive commands come here as +CUSATP, so its toolkit can be
+    /// shown, rather than to the modem, which has no screen.
+    pub async fn enable_toolkit(&self) -> Result<()> {
+        self.command("AT+CUSATA=1").await?;
+        Ok(())
+    }
+
+    /// Answer the SIM's proactive command with a terminal response, in hex.
+    pub async fn toolkit_response(&self, response: &str) -> Result<()> {
+        ensure_hex(response)?;
+        self.command(&format!("AT+CUSATT=\"{response}\"")).await?;
+        Ok(())
+    }
+
+    /// Send the SIM an envelope, such as the user opening an item of its menu, in hex.
+    pub async fn toolkit_envelope(&self, envelope: &str) -> Result<()> {
+        ensure_hex(envelope)?;
+        self.command(&format!("AT+CUSATE=\"{envelope}\"")).await?;
+        Ok(())
+    }
+
+    /// Whether a SIM is inserted, locked or not.
+    pub async fn sim_present(&self) -> Result<bool> {
+        match self.command("AT+CPIN?").await {
+            Ok(_) => Ok(true),
+            // CME error 10: SIM not inserted
+            Err(e) if e.to_string().ends_with("+CME ERROR: 10") => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Channel {
+    async fn start(&mut self, command: &str, busy: &AtomicBool) -> Result<()> {
+        // Drop leftovers of a command that timed out, so they aren't taken as ours
+        while self.responses.try_recv().is_ok() {}
+        debug!(command, "sending AT command");
+        busy.store(true, Ordering::Relaxed);
+        self.writer
+            .write_all(format!("{command}\r").as_bytes())
+            .await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<Response> {
+        self.responses
+            .recv()
+            .await
+            .ok_or_else(|| anyhow!("modem port closed"))
+    }
+
+    async fn wait_for_prompt(&mut self, command: &str) -> Result<()> {
+        loop {
+            match self.next().await? {
+                Response::Prompt => return Ok(()),
+                Response::Line(line) => match final_result(&line) {
+                    Some(Ok(())) => bail!("{command}: modem answered OK without a prompt"),
+                    Some(Err(e)) => bail!("{command}: {e}"),
+                    None => {}
+                },
+            }
+        }
+    }
+
+    async fn finish(&mut self, command: &str) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let Response::Line(line) = self.next().await? else {
+                continue;
+            };
+            match final_result(&line) {
+                Some(Ok(())) => return Ok(lines),
+                Some(Err(e)) => bail!("{command}: {e}"),
+                // Echo, before ATE0 has taken effect
+                None if line == command => {}
+                None => lines.push(line),
+            }
+        }
+    }
+}
+
+/// Split the modem's output into lines, routing URCs to `urcs` and everything else to
+/// the command in flight.
+async fn read_loop(
+    mut reader: impl AsyncRead + Unpin,
+    responses: mpsc::UnboundedSender<Response>,
+    urcs: mpsc::UnboundedSender<Urc>,
+    busy: Arc<AtomicBool>,
+) {
+    let mut buf = [0u8; 256];
+    let mut line = Vec::new();
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
+                warn!(error = %e, "failed to read from modem");
+                break;
+            }
+        };
+
+        for &byte in &buf[..n] {
+            if byte != b'\r' && byte != b'\n' {
+                line.push(byte);
+                if line == b"> " {
+                    line.clear();
+                    let _ = responses.send(Response::Prompt);
+                }
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            let text = String::from_utf8_lossy(&line).into_owned();
+            line.clear();
+            let command_pending = busy.load(Ordering::Relaxed);
+            match parse_urc(&text, command_pending) {
+                Some(urc) => {
+                    let _ = urcs.send(urc);
+                }
+                None => {
+                    let _ = responses.send(Response::Line(text));
+                }
+            }
+        }
+    }
+}
+
+/// Check toolkit data is hex, as it goes into a quoted string.
+fn ensure_hex(data: &str) -> Result<()> {
+    if data.is_empty() || !data.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("toolkit data '{data}' is not hex");
+    }
+    Ok(())
+}
+
+/// The outcome a line reports if it ends a command: `Ok` for OK, the line itself as
+/// the error for any failure.
+fn final_result(line: &str) -> Option<Result<(), String>> {
+    const FAILURES: &[&str] = &["ERROR", "NO CARRIER", "BUSY", "NO ANSWER", "NO DIALTONE"];
+    if line == "OK" {
+        Some(Ok(()))
+    } else if FAILURES.contains(&line)
+        || line.starts_with("+CME ERROR:")
+        || line.starts_with("+CMS ERROR:")
+    {
+        Some(Err(line.to_string()))
+    } else {
+        None
+    }
+}
+
+/// Recognize an unsolicited result code. NO CARRIER and BUSY are only URCs when no
+/// command is waiting for them as its final result.
+pub fn parse_urc(line: &str, command_pending: bool) -> Option<Urc> {
+    if line == "RING" {
+        return Some(Urc::Ring);
+    }
+    if !command_pending && (line == "NO CARRIER" || line == "BUSY") {
+        return Some(Urc::CallEnded);
+    }
+    if let Some(rest) = line.strip_prefix("+CLIP:") {
+        let number = rest.split(',').next()?.trim().trim_matches('"');
+        return Some(Urc::CallerId(number.to_string()));
+    }
+    if let Some(rest) = line.strip_prefix("+CMTI:") {
+        let index = rest.split(',').nth(1)?.trim().parse().ok()?;
+        return Some(Urc::NewSms(index));
+    }
+    if let Some(rest) = line.strip_prefix("+CDSI:") {
+        let index = rest.split(',').nth(1)?.trim().parse().ok()?;
+        return Some(Urc::StatusReport(index));
+    }
+    if let Some(rest) = line.strip_prefix("+CUSATP:") {
+        return Some(Urc::Toolkit(rest.trim().trim_matches('"').to_string()));
+    }
+    if line == "+CUSATEND" {
+        return Some(Urc::ToolkitEnded);
+    }
+    if let Some(rest) = line.strip_prefix("+CREG:") {
+        // The URC has just the status; the answer to AT+CREG? starts with the mode
+        let fields: Vec<&str> = rest.split(',').map(str::trim).collect();
+        if fields.len() == 1 || fields.len() == 3 {
+            return Some(Urc::Registration(fields[0].parse().ok()?));
+        }
+    }
+    None
+}
+
+/// Parse `+CSQ: <rssi>,<ber>` into percent. The outer `None` means the line isn't a
+/// +CSQ answer; the inner one that the signal is unknown (rssi 99).
+pub fn parse_csq(line: &str) -> Option<Option<u8>> {
+    let rssi: u32 = line
+        .strip_prefix("+CSQ:")?
+        .split(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()?;
+    match rssi {
+        0..=31 => Some(Some((rssi * 100 / 31) as u8)),
+        _ => Some(None),
+    }
+}
+
+/// Parse `+CLCC: <index>,<dir>,<stat>,<mode>,<mpty>[,"<number>",<type>]` into the
+/// call it lists. None for other lines, and for data and fax calls.
+pub fn parse_clcc(line: &str) -> Option<Call> {
+    let fields: Vec<&str> = line
+        .strip_prefix("+CLCC:")?
+        .split(',')
+        .map(str::trim)
+        .collect();
+    let [index, dir, stat, "0", mpty, rest @ ..] = fields.as_slice() else {
+        return None;
+    };
+    Some(Call {
+        index: index.parse().ok()?,
+        number: rest
+            .first()
+            .map(|number| number.trim_matches('"').to_string())
+            .unwrap_or_default(),
+        incoming: *dir == "1",
+        state: CallState::from_clcc(stat.parse().ok()?)?,
+        conference: *mpty == "1",
+    })
+}
+
+/// Parse `+CREG: <n>,<stat>[,<lac>,<ci>]`, the answer to AT+CREG?, into the
+/// registration status.
+pub fn parse_creg(line: &str) -> Option<u8> {
+    line.strip_prefix("+CREG:")?
+        .split(',')
+        .nth(1)?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Whether a +CREG status is registered: 1 on the home network, 5 roaming.
+pub fn is_registered(status: u8) -> bool {
+    matches!(status, 1 | 5)
+}
+
+/// Parse `+COPS: <mode>,<format>,"<operator>"[,<act>]` into the operator name.
+pub fn parse_cops(line: &str) -> Option<String> {
+    let name = line.strip_prefix("+COPS:")?.split(',').nth(2)?;
+    Some(name.trim().trim_matches('"').to_string())
+}
+
+/// The answer to an identification command such as AT+CGMR, which modems give bare
+/// or after `prefix`, quoted or not.
+pub fn parse_info(lines: &[String], prefix: &str) -> Option<String> {
+    let line = lines.iter().find(|line| !line.trim().is_empty())?;
+    let value = line.strip_prefix(prefix).unwrap_or(line);
+    Some(value.trim().trim_matches('"').to_string())
+}
+
+/// Find the AT port of a USB modem: the first /dev/ttyUSB* that answers `AT` with OK.
+/// Modems expose several ports, and only some accept commands.
+pub fn probe(dev: &Path) -> Option<PathBuf> {
+    let mut ports: Vec<PathBuf> = std::fs::read_dir(dev)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("ttyUSB"))
+        })
+        .collect();
+    ports.sort();
+
+    ports.into_iter().find(|port| match answers_at(port) {
+        Ok(answered) => answered,
+        Err(e) => {
+            debug!(port = %port.display(), error = %e, "failed to probe port");
+            false
+        }
+    })
+}
+
+/// Send `AT` and wait briefly for OK. Blocking reads with a tty-level timeout, so a
+/// silent port is closed again instead of leaving a reader stuck on it.
+fn answers_at(path: &Path) -> std::io::Result<bool> {
+    let mut port = open_port(path)?;
+    let mut termios = tcgetattr(&port)?;
+    termios.special_codes[SpecialCodeIndex::VMIN] = 0;
+    termios.special_codes[SpecialCodeIndex::VTIME] = PROBE_TIMEOUT_DECISECONDS;
+    tcsetattr(&port, OptionalActions::Now, &termios)?;
+
+    port.write_all(b"AT\r")?;
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 64];
+    while reply.len() < 256 {
+        let n = port.read(&mut buf)?;
+        if n == 0 {
+            break; // timed out
+        }
+        reply.extend_from_slice(&buf[..n]);
+        if reply.windows(2).any(|w| w == b"OK") {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Open a tty read-write in raw mode at the modem's baud rate. Ports that aren't ttys,
+/// like a virtio-serial port to an emulated modem, are used as they are.
+fn open_port(path: &Path) -> std::io::Result<std::fs::File> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+    let mut termios = match tcgetattr(&file) {
+        Ok(termios) => termios,
+        Err(rustix::io::Errno::NOTTY) => return Ok(file),
+        Err(e) => return Err(e.into()),
+    };
+    termios.make_raw();
+    termios.set_speed(BAUD_RATE)?;
+    tcsetattr(&file, OptionalActions::Now, &termios)?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader, DuplexStream};
+
+    /// A modem on the other end of an in-memory pipe, answering from a script of
+    /// (expected command, reply) pairs.
+    fn scripted(
+        script: Vec<(&'static str, &'static str)>,
+    ) -> (AtModem, mpsc::UnboundedReceiver<Urc>) {
+        let (ours, theirs) = tokio::io::duplex(4096);
+        let (reader, writer) = tokio::io::split(ours);
+        tokio::spawn(serve_script(theirs, script));
+        AtModem::spawn(reader, writer)
+    }
+
+    async fn serve_script(stream: DuplexStream, script: Vec<(&'static str, &'static str)>) {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+        for (expected, reply) in script {
+            let mut request = Vec::new();
+            let terminator = if expected.ends_with('\u{1a}') {
+                0x1a
+            } else {
+                b'\r'
+            };
+            reader.read_until(terminator, &mut request).await.unwrap();
+            let request = String::from_utf8(request).unwrap();
+            assert_eq!(request.trim_end_matches('\r'), expected);
+            writer.write_all(reply.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn classifies_final_results() {
+        assert_eq!(final_result("OK"), Some(Ok(())));
+        assert_eq!(
+            final_result("+CME ERROR: 10"),
+            Some(Err("+CME ERROR: 10".into()))
+        );
+        assert_eq!(final_result("NO CARRIER"), Some(Err("NO CARRIER".into())));
+        assert_eq!(final_result("+CSQ: 20,99"), None);
+    }
+
+    #[test]
+    fn parses_urcs() {
+        assert_eq!(parse_urc("RING", false), Some(Urc::Ring));
+        assert_eq!(
+            parse_urc("+CLIP: \"+15551234\",145,,,,0", false),
+            Some(Urc::CallerId("+15551234".into()))
+        );
+        assert_eq!(parse_urc("+CMTI: \"SM\",3", false), Some(Urc::NewSms(3)));
+        assert_eq!(
+            parse_urc("+CDSI: \"SR\",4", false),
+            Some(Urc::StatusReport(4))
+        );
+        assert_eq!(parse_urc("+CREG: 5", false), Some(Urc::Registration(5)));
+        assert_eq!(
+            parse_urc("+CREG: 1,\"1A2B\",\"0001C3D4\"", false),
+            Some(Urc::Registration(1))
+        );
+        assert_eq!(parse_urc("+CREG: 1,5", true), None);
+        assert_eq!(parse_urc("NO CARRIER", false), Some(Urc::CallEnded));
+        assert_eq!(parse_urc("NO CARRIER", true), None);
+        assert_eq!(
+            parse_urc("+CUSATP: \"D00981030121808202818F\"", false),
+            Some(Urc::Toolkit("D00981030121808202818F".into()))
+        );
+        assert_eq!(parse_urc("+CUSATEND", true), Some(Urc::ToolkitEnded));
+        assert_eq!(parse_urc("OK", false), None);
+    }
+
+    #[test]
+    fn parses_signal_and_operator() {
+        assert_eq!(parse_csq("+CSQ: 31,99"), Some(Some(100)));
+        assert_eq!(parse_csq("+CSQ: 15,0"), Some(Some(48)));
+        assert_eq!(parse_csq("+CSQ: 99,99"), Some(None));
+        assert_eq!(parse_csq("+COPS: 0"), None);
+        assert_eq!(
+            parse_cops("+COPS: 0,0,\"Carrier\",7"),
+            Some("Carrier".into())
+        );
+        assert_eq!(parse_cops("+COPS: 0"), None);
+        assert_eq!(parse_creg("+CREG: 1,5"), Some(5));
+        assert_eq!(parse_creg("+CREG: 2"), None);
+        assert!(is_registered(1) && is_registered(5));
+        assert!(!is_registered(2));
+    }
+
+    #[test]
+    fn parses_identification_answers() {
+        let lines = |line: &str| vec![line.to_string()];
+        assert_eq!(
+            parse_info(&lines("EG25GGBR07A08M2G"), "+CGMR:").as_deref(),
+            Some("EG25GGBR07A08M2G")
+        );
+        assert_eq!(
+            parse_info(&lines("+CGSN: \"861536030196001\""), "+CGSN:").as_deref(),
+            Some("861536030196001")
+        );
+        assert_eq!(parse_info(&[], "+CGMI:"), None);
+    }
+
+    #[tokio::test]
+    async fn reads_identity() {
+        let (modem, _urcs) = scripted(vec![
+            ("AT+CGSN", "\r\n861536030196001\r\n\r\nOK\r\n"),
+            ("AT+CGMR", "\r\nEG25GGBR07A08M2G\r\n\r\nOK\r\n"),
+            ("AT+CGMI", "\r\nQuectel\r\n\r\nOK\r\n"),
+            ("AT+CGMM", "\r\nEG25\r\n\r\nOK\r\n"),
+        ]);
+        assert_eq!(
+            modem.identity().await.unwrap(),
+            Identity {
+                imei: "861536030196001".into(),
+                firmware: "EG25GGBR07A08M2G".into(),
+                baseband: "Quectel EG25".into(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn command_collects_information_lines() {
+        let (modem, _urcs) = scripted(vec![("AT+CSQ", "\r\n+CSQ: 20,99\r\n\r\nOK\r\n")]);
+        assert_eq!(modem.signal_strength().await.unwrap(), Some(64));
+    }
+
+    #[tokio::test]
+    async fn command_errors_carry_the_result() {
+        let (modem, _urcs) = scripted(vec![("AT+CPIN?", "\r\n+CME ERROR: 10\r\n")]);
+        assert!(!modem.sim_present().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn urcs_are_separated_from_responses() {
+        let (modem, mut urcs) = scripted(vec![(
+            "AT+COPS?",
+            "\r\nRING\r\n\r\n+COPS: 0,0,\"Carrier\",7\r\n\r\n+CMTI: \"SM\",1\r\n\r\nOK\r\n",
+        )]);
+        assert_eq!(modem.operator().await.unwrap().as_deref(), Some("Carrier"));
+        assert_eq!(urcs.recv().await, Some(Urc::Ring));
+        assert_eq!(urcs.recv().await, Some(Urc::NewSms(1)));
+    }
+
+    #[tokio::test]
+    async fn dial_rejects_injected_commands() {
+        let (modem, _urcs) = scripted(vec![("ATD+15551234;", "\r\nOK\r\n")]);
+        assert!(modem.dial("+1555;\rAT+CFUN=0").await.is_err());
+        modem.dial("+15551234").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn merges_calls_into_a_conference() {
+        let (modem, _urcs) = scripted(vec![
+            ("AT+CHLD=3", "\r\nOK\r\n"),
+            (
+                "AT+CLCC",
+                "\r\n+CLCC: 1,0,0,0,1,\"+15550001\",145\r\n\
+                 +CLCC: 2,1,0,0,1,\"+15550002\",145\r\n\
+                 +CLCC: 3,1,5,0,0\r\n\
+                 +CLCC: 4,0,0,1,0,\"*99#\",129\r\n\r\nOK\r\n",
+            ),
+            ("AT+CHLD=12", "\r\n+CME ERROR: 3\r\n"),
+        ]);
+        modem.merge_calls().await.unwrap();
+        let calls = modem.calls().await.unwrap();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(
+            calls[1],
+            Call {
+                index: 2,
+                number: "+15550002".into(),
+                incoming: true,
+                state: CallState::Active,
+                conference: true,
+            }
+        );
+        assert_eq!(
+            (calls[2].state, calls[2].number.as_str()),
+            (CallState::Waiting, "")
+        );
+        // Not every network lets one caller go from a conference
+        assert!(modem.release_call(2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn data_bearer_goes_up_and_down() {
+        let (modem, _urcs) = scripted(vec![
+            ("AT+CGACT=0,1", "\r\nOK\r\n"),
+            ("AT+CGACT=1,1", "\r\nERROR\r\n"),
+        ]);
+        modem.set_data(false).await.unwrap();
+        assert!(modem.set_data(true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn configures_the_sim_carrier_apn() {
+        let (modem, _urcs) = scripted(vec![
+            ("AT+CIMI", "\r\n234150123456789\r\n\r\nOK\r\n"),
+            ("AT+CGDCONT=1,\"IP\",\"wap.vodafone.co.uk\"", "\r\nOK\r\n"),
+            ("AT+CGAUTH=1,1,\"wap\",\"wap\"", "\r\nOK\r\n"),
+        ]);
+        assert_eq!(modem.imsi().await.unwrap(), "234150123456789");
+        let apn = Apn {
+            apn: "wap.vodafone.co.uk".into(),
+            username: "wap".into(),
+            password: "wap".into(),
+            auth: "pap".into(),
+            ip_type: "IP".into(),
+            ..Apn::default()
+        };
+        modem.set_apn(&apn).await.unwrap();
+
+        let injected = Apn {
+            apn: "x\"\rAT+CFUN=0".into(),
+            ..Apn::default()
+        };
+        assert!(modem.set_apn(&injected).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn answers_the_sim_toolkit() {
+        let (modem, _urcs) = scripted(vec![
+            ("AT+CUSATA=1", "\r\nOK\r\n"),
+            ("AT+CUSATE=\"D30782020181900102\"", "\r\nOK\r\n"),
+            ("AT+CUSATT=\"810301210082028281830100\"", "\r\nOK\r\n"),
+        ]);
+        modem.enable_toolkit().await.unwrap();
+        modem.toolkit_envelope("D30782020181900102").await.unwrap();
+        assert!(modem.toolkit_response("00\"\rAT+CFUN=0").await.is_err());
+        modem
+            .toolkit_response("810301210082028281830100")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn sends_sms_after_prompt() {
+        let (modem, _urcs) = scripted(vec![
+            ("AT+CMGS=22", "\r\n> "),
+            (
+                "0021000B916407281553F800000AE8329BFD4697D9EC37\u{1a}",
+                "\r\n+CMGS: 7\r\n\r\nOK\r\n",
+            ),
+        ]);
+        let reference = modem
+            .send_sms("+46708251358", "hellohello", true)
+            .await
+            .unwrap();
+        assert_eq!(reference, 7);
+    }
+
+    #[tokio::test]
+    async fn reads_and_deletes_stored_sms() {
+        let (modem, _urcs) = scripted(vec![
+            (
+                "AT+CMGR=3",
+                "\r\n+CMGR: 0,,28\r\n07917283010010F5040BC87238880900F10000993092516195800AE8329BFD4697D9EC37\r\n\r\nOK\r\n",
+            ),
+            ("AT+CMGD=3", "\r\nOK\r\n"),
+        ]);
+        let sms = modem.take_sms(3).await.unwrap();
+        assert_eq!(sms.text, "hellohello");
+    }
+
+    #[tokio::test]
+    async fn reads_and_deletes_status_reports() {
+        let (modem, _urcs) = scripted(vec![
+            (
+                "AT+CMGR=4",
+                "\r\n+CMGR: 0,,23\r\n000607089151551432421010000000004210100000000000\r\n\r\nOK\r\n",
+            ),
+            ("AT+CMGD=4", "\r\nOK\r\n"),
+        ]);
+        let report = modem.take_status_report(4).await.unwrap();
+        assert_eq!(report.reference, 7);
+        assert_eq!(report.recipient, "+15554123");
+        assert_eq!(report.delivery(), pdu::Delivery::Delivered);
+    }
+
+    #[tokio::test]
+    async fn closed_port_is_an_error() {
+        let (ours, theirs) = tokio::io::duplex(64);
+        drop(theirs);
+        let (reader, writer) = tokio::io::split(ours);
+        let (modem, _urcs) = AtModem::spawn(reader, writer);
+        assert!(modem.command("AT").await.is_err());
+    }
+}
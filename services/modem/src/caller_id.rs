@@ -0,0 +1,249 @@
+// ABOUTME: Caller ID: who a number belongs to and how likely a call from it is spam, as providers apps register say.
+// ABOUTME: Providers on the phone, such as contacts, are asked first; online ones only with the user's leave. Answers are kept in the call log.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Calls made and taken, with who they were with, and whether online providers may be
+/// asked.
+pub const CALL_LOG_PATH: &str = "/data/modem/calls.toml";
+
+/// Calls the log keeps, the oldest dropping off first.
+const CALL_LOG_LENGTH: usize = 200;
+
+/// What a provider says of a number. Empty, with no spam likelihood, where it doesn't
+/// know it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CallerId {
+    pub name: String,
+    /// How likely calls from the number are spam, from 0 to 1.
+    pub spam: f64,
+    /// Which provider said so.
+    pub provider: String,
+}
+
+impl CallerId {
+    pub fn new(provider: &str, name: String, spam: f64) -> Self {
+        Self {
+            name: name.trim().to_string(),
+            spam: if spam.is_finite() {
+                spam.clamp(0.0, 1.0)
+            } else {
+                0.0
+            },
+            provider: provider.to_string(),
+        }
+    }
+
+    /// Whether the provider knew anything of the number.
+    pub fn is_known(&self) -> bool {
+        !self.name.is_empty() || self.spam > 0.0
+    }
+}
+
+/// Where a provider looks numbers up. Numbers only go off the phone once the user lets
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reach {
+    Local,
+    Online,
+}
+
+/// The providers to ask about a number, given as name and reach in the order they
+/// registered: local ones first, then online ones if `online` is allowed.
+pub fn lookup_order<'a>(
+    providers: impl IntoIterator<Item = (&'a str, Reach)>,
+    online: bool,
+) -> Vec<(&'a str, Reach)> {
+    let mut order: Vec<(&str, Reach)> = providers
+        .into_iter()
+        .filter(|(_, reach)| online || *reach == Reach::Local)
+        .collect();
+    order.sort_by_key(|(_, reach)| *reach == Reach::Online);
+    order
+}
+
+/// A call in the log.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggedCall {
+    /// Empty where the caller withheld it.
+    pub number: String,
+    pub incoming: bool,
+    /// When it started, in seconds since the Unix epoch.
+    pub time: u64,
+    #[serde(flatten)]
+    pub caller: CallerId,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CallLog {
+    /// Whether the user lets online providers see the numbers that call.
+    pub online: bool,
+    /// Oldest first.
+    #[serde(rename = "call")]
+    calls: Vec<LoggedCall>,
+}
+
+impl CallLog {
+    /// The log saved at `path`; none yet is an empty one.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let text = toml::to_string(self).context("failed to serialize the call log")?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, text).with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))
+    }
+
+    pub fn list(&self) -> &[LoggedCall] {
+        &self.calls
+    }
+
+    /// What was last found out about `number`, if anything was.
+    pub fn caller(&self, number: &str) -> Option<&CallerId> {
+        self.calls
+            .iter()
+            .rev()
+            .filter(|call| !number.is_empty() && call.number == number)
+            .map(|call| &call.caller)
+            .find(|caller| caller.is_known())
+    }
+
+    /// Log a call with `number` starting at `time`, with who it is as far as is known.
+    pub fn record(&mut self, number: &str, incoming: bool, time: u64) {
+        let caller = self.caller(number).cloned().unwrap_or_default();
+        self.calls.push(LoggedCall {
+            number: number.to_string(),
+            incoming,
+            time,
+            caller,
+        });
+        let excess = self.calls.len().saturating_sub(CALL_LOG_LENGTH);
+        self.calls.drain(..excess);
+    }
+
+    /// Give the last call logged `number`, if it was logged without one, as a caller's
+    /// number comes after RING does.
+    pub fn numbered(&mut self, number: &str) {
+        let caller = self.caller(number).cloned().unwrap_or_default();
+        if let Some(call) = self.calls.last_mut()
+            && call.number.is_empty()
+        {
+            call.number = number.to_string();
+            call.caller = caller;
+        }
+    }
+
+    /// Keep what was found out about `number` with its last call, for the log to show
+    /// and for the next call from it. Whether anything changed.
+    pub fn identify(&mut self, number: &str, caller: &CallerId) -> bool {
+        if number.is_empty() || !caller.is_known() {
+            return false;
+        }
+        match self
+            .calls
+            .iter_mut()
+            .rev()
+            .find(|call| call.number == number)
+        {
+            Some(call) if call.caller != *caller => {
+                call.caller = caller.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_providers_are_asked_before_online_ones() {
+        let providers = [
+            ("spam-check", Reach::Online),
+            ("contacts", Reach::Local),
+            ("work-directory", Reach::Local),
+        ];
+        assert_eq!(
+            lookup_order(providers, true),
+            [
+                ("contacts", Reach::Local),
+                ("work-directory", Reach::Local),
+                ("spam-check", Reach::Online),
+            ]
+        );
+        assert_eq!(
+            lookup_order(providers, false),
+            [("contacts", Reach::Local), ("work-directory", Reach::Local)]
+        );
+    }
+
+    #[test]
+    fn the_call_log_remembers_callers() {
+        let mut log = CallLog::default();
+        log.record("+15550001", true, 100);
+        assert!(log.caller("+15550001").is_none());
+
+        let spam = CallerId::new("spam-check", " ".to_string(), 7.0);
+        assert_eq!(spam.spam, 1.0);
+        assert!(spam.is_known());
+        assert!(!log.identify("+15550002", &spam));
+        assert!(log.identify("+15550001", &spam));
+        assert!(!log.identify("+15550001", &spam));
+        assert!(!log.identify("+15550001", &CallerId::default()));
+
+        // The next call from the number is known before anyone is asked
+        log.record("+15550001", true, 200);
+        assert_eq!(log.list()[1].caller, spam);
+        log.record("", true, 300);
+        assert!(log.caller("").is_none());
+        log.numbered("+15550001");
+        assert_eq!(log.list()[2].caller, spam);
+        log.numbered("+15550002");
+        assert_eq!(log.list()[2].number, "+15550001");
+
+        for time in 0..CALL_LOG_LENGTH as u64 {
+            log.record("+15550003", false, 1000 + time);
+        }
+        assert_eq!(log.list().len(), CALL_LOG_LENGTH);
+        assert!(log.caller("+15550001").is_none());
+    }
+
+    #[test]
+    fn the_call_log_is_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modem/calls.toml");
+        assert_eq!(CallLog::load(&path).unwrap(), CallLog::default());
+
+        let mut log = CallLog {
+            online: true,
+            ..Default::default()
+        };
+        log.record("+15550001", true, 100);
+        log.identify(
+            "+15550001",
+            &CallerId::new("contacts", "Mum".to_string(), 0.0),
+        );
+        log.save(&path).unwrap();
+        assert_eq!(CallLog::load(&path).unwrap(), log);
+    }
+}
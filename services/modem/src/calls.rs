@@ -0,0 +1,288 @@
+// ABOUTME: The calls up at once, as AT+CLCC lists them: active, held or ringing, and which form a conference.
+// ABOUTME: Changed as AT+CHLD changes them, to check a request before the modem gets it and to stand in for one.
+
+use anyhow::{Result, bail};
+
+/// Where a call is, numbered as +CLCC has it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallState {
+    Active,
+    Held,
+    Dialing,
+    Alerting,
+    Incoming,
+    /// Ringing while another call is up.
+    Waiting,
+}
+
+impl CallState {
+    pub fn from_clcc(stat: u8) -> Option<Self> {
+        Some(match stat {
+            0 => CallState::Active,
+            1 => CallState::Held,
+            2 => CallState::Dialing,
+            3 => CallState::Alerting,
+            4 => CallState::Incoming,
+            5 => CallState::Waiting,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CallState::Active => "active",
+            CallState::Held => "held",
+            CallState::Dialing => "dialing",
+            CallState::Alerting => "alerting",
+            CallState::Incoming => "incoming",
+            CallState::Waiting => "waiting",
+        }
+    }
+
+    fn is_up(self) -> bool {
+        matches!(
+            self,
+            CallState::Active | CallState::Dialing | CallState::Alerting
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    /// The modem's number for the call, from 1, which AT+CHLD takes.
+    pub index: u8,
+    /// Empty where the caller withheld it.
+    pub number: String,
+    pub incoming: bool,
+    pub state: CallState,
+    /// Whether the call is one leg of a conference.
+    pub conference: bool,
+}
+
+/// The calls the modem has, in its order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Calls {
+    calls: Vec<Call>,
+}
+
+impl Calls {
+    pub fn new(calls: Vec<Call>) -> Self {
+        Self { calls }
+    }
+
+    pub fn list(&self) -> &[Call] {
+        &self.calls
+    }
+
+    /// The legs of the conference, none if there isn't one.
+    pub fn conference(&self) -> Vec<&Call> {
+        self.calls.iter().filter(|call| call.conference).collect()
+    }
+
+    pub fn get(&self, index: u8) -> Option<&Call> {
+        self.calls.iter().find(|call| call.index == index)
+    }
+
+    /// What ModemState says of them: "idle" without calls, "ringing" while the only
+    /// ones ring, and "in-call" otherwise.
+    pub fn modem_state(&self) -> &'static str {
+        if self.calls.is_empty() {
+            "idle"
+        } else if self
+            .calls
+            .iter()
+            .all(|call| matches!(call.state, CallState::Incoming | CallState::Waiting))
+        {
+            "ringing"
+        } else {
+            "in-call"
+        }
+    }
+
+    fn has(&self, state: CallState) -> bool {
+        self.calls.iter().any(|call| call.state == state)
+    }
+
+    /// Whether dialing has to hold the active call first, as a phone only has one up.
+    pub fn must_hold_to_dial(&self) -> bool {
+        self.has(CallState::Active)
+    }
+
+    fn next_index(&self) -> u8 {
+        (1..=u8::MAX)
+            .find(|index| self.get(*index).is_none())
+            .unwrap_or(u8::MAX)
+    }
+
+    /// Call `number`, holding the active call. There is no room for another while one
+    /// call is held and another up.
+    pub fn dial(&mut self, number: &str) -> Result<()> {
+        if self.has(CallState::Held) && self.calls.iter().any(|call| call.state.is_up()) {
+            bail!("a call is already on hold: end or merge it before calling again");
+        }
+        self.hold_active();
+        let index = self.next_index();
+        self.calls.push(Call {
+            index,
+            number: number.to_string(),
+            incoming: false,
+            state: CallState::Active,
+            conference: false,
+        });
+        Ok(())
+    }
+
+    /// A call comes in from `number`, waiting if another is up.
+    pub fn ring(&mut self, number: &str) {
+        let state = if self.calls.is_empty() {
+            CallState::Incoming
+        } else {
+            CallState::Waiting
+        };
+        let index = self.next_index();
+        self.calls.push(Call {
+            index,
+            number: number.to_string(),
+            incoming: true,
+            state,
+            conference: false,
+        });
+    }
+
+    fn hold_active(&mut self) {
+        for call in &mut self.calls {
+            if call.state == CallState::Active {
+                call.state = CallState::Held;
+            }
+        }
+    }
+
+    /// AT+CHLD=2: hold the active call and take the waiting one, or else swap the
+    /// active and held ones.
+    pub fn swap(&mut self) -> Result<()> {
+        let next = if self.has(CallState::Waiting) {
+            CallState::Waiting
+        } else {
+            CallState::Held
+        };
+        if !self.has(next) && !self.has(CallState::Active) {
+            bail!("there is no call to hold or take");
+        }
+        for call in &mut self.calls {
+            call.state = match call.state {
+                CallState::Active => CallState::Held,
+                state if state == next => CallState::Active,
+                state => state,
+            };
+        }
+        Ok(())
+    }
+
+    /// AT+CHLD=3: bring the held call into the active one, making a conference.
+    pub fn merge(&mut self) -> Result<()> {
+        if !self.has(CallState::Active) || !self.has(CallState::Held) {
+            bail!("a conference needs an active and a held call");
+        }
+        for call in &mut self.calls {
+            if matches!(call.state, CallState::Active | CallState::Held) {
+                call.state = CallState::Active;
+                call.conference = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// AT+CHLD=1x: end call `index` alone, such as one caller in a conference. A
+    /// conference left with one caller is an ordinary call with them.
+    pub fn release(&mut self, index: u8) -> Result<()> {
+        let Some(position) = self.calls.iter().position(|call| call.index == index) else {
+            bail!("there is no call {index}");
+        };
+        self.calls.remove(position);
+        let legs: Vec<u8> = self.conference().iter().map(|call| call.index).collect();
+        if let [last] = legs[..]
+            && let Some(call) = self.calls.iter_mut().find(|call| call.index == last)
+        {
+            call.conference = false;
+        }
+        Ok(())
+    }
+
+    /// ATH: every call ends.
+    pub fn end_all(&mut self) {
+        self.calls.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_merge_into_a_conference() {
+        let mut calls = Calls::default();
+        assert_eq!(calls.modem_state(), "idle");
+        calls.dial("+15550001").unwrap();
+        assert_eq!(calls.modem_state(), "in-call");
+        assert!(calls.merge().is_err());
+
+        calls.dial("+15550002").unwrap();
+        let states: Vec<CallState> = calls.list().iter().map(|call| call.state).collect();
+        assert_eq!(states, [CallState::Held, CallState::Active]);
+        assert!(calls.dial("+15550003").is_err());
+
+        calls.swap().unwrap();
+        assert_eq!(calls.get(1).unwrap().state, CallState::Active);
+        assert_eq!(calls.get(2).unwrap().state, CallState::Held);
+
+        calls.merge().unwrap();
+        let legs: Vec<u8> = calls.conference().iter().map(|call| call.index).collect();
+        assert_eq!(legs, [1, 2]);
+        assert!(
+            calls
+                .list()
+                .iter()
+                .all(|call| call.state == CallState::Active)
+        );
+
+        // A third caller joins the conference the same way
+        calls.dial("+15550003").unwrap();
+        assert_eq!(calls.get(1).unwrap().state, CallState::Held);
+        calls.merge().unwrap();
+        assert_eq!(calls.conference().len(), 3);
+    }
+
+    #[test]
+    fn callers_leave_a_conference_one_at_a_time() {
+        let mut calls = Calls::default();
+        calls.dial("+15550001").unwrap();
+        calls.dial("+15550002").unwrap();
+        calls.merge().unwrap();
+
+        calls.release(1).unwrap();
+        assert!(calls.conference().is_empty());
+        assert_eq!(calls.get(2).unwrap().state, CallState::Active);
+        assert!(calls.release(1).is_err());
+
+        calls.release(2).unwrap();
+        assert_eq!(calls.modem_state(), "idle");
+    }
+
+    #[test]
+    fn waiting_calls_are_taken_by_holding() {
+        let mut calls = Calls::default();
+        assert!(calls.swap().is_err());
+        calls.ring("+15550001");
+        assert_eq!(calls.modem_state(), "ringing");
+        assert_eq!(calls.get(1).unwrap().state, CallState::Incoming);
+
+        calls.end_all();
+        calls.dial("+15550001").unwrap();
+        calls.ring("+15550002");
+        assert_eq!(calls.get(2).unwrap().state, CallState::Waiting);
+        calls.swap().unwrap();
+        assert_eq!(calls.get(1).unwrap().state, CallState::Held);
+        assert_eq!(calls.get(2).unwrap().state, CallState::Active);
+        assert!(calls.get(2).unwrap().incoming);
+    }
+}
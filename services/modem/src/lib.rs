@@ -0,0 +1,10 @@
+// ABOUTME: Library half of mos-modem, for code shared with tools outside the service.
+// ABOUTME: The AT backend, call list, caller ID and call log, SMS PDU codec and outbox, SIM toolkit codec and APN database, also driven by the virtual modem's tests.
+
+pub mod apn;
+pub mod at;
+pub mod caller_id;
+pub mod calls;
+pub mod pdu;
+pub mod sms;
+pub mod stk;
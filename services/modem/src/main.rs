@@ -1,20 +1,145 @@
 // ABOUTME: Modem management D-Bus daemon for MobileOS.
-// ABOUTME: Exposes signal strength, call state, and SMS over org.mobileos.Modem.
+// ABOUTME: Exposes signal strength, calls and conferences, caller ID and the call log, SMS and their delivery, mobile data, the carrier's APN, the SIM toolkit and the modem's identity over org.mobileos.Modem.
 
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use tracing::info;
-use zbus::{connection, interface};
+use futures_util::future::{self, Either};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use zbus::message::Header;
+use zbus::names::{BusName, UniqueName};
+use zbus::object_server::{InterfaceRef, SignalEmitter};
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{Connection, connection, interface};
+
+use mos_modem::apn::{self, Apn, ApnDatabase};
+use mos_modem::at::{self, AtModem, Identity, Urc};
+use mos_modem::caller_id::{self, CallLog, CallerId, Reach};
+use mos_modem::calls::{CallState, Calls};
+use mos_modem::pdu::{self, Delivery, StatusReport};
+use mos_modem::sms::{self, Outbox};
+use mos_modem::stk::{self, Command, Item, Outcome, Proactive};
+
+mod access;
+
+/// Serial port of an AT-command modem, skipping the probe of /dev/ttyUSB*.
+const PORT_ENV: &str = "MOS_MODEM_PORT";
+
+/// How often signal strength is read from an AT-command modem.
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The interface a registered caller ID provider serves.
+const CALLER_ID_INTERFACE: &str = "org.mobileos.CallerIdProvider";
+
+/// How long a caller ID provider has to answer before the next one is asked, so the
+/// phone still rings with a name while it does.
+const CALLER_ID_TIMEOUT: Duration = Duration::from_secs(2);
 
 struct ModemState {
     signal_strength: u8,
     operator: String,
     sim_present: bool,
+    /// Whether the modem is on a network, so messages can go out.
+    registered: bool,
     modem_state: String,
+    /// The calls up, ringing or on hold, as the modem last listed them.
+    calls: Calls,
+    /// Whether the data bearer is up.
+    data_enabled: bool,
+    identity: Identity,
+    /// The SIM's IMSI, which names its carrier. Like the IMEI it identifies the user,
+    /// so it stays in here.
+    imsi: String,
+    /// The APN the data bearer uses, if the carrier is known or the user entered one.
+    apn: Option<Apn>,
+    /// Whether the APN is the user's rather than the database's.
+    apn_manual: bool,
+    /// The SIM toolkit's menu title and items, no items if the SIM has no menu.
+    toolkit_menu: (String, Vec<Item>),
+    /// The toolkit command waiting on the user: text to clear or a choice to make.
+    toolkit_prompt: Option<Proactive>,
+    /// Calls made and taken, and who with.
+    call_log: CallLog,
+    /// Messages sent, and the ones waiting to go.
+    outbox: Outbox,
+}
+
+/// A call on the bus: its index, number, state, whether it came in, and whether it is
+/// in the conference.
+type CallEntry = (u8, String, String, bool, bool);
+
+/// A call in the log on the bus: number, whether it came in, when it started in
+/// seconds since the Unix epoch, the caller's name and how likely it was spam.
+type LoggedCallEntry = (String, bool, u64, String, f64);
+
+/// Who a number is on the bus: name, spam likelihood from 0 to 1, and the provider
+/// that said so. All empty or 0 where no one knows.
+type CallerIdEntry = (String, f64, String);
+
+/// A message in the outbox on the bus: its id, number, text, when it was written in
+/// seconds since the Unix epoch, and "pending", "sent", "delivered" or "failed".
+type OutgoingSmsEntry = (u32, String, String, u64, String);
+
+/// An APN on the bus: carrier, APN, user name, password, authentication, IP type,
+/// MMSC and MMS proxy. All empty for none.
+type ApnEntry = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+);
+
+/// A caller ID provider an app registered, served by the app itself.
+#[derive(Clone)]
+struct CallerIdProvider {
+    name: String,
+    owner: UniqueName<'static>,
+    path: OwnedObjectPath,
+    reach: Reach,
+}
+
+impl CallerIdProvider {
+    async fn look_up(&self, connection: &Connection, number: &str) -> zbus::Result<CallerId> {
+        let proxy = zbus::Proxy::new(
+            connection,
+            BusName::from(self.owner.clone()),
+            self.path.clone(),
+            CALLER_ID_INTERFACE,
+        )
+        .await?;
+        let (name, spam): (String, f64) = proxy.call("LookUp", &(number,)).await?;
+        Ok(CallerId::new(&self.name, name, spam))
+    }
 }
 
 struct ModemService {
     state: Arc<Mutex<ModemState>>,
+    /// Direct AT-command backend. Without one the service simulates a modem.
+    at: Option<Arc<AtModem>>,
+    /// Uids whose calls to Imei are answered.
+    imei_readers: HashSet<u32>,
+    /// Uids granted the network: caller ID providers running under them are online.
+    network_uids: HashSet<u32>,
+    /// The executable whose calls to SetOnlineCallerId are answered.
+    settings_app: PathBuf,
+    apns: ApnDatabase,
+    /// Where an APN the user entered is kept. Without one, entering an APN is refused.
+    apn_path: Option<PathBuf>,
+    /// In the order they registered.
+    caller_id_providers: Vec<CallerIdProvider>,
+    /// Where the call log is kept. Without one it lasts until the service stops.
+    call_log_path: Option<PathBuf>,
+    /// Where the outbox is kept. Without one it lasts until the service stops.
+    outbox_path: Option<PathBuf>,
+    /// Held while the outbox is being sent, so each message goes out once.
+    sending: tokio::sync::Mutex<()>,
 }
 
 impl ModemService {
@@ -24,10 +149,369 @@ impl ModemService {
                 signal_strength: 75,
                 operator: "MobileOS Carrier".to_string(),
                 sim_present: true,
+                registered: true,
                 modem_state: "idle".to_string(),
+                calls: Calls::default(),
+                data_enabled: true,
+                identity: Identity {
+                    imei: "000000000000000".to_string(),
+                    firmware: "simulated".to_string(),
+                    baseband: "MobileOS simulated modem".to_string(),
+                },
+                // The test network, 001/01
+                imsi: "001010000000000".to_string(),
+                apn: None,
+                apn_manual: false,
+                toolkit_menu: Default::default(),
+                toolkit_prompt: None,
+                call_log: CallLog::default(),
+                outbox: Outbox::default(),
             })),
+            at: None,
+            imei_readers: HashSet::from([0]),
+            network_uids: HashSet::from([0]),
+            settings_app: PathBuf::from(access::SETTINGS_EXE),
+            apns: ApnDatabase::default(),
+            apn_path: None,
+            caller_id_providers: Vec::new(),
+            call_log_path: None,
+            outbox_path: None,
+            sending: tokio::sync::Mutex::new(()),
         }
     }
+
+    fn with_at_modem(mut self, modem: Arc<AtModem>) -> Self {
+        self.at = Some(modem);
+        self
+    }
+
+    fn with_imei_readers(mut self, uids: HashSet<u32>) -> Self {
+        self.imei_readers = uids;
+        self
+    }
+
+    fn with_network_uids(mut self, uids: HashSet<u32>) -> Self {
+        self.network_uids = uids;
+        self
+    }
+
+    fn with_apns(mut self, apns: ApnDatabase) -> Self {
+        self.apns = apns;
+        self
+    }
+
+    fn with_apn_path(mut self, path: PathBuf) -> Self {
+        self.apn_path = Some(path);
+        self
+    }
+
+    fn with_call_log(mut self, path: PathBuf, log: CallLog) -> Self {
+        self.state.lock().unwrap().call_log = log;
+        self.call_log_path = Some(path);
+        self
+    }
+
+    fn with_outbox(mut self, path: PathBuf, outbox: Outbox) -> Self {
+        self.state.lock().unwrap().outbox = outbox;
+        self.outbox_path = Some(path);
+        self
+    }
+
+    /// Set the data bearer up for the SIM: the user's APN if they entered one, else
+    /// the one the database has for the SIM's carrier. Runs at startup and whenever a
+    /// SIM is inserted.
+    async fn configure_apn(&self) -> anyhow::Result<()> {
+        if let Some(at) = &self.at {
+            let imsi = at.imsi().await?;
+            self.state.lock().unwrap().imsi = imsi;
+        }
+        let manual = match &self.apn_path {
+            Some(path) => Apn::load(path)?,
+            None => None,
+        };
+        let apn_manual = manual.is_some();
+        let imsi = self.state.lock().unwrap().imsi.clone();
+        let apn = manual.or_else(|| self.apns.lookup(&imsi).cloned());
+        match &apn {
+            Some(apn) => info!(carrier = %apn.carrier, apn = %apn.apn, apn_manual, "APN chosen"),
+            None => warn!("no APN known for the SIM's carrier, enter one in settings"),
+        }
+        if let Some(apn) = &apn {
+            self.apply_apn(apn).await?;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.apn = apn;
+        state.apn_manual = apn_manual;
+        Ok(())
+    }
+
+    /// Give the modem `apn`, restarting the bearer so it takes effect if data is on.
+    async fn apply_apn(&self, apn: &Apn) -> anyhow::Result<()> {
+        let Some(at) = &self.at else {
+            return Ok(());
+        };
+        // Most modems refuse to redefine a context that is up
+        at.set_data(false).await?;
+        at.set_apn(apn).await?;
+        if self.state.lock().unwrap().data_enabled {
+            at.set_data(true).await?;
+        }
+        Ok(())
+    }
+}
+
+impl ModemService {
+    /// The calls as `change` leaves them, refused if it can't be done with them as
+    /// they are.
+    fn planned_calls(
+        &self,
+        change: impl FnOnce(&mut Calls) -> anyhow::Result<()>,
+    ) -> zbus::fdo::Result<Calls> {
+        let mut calls = self.state.lock().unwrap().calls.clone();
+        change(&mut calls).map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e:#}")))?;
+        Ok(calls)
+    }
+
+    /// Take up the calls the modem now lists, or `expected` without a modem or where
+    /// it can't list them, and tell the dialer and the power service.
+    async fn update_calls(&self, expected: Calls, emitter: &SignalEmitter<'_>) -> zbus::Result<()> {
+        let calls = match &self.at {
+            Some(at) => match at.calls().await {
+                Ok(calls) => Calls::new(calls),
+                Err(e) => {
+                    warn!(error = %e, "failed to list calls");
+                    expected
+                }
+            },
+            None => expected,
+        };
+        let logged = {
+            let mut state = self.state.lock().unwrap();
+            let logged = log_calls(&mut state, &calls);
+            state.modem_state = calls.modem_state().to_string();
+            state.calls = calls;
+            logged
+        };
+        if logged {
+            self.save_call_log();
+            self.call_log_changed(emitter).await?;
+        }
+        self.modem_state_changed(emitter).await?;
+        self.calls_changed(emitter).await?;
+        self.conference_changed(emitter).await
+    }
+
+    fn save_call_log(&self) {
+        if let Some(path) = &self.call_log_path {
+            let log = self.state.lock().unwrap().call_log.clone();
+            if let Err(e) = log.save(path) {
+                warn!(error = %e, "failed to save the call log");
+            }
+        }
+    }
+
+    /// Who the first of `providers` that knows `number` says it is.
+    async fn ask_providers(
+        connection: &Connection,
+        providers: &[CallerIdProvider],
+        number: &str,
+    ) -> Option<CallerId> {
+        for provider in providers {
+            // Interface methods run on the bus's executor rather than tokio's, so the
+            // timer is one that needs no runtime
+            let lookup = std::pin::pin!(provider.look_up(connection, number));
+            match future::select(lookup, async_io::Timer::after(CALLER_ID_TIMEOUT)).await {
+                Either::Left((Ok(caller), _)) if caller.is_known() => return Some(caller),
+                Either::Left((Ok(_), _)) => {}
+                Either::Left((Err(e), _)) => {
+                    warn!(provider = %provider.name, error = %e, "caller ID provider failed")
+                }
+                Either::Right(_) => warn!(provider = %provider.name, "caller ID provider too slow"),
+            }
+        }
+        None
+    }
+}
+
+/// Log the calls in `calls` that `state` doesn't have yet, and the numbers of those it
+/// had without one. Whether the log changed.
+fn log_calls(state: &mut ModemState, calls: &Calls) -> bool {
+    let now = unix_time();
+    let mut logged = false;
+    for call in calls.list() {
+        match state.calls.get(call.index) {
+            Some(known) if known.incoming == call.incoming => {
+                if known.number.is_empty() && !call.number.is_empty() {
+                    state.call_log.numbered(&call.number);
+                    logged = true;
+                }
+            }
+            _ => {
+                state.call_log.record(&call.number, call.incoming, now);
+                logged = true;
+            }
+        }
+    }
+    logged
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+impl ModemService {
+    /// Send the messages waiting in the outbox, if the modem is on a network. Those the
+    /// network refuses wait to be tried again, up to `sms::MAX_ATTEMPTS` times. Whether
+    /// any were tried.
+    async fn send_outbox(&self) -> bool {
+        let _sending = self.sending.lock().await;
+        let pending = {
+            let state = self.state.lock().unwrap();
+            if !state.registered {
+                return false;
+            }
+            state.outbox.pending()
+        };
+        for message in &pending {
+            let sent = match &self.at {
+                Some(at) => at.send_sms(&message.number, &message.text, true).await,
+                None => Ok(0),
+            };
+            let mut state = self.state.lock().unwrap();
+            match sent {
+                Ok(reference) => {
+                    info!(id = message.id, reference, "SMS sent");
+                    state.outbox.sent(message.id, reference);
+                    // Without a modem, every message arrives
+                    if self.at.is_none() {
+                        state.outbox.report(reference, Delivery::Delivered);
+                    }
+                }
+                Err(e) => {
+                    let status = state.outbox.refused(message.id).as_str();
+                    warn!(id = message.id, status, error = %e, "failed to send SMS");
+                }
+            }
+        }
+        !pending.is_empty()
+    }
+
+    /// Send the messages waiting in the outbox, and tell the messages app how they went.
+    async fn flush_outbox(&self, emitter: &SignalEmitter<'_>) -> zbus::Result<()> {
+        if !self.send_outbox().await {
+            return Ok(());
+        }
+        self.save_outbox();
+        self.outbox_changed(emitter).await
+    }
+
+    /// Mark the message `report` is on delivered or failed, once the network knows.
+    async fn delivery_reported(
+        &self,
+        report: &StatusReport,
+        emitter: &SignalEmitter<'_>,
+    ) -> zbus::Result<()> {
+        let settled = self
+            .state
+            .lock()
+            .unwrap()
+            .outbox
+            .report(report.reference, report.delivery());
+        if !settled {
+            return Ok(());
+        }
+        self.save_outbox();
+        self.outbox_changed(emitter).await
+    }
+
+    fn save_outbox(&self) {
+        if let Some(path) = &self.outbox_path {
+            let outbox = self.state.lock().unwrap().outbox.clone();
+            if let Err(e) = outbox.save(path) {
+                warn!(error = %e, "failed to save the outbox");
+            }
+        }
+    }
+}
+
+impl ModemService {
+    /// Carry out a proactive command from the SIM: keep its menu, put prompts to the
+    /// user, send its SMS, and turn down what the phone doesn't do. Prompts are
+    /// answered once the user has.
+    async fn proactive(&self, proactive: Proactive) -> anyhow::Result<()> {
+        let Proactive { details, command } = proactive;
+        let outcome = match command {
+            Command::SetUpMenu { title, items } => {
+                info!(title = %title, items = items.len(), "SIM toolkit menu");
+                self.state.lock().unwrap().toolkit_menu = (title, items);
+                Outcome::Done
+            }
+            Command::DisplayText { .. } | Command::SelectItem { .. } => {
+                self.state.lock().unwrap().toolkit_prompt = Some(Proactive { details, command });
+                return Ok(());
+            }
+            Command::SendSms {
+                alpha,
+                recipient,
+                text,
+            } => {
+                info!(alpha = %alpha, "SIM toolkit sending an SMS");
+                match &self.at {
+                    Some(at) => match at.send_sms(&recipient, &text, false).await {
+                        Ok(_) => Outcome::Done,
+                        Err(e) => {
+                            warn!(error = %e, "failed to send the SIM toolkit's SMS");
+                            Outcome::NetworkRefused
+                        }
+                    },
+                    None => Outcome::Done,
+                }
+            }
+            Command::Unsupported => {
+                info!(kind = details.kind, "SIM toolkit command not supported");
+                Outcome::Unsupported
+            }
+        };
+        self.toolkit_respond(details, outcome, None).await
+    }
+
+    /// Answer the toolkit prompt, if one is up, and take it down.
+    async fn answer_prompt(&self, outcome: Outcome, item: Option<u8>) -> zbus::fdo::Result<()> {
+        let Some(prompt) = self.state.lock().unwrap().toolkit_prompt.take() else {
+            return Err(zbus::fdo::Error::Failed(
+                "the SIM toolkit is asking nothing".to_string(),
+            ));
+        };
+        self.toolkit_respond(prompt.details, outcome, item)
+            .await
+            .map_err(failed)
+    }
+
+    async fn toolkit_respond(
+        &self,
+        details: stk::Details,
+        outcome: Outcome,
+        item: Option<u8>,
+    ) -> anyhow::Result<()> {
+        if let Some(at) = &self.at {
+            at.toolkit_response(&stk::terminal_response(details, outcome, item))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn failed(e: anyhow::Error) -> zbus::fdo::Error {
+    zbus::fdo::Error::Failed(format!("{e:#}"))
+}
+
+fn sender(header: &Header<'_>) -> zbus::fdo::Result<UniqueName<'static>> {
+    header
+        .sender()
+        .map(|sender| sender.to_owned())
+        .ok_or_else(|| zbus::fdo::Error::AccessDenied("caller unknown".to_string()))
 }
 
 #[interface(name = "org.mobileos.Modem")]
@@ -52,19 +536,590 @@ impl ModemService {
         self.state.lock().unwrap().modem_state.clone()
     }
 
-    async fn dial(&self, number: String) {
+    #[zbus(property)]
+    fn data_enabled(&self) -> bool {
+        self.state.lock().unwrap().data_enabled
+    }
+
+    /// Bring mobile data up or down. The network service takes it down at the data
+    /// cap.
+    async fn set_data_enabled(
+        &self,
+        enabled: bool,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        info!(enabled, "mobile data");
+        if let Some(at) = &self.at {
+            at.set_data(enabled).await.map_err(failed)?;
+        }
+        self.state.lock().unwrap().data_enabled = enabled;
+        self.data_enabled_changed(&emitter).await?;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn apn(&self) -> ApnEntry {
+        let Some(apn) = self.state.lock().unwrap().apn.clone() else {
+            return ApnEntry::default();
+        };
+        (
+            apn.carrier,
+            apn.apn,
+            apn.username,
+            apn.password,
+            apn.auth,
+            apn.ip_type,
+            apn.mmsc,
+            apn.mms_proxy,
+        )
+    }
+
+    #[zbus(property)]
+    fn apn_manual(&self) -> bool {
+        self.state.lock().unwrap().apn_manual
+    }
+
+    /// Use an APN the user entered instead of the database's, until ResetApn. Without
+    /// a carrier name, the network the modem is registered with names it.
+    async fn set_apn(
+        &self,
+        apn: ApnEntry,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let Some(path) = &self.apn_path else {
+            return Err(zbus::fdo::Error::Failed(
+                "there is nowhere to keep an APN".to_string(),
+            ));
+        };
+        let (carrier, apn, username, password, auth, ip_type, mmsc, mms_proxy) = apn;
+        let carrier = match carrier.trim() {
+            "" => self.state.lock().unwrap().operator.clone(),
+            carrier => carrier.to_string(),
+        };
+        let apn = Apn {
+            carrier,
+            apn,
+            username,
+            password,
+            auth,
+            ip_type,
+            mmsc,
+            mms_proxy,
+        };
+        apn.validate()
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e:#}")))?;
+        info!(apn = %apn.apn, "APN entered");
+        self.apply_apn(&apn).await.map_err(failed)?;
+        apn.save(path).map_err(failed)?;
+        {
+            let mut state = self.state.lock().unwrap();
+            state.apn = Some(apn);
+            state.apn_manual = true;
+        }
+        self.apn_changed(&emitter).await?;
+        self.apn_manual_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// Forget the APN the user entered and go back to the carrier's from the database.
+    async fn reset_apn(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        if let Some(path) = &self.apn_path {
+            Apn::forget(path).map_err(failed)?;
+        }
+        info!("APN reset to the carrier's");
+        self.configure_apn().await.map_err(failed)?;
+        self.apn_changed(&emitter).await?;
+        self.apn_manual_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// The SIM's own menu of carrier services: its title, and items by id. No items
+    /// when the SIM has none.
+    #[zbus(property)]
+    fn toolkit_menu(&self) -> (String, Vec<Item>) {
+        self.state.lock().unwrap().toolkit_menu.clone()
+    }
+
+    /// What the SIM toolkit asks of the user: "text" to show until cleared, or
+    /// "select" to choose one of the items; "" while it asks nothing. Then the text or
+    /// the choice's title.
+    #[zbus(property)]
+    fn toolkit_prompt(&self) -> (String, String, Vec<Item>) {
+        match &self.state.lock().unwrap().toolkit_prompt {
+            Some(Proactive {
+                command: Command::DisplayText { text },
+                ..
+            }) => ("text".to_string(), text.clone(), Vec::new()),
+            Some(Proactive {
+                command: Command::SelectItem { title, items },
+                ..
+            }) => ("select".to_string(), title.clone(), items.clone()),
+            _ => Default::default(),
+        }
+    }
+
+    /// Open `item` of the SIM's menu. What it does next comes as ToolkitPrompt.
+    async fn open_toolkit_item(&self, item: u8) -> zbus::fdo::Result<()> {
+        let known = self
+            .state
+            .lock()
+            .unwrap()
+            .toolkit_menu
+            .1
+            .iter()
+            .any(|(id, _)| *id == item);
+        if !known {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "the SIM's menu has no item {item}"
+            )));
+        }
+        info!(item, "SIM toolkit menu item opened");
+        if let Some(at) = &self.at {
+            at.toolkit_envelope(&stk::menu_selection(item))
+                .await
+                .map_err(failed)?;
+        }
+        Ok(())
+    }
+
+    /// Answer the toolkit prompt: the text was read, or `item` was chosen.
+    async fn answer_toolkit(
+        &self,
+        item: u8,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let choice = match &self.state.lock().unwrap().toolkit_prompt {
+            Some(Proactive {
+                command: Command::SelectItem { items, .. },
+                ..
+            }) => {
+                if !items.iter().any(|(id, _)| *id == item) {
+                    return Err(zbus::fdo::Error::InvalidArgs(format!(
+                        "there is no choice {item}"
+                    )));
+                }
+                Some(item)
+            }
+            _ => None,
+        };
+        self.answer_prompt(Outcome::Done, choice).await?;
+        self.toolkit_prompt_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// Leave the toolkit prompt unanswered: `back` a step, or else out of the SIM's
+    /// session altogether.
+    async fn leave_toolkit(
+        &self,
+        back: bool,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let outcome = if back { Outcome::Back } else { Outcome::Ended };
+        self.answer_prompt(outcome, None).await?;
+        self.toolkit_prompt_changed(&emitter).await?;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn firmware_version(&self) -> String {
+        self.state.lock().unwrap().identity.firmware.clone()
+    }
+
+    #[zbus(property)]
+    fn baseband(&self) -> String {
+        self.state.lock().unwrap().identity.baseband.clone()
+    }
+
+    /// The IMEI identifies the phone to every network it meets, so unlike the other
+    /// identity values it is a method only root and the radio group may call.
+    async fn imei(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> zbus::fdo::Result<String> {
+        let sender = header
+            .sender()
+            .ok_or_else(|| zbus::fdo::Error::AccessDenied("caller unknown".to_string()))?;
+        let uid = zbus::fdo::DBusProxy::new(connection)
+            .await?
+            .get_connection_unix_user(sender.clone().into())
+            .await?;
+        if !self.imei_readers.contains(&uid) {
+            warn!(uid, "refused the IMEI to a caller outside the radio group");
+            return Err(zbus::fdo::Error::AccessDenied(format!(
+                "reading the IMEI needs the {} group",
+                access::GROUP
+            )));
+        }
+        Ok(self.state.lock().unwrap().identity.imei.clone())
+    }
+
+    /// The calls up, ringing or on hold. States are "active", "held", "dialing",
+    /// "alerting", "incoming" and "waiting".
+    #[zbus(property)]
+    fn calls(&self) -> Vec<CallEntry> {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .list()
+            .iter()
+            .map(|call| {
+                (
+                    call.index,
+                    call.number.clone(),
+                    call.state.as_str().to_string(),
+                    call.incoming,
+                    call.conference,
+                )
+            })
+            .collect()
+    }
+
+    /// Who is in the conference call, by index and number; no one without one.
+    #[zbus(property)]
+    fn conference(&self) -> Vec<(u8, String)> {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .conference()
+            .iter()
+            .map(|call| (call.index, call.number.clone()))
+            .collect()
+    }
+
+    /// Calls are announced through ModemState, which the power service follows to
+    /// darken the screen at the user's ear. Calling while on a call holds it.
+    async fn dial(
+        &self,
+        number: String,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
         info!(number = %number, "dialing");
-        self.state.lock().unwrap().modem_state = "in-call".to_string();
+        let hold = self.state.lock().unwrap().calls.must_hold_to_dial();
+        let expected = self.planned_calls(|calls| calls.dial(&number))?;
+        if let Some(at) = &self.at {
+            if hold {
+                at.swap_calls().await.map_err(failed)?;
+            }
+            at.dial(&number).await.map_err(failed)?;
+        }
+        self.update_calls(expected, &emitter).await?;
+        Ok(())
     }
 
-    async fn hang_up(&self) {
+    /// End every call.
+    async fn hang_up(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
         info!("hanging up");
-        self.state.lock().unwrap().modem_state = "idle".to_string();
+        if let Some(at) = &self.at {
+            at.hang_up().await.map_err(failed)?;
+        }
+        self.update_calls(Calls::default(), &emitter).await?;
+        Ok(())
     }
 
-    async fn send_sms(&self, number: String, message: String) {
-        info!(number = %number, len = message.len(), "sending SMS");
+    /// End call `index` alone, such as one caller in the conference. Networks that
+    /// don't let a caller go from a conference refuse with NotSupported.
+    async fn hang_up_call(
+        &self,
+        index: u8,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let in_conference = self
+            .state
+            .lock()
+            .unwrap()
+            .calls
+            .get(index)
+            .is_some_and(|call| call.conference);
+        let expected = self.planned_calls(|calls| calls.release(index))?;
+        info!(index, in_conference, "hanging up one call");
+        if let Some(at) = &self.at
+            && let Err(e) = at.release_call(index).await
+        {
+            return Err(if in_conference {
+                zbus::fdo::Error::NotSupported(format!(
+                    "the network won't let one caller go from the conference: {e:#}"
+                ))
+            } else {
+                failed(e)
+            });
+        }
+        self.update_calls(expected, &emitter).await?;
+        Ok(())
+    }
+
+    /// Hold the active call and take the waiting one, or else swap the active and
+    /// held calls.
+    async fn swap_calls(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let expected = self.planned_calls(Calls::swap)?;
+        if let Some(at) = &self.at {
+            at.swap_calls().await.map_err(failed)?;
+        }
+        self.update_calls(expected, &emitter).await?;
+        Ok(())
     }
+
+    /// Bring the held call into the active one, making a conference or adding to it.
+    async fn merge_calls(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let expected = self.planned_calls(Calls::merge)?;
+        info!("merging calls into a conference");
+        if let Some(at) = &self.at {
+            at.merge_calls().await.map_err(failed)?;
+        }
+        self.update_calls(expected, &emitter).await?;
+        Ok(())
+    }
+
+    /// Have the caller tell who numbers are as caller ID provider `name`, at `path` on
+    /// its connection, until it unregisters or leaves the bus. A provider whose account
+    /// is granted the network may look numbers up off the phone, so it is only asked
+    /// with the user's leave. A name is held by one app.
+    async fn register_caller_id_provider(
+        &mut self,
+        name: &str,
+        path: OwnedObjectPath,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let owner = sender(&header)?;
+        if name.is_empty() {
+            return Err(zbus::fdo::Error::InvalidArgs(
+                "a caller ID provider needs a name".to_string(),
+            ));
+        }
+        let uid = zbus::fdo::DBusProxy::new(connection)
+            .await?
+            .get_connection_unix_user(owner.clone().into())
+            .await?;
+        let reach = if self.network_uids.contains(&uid) {
+            Reach::Online
+        } else {
+            Reach::Local
+        };
+        let online = reach == Reach::Online;
+        let provider = CallerIdProvider {
+            name: name.to_string(),
+            owner,
+            path,
+            reach,
+        };
+        match self.caller_id_providers.iter_mut().find(|p| p.name == name) {
+            Some(existing) if existing.owner != provider.owner => {
+                return Err(zbus::fdo::Error::AccessDenied(format!(
+                    "caller ID provider {name:?} is registered by another app"
+                )));
+            }
+            Some(existing) => *existing = provider,
+            None => self.caller_id_providers.push(provider),
+        }
+        info!(provider = name, online, "caller ID provider registered");
+        self.caller_id_providers_changed(&emitter).await?;
+        Ok(())
+    }
+
+    async fn unregister_caller_id_provider(
+        &mut self,
+        name: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let owner = sender(&header)?;
+        match self.caller_id_providers.iter().position(|p| p.name == name) {
+            Some(at) if self.caller_id_providers[at].owner == owner => {
+                self.caller_id_providers.remove(at);
+                info!(provider = name, "caller ID provider unregistered");
+                self.caller_id_providers_changed(&emitter).await?;
+                Ok(())
+            }
+            Some(_) => Err(zbus::fdo::Error::AccessDenied(format!(
+                "caller ID provider {name:?} is registered by another app"
+            ))),
+            None => Err(zbus::fdo::Error::InvalidArgs(format!(
+                "no caller ID provider {name:?}"
+            ))),
+        }
+    }
+
+    /// The caller ID providers by name, and whether each looks numbers up online.
+    #[zbus(property)]
+    fn caller_id_providers(&self) -> Vec<(String, bool)> {
+        self.caller_id_providers
+            .iter()
+            .map(|p| (p.name.clone(), p.reach == Reach::Online))
+            .collect()
+    }
+
+    /// Whether the user lets online caller ID providers see the numbers that call.
+    #[zbus(property)]
+    fn online_caller_id(&self) -> bool {
+        self.state.lock().unwrap().call_log.online
+    }
+
+    async fn set_online_caller_id(
+        &self,
+        allowed: bool,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let pid = zbus::fdo::DBusProxy::new(connection)
+            .await?
+            .get_connection_unix_process_id(sender(&header)?.into())
+            .await?;
+        let exe = std::fs::read_link(format!("/proc/{pid}/exe")).ok();
+        if exe.as_deref() != Some(self.settings_app.as_path()) {
+            warn!(pid, "refused online caller ID to a caller outside settings");
+            return Err(zbus::fdo::Error::AccessDenied(
+                "only settings may change online caller ID".to_string(),
+            ));
+        }
+        info!(allowed, "online caller ID");
+        self.state.lock().unwrap().call_log.online = allowed;
+        self.save_call_log();
+        self.online_caller_id_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// Who `number` is, for the dialer to show as it rings: what a provider on the
+    /// phone says, else what the call log has from before, else what an online
+    /// provider says if the user allows them. The answer is kept with the number's
+    /// last call in the log.
+    async fn look_up_caller(
+        &self,
+        number: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<CallerIdEntry> {
+        if number.is_empty() {
+            return Ok(Default::default());
+        }
+        let (online, cached) = {
+            let state = self.state.lock().unwrap();
+            (
+                state.call_log.online,
+                state.call_log.caller(number).cloned(),
+            )
+        };
+        let order = caller_id::lookup_order(
+            self.caller_id_providers
+                .iter()
+                .map(|p| (p.name.as_str(), p.reach)),
+            online,
+        );
+        let (local, remote): (Vec<CallerIdProvider>, Vec<CallerIdProvider>) = order
+            .into_iter()
+            .filter_map(|(name, _)| self.caller_id_providers.iter().find(|p| p.name == name))
+            .cloned()
+            .partition(|p| p.reach == Reach::Local);
+
+        let mut caller = Self::ask_providers(connection, &local, number).await;
+        if caller.is_none() {
+            caller = cached;
+        }
+        if caller.is_none() {
+            caller = Self::ask_providers(connection, &remote, number).await;
+        }
+        let caller = caller.unwrap_or_default();
+        let identified = self
+            .state
+            .lock()
+            .unwrap()
+            .call_log
+            .identify(number, &caller);
+        if identified {
+            self.save_call_log();
+            self.call_log_changed(&emitter).await?;
+        }
+        Ok((caller.name, caller.spam, caller.provider))
+    }
+
+    /// Calls made and taken, oldest first.
+    #[zbus(property)]
+    fn call_log(&self) -> Vec<LoggedCallEntry> {
+        self.state
+            .lock()
+            .unwrap()
+            .call_log
+            .list()
+            .iter()
+            .map(|call| {
+                (
+                    call.number.clone(),
+                    call.incoming,
+                    call.time,
+                    call.caller.name.clone(),
+                    call.caller.spam,
+                )
+            })
+            .collect()
+    }
+
+    /// Send `message` to `number`, or keep it until the modem is back on a network.
+    /// Its id in Outbox, where it shows as it goes out and arrives.
+    async fn send_sms(
+        &self,
+        number: String,
+        message: String,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<u32> {
+        // A message that could never go out isn't kept
+        pdu::encode_submit(&number, &message, true)
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e:#}")))?;
+        let id = self
+            .state
+            .lock()
+            .unwrap()
+            .outbox
+            .queue(&number, &message, unix_time());
+        info!(id, number = %number, len = message.len(), "sending SMS");
+        self.send_outbox().await;
+        self.save_outbox();
+        self.outbox_changed(&emitter).await?;
+        Ok(id)
+    }
+
+    /// Messages sent and waiting to go, oldest first.
+    #[zbus(property)]
+    fn outbox(&self) -> Vec<OutgoingSmsEntry> {
+        self.state
+            .lock()
+            .unwrap()
+            .outbox
+            .list()
+            .iter()
+            .map(|message| {
+                (
+                    message.id,
+                    message.number.clone(),
+                    message.text.clone(),
+                    message.time,
+                    message.status.as_str().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[zbus(signal)]
+    async fn sms_received(
+        emitter: &SignalEmitter<'_>,
+        sender: &str,
+        message: &str,
+    ) -> zbus::Result<()>;
 }
 
 #[tokio::main]
@@ -78,23 +1133,382 @@ async fn main() -> anyhow::Result<()> {
 
     info!("starting modem service");
 
-    let service = ModemService::new();
+    let apns = match ApnDatabase::load(Path::new(apn::DATABASE_PATH)) {
+        Ok(apns) => apns,
+        Err(e) => {
+            error!(error = %e, "failed to load the APN database");
+            ApnDatabase::default()
+        }
+    };
+    let call_log_path = PathBuf::from(caller_id::CALL_LOG_PATH);
+    let call_log = match CallLog::load(&call_log_path) {
+        Ok(log) => log,
+        Err(e) => {
+            error!(error = %e, "failed to load the call log");
+            CallLog::default()
+        }
+    };
+    let outbox_path = PathBuf::from(sms::OUTBOX_PATH);
+    let outbox = match Outbox::load(&outbox_path) {
+        Ok(outbox) => outbox,
+        Err(e) => {
+            error!(error = %e, "failed to load the outbox");
+            Outbox::default()
+        }
+    };
+    let mut service = ModemService::new()
+        .with_imei_readers(access::allowed_uids_from_system(access::GROUP))
+        .with_network_uids(access::allowed_uids_from_system(access::NETWORK_GROUP))
+        .with_apns(apns)
+        .with_apn_path(PathBuf::from(apn::MANUAL_APN_PATH))
+        .with_call_log(call_log_path, call_log)
+        .with_outbox(outbox_path, outbox);
+    let mut urcs = None;
+    match open_at_modem().await {
+        Some((modem, receiver)) => {
+            match modem.identity().await {
+                Ok(identity) => {
+                    info!(
+                        firmware = %identity.firmware,
+                        baseband = %identity.baseband,
+                        "modem identified"
+                    );
+                    service.state.lock().unwrap().identity = identity;
+                }
+                Err(e) => warn!(error = %e, "failed to read modem identity"),
+            }
+            refresh(&modem, &service.state).await;
+            if let Err(e) = modem.enable_toolkit().await {
+                warn!(error = %e, "modem can't hand over the SIM toolkit");
+            }
+            service = service.with_at_modem(modem);
+            urcs = Some(receiver);
+        }
+        None => info!("no AT modem found, simulating one"),
+    }
+    let at = service.at.clone();
 
-    let _connection = connection::Builder::session()?
+    if service.state.lock().unwrap().sim_present
+        && let Err(e) = service.configure_apn().await
+    {
+        warn!(error = %e, "failed to set up the APN");
+    }
+    // What was left waiting when the service last stopped
+    if service.send_outbox().await {
+        service.save_outbox();
+    }
+
+    let connection = connection::Builder::system()?
         .name("org.mobileos.Modem")?
         .serve_at("/org/mobileos/Modem", service)?
         .build()
         .await?;
 
-    info!("modem service running on session bus");
+    let watched = connection.clone();
+    tokio::spawn(async move {
+        if let Err(e) = drop_departed_providers(watched).await {
+            warn!(error = %e, "not watching for caller ID providers leaving the bus");
+        }
+    });
+
+    if let (Some(at), Some(urcs)) = (at.clone(), urcs) {
+        let iface = connection
+            .object_server()
+            .interface::<_, ModemService>("/org/mobileos/Modem")
+            .await?;
+        tokio::spawn(handle_urcs(at.clone(), urcs, iface.clone()));
+        tokio::spawn(poll_signal(at, iface));
+    }
+
+    info!("modem service running on system bus");
+    mos_notify::ready();
+    tokio::spawn(feed_watchdog(at));
 
     std::future::pending::<()>().await;
     Ok(())
 }
 
+/// Forget the caller ID providers of apps that leave the bus.
+async fn drop_departed_providers(connection: Connection) -> zbus::Result<()> {
+    use futures_util::StreamExt;
+
+    let dbus = zbus::fdo::DBusProxy::new(&connection).await?;
+    let mut changes = dbus.receive_name_owner_changed().await?;
+    let iface = connection
+        .object_server()
+        .interface::<_, ModemService>("/org/mobileos/Modem")
+        .await?;
+    while let Some(change) = changes.next().await {
+        let Ok(args) = change.args() else {
+            continue;
+        };
+        // A unique name losing its owner is an app gone
+        let BusName::Unique(gone) = args.name() else {
+            continue;
+        };
+        if args.new_owner().is_some() {
+            continue;
+        }
+        let mut service = iface.get_mut().await;
+        let before = service.caller_id_providers.len();
+        service.caller_id_providers.retain(|provider| {
+            let keep = provider.owner != *gone;
+            if !keep {
+                info!(provider = %provider.name, "caller ID provider left the bus");
+            }
+            keep
+        });
+        if service.caller_id_providers.len() != before {
+            let _ = service
+                .caller_id_providers_changed(iface.signal_emitter())
+                .await;
+        }
+    }
+    Ok(())
+}
+
+/// Open the AT-command modem named by MOS_MODEM_PORT, or else the first USB serial
+/// port that answers AT, and set it up.
+async fn open_at_modem() -> Option<(Arc<AtModem>, mpsc::UnboundedReceiver<Urc>)> {
+    let port = match std::env::var_os(PORT_ENV) {
+        Some(port) => PathBuf::from(port),
+        None => tokio::task::spawn_blocking(|| at::probe(Path::new("/dev")))
+            .await
+            .ok()??,
+    };
+
+    let (modem, urcs) = match AtModem::open(&port) {
+        Ok(opened) => opened,
+        Err(e) => {
+            error!(port = %port.display(), error = %e, "failed to open AT modem");
+            return None;
+        }
+    };
+    if let Err(e) = modem.init().await {
+        error!(port = %port.display(), error = %e, "failed to set up AT modem");
+        return None;
+    }
+    info!(port = %port.display(), "using AT-command modem");
+    Some((Arc::new(modem), urcs))
+}
+
+/// Read SIM, registration, operator and signal state from the modem. True if a SIM
+/// was inserted since the last refresh.
+async fn refresh(at: &AtModem, state: &Mutex<ModemState>) -> bool {
+    let sim_present = at.sim_present().await;
+    let registered = at.registered().await;
+    let operator = at.operator().await;
+    let signal = at.signal_strength().await;
+
+    let mut state = state.lock().unwrap();
+    let mut inserted = false;
+    match sim_present {
+        Ok(present) => {
+            inserted = present && !state.sim_present;
+            state.sim_present = present;
+        }
+        Err(e) => warn!(error = %e, "failed to query SIM"),
+    }
+    match registered {
+        Ok(registered) => state.registered = registered,
+        Err(e) => warn!(error = %e, "failed to query network registration"),
+    }
+    match operator {
+        Ok(operator) => state.operator = operator.unwrap_or_default(),
+        Err(e) => warn!(error = %e, "failed to query operator"),
+    }
+    match signal {
+        Ok(signal) => state.signal_strength = signal.unwrap_or(0),
+        Err(e) => warn!(error = %e, "failed to query signal strength"),
+    }
+    inserted
+}
+
+/// Track calls, registration, incoming SMS, delivery of sent ones and the SIM toolkit
+/// from the modem's unsolicited results.
+async fn handle_urcs(
+    at: Arc<AtModem>,
+    mut urcs: mpsc::UnboundedReceiver<Urc>,
+    iface: InterfaceRef<ModemService>,
+) {
+    let state = iface.get().await.state.clone();
+    let emitter = iface.signal_emitter();
+    while let Some(urc) = urcs.recv().await {
+        let result = match urc {
+            // RING repeats until the call is answered, for the one call
+            Urc::Ring => {
+                let mut expected = state.lock().unwrap().calls.clone();
+                if !expected
+                    .list()
+                    .iter()
+                    .any(|call| call.state == CallState::Incoming)
+                {
+                    expected.ring("");
+                }
+                iface.get().await.update_calls(expected, emitter).await
+            }
+            Urc::CallerId(number) => {
+                info!(number = %number, "incoming call");
+                let expected = state.lock().unwrap().calls.clone();
+                iface.get().await.update_calls(expected, emitter).await
+            }
+            // Without a call list, any call ending is taken to end them all
+            Urc::CallEnded => {
+                iface
+                    .get()
+                    .await
+                    .update_calls(Calls::default(), emitter)
+                    .await
+            }
+            Urc::Registration(status) => {
+                info!(status, "network registration changed");
+                state.lock().unwrap().registered = at::is_registered(status);
+                if refresh(&at, &state).await {
+                    info!("SIM inserted");
+                    sim_inserted(&iface).await;
+                }
+                // Messages kept while off the network go out now it is back
+                let service = iface.get().await;
+                let flushed = service.flush_outbox(emitter).await;
+                service.operator_changed(emitter).await.and(flushed)
+            }
+            Urc::NewSms(index) => match at.take_sms(index).await {
+                Ok(sms) => {
+                    info!(sender = %sms.sender, len = sms.text.len(), "SMS received");
+                    ModemService::sms_received(emitter, &sms.sender, &sms.text).await
+                }
+                Err(e) => {
+                    warn!(index, error = %e, "failed to read SMS");
+                    Ok(())
+                }
+            },
+            Urc::StatusReport(index) => match at.take_status_report(index).await {
+                Ok(report) => {
+                    info!(
+                        reference = report.reference,
+                        status = report.status,
+                        "SMS status report"
+                    );
+                    iface.get().await.delivery_reported(&report, emitter).await
+                }
+                Err(e) => {
+                    warn!(index, error = %e, "failed to read SMS status report");
+                    Ok(())
+                }
+            },
+            Urc::Toolkit(hex) => {
+                toolkit_command(&iface, &hex).await;
+                Ok(())
+            }
+            Urc::ToolkitEnded => {
+                state.lock().unwrap().toolkit_prompt = None;
+                iface.get().await.toolkit_prompt_changed(emitter).await
+            }
+        };
+        if let Err(e) = result {
+            warn!(error = %e, "failed to emit modem change");
+        }
+    }
+    warn!("AT modem port closed");
+}
+
+/// Set the new SIM's carrier up, and tell settings about it and its APN.
+async fn sim_inserted(iface: &InterfaceRef<ModemService>) {
+    let service = iface.get().await;
+    if let Err(e) = service.configure_apn().await {
+        warn!(error = %e, "failed to set up the APN");
+    }
+    let emitter = iface.signal_emitter();
+    let emitted = async {
+        service.sim_present_changed(emitter).await?;
+        service.apn_changed(emitter).await?;
+        service.apn_manual_changed(emitter).await
+    };
+    if let Err(e) = emitted.await {
+        warn!(error = %e, "failed to emit SIM change");
+    }
+}
+
+/// Carry out a proactive command from the SIM, and tell the toolkit app about it.
+async fn toolkit_command(iface: &InterfaceRef<ModemService>, hex: &str) {
+    let proactive = match stk::parse(hex) {
+        Ok(proactive) => proactive,
+        Err(e) => {
+            warn!(error = %e, "failed to decode a SIM toolkit command");
+            return;
+        }
+    };
+    let service = iface.get().await;
+    if let Err(e) = service.proactive(proactive).await {
+        warn!(error = %e, "failed to answer the SIM toolkit");
+    }
+    let emitter = iface.signal_emitter();
+    let emitted = async {
+        service.toolkit_menu_changed(emitter).await?;
+        service.toolkit_prompt_changed(emitter).await
+    };
+    if let Err(e) = emitted.await {
+        warn!(error = %e, "failed to emit SIM toolkit change");
+    }
+}
+
+/// Poll signal strength, which modems don't report on their own, and try messages the
+/// network refused again.
+async fn poll_signal(at: Arc<AtModem>, iface: InterfaceRef<ModemService>) {
+    let state = iface.get().await.state.clone();
+    loop {
+        tokio::time::sleep(SIGNAL_POLL_INTERVAL).await;
+        let signal = match at.signal_strength().await {
+            Ok(signal) => signal.unwrap_or(0),
+            Err(e) => {
+                warn!(error = %e, "failed to query signal strength");
+                continue;
+            }
+        };
+
+        let previous = std::mem::replace(&mut state.lock().unwrap().signal_strength, signal);
+        if previous != signal
+            && let Err(e) = iface
+                .get()
+                .await
+                .signal_strength_changed(iface.signal_emitter())
+                .await
+        {
+            warn!(error = %e, "failed to emit signal strength change");
+        }
+
+        let service = iface.get().await;
+        if let Err(e) = service.flush_outbox(iface.signal_emitter()).await {
+            warn!(error = %e, "failed to emit outbox change");
+        }
+    }
+}
+
+/// Ping initd's watchdog twice per timeout for as long as the modem answers AT. A
+/// modem that stops answering is wedged, and initd resets the phone to recover it.
+async fn feed_watchdog(at: Option<Arc<AtModem>>) {
+    let Some(interval) = mos_notify::watchdog_interval() else {
+        return;
+    };
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Some(at) = &at
+            && let Err(e) = at.command("AT").await
+        {
+            warn!(error = %e, "modem not answering, holding back the watchdog");
+            continue;
+        }
+        mos_notify::watchdog();
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use zbus::{connection, proxy, Connection};
+    use std::collections::HashSet;
+
+    use mos_modem::caller_id::Reach;
+    use zbus::zvariant::ObjectPath;
+    use zbus::{connection, interface, proxy, Connection};
 
     #[proxy(
         interface = "org.mobileos.Modem",
@@ -113,13 +1527,106 @@ mod tests {
         #[zbus(property)]
         fn modem_state(&self) -> zbus::Result<String>;
 
+        #[zbus(property)]
+        fn calls(&self) -> zbus::Result<Vec<super::CallEntry>>;
+
+        #[zbus(property)]
+        fn conference(&self) -> zbus::Result<Vec<(u8, String)>>;
+
+        #[zbus(property)]
+        fn data_enabled(&self) -> zbus::Result<bool>;
+
+        #[zbus(property)]
+        fn apn(&self) -> zbus::Result<super::ApnEntry>;
+
+        #[zbus(property)]
+        fn apn_manual(&self) -> zbus::Result<bool>;
+
+        #[zbus(property)]
+        fn toolkit_menu(&self) -> zbus::Result<(String, Vec<mos_modem::stk::Item>)>;
+
+        #[zbus(property)]
+        fn toolkit_prompt(&self) -> zbus::Result<(String, String, Vec<mos_modem::stk::Item>)>;
+
+        #[zbus(property)]
+        fn firmware_version(&self) -> zbus::Result<String>;
+
+        #[zbus(property)]
+        fn baseband(&self) -> zbus::Result<String>;
+
+        #[zbus(property)]
+        fn caller_id_providers(&self) -> zbus::Result<Vec<(String, bool)>>;
+
+        #[zbus(property)]
+        fn online_caller_id(&self) -> zbus::Result<bool>;
+
+        #[zbus(property)]
+        fn call_log(&self) -> zbus::Result<Vec<super::LoggedCallEntry>>;
+
+        #[zbus(property)]
+        fn outbox(&self) -> zbus::Result<Vec<super::OutgoingSmsEntry>>;
+
+        fn imei(&self) -> zbus::Result<String>;
         fn dial(&self, number: &str) -> zbus::Result<()>;
         fn hang_up(&self) -> zbus::Result<()>;
-        fn send_sms(&self, number: &str, message: &str) -> zbus::Result<()>;
+        fn hang_up_call(&self, index: u8) -> zbus::Result<()>;
+        fn swap_calls(&self) -> zbus::Result<()>;
+        fn merge_calls(&self) -> zbus::Result<()>;
+        fn set_data_enabled(&self, enabled: bool) -> zbus::Result<()>;
+        fn send_sms(&self, number: &str, message: &str) -> zbus::Result<u32>;
+        fn set_apn(&self, apn: &super::ApnEntry) -> zbus::Result<()>;
+        fn reset_apn(&self) -> zbus::Result<()>;
+        fn open_toolkit_item(&self, item: u8) -> zbus::Result<()>;
+        fn answer_toolkit(&self, item: u8) -> zbus::Result<()>;
+        fn leave_toolkit(&self, back: bool) -> zbus::Result<()>;
+        fn register_caller_id_provider(
+            &self,
+            name: &str,
+            path: &ObjectPath<'_>,
+        ) -> zbus::Result<()>;
+        fn unregister_caller_id_provider(&self, name: &str) -> zbus::Result<()>;
+        fn set_online_caller_id(&self, allowed: bool) -> zbus::Result<()>;
+        fn look_up_caller(&self, number: &str) -> zbus::Result<super::CallerIdEntry>;
+    }
+
+    /// Knows one number by name.
+    struct Contacts;
+
+    #[interface(name = "org.mobileos.CallerIdProvider")]
+    impl Contacts {
+        fn look_up(&self, number: &str) -> (String, f64) {
+            match number {
+                "+15550001" => ("Mum".to_string(), 0.0),
+                _ => (String::new(), 0.0),
+            }
+        }
+    }
+
+    /// Takes every number for spam.
+    struct SpamCheck;
+
+    #[interface(name = "org.mobileos.CallerIdProvider")]
+    impl SpamCheck {
+        fn look_up(&self, _number: &str) -> (String, f64) {
+            ("Telemarketer".to_string(), 0.9)
+        }
+    }
+
+    /// Never answers in time.
+    struct Stuck;
+
+    #[interface(name = "org.mobileos.CallerIdProvider")]
+    impl Stuck {
+        async fn look_up(&self, _number: &str) -> (String, f64) {
+            std::future::pending().await
+        }
     }
 
     async fn start_test_service() -> (Connection, zbus::names::OwnedUniqueName) {
-        let service = super::ModemService::new();
+        serve(super::ModemService::new()).await
+    }
+
+    async fn serve(service: super::ModemService) -> (Connection, zbus::names::OwnedUniqueName) {
         let conn = connection::Builder::session()
             .unwrap()
             .serve_at("/org/mobileos/Modem", service)
@@ -179,6 +1686,129 @@ mod tests {
         assert_eq!(proxy.modem_state().await.unwrap(), "in-call");
     }
 
+    #[tokio::test]
+    async fn data_goes_off_and_on() {
+        let (_conn, name) = start_test_service().await;
+        let client = Connection::session().await.unwrap();
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        assert!(proxy.data_enabled().await.unwrap());
+        proxy.set_data_enabled(false).await.unwrap();
+        assert!(!proxy.data_enabled().await.unwrap());
+        proxy.set_data_enabled(true).await.unwrap();
+        assert!(proxy.data_enabled().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn apn_comes_from_the_sim_carrier_until_entered() {
+        let dir = tempfile::tempdir().unwrap();
+        let apns = mos_modem::apn::ApnDatabase::parse(
+            "[[carrier]]\nmcc = \"001\"\nmnc = \"01\"\ncarrier = \"Test Network\"\napn = \"test\"\n\
+             mmsc = \"http://mms.test\"\n",
+        )
+        .unwrap();
+        let service = super::ModemService::new()
+            .with_apns(apns)
+            .with_apn_path(dir.path().join("apn.toml"));
+        service.configure_apn().await.unwrap();
+        let (_conn, name) = serve(service).await;
+        let client = Connection::session().await.unwrap();
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        let (carrier, apn, _, _, auth, _, mmsc, _) = proxy.apn().await.unwrap();
+        assert_eq!(
+            (carrier.as_str(), apn.as_str(), auth.as_str(), mmsc.as_str()),
+            ("Test Network", "test", "none", "http://mms.test")
+        );
+        assert!(!proxy.apn_manual().await.unwrap());
+
+        let entered = |apn: &str| {
+            let text = |text: &str| text.to_string();
+            (
+                text(""),
+                text(apn),
+                text("user"),
+                text("secret"),
+                text("chap"),
+                text("IPV4V6"),
+                text(""),
+                text(""),
+            )
+        };
+        match proxy.set_apn(&entered("bad\"apn")).await {
+            Err(zbus::Error::MethodError(name, _, _)) => {
+                assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.InvalidArgs")
+            }
+            other => panic!("expected the APN to be refused, got {other:?}"),
+        }
+        proxy.set_apn(&entered("internet")).await.unwrap();
+        let (carrier, apn, username, ..) = proxy.apn().await.unwrap();
+        assert_eq!(
+            (carrier.as_str(), apn.as_str(), username.as_str()),
+            ("MobileOS Carrier", "internet", "user")
+        );
+        assert!(proxy.apn_manual().await.unwrap());
+        assert!(dir.path().join("apn.toml").exists());
+
+        proxy.reset_apn().await.unwrap();
+        assert_eq!(proxy.apn().await.unwrap().1, "test");
+        assert!(!proxy.apn_manual().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn toolkit_prompts_wait_for_the_user() {
+        use mos_modem::stk::{self, Command};
+
+        let service = super::ModemService::new();
+        let menu = Command::SetUpMenu {
+            title: "Carrier".to_string(),
+            items: vec![(1, "Balance".to_string()), (2, "Services".to_string())],
+        };
+        let choice = Command::SelectItem {
+            title: "Services".to_string(),
+            items: vec![(7, "News".to_string())],
+        };
+        for (number, command) in [(1, menu), (2, choice)] {
+            let proactive = stk::parse(&stk::encode(number, &command).unwrap()).unwrap();
+            service.proactive(proactive).await.unwrap();
+        }
+        let (_conn, name) = serve(service).await;
+        let client = Connection::session().await.unwrap();
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        let (title, items) = proxy.toolkit_menu().await.unwrap();
+        assert_eq!(title, "Carrier");
+        assert_eq!(items.len(), 2);
+        assert!(proxy.open_toolkit_item(9).await.is_err());
+        proxy.open_toolkit_item(2).await.unwrap();
+
+        let (kind, title, items) = proxy.toolkit_prompt().await.unwrap();
+        assert_eq!((kind.as_str(), title.as_str()), ("select", "Services"));
+        assert_eq!(items, [(7, "News".to_string())]);
+        assert!(proxy.answer_toolkit(8).await.is_err());
+        proxy.answer_toolkit(7).await.unwrap();
+        assert_eq!(proxy.toolkit_prompt().await.unwrap().0, "");
+        assert!(proxy.leave_toolkit(true).await.is_err());
+    }
+
     #[tokio::test]
     async fn hang_up_returns_to_idle() {
         let (_conn, name) = start_test_service().await;
@@ -196,6 +1826,227 @@ mod tests {
         assert_eq!(proxy.modem_state().await.unwrap(), "idle");
     }
 
+    #[tokio::test]
+    async fn held_calls_merge_into_a_conference() {
+        let (_conn, name) = start_test_service().await;
+        let client = Connection::session().await.unwrap();
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        assert!(proxy.merge_calls().await.is_err());
+        proxy.dial("+15550001").await.unwrap();
+        proxy.dial("+15550002").await.unwrap();
+        let states: Vec<String> = proxy
+            .calls()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, _, state, _, _)| state)
+            .collect();
+        assert_eq!(states, ["held", "active"]);
+        assert!(proxy.dial("+15550003").await.is_err());
+
+        proxy.merge_calls().await.unwrap();
+        assert_eq!(
+            proxy.conference().await.unwrap(),
+            [(1, "+15550001".to_string()), (2, "+15550002".to_string())]
+        );
+        assert!(proxy.hang_up_call(9).await.is_err());
+        proxy.hang_up_call(1).await.unwrap();
+        assert!(proxy.conference().await.unwrap().is_empty());
+        assert_eq!(proxy.modem_state().await.unwrap(), "in-call");
+        proxy.hang_up_call(2).await.unwrap();
+        assert_eq!(proxy.modem_state().await.unwrap(), "idle");
+    }
+
+    #[tokio::test]
+    async fn providers_granted_the_network_are_online() {
+        let client = Connection::session().await.unwrap();
+        let uid = zbus::fdo::DBusProxy::new(&client)
+            .await
+            .unwrap()
+            .get_connection_unix_user(client.unique_name().unwrap().into())
+            .await
+            .unwrap();
+        let (_conn, name) =
+            serve(super::ModemService::new().with_network_uids(HashSet::from([uid]))).await;
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        let spam = ObjectPath::try_from("/spam").unwrap();
+        proxy
+            .register_caller_id_provider("spam-check", &spam)
+            .await
+            .unwrap();
+        assert_eq!(
+            proxy.caller_id_providers().await.unwrap(),
+            [("spam-check".to_string(), true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn callers_are_named_by_local_providers_first() {
+        let client = connection::Builder::session()
+            .unwrap()
+            .serve_at("/contacts", Contacts)
+            .unwrap()
+            .serve_at("/spam", SpamCheck)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        // This test's own account isn't granted the network, so what it registers is
+        // local; the online provider is one such an account would have registered
+        let mut service = super::ModemService::new().with_network_uids(HashSet::new());
+        service.settings_app = std::fs::read_link("/proc/self/exe").unwrap();
+        service.caller_id_providers.push(super::CallerIdProvider {
+            name: "spam-check".to_string(),
+            owner: client.unique_name().unwrap().to_owned().into(),
+            path: ObjectPath::try_from("/spam").unwrap().into(),
+            reach: Reach::Online,
+        });
+        let (_conn, name) = serve(service).await;
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        let contacts = ObjectPath::try_from("/contacts").unwrap();
+        proxy
+            .register_caller_id_provider("contacts", &contacts)
+            .await
+            .unwrap();
+        assert_eq!(
+            proxy.caller_id_providers().await.unwrap(),
+            [
+                ("spam-check".to_string(), true),
+                ("contacts".to_string(), false)
+            ]
+        );
+
+        // A contact is asked before the online provider, which isn't asked at all
+        // until the user allows it
+        assert_eq!(
+            proxy.look_up_caller("+15550001").await.unwrap(),
+            ("Mum".to_string(), 0.0, "contacts".to_string())
+        );
+        assert_eq!(
+            proxy.look_up_caller("+15550009").await.unwrap(),
+            (String::new(), 0.0, String::new())
+        );
+        assert!(!proxy.online_caller_id().await.unwrap());
+        proxy.set_online_caller_id(true).await.unwrap();
+        proxy.dial("+15550009").await.unwrap();
+        assert_eq!(
+            proxy.look_up_caller("+15550009").await.unwrap(),
+            ("Telemarketer".to_string(), 0.9, "spam-check".to_string())
+        );
+
+        // The answer is kept in the call log, and names the number's next call
+        // without asking again
+        proxy
+            .unregister_caller_id_provider("spam-check")
+            .await
+            .unwrap();
+        proxy.hang_up().await.unwrap();
+        proxy.dial("+15550009").await.unwrap();
+        let log: Vec<(String, String, f64)> = proxy
+            .call_log()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(number, _, _, name, spam)| (number, name, spam))
+            .collect();
+        let telemarketer = ("+15550009".to_string(), "Telemarketer".to_string(), 0.9);
+        assert_eq!(log, [telemarketer.clone(), telemarketer]);
+        assert_eq!(
+            proxy.look_up_caller("+15550009").await.unwrap().0,
+            "Telemarketer"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_stuck_provider_is_passed_over() {
+        let (_conn, name) =
+            serve(super::ModemService::new().with_network_uids(HashSet::new())).await;
+        let stuck = connection::Builder::session()
+            .unwrap()
+            .serve_at("/stuck", Stuck)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let client = connection::Builder::session()
+            .unwrap()
+            .serve_at("/contacts", Contacts)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        for (conn, provider, path) in [
+            (&stuck, "stuck", "/stuck"),
+            (&client, "contacts", "/contacts"),
+        ] {
+            ModemProxy::builder(conn)
+                .destination(name.clone())
+                .unwrap()
+                .build()
+                .await
+                .unwrap()
+                .register_caller_id_provider(provider, &ObjectPath::try_from(path).unwrap())
+                .await
+                .unwrap();
+        }
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        assert_eq!(
+            proxy.look_up_caller("+15550001").await.unwrap(),
+            ("Mum".to_string(), 0.0, "contacts".to_string())
+        );
+        assert!(started.elapsed() < super::CALLER_ID_TIMEOUT * 2);
+    }
+
+    #[tokio::test]
+    async fn only_settings_may_allow_online_caller_id() {
+        let (_conn, name) = start_test_service().await;
+        let client = Connection::session().await.unwrap();
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        match proxy.set_online_caller_id(true).await {
+            Err(zbus::Error::MethodError(name, _, _)) => {
+                assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.AccessDenied")
+            }
+            other => panic!("expected access to be denied, got {other:?}"),
+        }
+        assert!(!proxy.online_caller_id().await.unwrap());
+    }
+
     #[tokio::test]
     async fn send_sms_does_not_error() {
         let (_conn, name) = start_test_service().await;
@@ -210,4 +2061,84 @@ mod tests {
 
         proxy.send_sms("+1234567890", "Hello!").await.unwrap();
     }
+
+    #[tokio::test]
+    async fn sms_waits_for_the_network() {
+        let service = super::ModemService::new();
+        service.state.lock().unwrap().registered = false;
+        let (conn, name) = serve(service).await;
+        let client = Connection::session().await.unwrap();
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        assert!(proxy.send_sms("call me", "Hello!").await.is_err());
+        let id = proxy.send_sms("+1234567890", "Hello!").await.unwrap();
+        let statuses = |outbox: Vec<super::OutgoingSmsEntry>| -> Vec<(u32, String)> {
+            outbox
+                .into_iter()
+                .map(|(id, _, _, _, status)| (id, status))
+                .collect()
+        };
+        assert_eq!(
+            statuses(proxy.outbox().await.unwrap()),
+            [(id, "pending".to_string())]
+        );
+
+        // Back on the network, the message goes out and arrives
+        let iface = conn
+            .object_server()
+            .interface::<_, super::ModemService>("/org/mobileos/Modem")
+            .await
+            .unwrap();
+        let service = iface.get().await;
+        service.state.lock().unwrap().registered = true;
+        service.flush_outbox(iface.signal_emitter()).await.unwrap();
+        assert_eq!(
+            statuses(proxy.outbox().await.unwrap()),
+            [(id, "delivered".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn identity_is_readable_but_imei_needs_the_group() {
+        let client = Connection::session().await.unwrap();
+        let uid = zbus::fdo::DBusProxy::new(&client)
+            .await
+            .unwrap()
+            .get_connection_unix_user(client.unique_name().unwrap().into())
+            .await
+            .unwrap();
+
+        let readers = std::collections::HashSet::from([uid]);
+        let (_conn, name) = serve(super::ModemService::new().with_imei_readers(readers)).await;
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(proxy.firmware_version().await.unwrap(), "simulated");
+        assert_eq!(proxy.baseband().await.unwrap(), "MobileOS simulated modem");
+        assert_eq!(proxy.imei().await.unwrap(), "000000000000000");
+
+        let readers = std::collections::HashSet::from([uid + 1]);
+        let (_conn, name) = serve(super::ModemService::new().with_imei_readers(readers)).await;
+        let proxy = ModemProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        match proxy.imei().await {
+            Err(zbus::Error::MethodError(name, _, _)) => {
+                assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.AccessDenied")
+            }
+            other => panic!("expected access to be denied, got {other:?}"),
+        }
+    }
 }
@@ -0,0 +1,642 @@
+// ABOUTME: SMS PDU encoding and decoding (3GPP TS 23.040) for the AT backend and modem emulator.
+// ABOUTME: Handles SMS-SUBMIT PDUs sent with AT+CMGS, and SMS-DELIVER and SMS-STATUS-REPORT PDUs read with AT+CMGR.
+
+use anyhow::{Context, Result, bail, ensure};
+
+/// The GSM 7-bit default alphabet, indexed by septet. 0x1B escapes to `GSM_EXTENSION`.
+const GSM_ALPHABET: &str = "@£$¥èéùìòÇ\nØø\rÅåΔ_ΦΓΛΩΠΨΣΘΞ\u{1b}ÆæßÉ !\"#¤%&'()*+,-./0123456789:;<=>?\
+¡ABCDEFGHIJKLMNOPQRSTUVWXYZÄÖÑÜ§¿abcdefghijklmnopqrstuvwxyzäöñüà";
+
+const ESCAPE: u8 = 0x1b;
+
+/// Characters reachable through the escape septet, as (septet, char) pairs.
+const GSM_EXTENSION: &[(u8, char)] = &[
+    (0x0a, '\u{c}'),
+    (0x14, '^'),
+    (0x28, '{'),
+    (0x29, '}'),
+    (0x2f, '\\'),
+    (0x3c, '['),
+    (0x3d, '~'),
+    (0x3e, ']'),
+    (0x40, '|'),
+    (0x65, '€'),
+];
+
+/// Longest single-part message: 140 octets of user data.
+const MAX_SEPTETS: usize = 160;
+const MAX_UCS2_UNITS: usize = 70;
+
+/// Type-of-address for numbers with a leading `+`.
+const INTERNATIONAL: u8 = 0x91;
+const UNKNOWN_TYPE: u8 = 0x81;
+/// Type-of-number bits marking an alphanumeric sender such as a carrier name.
+const ALPHANUMERIC: u8 = 0x50;
+
+/// TP-SRR in the first octet of an SMS-SUBMIT: the sender wants a status report.
+const STATUS_REPORT_REQUEST: u8 = 0x20;
+
+const DCS_GSM7: u8 = 0x00;
+const DCS_UCS2: u8 = 0x08;
+
+/// Service centre timestamp of emulated deliveries: 2024-01-01 00:00:00 UTC, in
+/// swapped semi-octets.
+const SERVICE_CENTRE_TIMESTAMP: [u8; 7] = [0x42, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00];
+
+/// A received text message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sms {
+    pub sender: String,
+    pub text: String,
+}
+
+/// A text message as submitted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submit {
+    pub recipient: String,
+    pub text: String,
+    /// Whether the sender asked to hear when it is delivered.
+    pub status_report: bool,
+}
+
+/// What the network says became of a message sent asking for a status report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusReport {
+    /// The reference the modem gave the message in its answer to AT+CMGS.
+    pub reference: u8,
+    pub recipient: String,
+    /// TP-ST, from 3GPP TS 23.040 9.2.3.15.
+    pub status: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Delivered,
+    /// The service centre is still trying, and reports again when it is done.
+    Trying,
+    /// The service centre gave up, or was refused.
+    Failed,
+}
+
+impl StatusReport {
+    pub fn delivery(&self) -> Delivery {
+        match self.status {
+            0x00..=0x1f => Delivery::Delivered,
+            0x20..=0x3f => Delivery::Trying,
+            _ => Delivery::Failed,
+        }
+    }
+}
+
+/// Encode a single-part SMS-SUBMIT, asking for a status report if `status_report`.
+/// Returns the PDU as hex, prefixed with an empty SMSC field so the modem uses the
+/// SIM's service centre, and the TPDU length in octets that AT+CMGS expects.
+pub fn encode_submit(number: &str, text: &str, status_report: bool) -> Result<(String, usize)> {
+    let mut first = 0x01; // SMS-SUBMIT, no validity period
+    if status_report {
+        first |= STATUS_REPORT_REQUEST;
+    }
+    // The message reference is assigned by the modem
+    let mut pdu = vec![first, 0x00];
+    pdu.extend(encode_address(number)?);
+    pdu.push(0x00); // protocol identifier
+    pdu.extend(encode_user_data(text)?);
+
+    Ok((format!("00{}", to_hex(&pdu)), pdu.len()))
+}
+
+/// Encode a single-part SMS-DELIVER from `sender`, as a modem stores a received
+/// message. Returns the PDU as hex with an empty SMSC field and the TPDU length, in
+/// the form AT+CMGR reports them. Used by the modem emulator.
+pub fn encode_deliver(sender: &str, text: &str) -> Result<(String, usize)> {
+    let mut pdu = vec![0x04]; // SMS-DELIVER, no more messages to send
+    pdu.extend(encode_address(sender)?);
+    pdu.push(0x00); // protocol identifier
+    let user_data = encode_user_data(text)?;
+    pdu.push(user_data[0]);
+    pdu.extend(SERVICE_CENTRE_TIMESTAMP);
+    pdu.extend(&user_data[1..]);
+
+    Ok((format!("00{}", to_hex(&pdu)), pdu.len()))
+}
+
+/// Encode an SMS-STATUS-REPORT on the message `reference` to `recipient`, as a modem
+/// stores one. Returns the PDU as hex with an empty SMSC field and the TPDU length, in
+/// the form AT+CMGR reports them. Used by the modem emulator.
+pub fn encode_status_report(reference: u8, recipient: &str, status: u8) -> Result<(String, usize)> {
+    let mut pdu = vec![
+        0x06, // SMS-STATUS-REPORT, no more messages to send
+        reference,
+    ];
+    pdu.extend(encode_address(recipient)?);
+    pdu.extend(SERVICE_CENTRE_TIMESTAMP);
+    pdu.extend(SERVICE_CENTRE_TIMESTAMP); // discharge time
+    pdu.push(status);
+
+    Ok((format!("00{}", to_hex(&pdu)), pdu.len()))
+}
+
+/// Decode an SMS-SUBMIT PDU, including its leading SMSC field, as written after the
+/// prompt of AT+CMGS.
+pub fn decode_submit(hex: &str) -> Result<Submit> {
+    let bytes = from_hex(hex)?;
+    let mut reader = Reader {
+        bytes: &bytes,
+        pos: 0,
+    };
+
+    let smsc_len = reader.byte()? as usize;
+    reader.take(smsc_len)?;
+
+    let first = reader.byte()?;
+    ensure!(
+        first & 0x03 == 0x01,
+        "not an SMS-SUBMIT PDU (type {:#04x})",
+        first & 0x03
+    );
+    let has_header = first & 0x40 != 0;
+    let status_report = first & STATUS_REPORT_REQUEST != 0;
+
+    reader.byte()?; // message reference
+    let recipient = decode_address(&mut reader)?;
+    reader.byte()?; // protocol identifier
+    let dcs = reader.byte()?;
+    let validity_len = match (first >> 3) & 0x03 {
+        0b00 => 0,
+        0b10 => 1, // relative
+        _ => 7,    // enhanced or absolute
+    };
+    reader.take(validity_len)?;
+    let length = reader.byte()? as usize;
+    let text = decode_user_data(dcs, has_header, length, reader.rest())?;
+
+    Ok(Submit {
+        recipient,
+        text,
+        status_report,
+    })
+}
+
+/// Decode an SMS-DELIVER PDU, including its leading SMSC field, as returned by AT+CMGR
+/// in PDU mode. Only the text of each part is returned; concatenated messages arrive
+/// as separate parts.
+pub fn decode_deliver(hex: &str) -> Result<Sms> {
+    let bytes = from_hex(hex)?;
+    let mut reader = Reader {
+        bytes: &bytes,
+        pos: 0,
+    };
+
+    let smsc_len = reader.byte()? as usize;
+    reader.take(smsc_len)?;
+
+    let first = reader.byte()?;
+    ensure!(
+        first & 0x03 == 0x00,
+        "not an SMS-DELIVER PDU (type {:#04x})",
+        first & 0x03
+    );
+    let has_header = first & 0x40 != 0;
+
+    let sender = decode_address(&mut reader)?;
+    reader.byte()?; // protocol identifier
+    let dcs = reader.byte()?;
+    reader.take(7)?; // service centre timestamp
+    let length = reader.byte()? as usize;
+    let text = decode_user_data(dcs, has_header, length, reader.rest())?;
+
+    Ok(Sms { sender, text })
+}
+
+/// Decode an SMS-STATUS-REPORT PDU, including its leading SMSC field, as returned by
+/// AT+CMGR in PDU mode.
+pub fn decode_status_report(hex: &str) -> Result<StatusReport> {
+    let bytes = from_hex(hex)?;
+    let mut reader = Reader {
+        bytes: &bytes,
+        pos: 0,
+    };
+
+    let smsc_len = reader.byte()? as usize;
+    reader.take(smsc_len)?;
+
+    let first = reader.byte()?;
+    ensure!(
+        first & 0x03 == 0x02,
+        "not an SMS-STATUS-REPORT PDU (type {:#04x})",
+        first & 0x03
+    );
+    let reference = reader.byte()?;
+    let recipient = decode_address(&mut reader)?;
+    reader.take(7)?; // service centre timestamp
+    reader.take(7)?; // discharge time
+    let status = reader.byte()?;
+
+    Ok(StatusReport {
+        reference,
+        recipient,
+        status,
+    })
+}
+
+/// The data coding scheme, user data length and user data for `text`: GSM 7-bit when
+/// every character is in the default alphabet, UCS2 otherwise.
+fn encode_user_data(text: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    match to_septets(text) {
+        Some(septets) => {
+            ensure!(
+                septets.len() <= MAX_SEPTETS,
+                "message is {} characters, longer than one SMS",
+                septets.len()
+            );
+            out.push(DCS_GSM7);
+            out.push(septets.len() as u8);
+            out.extend(pack_septets(&septets));
+        }
+        None => {
+            let units: Vec<u16> = text.encode_utf16().collect();
+            ensure!(
+                units.len() <= MAX_UCS2_UNITS,
+                "message is {} characters, longer than one SMS",
+                units.len()
+            );
+            out.push(DCS_UCS2);
+            out.push((units.len() * 2) as u8);
+            out.extend(units.iter().flat_map(|u| u.to_be_bytes()));
+        }
+    }
+    Ok(out)
+}
+
+/// The text of the user data, skipping a user data header if there is one.
+fn decode_user_data(dcs: u8, has_header: bool, length: usize, data: &[u8]) -> Result<String> {
+    let header_len = if has_header {
+        *data.first().context("user data header missing")? as usize + 1
+    } else {
+        0
+    };
+
+    let text = match alphabet(dcs) {
+        Alphabet::Gsm7 => {
+            let septets = unpack_septets(data, length);
+            // The header is padded to a septet boundary
+            let skip = (header_len * 8).div_ceil(7);
+            from_septets(septets.get(skip..).unwrap_or_default())
+        }
+        Alphabet::Ucs2 => {
+            let payload = data
+                .get(header_len..length.min(data.len()))
+                .unwrap_or_default();
+            let units: Vec<u16> = payload
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        Alphabet::Data => {
+            let payload = data
+                .get(header_len..length.min(data.len()))
+                .unwrap_or_default();
+            String::from_utf8_lossy(payload).into_owned()
+        }
+    };
+    Ok(text)
+}
+
+enum Alphabet {
+    Gsm7,
+    Data,
+    Ucs2,
+}
+
+/// The alphabet named by a data coding scheme; reserved values are treated as GSM 7-bit.
+fn alphabet(dcs: u8) -> Alphabet {
+    let bits = match dcs >> 4 {
+        // General data coding, with or without compression and message class
+        0x0..=0x7 => (dcs >> 2) & 0x03,
+        // Data coding / message class group
+        0xf => (dcs >> 2) & 0x01,
+        _ => 0,
+    };
+    match bits {
+        1 => Alphabet::Data,
+        2 => Alphabet::Ucs2,
+        _ => Alphabet::Gsm7,
+    }
+}
+
+fn encode_address(number: &str) -> Result<Vec<u8>> {
+    let (kind, digits) = match number.strip_prefix('+') {
+        Some(rest) => (INTERNATIONAL, rest),
+        None => (UNKNOWN_TYPE, number),
+    };
+    ensure!(
+        !digits.is_empty()
+            && digits.len() <= 20
+            && digits
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '*' || c == '#'),
+        "invalid phone number '{number}'"
+    );
+
+    let mut out = vec![digits.len() as u8, kind];
+    out.extend(to_semi_octets(digits));
+    Ok(out)
+}
+
+fn decode_address(reader: &mut Reader) -> Result<String> {
+    let digits = reader.byte()? as usize;
+    let kind = reader.byte()?;
+    let octets = reader.take(digits.div_ceil(2))?;
+
+    if kind & 0x70 == ALPHANUMERIC {
+        return Ok(from_septets(&unpack_septets(octets, digits * 4 / 7)));
+    }
+
+    let number = from_semi_octets(octets, digits);
+    if kind == INTERNATIONAL {
+        Ok(format!("+{number}"))
+    } else {
+        Ok(number)
+    }
+}
+
+/// Phone number digits, two per octet with the low nibble first and `F` padding.
+fn to_semi_octets(digits: &str) -> Vec<u8> {
+    let nibble = |c: u8| match c {
+        b'*' => 0xa,
+        b'#' => 0xb,
+        d => d - b'0',
+    };
+    digits
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| nibble(pair[0]) | pair.get(1).map_or(0xf, |&c| nibble(c)) << 4)
+        .collect()
+}
+
+fn from_semi_octets(octets: &[u8], digits: usize) -> String {
+    octets
+        .iter()
+        .flat_map(|b| [b & 0x0f, b >> 4])
+        .take(digits)
+        .map(|n| match n {
+            0..=9 => (b'0' + n) as char,
+            0xa => '*',
+            0xb => '#',
+            _ => '?',
+        })
+        .collect()
+}
+
+/// Map text onto the GSM default alphabet, or `None` if any character is outside it.
+pub(crate) fn to_septets(text: &str) -> Option<Vec<u8>> {
+    let mut septets = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        if let Some(index) = GSM_ALPHABET.chars().position(|g| g == c)
+            && index != ESCAPE as usize
+        {
+            septets.push(index as u8);
+        } else if let Some(&(code, _)) = GSM_EXTENSION.iter().find(|&&(_, g)| g == c) {
+            septets.extend([ESCAPE, code]);
+        } else {
+            return None;
+        }
+    }
+    Some(septets)
+}
+
+pub(crate) fn from_septets(septets: &[u8]) -> String {
+    let mut text = String::with_capacity(septets.len());
+    let mut escaped = false;
+    for &septet in septets {
+        if escaped {
+            escaped = false;
+            match GSM_EXTENSION.iter().find(|&&(code, _)| code == septet) {
+                Some(&(_, c)) => text.push(c),
+                // Unknown extensions fall back to the default alphabet
+                None => text.extend(GSM_ALPHABET.chars().nth(septet as usize)),
+            }
+        } else if septet == ESCAPE {
+            escaped = true;
+        } else {
+            text.extend(GSM_ALPHABET.chars().nth(septet as usize));
+        }
+    }
+    text
+}
+
+/// Pack 7-bit values into octets, least significant bits first.
+fn pack_septets(septets: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; (septets.len() * 7).div_ceil(8)];
+    for (i, &septet) in septets.iter().enumerate() {
+        let bit = i * 7;
+        let (byte, shift) = (bit / 8, bit % 8);
+        out[byte] |= septet << shift;
+        if shift > 1 {
+            out[byte + 1] |= septet >> (8 - shift);
+        }
+    }
+    out
+}
+
+pub(crate) fn unpack_septets(octets: &[u8], count: usize) -> Vec<u8> {
+    let count = count.min(octets.len() * 8 / 7);
+    (0..count)
+        .map(|i| {
+            let bit = i * 7;
+            let (byte, shift) = (bit / 8, bit % 8);
+            let mut value = octets[byte] >> shift;
+            if shift > 1 {
+                value |= octets[byte + 1] << (8 - shift);
+            }
+            value & 0x7f
+        })
+        .collect()
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex = hex.trim();
+    ensure!(hex.len().is_multiple_of(2), "odd-length PDU");
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .context("invalid hex in PDU")
+        })
+        .collect()
+}
+
+/// Cursor over PDU octets that fails instead of panicking on truncated input.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let Some(slice) = self.bytes.get(self.pos..self.pos + n) else {
+            bail!("truncated PDU");
+        };
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.pos..];
+        self.pos = self.bytes.len();
+        rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alphabet_covers_every_septet() {
+        assert_eq!(GSM_ALPHABET.chars().count(), 128);
+        assert_eq!(GSM_ALPHABET.chars().nth(0x41), Some('A'));
+        assert_eq!(GSM_ALPHABET.chars().nth(0x7f), Some('à'));
+    }
+
+    #[test]
+    fn encodes_gsm7_submit() {
+        let (pdu, len) = encode_submit("+46708251358", "hellohello", false).unwrap();
+        assert_eq!(pdu, "0001000B916407281553F800000AE8329BFD4697D9EC37");
+        assert_eq!(len, 22);
+    }
+
+    #[test]
+    fn encodes_ucs2_when_text_is_not_gsm() {
+        let (pdu, len) = encode_submit("5551234", "héllo ✓", false).unwrap();
+        assert!(pdu.starts_with("0001000781551532F400080E"));
+        assert!(pdu.ends_with("2713"));
+        assert_eq!(len, 11 + 14);
+    }
+
+    #[test]
+    fn extension_characters_take_two_septets() {
+        let septets = to_septets("[€]").unwrap();
+        assert_eq!(septets, [ESCAPE, 0x3c, ESCAPE, 0x65, ESCAPE, 0x3e]);
+        assert_eq!(from_septets(&septets), "[€]");
+    }
+
+    #[test]
+    fn rejects_overlong_messages_and_bad_numbers() {
+        assert!(encode_submit("+1555", &"a".repeat(160), false).is_ok());
+        assert!(encode_submit("+1555", &"a".repeat(161), false).is_err());
+        assert!(encode_submit("+1555", &"✓".repeat(71), false).is_err());
+        assert!(encode_submit("call me", "hi", false).is_err());
+        assert!(encode_submit("+", "hi", false).is_err());
+    }
+
+    #[test]
+    fn decodes_gsm7_deliver() {
+        let sms = decode_deliver(
+            "07917283010010F5040BC87238880900F10000993092516195800AE8329BFD4697D9EC37",
+        )
+        .unwrap();
+        assert_eq!(sms.sender, "27838890001");
+        assert_eq!(sms.text, "hellohello");
+    }
+
+    #[test]
+    fn decodes_international_ucs2_deliver() {
+        let sms = decode_deliver("07914400000000F0040C914477123456780008421010000000000400480069")
+            .unwrap();
+        assert_eq!(sms.sender, "+447721436587");
+        assert_eq!(sms.text, "Hi");
+    }
+
+    #[test]
+    fn decodes_alphanumeric_sender() {
+        // "Test" takes 28 bits, so the address length is 7 semi-octets
+        let sms = decode_deliver("000407D0D4F29C0E00004210100000000002E834").unwrap();
+        assert_eq!(sms.sender, "Test");
+        assert_eq!(sms.text, "hi");
+    }
+
+    #[test]
+    fn skips_user_data_header() {
+        // Concatenation header (5 octets + length) before "hello"
+        let sms = decode_deliver("0044048155550000421010000000000C050003010201D06536FB0D").unwrap();
+        assert_eq!(sms.text, "hello");
+    }
+
+    #[test]
+    fn septets_round_trip() {
+        let text = "The quick brown fox @ 5£ {ok}";
+        let septets = to_septets(text).unwrap();
+        let packed = pack_septets(&septets);
+        assert_eq!(from_septets(&unpack_septets(&packed, septets.len())), text);
+    }
+
+    #[test]
+    fn deliver_and_submit_round_trip() {
+        let (pdu, len) = encode_deliver("+15551234", "meet at 5? ✓").unwrap();
+        assert_eq!(len, pdu.len() / 2 - 1);
+        let sms = decode_deliver(&pdu).unwrap();
+        assert_eq!(sms.sender, "+15551234");
+        assert_eq!(sms.text, "meet at 5? ✓");
+
+        let (pdu, _) = encode_submit("5551234", "on my way {soon}", true).unwrap();
+        assert_eq!(
+            decode_submit(&pdu).unwrap(),
+            Submit {
+                recipient: "5551234".to_string(),
+                text: "on my way {soon}".to_string(),
+                status_report: true,
+            }
+        );
+    }
+
+    #[test]
+    fn status_reports_round_trip() {
+        let (pdu, _) = encode_submit("+46708251358", "hellohello", true).unwrap();
+        assert!(pdu.starts_with("0021"));
+
+        let (pdu, len) = encode_status_report(7, "+15551234", 0x00).unwrap();
+        assert_eq!(len, pdu.len() / 2 - 1);
+        let report = decode_status_report(&pdu).unwrap();
+        assert_eq!(report.reference, 7);
+        assert_eq!(report.recipient, "+15551234");
+        assert_eq!(report.delivery(), Delivery::Delivered);
+
+        let report = |status| {
+            let (pdu, _) = encode_status_report(7, "+15551234", status).unwrap();
+            decode_status_report(&pdu).unwrap().delivery()
+        };
+        assert_eq!(report(0x30), Delivery::Trying);
+        assert_eq!(report(0x41), Delivery::Failed);
+        assert!(decode_status_report(&encode_deliver("+1555", "hi").unwrap().0).is_err());
+    }
+
+    #[test]
+    fn decodes_submit_with_relative_validity() {
+        // SMS-SUBMIT with TP-VPF relative (0x11) and a validity period octet of 0xAA
+        let submit = decode_submit("0011000B916407281553F80000AA0AE8329BFD4697D9EC37").unwrap();
+        assert_eq!(submit.recipient, "+46708251358");
+        assert_eq!(submit.text, "hellohello");
+        assert!(!submit.status_report);
+        assert!(decode_submit("0004048155550000").is_err());
+    }
+
+    #[test]
+    fn truncated_pdus_are_errors() {
+        assert!(decode_deliver("").is_err());
+        assert!(decode_deliver("07917283010010").is_err());
+        assert!(decode_deliver("0XYZ").is_err());
+        assert!(decode_deliver("000100").is_err());
+    }
+}
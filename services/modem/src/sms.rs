@@ -0,0 +1,251 @@
+// ABOUTME: The outbox: messages the user sent, and how far each got, from waiting on the network to delivered.
+// ABOUTME: Messages wait while the modem is off the network and go out once it is back, tried again a few times if refused.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::pdu::Delivery;
+
+/// Messages sent, and the ones still to go.
+pub const OUTBOX_PATH: &str = "/data/modem/outbox.toml";
+
+/// Finished messages the outbox keeps, the oldest dropping off first. Messages still to
+/// go are kept however many there are.
+const OUTBOX_LENGTH: usize = 200;
+
+/// Times the network is asked to take a message before it is given up on.
+pub const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Waiting for the network, or to be tried again.
+    #[default]
+    Pending,
+    /// The network took it; a status report says when it arrives.
+    Sent,
+    Delivered,
+    Failed,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Pending => "pending",
+            Status::Sent => "sent",
+            Status::Delivered => "delivered",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutgoingSms {
+    pub id: u32,
+    pub number: String,
+    pub text: String,
+    /// When it was written, in seconds since the Unix epoch.
+    pub time: u64,
+    pub status: Status,
+    /// The modem's reference for it once sent, which its status report names it by.
+    pub reference: Option<u8>,
+    /// Times the network was asked to take it.
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Outbox {
+    next_id: u32,
+    /// Oldest first.
+    #[serde(rename = "message")]
+    messages: Vec<OutgoingSms>,
+}
+
+impl Outbox {
+    /// The outbox saved at `path`; none yet is an empty one.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let text = toml::to_string(self).context("failed to serialize the outbox")?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, text).with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))
+    }
+
+    pub fn list(&self) -> &[OutgoingSms] {
+        &self.messages
+    }
+
+    /// The messages still to go out, oldest first.
+    pub fn pending(&self) -> Vec<OutgoingSms> {
+        self.messages
+            .iter()
+            .filter(|message| message.status == Status::Pending)
+            .cloned()
+            .collect()
+    }
+
+    /// Add `text` to `number`, written at `time`, to go out. Its id.
+    pub fn queue(&mut self, number: &str, text: &str, time: u64) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.messages.push(OutgoingSms {
+            id,
+            number: number.to_string(),
+            text: text.to_string(),
+            time,
+            ..Default::default()
+        });
+        while self.messages.len() > OUTBOX_LENGTH {
+            let Some(oldest) = self
+                .messages
+                .iter()
+                .position(|message| message.status != Status::Pending)
+            else {
+                break;
+            };
+            self.messages.remove(oldest);
+        }
+        id
+    }
+
+    fn get_mut(&mut self, id: u32) -> Option<&mut OutgoingSms> {
+        self.messages.iter_mut().find(|message| message.id == id)
+    }
+
+    /// The network took message `id`, and the modem gave it `reference`.
+    pub fn sent(&mut self, id: u32, reference: u8) {
+        if let Some(message) = self.get_mut(id) {
+            message.status = Status::Sent;
+            message.reference = Some(reference);
+            message.attempts += 1;
+        }
+    }
+
+    /// The network refused message `id`. It is tried again until it has been tried
+    /// `MAX_ATTEMPTS` times. Where it is now.
+    pub fn refused(&mut self, id: u32) -> Status {
+        let Some(message) = self.get_mut(id) else {
+            return Status::Failed;
+        };
+        message.attempts += 1;
+        if message.attempts >= MAX_ATTEMPTS {
+            message.status = Status::Failed;
+        }
+        message.status
+    }
+
+    /// Take a status report on the last message sent with `reference`. Whether it
+    /// changed anything.
+    pub fn report(&mut self, reference: u8, delivery: Delivery) -> bool {
+        let status = match delivery {
+            Delivery::Delivered => Status::Delivered,
+            Delivery::Failed => Status::Failed,
+            // A final report follows
+            Delivery::Trying => return false,
+        };
+        match self
+            .messages
+            .iter_mut()
+            .rev()
+            .find(|message| message.status == Status::Sent && message.reference == Some(reference))
+        {
+            Some(message) => {
+                message.status = status;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_wait_and_are_tried_again() {
+        let mut outbox = Outbox::default();
+        let first = outbox.queue("+15550001", "on my way", 100);
+        let second = outbox.queue("+15550002", "running late", 200);
+        assert_ne!(first, second);
+        let pending: Vec<u32> = outbox.pending().iter().map(|m| m.id).collect();
+        assert_eq!(pending, [first, second]);
+
+        for _ in 1..MAX_ATTEMPTS {
+            assert_eq!(outbox.refused(first), Status::Pending);
+        }
+        assert_eq!(outbox.refused(first), Status::Failed);
+        assert_eq!(outbox.list()[0].attempts, MAX_ATTEMPTS);
+
+        outbox.sent(second, 7);
+        assert!(outbox.pending().is_empty());
+        assert_eq!(outbox.list()[1].status, Status::Sent);
+        assert_eq!(outbox.list()[1].reference, Some(7));
+    }
+
+    #[test]
+    fn status_reports_settle_sent_messages() {
+        let mut outbox = Outbox::default();
+        let id = outbox.queue("+15550001", "on my way", 100);
+        assert!(!outbox.report(7, Delivery::Delivered));
+
+        outbox.sent(id, 7);
+        assert!(!outbox.report(7, Delivery::Trying));
+        assert!(!outbox.report(8, Delivery::Delivered));
+        assert!(outbox.report(7, Delivery::Delivered));
+        assert_eq!(outbox.list()[0].status, Status::Delivered);
+        // A second report on it changes nothing
+        assert!(!outbox.report(7, Delivery::Failed));
+
+        let id = outbox.queue("+15550002", "running late", 200);
+        outbox.sent(id, 8);
+        assert!(outbox.report(8, Delivery::Failed));
+        assert_eq!(outbox.list()[1].status, Status::Failed);
+    }
+
+    #[test]
+    fn the_outbox_keeps_what_is_still_to_go() {
+        let mut outbox = Outbox::default();
+        let waiting = outbox.queue("+15550001", "still to go", 0);
+        for time in 0..OUTBOX_LENGTH as u64 {
+            let id = outbox.queue("+15550002", "sent", time);
+            outbox.sent(id, time as u8);
+        }
+        assert_eq!(outbox.list().len(), OUTBOX_LENGTH);
+        assert_eq!(outbox.list()[0].id, waiting);
+        assert_eq!(outbox.list()[1].time, 1);
+    }
+
+    #[test]
+    fn the_outbox_is_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modem/outbox.toml");
+        assert_eq!(Outbox::load(&path).unwrap(), Outbox::default());
+
+        let mut outbox = Outbox::default();
+        let id = outbox.queue("+15550001", "on my way", 100);
+        outbox.sent(id, 7);
+        outbox.queue("+15550002", "running late", 200);
+        outbox.save(&path).unwrap();
+        let mut loaded = Outbox::load(&path).unwrap();
+        assert_eq!(loaded, outbox);
+        assert_eq!(loaded.queue("+15550003", "hi", 300), 2);
+    }
+}
@@ -0,0 +1,536 @@
+// ABOUTME: SIM toolkit (ETSI TS 102 223): the proactive commands a SIM sends over +CUSATP, and the phone's answers.
+// ABOUTME: Decodes set up menu, display text, select item and send SMS; encodes terminal responses and menu selections.
+
+use anyhow::{Context, Result, bail, ensure};
+
+use crate::pdu;
+
+/// BER-TLV tags around a proactive command, and around the envelope a menu choice
+/// goes to the SIM in.
+const PROACTIVE_COMMAND: u8 = 0xd0;
+const MENU_SELECTION: u8 = 0xd3;
+
+/// Set on a data object's tag when its receiver has to understand it.
+const COMPREHENSION_REQUIRED: u8 = 0x80;
+
+const COMMAND_DETAILS: u8 = 0x01;
+const DEVICE_IDENTITIES: u8 = 0x02;
+const RESULT: u8 = 0x03;
+const ALPHA_IDENTIFIER: u8 = 0x05;
+const SMS_TPDU: u8 = 0x0b;
+const TEXT_STRING: u8 = 0x0d;
+const ITEM: u8 = 0x0f;
+const ITEM_IDENTIFIER: u8 = 0x10;
+
+/// Types of command the phone carries out.
+const SEND_SHORT_MESSAGE: u8 = 0x13;
+const DISPLAY_TEXT: u8 = 0x21;
+const SELECT_ITEM: u8 = 0x24;
+const SET_UP_MENU: u8 = 0x25;
+
+/// Device identities, naming where a command or answer comes from and goes to.
+const KEYPAD: u8 = 0x01;
+const DISPLAY: u8 = 0x02;
+const UICC: u8 = 0x81;
+const TERMINAL: u8 = 0x82;
+const NETWORK: u8 = 0x83;
+
+/// Display text qualifier bit: the text stays until the user clears it.
+const WAIT_FOR_USER: u8 = 0x80;
+
+/// One step of a toolkit session: command number, type and qualifier. Its answer
+/// repeats them, so the SIM knows what is being answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Details {
+    pub number: u8,
+    pub kind: u8,
+    pub qualifier: u8,
+}
+
+/// A menu entry or choice: the id answers give back, and its text.
+pub type Item = (u8, String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// The SIM's own menu of carrier services. No items takes it away.
+    SetUpMenu { title: String, items: Vec<Item> },
+    /// Text to show until the user clears it.
+    DisplayText { text: String },
+    /// A choice to put to the user.
+    SelectItem { title: String, items: Vec<Item> },
+    /// An SMS to send, with what to tell the user while it goes, if anything.
+    SendSms {
+        alpha: String,
+        recipient: String,
+        text: String,
+    },
+    /// A command the phone doesn't carry out, which it answers as such.
+    Unsupported,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proactive {
+    pub details: Details,
+    pub command: Command,
+}
+
+/// How a command went, as a terminal response reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// The user ended the session.
+    Ended,
+    /// The user went back a step.
+    Back,
+    /// The phone can't right now.
+    Busy,
+    /// The network turned it down, as it can an SMS.
+    NetworkRefused,
+    /// The phone doesn't carry out this command.
+    Unsupported,
+}
+
+impl Outcome {
+    pub fn code(self) -> u8 {
+        match self {
+            Outcome::Done => 0x00,
+            Outcome::Ended => 0x10,
+            Outcome::Back => 0x11,
+            Outcome::Busy => 0x20,
+            Outcome::NetworkRefused => 0x21,
+            Outcome::Unsupported => 0x30,
+        }
+    }
+}
+
+/// A terminal response as the SIM reads it. Used by the modem emulator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub details: Details,
+    pub result: u8,
+    pub item: Option<u8>,
+}
+
+/// Decode a proactive command as +CUSATP reports it, in hex.
+pub fn parse(hex: &str) -> Result<Proactive> {
+    let bytes = pdu::from_hex(hex.trim().trim_matches('"'))?;
+    let outer = tlvs(&bytes)?;
+    let [(PROACTIVE_COMMAND, body)] = outer.as_slice() else {
+        bail!("not a proactive command");
+    };
+    let objects = tlvs(body)?;
+    let details = match find(&objects, COMMAND_DETAILS) {
+        Some(&[number, kind, qualifier]) => Details {
+            number,
+            kind,
+            qualifier,
+        },
+        _ => bail!("proactive command without its details"),
+    };
+
+    let alpha = || {
+        find(&objects, ALPHA_IDENTIFIER)
+            .map(decode_alpha)
+            .unwrap_or_default()
+    };
+    // An empty item is how the SIM says there are none
+    let items = || {
+        objects
+            .iter()
+            .filter(|(tag, _)| tag & !COMPREHENSION_REQUIRED == ITEM)
+            .filter_map(|(_, value)| {
+                let (&id, text) = value.split_first()?;
+                Some((id, decode_alpha(text)))
+            })
+            .collect()
+    };
+    let command = match details.kind {
+        SET_UP_MENU => Command::SetUpMenu {
+            title: alpha(),
+            items: items(),
+        },
+        DISPLAY_TEXT => Command::DisplayText {
+            text: find(&objects, TEXT_STRING)
+                .map(decode_text)
+                .context("display text without its text")?,
+        },
+        SELECT_ITEM => Command::SelectItem {
+            title: alpha(),
+            items: items(),
+        },
+        SEND_SHORT_MESSAGE => {
+            let tpdu = find(&objects, SMS_TPDU).context("send SMS without its message")?;
+            // Decoding packs text the SIM left for the phone to pack, as the message is
+            // encoded again to send
+            let submit = pdu::decode_submit(&format!("00{}", pdu::to_hex(tpdu)))?;
+            Command::SendSms {
+                alpha: alpha(),
+                recipient: submit.recipient,
+                text: submit.text,
+            }
+        }
+        _ => Command::Unsupported,
+    };
+    Ok(Proactive { details, command })
+}
+
+/// The answer to the command `details` name, in hex for AT+CUSATT. `item` is the
+/// user's choice, for select item.
+pub fn terminal_response(details: Details, outcome: Outcome, item: Option<u8>) -> String {
+    let mut out = Vec::new();
+    push_tlv(
+        &mut out,
+        COMMAND_DETAILS | COMPREHENSION_REQUIRED,
+        &[details.number, details.kind, details.qualifier],
+    );
+    push_tlv(
+        &mut out,
+        DEVICE_IDENTITIES | COMPREHENSION_REQUIRED,
+        &[TERMINAL, UICC],
+    );
+    push_tlv(&mut out, RESULT | COMPREHENSION_REQUIRED, &[outcome.code()]);
+    if let Some(item) = item {
+        push_tlv(&mut out, ITEM_IDENTIFIER | COMPREHENSION_REQUIRED, &[item]);
+    }
+    pdu::to_hex(&out)
+}
+
+/// The envelope telling the SIM the user opened `item` of its menu, in hex for
+/// AT+CUSATE.
+pub fn menu_selection(item: u8) -> String {
+    let mut body = Vec::new();
+    push_tlv(
+        &mut body,
+        DEVICE_IDENTITIES | COMPREHENSION_REQUIRED,
+        &[KEYPAD, UICC],
+    );
+    push_tlv(&mut body, ITEM_IDENTIFIER | COMPREHENSION_REQUIRED, &[item]);
+    let mut out = Vec::new();
+    push_tlv(&mut out, MENU_SELECTION, &body);
+    pdu::to_hex(&out)
+}
+
+/// Encode `command` as a SIM sends it, numbered `number`, in hex. Used by the modem
+/// emulator.
+pub fn encode(number: u8, command: &Command) -> Result<String> {
+    let (kind, qualifier, destination) = match command {
+        Command::SetUpMenu { .. } => (SET_UP_MENU, 0, TERMINAL),
+        Command::DisplayText { .. } => (DISPLAY_TEXT, WAIT_FOR_USER, DISPLAY),
+        Command::SelectItem { .. } => (SELECT_ITEM, 0, TERMINAL),
+        Command::SendSms { .. } => (SEND_SHORT_MESSAGE, 0, NETWORK),
+        Command::Unsupported => bail!("there is nothing to encode for an unsupported command"),
+    };
+    let mut body = Vec::new();
+    push_tlv(
+        &mut body,
+        COMMAND_DETAILS | COMPREHENSION_REQUIRED,
+        &[number, kind, qualifier],
+    );
+    push_tlv(
+        &mut body,
+        DEVICE_IDENTITIES | COMPREHENSION_REQUIRED,
+        &[UICC, destination],
+    );
+    match command {
+        Command::SetUpMenu { title, items } | Command::SelectItem { title, items } => {
+            push_tlv(&mut body, ALPHA_IDENTIFIER, &encode_alpha(title));
+            for (id, text) in items {
+                let mut item = vec![*id];
+                item.extend(encode_alpha(text));
+                push_tlv(&mut body, ITEM, &item);
+            }
+            if items.is_empty() {
+                push_tlv(&mut body, ITEM, &[]);
+            }
+        }
+        Command::DisplayText { text } => {
+            push_tlv(
+                &mut body,
+                TEXT_STRING | COMPREHENSION_REQUIRED,
+                &encode_text(text),
+            );
+        }
+        Command::SendSms {
+            alpha,
+            recipient,
+            text,
+        } => {
+            push_tlv(&mut body, ALPHA_IDENTIFIER, &encode_alpha(alpha));
+            let (pdu, _) = pdu::encode_submit(recipient, text, false)?;
+            // Without the empty SMSC field in front
+            let tpdu = pdu::from_hex(&pdu[2..])?;
+            push_tlv(&mut body, SMS_TPDU | COMPREHENSION_REQUIRED, &tpdu);
+        }
+        Command::Unsupported => {}
+    }
+    ensure!(body.len() <= 0xff, "proactive command too long");
+    let mut out = Vec::new();
+    push_tlv(&mut out, PROACTIVE_COMMAND, &body);
+    Ok(pdu::to_hex(&out))
+}
+
+/// Decode a terminal response written with AT+CUSATT. Used by the modem emulator.
+pub fn parse_terminal_response(hex: &str) -> Result<Response> {
+    let bytes = pdu::from_hex(hex.trim().trim_matches('"'))?;
+    let objects = tlvs(&bytes)?;
+    let details = match find(&objects, COMMAND_DETAILS) {
+        Some(&[number, kind, qualifier]) => Details {
+            number,
+            kind,
+            qualifier,
+        },
+        _ => bail!("terminal response without the command's details"),
+    };
+    let result = *find(&objects, RESULT)
+        .and_then(<[u8]>::first)
+        .context("terminal response without a result")?;
+    let item = find(&objects, ITEM_IDENTIFIER).and_then(|id| id.first().copied());
+    Ok(Response {
+        details,
+        result,
+        item,
+    })
+}
+
+/// The menu item a menu selection envelope written with AT+CUSATE opens. Used by the
+/// modem emulator.
+pub fn parse_menu_selection(hex: &str) -> Result<u8> {
+    let bytes = pdu::from_hex(hex.trim().trim_matches('"'))?;
+    let outer = tlvs(&bytes)?;
+    let [(MENU_SELECTION, body)] = outer.as_slice() else {
+        bail!("not a menu selection");
+    };
+    let objects = tlvs(body)?;
+    find(&objects, ITEM_IDENTIFIER)
+        .and_then(|id| id.first().copied())
+        .context("menu selection without an item")
+}
+
+/// Split a run of TLV data objects into tags and values.
+fn tlvs(mut bytes: &[u8]) -> Result<Vec<(u8, &[u8])>> {
+    let mut objects = Vec::new();
+    while let Some((&tag, rest)) = bytes.split_first() {
+        // Lengths past 127 take a second octet after 0x81
+        let (length, rest) = match rest {
+            [0x81, length, rest @ ..] => (*length as usize, rest),
+            [length, rest @ ..] if *length < 0x80 => (*length as usize, rest),
+            _ => bail!("bad length after tag {tag:#04x}"),
+        };
+        ensure!(rest.len() >= length, "tag {tag:#04x} runs past the end");
+        let (value, rest) = rest.split_at(length);
+        objects.push((tag, value));
+        bytes = rest;
+    }
+    Ok(objects)
+}
+
+/// The value of the first object tagged `tag`, comprehension required or not.
+fn find<'a>(objects: &[(u8, &'a [u8])], tag: u8) -> Option<&'a [u8]> {
+    objects
+        .iter()
+        .find(|(t, _)| t & !COMPREHENSION_REQUIRED == tag)
+        .map(|(_, value)| *value)
+}
+
+fn push_tlv(out: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    out.push(tag);
+    if value.len() >= 0x80 {
+        out.push(0x81);
+    }
+    out.push(value.len() as u8);
+    out.extend(value);
+}
+
+/// Decode a text string: a data coding scheme, then the text in GSM 7-bit packed,
+/// GSM 8-bit or UCS2.
+fn decode_text(value: &[u8]) -> String {
+    let Some((&dcs, data)) = value.split_first() else {
+        return String::new();
+    };
+    match dcs & 0x0c {
+        0x00 => {
+            let septets = pdu::unpack_septets(data, data.len() * 8 / 7);
+            // Seven spare bits at the end are filled with CR, not a character
+            pdu::from_septets(&septets)
+                .trim_end_matches('\r')
+                .to_string()
+        }
+        0x08 => ucs2(data),
+        _ => gsm_unpacked(data),
+    }
+}
+
+/// Decode an alpha identifier or item text, coded as SIM files are (TS 102 221
+/// annex A): GSM default alphabet one character per octet, or one of three UCS2 forms
+/// flagged by the first octet.
+fn decode_alpha(value: &[u8]) -> String {
+    match value {
+        [0x80, rest @ ..] => ucs2(rest),
+        // A count, then half of a base code point, then octets that are either GSM
+        // characters or offsets from the base
+        [0x81, count, base, rest @ ..] => offsets(u16::from(*base) << 7, *count, rest),
+        [0x82, count, high, low, rest @ ..] => {
+            offsets(u16::from_be_bytes([*high, *low]), *count, rest)
+        }
+        _ => gsm_unpacked(value),
+    }
+}
+
+fn offsets(base: u16, count: u8, octets: &[u8]) -> String {
+    octets
+        .iter()
+        .take(count as usize)
+        .map(|&octet| {
+            if octet < 0x80 {
+                pdu::from_septets(&[octet])
+            } else {
+                char::from_u32(u32::from(base) + u32::from(octet & 0x7f))
+                    .map(String::from)
+                    .unwrap_or_default()
+            }
+        })
+        .collect()
+}
+
+/// GSM default alphabet one character per octet, padded with 0xFF.
+fn gsm_unpacked(octets: &[u8]) -> String {
+    let septets: Vec<u8> = octets
+        .iter()
+        .take_while(|&&octet| octet != 0xff)
+        .map(|octet| octet & 0x7f)
+        .collect();
+    pdu::from_septets(&septets)
+}
+
+fn ucs2(octets: &[u8]) -> String {
+    let units: Vec<u16> = octets
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .take_while(|&unit| unit != 0xffff)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn encode_alpha(text: &str) -> Vec<u8> {
+    match pdu::to_septets(text) {
+        Some(septets) => septets,
+        None => {
+            let mut out = vec![0x80];
+            out.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+            out
+        }
+    }
+}
+
+fn encode_text(text: &str) -> Vec<u8> {
+    match pdu::to_septets(text) {
+        // GSM 8-bit
+        Some(septets) => [&[0x04][..], &septets].concat(),
+        None => {
+            let mut out = vec![0x08];
+            out.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_a_sim_menu() {
+        // Set up menu "SIM" with Balance and Help, as a SIM sends it
+        let hex = "D01F810301250082028182850353494D8F080142616C616E63658F050248656C70";
+        let proactive = parse(hex).unwrap();
+        assert_eq!(
+            proactive.details,
+            Details {
+                number: 1,
+                kind: SET_UP_MENU,
+                qualifier: 0
+            }
+        );
+        assert_eq!(
+            proactive.command,
+            Command::SetUpMenu {
+                title: "SIM".to_string(),
+                items: vec![(1, "Balance".to_string()), (2, "Help".to_string())],
+            }
+        );
+
+        // A menu without items takes it away
+        assert_eq!(
+            parse("D00D81030125008202818285008F00").unwrap().command,
+            Command::SetUpMenu {
+                title: String::new(),
+                items: Vec::new()
+            }
+        );
+        assert!(parse("D00681030125").is_err());
+        assert!(parse("D10381030125").is_err());
+    }
+
+    #[test]
+    fn commands_survive_encoding() {
+        let commands = [
+            Command::DisplayText {
+                text: "Your balance is €4.20".to_string(),
+            },
+            Command::DisplayText {
+                text: "Баланс".to_string(),
+            },
+            Command::SelectItem {
+                title: "Services".to_string(),
+                items: vec![(7, "News".to_string()), (8, "Погода".to_string())],
+            },
+            Command::SendSms {
+                alpha: "Subscribing".to_string(),
+                recipient: "+15550100".to_string(),
+                text: "NEWS ON".to_string(),
+            },
+        ];
+        for (number, command) in (1..).zip(commands) {
+            let proactive = parse(&encode(number, &command).unwrap()).unwrap();
+            assert_eq!(proactive.details.number, number);
+            assert_eq!(proactive.command, command);
+        }
+        assert!(encode(1, &Command::Unsupported).is_err());
+    }
+
+    #[test]
+    fn packed_and_compressed_texts_decode() {
+        // "Hi" packed in 7 bits
+        assert_eq!(decode_text(&[0x00, 0xc8, 0x34]), "Hi");
+        assert_eq!(decode_text(&[]), "");
+        // Two Cyrillic letters as offsets from U+0400, and a GSM space between
+        assert_eq!(decode_alpha(&[0x81, 0x03, 0x08, 0x91, 0x20, 0x92]), "Б В");
+        assert_eq!(decode_alpha(&[0x82, 0x02, 0x04, 0x00, 0x91, 0x92]), "БВ");
+        assert_eq!(decode_alpha(&[0x4f, 0x4b, 0xff, 0xff]), "OK");
+    }
+
+    #[test]
+    fn answers_repeat_the_command() {
+        let details = Details {
+            number: 3,
+            kind: SELECT_ITEM,
+            qualifier: 0,
+        };
+        let response = terminal_response(details, Outcome::Done, Some(8));
+        assert_eq!(response, "810303240082028281830100900108");
+        assert_eq!(
+            parse_terminal_response(&response).unwrap(),
+            Response {
+                details,
+                result: 0,
+                item: Some(8)
+            }
+        );
+        let ended = terminal_response(details, Outcome::Ended, None);
+        assert_eq!(parse_terminal_response(&ended).unwrap().result, 0x10);
+
+        assert_eq!(menu_selection(2), "D30782020181900102");
+        assert_eq!(parse_menu_selection(&menu_selection(2)).unwrap(), 2);
+        assert!(parse_menu_selection(&response).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+// ABOUTME: Mobile data use per day and the plan it counts against: a warning level and a cap per billing cycle.
+// ABOUTME: Counted from the cellular interface's byte counters and kept on /data with the plan.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use chrono::{Datelike, Days, Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+/// Where the plan and use are kept, in the network service's state directory.
+pub const DATA_FILE: &str = "data.toml";
+
+/// Names the cellular interface counted, if it isn't CELLULAR_INTERFACE.
+pub const CELLULAR_INTERFACE_ENV: &str = "MOS_CELLULAR_INTERFACE";
+
+pub const CELLULAR_INTERFACE: &str = "wwan0";
+
+/// Days of use kept, a little over two cycles.
+const KEEP_DAYS: u64 = 70;
+
+/// The billing plan, in bytes per cycle; 0 for no warning or no cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DataPlan {
+    pub warning: u64,
+    pub cap: u64,
+    /// Day of the month the cycle starts on, 1 to 28 so every month has it.
+    pub cycle_day: u32,
+}
+
+impl Default for DataPlan {
+    fn default() -> Self {
+        Self {
+            warning: 0,
+            cap: 0,
+            cycle_day: 1,
+        }
+    }
+}
+
+impl DataPlan {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=28).contains(&self.cycle_day) {
+            bail!("the cycle starts on day 1 to 28, not {}", self.cycle_day);
+        }
+        if self.warning > 0 && self.cap > 0 && self.warning >= self.cap {
+            bail!("the warning has to come before the cap");
+        }
+        Ok(())
+    }
+
+    /// The day the cycle `today` is in started.
+    pub fn cycle_start(&self, today: NaiveDate) -> NaiveDate {
+        let this_month = today.with_day(self.cycle_day).unwrap_or(today);
+        if this_month <= today {
+            this_month
+        } else {
+            this_month - Months::new(1)
+        }
+    }
+}
+
+/// Where use stands against the plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Normal,
+    Warning,
+    Capped,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DataUsage {
+    pub plan: DataPlan,
+    /// Bytes sent and received each day.
+    days: BTreeMap<NaiveDate, u64>,
+    /// The start of the cycle the warning was last given in, so it comes once a cycle.
+    warned: Option<NaiveDate>,
+    /// Data was taken down at the cap, and is to come back up when use is under it.
+    pub capped: bool,
+}
+
+impl DataUsage {
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string(self).context("failed to serialize data use")?;
+        crate::proxy::write_atomically(path, &text)
+    }
+
+    /// Count `bytes` against `today`, forgetting days long gone.
+    pub fn add(&mut self, today: NaiveDate, bytes: u64) {
+        *self.days.entry(today).or_default() += bytes;
+        if let Some(oldest) = today.checked_sub_days(Days::new(KEEP_DAYS)) {
+            self.days = self.days.split_off(&oldest);
+        }
+    }
+
+    /// Bytes each day of the cycle so far, from its first day up to `today`.
+    pub fn cycle(&self, today: NaiveDate) -> Vec<(NaiveDate, u64)> {
+        self.plan
+            .cycle_start(today)
+            .iter_days()
+            .take_while(|day| *day <= today)
+            .map(|day| (day, self.days.get(&day).copied().unwrap_or(0)))
+            .collect()
+    }
+
+    pub fn used(&self, today: NaiveDate) -> u64 {
+        self.days
+            .range(self.plan.cycle_start(today)..=today)
+            .map(|(_, bytes)| bytes)
+            .sum()
+    }
+
+    pub fn level(&self, today: NaiveDate) -> Level {
+        let used = self.used(today);
+        if self.plan.cap > 0 && used >= self.plan.cap {
+            Level::Capped
+        } else if self.plan.warning > 0 && used >= self.plan.warning {
+            Level::Warning
+        } else {
+            Level::Normal
+        }
+    }
+
+    /// Whether the warning is due `today`: use is past it, and it wasn't given yet
+    /// this cycle. Marks it given.
+    pub fn take_warning(&mut self, today: NaiveDate) -> bool {
+        let cycle = self.plan.cycle_start(today);
+        if self.level(today) < Level::Warning || self.warned == Some(cycle) {
+            return false;
+        }
+        self.warned = Some(cycle);
+        true
+    }
+}
+
+/// Reads how many bytes went over an interface since the last read.
+pub struct Counter {
+    /// The interface's directory under /sys/class/net.
+    dir: PathBuf,
+    last: Option<u64>,
+}
+
+impl Counter {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir, last: None }
+    }
+
+    /// Bytes sent and received since the last read, 0 at the first. Counters start
+    /// over when the interface comes back, and then all they hold is new.
+    pub fn read(&mut self) -> Result<u64> {
+        let read = |name: &str| -> Result<u64> {
+            let path = self.dir.join("statistics").join(name);
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            text.trim()
+                .parse()
+                .with_context(|| format!("{} is not a count", path.display()))
+        };
+        let total = read("rx_bytes")? + read("tx_bytes")?;
+        let new = match self.last {
+            Some(last) if total >= last => total - last,
+            Some(_) => total,
+            None => 0,
+        };
+        self.last = Some(total);
+        Ok(new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, month, day).unwrap()
+    }
+
+    #[test]
+    fn cycles_start_on_the_plan_day() {
+        let plan = DataPlan {
+            cycle_day: 15,
+            ..DataPlan::default()
+        };
+        assert_eq!(plan.cycle_start(date(10, 18)), date(10, 15));
+        assert_eq!(plan.cycle_start(date(10, 15)), date(10, 15));
+        assert_eq!(plan.cycle_start(date(10, 3)), date(9, 15));
+        assert_eq!(
+            plan.cycle_start(date(1, 3)),
+            NaiveDate::from_ymd_opt(2025, 12, 15).unwrap()
+        );
+        assert!(plan.validate().is_ok());
+
+        let plan = |warning, cap, cycle_day| DataPlan {
+            warning,
+            cap,
+            cycle_day,
+        };
+        assert!(plan(0, 0, 31).validate().is_err());
+        assert!(plan(5, 5, 1).validate().is_err());
+        assert!(plan(5, 0, 1).validate().is_ok());
+    }
+
+    #[test]
+    fn use_counts_against_the_cycle() {
+        let mut usage = DataUsage {
+            plan: DataPlan {
+                warning: 800,
+                cap: 1000,
+                cycle_day: 15,
+            },
+            ..DataUsage::default()
+        };
+        usage.add(date(10, 14), 900);
+        usage.add(date(10, 15), 300);
+        usage.add(date(10, 17), 200);
+        assert_eq!(usage.used(date(10, 17)), 500);
+        assert_eq!(
+            usage.cycle(date(10, 17)),
+            [(date(10, 15), 300), (date(10, 16), 0), (date(10, 17), 200)]
+        );
+        assert_eq!(usage.level(date(10, 17)), Level::Normal);
+        assert!(!usage.take_warning(date(10, 17)));
+
+        usage.add(date(10, 18), 400);
+        assert_eq!(usage.level(date(10, 18)), Level::Warning);
+        assert!(usage.take_warning(date(10, 18)));
+        assert!(!usage.take_warning(date(10, 18)));
+
+        usage.add(date(10, 18), 100);
+        assert_eq!(usage.level(date(10, 18)), Level::Capped);
+        // A new cycle starts over, and forgets days long gone
+        assert_eq!(usage.level(date(11, 15)), Level::Normal);
+        usage.add(date(12, 30), 1);
+        assert_eq!(usage.used(date(12, 30)), 1);
+        assert!(!usage.days.contains_key(&date(10, 14)));
+    }
+
+    #[test]
+    fn counters_give_what_is_new() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = dir.path().join("statistics");
+        std::fs::create_dir(&stats).unwrap();
+        let set = |rx: u64, tx: u64| {
+            std::fs::write(stats.join("rx_bytes"), format!("{rx}\n")).unwrap();
+            std::fs::write(stats.join("tx_bytes"), format!("{tx}\n")).unwrap();
+        };
+        let mut counter = Counter::new(dir.path().to_path_buf());
+
+        set(1000, 500);
+        assert_eq!(counter.read().unwrap(), 0);
+        set(1600, 700);
+        assert_eq!(counter.read().unwrap(), 800);
+        // The interface came back with fresh counters
+        set(100, 50);
+        assert_eq!(counter.read().unwrap(), 150);
+
+        std::fs::remove_dir_all(&stats).unwrap();
+        assert!(counter.read().is_err());
+    }
+}
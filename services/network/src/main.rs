@@ -1,10 +1,33 @@
 // ABOUTME: Network management D-Bus daemon for MobileOS.
-// ABOUTME: Exposes WiFi connection state, scanning, and connect/disconnect over org.mobileos.Network.
+// ABOUTME: Exposes WiFi connection state, scanning, connect/disconnect, proxy settings and the mobile data cap over org.mobileos.Network.
 
+mod data;
+mod proxy;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{Local, NaiveDate};
+use tokio::sync::Notify;
+use tracing::{debug, error, info, warn};
+use zbus::object_server::{InterfaceRef, SignalEmitter};
+use zbus::{connection, fdo, interface};
+
+use data::{Counter, DataPlan, DataUsage, Level};
+use proxy::{Proxy, ProxySettings};
+
+/// Where the proxy settings, the environment file for them, and mobile data use are
+/// kept.
+const STATE_DIR: &str = "/data/network";
 
-use tracing::info;
-use zbus::{connection, interface};
+/// How often mobile data use is counted and checked against the plan.
+const DATA_TICK: Duration = Duration::from_secs(60);
+
+/// A proxy as it goes over the bus: mode, `host:port` servers by scheme, hosts to
+/// reach directly, and PAC URL.
+type ProxyTuple = (String, HashMap<String, String>, Vec<String>, String);
 
 struct NetworkState {
     connected: bool,
@@ -13,8 +36,24 @@ struct NetworkState {
     connection_type: String,
 }
 
+/// What counting mobile data called for.
+#[derive(Debug, Default, PartialEq)]
+struct DataChange {
+    /// Use went past the warning, first this cycle: bytes used and the warning.
+    warning: Option<(u64, u64)>,
+    /// Data is to go down at the cap, or back up under it.
+    capped: Option<bool>,
+}
+
 struct NetworkService {
     state: Arc<Mutex<NetworkState>>,
+    proxy: Arc<Mutex<ProxySettings>>,
+    data: Arc<Mutex<DataUsage>>,
+    /// Woken when the data plan changes, to check use against it straight away.
+    data_plan_changed: Arc<Notify>,
+    /// Where the proxy settings and data use are stored; kept in memory only without
+    /// one.
+    state_dir: Option<PathBuf>,
 }
 
 impl NetworkService {
@@ -26,8 +65,96 @@ impl NetworkService {
                 ip_address: String::new(),
                 connection_type: "none".to_string(),
             })),
+            proxy: Arc::new(Mutex::new(ProxySettings::default())),
+            data: Arc::new(Mutex::new(DataUsage::default())),
+            data_plan_changed: Arc::new(Notify::new()),
+            state_dir: None,
         }
     }
+
+    /// Keep the proxy settings in `dir`, starting from those stored there.
+    fn with_state_dir(mut self, dir: PathBuf) -> Self {
+        let settings = ProxySettings::load(&dir.join(proxy::SETTINGS_FILE)).unwrap_or_else(|e| {
+            error!(error = %e, "failed to load proxy settings");
+            ProxySettings::default()
+        });
+        self.proxy = Arc::new(Mutex::new(settings));
+        let data = DataUsage::load(&dir.join(data::DATA_FILE)).unwrap_or_else(|e| {
+            error!(error = %e, "failed to load mobile data use");
+            DataUsage::default()
+        });
+        self.data = Arc::new(Mutex::new(data));
+        self.state_dir = Some(dir);
+        self
+    }
+
+    /// Write the environment file for the proxy of the network connected to, so apps
+    /// started from now on use it.
+    fn apply_proxy(&self) {
+        let Some(dir) = &self.state_dir else {
+            return;
+        };
+        let ssid = {
+            let state = self.state.lock().unwrap();
+            if state.connected {
+                state.ssid.clone()
+            } else {
+                String::new()
+            }
+        };
+        let env = self.proxy.lock().unwrap().get(&ssid).environment();
+        if let Err(e) = proxy::write_atomically(&dir.join(proxy::ENV_FILE), &env) {
+            error!(error = %e, "failed to write the proxy environment");
+        }
+    }
+
+    /// Store the proxy settings after a change, and apply them.
+    fn save_proxy(&self) -> fdo::Result<()> {
+        if let Some(dir) = &self.state_dir {
+            let saved = self
+                .proxy
+                .lock()
+                .unwrap()
+                .save(&dir.join(proxy::SETTINGS_FILE));
+            if let Err(e) = saved {
+                error!(error = %e, "failed to save proxy settings");
+                return Err(fdo::Error::Failed(format!("{e:#}")));
+            }
+        }
+        self.apply_proxy();
+        Ok(())
+    }
+
+    fn save_data(&self) {
+        if let Some(dir) = &self.state_dir
+            && let Err(e) = self.data.lock().unwrap().save(&dir.join(data::DATA_FILE))
+        {
+            error!(error = %e, "failed to save mobile data use");
+        }
+    }
+
+    /// Count `bytes` of mobile data against `today`, and say whether the warning is
+    /// due or data is to go down or come back up.
+    fn count_data(&self, bytes: u64, today: NaiveDate) -> DataChange {
+        let change = {
+            let mut data = self.data.lock().unwrap();
+            data.add(today, bytes);
+            let warning = data
+                .take_warning(today)
+                .then(|| (data.used(today), data.plan.warning));
+            let capped = data.level(today) == Level::Capped;
+            let change = DataChange {
+                warning,
+                capped: (capped != data.capped).then_some(capped),
+            };
+            data.capped = capped;
+            change
+        };
+        if bytes > 0 || change != DataChange::default() {
+            self.save_data();
+        }
+        change
+    }
 }
 
 #[interface(name = "org.mobileos.Network")]
@@ -63,20 +190,185 @@ impl NetworkService {
 
     async fn connect(&self, ssid: String, _password: String) {
         info!(ssid = %ssid, "connecting to network");
-        let mut state = self.state.lock().unwrap();
-        state.connected = true;
-        state.ssid = ssid;
-        state.ip_address = "192.168.1.100".to_string();
-        state.connection_type = "wifi".to_string();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.connected = true;
+            state.ssid = ssid;
+            state.ip_address = "192.168.1.100".to_string();
+            state.connection_type = "wifi".to_string();
+        }
+        self.apply_proxy();
     }
 
     async fn disconnect(&self) {
         info!("disconnecting from network");
-        let mut state = self.state.lock().unwrap();
-        state.connected = false;
-        state.ssid.clear();
-        state.ip_address.clear();
-        state.connection_type = "none".to_string();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.connected = false;
+            state.ssid.clear();
+            state.ip_address.clear();
+            state.connection_type = "none".to_string();
+        }
+        self.apply_proxy();
+    }
+
+    /// The proxy used on the network `ssid`, or by default when it is empty.
+    async fn proxy(&self, ssid: String) -> ProxyTuple {
+        let settings = self.proxy.lock().unwrap();
+        let proxy = settings.get(&ssid);
+        (
+            proxy.mode.as_str().to_string(),
+            proxy.servers(),
+            proxy.ignore_hosts.clone(),
+            proxy.pac_url.clone(),
+        )
+    }
+
+    /// Use a proxy on the network `ssid`, or by default when it is empty. `mode` is
+    /// "none", "manual" with `host:port` servers for "http", "https" and "socks", or
+    /// "auto" with a PAC URL.
+    async fn set_proxy(
+        &self,
+        ssid: String,
+        mode: String,
+        servers: HashMap<String, String>,
+        ignore_hosts: Vec<String>,
+        pac_url: String,
+    ) -> fdo::Result<()> {
+        let invalid = |e: anyhow::Error| fdo::Error::InvalidArgs(format!("{e:#}"));
+        let mut proxy = Proxy {
+            mode: mode.parse().map_err(invalid)?,
+            ignore_hosts,
+            pac_url,
+            ..Proxy::default()
+        };
+        for (scheme, server) in servers {
+            proxy.set_server(&scheme, server).map_err(invalid)?;
+        }
+        proxy.validate().map_err(invalid)?;
+        info!(ssid = %ssid, mode = proxy.mode.as_str(), "proxy set");
+        self.proxy.lock().unwrap().set(&ssid, proxy);
+        self.save_proxy()
+    }
+
+    /// Drop the proxy of the network `ssid`, which goes back to the default, or the
+    /// default proxy when it is empty.
+    async fn clear_proxy(&self, ssid: String) -> fdo::Result<()> {
+        info!(ssid = %ssid, "proxy cleared");
+        self.proxy.lock().unwrap().clear(&ssid);
+        self.save_proxy()
+    }
+
+    /// The mobile data plan: bytes a cycle at which to warn and at which to turn data
+    /// off, 0 for neither, and the day of the month the cycle starts.
+    #[zbus(property)]
+    fn data_plan(&self) -> (u64, u64, u32) {
+        let plan = self.data.lock().unwrap().plan;
+        (plan.warning, plan.cap, plan.cycle_day)
+    }
+
+    async fn set_data_plan(
+        &self,
+        warning: u64,
+        cap: u64,
+        cycle_day: u32,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        let plan = DataPlan {
+            warning,
+            cap,
+            cycle_day,
+        };
+        plan.validate()
+            .map_err(|e| fdo::Error::InvalidArgs(format!("{e:#}")))?;
+        info!(warning, cap, cycle_day, "mobile data plan set");
+        self.data.lock().unwrap().plan = plan;
+        self.save_data();
+        self.data_plan_changed.notify_one();
+        self.data_plan_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// Bytes of mobile data used each day of the billing cycle so far, by date as
+    /// "2026-10-18".
+    async fn data_cycle(&self) -> Vec<(String, u64)> {
+        self.data
+            .lock()
+            .unwrap()
+            .cycle(Local::now().date_naive())
+            .into_iter()
+            .map(|(date, bytes)| (date.to_string(), bytes))
+            .collect()
+    }
+
+    /// Mobile data is off for the rest of the cycle, having reached the cap.
+    #[zbus(property)]
+    fn data_capped(&self) -> bool {
+        self.data.lock().unwrap().capped
+    }
+
+    /// Mobile data use went past the plan's warning this cycle.
+    #[zbus(signal)]
+    async fn data_warning(emitter: &SignalEmitter<'_>, used: u64, warning: u64)
+    -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Modem",
+    default_service = "org.mobileos.Modem",
+    default_path = "/org/mobileos/Modem"
+)]
+trait Modem {
+    fn set_data_enabled(&self, enabled: bool) -> zbus::Result<()>;
+}
+
+/// Count mobile data every tick, or as soon as the plan changes, warn once a cycle,
+/// and have the modem take data down at the cap and bring it back under it.
+async fn watch_data(
+    mut counter: Counter,
+    modem: ModemProxy<'static>,
+    iface: InterfaceRef<NetworkService>,
+) {
+    let (data_plan_changed, capped) = {
+        let service = iface.get().await;
+        let capped = service.data.lock().unwrap().capped;
+        (service.data_plan_changed.clone(), capped)
+    };
+    // The modem may have come back up with data since the cap was reached
+    if capped && let Err(e) = modem.set_data_enabled(false).await {
+        warn!(error = %e, "failed to keep mobile data off at the cap");
+    }
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(DATA_TICK) => {}
+            _ = data_plan_changed.notified() => {}
+        }
+        // Without a cellular interface up there is nothing to count
+        let bytes = counter.read().unwrap_or_else(|e| {
+            debug!(error = %e, "no mobile data counted");
+            0
+        });
+        let change = iface
+            .get()
+            .await
+            .count_data(bytes, Local::now().date_naive());
+
+        let emitter = iface.signal_emitter();
+        if let Some((used, warning)) = change.warning {
+            info!(used, warning, "mobile data past the warning");
+            if let Err(e) = NetworkService::data_warning(emitter, used, warning).await {
+                warn!(error = %e, "failed to signal the data warning");
+            }
+        }
+        if let Some(capped) = change.capped {
+            info!(capped, "mobile data cap");
+            if let Err(e) = modem.set_data_enabled(!capped).await {
+                warn!(error = %e, "failed to turn mobile data on or off at the cap");
+            }
+            if let Err(e) = iface.get().await.data_capped_changed(emitter).await {
+                warn!(error = %e, "failed to emit the data cap change");
+            }
+        }
     }
 }
 
@@ -91,15 +383,27 @@ async fn main() -> anyhow::Result<()> {
 
     info!("starting network service");
 
-    let service = NetworkService::new();
+    let service = NetworkService::new().with_state_dir(PathBuf::from(STATE_DIR));
+    service.apply_proxy();
 
-    let _connection = connection::Builder::session()?
+    let connection = connection::Builder::system()?
         .name("org.mobileos.Network")?
         .serve_at("/org/mobileos/Network", service)?
         .build()
         .await?;
 
-    info!("network service running on session bus");
+    let iface = connection
+        .object_server()
+        .interface::<_, NetworkService>("/org/mobileos/Network")
+        .await?;
+    let modem = ModemProxy::new(&connection).await?;
+    let interface = std::env::var(data::CELLULAR_INTERFACE_ENV)
+        .unwrap_or_else(|_| data::CELLULAR_INTERFACE.to_string());
+    let counter = Counter::new(PathBuf::from("/sys/class/net").join(interface));
+    tokio::spawn(watch_data(counter, modem, iface));
+
+    info!("network service running on system bus");
+    mos_notify::ready();
 
     std::future::pending::<()>().await;
     Ok(())
@@ -107,6 +411,8 @@ async fn main() -> anyhow::Result<()> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use zbus::{connection, proxy, Connection};
 
     #[proxy(
@@ -129,6 +435,26 @@ mod tests {
         fn scan(&self) -> zbus::Result<Vec<String>>;
         fn connect(&self, ssid: &str, password: &str) -> zbus::Result<()>;
         fn disconnect(&self) -> zbus::Result<()>;
+
+        fn proxy(&self, ssid: &str) -> zbus::Result<super::ProxyTuple>;
+        fn set_proxy(
+            &self,
+            ssid: &str,
+            mode: &str,
+            servers: HashMap<&str, &str>,
+            ignore_hosts: &[&str],
+            pac_url: &str,
+        ) -> zbus::Result<()>;
+        fn clear_proxy(&self, ssid: &str) -> zbus::Result<()>;
+
+        #[zbus(property)]
+        fn data_plan(&self) -> zbus::Result<(u64, u64, u32)>;
+
+        fn set_data_plan(&self, warning: u64, cap: u64, cycle_day: u32) -> zbus::Result<()>;
+        fn data_cycle(&self) -> zbus::Result<Vec<(String, u64)>>;
+
+        #[zbus(property)]
+        fn data_capped(&self) -> zbus::Result<bool>;
     }
 
     async fn start_test_service() -> (Connection, zbus::names::OwnedUniqueName) {
@@ -212,4 +538,113 @@ mod tests {
         assert!(!proxy.connected().await.unwrap());
         assert_eq!(proxy.ssid().await.unwrap(), "");
     }
+
+    #[tokio::test]
+    async fn proxy_follows_the_network() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = super::NetworkService::new().with_state_dir(dir.path().to_path_buf());
+        let conn = connection::Builder::session()
+            .unwrap()
+            .serve_at("/org/mobileos/Network", service)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let client = Connection::session().await.unwrap();
+        let proxy = NetworkProxy::builder(&client)
+            .destination(conn.unique_name().unwrap().to_owned())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let env = || std::fs::read_to_string(dir.path().join("proxy.env")).unwrap();
+
+        let servers = HashMap::from([("http", "proxy.office.example:3128")]);
+        proxy
+            .set_proxy("Office", "manual", servers, &["localhost"], "")
+            .await
+            .unwrap();
+        let (mode, servers, ignore_hosts, _) = proxy.proxy("Office").await.unwrap();
+        assert_eq!(mode, "manual");
+        assert_eq!(servers["http"], "proxy.office.example:3128");
+        assert_eq!(ignore_hosts, ["localhost"]);
+        assert_eq!(proxy.proxy("").await.unwrap().0, "none");
+
+        proxy.connect("Office", "").await.unwrap();
+        assert!(env().contains("http_proxy=http://proxy.office.example:3128"));
+        proxy.connect("HomeWiFi", "").await.unwrap();
+        assert!(!env().contains("http_proxy"));
+
+        // Bad settings are refused and leave the stored ones alone
+        let bad = HashMap::from([("http", "proxy.office.example")]);
+        assert!(
+            proxy
+                .set_proxy("Office", "manual", bad, &[], "")
+                .await
+                .is_err()
+        );
+        assert!(
+            proxy
+                .set_proxy("", "direct", HashMap::new(), &[], "")
+                .await
+                .is_err()
+        );
+        let stored = std::fs::read_to_string(dir.path().join("proxy.toml")).unwrap();
+        assert!(stored.contains("proxy.office.example:3128"));
+
+        proxy.clear_proxy("Office").await.unwrap();
+        assert_eq!(proxy.proxy("Office").await.unwrap().0, "none");
+    }
+
+    #[test]
+    fn data_warns_once_and_goes_down_at_the_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = super::NetworkService::new().with_state_dir(dir.path().to_path_buf());
+        service.data.lock().unwrap().plan = super::DataPlan {
+            warning: 800,
+            cap: 1000,
+            cycle_day: 15,
+        };
+        let day = |day| chrono::NaiveDate::from_ymd_opt(2026, 10, day).unwrap();
+        let change = |warning, capped| super::DataChange { warning, capped };
+
+        assert_eq!(service.count_data(500, day(16)), change(None, None));
+        assert_eq!(
+            service.count_data(400, day(17)),
+            change(Some((900, 800)), None)
+        );
+        assert_eq!(service.count_data(50, day(17)), change(None, None));
+        assert_eq!(service.count_data(50, day(18)), change(None, Some(true)));
+        assert_eq!(service.count_data(0, day(18)), change(None, None));
+
+        // Use is kept, and data comes back up in the next cycle
+        let service = super::NetworkService::new().with_state_dir(dir.path().to_path_buf());
+        assert!(service.data.lock().unwrap().capped);
+        let next = chrono::NaiveDate::from_ymd_opt(2026, 11, 15).unwrap();
+        assert_eq!(service.count_data(0, next), change(None, Some(false)));
+    }
+
+    #[tokio::test]
+    async fn data_plan_is_checked_and_kept() {
+        let (_conn, name) = start_test_service().await;
+        let client = Connection::session().await.unwrap();
+        let proxy = NetworkProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(proxy.data_plan().await.unwrap(), (0, 0, 1));
+        assert!(proxy.set_data_plan(0, 0, 30).await.is_err());
+        assert!(proxy.set_data_plan(2000, 1000, 1).await.is_err());
+        proxy.set_data_plan(1000, 2000, 5).await.unwrap();
+        assert_eq!(proxy.data_plan().await.unwrap(), (1000, 2000, 5));
+        assert!(!proxy.data_capped().await.unwrap());
+
+        let cycle = proxy.data_cycle().await.unwrap();
+        assert!((1..=31).contains(&cycle.len()));
+        assert_eq!(cycle.last().unwrap().1, 0);
+    }
 }
@@ -0,0 +1,311 @@
+// ABOUTME: Proxy settings: a manual server per scheme or a PAC URL, with overrides per Wi-Fi network.
+// ABOUTME: Kept on /data and handed to launched apps as the usual http_proxy environment variables.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+/// Where the settings are kept, in the network service's state directory.
+pub const SETTINGS_FILE: &str = "proxy.toml";
+
+/// The environment file for the proxy in use, read by the services that start apps.
+pub const ENV_FILE: &str = "proxy.env";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    /// Connect directly.
+    #[default]
+    None,
+    /// Use the servers given for each scheme.
+    Manual,
+    /// Use whatever the PAC script at `pac_url` picks for each URL.
+    Auto,
+}
+
+impl ProxyMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Manual => "manual",
+            Self::Auto => "auto",
+        }
+    }
+}
+
+impl FromStr for ProxyMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "manual" => Ok(Self::Manual),
+            "auto" => Ok(Self::Auto),
+            _ => bail!("unknown proxy mode {s:?}, expected none, manual or auto"),
+        }
+    }
+}
+
+/// How to reach the internet from one network.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Proxy {
+    pub mode: ProxyMode,
+    /// `host:port` of the server for each scheme in manual mode; empty for none.
+    pub http: String,
+    pub https: String,
+    pub socks: String,
+    /// Hosts reached directly in manual mode, e.g. "localhost" or ".corp.example.com"
+    /// for a whole domain.
+    pub ignore_hosts: Vec<String>,
+    /// Where the PAC script is in auto mode. Apps fetch and run it themselves, through
+    /// libproxy or their own engine, as it is JavaScript.
+    pub pac_url: String,
+}
+
+impl Proxy {
+    /// The servers set, by scheme.
+    pub fn servers(&self) -> HashMap<String, String> {
+        [
+            ("http", &self.http),
+            ("https", &self.https),
+            ("socks", &self.socks),
+        ]
+        .into_iter()
+        .filter(|(_, server)| !server.is_empty())
+        .map(|(scheme, server)| (scheme.to_string(), server.clone()))
+        .collect()
+    }
+
+    /// Use `server` for `scheme`: "http", "https" or "socks".
+    pub fn set_server(&mut self, scheme: &str, server: String) -> Result<()> {
+        match scheme {
+            "http" => self.http = server,
+            "https" => self.https = server,
+            "socks" => self.socks = server,
+            _ => bail!("unknown proxy scheme {scheme:?}, expected http, https or socks"),
+        }
+        Ok(())
+    }
+
+    /// Check the servers and URL the mode needs are there and well formed.
+    pub fn validate(&self) -> Result<()> {
+        match self.mode {
+            ProxyMode::None => {}
+            ProxyMode::Manual => {
+                let servers = self.servers();
+                if servers.is_empty() {
+                    bail!("a manual proxy needs at least one server");
+                }
+                for (scheme, server) in &servers {
+                    validate_server(server).with_context(|| format!("bad {scheme} proxy"))?;
+                }
+            }
+            ProxyMode::Auto => {
+                let Some((scheme, rest)) = self.pac_url.split_once("://") else {
+                    bail!("PAC URL {:?} is not a URL", self.pac_url);
+                };
+                if !matches!(scheme, "http" | "https" | "file") || rest.is_empty() {
+                    bail!("PAC URL {:?} must be http, https or file", self.pac_url);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The contents of an environment file setting this proxy for the programs that
+    /// read it: `http_proxy` and friends in both cases, as tools disagree on which they
+    /// look at. Auto mode sets nothing, as a PAC script can't be put in a variable.
+    pub fn environment(&self) -> String {
+        let mut env = String::from("# Written by the network service; changes are lost\n");
+        if self.mode != ProxyMode::Manual {
+            return env;
+        }
+        let mut vars = Vec::new();
+        if !self.http.is_empty() {
+            vars.push(("http_proxy", format!("http://{}", self.http)));
+        }
+        if !self.https.is_empty() {
+            vars.push(("https_proxy", format!("http://{}", self.https)));
+        }
+        if !self.socks.is_empty() {
+            vars.push(("all_proxy", format!("socks5://{}", self.socks)));
+        }
+        if !self.ignore_hosts.is_empty() {
+            vars.push(("no_proxy", self.ignore_hosts.join(",")));
+        }
+        for (name, value) in vars {
+            env.push_str(&format!("{name}={value}\n"));
+            env.push_str(&format!("{}={value}\n", name.to_uppercase()));
+        }
+        env
+    }
+}
+
+/// A `host:port` server, with the host in brackets if it is an IPv6 address.
+fn validate_server(server: &str) -> Result<()> {
+    let Some((host, port)) = server.rsplit_once(':') else {
+        bail!("{server:?} needs a port, as host:port");
+    };
+    if host.is_empty() || host.contains(|c: char| c.is_whitespace() || c == '/') {
+        bail!("{server:?} has no valid host");
+    }
+    match port.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(()),
+        _ => bail!("{server:?} has no valid port"),
+    }
+}
+
+/// The proxy to use by default, and for particular Wi-Fi networks.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxySettings {
+    pub default: Proxy,
+    /// By SSID, in place of the default on that network.
+    pub networks: BTreeMap<String, Proxy>,
+}
+
+impl ProxySettings {
+    /// Load the settings stored at `path`; the defaults when there are none yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        toml::from_str(&content).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    /// Store the settings, replacing the file atomically so a crash can't leave half of it.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self).context("failed to serialize proxy settings")?;
+        write_atomically(path, &content)
+    }
+
+    /// The proxy set for `ssid`, or the default when it has none or `ssid` is empty.
+    pub fn get(&self, ssid: &str) -> &Proxy {
+        self.networks.get(ssid).unwrap_or(&self.default)
+    }
+
+    /// Use `proxy` on `ssid`, or by default when `ssid` is empty.
+    pub fn set(&mut self, ssid: &str, proxy: Proxy) {
+        if ssid.is_empty() {
+            self.default = proxy;
+        } else {
+            self.networks.insert(ssid.to_string(), proxy);
+        }
+    }
+
+    /// Go back to the default proxy on `ssid`, or to none by default when it is empty.
+    pub fn clear(&mut self, ssid: &str) {
+        if ssid.is_empty() {
+            self.default = Proxy::default();
+        } else {
+            self.networks.remove(ssid);
+        }
+    }
+}
+
+/// Write `content` to `path` through a temporary file, creating its directory.
+pub fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content).with_context(|| format!("failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manual() -> Proxy {
+        Proxy {
+            mode: ProxyMode::Manual,
+            http: "proxy.corp.example.com:3128".to_string(),
+            https: "proxy.corp.example.com:3128".to_string(),
+            ignore_hosts: vec!["localhost".to_string(), ".corp.example.com".to_string()],
+            ..Proxy::default()
+        }
+    }
+
+    #[test]
+    fn proxies_are_checked_for_their_mode() {
+        assert!(Proxy::default().validate().is_ok());
+        assert!(manual().validate().is_ok());
+        for server in ["proxy", "proxy:0", ":8080", "proxy:http", "[::1]:1080"] {
+            let proxy = Proxy {
+                socks: server.to_string(),
+                ..manual()
+            };
+            assert_eq!(proxy.validate().is_ok(), server == "[::1]:1080", "{server}");
+        }
+        let empty = Proxy {
+            mode: ProxyMode::Manual,
+            ..Proxy::default()
+        };
+        assert!(empty.validate().is_err());
+
+        let auto = |url: &str| Proxy {
+            mode: ProxyMode::Auto,
+            pac_url: url.to_string(),
+            ..Proxy::default()
+        };
+        assert!(
+            auto("http://wpad.corp.example.com/wpad.dat")
+                .validate()
+                .is_ok()
+        );
+        assert!(auto("wpad.dat").validate().is_err());
+        assert!(auto("ftp://example.com/proxy.pac").validate().is_err());
+        assert!("direct".parse::<ProxyMode>().is_err());
+        assert!(
+            Proxy::default()
+                .set_server("ftp", "proxy:21".to_string())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn manual_proxy_is_handed_to_apps_in_the_environment() {
+        let env = manual().environment();
+        assert!(env.contains("http_proxy=http://proxy.corp.example.com:3128\n"));
+        assert!(env.contains("HTTPS_PROXY=http://proxy.corp.example.com:3128\n"));
+        assert!(env.contains("no_proxy=localhost,.corp.example.com\n"));
+        assert!(!env.contains("all_proxy"));
+
+        let auto = Proxy {
+            mode: ProxyMode::Auto,
+            ..manual()
+        };
+        assert!(!auto.environment().contains("proxy="));
+    }
+
+    #[test]
+    fn networks_override_the_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("network/proxy.toml");
+        assert_eq!(
+            ProxySettings::load(&path).unwrap(),
+            ProxySettings::default()
+        );
+
+        let mut settings = ProxySettings::default();
+        settings.set("Office", manual());
+        assert_eq!(settings.get("Office"), &manual());
+        assert_eq!(settings.get("HomeWiFi").mode, ProxyMode::None);
+        assert_eq!(settings.get("").mode, ProxyMode::None);
+
+        settings.save(&path).unwrap();
+        let mut loaded = ProxySettings::load(&path).unwrap();
+        assert_eq!(loaded, settings);
+
+        loaded.clear("Office");
+        assert_eq!(loaded.get("Office").mode, ProxyMode::None);
+    }
+}
@@ -1,16 +1,33 @@
 // ABOUTME: Power management D-Bus daemon for MobileOS.
-// ABOUTME: Exposes battery level, charging state, and screen brightness over org.mobileos.Power.
+// ABOUTME: Exposes battery level, charging state, and screen brightness over org.mobileos.Power, and turns the screen off when idle.
 
+mod screen;
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::Arc;
 
-use tracing::info;
+use anyhow::{bail, Context};
+use tracing::{info, warn};
+use zbus::object_server::SignalEmitter;
 use zbus::{connection, interface};
 
+use screen::{DisplayProxy, IdleProxy, Screen};
+
+/// initd's control socket, which carries out suspends and reboots.
+const INIT_CONTROL_SOCKET: &str = "/run/mos/initctl";
+
+/// What `Reboot` can boot into, as initd names them.
+const REBOOT_MODES: &[&str] = &["normal", "bootloader", "recovery"];
+
 struct PowerService {
     battery_level: Arc<AtomicU8>,
     charging: Arc<AtomicBool>,
     brightness: Arc<AtomicU8>,
+    control_socket: PathBuf,
+    screen: Arc<Screen>,
 }
 
 impl PowerService {
@@ -19,6 +36,8 @@ impl PowerService {
             battery_level: Arc::new(AtomicU8::new(85)),
             charging: Arc::new(AtomicBool::new(false)),
             brightness: Arc::new(AtomicU8::new(128)),
+            control_socket: PathBuf::from(INIT_CONTROL_SOCKET),
+            screen: Arc::new(Screen::new(screen::DEFAULT_TIMEOUT)),
         }
     }
 }
@@ -46,12 +65,41 @@ impl PowerService {
         self.brightness.store(value, Ordering::Relaxed);
     }
 
-    async fn suspend(&self) {
+    /// Seconds without use before the screen turns off, or 0 to leave it on. It dims
+    /// a little before, and the phone locks.
+    #[zbus(property)]
+    fn screen_timeout(&self) -> u32 {
+        self.screen.timeout()
+    }
+
+    #[zbus(property)]
+    fn set_screen_timeout(&mut self, seconds: u32) {
+        info!(seconds, "setting screen timeout");
+        self.screen.set_timeout(seconds);
+    }
+
+    /// Whether the screen is meant to be on: it isn't once the phone has been left
+    /// alone for the timeout, or while it is held to an ear on a call.
+    #[zbus(property)]
+    fn screen_on(&self) -> bool {
+        self.screen.on()
+    }
+
+    /// Whether the screen is dimmed, as the timeout nears, until the next input.
+    #[zbus(property)]
+    fn screen_dimmed(&self) -> bool {
+        self.screen.dimmed()
+    }
+
+    /// The phone has been left alone and should lock. The shell shows its lock screen.
+    #[zbus(signal)]
+    async fn lock_requested(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    /// Suspend to RAM. initd announces it with PrepareForSleep on org.mobileos.Init,
+    /// waits for inhibitors, and sends Resumed once the phone is awake again.
+    async fn suspend(&self) -> zbus::fdo::Result<()> {
         info!("suspend requested");
-        #[cfg(feature = "hardware")]
-        {
-            // Write to /sys/power/state
-        }
+        request_init(&self.control_socket, "suspend\n").map_err(failed)
     }
 
     async fn shutdown(&self) {
@@ -61,6 +109,38 @@ impl PowerService {
             // Trigger system shutdown
         }
     }
+
+    /// Reboot into `mode`: "normal", "bootloader" or "recovery". `reason` is kept in
+    /// pstore for the next boot to read.
+    async fn reboot(&self, mode: &str, reason: &str) -> zbus::fdo::Result<()> {
+        if !REBOOT_MODES.contains(&mode) {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "unknown reboot mode '{mode}'"
+            )));
+        }
+        info!(mode, reason, "reboot requested");
+        // The control protocol is one line per request
+        let reason = reason.replace(['\r', '\n'], " ");
+        request_init(&self.control_socket, &format!("reboot {mode} {reason}\n")).map_err(failed)
+    }
+}
+
+fn failed(e: anyhow::Error) -> zbus::fdo::Error {
+    zbus::fdo::Error::Failed(format!("{e:#}"))
+}
+
+/// Send one request over initd's control socket and check its reply. initd answers
+/// straight away, so this doesn't hold up the bus for long.
+fn request_init(socket: &Path, request: &str) -> anyhow::Result<()> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("failed to connect to {}", socket.display()))?;
+    stream.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    if let Some(error) = response.strip_prefix("error:") {
+        bail!("init refused: {}", error.trim());
+    }
+    Ok(())
 }
 
 #[tokio::main]
@@ -75,14 +155,38 @@ async fn main() -> anyhow::Result<()> {
     info!("starting power service");
 
     let service = PowerService::new();
+    let screen = service.screen.clone();
 
-    let _connection = connection::Builder::session()?
+    let connection = connection::Builder::system()?
         .name("org.mobileos.Power")?
         .serve_at("/org/mobileos/Power", service)?
         .build()
         .await?;
 
-    info!("power service running on session bus");
+    // The compositor doesn't emit changes to Powered, and may come up after us
+    let display = DisplayProxy::builder(&connection)
+        .cache_properties(zbus::proxy::CacheProperties::No)
+        .build()
+        .await?;
+    let compositor = IdleProxy::new(&connection).await?;
+    let iface = connection
+        .object_server()
+        .interface::<_, PowerService>("/org/mobileos/Power")
+        .await?;
+    tokio::spawn(screen::drive_display(display, screen.clone()));
+    tokio::spawn(async move {
+        if let Err(e) = screen::follow_idle(compositor, iface).await {
+            warn!(error = %e, "lost the compositor, the screen won't time out");
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = screen::watch_calls(connection, screen).await {
+            warn!(error = %e, "lost the sensors or modem service, screen stays on during calls");
+        }
+    });
+
+    info!("power service running on system bus");
+    mos_notify::ready();
 
     std::future::pending::<()>().await;
     Ok(())
@@ -90,6 +194,14 @@ async fn main() -> anyhow::Result<()> {
 
 #[cfg(test)]
 mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+    use zbus::object_server::SignalEmitter;
     use zbus::{connection, proxy, Connection};
 
     #[proxy(
@@ -109,12 +221,27 @@ mod tests {
         #[zbus(property)]
         fn set_screen_brightness(&self, value: u8) -> zbus::Result<()>;
 
+        #[zbus(property)]
+        fn set_screen_timeout(&self, seconds: u32) -> zbus::Result<()>;
+
+        #[zbus(property)]
+        fn screen_dimmed(&self) -> zbus::Result<bool>;
+
+        #[zbus(signal)]
+        fn lock_requested(&self) -> zbus::Result<()>;
+
         fn suspend(&self) -> zbus::Result<()>;
         fn shutdown(&self) -> zbus::Result<()>;
+        fn reboot(&self, mode: &str, reason: &str) -> zbus::Result<()>;
     }
 
     async fn start_test_service() -> (Connection, zbus::names::OwnedUniqueName) {
-        let service = super::PowerService::new();
+        start_service(super::PowerService::new()).await
+    }
+
+    async fn start_service(
+        service: super::PowerService,
+    ) -> (Connection, zbus::names::OwnedUniqueName) {
         let conn = connection::Builder::session()
             .unwrap()
             .serve_at("/org/mobileos/Power", service)
@@ -171,9 +298,31 @@ mod tests {
         assert_eq!(proxy.screen_brightness().await.unwrap(), 200);
     }
 
+    /// A stand-in for initd's control socket at `socket` that answers one request with
+    /// `reply`, and hands back the request it got.
+    fn fake_init(
+        socket: &std::path::Path,
+        reply: &'static [u8],
+    ) -> std::thread::JoinHandle<String> {
+        let listener = UnixListener::bind(socket).unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            stream.write_all(reply).unwrap();
+            line
+        })
+    }
+
     #[tokio::test]
-    async fn suspend_does_not_error() {
-        let (_conn, name) = start_test_service().await;
+    async fn suspend_asks_init() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("initctl");
+        let init = fake_init(&socket, b"suspending\n");
+
+        let mut service = super::PowerService::new();
+        service.control_socket = socket;
+        let (_conn, name) = start_service(service).await;
         let client = Connection::session().await.unwrap();
         let proxy = PowerProxy::builder(&client)
             .destination(name)
@@ -183,6 +332,7 @@ mod tests {
             .unwrap();
 
         proxy.suspend().await.unwrap();
+        assert_eq!(init.join().unwrap(), "suspend\n");
     }
 
     #[tokio::test]
@@ -198,4 +348,157 @@ mod tests {
 
         proxy.shutdown().await.unwrap();
     }
+
+    #[tokio::test]
+    async fn reboot_asks_init() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("initctl");
+        let init = fake_init(&socket, b"rebooting into recovery\n");
+
+        let mut service = super::PowerService::new();
+        service.control_socket = socket;
+        let (_conn, name) = start_service(service).await;
+        let client = Connection::session().await.unwrap();
+        let proxy = PowerProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        assert!(proxy.reboot("fastboot", "").await.is_err());
+        proxy.reboot("recovery", "from\nsettings").await.unwrap();
+        assert_eq!(init.join().unwrap(), "reboot recovery from settings\n");
+    }
+
+    /// A stand-in for the compositor's org.mobileos.Display.
+    struct FakeDisplay {
+        powered: Arc<AtomicBool>,
+    }
+
+    #[zbus::interface(name = "org.mobileos.Display")]
+    impl FakeDisplay {
+        #[zbus(property)]
+        fn powered(&self) -> bool {
+            self.powered.load(Ordering::Relaxed)
+        }
+
+        #[zbus(property)]
+        fn set_powered(&mut self, on: bool) {
+            self.powered.store(on, Ordering::Relaxed);
+        }
+    }
+
+    /// A stand-in for the compositor's idle thresholds on org.mobileos.Compositor.
+    struct FakeCompositor {
+        thresholds: Arc<Mutex<Vec<u32>>>,
+    }
+
+    #[zbus::interface(name = "org.mobileos.Compositor")]
+    impl FakeCompositor {
+        fn set_idle_thresholds(&self, thresholds: Vec<u32>) {
+            *self.thresholds.lock().unwrap() = thresholds;
+        }
+
+        #[zbus(signal)]
+        async fn idle_passed(emitter: &SignalEmitter<'_>, threshold: u32) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        async fn active(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+    }
+
+    /// Wait up to a few seconds for `done` to hold, saying what never happened if not.
+    async fn wait_for(what: &str, mut done: impl AsyncFnMut() -> bool) {
+        let waited = tokio::time::timeout(Duration::from_secs(5), async {
+            while !done().await {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await;
+        assert!(waited.is_ok(), "{what} never happened");
+    }
+
+    #[tokio::test]
+    async fn the_screen_dims_locks_and_turns_off_when_left_alone() {
+        let powered = Arc::new(AtomicBool::new(true));
+        let thresholds = Arc::new(Mutex::new(Vec::new()));
+        let compositor = connection::Builder::session()
+            .unwrap()
+            .serve_at(
+                "/org/mobileos/Display",
+                FakeDisplay {
+                    powered: powered.clone(),
+                },
+            )
+            .unwrap()
+            .serve_at(
+                "/org/mobileos/Compositor",
+                FakeCompositor {
+                    thresholds: thresholds.clone(),
+                },
+            )
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let compositor_name = compositor.unique_name().unwrap().to_owned();
+        let idle = compositor
+            .object_server()
+            .interface::<_, FakeCompositor>("/org/mobileos/Compositor")
+            .await
+            .unwrap();
+
+        let service = super::PowerService::new();
+        let screen = service.screen.clone();
+        let (conn, name) = start_service(service).await;
+        let iface = conn
+            .object_server()
+            .interface::<_, super::PowerService>("/org/mobileos/Power")
+            .await
+            .unwrap();
+        let client = Connection::session().await.unwrap();
+        let display_proxy = super::DisplayProxy::builder(&client)
+            .destination(compositor_name.clone())
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+        let idle_proxy = super::IdleProxy::builder(&client)
+            .destination(compositor_name)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        tokio::spawn(super::screen::drive_display(display_proxy, screen));
+        tokio::spawn(super::screen::follow_idle(idle_proxy, iface));
+
+        let proxy = PowerProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+        let mut locks = proxy.receive_lock_requested().await.unwrap();
+        proxy.set_screen_timeout(60).await.unwrap();
+        wait_for("setting idle thresholds", async || {
+            *thresholds.lock().unwrap() == [50, 58, 60]
+        })
+        .await;
+
+        let emitter = idle.signal_emitter();
+        FakeCompositor::idle_passed(emitter, 0).await.unwrap();
+        wait_for("dimming", async || proxy.screen_dimmed().await.unwrap()).await;
+        FakeCompositor::idle_passed(emitter, 1).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), locks.next())
+            .await
+            .expect("locking never happened");
+        FakeCompositor::idle_passed(emitter, 2).await.unwrap();
+        wait_for("turning off", async || !powered.load(Ordering::Relaxed)).await;
+
+        FakeCompositor::active(emitter).await.unwrap();
+        wait_for("turning on", async || powered.load(Ordering::Relaxed)).await;
+        assert!(!proxy.screen_dimmed().await.unwrap());
+    }
 }
@@ -0,0 +1,290 @@
+// ABOUTME: Decides when the screen dims, locks and goes off as the phone is left alone, and while it is held to an ear on a call.
+// ABOUTME: Follows the compositor's idle thresholds, and turns the panel off and on through its org.mobileos.Display.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures_util::StreamExt;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use zbus::object_server::InterfaceRef;
+use zbus::proxy;
+
+use crate::PowerService;
+
+/// Seconds without use before the screen goes off, until settings choose otherwise.
+pub const DEFAULT_TIMEOUT: u32 = 30;
+
+/// Seconds before the screen goes off that it dims, so the user can keep it on.
+const DIM_AHEAD: u32 = 10;
+
+/// Seconds before the screen goes off that the phone locks.
+const LOCK_AHEAD: u32 = 2;
+
+/// What happens as the phone is left alone, in order, one per idle threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Dim,
+    Lock,
+    Off,
+}
+
+const STAGES: [Stage; 3] = [Stage::Dim, Stage::Lock, Stage::Off];
+
+/// The compositor's idle thresholds for each of the stages, given the timeout: the
+/// screen dims, then locks, then goes off at the timeout. None for a timeout of 0.
+fn thresholds(timeout: u32) -> Vec<u32> {
+    if timeout == 0 {
+        return Vec::new();
+    }
+    let dim = timeout.saturating_sub(DIM_AHEAD).max(timeout / 2).max(1);
+    let lock = timeout.saturating_sub(LOCK_AHEAD).max(dim);
+    vec![dim, lock, timeout]
+}
+
+#[proxy(
+    interface = "org.mobileos.Display",
+    default_service = "org.mobileos.Display",
+    default_path = "/org/mobileos/Display"
+)]
+pub trait Display {
+    #[zbus(property)]
+    fn powered(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn set_powered(&self, on: bool) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.mobileos.Compositor",
+    default_service = "org.mobileos.Compositor",
+    default_path = "/org/mobileos/Compositor"
+)]
+pub trait Idle {
+    fn set_idle_thresholds(&self, thresholds: Vec<u32>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn idle_passed(&self, threshold: u32) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn active(&self) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.mobileos.Sensors",
+    default_service = "org.mobileos.Sensors",
+    default_path = "/org/mobileos/Sensors"
+)]
+trait Sensors {
+    #[zbus(property)]
+    fn proximity(&self) -> zbus::Result<bool>;
+}
+
+#[proxy(
+    interface = "org.mobileos.Modem",
+    default_service = "org.mobileos.Modem",
+    default_path = "/org/mobileos/Modem"
+)]
+trait Modem {
+    #[zbus(property)]
+    fn modem_state(&self) -> zbus::Result<String>;
+}
+
+/// What keeps the screen dark.
+#[derive(Debug, Default)]
+struct Reasons {
+    /// Nobody has used the phone for the timeout.
+    idle: bool,
+    /// Something is up against the proximity sensor on a call, most likely an ear.
+    covered: bool,
+}
+
+/// Whether the screen should be on, shared between the service and the tasks that
+/// watch for reasons to turn it off.
+#[derive(Default)]
+pub struct Screen {
+    reasons: Mutex<Reasons>,
+    /// Woken whenever the reasons change.
+    changed: Notify,
+    /// Woken when the timeout changes, to hand the compositor new thresholds.
+    timeout_changed: Notify,
+    /// The idle timeout in seconds, 0 for never.
+    timeout: AtomicU32,
+    /// The phone has been left alone long enough that the screen is dimmed.
+    dimmed: AtomicBool,
+}
+
+impl Screen {
+    pub fn new(timeout: u32) -> Self {
+        Self {
+            timeout: AtomicU32::new(timeout),
+            ..Default::default()
+        }
+    }
+
+    pub fn on(&self) -> bool {
+        let reasons = self.reasons.lock().unwrap();
+        !reasons.idle && !reasons.covered
+    }
+
+    pub fn timeout(&self) -> u32 {
+        self.timeout.load(Ordering::Relaxed)
+    }
+
+    /// Change the idle timeout; it counts from the last input.
+    pub fn set_timeout(&self, seconds: u32) {
+        self.timeout.store(seconds, Ordering::Relaxed);
+        self.timeout_changed.notify_one();
+    }
+
+    pub fn dimmed(&self) -> bool {
+        self.dimmed.load(Ordering::Relaxed)
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Reasons)) {
+        let mut reasons = self.reasons.lock().unwrap();
+        let was_on = !reasons.idle && !reasons.covered;
+        change(&mut reasons);
+        if was_on != (!reasons.idle && !reasons.covered) {
+            self.changed.notify_one();
+        }
+    }
+}
+
+/// Whether the proximity sensor says the phone is at an ear: only on a call, so a hand
+/// passing over the sensor otherwise doesn't blank the screen.
+fn at_ear(near: bool, modem_state: &str) -> bool {
+    near && modem_state == "in-call"
+}
+
+async fn set_thresholds(compositor: &IdleProxy<'_>, timeout: u32) {
+    let thresholds = thresholds(timeout);
+    // The compositor may not be up yet; it gets them once it is
+    if let Err(e) = compositor.set_idle_thresholds(thresholds.clone()).await {
+        warn!(?thresholds, error = %e, "failed to set idle thresholds");
+    }
+}
+
+/// Hand the compositor idle thresholds for the timeout, now and whenever it changes
+/// or the compositor starts over. Then dim the screen, lock the phone and turn the
+/// screen off as each passes, and bring the screen back at the next input.
+pub async fn follow_idle(
+    compositor: IdleProxy<'static>,
+    iface: InterfaceRef<PowerService>,
+) -> zbus::Result<()> {
+    let screen = iface.get().await.screen.clone();
+    let emitter = iface.signal_emitter();
+    let mut passed = compositor.receive_idle_passed().await?;
+    let mut active = compositor.receive_active().await?;
+    let mut restarts = compositor.inner().receive_owner_changed().await?;
+    set_thresholds(&compositor, screen.timeout()).await;
+    loop {
+        tokio::select! {
+            _ = screen.timeout_changed.notified() => {
+                set_thresholds(&compositor, screen.timeout()).await;
+            }
+            Some(owner) = restarts.next() => {
+                if owner.is_some() {
+                    set_thresholds(&compositor, screen.timeout()).await;
+                }
+            }
+            Some(signal) = passed.next() => {
+                let threshold = signal.args()?.threshold;
+                match STAGES.get(threshold as usize) {
+                    Some(Stage::Dim) => {
+                        info!("idle, dimming the screen");
+                        screen.dimmed.store(true, Ordering::Relaxed);
+                        iface.get().await.screen_dimmed_changed(emitter).await?;
+                    }
+                    Some(Stage::Lock) => {
+                        info!("idle, locking");
+                        PowerService::lock_requested(emitter).await?;
+                    }
+                    Some(Stage::Off) => {
+                        info!("idle, turning the screen off");
+                        screen.update(|reasons| reasons.idle = true);
+                    }
+                    None => warn!(threshold, "idle threshold that isn't ours"),
+                }
+            }
+            Some(_) = active.next() => {
+                screen.update(|reasons| reasons.idle = false);
+                if screen.dimmed.swap(false, Ordering::Relaxed) {
+                    iface.get().await.screen_dimmed_changed(emitter).await?;
+                }
+            }
+            else => return Ok(()),
+        }
+    }
+}
+
+/// Follow the proximity sensor and the modem's call state, darkening the screen while
+/// the phone is held to an ear.
+pub async fn watch_calls(connection: zbus::Connection, screen: Arc<Screen>) -> zbus::Result<()> {
+    let sensors = SensorsProxy::new(&connection).await?;
+    let modem = ModemProxy::new(&connection).await?;
+    let mut proximity_changes = sensors.receive_proximity_changed().await;
+    let mut call_changes = modem.receive_modem_state_changed().await;
+    loop {
+        // Either service may not be up yet; its changes arrive once it is
+        let near = sensors.proximity().await.unwrap_or(false);
+        let modem_state = modem.modem_state().await.unwrap_or_default();
+        screen.update(|reasons| reasons.covered = at_ear(near, &modem_state));
+        tokio::select! {
+            Some(_) = proximity_changes.next() => {}
+            Some(_) = call_changes.next() => {}
+            else => return Ok(()),
+        }
+    }
+}
+
+/// Carry the screen's state over to the compositor whenever it changes.
+pub async fn drive_display(display: DisplayProxy<'static>, screen: Arc<Screen>) {
+    let mut powered = true;
+    loop {
+        screen.changed.notified().await;
+        let on = screen.on();
+        if on == powered {
+            continue;
+        }
+        match display.set_powered(on).await {
+            Ok(()) => powered = on,
+            Err(e) => warn!(error = %e, on, "failed to turn the display"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_call_puts_the_phone_at_an_ear() {
+        assert!(at_ear(true, "in-call"));
+        assert!(!at_ear(false, "in-call"));
+        assert!(!at_ear(true, "idle"));
+        assert!(!at_ear(true, "ringing"));
+    }
+
+    #[test]
+    fn the_screen_dims_then_locks_then_goes_off() {
+        assert_eq!(thresholds(30), [20, 28, 30]);
+        assert_eq!(thresholds(60), [50, 58, 60]);
+        assert_eq!(thresholds(8), [4, 6, 8]);
+        assert_eq!(thresholds(1), [1, 1, 1]);
+        assert!(thresholds(0).is_empty());
+    }
+
+    #[test]
+    fn either_reason_keeps_the_screen_dark() {
+        let screen = Screen::new(DEFAULT_TIMEOUT);
+        assert!(screen.on());
+        screen.update(|reasons| reasons.covered = true);
+        screen.update(|reasons| reasons.idle = true);
+        assert!(!screen.on());
+        screen.update(|reasons| reasons.covered = false);
+        assert!(!screen.on());
+        screen.update(|reasons| reasons.idle = false);
+        assert!(screen.on());
+    }
+}
@@ -0,0 +1,222 @@
+// ABOUTME: Copies the screen from the compositor over wlr-screencopy-unstable-v1, into an shm buffer.
+// ABOUTME: Connects for each screenshot, so a restarted compositor is picked up without restarting the service.
+
+use std::os::fd::AsFd;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail, ensure};
+use rustix::event::{PollFd, PollFlags, Timespec, poll};
+use rustix::fs::{MemfdFlags, ftruncate, memfd_create};
+use rustix::io::Errno;
+use wayland_client::globals::{GlobalListContents, registry_queue_init};
+use wayland_client::protocol::{wl_buffer, wl_output, wl_registry, wl_shm, wl_shm_pool};
+use wayland_client::{Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum, delegate_noop};
+use wayland_protocols_wlr::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1, zwlr_screencopy_manager_v1,
+};
+
+/// How long the compositor has to copy the screen. It copies after drawing its next
+/// frame, which it may not do while nothing changes.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The screen, as rows of red, green and blue bytes from the top.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+/// What the compositor has said about the frame so far.
+#[derive(Default)]
+struct Frame {
+    /// The shm buffer to copy into: format, width, height and stride.
+    buffer: Option<(WEnum<wl_shm::Format>, u32, u32, u32)>,
+    buffer_done: bool,
+    y_invert: bool,
+    ready: bool,
+    failed: bool,
+}
+
+/// Copy the first output.
+pub fn capture() -> Result<Image> {
+    let conn = Connection::connect_to_env().context("failed to connect to the compositor")?;
+    let (globals, mut queue) =
+        registry_queue_init::<Frame>(&conn).context("failed to read the compositor's globals")?;
+    let qh = queue.handle();
+
+    let shm: wl_shm::WlShm = globals.bind(&qh, 1..=1, ()).context("no wl_shm")?;
+    let output: wl_output::WlOutput = globals.bind(&qh, 1..=4, ()).context("no output")?;
+    let manager: zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1 = globals
+        .bind(&qh, 1..=3, ())
+        .context("no zwlr_screencopy_manager_v1")?;
+
+    let mut state = Frame::default();
+    let frame = manager.capture_output(0, &output, &qh, ());
+    // From version 3 every buffer type on offer is listed before buffer_done
+    let listed_all = |f: &Frame| f.buffer.is_some() && (frame.version() < 3 || f.buffer_done);
+    wait(&conn, &mut queue, &mut state, |f| f.failed || listed_all(f))?;
+    ensure!(!state.failed, "the compositor can't copy the screen");
+
+    let (format, width, height, stride) = state.buffer.context("no shm buffer offered")?;
+    let format = match format {
+        WEnum::Value(format @ (wl_shm::Format::Xrgb8888 | wl_shm::Format::Argb8888)) => format,
+        other => bail!("screen copied as {other:?}, which is not supported"),
+    };
+    let len = stride as usize * height as usize;
+    let fd = memfd_create("screenshot", MemfdFlags::CLOEXEC).context("memfd_create failed")?;
+    ftruncate(&fd, len as u64).context("ftruncate failed")?;
+    let pool = shm.create_pool(fd.as_fd(), len as i32, &qh, ());
+    let buffer = pool.create_buffer(
+        0,
+        width as i32,
+        height as i32,
+        stride as i32,
+        format,
+        &qh,
+        (),
+    );
+
+    frame.copy(&buffer);
+    let copied = wait(&conn, &mut queue, &mut state, |f| f.failed || f.ready);
+    frame.destroy();
+    buffer.destroy();
+    pool.destroy();
+    manager.destroy();
+    let _ = conn.flush();
+    copied?;
+    ensure!(!state.failed, "the compositor failed to copy the screen");
+
+    let mut pixels = vec![0; len];
+    rustix::io::pread(&fd, &mut pixels, 0).context("failed to read the copy")?;
+    Ok(Image {
+        width,
+        height,
+        rgb: to_rgb(&pixels, width, height, stride, state.y_invert),
+    })
+}
+
+/// Dispatch events until `done`, or fail once the compositor has taken too long.
+fn wait(
+    conn: &Connection,
+    queue: &mut EventQueue<Frame>,
+    state: &mut Frame,
+    done: impl Fn(&Frame) -> bool,
+) -> Result<()> {
+    let deadline = Instant::now() + TIMEOUT;
+    while !done(state) {
+        conn.flush().context("lost the compositor")?;
+        let Some(guard) = queue.prepare_read() else {
+            queue
+                .dispatch_pending(state)
+                .context("lost the compositor")?;
+            continue;
+        };
+        let left = deadline.saturating_duration_since(Instant::now());
+        ensure!(
+            !left.is_zero(),
+            "the compositor took too long to copy the screen"
+        );
+        let timeout = Timespec::try_from(left).ok();
+        let readable = {
+            let fd = guard.connection_fd();
+            let mut fds = [PollFd::new(&fd, PollFlags::IN)];
+            match poll(&mut fds, timeout.as_ref()) {
+                Ok(ready) => ready > 0,
+                Err(Errno::INTR) => false,
+                Err(e) => return Err(e).context("poll failed"),
+            }
+        };
+        if readable {
+            guard.read().context("lost the compositor")?;
+        }
+        queue
+            .dispatch_pending(state)
+            .context("lost the compositor")?;
+    }
+    Ok(())
+}
+
+/// The red, green and blue of `pixels`, XRGB8888 or ARGB8888 rows `stride` bytes
+/// apart, upside down if `y_invert`.
+fn to_rgb(pixels: &[u8], width: u32, height: u32, stride: u32, y_invert: bool) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(width as usize * height as usize * 3);
+    for y in 0..height {
+        let row = if y_invert { height - 1 - y } else { y };
+        let start = (row * stride) as usize;
+        // wl_shm formats are little-endian, so blue comes first
+        for pixel in pixels[start..start + width as usize * 4].chunks_exact(4) {
+            rgb.extend([pixel[2], pixel[1], pixel[0]]);
+        }
+    }
+    rgb
+}
+
+impl Dispatch<zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1, ()> for Frame {
+    fn event(
+        state: &mut Self,
+        _: &zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_screencopy_frame_v1::Event::Buffer {
+                format,
+                width,
+                height,
+                stride,
+            } => state.buffer = Some((format, width, height, stride)),
+            zwlr_screencopy_frame_v1::Event::BufferDone => state.buffer_done = true,
+            zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+                state.y_invert = matches!(
+                    flags,
+                    WEnum::Value(flags) if flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert)
+                );
+            }
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => state.ready = true,
+            zwlr_screencopy_frame_v1::Event::Failed => state.failed = true,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for Frame {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(Frame: wl_shm_pool::WlShmPool);
+delegate_noop!(Frame: zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1);
+delegate_noop!(Frame: ignore wl_buffer::WlBuffer);
+delegate_noop!(Frame: ignore wl_output::WlOutput);
+delegate_noop!(Frame: ignore wl_shm::WlShm);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_are_read_upright() {
+        // Two rows of two pixels, with two bytes of padding on each row
+        let pixels = [
+            1, 2, 3, 0xff, 4, 5, 6, 0xff, 0, 0, //
+            7, 8, 9, 0xff, 10, 11, 12, 0xff, 0, 0,
+        ];
+        assert_eq!(
+            to_rgb(&pixels, 2, 2, 10, false),
+            [3, 2, 1, 6, 5, 4, 9, 8, 7, 12, 11, 10]
+        );
+        assert_eq!(
+            to_rgb(&pixels, 2, 2, 10, true),
+            [9, 8, 7, 12, 11, 10, 3, 2, 1, 6, 5, 4]
+        );
+    }
+}
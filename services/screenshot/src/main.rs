@@ -0,0 +1,177 @@
+// ABOUTME: Screenshot D-Bus daemon for MobileOS: copies the screen from the compositor and saves it as a PNG.
+// ABOUTME: Screenshots go to /data/Pictures, named for the time they were taken.
+
+mod capture;
+mod png;
+
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use tokio::runtime::Handle;
+use tracing::{info, warn};
+use zbus::object_server::SignalEmitter;
+use zbus::{connection, interface};
+
+const PICTURES_DIR: &str = "/data/Pictures";
+
+struct ScreenshotService {
+    dir: PathBuf,
+    /// Runs captures, which block on the compositor.
+    runtime: Handle,
+}
+
+#[interface(name = "org.mobileos.Screenshot")]
+impl ScreenshotService {
+    /// Take a screenshot, returning the path it was saved to.
+    async fn take(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<String> {
+        let dir = self.dir.clone();
+        let saved = self
+            .runtime
+            .spawn_blocking(move || {
+                let image = capture::capture()?;
+                let png = png::encode(image.width, image.height, &image.rgb)?;
+                save(&dir, SystemTime::now(), &png)
+            })
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(format!("screenshot failed: {e}")))?;
+        let path = match saved {
+            Ok(path) => path.display().to_string(),
+            Err(e) => {
+                warn!(error = %format!("{e:#}"), "screenshot failed");
+                return Err(zbus::fdo::Error::Failed(format!("{e:#}")));
+            }
+        };
+        info!(path = %path, "screenshot saved");
+        let _ = Self::taken(&emitter, &path).await;
+        Ok(path)
+    }
+
+    /// A screenshot was saved at `path`.
+    #[zbus(signal)]
+    async fn taken(emitter: &SignalEmitter<'_>, path: &str) -> zbus::Result<()>;
+}
+
+/// The file name for a screenshot taken at `time`, in UTC as there is no time zone
+/// database on the device: Screenshot_20240131-235959.png.
+fn file_name(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_date(days as i64);
+    format!(
+        "Screenshot_{year:04}{month:02}{day:02}-{:02}{:02}{:02}.png",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// The year, month and day `days` after 1970-01-01, in the proleptic Gregorian calendar.
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    // Counted in 400-year eras from 0000-03-01, so leap days fall at the end of a year
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Write `png` to `dir`, named for `time`. Screenshots taken within the same second
+/// get a number after the time rather than replacing each other.
+fn save(dir: &Path, time: SystemTime, png: &[u8]) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let name = file_name(time);
+    let stem = name.trim_end_matches(".png");
+    for n in 1.. {
+        let path = match n {
+            1 => dir.join(&name),
+            n => dir.join(format!("{stem}-{n}.png")),
+        };
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to create {}", path.display()));
+            }
+        };
+        file.write_all(png)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        return Ok(path);
+    }
+    unreachable!("ran out of screenshot names")
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    info!("starting screenshot service");
+
+    let service = ScreenshotService {
+        dir: PathBuf::from(PICTURES_DIR),
+        runtime: Handle::current(),
+    };
+    let _connection = connection::Builder::system()?
+        .name("org.mobileos.Screenshot")?
+        .serve_at("/org/mobileos/Screenshot", service)?
+        .build()
+        .await?;
+
+    info!("screenshot service running on system bus");
+    mos_notify::ready();
+
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn screenshots_are_named_for_when_they_were_taken() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(file_name(at(0)), "Screenshot_19700101-000000.png");
+        assert_eq!(file_name(at(951_825_599)), "Screenshot_20000229-115959.png");
+        assert_eq!(
+            file_name(at(1_735_689_599)),
+            "Screenshot_20241231-235959.png"
+        );
+    }
+
+    #[test]
+    fn screenshots_in_the_same_second_are_all_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let pictures = dir.path().join("Pictures");
+        let at = UNIX_EPOCH + Duration::from_secs(1_735_689_599);
+
+        let first = save(&pictures, at, b"one").unwrap();
+        let second = save(&pictures, at, b"two").unwrap();
+        assert_eq!(first, pictures.join("Screenshot_20241231-235959.png"));
+        assert_eq!(second, pictures.join("Screenshot_20241231-235959-2.png"));
+        assert_eq!(std::fs::read(first).unwrap(), b"one");
+        assert_eq!(std::fs::read(second).unwrap(), b"two");
+    }
+}
@@ -0,0 +1,123 @@
+// ABOUTME: PNG encoding for screenshots: 8-bit RGB in one zlib-compressed IDAT chunk.
+// ABOUTME: The screen is opaque, so there is no alpha; each row is Sub-filtered, which suits flat UI colors.
+
+use std::io::Write;
+
+use anyhow::{Context, Result, ensure};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Bytes per pixel, which the Sub filter subtracts the byte that far to the left.
+const PIXEL: usize = 3;
+
+const FILTER_SUB: u8 = 1;
+
+/// Encode `rgb`, `height` rows of `width` pixels of red, green and blue bytes.
+pub fn encode(width: u32, height: u32, rgb: &[u8]) -> Result<Vec<u8>> {
+    let row = width as usize * PIXEL;
+    ensure!(width > 0 && height > 0, "image is empty");
+    ensure!(
+        rgb.len() == row * height as usize,
+        "image is not {width}x{height}"
+    );
+
+    let mut header = Vec::with_capacity(13);
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // 8 bits per channel, RGB, deflate, per-row filters, no interlacing
+    header.extend([8, 2, 0, 0, 0]);
+
+    let mut data = ZlibEncoder::new(Vec::new(), Compression::fast());
+    let mut filtered = Vec::with_capacity(row + 1);
+    for pixels in rgb.chunks_exact(row) {
+        filtered.clear();
+        filtered.push(FILTER_SUB);
+        filtered.extend_from_slice(&pixels[..PIXEL]);
+        filtered.extend(
+            pixels[PIXEL..]
+                .iter()
+                .zip(pixels)
+                .map(|(byte, left)| byte.wrapping_sub(*left)),
+        );
+        data.write_all(&filtered)
+            .context("failed to compress image")?;
+    }
+    let data = data.finish().context("failed to compress image")?;
+
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &data);
+    chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    png.extend(crc.finalize().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    /// The chunks of `png`, checking each one's CRC.
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut rest = &png[SIGNATURE.len()..];
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32fast::hash(&[kind, data].concat()));
+            chunks.push((String::from_utf8(kind.to_vec()).unwrap(), data.to_vec()));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn pixels_come_back_out() {
+        let rgb: Vec<u8> = (0..3 * 2 * 3).map(|i| (i * 40) as u8).collect();
+        let png = encode(3, 2, &rgb).unwrap();
+        assert_eq!(png[..8], SIGNATURE);
+
+        let chunks = chunks(&png);
+        let kinds: Vec<_> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+
+        let mut data = Vec::new();
+        flate2::read::ZlibDecoder::new(chunks[1].1.as_slice())
+            .read_to_end(&mut data)
+            .unwrap();
+        let mut decoded = Vec::new();
+        for row in data.chunks_exact(1 + 3 * PIXEL) {
+            assert_eq!(row[0], FILTER_SUB);
+            let start = decoded.len();
+            for (i, byte) in row[1..].iter().enumerate() {
+                let left = if i < PIXEL {
+                    0
+                } else {
+                    decoded[start + i - PIXEL]
+                };
+                decoded.push(byte.wrapping_add(left));
+            }
+        }
+        assert_eq!(decoded, rgb);
+    }
+
+    #[test]
+    fn images_must_match_their_size() {
+        assert!(encode(0, 0, &[]).is_err());
+        assert!(encode(2, 2, &[0; 3]).is_err());
+    }
+}
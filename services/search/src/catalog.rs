@@ -0,0 +1,155 @@
+// ABOUTME: Built-in search providers: fixed lists of entries, such as the installed apps and settings pages,
+// ABOUTME: read from one TOML file per provider in /etc/mos/search.d.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::rank::{self, Hit};
+
+pub const CATALOG_DIR: &str = "/etc/mos/search.d";
+
+/// Keywords match a little below the title, so a page named for the query wins.
+const KEYWORD_WEIGHT: f64 = 0.8;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Entry {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub subtitle: String,
+    /// Other words the entry is found by, e.g. "wireless" for Wi-Fi.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+/// One provider's entries, as in its file:
+///
+/// ```toml
+/// provider = "settings"
+///
+/// [[entry]]
+/// id = "settings:wifi"
+/// title = "Wi-Fi"
+/// keywords = ["wireless", "network", "internet"]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Catalog {
+    pub provider: String,
+    #[serde(default, rename = "entry")]
+    pub entries: Vec<Entry>,
+}
+
+impl Catalog {
+    pub fn parse(content: &str) -> Result<Self> {
+        toml::from_str(content).context("invalid search catalog")
+    }
+
+    /// The entries matching `query`, each scored by its title or best keyword.
+    pub fn search(&self, query: &str) -> Vec<Hit> {
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                let keyword = entry
+                    .keywords
+                    .iter()
+                    .filter_map(|keyword| rank::score(query, keyword))
+                    .map(|score| score * KEYWORD_WEIGHT)
+                    .reduce(f64::max);
+                let title = rank::score(query, &entry.title);
+                let score = [title, keyword].into_iter().flatten().reduce(f64::max)?;
+                Some(Hit {
+                    provider: self.provider.clone(),
+                    id: entry.id.clone(),
+                    title: entry.title.clone(),
+                    subtitle: entry.subtitle.clone(),
+                    score,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The catalogs in every `*.toml` file in `dir`, in file name order. A file that
+/// can't be read is skipped, so one bad catalog leaves search working.
+pub fn load_dir(dir: &Path) -> Vec<Catalog> {
+    let mut paths: Vec<_> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+            .collect(),
+        Err(e) => {
+            warn!(dir = %dir.display(), error = %e, "no search catalogs");
+            return Vec::new();
+        }
+    };
+    paths.sort();
+
+    paths
+        .iter()
+        .filter_map(|path| {
+            let catalog = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))
+                .and_then(|content| Catalog::parse(&content));
+            catalog
+                .inspect_err(
+                    |e| warn!(path = %path.display(), error = %e, "skipping search catalog"),
+                )
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: &str = r#"
+provider = "settings"
+
+[[entry]]
+id = "settings:wifi"
+title = "Wi-Fi"
+subtitle = "Settings"
+keywords = ["wireless", "network", "internet"]
+
+[[entry]]
+id = "settings:display"
+title = "Display"
+keywords = ["brightness", "screen"]
+"#;
+
+    #[test]
+    fn entries_are_found_by_title_or_keyword() {
+        let catalog = Catalog::parse(SETTINGS).unwrap();
+        let hits = catalog.search("wi");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, "settings:wifi");
+        assert_eq!(hits[0].provider, "settings");
+        assert_eq!(hits[0].score, 0.9);
+
+        let hits = catalog.search("bright");
+        assert_eq!(hits[0].id, "settings:display");
+        assert!(hits[0].score < 0.9);
+        assert!(catalog.search("camera").is_empty());
+    }
+
+    #[test]
+    fn bad_catalogs_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("20-settings.toml"), SETTINGS).unwrap();
+        std::fs::write(dir.path().join("10-apps.toml"), "provider = \"apps\"\n").unwrap();
+        std::fs::write(dir.path().join("30-broken.toml"), "[[entry]]\n").unwrap();
+        std::fs::write(dir.path().join("README"), "not a catalog").unwrap();
+
+        let providers: Vec<_> = load_dir(dir.path())
+            .into_iter()
+            .map(|catalog| catalog.provider)
+            .collect();
+        assert_eq!(providers, ["apps", "settings"]);
+        assert!(load_dir(&dir.path().join("missing")).is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+// ABOUTME: Search D-Bus daemon for MobileOS: one query over every provider, results ranked together.
+// ABOUTME: Built-in catalogs cover apps and settings; apps register providers for contacts, messages and files.
+
+mod catalog;
+mod rank;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use futures_util::future::join_all;
+use tracing::{info, warn};
+use zbus::message::Header;
+use zbus::names::{BusName, UniqueName};
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{Connection, connection, interface};
+
+use catalog::Catalog;
+use rank::Hit;
+
+/// The interface a registered provider serves.
+const PROVIDER_INTERFACE: &str = "org.mobileos.SearchProvider";
+
+/// How long a provider has to answer. Slower ones are left out of the results, so
+/// one stuck app can't hold up search. The service calls nothing but providers, so
+/// this is the timeout of every call it makes.
+const PROVIDER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Results returned for a query.
+const MAX_RESULTS: usize = 30;
+
+/// A result on the bus: provider, id, title, subtitle and score.
+type HitTuple = (String, String, String, String, f64);
+
+/// What a provider answers a query with: id, title, subtitle and score.
+type ProviderHit = (String, String, String, f64);
+
+/// A provider an app registered, served by the app itself.
+struct Provider {
+    owner: UniqueName<'static>,
+    path: OwnedObjectPath,
+}
+
+struct SearchService {
+    catalogs: Vec<Catalog>,
+    /// By provider name.
+    providers: BTreeMap<String, Provider>,
+}
+
+impl SearchService {
+    fn new(catalogs: Vec<Catalog>) -> Self {
+        Self {
+            catalogs,
+            providers: BTreeMap::new(),
+        }
+    }
+
+    /// Ask one registered provider, keeping only the results it scored as matches.
+    async fn ask(
+        connection: &Connection,
+        name: &str,
+        provider: &Provider,
+        query: &str,
+    ) -> zbus::Result<Vec<Hit>> {
+        let proxy = zbus::Proxy::new(
+            connection,
+            BusName::from(provider.owner.clone()),
+            provider.path.clone(),
+            PROVIDER_INTERFACE,
+        )
+        .await?;
+        let hits: Vec<ProviderHit> = proxy.call("Search", &(query,)).await?;
+        Ok(hits
+            .into_iter()
+            .map(|(id, title, subtitle, score)| Hit {
+                provider: name.to_string(),
+                id,
+                title,
+                subtitle,
+                score: score.clamp(0.0, 1.0),
+            })
+            .collect())
+    }
+}
+
+fn caller(header: &Header<'_>) -> zbus::fdo::Result<UniqueName<'static>> {
+    header
+        .sender()
+        .map(|sender| sender.to_owned())
+        .ok_or_else(|| zbus::fdo::Error::AccessDenied("caller unknown".to_string()))
+}
+
+#[interface(name = "org.mobileos.Search")]
+impl SearchService {
+    /// Every provider, built-in and registered.
+    #[zbus(property)]
+    fn providers(&self) -> Vec<String> {
+        self.catalogs
+            .iter()
+            .map(|catalog| catalog.provider.clone())
+            .chain(self.providers.keys().cloned())
+            .collect()
+    }
+
+    /// The results for `query` from every provider, best first. Providers that fail
+    /// or are too slow are left out.
+    async fn search(
+        &self,
+        query: &str,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Vec<HitTuple> {
+        if query.trim().is_empty() {
+            return Vec::new();
+        }
+        let mut hits: Vec<Hit> = self
+            .catalogs
+            .iter()
+            .flat_map(|catalog| catalog.search(query))
+            .collect();
+
+        let asked = self.providers.iter().map(|(name, provider)| async move {
+            (name, Self::ask(connection, name, provider, query).await)
+        });
+        for (name, answer) in join_all(asked).await {
+            match answer {
+                Ok(found) => hits.extend(found),
+                Err(e) => warn!(provider = %name, error = %e, "search provider failed"),
+            }
+        }
+
+        rank::rank(hits, MAX_RESULTS)
+            .into_iter()
+            .map(|hit| (hit.provider, hit.id, hit.title, hit.subtitle, hit.score))
+            .collect()
+    }
+
+    /// Have the caller answer searches as provider `name`, at `path` on its
+    /// connection, until it unregisters or leaves the bus. A name is held by one app.
+    async fn register_provider(
+        &mut self,
+        name: &str,
+        path: OwnedObjectPath,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: zbus::object_server::SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let owner = caller(&header)?;
+        if name.is_empty() || self.catalogs.iter().any(|c| c.provider == name) {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "{name:?} is not a name a provider can take"
+            )));
+        }
+        if let Some(existing) = self.providers.get(name)
+            && existing.owner != owner
+        {
+            return Err(zbus::fdo::Error::AccessDenied(format!(
+                "provider {name:?} is registered by another app"
+            )));
+        }
+        info!(provider = name, owner = %owner, "search provider registered");
+        self.providers
+            .insert(name.to_string(), Provider { owner, path });
+        let _ = self.providers_changed(&emitter).await;
+        Ok(())
+    }
+
+    async fn unregister_provider(
+        &mut self,
+        name: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: zbus::object_server::SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let owner = caller(&header)?;
+        match self.providers.get(name) {
+            Some(provider) if provider.owner == owner => {
+                self.providers.remove(name);
+                info!(provider = name, "search provider unregistered");
+                let _ = self.providers_changed(&emitter).await;
+                Ok(())
+            }
+            Some(_) => Err(zbus::fdo::Error::AccessDenied(format!(
+                "provider {name:?} is registered by another app"
+            ))),
+            None => Err(zbus::fdo::Error::InvalidArgs(format!(
+                "no provider {name:?}"
+            ))),
+        }
+    }
+}
+
+/// Forget the providers of apps that leave the bus.
+async fn drop_departed_providers(connection: Connection) -> zbus::Result<()> {
+    use futures_util::StreamExt;
+
+    let dbus = zbus::fdo::DBusProxy::new(&connection).await?;
+    let mut changes = dbus.receive_name_owner_changed().await?;
+    let iface = connection
+        .object_server()
+        .interface::<_, SearchService>("/org/mobileos/Search")
+        .await?;
+    while let Some(change) = changes.next().await {
+        let Ok(args) = change.args() else {
+            continue;
+        };
+        // A unique name losing its owner is an app gone
+        let BusName::Unique(gone) = args.name() else {
+            continue;
+        };
+        if args.new_owner().is_some() {
+            continue;
+        }
+        let mut service = iface.get_mut().await;
+        let before = service.providers.len();
+        service.providers.retain(|name, provider| {
+            let keep = provider.owner != *gone;
+            if !keep {
+                info!(provider = %name, "search provider left the bus");
+            }
+            keep
+        });
+        if service.providers.len() != before {
+            let _ = service.providers_changed(iface.signal_emitter()).await;
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    info!("starting search service");
+
+    let catalogs = catalog::load_dir(Path::new(catalog::CATALOG_DIR));
+    info!(catalogs = catalogs.len(), "search catalogs loaded");
+
+    let connection = connection::Builder::system()?
+        .name("org.mobileos.Search")?
+        .serve_at("/org/mobileos/Search", SearchService::new(catalogs))?
+        .method_timeout(PROVIDER_TIMEOUT)
+        .build()
+        .await?;
+
+    let watched = connection.clone();
+    tokio::spawn(async move {
+        if let Err(e) = drop_departed_providers(watched).await {
+            warn!(error = %e, "not watching for providers leaving the bus");
+        }
+    });
+
+    info!("search service running on system bus");
+    mos_notify::ready();
+
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use zbus::zvariant::ObjectPath;
+    use zbus::{Connection, connection, interface, proxy};
+
+    use super::{HitTuple, ProviderHit};
+    use crate::catalog::Catalog;
+
+    #[proxy(
+        interface = "org.mobileos.Search",
+        default_path = "/org/mobileos/Search"
+    )]
+    trait Search {
+        #[zbus(property)]
+        fn providers(&self) -> zbus::Result<Vec<String>>;
+
+        fn search(&self, query: &str) -> zbus::Result<Vec<HitTuple>>;
+        fn register_provider(&self, name: &str, path: &ObjectPath<'_>) -> zbus::Result<()>;
+        fn unregister_provider(&self, name: &str) -> zbus::Result<()>;
+    }
+
+    struct Contacts;
+
+    #[interface(name = "org.mobileos.SearchProvider")]
+    impl Contacts {
+        fn search(&self, query: &str) -> Vec<ProviderHit> {
+            ["Mum", "Wilma Flintstone"]
+                .into_iter()
+                .filter(|name| name.to_lowercase().contains(&query.to_lowercase()))
+                .map(|name| {
+                    let id = format!("contacts:{name}");
+                    (id, name.to_string(), "Contact".to_string(), 0.95)
+                })
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn providers_are_searched_together() {
+        let catalog = Catalog::parse(
+            "provider = \"apps\"\n\
+             [[entry]]\nid = \"app:music\"\ntitle = \"Music\"\n\
+             [[entry]]\nid = \"app:messages\"\ntitle = \"Messages\"\n",
+        )
+        .unwrap();
+        let service = super::SearchService::new(vec![catalog]);
+        let conn = connection::Builder::session()
+            .unwrap()
+            .serve_at("/org/mobileos/Search", service)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let client = connection::Builder::session()
+            .unwrap()
+            .serve_at("/contacts", Contacts)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let proxy = SearchProxy::builder(&client)
+            .destination(conn.unique_name().unwrap().to_owned())
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        let titles = |hits: Vec<HitTuple>| -> Vec<String> {
+            hits.into_iter().map(|(_, _, title, _, _)| title).collect()
+        };
+        assert_eq!(titles(proxy.search("mu").await.unwrap()), ["Music"]);
+        assert!(proxy.search(" ").await.unwrap().is_empty());
+
+        let path = ObjectPath::try_from("/contacts").unwrap();
+        proxy.register_provider("contacts", &path).await.unwrap();
+        assert!(proxy.register_provider("apps", &path).await.is_err());
+        assert_eq!(proxy.providers().await.unwrap(), ["apps", "contacts"]);
+        // The contact says it matches best, so comes first
+        assert_eq!(titles(proxy.search("mu").await.unwrap()), ["Mum", "Music"]);
+
+        // Another app can't take the name over or drop it
+        let other = Connection::session().await.unwrap();
+        let intruder = SearchProxy::builder(&other)
+            .destination(conn.unique_name().unwrap().to_owned())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        assert!(intruder.register_provider("contacts", &path).await.is_err());
+        assert!(intruder.unregister_provider("contacts").await.is_err());
+
+        proxy.unregister_provider("contacts").await.unwrap();
+        assert_eq!(titles(proxy.search("mu").await.unwrap()), ["Music"]);
+    }
+}
@@ -0,0 +1,111 @@
+// ABOUTME: How well a result matches a query, and the order results from every provider are shown in.
+// ABOUTME: Whole-word and prefix matches rank above matches inside words or of scattered letters.
+
+/// One result, from whichever provider found it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub provider: String,
+    /// What the shell opens: "app:<name>", "settings:<panel>", or whatever the
+    /// provider's own app understands.
+    pub id: String,
+    pub title: String,
+    pub subtitle: String,
+    /// From 0, barely matching, to 1, an exact match.
+    pub score: f64,
+}
+
+/// How well `text` matches one query `term`, already lowercase: `None` when it
+/// doesn't. Each letter of the term must appear in order for any match.
+fn term_score(term: &str, text: &str) -> Option<f64> {
+    if text == term {
+        return Some(1.0);
+    }
+    if text.starts_with(term) {
+        return Some(0.9);
+    }
+    if text
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(term))
+    {
+        return Some(0.8);
+    }
+    if text.contains(term) {
+        return Some(0.6);
+    }
+    let mut chars = text.chars();
+    term.chars().all(|t| chars.any(|c| c == t)).then_some(0.3)
+}
+
+/// How well `text` matches `query`: every word of the query has to match, and the
+/// score is their average. `None` for an empty query.
+pub fn score(query: &str, text: &str) -> Option<f64> {
+    let text = text.to_lowercase();
+    let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    if terms.is_empty() {
+        return None;
+    }
+    let mut total = 0.0;
+    for term in &terms {
+        total += term_score(term, &text)?;
+    }
+    Some(total / terms.len() as f64)
+}
+
+/// The best of `hits` first, at most `limit` of them. Equal scores are in title
+/// order, so results don't jump around as providers answer in a different order.
+pub fn rank(mut hits: Vec<Hit>, limit: usize) -> Vec<Hit> {
+    hits.retain(|hit| hit.score > 0.0);
+    hits.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.title.cmp(&b.title))
+    });
+    hits.truncate(limit);
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closer_matches_score_higher() {
+        let scores: Vec<_> = ["wi", "wi-fi", "home wi-fi", "kiwis", "wait"]
+            .iter()
+            .map(|text| score("wi", text).unwrap())
+            .collect();
+        assert!(
+            scores.windows(2).all(|pair| pair[0] > pair[1]),
+            "{scores:?}"
+        );
+        assert_eq!(score("Wi-Fi", "wi-fi"), Some(1.0));
+        assert_eq!(score("wfi", "Wi-Fi"), Some(0.3));
+        assert_eq!(score("bluetooth", "Wi-Fi"), None);
+        assert_eq!(score("  ", "Wi-Fi"), None);
+        // Every word has to match, in any order
+        assert!(score("settings dis", "Display settings").is_some());
+        assert!(score("settings sound", "Display settings").is_none());
+    }
+
+    #[test]
+    fn best_results_come_first() {
+        let hit = |provider: &str, title: &str, score: f64| Hit {
+            provider: provider.to_string(),
+            id: format!("{provider}:{title}"),
+            title: title.to_string(),
+            subtitle: String::new(),
+            score,
+        };
+        let ranked = rank(
+            vec![
+                hit("contacts", "Mum", 0.6),
+                hit("apps", "Music", 0.9),
+                hit("settings", "Sound", 0.0),
+                hit("messages", "Meet at 6?", 0.9),
+            ],
+            2,
+        );
+        let titles: Vec<_> = ranked.iter().map(|hit| hit.title.as_str()).collect();
+        assert_eq!(titles, ["Meet at 6?", "Music"]);
+    }
+}
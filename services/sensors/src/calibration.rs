@@ -0,0 +1,385 @@
+// ABOUTME: Magnetometer calibration: hard/soft-iron correction, fitting it from a figure-eight
+// ABOUTME: sweep, and spotting a bad calibration from the residuals of fusing with the accelerometer.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+pub const CALIBRATION_PATH: &str = "/data/sensors/magnetometer.toml";
+
+/// Samples the fusion residuals are computed over; about five seconds at the poll rate.
+const RESIDUAL_WINDOW: usize = 100;
+
+/// Relative spread of the corrected field strength above which calibration is off. The
+/// earth's field is the same strength whichever way the phone points.
+const MAX_MAGNITUDE_SPREAD: f64 = 0.1;
+
+/// Spread of the angle between the field and gravity, in radians, above which
+/// calibration is off. The angle is the local magnetic inclination and doesn't change
+/// as the phone turns.
+const MAX_INCLINATION_SPREAD: f64 = 0.1;
+
+/// The sweep is done once the phone has been turned so gravity pointed into every octant.
+const OCTANTS: u32 = 8;
+
+/// Hard-iron offsets and soft-iron scale factors, applied as `(raw - offset) * scale`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub offset: [f64; 3],
+    pub scale: [f64; 3],
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            offset: [0.0; 3],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl Calibration {
+    pub fn apply(&self, raw: [f64; 3]) -> [f64; 3] {
+        std::array::from_fn(|i| (raw[i] - self.offset[i]) * self.scale[i])
+    }
+
+    /// Load a stored calibration; `None` if none was stored yet.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let calibration =
+            toml::from_str(&content).with_context(|| format!("invalid {}", path.display()))?;
+        Ok(Some(calibration))
+    }
+
+    /// Store the calibration, replacing the file atomically so a crash can't leave half of it.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let content = toml::to_string(self).context("failed to serialize calibration")?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))
+    }
+}
+
+/// Raw readings taken while the user waves the phone in a figure eight.
+#[derive(Debug, Default)]
+pub struct Sweep {
+    min: Option<[f64; 3]>,
+    max: Option<[f64; 3]>,
+    samples: Vec<[f64; 3]>,
+    /// Octants gravity pointed into, one bit each: the ways the phone has been turned.
+    octants: u8,
+}
+
+impl Sweep {
+    /// Add a raw reading with the gravity vector measured at the same time.
+    pub fn push(&mut self, raw: [f64; 3], gravity: [f64; 3]) {
+        let min = self.min.get_or_insert(raw);
+        let max = self.max.get_or_insert(raw);
+        for i in 0..3 {
+            min[i] = min[i].min(raw[i]);
+            max[i] = max[i].max(raw[i]);
+        }
+        self.samples.push(raw);
+
+        if norm(gravity) > f64::EPSILON {
+            let octant = (0..3)
+                .filter(|&i| gravity[i] >= 0.0)
+                .fold(0, |octant, i| octant | 1 << i);
+            self.octants |= 1 << octant;
+        }
+    }
+
+    /// How much of the sweep is done, in percent.
+    pub fn progress(&self) -> u8 {
+        (self.octants.count_ones() * 100 / OCTANTS) as u8
+    }
+
+    /// Fit the calibration: the centre of the readings is the hard-iron offset, and
+    /// scaling each axis to the mean radius undoes the soft-iron stretch.
+    pub fn fit(&self) -> Result<Calibration> {
+        let (Some(min), Some(max)) = (self.min, self.max) else {
+            bail!("no magnetometer readings were taken");
+        };
+        if self.octants.count_ones() < OCTANTS {
+            bail!("the phone wasn't turned in every direction");
+        }
+        let radius: [f64; 3] = std::array::from_fn(|i| (max[i] - min[i]) / 2.0);
+        if radius.iter().any(|&r| r <= f64::EPSILON) {
+            bail!("the magnetometer readings don't change");
+        }
+        let mean = radius.iter().sum::<f64>() / 3.0;
+        let calibration = Calibration {
+            offset: std::array::from_fn(|i| (min[i] + max[i]) / 2.0),
+            scale: radius.map(|r| mean / r),
+        };
+
+        // A magnet moving nearby during the sweep leaves readings no correction fits
+        let strengths: VecDeque<f64> = self
+            .samples
+            .iter()
+            .map(|&raw| norm(calibration.apply(raw)))
+            .collect();
+        let (mean, spread) = mean_and_deviation(&strengths);
+        if spread / mean > MAX_MAGNITUDE_SPREAD {
+            bail!("the magnetometer readings are inconsistent; move away from magnets and metal");
+        }
+        Ok(calibration)
+    }
+}
+
+/// Watches corrected readings against gravity for signs that the calibration no longer
+/// fits, e.g. after a magnetic case was put on.
+#[derive(Debug, Default)]
+pub struct Residuals {
+    magnitude: VecDeque<f64>,
+    inclination: VecDeque<f64>,
+}
+
+impl Residuals {
+    /// Add a corrected field reading with the gravity vector measured at the same time,
+    /// and return whether the calibration looks poor.
+    pub fn push(&mut self, field: [f64; 3], gravity: [f64; 3]) -> bool {
+        let (field_norm, gravity_norm) = (norm(field), norm(gravity));
+        if field_norm <= f64::EPSILON || gravity_norm <= f64::EPSILON {
+            return false;
+        }
+        let cos = (0..3).map(|i| field[i] * gravity[i]).sum::<f64>() / (field_norm * gravity_norm);
+        push_bounded(&mut self.magnitude, field_norm);
+        push_bounded(&mut self.inclination, cos.clamp(-1.0, 1.0).acos());
+        self.poor()
+    }
+
+    pub fn clear(&mut self) {
+        self.magnitude.clear();
+        self.inclination.clear();
+    }
+
+    fn poor(&self) -> bool {
+        if self.magnitude.len() < RESIDUAL_WINDOW {
+            return false;
+        }
+        let (mean, spread) = mean_and_deviation(&self.magnitude);
+        let (_, inclination_spread) = mean_and_deviation(&self.inclination);
+        spread / mean > MAX_MAGNITUDE_SPREAD || inclination_spread > MAX_INCLINATION_SPREAD
+    }
+}
+
+fn push_bounded(window: &mut VecDeque<f64>, value: f64) {
+    if window.len() == RESIDUAL_WINDOW {
+        window.pop_front();
+    }
+    window.push_back(value);
+}
+
+fn norm(v: [f64; 3]) -> f64 {
+    v.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+fn mean_and_deviation(values: &VecDeque<f64>) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+}
+
+/// The magnetometer's state: the calibration in use, a sweep when one is running, and
+/// the residual check.
+#[derive(Debug)]
+pub struct Compass {
+    path: PathBuf,
+    calibration: Calibration,
+    sweep: Option<Sweep>,
+    residuals: Residuals,
+    field: [f64; 3],
+    needs_calibration: bool,
+}
+
+impl Compass {
+    /// Use the calibration stored at `path`. Without one the compass needs calibrating.
+    pub fn load(path: PathBuf) -> Self {
+        let stored = Calibration::load(&path).unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "ignoring stored magnetometer calibration");
+            None
+        });
+        Self {
+            path,
+            needs_calibration: stored.is_none(),
+            calibration: stored.unwrap_or_default(),
+            sweep: None,
+            residuals: Residuals::default(),
+            field: [0.0; 3],
+        }
+    }
+
+    /// The corrected field, in the sensor's units.
+    pub fn field(&self) -> [f64; 3] {
+        self.field
+    }
+
+    pub fn needs_calibration(&self) -> bool {
+        self.needs_calibration
+    }
+
+    /// Sweep progress in percent, or `None` when no calibration is running.
+    pub fn progress(&self) -> Option<u8> {
+        self.sweep.as_ref().map(Sweep::progress)
+    }
+
+    /// Take a raw reading and the gravity vector. Returns true when this reading showed
+    /// the calibration to be poor, so listeners can be told once.
+    pub fn update(&mut self, raw: [f64; 3], gravity: [f64; 3]) -> bool {
+        self.field = self.calibration.apply(raw);
+        if let Some(sweep) = &mut self.sweep {
+            sweep.push(raw, gravity);
+            return false;
+        }
+        if self.needs_calibration || !self.residuals.push(self.field, gravity) {
+            return false;
+        }
+        self.needs_calibration = true;
+        true
+    }
+
+    pub fn start_calibration(&mut self) {
+        self.sweep = Some(Sweep::default());
+    }
+
+    pub fn cancel_calibration(&mut self) {
+        self.sweep = None;
+    }
+
+    /// Fit and store the calibration from the running sweep. The sweep keeps running if
+    /// it isn't good enough yet.
+    pub fn finish_calibration(&mut self) -> Result<Calibration> {
+        let Some(sweep) = &self.sweep else {
+            bail!("no calibration is running");
+        };
+        let calibration = sweep.fit()?;
+        calibration.save(&self.path)?;
+        self.calibration = calibration;
+        self.sweep = None;
+        self.residuals.clear();
+        self.needs_calibration = false;
+        Ok(calibration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EARTH: f64 = 50.0;
+
+    /// Field readings around a sphere, distorted by a known offset and stretch, each with
+    /// the gravity vector of the phone's orientation at the time.
+    fn figure_eight(offset: [f64; 3], stretch: [f64; 3]) -> Vec<([f64; 3], [f64; 3])> {
+        let mut readings = Vec::new();
+        for i in 0..12 {
+            for j in 0..24 {
+                let (theta, phi) = (
+                    std::f64::consts::PI * (i as f64 + 0.5) / 12.0,
+                    std::f64::consts::TAU * j as f64 / 24.0,
+                );
+                let unit = [
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ];
+                let raw = std::array::from_fn(|k| unit[k] * EARTH * stretch[k] + offset[k]);
+                readings.push((raw, unit.map(|u| u * 9.8)));
+            }
+        }
+        readings
+    }
+
+    #[test]
+    fn fits_hard_and_soft_iron() {
+        let mut sweep = Sweep::default();
+        assert_eq!(sweep.progress(), 0);
+        assert!(sweep.fit().is_err());
+
+        for (raw, gravity) in figure_eight([12.0, -30.0, 5.0], [1.2, 1.0, 0.8]) {
+            sweep.push(raw, gravity);
+        }
+        assert_eq!(sweep.progress(), 100);
+
+        let calibration = sweep.fit().unwrap();
+        for (raw, _) in figure_eight([12.0, -30.0, 5.0], [1.2, 1.0, 0.8]) {
+            let strength = norm(calibration.apply(raw));
+            assert!((strength - EARTH).abs() < 2.0, "{strength}");
+        }
+    }
+
+    #[test]
+    fn partial_sweep_is_not_enough() {
+        let mut sweep = Sweep::default();
+        for (raw, gravity) in figure_eight([0.0; 3], [1.0; 3]) {
+            if gravity[2] > 0.0 {
+                sweep.push(raw, gravity);
+            }
+        }
+        assert_eq!(sweep.progress(), 50);
+        assert!(sweep.fit().is_err());
+    }
+
+    #[test]
+    fn sweep_disturbed_by_a_magnet_is_rejected() {
+        let mut sweep = Sweep::default();
+        for (i, (raw, gravity)) in figure_eight([0.0; 3], [1.0; 3]).into_iter().enumerate() {
+            let magnet = if i % 3 == 0 { 30.0 } else { 0.0 };
+            sweep.push([raw[0] + magnet, raw[1], raw[2]], gravity);
+        }
+        assert_eq!(sweep.progress(), 100);
+        assert!(sweep.fit().is_err());
+    }
+
+    #[test]
+    fn residuals_flag_an_offset_field() {
+        let gravity = [0.0, 0.0, 9.8];
+        let mut residuals = Residuals::default();
+        // A phone lying still sees the same field throughout
+        for _ in 0..RESIDUAL_WINDOW {
+            assert!(!residuals.push([20.0, 0.0, -45.0], gravity));
+        }
+
+        let mut flagged = false;
+        for (raw, _) in figure_eight([40.0, 0.0, 0.0], [1.0; 3]) {
+            flagged |= residuals.push(raw, gravity);
+        }
+        assert!(flagged);
+    }
+
+    #[test]
+    fn compass_stores_calibration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sensors/magnetometer.toml");
+
+        let mut compass = Compass::load(path.clone());
+        assert!(compass.needs_calibration());
+        assert!(compass.finish_calibration().is_err());
+
+        compass.start_calibration();
+        for (raw, gravity) in figure_eight([20.0, 0.0, -10.0], [1.0; 3]) {
+            assert!(!compass.update(raw, gravity));
+        }
+        assert_eq!(compass.progress(), Some(100));
+        let calibration = compass.finish_calibration().unwrap();
+        assert!(!compass.needs_calibration());
+        assert_eq!(compass.progress(), None);
+
+        let stored = Compass::load(path);
+        assert!(!stored.needs_calibration());
+        assert_eq!(stored.calibration, calibration);
+    }
+}
@@ -1,18 +1,44 @@
 // ABOUTME: Sensor D-Bus daemon for MobileOS.
-// ABOUTME: Exposes proximity, ambient light, and accelerometer readings over org.mobileos.Sensors.
+// ABOUTME: Exposes proximity, ambient light, pocket state, accelerometer, orientation, and compass readings over org.mobileos.Sensors.
 
+mod calibration;
+mod orientation;
+mod pocket;
+
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use tracing::info;
+use tracing::{info, warn};
+use zbus::object_server::{InterfaceRef, SignalEmitter};
 use zbus::{connection, interface};
 
+use calibration::Compass;
+use orientation::Orientation;
+use pocket::PocketDetector;
+
+const IIO_DEVICES_DIR: &str = "/sys/bus/iio/devices";
+
+/// How often the magnetometer and accelerometer are read.
+const IIO_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often the proximity and light sensors are read.
+const LIGHT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Raw proximity readings at or above this count as near, for drivers that don't give
+/// their own threshold in in_proximity_nearlevel. Higher readings are closer.
+const PROXIMITY_NEAR_LEVEL: f64 = 100.0;
+
 struct SensorsService {
     proximity: Arc<AtomicBool>,
     ambient_light: Arc<AtomicU32>,
     accel_x: Arc<AtomicU64>,
     accel_y: Arc<AtomicU64>,
     accel_z: Arc<AtomicU64>,
+    orientation: Arc<Mutex<Orientation>>,
+    compass: Arc<Mutex<Compass>>,
+    pocket: Arc<Mutex<PocketDetector>>,
 }
 
 impl SensorsService {
@@ -23,8 +49,29 @@ impl SensorsService {
             accel_x: Arc::new(AtomicU64::new(0.0f64.to_bits())),
             accel_y: Arc::new(AtomicU64::new(0.0f64.to_bits())),
             accel_z: Arc::new(AtomicU64::new(9.8f64.to_bits())),
+            orientation: Arc::new(Mutex::new(Orientation::default())),
+            compass: Arc::new(Mutex::new(Compass::load(PathBuf::from(
+                calibration::CALIBRATION_PATH,
+            )))),
+            pocket: Arc::new(Mutex::new(PocketDetector::default())),
         }
     }
+
+    /// Keep the magnetometer calibration at `path` instead of the system location.
+    #[cfg(test)]
+    fn with_calibration_path(mut self, path: PathBuf) -> Self {
+        self.compass = Arc::new(Mutex::new(Compass::load(path)));
+        self
+    }
+
+    fn gravity(&self) -> [f64; 3] {
+        [&self.accel_x, &self.accel_y, &self.accel_z]
+            .map(|a| f64::from_bits(a.load(Ordering::Relaxed)))
+    }
+}
+
+fn failed(e: anyhow::Error) -> zbus::fdo::Error {
+    zbus::fdo::Error::Failed(format!("{e:#}"))
 }
 
 #[interface(name = "org.mobileos.Sensors")]
@@ -39,6 +86,13 @@ impl SensorsService {
         self.ambient_light.load(Ordering::Relaxed)
     }
 
+    /// Something has covered the proximity sensor in the dark for a moment: the phone is
+    /// in a pocket or bag, and touches on the screen aren't meant.
+    #[zbus(property)]
+    fn in_pocket(&self) -> bool {
+        self.pocket.lock().unwrap().in_pocket()
+    }
+
     #[zbus(property)]
     fn accelerometer_x(&self) -> f64 {
         f64::from_bits(self.accel_x.load(Ordering::Relaxed))
@@ -53,6 +107,163 @@ impl SensorsService {
     fn accelerometer_z(&self) -> f64 {
         f64::from_bits(self.accel_z.load(Ordering::Relaxed))
     }
+
+    /// Which edge of the screen is up: "normal", "left-up", "bottom-up" or "right-up".
+    /// It stays put while the phone lies flat.
+    #[zbus(property)]
+    fn orientation(&self) -> String {
+        self.orientation.lock().unwrap().as_str().to_string()
+    }
+
+    #[zbus(property)]
+    fn magnetometer_x(&self) -> f64 {
+        self.compass.lock().unwrap().field()[0]
+    }
+
+    #[zbus(property)]
+    fn magnetometer_y(&self) -> f64 {
+        self.compass.lock().unwrap().field()[1]
+    }
+
+    #[zbus(property)]
+    fn magnetometer_z(&self) -> f64 {
+        self.compass.lock().unwrap().field()[2]
+    }
+
+    #[zbus(property)]
+    fn needs_calibration(&self) -> bool {
+        self.compass.lock().unwrap().needs_calibration()
+    }
+
+    /// How much of the figure-eight sweep is done, in percent; 0 when no calibration runs.
+    #[zbus(property)]
+    fn calibration_progress(&self) -> u8 {
+        self.compass.lock().unwrap().progress().unwrap_or(0)
+    }
+
+    /// Start collecting magnetometer readings while the user moves the phone in a figure eight.
+    async fn start_calibration(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) {
+        info!("magnetometer calibration started");
+        self.compass.lock().unwrap().start_calibration();
+        if let Err(e) = self.calibration_progress_changed(&emitter).await {
+            warn!(error = %e, "failed to emit calibration progress");
+        }
+    }
+
+    /// Fit the hard and soft-iron correction from the sweep and store it. Fails, and keeps
+    /// the sweep going, while the phone hasn't been turned every way yet.
+    async fn finish_calibration(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let calibration = self
+            .compass
+            .lock()
+            .unwrap()
+            .finish_calibration()
+            .map_err(failed)?;
+        info!(offset = ?calibration.offset, scale = ?calibration.scale, "magnetometer calibrated");
+        if let Err(e) = self.needs_calibration_changed(&emitter).await {
+            warn!(error = %e, "failed to emit calibration state");
+        }
+        Ok(())
+    }
+
+    async fn cancel_calibration(&self) {
+        info!("magnetometer calibration cancelled");
+        self.compass.lock().unwrap().cancel_calibration();
+    }
+
+    /// The fusion residuals show the compass is off, e.g. after a magnet came close.
+    #[zbus(signal)]
+    async fn calibration_needed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
+
+/// Take an accelerometer reading, and tell clients when the screen has turned.
+async fn record_accelerometer(iface: &InterfaceRef<SensorsService>, accel: [f64; 3]) {
+    let service = iface.get().await;
+    for (atomic, value) in [&service.accel_x, &service.accel_y, &service.accel_z]
+        .into_iter()
+        .zip(accel)
+    {
+        atomic.store(value.to_bits(), Ordering::Relaxed);
+    }
+    let turned = {
+        let mut current = service.orientation.lock().unwrap();
+        let next = orientation::orientation(accel, *current);
+        (next != *current).then(|| {
+            *current = next;
+            next
+        })
+    };
+    if let Some(orientation) = turned {
+        info!(
+            orientation = orientation.as_str(),
+            "screen orientation changed"
+        );
+        if let Err(e) = service.orientation_changed(iface.signal_emitter()).await {
+            warn!(error = %e, "failed to emit orientation change");
+        }
+    }
+}
+
+/// Feed a raw magnetometer reading to the compass and tell clients what it changed.
+async fn record_magnetometer(iface: &InterfaceRef<SensorsService>, raw: [f64; 3]) {
+    let service = iface.get().await;
+    let (newly_needed, progress) = {
+        let mut compass = service.compass.lock().unwrap();
+        let before = compass.progress();
+        let newly_needed = compass.update(raw, service.gravity());
+        let progress = compass.progress();
+        (newly_needed, progress != before)
+    };
+
+    let emitter = iface.signal_emitter();
+    let mut result = Ok(());
+    if newly_needed {
+        warn!("magnetometer calibration looks poor");
+        result = SensorsService::calibration_needed(emitter).await;
+        if result.is_ok() {
+            result = service.needs_calibration_changed(emitter).await;
+        }
+    }
+    if progress && result.is_ok() {
+        result = service.calibration_progress_changed(emitter).await;
+    }
+    if let Err(e) = result {
+        warn!(error = %e, "failed to emit compass change");
+    }
+}
+
+/// Take a proximity and ambient light reading, and tell clients what it changed.
+async fn record_light_and_proximity(
+    iface: &InterfaceRef<SensorsService>,
+    near: bool,
+    lux: u32,
+    at: Instant,
+) {
+    let service = iface.get().await;
+    let near_changed = service.proximity.swap(near, Ordering::Relaxed) != near;
+    let lux_changed = service.ambient_light.swap(lux, Ordering::Relaxed) != lux;
+    let pocket = service.pocket.lock().unwrap().update(near, lux, at);
+
+    let emitter = iface.signal_emitter();
+    let mut result = Ok(());
+    if near_changed {
+        result = service.proximity_changed(emitter).await;
+    }
+    if lux_changed && result.is_ok() {
+        result = service.ambient_light_changed(emitter).await;
+    }
+    if let Some(in_pocket) = pocket {
+        info!(in_pocket, "pocket state changed");
+        if result.is_ok() {
+            result = service.in_pocket_changed(emitter).await;
+        }
+    }
+    if let Err(e) = result {
+        warn!(error = %e, "failed to emit light or proximity change");
+    }
 }
 
 #[tokio::main]
@@ -68,20 +279,193 @@ async fn main() -> anyhow::Result<()> {
 
     let service = SensorsService::new();
 
-    let _connection = connection::Builder::session()?
+    let connection = connection::Builder::system()?
         .name("org.mobileos.Sensors")?
         .serve_at("/org/mobileos/Sensors", service)?
         .build()
         .await?;
 
-    info!("sensors service running on session bus");
+    // Phones whose device tree lacks the sensors' mount matrices have them in the
+    // device config instead.
+    let mounting = match mos_device::load_from_system() {
+        Ok(device) => device.map(|device| device.sensors).unwrap_or_default(),
+        Err(e) => {
+            warn!(error = %e, "failed to load device config");
+            Default::default()
+        }
+    };
+
+    let magnetometer = find_iio_device(Path::new(IIO_DEVICES_DIR), "magn");
+    let accelerometer = find_iio_device(Path::new(IIO_DEVICES_DIR), "accel");
+    match &magnetometer {
+        Some(magnetometer) => info!(device = %magnetometer.display(), "using IIO magnetometer"),
+        None => info!("no magnetometer found"),
+    }
+    match &accelerometer {
+        Some(accelerometer) => {
+            info!(device = %accelerometer.display(), "using IIO accelerometer")
+        }
+        None => info!("no accelerometer found, auto-rotate disabled"),
+    }
+    if magnetometer.is_some() || accelerometer.is_some() {
+        let iface = connection
+            .object_server()
+            .interface::<_, SensorsService>("/org/mobileos/Sensors")
+            .await?;
+        tokio::spawn(poll_iio(magnetometer, accelerometer, mounting, iface));
+    }
+
+    match find_iio_scalar_device(Path::new(IIO_DEVICES_DIR), "proximity") {
+        Some(proximity) => {
+            info!(device = %proximity.display(), "using IIO proximity sensor");
+            let light = find_iio_scalar_device(Path::new(IIO_DEVICES_DIR), "illuminance");
+            let iface = connection
+                .object_server()
+                .interface::<_, SensorsService>("/org/mobileos/Sensors")
+                .await?;
+            tokio::spawn(poll_light_and_proximity(proximity, light, iface));
+        }
+        None => info!("no proximity sensor found, pocket detection disabled"),
+    }
+
+    info!("sensors service running on system bus");
+    mos_notify::ready();
 
     std::future::pending::<()>().await;
     Ok(())
 }
 
+/// The first IIO device with raw readings for `channel`, e.g. "magn" or "accel".
+fn find_iio_device(dir: &Path, channel: &str) -> Option<PathBuf> {
+    let mut devices: Vec<PathBuf> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join(format!("in_{channel}_x_raw")).exists())
+        .collect();
+    devices.sort();
+    devices.into_iter().next()
+}
+
+/// The first IIO device with a single reading for `channel`, e.g. "proximity" or
+/// "illuminance".
+fn find_iio_scalar_device(dir: &Path, channel: &str) -> Option<PathBuf> {
+    let mut devices: Vec<PathBuf> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.join(format!("in_{channel}_raw")).exists()
+                || path.join(format!("in_{channel}_input")).exists()
+        })
+        .collect();
+    devices.sort();
+    devices.into_iter().next()
+}
+
+fn read_iio_value(device: &Path, name: &str) -> Option<f64> {
+    std::fs::read_to_string(device.join(name))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Read a single IIO channel: the processed value if the driver gives one, otherwise
+/// the raw one scaled.
+fn read_iio_scalar(device: &Path, channel: &str) -> Option<f64> {
+    if let Some(value) = read_iio_value(device, &format!("in_{channel}_input")) {
+        return Some(value);
+    }
+    let scale = read_iio_value(device, &format!("in_{channel}_scale")).unwrap_or(1.0);
+    Some(read_iio_value(device, &format!("in_{channel}_raw"))? * scale)
+}
+
+/// Read the x, y and z channels of an IIO device, scaled to the driver's units
+/// (gauss for magnetometers, m/s² for accelerometers).
+fn read_iio_vector(device: &Path, channel: &str) -> Option<[f64; 3]> {
+    let read = |name: String| -> Option<f64> {
+        std::fs::read_to_string(device.join(name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    let scale = read(format!("in_{channel}_scale")).unwrap_or(1.0);
+    let mut vector = [0.0; 3];
+    for (value, axis) in vector.iter_mut().zip(["x", "y", "z"]) {
+        let axis_scale = read(format!("in_{channel}_{axis}_scale")).unwrap_or(scale);
+        *value = read(format!("in_{channel}_{axis}_raw"))? * axis_scale;
+    }
+    Some(vector)
+}
+
+/// Poll the accelerometer, for orientation and for the magnetometer's residuals to be
+/// checked against, and the magnetometer, turning readings onto the phone's axes by
+/// `mounting`.
+async fn poll_iio(
+    magnetometer: Option<PathBuf>,
+    accelerometer: Option<PathBuf>,
+    mounting: mos_device::Sensors,
+    iface: InterfaceRef<SensorsService>,
+) {
+    let mount = |matrix: &Option<mos_device::MountMatrix>, vector| match matrix {
+        Some(matrix) => mos_device::apply_mount_matrix(matrix, vector),
+        None => vector,
+    };
+    let mut interval = tokio::time::interval(IIO_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Some(accel) = accelerometer
+            .as_deref()
+            .and_then(|dev| read_iio_vector(dev, "accel"))
+        {
+            let accel = mount(&mounting.accelerometer_mount_matrix, accel);
+            record_accelerometer(&iface, accel).await;
+        }
+        let Some(magnetometer) = magnetometer.as_deref() else {
+            continue;
+        };
+        match read_iio_vector(magnetometer, "magn") {
+            Some(raw) => {
+                let raw = mount(&mounting.magnetometer_mount_matrix, raw);
+                record_magnetometer(&iface, raw).await
+            }
+            None => warn!(device = %magnetometer.display(), "failed to read magnetometer"),
+        }
+    }
+}
+
+/// Poll the proximity sensor, and the light sensor if there is one, for pocket detection.
+/// Without a light sensor the last ambient light reading stands.
+async fn poll_light_and_proximity(
+    proximity: PathBuf,
+    light: Option<PathBuf>,
+    iface: InterfaceRef<SensorsService>,
+) {
+    let near_level =
+        read_iio_value(&proximity, "in_proximity_nearlevel").unwrap_or(PROXIMITY_NEAR_LEVEL);
+    let mut interval = tokio::time::interval(LIGHT_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(distance) = read_iio_scalar(&proximity, "proximity") else {
+            warn!(device = %proximity.display(), "failed to read proximity sensor");
+            continue;
+        };
+        let lux = match light
+            .as_deref()
+            .and_then(|dev| read_iio_scalar(dev, "illuminance"))
+        {
+            Some(lux) => lux.max(0.0).round() as u32,
+            None => iface.get().await.ambient_light.load(Ordering::Relaxed),
+        };
+        record_light_and_proximity(&iface, distance >= near_level, lux, Instant::now()).await;
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::Ordering;
+
+    use zbus::object_server::InterfaceRef;
     use zbus::{connection, proxy, Connection};
 
     #[proxy(
@@ -95,6 +479,9 @@ mod tests {
         #[zbus(property)]
         fn ambient_light(&self) -> zbus::Result<u32>;
 
+        #[zbus(property)]
+        fn in_pocket(&self) -> zbus::Result<bool>;
+
         #[zbus(property)]
         fn accelerometer_x(&self) -> zbus::Result<f64>;
 
@@ -103,6 +490,25 @@ mod tests {
 
         #[zbus(property)]
         fn accelerometer_z(&self) -> zbus::Result<f64>;
+
+        #[zbus(property)]
+        fn orientation(&self) -> zbus::Result<String>;
+
+        #[zbus(property)]
+        fn magnetometer_x(&self) -> zbus::Result<f64>;
+
+        #[zbus(property)]
+        fn needs_calibration(&self) -> zbus::Result<bool>;
+
+        #[zbus(property)]
+        fn calibration_progress(&self) -> zbus::Result<u8>;
+
+        fn start_calibration(&self) -> zbus::Result<()>;
+        fn finish_calibration(&self) -> zbus::Result<()>;
+        fn cancel_calibration(&self) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        fn calibration_needed(&self) -> zbus::Result<()>;
     }
 
     async fn start_test_service() -> (Connection, zbus::names::OwnedUniqueName) {
@@ -164,4 +570,164 @@ mod tests {
         assert!((proxy.accelerometer_y().await.unwrap() - 0.0).abs() < f64::EPSILON);
         assert!((proxy.accelerometer_z().await.unwrap() - 9.8).abs() < f64::EPSILON);
     }
+
+    #[tokio::test]
+    async fn detects_pocket_from_proximity_and_light() {
+        use std::time::{Duration, Instant};
+
+        let (conn, name) = start_test_service().await;
+        let iface = conn
+            .object_server()
+            .interface::<_, super::SensorsService>("/org/mobileos/Sensors")
+            .await
+            .unwrap();
+        let client = Connection::session().await.unwrap();
+        let proxy = SensorsProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        assert!(!proxy.in_pocket().await.unwrap());
+        super::record_light_and_proximity(&iface, true, 2, ms(0)).await;
+        assert!(proxy.proximity().await.unwrap());
+        assert_eq!(proxy.ambient_light().await.unwrap(), 2);
+        assert!(!proxy.in_pocket().await.unwrap());
+
+        super::record_light_and_proximity(&iface, true, 1, ms(1000)).await;
+        assert!(proxy.in_pocket().await.unwrap());
+
+        super::record_light_and_proximity(&iface, false, 1, ms(1200)).await;
+        assert!(!proxy.in_pocket().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn follows_which_edge_is_up() {
+        use futures_util::StreamExt;
+
+        let (conn, name) = start_test_service().await;
+        let iface = conn
+            .object_server()
+            .interface::<_, super::SensorsService>("/org/mobileos/Sensors")
+            .await
+            .unwrap();
+        let client = Connection::session().await.unwrap();
+        let proxy = SensorsProxy::builder(&client)
+            .destination(name.clone())
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+        // Only a proxy caching properties follows their changes
+        let watcher = SensorsProxy::builder(&client)
+            .destination(name)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let mut changes = watcher.receive_orientation_changed().await;
+
+        assert_eq!(proxy.orientation().await.unwrap(), "normal");
+        super::record_accelerometer(&iface, [-9.8, 0.0, 0.0]).await;
+        assert_eq!(proxy.orientation().await.unwrap(), "left-up");
+        let turned = async {
+            while let Some(change) = changes.next().await {
+                if change.get().await.unwrap() == "left-up" {
+                    return;
+                }
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(5), turned)
+            .await
+            .expect("no orientation change was signalled");
+
+        // Laid down on a table, it stays as it was
+        super::record_accelerometer(&iface, [0.0, 0.0, 9.8]).await;
+        assert_eq!(proxy.orientation().await.unwrap(), "left-up");
+        assert!((proxy.accelerometer_z().await.unwrap() - 9.8).abs() < f64::EPSILON);
+    }
+
+    /// Turn the phone so gravity points along `direction`, and take a magnetometer reading.
+    async fn turn(
+        iface: &InterfaceRef<super::SensorsService>,
+        direction: [f64; 3],
+        raw: [f64; 3],
+    ) {
+        {
+            let service = iface.get().await;
+            let accel = [&service.accel_x, &service.accel_y, &service.accel_z];
+            for (axis, value) in accel.into_iter().zip(direction) {
+                axis.store((value * 9.8).to_bits(), Ordering::Relaxed);
+            }
+        }
+        super::record_magnetometer(iface, raw).await;
+    }
+
+    #[tokio::test]
+    async fn calibrates_compass_and_reports_drift() {
+        use futures_util::StreamExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("magnetometer.toml");
+        let service = super::SensorsService::new().with_calibration_path(path.clone());
+        let conn = connection::Builder::session()
+            .unwrap()
+            .serve_at("/org/mobileos/Sensors", service)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let iface = conn
+            .object_server()
+            .interface::<_, super::SensorsService>("/org/mobileos/Sensors")
+            .await
+            .unwrap();
+        let client = Connection::session().await.unwrap();
+        let proxy = SensorsProxy::builder(&client)
+            .destination(conn.unique_name().unwrap().to_owned())
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        assert!(proxy.needs_calibration().await.unwrap());
+        proxy.start_calibration().await.unwrap();
+        assert!(proxy.finish_calibration().await.is_err());
+
+        // A figure eight: the field seen along every direction, off centre by a hard-iron offset
+        let offset = [0.3, -0.1, 0.05];
+        let directions: Vec<[f64; 3]> = (0..8)
+            .map(|octant| {
+                std::array::from_fn(|axis| if octant & 1 << axis != 0 { 0.5 } else { -0.5 })
+            })
+            .collect();
+        for &direction in &directions {
+            let raw = std::array::from_fn(|i| direction[i] + offset[i]);
+            turn(&iface, direction, raw).await;
+        }
+        assert_eq!(proxy.calibration_progress().await.unwrap(), 100);
+        proxy.finish_calibration().await.unwrap();
+        assert!(!proxy.needs_calibration().await.unwrap());
+        assert!(path.exists());
+
+        super::record_magnetometer(&iface, [0.8, 0.4, 0.55]).await;
+        assert!((proxy.magnetometer_x().await.unwrap() - 0.5).abs() < 1e-9);
+
+        // A magnet next to the phone shifts the field while it's turned around
+        let mut needed = proxy.receive_calibration_needed().await.unwrap();
+        for i in 0..200 {
+            let direction = directions[i % directions.len()];
+            let raw =
+                std::array::from_fn(|i| direction[i] + offset[i] + if i == 0 { 1.0 } else { 0.0 });
+            turn(&iface, direction, raw).await;
+        }
+        assert!(needed.next().await.is_some());
+        assert!(proxy.needs_calibration().await.unwrap());
+    }
 }
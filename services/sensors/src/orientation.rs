@@ -0,0 +1,90 @@
+// ABOUTME: Which edge of the screen is up, worked out from the accelerometer for auto-rotate.
+// ABOUTME: The last orientation holds through in-between angles and while the phone lies flat.
+
+/// Which edge of the screen is up, named as iio-sensor-proxy names them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Orientation {
+    #[default]
+    Normal,
+    LeftUp,
+    BottomUp,
+    RightUp,
+}
+
+impl Orientation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Orientation::Normal => "normal",
+            Orientation::LeftUp => "left-up",
+            Orientation::BottomUp => "bottom-up",
+            Orientation::RightUp => "right-up",
+        }
+    }
+}
+
+/// More of gravity than this along the axis out of the screen means the phone lies
+/// flat, and which edge is up means nothing.
+const FLAT: f64 = 0.8;
+
+/// How close, in degrees, the screen must be turned to an orientation for it to be
+/// taken. Less than 45, so in-between angles keep the last one rather than flicker.
+const CAPTURE: f64 = 30.0;
+
+/// The orientation for `gravity`, an accelerometer reading on the phone's axes (x to
+/// the right, y up the screen, z out of it, the axis pointing up reading +g), given it
+/// was `current`.
+pub fn orientation(gravity: [f64; 3], current: Orientation) -> Orientation {
+    let [x, y, z] = gravity;
+    let g = (x * x + y * y + z * z).sqrt();
+    if g < 1.0 || z.abs() > FLAT * g {
+        return current;
+    }
+    // How far the phone is turned clockwise from upright
+    let angle = (-x).atan2(y).to_degrees().rem_euclid(360.0);
+    let nearest = (angle / 90.0).round();
+    if (angle - nearest * 90.0).abs() > CAPTURE {
+        return current;
+    }
+    match nearest as u32 % 4 {
+        0 => Orientation::Normal,
+        1 => Orientation::LeftUp,
+        2 => Orientation::BottomUp,
+        _ => Orientation::RightUp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const G: f64 = 9.8;
+
+    #[test]
+    fn the_edge_pointing_up_is_up() {
+        let from = Orientation::Normal;
+        assert_eq!(orientation([0.0, G, 0.0], from), Orientation::Normal);
+        assert_eq!(orientation([-G, 0.0, 0.0], from), Orientation::LeftUp);
+        assert_eq!(orientation([0.0, -G, 0.0], from), Orientation::BottomUp);
+        assert_eq!(orientation([G, 0.0, 0.0], from), Orientation::RightUp);
+    }
+
+    #[test]
+    fn lying_flat_keeps_the_orientation() {
+        let from = Orientation::RightUp;
+        assert_eq!(orientation([0.0, 0.0, G], from), from);
+        assert_eq!(orientation([0.0, 1.0, -G], from), from);
+        assert_eq!(orientation([0.0, 0.0, 0.0], from), from);
+    }
+
+    #[test]
+    fn in_between_angles_keep_the_orientation() {
+        let diagonal = G / 2f64.sqrt();
+        let from = Orientation::Normal;
+        assert_eq!(orientation([-diagonal, diagonal, 0.0], from), from);
+
+        // 25 degrees short of left-up is close enough
+        let angle = 65f64.to_radians();
+        let tilted = [-G * angle.sin(), G * angle.cos(), 2.0];
+        assert_eq!(orientation(tilted, from), Orientation::LeftUp);
+    }
+}
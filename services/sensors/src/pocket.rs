@@ -0,0 +1,86 @@
+// ABOUTME: Pocket detection from the proximity sensor and ambient light.
+// ABOUTME: Something close and dark for a moment means the phone is in a pocket or bag.
+
+use std::time::{Duration, Instant};
+
+/// Darker than this, in lux, with something near the proximity sensor counts as covered.
+pub const DARK_LUX: u32 = 5;
+
+/// Lighter than this, in lux, ends pocket mode. Above DARK_LUX so that light hovering
+/// around the threshold doesn't toggle it.
+pub const LIGHT_LUX: u32 = 20;
+
+/// How long the sensors must stay covered. A hand passing over the phone, or a thumb
+/// resting on the sensor, doesn't last this long in the dark.
+pub const SETTLE: Duration = Duration::from_millis(800);
+
+#[derive(Debug, Default)]
+pub struct PocketDetector {
+    in_pocket: bool,
+    covered_since: Option<Instant>,
+}
+
+impl PocketDetector {
+    pub fn in_pocket(&self) -> bool {
+        self.in_pocket
+    }
+
+    /// Take a reading taken at `at`. Returns the new state when it changes. Leaving the
+    /// pocket takes effect straight away, so input is never held back once uncovered.
+    pub fn update(&mut self, near: bool, lux: u32, at: Instant) -> Option<bool> {
+        if self.in_pocket {
+            if near && lux < LIGHT_LUX {
+                return None;
+            }
+            self.in_pocket = false;
+            self.covered_since = None;
+            return Some(false);
+        }
+
+        if !near || lux >= DARK_LUX {
+            self.covered_since = None;
+            return None;
+        }
+        let since = *self.covered_since.get_or_insert(at);
+        if at.saturating_duration_since(since) < SETTLE {
+            return None;
+        }
+        self.in_pocket = true;
+        Some(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enters_after_settling_and_leaves_at_once() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut pocket = PocketDetector::default();
+
+        // A hand passing over the sensor
+        assert_eq!(pocket.update(true, 2, ms(0)), None);
+        assert_eq!(pocket.update(false, 2, ms(300)), None);
+        assert_eq!(pocket.update(true, 2, ms(900)), None);
+        assert!(!pocket.in_pocket());
+
+        // Near but in the light, as when held to the ear
+        assert_eq!(pocket.update(true, 300, ms(2000)), None);
+        assert_eq!(pocket.update(true, 300, ms(3000)), None);
+
+        assert_eq!(pocket.update(true, 1, ms(4000)), None);
+        assert_eq!(pocket.update(true, 1, ms(4800)), Some(true));
+        assert!(pocket.in_pocket());
+
+        // Some light through the fabric doesn't end it, daylight does
+        assert_eq!(pocket.update(true, 10, ms(5000)), None);
+        assert_eq!(pocket.update(true, 40, ms(5200)), Some(false));
+        assert!(!pocket.in_pocket());
+
+        assert_eq!(pocket.update(true, 1, ms(6000)), None);
+        assert_eq!(pocket.update(true, 1, ms(7000)), Some(true));
+        assert_eq!(pocket.update(false, 1, ms(7200)), Some(false));
+    }
+}
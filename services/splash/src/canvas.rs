@@ -0,0 +1,147 @@
+// ABOUTME: Software drawing of the splash into an XRGB8888 buffer: the logo and the progress bar.
+// ABOUTME: Sizes follow the screen, so the same splash fits any panel.
+
+/// Colors as 0xRRGGBB.
+pub const BACKGROUND: u32 = 0x10_10_20;
+pub const ACCENT: u32 = 0x4a_90_d9;
+pub const TRACK: u32 = 0x2a_2a_4a;
+
+/// A mapped scanout buffer, 4 bytes per pixel, `stride` bytes per row.
+pub struct Canvas<'a> {
+    pixels: &'a mut [u8],
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(pixels: &'a mut [u8], width: u32, height: u32, stride: u32) -> Self {
+        Self {
+            pixels,
+            width,
+            height,
+            stride,
+        }
+    }
+
+    /// Fill a rectangle, clipped to the canvas.
+    pub fn fill_rect(&mut self, x: u32, y: u32, w: u32, h: u32, color: u32) {
+        let right = x.saturating_add(w).min(self.width);
+        let bottom = y.saturating_add(h).min(self.height);
+        for row in y..bottom {
+            for col in x..right {
+                self.put(col, row, color);
+            }
+        }
+    }
+
+    /// Fill the ring between `inner` and `outer` radius around (cx, cy).
+    pub fn ring(&mut self, cx: u32, cy: u32, outer: u32, inner: u32, color: u32) {
+        let (outer_sq, inner_sq) = ((outer * outer) as i64, (inner * inner) as i64);
+        let top = cy.saturating_sub(outer);
+        let left = cx.saturating_sub(outer);
+        for row in top..(cy + outer).min(self.height) {
+            for col in left..(cx + outer).min(self.width) {
+                let (dx, dy) = (col as i64 - cx as i64, row as i64 - cy as i64);
+                let dist_sq = dx * dx + dy * dy;
+                if dist_sq < outer_sq && dist_sq >= inner_sq {
+                    self.put(col, row, color);
+                }
+            }
+        }
+    }
+
+    fn put(&mut self, x: u32, y: u32, color: u32) {
+        let offset = (y * self.stride + x * 4) as usize;
+        // XRGB8888 is little-endian: blue first
+        if let Some(pixel) = self.pixels.get_mut(offset..offset + 4) {
+            pixel.copy_from_slice(&color.to_le_bytes());
+        }
+    }
+}
+
+/// Where the progress bar goes: x, y, width, height.
+fn bar_area(width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let w = width / 2;
+    let h = (height / 120).max(4);
+    ((width - w) / 2, height * 7 / 10, w, h)
+}
+
+/// Clear the screen and draw the logo: a ring a little above the middle.
+pub fn draw_logo(canvas: &mut Canvas) {
+    let (width, height) = (canvas.width, canvas.height);
+    canvas.fill_rect(0, 0, width, height, BACKGROUND);
+    let outer = width.min(height) / 8;
+    canvas.ring(width / 2, height * 2 / 5, outer, outer * 3 / 4, ACCENT);
+}
+
+/// Draw the progress bar with `settled` of `total` services done.
+pub fn draw_progress(canvas: &mut Canvas, settled: usize, total: usize) {
+    let (x, y, w, h) = bar_area(canvas.width, canvas.height);
+    let filled = match total {
+        0 => 0,
+        _ => (w as u64 * settled.min(total) as u64 / total as u64) as u32,
+    };
+    canvas.fill_rect(x, y, filled, h, ACCENT);
+    canvas.fill_rect(x + filled, y, w - filled, h, TRACK);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 200;
+    const HEIGHT: u32 = 400;
+    // Rows padded past the visible width, as scanout buffers often are
+    const STRIDE: u32 = WIDTH * 4 + 32;
+
+    fn pixel(pixels: &[u8], x: u32, y: u32) -> u32 {
+        let offset = (y * STRIDE + x * 4) as usize;
+        u32::from_le_bytes(pixels[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn rectangles_are_clipped() {
+        let mut pixels = vec![0; (STRIDE * HEIGHT) as usize];
+        let mut canvas = Canvas::new(&mut pixels, WIDTH, HEIGHT, STRIDE);
+        canvas.fill_rect(190, 390, 50, 50, ACCENT);
+
+        assert_eq!(pixel(&pixels, 199, 399), ACCENT);
+        assert_eq!(pixel(&pixels, 189, 399), 0);
+        // Nothing spills into the row padding
+        let padding = (399 * STRIDE + WIDTH * 4) as usize..(400 * STRIDE) as usize;
+        assert!(pixels[padding].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn logo_is_a_ring() {
+        let mut pixels = vec![0; (STRIDE * HEIGHT) as usize];
+        draw_logo(&mut Canvas::new(&mut pixels, WIDTH, HEIGHT, STRIDE));
+
+        // The ring reaches 25 pixels out from (100, 160) and is 7 thick
+        assert_eq!(pixel(&pixels, 100, 160), BACKGROUND);
+        assert_eq!(pixel(&pixels, 100 + 22, 160), ACCENT);
+        assert_eq!(pixel(&pixels, 100, 160 - 22), ACCENT);
+        assert_eq!(pixel(&pixels, 100 + 30, 160), BACKGROUND);
+    }
+
+    #[test]
+    fn progress_bar_fills_in_proportion() {
+        let mut pixels = vec![0; (STRIDE * HEIGHT) as usize];
+        let mut canvas = Canvas::new(&mut pixels, WIDTH, HEIGHT, STRIDE);
+        // The bar runs from x 50 to 150 at y 280
+        draw_progress(&mut canvas, 1, 4);
+        assert_eq!(pixel(&pixels, 50, 280), ACCENT);
+        assert_eq!(pixel(&pixels, 74, 280), ACCENT);
+        assert_eq!(pixel(&pixels, 75, 280), TRACK);
+        assert_eq!(pixel(&pixels, 149, 280), TRACK);
+
+        let mut canvas = Canvas::new(&mut pixels, WIDTH, HEIGHT, STRIDE);
+        draw_progress(&mut canvas, 0, 0);
+        assert_eq!(pixel(&pixels, 50, 280), TRACK);
+
+        let mut canvas = Canvas::new(&mut pixels, WIDTH, HEIGHT, STRIDE);
+        draw_progress(&mut canvas, 5, 4);
+        assert_eq!(pixel(&pixels, 149, 280), ACCENT);
+    }
+}
@@ -0,0 +1,150 @@
+// ABOUTME: The splash's display: a dumb buffer scanned out on the first connected output of a DRM card.
+// ABOUTME: The splash draws straight into it, and lets go of DRM master for the compositor when it is stopped.
+
+use std::fs::File;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use drm::Device;
+use drm::buffer::{Buffer, DrmFourcc};
+use drm::control::dumbbuffer::DumbBuffer;
+use drm::control::{Device as ControlDevice, Mode, ModeTypeFlags, connector, crtc, framebuffer};
+use tracing::{info, warn};
+
+use crate::canvas::Canvas;
+
+pub const DRI_DIR: &str = "/dev/dri";
+
+struct Card(File);
+
+impl AsFd for Card {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl Device for Card {}
+impl ControlDevice for Card {}
+
+pub struct Display {
+    card: Card,
+    buffer: DumbBuffer,
+    framebuffer: framebuffer::Handle,
+}
+
+/// The output to show the splash on: the first connected connector, its preferred
+/// mode, and a CRTC that can drive it.
+fn pick_output(card: &Card) -> Result<(connector::Handle, Mode, crtc::Handle)> {
+    let resources = card
+        .resource_handles()
+        .context("failed to get DRM resources")?;
+    for &handle in resources.connectors() {
+        let info = card.get_connector(handle, false)?;
+        if info.state() != connector::State::Connected {
+            continue;
+        }
+        let Some(&mode) = info
+            .modes()
+            .iter()
+            .find(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
+            .or(info.modes().first())
+        else {
+            continue;
+        };
+        // Keep the CRTC the firmware lit the panel with, if there is one
+        let current = info
+            .current_encoder()
+            .and_then(|encoder| card.get_encoder(encoder).ok()?.crtc());
+        let possible = info.encoders().iter().find_map(|&encoder| {
+            let encoder = card.get_encoder(encoder).ok()?;
+            resources
+                .filter_crtcs(encoder.possible_crtcs())
+                .first()
+                .copied()
+        });
+        if let Some(crtc) = current.or(possible) {
+            return Ok((handle, mode, crtc));
+        }
+    }
+    bail!("no connected display")
+}
+
+impl Display {
+    /// Light up the first connected output of `path`, a DRM card, with a blank frame.
+    /// Opening the card first makes the splash DRM master.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let card = Card(file);
+        let (connector, mode, crtc) = pick_output(&card)?;
+
+        let (width, height) = mode.size();
+        let buffer = card
+            .create_dumb_buffer((width.into(), height.into()), DrmFourcc::Xrgb8888, 32)
+            .context("failed to create dumb buffer")?;
+        let framebuffer = card
+            .add_framebuffer(&buffer, 24, 32)
+            .context("failed to add framebuffer")?;
+        card.set_crtc(crtc, Some(framebuffer), (0, 0), &[connector], Some(mode))
+            .context("failed to set mode")?;
+        info!(
+            card = %path.display(),
+            width,
+            height,
+            "showing splash"
+        );
+
+        Ok(Self {
+            card,
+            buffer,
+            framebuffer,
+        })
+    }
+
+    /// Paint the frame on screen.
+    pub fn draw(&mut self, paint: impl FnOnce(&mut Canvas)) -> Result<()> {
+        let (width, height) = self.buffer.size();
+        let stride = self.buffer.pitch();
+        let mut mapping = self
+            .card
+            .map_dumb_buffer(&mut self.buffer)
+            .context("failed to map dumb buffer")?;
+        paint(&mut Canvas::new(&mut mapping, width, height, stride));
+        drop(mapping);
+        // Panels that don't scan out continuously only update when told to; the rest
+        // don't implement it
+        let _ = self.card.dirty_framebuffer(self.framebuffer, &[]);
+        Ok(())
+    }
+
+    /// Give up DRM master so the compositor can take the display. The frame goes with
+    /// the splash when it exits.
+    pub fn release(self) {
+        if let Err(e) = self.card.release_master_lock() {
+            warn!(error = %e, "failed to drop DRM master");
+        }
+        info!("handed the display over");
+    }
+}
+
+/// Show the splash on the first card under `dir` with a connected display.
+pub fn open_from_system(dir: &Path) -> Result<Display> {
+    let mut cards: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("card"))
+        .map(|entry| entry.path())
+        .collect();
+    cards.sort();
+    for card in &cards {
+        match Display::open(card) {
+            Ok(display) => return Ok(display),
+            Err(e) => warn!(card = %card.display(), error = %e, "can't show splash"),
+        }
+    }
+    bail!("no DRM card with a connected display")
+}
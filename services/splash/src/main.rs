@@ -0,0 +1,81 @@
+// ABOUTME: Boot splash for MobileOS: the logo and boot progress on the display until the compositor starts.
+// ABOUTME: Asks initd how far boot has got; initd stops it before starting the compositor, which conflicts with it.
+
+mod canvas;
+mod display;
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+const CONTROL_SOCKET: &str = "/run/mos/initctl";
+
+/// How often initd is asked for boot progress.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Ask initd how many services have settled, out of how many are booting. None while
+/// it can't answer, e.g. before its control socket is up.
+fn boot_progress(socket: &Path) -> Option<(usize, usize)> {
+    let mut stream = UnixStream::connect(socket).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(1))).ok()?;
+    stream.write_all(b"boot-progress\n").ok()?;
+    stream.shutdown(std::net::Shutdown::Write).ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    parse_progress(&response)
+}
+
+fn parse_progress(response: &str) -> Option<(usize, usize)> {
+    let (settled, total) = response.trim().split_once(' ')?;
+    Some((settled.parse().ok()?, total.parse().ok()?))
+}
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGTERM, Arc::clone(&stop))?;
+    signal_hook::flag::register(SIGINT, Arc::clone(&stop))?;
+
+    let mut display = display::open_from_system(Path::new(display::DRI_DIR))?;
+    display.draw(canvas::draw_logo)?;
+
+    let mut shown = None;
+    while !stop.load(Ordering::Relaxed) {
+        if let Some((settled, total)) = boot_progress(Path::new(CONTROL_SOCKET))
+            && shown != Some((settled, total))
+        {
+            display.draw(|canvas| canvas::draw_progress(canvas, settled, total))?;
+            shown = Some((settled, total));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    display.release();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_is_settled_then_total() {
+        assert_eq!(parse_progress("3 12\n"), Some((3, 12)));
+        assert_eq!(
+            parse_progress("error: unknown request 'boot-progress'\n"),
+            None
+        );
+        assert_eq!(parse_progress(""), None);
+    }
+}
@@ -0,0 +1,197 @@
+// ABOUTME: The limits the user set themselves: a daily timer per app, and a bedtime when the screen goes gray.
+// ABOUTME: Kept in /data/wellbeing/limits.toml.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{Context, Result};
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+use crate::usage::Day;
+
+pub const LIMITS_PATH: &str = "/data/wellbeing/limits.toml";
+
+/// How bedtime times are written on the bus: "22:30".
+const CLOCK_FORMAT: &str = "%H:%M";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bedtime {
+    #[serde(default)]
+    pub enabled: bool,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl Default for Bedtime {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start: NaiveTime::from_hms_opt(22, 0, 0).unwrap_or_default(),
+            end: NaiveTime::from_hms_opt(7, 0, 0).unwrap_or_default(),
+        }
+    }
+}
+
+impl Bedtime {
+    /// Bedtime from times written as "22:30".
+    pub fn parse(enabled: bool, start: &str, end: &str) -> Result<Self> {
+        let clock = |time: &str| {
+            NaiveTime::parse_from_str(time, CLOCK_FORMAT)
+                .with_context(|| format!("'{time}' is not a time of day like 22:30"))
+        };
+        Ok(Self {
+            enabled,
+            start: clock(start)?,
+            end: clock(end)?,
+        })
+    }
+
+    pub fn start(&self) -> String {
+        self.start.format(CLOCK_FORMAT).to_string()
+    }
+
+    pub fn end(&self) -> String {
+        self.end.format(CLOCK_FORMAT).to_string()
+    }
+
+    /// Whether `now` falls in bedtime, which mostly runs past midnight.
+    pub fn covers(&self, now: NaiveTime) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.start <= self.end {
+            self.start <= now && now < self.end
+        } else {
+            now >= self.start || now < self.end
+        }
+    }
+}
+
+/// As stored:
+///
+/// ```toml
+/// hard = ["games"]
+///
+/// [timers]
+/// browser = 60
+/// games = 30
+///
+/// [bedtime]
+/// enabled = true
+/// start = "22:30:00"
+/// end = "07:00:00"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    /// Minutes a day each app may be on screen.
+    #[serde(default)]
+    pub timers: BTreeMap<String, u32>,
+    /// Apps that can't be opened once their timer runs out, rather than only being
+    /// grayed.
+    #[serde(default)]
+    pub hard: BTreeSet<String>,
+    #[serde(default)]
+    pub bedtime: Bedtime,
+}
+
+impl Limits {
+    /// Set `app`'s daily timer, `hard` if it blocks the app once run out, or clear it
+    /// with 0 minutes.
+    pub fn set_timer(&mut self, app: &str, minutes: u32, hard: bool) {
+        if minutes == 0 {
+            self.timers.remove(app);
+        } else {
+            self.timers.insert(app.to_string(), minutes);
+        }
+        if minutes > 0 && hard {
+            self.hard.insert(app.to_string());
+        } else {
+            self.hard.remove(app);
+        }
+    }
+
+    /// The apps whose timer has run out on `day`.
+    pub fn times_up(&self, day: Option<&Day>) -> Vec<String> {
+        self.timers
+            .iter()
+            .filter(|(app, minutes)| {
+                day.is_some_and(|day| day.seconds(app) >= u64::from(**minutes) * 60)
+            })
+            .map(|(app, _)| app.clone())
+            .collect()
+    }
+
+    /// Of the apps out of time, those that can't be opened.
+    pub fn blocked(&self, times_up: &[String]) -> Vec<String> {
+        times_up
+            .iter()
+            .filter(|app| self.hard.contains(*app))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::usage::Usage;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn bedtime_runs_past_midnight() {
+        let bedtime = Bedtime::parse(true, "22:30", "07:00").unwrap();
+        assert!(bedtime.covers(time(23, 0)));
+        assert!(bedtime.covers(time(3, 0)));
+        assert!(!bedtime.covers(time(7, 0)));
+        assert!(!bedtime.covers(time(12, 0)));
+        assert_eq!(bedtime.start(), "22:30");
+
+        let nap = Bedtime::parse(true, "13:00", "14:00").unwrap();
+        assert!(nap.covers(time(13, 30)));
+        assert!(!nap.covers(time(23, 0)));
+
+        let off = Bedtime::parse(false, "22:30", "07:00").unwrap();
+        assert!(!off.covers(time(23, 0)));
+    }
+
+    #[test]
+    fn bedtime_needs_times_of_day() {
+        assert!(Bedtime::parse(true, "25:00", "07:00").is_err());
+        assert!(Bedtime::parse(true, "22:00", "soon").is_err());
+    }
+
+    #[test]
+    fn timers_run_out_on_the_day_they_are_used_up() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let mut limits = Limits::default();
+        limits.set_timer("browser", 30, false);
+        limits.set_timer("messages", 60, false);
+        let mut usage = Usage::default();
+        usage.add_time(today, "browser", 30 * 60);
+        usage.add_time(today, "messages", 59 * 60);
+
+        assert_eq!(limits.times_up(usage.day(today)), ["browser"]);
+        assert!(limits.times_up(None).is_empty());
+
+        limits.set_timer("browser", 0, false);
+        assert!(limits.times_up(usage.day(today)).is_empty());
+    }
+
+    #[test]
+    fn only_hard_timers_block() {
+        let mut limits = Limits::default();
+        limits.set_timer("browser", 30, false);
+        limits.set_timer("games", 30, true);
+        let times_up = ["browser".to_string(), "games".to_string()];
+        assert_eq!(limits.blocked(&times_up), ["games"]);
+
+        limits.set_timer("games", 0, true);
+        assert!(limits.hard.is_empty());
+        assert!(limits.blocked(&times_up).is_empty());
+    }
+}
@@ -0,0 +1,609 @@
+// ABOUTME: Digital wellbeing D-Bus daemon for MobileOS: screen time, unlocks and notifications per app and day.
+// ABOUTME: Serves org.mobileos.Wellbeing, with daily app timers and a bedtime that turns the screen gray, behind the owner's PIN.
+
+mod limits;
+mod pin;
+mod store;
+mod usage;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, NaiveDateTime};
+use futures_util::StreamExt;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use zbus::object_server::{InterfaceRef, SignalEmitter};
+use zbus::{connection, interface, proxy};
+
+use limits::{Bedtime, Limits};
+use pin::PinStore;
+use usage::Usage;
+
+/// How often the app on screen has its time counted and saved, timers are checked, and
+/// bedtime starts or ends.
+const TICK: Duration = Duration::from_secs(60);
+
+/// The most one stretch on screen counts for, in seconds. Time is counted at least
+/// every tick, so only a clock set forward gets near it.
+const MAX_STRETCH: i64 = 2 * 60;
+
+/// An app's day on the bus: app, seconds on screen and notifications.
+type AppUsage = (String, u64, u32);
+
+/// An app's timer on the bus: minutes a day, and whether it blocks the app once run out.
+type AppTimer = (u32, bool);
+
+struct WellbeingService {
+    usage: Usage,
+    limits: Limits,
+    usage_path: PathBuf,
+    limits_path: PathBuf,
+    /// Guards the timers and bedtime, once the owner sets it.
+    pin: PinStore,
+    /// The app on screen, and when its time was last counted.
+    on_screen: Option<(String, NaiveDateTime)>,
+    /// Usage changed since it was last saved.
+    dirty: bool,
+    times_up: Vec<String>,
+    bedtime_active: bool,
+    /// Woken when bedtime starts or ends, to tell the compositor.
+    bedtime_changed: Arc<Notify>,
+}
+
+impl WellbeingService {
+    fn new(usage_path: PathBuf, limits_path: PathBuf, pin_path: PathBuf) -> Self {
+        Self {
+            usage: store::load(&usage_path),
+            limits: store::load(&limits_path),
+            usage_path,
+            limits_path,
+            pin: PinStore::new(pin_path),
+            on_screen: None,
+            dirty: false,
+            times_up: Vec::new(),
+            bedtime_active: false,
+            bedtime_changed: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Count the time of the app on screen up to `now`.
+    fn count_time(&mut self, now: NaiveDateTime) {
+        let Some((app, since)) = &mut self.on_screen else {
+            return;
+        };
+        let seconds = (now - *since).num_seconds().clamp(0, MAX_STRETCH);
+        if seconds > 0 {
+            self.usage.add_time(now.date(), app, seconds as u64);
+            self.dirty = true;
+        }
+        *since = now;
+    }
+
+    /// `app` came on screen at `now`, or nothing did if it is empty.
+    fn set_on_screen(&mut self, app: &str, now: NaiveDateTime) {
+        self.count_time(now);
+        self.on_screen = (!app.is_empty()).then(|| (app.to_string(), now));
+    }
+
+    /// Check the timers and bedtime at `now`. Says whether the apps out of time, and
+    /// whether bedtime, changed.
+    fn check_limits(&mut self, now: NaiveDateTime) -> (bool, bool) {
+        let times_up = self.limits.times_up(self.usage.day(now.date()));
+        let bedtime_active = self.limits.bedtime.covers(now.time());
+        let changed = (
+            times_up != self.times_up,
+            bedtime_active != self.bedtime_active,
+        );
+        self.times_up = times_up;
+        self.bedtime_active = bedtime_active;
+        changed
+    }
+
+    /// Count time up to now, check the limits, and tell clients what changed.
+    async fn refresh(&mut self, emitter: &SignalEmitter<'_>) {
+        let now = Local::now().naive_local();
+        self.count_time(now);
+        let (times_up, bedtime) = self.check_limits(now);
+        let mut result = Ok(());
+        if times_up {
+            info!(apps = ?self.times_up, "apps out of time today");
+            result = self.times_up_changed(emitter).await;
+            result = result.and(self.blocked_changed(emitter).await);
+        }
+        if bedtime {
+            info!(active = self.bedtime_active, "bedtime");
+            self.bedtime_changed.notify_one();
+            result = result.and(self.bedtime_active_changed(emitter).await);
+        }
+        if let Err(e) = result {
+            warn!(error = %e, "failed to emit wellbeing change");
+        }
+    }
+
+    fn save_usage(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        match store::save(&self.usage, &self.usage_path) {
+            Ok(()) => self.dirty = false,
+            Err(e) => warn!(error = %e, "failed to save usage"),
+        }
+    }
+
+    fn save_limits(&self) -> zbus::fdo::Result<()> {
+        store::save(&self.limits, &self.limits_path).map_err(failed)
+    }
+
+    /// Refuse a change to the limits without the owner's PIN, once there is one.
+    fn check_pin(&mut self, pin: &str) -> zbus::fdo::Result<()> {
+        self.pin.check(pin, Instant::now()).map_err(|e| {
+            warn!(error = %e, "screen time change refused");
+            zbus::fdo::Error::AccessDenied(format!("{e:#}"))
+        })
+    }
+}
+
+fn failed(e: anyhow::Error) -> zbus::fdo::Error {
+    zbus::fdo::Error::Failed(format!("{e:#}"))
+}
+
+#[interface(name = "org.mobileos.Wellbeing")]
+impl WellbeingService {
+    /// Today so far: how often the phone was unlocked, and for each app its seconds on
+    /// screen and notifications, most used first.
+    async fn today(
+        &mut self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> (u32, Vec<AppUsage>) {
+        self.refresh(&emitter).await;
+        let Some(day) = self.usage.day(Local::now().date_naive()) else {
+            return (0, Vec::new());
+        };
+        let mut apps: Vec<AppUsage> = day
+            .apps
+            .iter()
+            .map(|(app, usage)| (app.clone(), usage.seconds, usage.notifications))
+            .collect();
+        apps.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        (day.unlocks, apps)
+    }
+
+    /// Seconds of screen time on each of the last seven days, oldest first, by date
+    /// as "2026-10-18".
+    async fn week(
+        &mut self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> Vec<(String, u64)> {
+        self.refresh(&emitter).await;
+        self.usage
+            .week(Local::now().date_naive())
+            .into_iter()
+            .map(|(date, seconds)| (date.to_string(), seconds))
+            .collect()
+    }
+
+    /// For each app with a timer, the minutes a day it may be on screen, and whether it
+    /// is hard, blocking the app once run out.
+    #[zbus(property)]
+    fn app_timers(&self) -> HashMap<String, AppTimer> {
+        self.limits
+            .timers
+            .iter()
+            .map(|(app, minutes)| (app.clone(), (*minutes, self.limits.hard.contains(app))))
+            .collect()
+    }
+
+    /// Give `app` a daily timer of `minutes`, or take it away with 0. Once it runs out,
+    /// the app is in TimesUp until midnight, and in Blocked too if the timer is `hard`.
+    /// Takes the owner's PIN once one is set.
+    async fn set_app_timer(
+        &mut self,
+        app: &str,
+        minutes: u32,
+        hard: bool,
+        pin: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.check_pin(pin)?;
+        info!(app, minutes, hard, "app timer set");
+        self.limits.set_timer(app, minutes, hard);
+        self.save_limits()?;
+        self.app_timers_changed(&emitter).await?;
+        self.blocked_changed(&emitter).await?;
+        self.refresh(&emitter).await;
+        Ok(())
+    }
+
+    /// The apps whose timer has run out today. The home screen grays their icons.
+    #[zbus(property)]
+    fn times_up(&self) -> Vec<String> {
+        self.times_up.clone()
+    }
+
+    /// The apps out of time with a hard timer. The launcher won't open them until
+    /// midnight.
+    #[zbus(property)]
+    fn blocked(&self) -> Vec<String> {
+        self.limits.blocked(&self.times_up)
+    }
+
+    /// Whether bedtime is on, and when it starts and ends, as "22:30".
+    #[zbus(property)]
+    fn bedtime(&self) -> (bool, String, String) {
+        let bedtime = &self.limits.bedtime;
+        (bedtime.enabled, bedtime.start(), bedtime.end())
+    }
+
+    /// Turn bedtime on from `start` to `end`, times of day as "22:30", or off. During
+    /// bedtime the screen is gray. Takes the owner's PIN once one is set.
+    async fn set_bedtime(
+        &mut self,
+        enabled: bool,
+        start: &str,
+        end: &str,
+        pin: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.check_pin(pin)?;
+        let bedtime = Bedtime::parse(enabled, start, end)
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e:#}")))?;
+        info!(enabled, start, end, "bedtime set");
+        self.limits.bedtime = bedtime;
+        self.save_limits()?;
+        self.bedtime_changed(&emitter).await?;
+        self.refresh(&emitter).await;
+        Ok(())
+    }
+
+    /// Whether it is bedtime now.
+    #[zbus(property)]
+    fn bedtime_active(&self) -> bool {
+        self.bedtime_active
+    }
+
+    /// Whether the owner has set a PIN, which changes to the timers and bedtime take.
+    #[zbus(property)]
+    fn pin_set(&self) -> bool {
+        self.pin.is_set()
+    }
+
+    /// Set the owner's PIN to `new_pin`, 4 to 8 digits, or take it away with "". Takes
+    /// the current `pin` if there is one.
+    async fn set_pin(
+        &mut self,
+        pin: &str,
+        new_pin: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.check_pin(pin)?;
+        if new_pin.is_empty() {
+            info!("screen time PIN removed");
+            self.pin.clear().map_err(failed)?;
+        } else {
+            info!("screen time PIN set");
+            self.pin
+                .set(new_pin)
+                .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e:#}")))?;
+        }
+        self.pin_set_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// Called by the notification daemon for each notification an app posts.
+    fn notification_posted(&mut self, app: &str) {
+        self.usage
+            .count_notification(Local::now().date_naive(), app);
+        self.dirty = true;
+    }
+}
+
+#[proxy(
+    interface = "org.mobileos.Compositor",
+    default_service = "org.mobileos.Compositor",
+    default_path = "/org/mobileos/Compositor"
+)]
+trait Compositor {
+    #[zbus(property)]
+    fn foreground_app(&self) -> zbus::Result<String>;
+
+    fn set_bedtime(&self, on: bool) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn foreground_changed(&self, app_id: String) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn unlocked(&self) -> zbus::Result<()>;
+}
+
+/// Count the time of the app on screen, and check the limits, once a tick.
+async fn tick(iface: InterfaceRef<WellbeingService>) {
+    let mut ticks = tokio::time::interval(TICK);
+    loop {
+        ticks.tick().await;
+        let mut service = iface.get_mut().await;
+        service.refresh(iface.signal_emitter()).await;
+        service.save_usage();
+    }
+}
+
+/// Follow the compositor's app on screen and unlocks.
+async fn watch_compositor(
+    compositor: CompositorProxy<'static>,
+    iface: InterfaceRef<WellbeingService>,
+) -> zbus::Result<()> {
+    let mut foreground = compositor.receive_foreground_changed().await?;
+    let mut unlocks = compositor.receive_unlocked().await?;
+    // The compositor may not be up yet; its signals arrive once it is
+    let app = compositor.foreground_app().await.unwrap_or_default();
+    let now = Local::now().naive_local();
+    iface.get_mut().await.set_on_screen(&app, now);
+    loop {
+        tokio::select! {
+            Some(change) = foreground.next() => {
+                let app = change.args()?.app_id;
+                iface
+                    .get_mut()
+                    .await
+                    .set_on_screen(&app, Local::now().naive_local());
+            }
+            Some(_) = unlocks.next() => {
+                let mut service = iface.get_mut().await;
+                service.usage.count_unlock(Local::now().date_naive());
+                service.dirty = true;
+            }
+            else => return Ok(()),
+        }
+    }
+}
+
+/// Gray the screen, or bring its colors back, as bedtime starts and ends.
+async fn drive_bedtime(
+    compositor: CompositorProxy<'static>,
+    iface: InterfaceRef<WellbeingService>,
+) {
+    let changed = iface.get().await.bedtime_changed.clone();
+    loop {
+        changed.notified().await;
+        let on = iface.get().await.bedtime_active;
+        if let Err(e) = compositor.set_bedtime(on).await {
+            warn!(on, error = %e, "failed to set bedtime on the screen");
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    info!("starting wellbeing service");
+
+    let service = WellbeingService::new(
+        PathBuf::from(usage::USAGE_PATH),
+        PathBuf::from(limits::LIMITS_PATH),
+        PathBuf::from(pin::PIN_PATH),
+    );
+
+    let connection = connection::Builder::system()?
+        .name("org.mobileos.Wellbeing")?
+        .serve_at("/org/mobileos/Wellbeing", service)?
+        .build()
+        .await?;
+
+    let iface = connection
+        .object_server()
+        .interface::<_, WellbeingService>("/org/mobileos/Wellbeing")
+        .await?;
+    let compositor = CompositorProxy::builder(&connection)
+        .cache_properties(zbus::proxy::CacheProperties::No)
+        .build()
+        .await?;
+    tokio::spawn(tick(iface.clone()));
+    tokio::spawn(drive_bedtime(compositor.clone(), iface.clone()));
+    tokio::spawn(async move {
+        if let Err(e) = watch_compositor(compositor, iface).await {
+            warn!(error = %e, "lost the compositor, screen time not counted");
+        }
+    });
+
+    info!("wellbeing service running on system bus");
+    mos_notify::ready();
+
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{Local, NaiveDate, NaiveDateTime};
+    use zbus::{Connection, connection, proxy};
+
+    use super::{AppTimer, AppUsage, WellbeingService};
+
+    #[proxy(
+        interface = "org.mobileos.Wellbeing",
+        default_path = "/org/mobileos/Wellbeing"
+    )]
+    trait Wellbeing {
+        fn today(&self) -> zbus::Result<(u32, Vec<AppUsage>)>;
+        fn week(&self) -> zbus::Result<Vec<(String, u64)>>;
+
+        #[zbus(property)]
+        fn app_timers(&self) -> zbus::Result<HashMap<String, AppTimer>>;
+
+        fn set_app_timer(&self, app: &str, minutes: u32, hard: bool, pin: &str)
+        -> zbus::Result<()>;
+
+        #[zbus(property)]
+        fn times_up(&self) -> zbus::Result<Vec<String>>;
+
+        #[zbus(property)]
+        fn blocked(&self) -> zbus::Result<Vec<String>>;
+
+        #[zbus(property)]
+        fn bedtime(&self) -> zbus::Result<(bool, String, String)>;
+
+        fn set_bedtime(&self, enabled: bool, start: &str, end: &str, pin: &str)
+        -> zbus::Result<()>;
+
+        #[zbus(property)]
+        fn pin_set(&self) -> zbus::Result<bool>;
+
+        fn set_pin(&self, pin: &str, new_pin: &str) -> zbus::Result<()>;
+        fn notification_posted(&self, app: &str) -> zbus::Result<()>;
+    }
+
+    fn at(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 18)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap()
+    }
+
+    fn service(dir: &tempfile::TempDir) -> WellbeingService {
+        WellbeingService::new(
+            dir.path().join("usage.toml"),
+            dir.path().join("limits.toml"),
+            dir.path().join("pin"),
+        )
+    }
+
+    async fn serve(service: WellbeingService) -> (Connection, WellbeingProxy<'static>) {
+        let conn = connection::Builder::session()
+            .unwrap()
+            .serve_at("/org/mobileos/Wellbeing", service)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let client = Connection::session().await.unwrap();
+        let proxy = WellbeingProxy::builder(&client)
+            .destination(conn.unique_name().unwrap().to_owned())
+            .unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+        (conn, proxy)
+    }
+
+    #[test]
+    fn time_counts_for_the_app_on_screen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut service = service(&dir);
+        service.set_on_screen("browser", at(9, 0, 0));
+        service.count_time(at(9, 0, 40));
+        service.set_on_screen("messages", at(9, 1, 0));
+        service.set_on_screen("", at(9, 1, 30));
+        service.count_time(at(9, 5, 0));
+
+        let day = service.usage.day(at(9, 0, 0).date()).unwrap();
+        assert_eq!(day.seconds("browser"), 60);
+        assert_eq!(day.seconds("messages"), 30);
+        assert_eq!(day.screen_time(), 90);
+    }
+
+    #[test]
+    fn a_clock_set_forward_adds_little() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut service = service(&dir);
+        service.set_on_screen("browser", at(9, 0, 0));
+        service.count_time(at(15, 0, 0));
+        service.count_time(at(14, 0, 0));
+
+        let day = service.usage.day(at(9, 0, 0).date()).unwrap();
+        assert_eq!(day.seconds("browser"), super::MAX_STRETCH as u64);
+    }
+
+    #[test]
+    fn timers_and_bedtime_are_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut service = service(&dir);
+        service.limits.set_timer("browser", 1, false);
+        service.limits.bedtime = super::Bedtime::parse(true, "22:00", "07:00").unwrap();
+        service.set_on_screen("browser", at(21, 59, 0));
+
+        assert_eq!(service.check_limits(at(21, 59, 30)), (false, false));
+        service.count_time(at(22, 0, 0));
+        assert_eq!(service.check_limits(at(22, 0, 0)), (true, true));
+        assert_eq!(service.times_up, ["browser"]);
+        assert!(service.bedtime_active);
+        assert_eq!(service.check_limits(at(22, 1, 0)), (false, false));
+    }
+
+    #[tokio::test]
+    async fn notifications_are_counted_for_today() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_conn, proxy) = serve(service(&dir)).await;
+
+        proxy.notification_posted("messages").await.unwrap();
+        proxy.notification_posted("messages").await.unwrap();
+
+        let (unlocks, apps) = proxy.today().await.unwrap();
+        assert_eq!(unlocks, 0);
+        assert_eq!(apps, [("messages".to_string(), 0, 2)]);
+
+        let week = proxy.week().await.unwrap();
+        assert_eq!(week.len(), 7);
+        assert_eq!(week[6].0, Local::now().date_naive().to_string());
+    }
+
+    #[tokio::test]
+    async fn limits_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (conn, proxy) = serve(service(&dir)).await;
+
+        proxy.set_app_timer("browser", 45, true, "").await.unwrap();
+        assert!(proxy.set_bedtime(true, "22:30", "late", "").await.is_err());
+        proxy.set_bedtime(true, "22:30", "06:45", "").await.unwrap();
+        assert_eq!(proxy.app_timers().await.unwrap()["browser"], (45, true));
+        assert!(proxy.times_up().await.unwrap().is_empty());
+        assert!(proxy.blocked().await.unwrap().is_empty());
+        drop(conn);
+
+        // Another start finds them where they were left
+        let (_conn, proxy) = serve(service(&dir)).await;
+        assert_eq!(proxy.app_timers().await.unwrap()["browser"], (45, true));
+        assert_eq!(
+            proxy.bedtime().await.unwrap(),
+            (true, "22:30".to_string(), "06:45".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn the_pin_guards_the_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_conn, proxy) = serve(service(&dir)).await;
+
+        assert!(!proxy.pin_set().await.unwrap());
+        assert!(proxy.set_pin("", "12").await.is_err());
+        proxy.set_pin("", "2468").await.unwrap();
+        assert!(proxy.pin_set().await.unwrap());
+
+        let refused = proxy.set_app_timer("games", 30, true, "1357").await;
+        assert!(matches!(refused, Err(zbus::Error::MethodError(name, _, _))
+            if name.as_str() == "org.freedesktop.DBus.Error.AccessDenied"));
+        assert!(proxy.set_bedtime(false, "", "", "").await.is_err());
+        assert!(proxy.set_pin("", "").await.is_err());
+        assert!(proxy.app_timers().await.unwrap().is_empty());
+
+        proxy
+            .set_app_timer("games", 30, true, "2468")
+            .await
+            .unwrap();
+        assert_eq!(proxy.app_timers().await.unwrap()["games"], (30, true));
+        proxy.set_pin("2468", "").await.unwrap();
+        assert!(!proxy.pin_set().await.unwrap());
+        proxy.set_app_timer("games", 0, false, "").await.unwrap();
+        assert!(proxy.app_timers().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+// ABOUTME: The owner's PIN guarding screen time settings, stored salted and hashed under /data/wellbeing.
+// ABOUTME: Without one anybody may change them; with one, timers and bedtime only change for the PIN.
+
+use std::io::Read;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+
+pub const PIN_PATH: &str = "/data/wellbeing/pin";
+
+/// Wrong PINs in a row before tries are refused for a while.
+const MAX_TRIES: u32 = 5;
+
+/// How long tries are refused after too many wrong PINs, so a child can't run
+/// through them all.
+const LOCKOUT: Duration = Duration::from_secs(60);
+
+pub struct PinStore {
+    path: PathBuf,
+    wrong_tries: u32,
+    locked_until: Option<Instant>,
+}
+
+impl PinStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            wrong_tries: 0,
+            locked_until: None,
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Store `pin`, 4 to 8 digits, replacing any earlier one.
+    pub fn set(&self, pin: &str) -> Result<()> {
+        if !(4..=8).contains(&pin.len()) || !pin.bytes().all(|b| b.is_ascii_digit()) {
+            bail!("a PIN is 4 to 8 digits");
+        }
+        let mut salt = [0u8; 16];
+        std::fs::File::open("/dev/urandom")
+            .and_then(|mut random| random.read_exact(&mut salt))
+            .context("failed to generate a salt")?;
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        let content = format!("{}:{}\n", hex(&salt), hex(&hash(&salt, pin)));
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, content.as_bytes()))
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed to replace {}", self.path.display()))
+    }
+
+    /// Take the PIN away, leaving the settings open to anybody.
+    pub fn clear(&self) -> Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("failed to remove {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check `pin` at `now`, which passes for anything while no PIN is set. After too
+    /// many wrong ones in a row, even the right one fails for a while.
+    pub fn check(&mut self, pin: &str, now: Instant) -> Result<()> {
+        if !self.is_set() {
+            return Ok(());
+        }
+        if self.locked_until.is_some_and(|until| now < until) {
+            bail!("too many wrong PINs, try again in a minute");
+        }
+        if self.verify(pin) {
+            self.wrong_tries = 0;
+            self.locked_until = None;
+            return Ok(());
+        }
+        self.wrong_tries += 1;
+        if self.wrong_tries >= MAX_TRIES {
+            self.wrong_tries = 0;
+            self.locked_until = Some(now + LOCKOUT);
+        }
+        bail!("wrong PIN")
+    }
+
+    fn verify(&self, pin: &str) -> bool {
+        let Ok(content) = std::fs::read_to_string(&self.path) else {
+            return false;
+        };
+        let Some((salt, stored)) = content.trim().split_once(':') else {
+            return false;
+        };
+        let Some(salt) = unhex(salt) else {
+            return false;
+        };
+        hex(&hash(&salt, pin)) == stored
+    }
+}
+
+fn hash(salt: &[u8], pin: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(pin.as_bytes());
+    hasher.finalize().into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anything_passes_until_a_pin_is_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut pins = PinStore::new(dir.path().join("pin"));
+        let now = Instant::now();
+        assert!(pins.check("", now).is_ok());
+
+        assert!(pins.set("12").is_err());
+        assert!(pins.set("12ab").is_err());
+        pins.set("2468").unwrap();
+        assert!(pins.check("2468", now).is_ok());
+        assert!(pins.check("1357", now).is_err());
+
+        pins.clear().unwrap();
+        assert!(!pins.is_set());
+        assert!(pins.check("", now).is_ok());
+        pins.clear().unwrap();
+    }
+
+    #[test]
+    fn too_many_wrong_pins_lock_tries_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut pins = PinStore::new(dir.path().join("pin"));
+        pins.set("2468").unwrap();
+        let now = Instant::now();
+        for _ in 0..MAX_TRIES {
+            assert!(pins.check("0000", now).is_err());
+        }
+        assert!(pins.check("2468", now).is_err());
+        assert!(pins.check("2468", now + LOCKOUT).is_ok());
+    }
+}
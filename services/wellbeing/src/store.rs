@@ -0,0 +1,39 @@
+// ABOUTME: Reads and writes the wellbeing service's TOML files under /data/wellbeing.
+// ABOUTME: A file that is missing or can't be read gives the defaults; writes replace the file whole.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tracing::warn;
+
+/// What is stored at `path`, or the default if there is nothing there yet or it can't
+/// be read.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return T::default(),
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "failed to read");
+            return T::default();
+        }
+    };
+    toml::from_str(&content).unwrap_or_else(|e| {
+        warn!(path = %path.display(), error = %e, "invalid, starting over");
+        T::default()
+    })
+}
+
+/// Store `value` at `path`, through a temporary file so a crash leaves the old one.
+pub fn save<T: Serialize>(value: &T, path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let content = toml::to_string(value)
+        .with_context(|| format!("failed to serialize {}", path.display()))?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content).with_context(|| format!("failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))
+}
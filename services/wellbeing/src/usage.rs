@@ -0,0 +1,169 @@
+// ABOUTME: Screen time, unlocks and notifications, added up per day and per app.
+// ABOUTME: Kept in /data/wellbeing/usage.toml for the last four weeks.
+
+use std::collections::BTreeMap;
+
+use chrono::{Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+pub const USAGE_PATH: &str = "/data/wellbeing/usage.toml";
+
+/// Days of history kept: the dashboard shows a week, the rest is for comparing weeks.
+const HISTORY_DAYS: usize = 28;
+
+/// One app's share of a day.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppDay {
+    /// Seconds it was on screen.
+    #[serde(default)]
+    pub seconds: u64,
+    #[serde(default)]
+    pub notifications: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Day {
+    pub date: NaiveDate,
+    #[serde(default)]
+    pub unlocks: u32,
+    #[serde(default)]
+    pub apps: BTreeMap<String, AppDay>,
+}
+
+impl Day {
+    fn new(date: NaiveDate) -> Self {
+        Self {
+            date,
+            unlocks: 0,
+            apps: BTreeMap::new(),
+        }
+    }
+
+    /// Seconds of screen time over all apps.
+    pub fn screen_time(&self) -> u64 {
+        self.apps.values().map(|app| app.seconds).sum()
+    }
+
+    pub fn seconds(&self, app: &str) -> u64 {
+        self.apps.get(app).map_or(0, |app| app.seconds)
+    }
+}
+
+/// As stored:
+///
+/// ```toml
+/// [[day]]
+/// date = "2026-10-18"
+/// unlocks = 31
+///
+/// [day.apps.messages]
+/// seconds = 1260
+/// notifications = 14
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Oldest first.
+    #[serde(default, rename = "day")]
+    pub days: Vec<Day>,
+}
+
+impl Usage {
+    pub fn day(&self, date: NaiveDate) -> Option<&Day> {
+        self.days.iter().rev().find(|day| day.date == date)
+    }
+
+    /// The day for `date`, started if it hasn't been. Starting one lets the oldest go,
+    /// though never the day being counted, even with the clock set back.
+    fn day_mut(&mut self, date: NaiveDate) -> &mut Day {
+        let at = self.days.partition_point(|day| day.date < date);
+        if self.days.get(at).is_none_or(|day| day.date != date) {
+            self.days.insert(at, Day::new(date));
+        }
+        let excess = self.days.len().saturating_sub(HISTORY_DAYS).min(at);
+        self.days.drain(..excess);
+        &mut self.days[at - excess]
+    }
+
+    pub fn add_time(&mut self, date: NaiveDate, app: &str, seconds: u64) {
+        let day = self.day_mut(date);
+        day.apps.entry(app.to_string()).or_default().seconds += seconds;
+    }
+
+    pub fn count_unlock(&mut self, date: NaiveDate) {
+        self.day_mut(date).unlocks += 1;
+    }
+
+    pub fn count_notification(&mut self, date: NaiveDate, app: &str) {
+        let day = self.day_mut(date);
+        day.apps.entry(app.to_string()).or_default().notifications += 1;
+    }
+
+    /// Screen time for each of the seven days up to `today`, oldest first.
+    pub fn week(&self, today: NaiveDate) -> Vec<(NaiveDate, u64)> {
+        (0..7)
+            .rev()
+            .filter_map(|back| today.checked_sub_days(Days::new(back)))
+            .map(|date| (date, self.day(date).map_or(0, Day::screen_time)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, day).unwrap()
+    }
+
+    #[test]
+    fn time_and_counts_add_up_per_app_and_day() {
+        let mut usage = Usage::default();
+        usage.add_time(date(18), "messages", 60);
+        usage.add_time(date(18), "messages", 30);
+        usage.add_time(date(18), "browser", 10);
+        usage.count_notification(date(18), "messages");
+        usage.count_unlock(date(18));
+        usage.add_time(date(19), "browser", 5);
+
+        let day = usage.day(date(18)).unwrap();
+        assert_eq!(day.seconds("messages"), 90);
+        assert_eq!(day.apps["messages"].notifications, 1);
+        assert_eq!(day.screen_time(), 100);
+        assert_eq!(day.unlocks, 1);
+        assert_eq!(usage.day(date(19)).unwrap().screen_time(), 5);
+    }
+
+    #[test]
+    fn a_week_has_every_day_even_unused_ones() {
+        let mut usage = Usage::default();
+        usage.add_time(date(12), "browser", 1);
+        usage.add_time(date(18), "browser", 7);
+
+        let week = usage.week(date(18));
+        assert_eq!(week.len(), 7);
+        assert_eq!(week[0], (date(12), 1));
+        assert_eq!(week[3], (date(15), 0));
+        assert_eq!(week[6], (date(18), 7));
+    }
+
+    #[test]
+    fn old_days_are_let_go() {
+        let mut usage = Usage::default();
+        let start = date(1);
+        for offset in 0..40 {
+            usage.count_unlock(start + Days::new(offset));
+        }
+        assert_eq!(usage.days.len(), HISTORY_DAYS);
+        assert_eq!(usage.days[0].date, start + Days::new(12));
+    }
+
+    #[test]
+    fn usage_round_trips_through_toml() {
+        let mut usage = Usage::default();
+        usage.add_time(date(18), "messages", 1260);
+        usage.count_notification(date(18), "messages");
+        let stored = toml::to_string(&usage).unwrap();
+        assert_eq!(toml::from_str::<Usage>(&stored).unwrap(), usage);
+    }
+}
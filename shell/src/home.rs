@@ -0,0 +1,406 @@
+// ABOUTME: The home screen's arrangement: pages of app icons and folders, and the dock along the bottom.
+// ABOUTME: Kept in /data/shell/home.toml so it survives reboots; apps installed since are added at the end.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+pub const HOME_PATH: &str = "/data/shell/home.toml";
+
+/// Icons on a page: three columns of four rows.
+pub const PAGE_SIZE: usize = 12;
+pub const DOCK_SIZE: usize = 4;
+
+/// What a folder made by dropping one app on another is called, until it is renamed.
+const FOLDER_NAME: &str = "Folder";
+
+/// The SIM's own menu of carrier services, which only some SIMs have.
+pub const SIM_TOOLKIT: &str = "sim-toolkit";
+
+/// Apps on the home screen only while something offers them, added and taken away
+/// with set_shown rather than when the layout is reconciled.
+const ON_DEMAND: &[&str] = &[SIM_TOOLKIT];
+
+pub struct App {
+    pub id: &'static str,
+    pub label: &'static str,
+    pub color: (u8, u8, u8),
+}
+
+/// The installed apps.
+pub const APPS: &[App] = &[
+    App {
+        id: "phone",
+        label: "Phone",
+        color: (0x27, 0xae, 0x60),
+    },
+    App {
+        id: "messages",
+        label: "Messages",
+        color: (0x29, 0x80, 0xb9),
+    },
+    App {
+        id: "settings",
+        label: "Settings",
+        color: (0x8e, 0x44, 0xad),
+    },
+    App {
+        id: "terminal",
+        label: "Terminal",
+        color: (0x2c, 0x3e, 0x50),
+    },
+    App {
+        id: "files",
+        label: "Files",
+        color: (0xd3, 0x54, 0x00),
+    },
+    App {
+        id: "browser",
+        label: "Browser",
+        color: (0xc0, 0x39, 0x2b),
+    },
+    App {
+        id: "camera",
+        label: "Camera",
+        color: (0x16, 0xa0, 0x85),
+    },
+    App {
+        id: "gallery",
+        label: "Gallery",
+        color: (0xe7, 0x4c, 0x3c),
+    },
+    App {
+        id: "music",
+        label: "Music",
+        color: (0xf3, 0x9c, 0x12),
+    },
+    App {
+        id: SIM_TOOLKIT,
+        label: "SIM Toolkit",
+        color: (0x34, 0x49, 0x5e),
+    },
+];
+
+pub fn app(id: &str) -> Option<&'static App> {
+    APPS.iter().find(|app| app.id == id)
+}
+
+/// What takes up one spot on a page or in the dock.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Tile {
+    App { app: String },
+    Folder { folder: String, apps: Vec<String> },
+}
+
+impl Tile {
+    fn app(id: &str) -> Self {
+        Tile::App {
+            app: id.to_string(),
+        }
+    }
+
+    /// Keep only the apps `keep` says to, turning a folder left with one app into
+    /// that app. False if nothing is left.
+    fn retain_apps(&mut self, keep: &mut impl FnMut(&String) -> bool) -> bool {
+        match self {
+            Tile::App { app } => keep(app),
+            Tile::Folder { apps, .. } => {
+                apps.retain(|app| keep(app));
+                if let [app] = apps.as_slice() {
+                    *self = Tile::app(app);
+                }
+                !matches!(self, Tile::Folder { apps, .. } if apps.is_empty())
+            }
+        }
+    }
+
+    /// `moving` dropped on this tile: two apps make a folder, and an app dropped on a
+    /// folder goes in it. None for a folder dropped on anything, as folders don't nest.
+    fn combined(&self, moving: &Tile) -> Option<Tile> {
+        let Tile::App { app: moving } = moving else {
+            return None;
+        };
+        match self {
+            Tile::App { app } => Some(Tile::Folder {
+                folder: FOLDER_NAME.to_string(),
+                apps: vec![app.clone(), moving.clone()],
+            }),
+            Tile::Folder { folder, apps } => {
+                let mut apps = apps.clone();
+                apps.push(moving.clone());
+                Some(Tile::Folder {
+                    folder: folder.clone(),
+                    apps,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Page {
+    #[serde(default)]
+    pub tiles: Vec<Tile>,
+}
+
+/// Where a tile is: in the dock, or on a page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Place {
+    Dock,
+    Page(usize),
+}
+
+/// As stored:
+///
+/// ```toml
+/// dock = [{ app = "phone" }, { app = "messages" }]
+///
+/// [[page]]
+/// tiles = [{ app = "settings" }, { folder = "Tools", apps = ["terminal", "files"] }]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    #[serde(default)]
+    pub dock: Vec<Tile>,
+    #[serde(default, rename = "page")]
+    pub pages: Vec<Page>,
+}
+
+impl Default for Layout {
+    /// The first-boot arrangement: the everyday apps in the dock, the rest on a page.
+    fn default() -> Self {
+        let mut layout = Self {
+            dock: ["phone", "messages", "browser", "camera"]
+                .into_iter()
+                .map(Tile::app)
+                .collect(),
+            pages: Vec::new(),
+        };
+        layout.reconcile();
+        layout
+    }
+}
+
+impl Layout {
+    /// The arrangement saved at `path`, or the first-boot one if there is none yet or
+    /// it can't be read.
+    pub fn load(path: &Path) -> Self {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "failed to read home screen layout");
+                return Self::default();
+            }
+        };
+        match toml::from_str::<Self>(&content) {
+            Ok(mut layout) => {
+                layout.reconcile();
+                layout
+            }
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "invalid home screen layout");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let content = toml::to_string(self).context("failed to serialize home screen layout")?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))
+    }
+
+    pub fn tiles(&self, place: Place) -> Option<&Vec<Tile>> {
+        match place {
+            Place::Dock => Some(&self.dock),
+            Place::Page(page) => self.pages.get(page).map(|page| &page.tiles),
+        }
+    }
+
+    fn tiles_mut(&mut self, place: Place) -> Option<&mut Vec<Tile>> {
+        match place {
+            Place::Dock => Some(&mut self.dock),
+            Place::Page(page) => self.pages.get_mut(page).map(|page| &mut page.tiles),
+        }
+    }
+
+    /// Move the tile at `from_index` of `from` to `to_index` of `to`, or onto the tile
+    /// there if `combine`. Dragging past the last page makes a new one. False if there
+    /// is no such tile or no room for it.
+    pub fn move_tile(
+        &mut self,
+        from: Place,
+        from_index: usize,
+        to: Place,
+        to_index: usize,
+        combine: bool,
+    ) -> bool {
+        if from == to && from_index == to_index {
+            return false;
+        }
+        let Some(moving) = self
+            .tiles(from)
+            .and_then(|tiles| tiles.get(from_index))
+            .cloned()
+        else {
+            return false;
+        };
+        if to == Place::Page(self.pages.len()) {
+            self.pages.push(Page::default());
+        }
+
+        let target = self.tiles(to).and_then(|tiles| tiles.get(to_index));
+        if let Some(combined) = target.filter(|_| combine).and_then(|t| t.combined(&moving)) {
+            if let Some(tiles) = self.tiles_mut(to) {
+                tiles[to_index] = combined;
+            }
+            if let Some(tiles) = self.tiles_mut(from) {
+                tiles.remove(from_index);
+            }
+            self.tidy();
+            return true;
+        }
+
+        let capacity = match to {
+            Place::Dock => DOCK_SIZE,
+            Place::Page(_) => PAGE_SIZE,
+        };
+        let room = from == to || self.tiles(to).is_some_and(|tiles| tiles.len() < capacity);
+        if !room {
+            self.tidy();
+            return false;
+        }
+        if let Some(tiles) = self.tiles_mut(from) {
+            tiles.remove(from_index);
+        }
+        if let Some(tiles) = self.tiles_mut(to) {
+            let index = to_index.min(tiles.len());
+            tiles.insert(index, moving);
+        }
+        self.tidy();
+        true
+    }
+
+    /// Take app `app_index` out of the folder at `index` of `place`, onto the first
+    /// page from there with room for it.
+    pub fn take_out(&mut self, place: Place, index: usize, app_index: usize) -> bool {
+        let Some(tile) = self.tiles_mut(place).and_then(|tiles| tiles.get_mut(index)) else {
+            return false;
+        };
+        let Tile::Folder { apps, .. } = &mut *tile else {
+            return false;
+        };
+        if app_index >= apps.len() {
+            return false;
+        }
+        let app = apps.remove(app_index);
+        let mut keep_all = |_: &String| true;
+        tile.retain_apps(&mut keep_all);
+
+        let first = match place {
+            Place::Dock => 0,
+            Place::Page(page) => page,
+        };
+        self.add(Tile::App { app }, first);
+        true
+    }
+
+    pub fn rename_folder(&mut self, place: Place, index: usize, name: &str) -> bool {
+        let name = name.trim();
+        if name.is_empty() {
+            return false;
+        }
+        match self.tiles_mut(place).and_then(|tiles| tiles.get_mut(index)) {
+            Some(Tile::Folder { folder, .. }) => {
+                *folder = name.to_string();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Add an on-demand app at the end, or take it away from wherever the user put it.
+    /// False if it already was as asked.
+    pub fn set_shown(&mut self, app: &str, shown: bool) -> bool {
+        let is_app = |tile: &Tile| match tile {
+            Tile::App { app: id } => id == app,
+            Tile::Folder { apps, .. } => apps.iter().any(|id| id == app),
+        };
+        let present = self
+            .dock
+            .iter()
+            .chain(self.pages.iter().flat_map(|page| &page.tiles))
+            .any(is_app);
+        if present == shown {
+            return false;
+        }
+        if shown {
+            self.add(Tile::app(app), 0);
+        } else {
+            let mut keep = |id: &String| id != app;
+            self.dock.retain_mut(|tile| tile.retain_apps(&mut keep));
+            for page in &mut self.pages {
+                page.tiles.retain_mut(|tile| tile.retain_apps(&mut keep));
+            }
+            self.tidy();
+        }
+        true
+    }
+
+    /// Put `tile` at the end of the first page from `first` with room, or a new page.
+    fn add(&mut self, tile: Tile, first: usize) {
+        match self
+            .pages
+            .iter_mut()
+            .skip(first)
+            .find(|page| page.tiles.len() < PAGE_SIZE)
+        {
+            Some(page) => page.tiles.push(tile),
+            None => self.pages.push(Page { tiles: vec![tile] }),
+        }
+    }
+
+    /// Drop empty pages, keeping one for the home screen to show.
+    fn tidy(&mut self) {
+        self.pages.retain(|page| !page.tiles.is_empty());
+        if self.pages.is_empty() {
+            self.pages.push(Page::default());
+        }
+    }
+
+    /// Make a layout read from disk fit the apps installed: drop apps that are gone or
+    /// shown twice, move what doesn't fit to later pages, and add new apps at the end.
+    fn reconcile(&mut self) {
+        let mut seen = HashSet::new();
+        let mut keep = |app: &String| self::app(app).is_some() && seen.insert(app.clone());
+        self.dock.retain_mut(|tile| tile.retain_apps(&mut keep));
+        for page in &mut self.pages {
+            page.tiles.retain_mut(|tile| tile.retain_apps(&mut keep));
+        }
+
+        let mut overflow = self.dock.split_off(self.dock.len().min(DOCK_SIZE));
+        for page in &mut self.pages {
+            overflow.extend(page.tiles.split_off(page.tiles.len().min(PAGE_SIZE)));
+        }
+        overflow.extend(
+            APPS.iter()
+                .filter(|app| !seen.contains(app.id) && !ON_DEMAND.contains(&app.id))
+                .map(|app| Tile::app(app.id)),
+        );
+        for tile in overflow {
+            self.add(tile, 0);
+        }
+        self.tidy();
+    }
+}
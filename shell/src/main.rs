@@ -1,13 +1,191 @@
-// ABOUTME: MobileOS UI shell — home screen, lock screen, and status bar.
+// ABOUTME: MobileOS UI shell — home screen, lock screen, status bar, quick settings, and task switcher.
 // ABOUTME: Runs as a Wayland client connecting to the MobileOS compositor.
 
+mod home;
+mod pin;
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
 use std::time::Duration;
 
-use slint::TimerMode;
-use tracing::info;
+use futures_util::StreamExt;
+use slint::{Color, Model, SharedString, TimerMode, VecModel};
+use tracing::{info, warn};
+
+use home::{Layout, Place, Tile};
+use pin::PinStore;
 
 slint::include_modules!();
 
+/// How long the quick settings panel gets to leave the screen before a screenshot.
+const SCREENSHOT_DELAY: Duration = Duration::from_millis(300);
+
+/// Names the registered voice assistant app, which a long press of Home opens.
+const ASSISTANT_APP_VAR: &str = "MOS_ASSISTANT_APP";
+
+enum ShellCommand {
+    AnswerAuthorization {
+        fingerprint: String,
+        allow: bool,
+        remember: bool,
+    },
+    ListApps,
+    Activate(String),
+    Close(String),
+    Pin(String),
+    Unpin,
+    CancelUnpin,
+    Split(String),
+    Unsplit,
+    SetBubblesHidden(bool),
+    QuickSettings,
+    SetColorFilterEnabled(bool),
+    SetRotationLocked(bool),
+    Search(String),
+    Screenshot,
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Bridge",
+    default_service = "org.mobileos.Bridge",
+    default_path = "/org/mobileos/Bridge"
+)]
+trait Bridge {
+    fn respond(&self, fingerprint: &str, allow: bool, remember: bool) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn authorization_requested(&self, host: &str, fingerprint: &str) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Compositor",
+    default_service = "org.mobileos.Compositor",
+    default_path = "/org/mobileos/Compositor"
+)]
+trait Compositor {
+    #[zbus(property)]
+    fn toplevels(&self) -> zbus::Result<Vec<(String, String)>>;
+
+    #[zbus(property)]
+    fn can_split(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn split_apps(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(property)]
+    fn color_filter_enabled(&self) -> zbus::Result<bool>;
+
+    fn activate(&self, app_id: &str) -> zbus::Result<()>;
+    fn close(&self, app_id: &str) -> zbus::Result<()>;
+    fn pin(&self, app_id: &str) -> zbus::Result<()>;
+    fn unpin(&self) -> zbus::Result<()>;
+    fn cancel_unpin(&self) -> zbus::Result<()>;
+    fn split(&self, app_id: &str) -> zbus::Result<()>;
+    fn unsplit(&self) -> zbus::Result<()>;
+    fn set_bubbles_hidden(&self, hidden: bool) -> zbus::Result<()>;
+    fn set_color_filter_enabled(&self, enabled: bool) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn unpin_requested(&self) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn assistant_requested(&self, app_id: String) -> zbus::Result<()>;
+}
+
+/// A search result on the bus: provider, id, title, subtitle and score.
+type SearchHit = (String, String, String, String, f64);
+
+#[zbus::proxy(
+    interface = "org.mobileos.Search",
+    default_service = "org.mobileos.Search",
+    default_path = "/org/mobileos/Search"
+)]
+trait Search {
+    fn search(&self, query: &str) -> zbus::Result<Vec<SearchHit>>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Screenshot",
+    default_service = "org.mobileos.Screenshot",
+    default_path = "/org/mobileos/Screenshot"
+)]
+trait Screenshot {
+    fn take(&self) -> zbus::Result<String>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Display",
+    default_service = "org.mobileos.Display",
+    default_path = "/org/mobileos/Display"
+)]
+trait Display {
+    #[zbus(property)]
+    fn rotation_locked(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn set_rotation_locked(&self, locked: bool) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Power",
+    default_service = "org.mobileos.Power",
+    default_path = "/org/mobileos/Power"
+)]
+trait Power {
+    #[zbus(signal)]
+    fn lock_requested(&self) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Wellbeing",
+    default_service = "org.mobileos.Wellbeing",
+    default_path = "/org/mobileos/Wellbeing"
+)]
+trait Wellbeing {
+    #[zbus(property)]
+    fn times_up(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(property)]
+    fn blocked(&self) -> zbus::Result<Vec<String>>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Network",
+    default_service = "org.mobileos.Network",
+    default_path = "/org/mobileos/Network"
+)]
+trait Network {
+    #[zbus(property)]
+    fn data_capped(&self) -> zbus::Result<bool>;
+
+    #[zbus(signal)]
+    fn data_warning(&self, used: u64, warning: u64) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Modem",
+    default_service = "org.mobileos.Modem",
+    default_path = "/org/mobileos/Modem"
+)]
+trait Modem {
+    /// The SIM toolkit's menu: its title and numbered items, none without one.
+    #[zbus(property)]
+    fn toolkit_menu(&self) -> zbus::Result<(String, Vec<(u8, String)>)>;
+}
+
+#[zbus::proxy(
+    interface = "org.mobileos.Sensors",
+    default_service = "org.mobileos.Sensors",
+    default_path = "/org/mobileos/Sensors"
+)]
+trait Sensors {
+    #[zbus(property)]
+    fn in_pocket(&self) -> zbus::Result<bool>;
+}
+
 fn main() -> Result<(), slint::PlatformError> {
     tracing_subscriber::fmt()
         .with_env_filter(
@@ -32,14 +210,772 @@ fn main() -> Result<(), slint::PlatformError> {
         }
     });
 
-    window.on_app_launched(|name| {
+    let weak = window.as_weak();
+    window.on_app_launched(move |name| {
+        let Some(w) = weak.upgrade() else {
+            return;
+        };
+        if w.get_blocked_apps().iter().any(|app| app == name) {
+            info!(app = name.as_str(), "app launch stopped, out of time");
+            let label = home::app(&name).map_or(name.as_str(), |app| app.label);
+            w.set_times_up_app(label.into());
+            return;
+        }
         info!(app = name.as_str(), "app launched");
     });
 
+    let (cmd_tx, cmd_rx) = mpsc::channel::<ShellCommand>();
+    let pins = Rc::new(PinStore::new(PathBuf::from(pin::PIN_PATH)));
+
+    let tx = cmd_tx.clone();
+    window.on_switcher_requested(move || {
+        let _ = tx.send(ShellCommand::ListApps);
+    });
+
+    // Bubbles float over everything, so they stay hidden until the screen is unlocked
+    let _ = cmd_tx.send(ShellCommand::SetBubblesHidden(true));
+    let tx = cmd_tx.clone();
+    window.on_unlocked(move || {
+        let _ = tx.send(ShellCommand::SetBubblesHidden(false));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_quick_settings_requested(move || {
+        let _ = tx.send(ShellCommand::QuickSettings);
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_color_filter_toggled(move |enabled| {
+        let _ = tx.send(ShellCommand::SetColorFilterEnabled(enabled));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_rotation_lock_toggled(move |locked| {
+        let _ = tx.send(ShellCommand::SetRotationLocked(locked));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_app_activated(move |app| {
+        let _ = tx.send(ShellCommand::Activate(app.to_string()));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_app_closed(move |app| {
+        let _ = tx.send(ShellCommand::Close(app.to_string()));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_app_split(move |app| {
+        let _ = tx.send(ShellCommand::Split(app.to_string()));
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_split_exited(move || {
+        let _ = tx.send(ShellCommand::Unsplit);
+    });
+
+    // Pinning takes a PIN to undo, so the first pin has the user choose one
+    let weak = window.as_weak();
+    let tx = cmd_tx.clone();
+    let store = pins.clone();
+    window.on_app_pinned(move |app| {
+        let Some(w) = weak.upgrade() else {
+            return;
+        };
+        if store.is_set() {
+            let _ = tx.send(ShellCommand::Pin(app.to_string()));
+        } else {
+            w.set_pin_app(app);
+            w.set_pin_error("".into());
+            w.set_pin_mode("choose".into());
+        }
+    });
+
+    let weak = window.as_weak();
+    let tx = cmd_tx.clone();
+    window.on_pin_submitted(move |entered| {
+        let Some(w) = weak.upgrade() else {
+            return;
+        };
+        match w.get_pin_mode().as_str() {
+            "choose" => match pins.set(&entered) {
+                Ok(()) => {
+                    w.set_pin_mode("".into());
+                    let _ = tx.send(ShellCommand::Pin(w.get_pin_app().to_string()));
+                }
+                Err(e) => w.set_pin_error(format!("{e:#}").into()),
+            },
+            "unpin" if pins.verify(&entered) => {
+                w.set_pin_mode("".into());
+                let _ = tx.send(ShellCommand::Unpin);
+            }
+            "unpin" => w.set_pin_error("Wrong PIN".into()),
+            _ => {}
+        }
+    });
+
+    let weak = window.as_weak();
+    let tx = cmd_tx.clone();
+    window.on_pin_cancelled(move || {
+        let Some(w) = weak.upgrade() else {
+            return;
+        };
+        if w.get_pin_mode() == "unpin" {
+            let _ = tx.send(ShellCommand::CancelUnpin);
+        }
+        w.set_pin_mode("".into());
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_screenshot_requested(move || {
+        let _ = tx.send(ShellCommand::Screenshot);
+    });
+
+    let tx = cmd_tx.clone();
+    window.on_search_changed(move |query| {
+        let _ = tx.send(ShellCommand::Search(query.to_string()));
+    });
+
+    // Apps found by search open like their icon; what other providers find opens in
+    // their own app, once there is a way to hand it over
+    let weak = window.as_weak();
+    window.on_search_result_chosen(move |id| {
+        let Some(w) = weak.upgrade() else {
+            return;
+        };
+        match id.strip_prefix("app:") {
+            Some(app) => w.invoke_app_launched(app.into()),
+            None => info!(result = id.as_str(), "search result opened"),
+        }
+    });
+
+    let home = Rc::new(RefCell::new(Layout::load(Path::new(home::HOME_PATH))));
+    show_home(&window, &home.borrow());
+
+    let weak = window.as_weak();
+    let layout = home.clone();
+    window.on_home_tile_moved(move |from_page, from, to_page, to, combine| {
+        let mut layout = layout.borrow_mut();
+        let (from_place, to_place) = (place(from_page), place(to_page));
+        if layout.move_tile(from_place, from as usize, to_place, to as usize, combine) {
+            home_changed(&weak, &layout);
+        } else if let Some(w) = weak.upgrade() {
+            // Turning to a new page and dropping nothing there leaves no page to show
+            show_home(&w, &layout);
+        }
+    });
+
+    let weak = window.as_weak();
+    let layout = home.clone();
+    window.on_times_up_changed(move || {
+        if let Some(w) = weak.upgrade() {
+            show_home(&w, &layout.borrow());
+        }
+    });
+
+    let weak = window.as_weak();
+    let layout = home.clone();
+    window.on_sim_menu_changed(move || {
+        let Some(w) = weak.upgrade() else {
+            return;
+        };
+        let mut layout = layout.borrow_mut();
+        if layout.set_shown(home::SIM_TOOLKIT, !w.get_sim_menu().is_empty()) {
+            home_changed(&weak, &layout);
+        } else {
+            show_home(&w, &layout);
+        }
+    });
+
+    let weak = window.as_weak();
+    let layout = home.clone();
+    window.on_home_app_taken_out(move |page, index, app| {
+        let mut layout = layout.borrow_mut();
+        if layout.take_out(place(page), index as usize, app as usize) {
+            home_changed(&weak, &layout);
+        }
+    });
+
+    let weak = window.as_weak();
+    let layout = home.clone();
+    window.on_home_folder_renamed(move |page, index, name| {
+        let mut layout = layout.borrow_mut();
+        if layout.rename_folder(place(page), index as usize, &name) {
+            home_changed(&weak, &layout);
+        }
+    });
+
+    let weak = window.as_weak();
+    window.on_auth_answered(move |allow, remember| {
+        let Some(w) = weak.upgrade() else {
+            return;
+        };
+        w.set_auth_pending(false);
+        let _ = cmd_tx.send(ShellCommand::AnswerAuthorization {
+            fingerprint: w.get_auth_fingerprint().to_string(),
+            allow,
+            remember,
+        });
+    });
+
+    // Background tokio thread for D-Bus communication
+    let weak = window.as_weak();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let conn = match zbus::Connection::system().await {
+                Ok(c) => c,
+                Err(e) => {
+                    info!("D-Bus not available: {e}");
+                    return;
+                }
+            };
+
+            let bridge = BridgeProxy::new(&conn).await.ok();
+
+            if let Some(ref b) = bridge
+                && let Ok(mut requests) = b.receive_authorization_requested().await
+            {
+                let weak = weak.clone();
+                tokio::spawn(async move {
+                    while let Some(request) = requests.next().await {
+                        let Ok(args) = request.args() else {
+                            continue;
+                        };
+                        let host = args.host().to_string();
+                        let fingerprint = args.fingerprint().to_string();
+                        info!(host = %host, "bridge authorization requested");
+
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                w.set_auth_host(host.into());
+                                w.set_auth_fingerprint(fingerprint.into());
+                                w.set_auth_pending(true);
+                            }
+                        });
+                    }
+                });
+            }
+
+            let compositor = match CompositorProxy::builder(&conn)
+                .cache_properties(zbus::proxy::CacheProperties::No)
+                .build()
+                .await
+            {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    warn!(error = %e, "compositor not on D-Bus, app pinning and split view unavailable");
+                    None
+                }
+            };
+
+            // The compositor raises the shell when the user swipes up out of a pinned app
+            if let Some(ref c) = compositor
+                && let Ok(mut requests) = c.receive_unpin_requested().await
+            {
+                let weak = weak.clone();
+                tokio::spawn(async move {
+                    while requests.next().await.is_some() {
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                w.set_pin_error("".into());
+                                w.set_pin_mode("unpin".into());
+                            }
+                        });
+                    }
+                });
+            }
+
+            // Open the registered assistant with the app the user was in, when asked for
+            if let Some(ref c) = compositor
+                && let Ok(mut requests) = c.receive_assistant_requested().await
+            {
+                let assistant = std::env::var(ASSISTANT_APP_VAR).ok();
+                tokio::spawn(async move {
+                    while let Some(request) = requests.next().await {
+                        let Ok(args) = request.args() else {
+                            continue;
+                        };
+                        match &assistant {
+                            Some(app) => {
+                                info!(app = %app, context = %args.app_id, "assistant launched")
+                            }
+                            None => info!("assistant requested, but none is registered"),
+                        }
+                    }
+                });
+            }
+
+            let search = match SearchProxy::new(&conn).await {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    warn!(error = %e, "search service not on D-Bus, search unavailable");
+                    None
+                }
+            };
+
+            let screenshot = match ScreenshotProxy::new(&conn).await {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    warn!(error = %e, "screenshot service not on D-Bus, screenshots unavailable");
+                    None
+                }
+            };
+
+            let display = match DisplayProxy::new(&conn).await {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    warn!(error = %e, "compositor display not on D-Bus, rotation lock unavailable");
+                    None
+                }
+            };
+
+            // The compositor drops touches while in a pocket; show why the screen ignores them
+            if let Ok(sensors) = SensorsProxy::new(&conn).await {
+                let weak = weak.clone();
+                tokio::spawn(async move {
+                    let mut changes = sensors.receive_in_pocket_changed().await;
+                    while let Some(change) = changes.next().await {
+                        let Ok(in_pocket) = change.get().await else {
+                            continue;
+                        };
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                w.set_in_pocket(in_pocket);
+                            }
+                        });
+                    }
+                });
+            }
+
+            // The power service locks the phone once it has been left alone
+            if let Ok(power) = PowerProxy::new(&conn).await
+                && let Ok(mut requests) = power.receive_lock_requested().await
+            {
+                let weak = weak.clone();
+                let compositor = compositor.clone();
+                tokio::spawn(async move {
+                    while requests.next().await.is_some() {
+                        info!("locking, the phone was left alone");
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.set_bubbles_hidden(true).await
+                        {
+                            warn!(error = %e, "failed to hide bubbles");
+                        }
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                w.set_switcher_open(false);
+                                w.set_quick_settings_open(false);
+                                w.set_search_open(false);
+                                w.set_locked(true);
+                            }
+                        });
+                    }
+                });
+            }
+
+            // Apps out of time for today are grayed on the home screen, and ones with a
+            // hard timer don't open
+            if let Ok(wellbeing) = WellbeingProxy::new(&conn).await {
+                let blocked = wellbeing.clone();
+                let weak_blocked = weak.clone();
+                tokio::spawn(async move {
+                    let mut changes = blocked.receive_blocked_changed().await;
+                    while let Some(change) = changes.next().await {
+                        let Ok(apps) = change.get().await else {
+                            continue;
+                        };
+                        let weak = weak_blocked.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                let apps: Vec<SharedString> =
+                                    apps.into_iter().map(SharedString::from).collect();
+                                w.set_blocked_apps(Rc::new(VecModel::from(apps)).into());
+                            }
+                        });
+                    }
+                });
+                let weak = weak.clone();
+                tokio::spawn(async move {
+                    let mut changes = wellbeing.receive_times_up_changed().await;
+                    while let Some(change) = changes.next().await {
+                        let Ok(apps) = change.get().await else {
+                            continue;
+                        };
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                let apps: Vec<SharedString> =
+                                    apps.into_iter().map(SharedString::from).collect();
+                                w.set_times_up(Rc::new(VecModel::from(apps)).into());
+                                w.invoke_times_up_changed();
+                            }
+                        });
+                    }
+                });
+            }
+
+            // Mobile data nearing or at the plan's cap gets a notice
+            if let Ok(network) = NetworkProxy::new(&conn).await {
+                if let Ok(mut warnings) = network.receive_data_warning().await {
+                    let weak = weak.clone();
+                    tokio::spawn(async move {
+                        while let Some(signal) = warnings.next().await {
+                            let Ok(args) = signal.args() else {
+                                continue;
+                            };
+                            let notice = format!(
+                                "You've used {} of mobile data this cycle, past the {} you \
+                                 asked to be warned at.",
+                                format_bytes(args.used),
+                                format_bytes(args.warning)
+                            );
+                            show_notice(&weak, notice);
+                        }
+                    });
+                }
+                let weak = weak.clone();
+                tokio::spawn(async move {
+                    let mut changes = network.receive_data_capped_changed().await;
+                    while let Some(change) = changes.next().await {
+                        if matches!(change.get().await, Ok(true)) {
+                            let notice = "Mobile data is off until the next cycle, as you've \
+                                          reached your cap. Wi-Fi still works.";
+                            show_notice(&weak, notice.to_string());
+                        }
+                    }
+                });
+            }
+
+            // The SIM's menu of carrier services is on the home screen while it has one
+            if let Ok(modem) = ModemProxy::new(&conn).await {
+                let weak = weak.clone();
+                tokio::spawn(async move {
+                    let mut changes = modem.receive_toolkit_menu_changed().await;
+                    while let Some(change) = changes.next().await {
+                        let Ok((title, items)) = change.get().await else {
+                            continue;
+                        };
+                        let menu = match (title.is_empty(), items.is_empty()) {
+                            (_, true) => String::new(),
+                            (true, false) => home::app(home::SIM_TOOLKIT)
+                                .map(|app| app.label.to_string())
+                                .unwrap_or_default(),
+                            (false, false) => title,
+                        };
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                w.set_sim_menu(menu.into());
+                                w.invoke_sim_menu_changed();
+                            }
+                        });
+                    }
+                });
+            }
+
+            while let Ok(cmd) = cmd_rx.recv() {
+                match cmd {
+                    ShellCommand::AnswerAuthorization {
+                        fingerprint,
+                        allow,
+                        remember,
+                    } => {
+                        if let Some(ref b) = bridge
+                            && let Err(e) = b.respond(&fingerprint, allow, remember).await
+                        {
+                            warn!(error = %e, "failed to answer bridge authorization");
+                        }
+                    }
+                    ShellCommand::ListApps => {
+                        let Some(ref c) = compositor else {
+                            continue;
+                        };
+                        let apps = match c.toplevels().await {
+                            Ok(apps) => apps,
+                            Err(e) => {
+                                warn!(error = %e, "failed to list open apps");
+                                continue;
+                            }
+                        };
+                        let can_split = c.can_split().await.unwrap_or(false);
+                        let split = c.split_apps().await.is_ok_and(|apps| !apps.is_empty());
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                let apps: Vec<SwitcherApp> = apps
+                                    .into_iter()
+                                    .map(|(id, title)| SwitcherApp {
+                                        id: id.into(),
+                                        title: title.into(),
+                                    })
+                                    .collect();
+                                w.set_switcher_apps(Rc::new(VecModel::from(apps)).into());
+                                w.set_switcher_can_split(can_split);
+                                w.set_switcher_split(split);
+                                w.set_switcher_open(true);
+                            }
+                        });
+                    }
+                    ShellCommand::Activate(app) => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.activate(&app).await
+                        {
+                            warn!(app = %app, error = %e, "failed to bring up app");
+                        }
+                    }
+                    ShellCommand::Close(app) => {
+                        let Some(ref c) = compositor else {
+                            continue;
+                        };
+                        if let Err(e) = c.close(&app).await {
+                            warn!(app = %app, error = %e, "failed to close app");
+                            continue;
+                        }
+                        // The app may take a moment to go, so it leaves the list straight
+                        // away
+                        let apps = c.toplevels().await.unwrap_or_default();
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                let apps: Vec<SwitcherApp> = apps
+                                    .into_iter()
+                                    .filter(|(id, _)| *id != app)
+                                    .map(|(id, title)| SwitcherApp {
+                                        id: id.into(),
+                                        title: title.into(),
+                                    })
+                                    .collect();
+                                w.set_switcher_apps(Rc::new(VecModel::from(apps)).into());
+                            }
+                        });
+                    }
+                    ShellCommand::Pin(app) => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.pin(&app).await
+                        {
+                            warn!(app = %app, error = %e, "failed to pin app");
+                        }
+                    }
+                    ShellCommand::Unpin => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.unpin().await
+                        {
+                            warn!(error = %e, "failed to unpin app");
+                        }
+                    }
+                    ShellCommand::CancelUnpin => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.cancel_unpin().await
+                        {
+                            warn!(error = %e, "failed to return to the pinned app");
+                        }
+                    }
+                    ShellCommand::Split(app) => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.split(&app).await
+                        {
+                            warn!(app = %app, error = %e, "failed to enter split view");
+                        }
+                    }
+                    ShellCommand::Unsplit => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.unsplit().await
+                        {
+                            warn!(error = %e, "failed to leave split view");
+                        }
+                    }
+                    ShellCommand::SetBubblesHidden(hidden) => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.set_bubbles_hidden(hidden).await
+                        {
+                            warn!(hidden, error = %e, "failed to set whether bubbles show");
+                        }
+                    }
+                    ShellCommand::QuickSettings => {
+                        // The tiles show the compositor's state, which settings may have changed
+                        let color_filter_on = match compositor {
+                            Some(ref c) => c.color_filter_enabled().await.unwrap_or(false),
+                            None => false,
+                        };
+                        let rotation_locked = match display {
+                            Some(ref d) => d.rotation_locked().await.unwrap_or(false),
+                            None => false,
+                        };
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(w) = weak.upgrade() {
+                                w.set_color_filter_on(color_filter_on);
+                                w.set_rotation_locked(rotation_locked);
+                                w.set_quick_settings_open(true);
+                            }
+                        });
+                    }
+                    ShellCommand::SetColorFilterEnabled(enabled) => {
+                        if let Some(ref c) = compositor
+                            && let Err(e) = c.set_color_filter_enabled(enabled).await
+                        {
+                            warn!(enabled, error = %e, "failed to toggle the color filter");
+                        }
+                    }
+                    ShellCommand::SetRotationLocked(locked) => {
+                        if let Some(ref d) = display
+                            && let Err(e) = d.set_rotation_locked(locked).await
+                        {
+                            warn!(locked, error = %e, "failed to toggle the rotation lock");
+                        }
+                    }
+                    ShellCommand::Search(query) => {
+                        let Some(ref s) = search else {
+                            continue;
+                        };
+                        let hits = match s.search(&query).await {
+                            Ok(hits) => hits,
+                            Err(e) => {
+                                warn!(error = %e, "search failed");
+                                continue;
+                            }
+                        };
+                        let results: Vec<SearchResult> = hits
+                            .into_iter()
+                            .map(|(_, id, title, subtitle, _)| SearchResult {
+                                id: id.into(),
+                                title: title.into(),
+                                subtitle: subtitle.into(),
+                            })
+                            .collect();
+                        let weak = weak.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            // Typing on may have outrun the answer; only the latest query shows
+                            if let Some(w) = weak.upgrade()
+                                && w.get_search_query() == query.as_str()
+                            {
+                                w.set_search_results(Rc::new(VecModel::from(results)).into());
+                            }
+                        });
+                    }
+                    ShellCommand::Screenshot => {
+                        let Some(ref s) = screenshot else {
+                            continue;
+                        };
+                        tokio::time::sleep(SCREENSHOT_DELAY).await;
+                        match s.take().await {
+                            Ok(path) => info!(path = %path, "screenshot taken"),
+                            Err(e) => warn!(error = %e, "failed to take a screenshot"),
+                        }
+                    }
+                }
+            }
+        });
+    });
+
     info!("shell running");
     window.run()
 }
 
+/// Where the home screen's `page` argument points: -1 is the dock.
+fn place(page: i32) -> Place {
+    match usize::try_from(page) {
+        Ok(page) => Place::Page(page),
+        Err(_) => Place::Dock,
+    }
+}
+
+fn show_home(window: &ShellWindow, layout: &Layout) {
+    let color = |(r, g, b)| Color::from_rgb_u8(r, g, b);
+    let times_up: HashSet<String> = window.get_times_up().iter().map(String::from).collect();
+    let dimmed = |id: &str| times_up.contains(id);
+    // The SIM toolkit goes by the name the SIM gives its menu
+    let sim_menu = window.get_sim_menu();
+    let label = |app: &home::App| match app.id {
+        home::SIM_TOOLKIT if !sim_menu.is_empty() => sim_menu.clone(),
+        _ => app.label.into(),
+    };
+    let tile = |tile: &Tile| match tile {
+        Tile::App { app } => home::app(app).map(|app| HomeTile {
+            id: app.id.into(),
+            label: label(app),
+            color: color(app.color),
+            folder: false,
+            dimmed: dimmed(app.id),
+            apps: Default::default(),
+        }),
+        Tile::Folder { folder, apps } => {
+            let apps: Vec<HomeApp> = apps
+                .iter()
+                .filter_map(|app| home::app(app))
+                .map(|app| HomeApp {
+                    id: app.id.into(),
+                    label: label(app),
+                    color: color(app.color),
+                    dimmed: dimmed(app.id),
+                })
+                .collect();
+            Some(HomeTile {
+                id: "".into(),
+                label: folder.as_str().into(),
+                color: Color::default(),
+                folder: true,
+                dimmed: false,
+                apps: Rc::new(VecModel::from(apps)).into(),
+            })
+        }
+    };
+    let pages: Vec<HomePage> = layout
+        .pages
+        .iter()
+        .map(|page| {
+            let tiles: Vec<HomeTile> = page.tiles.iter().filter_map(tile).collect();
+            HomePage {
+                tiles: Rc::new(VecModel::from(tiles)).into(),
+            }
+        })
+        .collect();
+    let dock: Vec<HomeTile> = layout.dock.iter().filter_map(tile).collect();
+    window.set_home_pages(Rc::new(VecModel::from(pages)).into());
+    window.set_home_dock(Rc::new(VecModel::from(dock)).into());
+
+    let last = layout.pages.len().saturating_sub(1) as i32;
+    if window.get_home_page() > last {
+        window.set_home_page(last);
+    }
+}
+
+/// Show the home screen's new arrangement, and keep it for next boot.
+fn home_changed(weak: &slint::Weak<ShellWindow>, layout: &Layout) {
+    if let Some(w) = weak.upgrade() {
+        show_home(&w, layout);
+    }
+    if let Err(e) = layout.save(Path::new(home::HOME_PATH)) {
+        warn!(error = %e, "failed to save home screen layout");
+    }
+}
+
+/// Put up a notice over whatever is on screen, until dismissed.
+fn show_notice(weak: &slint::Weak<ShellWindow>, notice: String) {
+    let weak = weak.clone();
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(w) = weak.upgrade() {
+            w.set_notice(notice.into());
+        }
+    });
+}
+
+/// An amount of data as notices give it, e.g. "1.5 GB".
+fn format_bytes(bytes: u64) -> String {
+    const MB: u64 = 1_000_000;
+    const GB: u64 = 1_000 * MB;
+    if bytes >= GB {
+        format!("{:.1} GB", bytes as f64 / GB as f64)
+    } else {
+        format!("{} MB", bytes / MB)
+    }
+}
+
 fn update_clock(window: &ShellWindow) {
     let now = chrono::Local::now();
     window.set_time(now.format("%H:%M").to_string().into());
@@ -0,0 +1,87 @@
+// ABOUTME: The PIN that unpins a pinned app, stored salted and hashed under /data.
+// ABOUTME: Chosen the first time an app is pinned, checked each time the user asks to unpin.
+
+use std::io::Read;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+
+pub const PIN_PATH: &str = "/data/shell/pin";
+
+pub struct PinStore {
+    path: PathBuf,
+}
+
+impl PinStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Store `pin`, 4 to 8 digits, replacing any earlier one.
+    pub fn set(&self, pin: &str) -> Result<()> {
+        if !(4..=8).contains(&pin.len()) || !pin.bytes().all(|b| b.is_ascii_digit()) {
+            bail!("a PIN is 4 to 8 digits");
+        }
+        let mut salt = [0u8; 16];
+        std::fs::File::open("/dev/urandom")
+            .and_then(|mut random| random.read_exact(&mut salt))
+            .context("failed to generate a salt")?;
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        let content = format!("{}:{}\n", hex(&salt), hex(&hash(&salt, pin)));
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, content.as_bytes()))
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed to replace {}", self.path.display()))
+    }
+
+    pub fn verify(&self, pin: &str) -> bool {
+        let Ok(content) = std::fs::read_to_string(&self.path) else {
+            return false;
+        };
+        let Some((salt, stored)) = content.trim().split_once(':') else {
+            return false;
+        };
+        let Some(salt) = unhex(salt) else {
+            return false;
+        };
+        hex(&hash(&salt, pin)) == stored
+    }
+}
+
+fn hash(salt: &[u8], pin: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(pin.as_bytes());
+    hasher.finalize().into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
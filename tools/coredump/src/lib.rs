@@ -0,0 +1,261 @@
+// ABOUTME: Core dump storage shared by the mos-coredump helper and initd.
+// ABOUTME: Compressed cores with TOML metadata under /var/lib/mos/coredumps, capped in size.
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+pub const DUMP_DIR: &str = "/var/lib/mos/coredumps";
+
+/// What initd writes to /proc/sys/kernel/core_pattern: the helper gets the crashing
+/// process's pid in the initial namespace, the signal, the time and the command name.
+pub const CORE_PATTERN: &str = "|/usr/bin/mos-coredump %P %s %t %e";
+
+/// Cores are cut off after this much, before compression.
+pub const MAX_CORE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The oldest dumps are deleted once the stored ones take up more than this.
+pub const MAX_TOTAL_SIZE: u64 = 128 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dump {
+    /// The service the process belonged to, if it was one.
+    pub service: Option<String>,
+    pub command: String,
+    pub pid: u32,
+    pub signal: u32,
+    /// Seconds since the epoch.
+    pub timestamp: u64,
+    /// Bytes taken by the compressed core.
+    #[serde(default)]
+    pub size: u64,
+    /// The core was cut off at MAX_CORE_SIZE.
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+impl Dump {
+    /// The file name both the core and its metadata are stored under, less the extension.
+    pub fn name(&self) -> String {
+        let who = self.service.as_deref().unwrap_or(&self.command);
+        format!("{}-{who}-{}", self.timestamp, self.pid)
+    }
+}
+
+/// Compress `core` into `dir` along with `dump`'s metadata, then prune old dumps down to
+/// `max_total`. The metadata goes last, so a dump is only listed once it is complete.
+pub fn store(
+    dir: &Path,
+    mut dump: Dump,
+    mut core: impl Read,
+    max_core: u64,
+    max_total: u64,
+) -> Result<Dump> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let name = dump.name();
+
+    let core_path = dir.join(format!("{name}.core.gz"));
+    let file = std::fs::File::create(&core_path)
+        .with_context(|| format!("failed to create {}", core_path.display()))?;
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    std::io::copy(&mut core.by_ref().take(max_core), &mut encoder)
+        .with_context(|| format!("failed to write {}", core_path.display()))?;
+    encoder.finish()?.flush()?;
+    dump.truncated = core.read(&mut [0u8])? > 0;
+    dump.size = std::fs::metadata(&core_path)?.len();
+
+    let meta_path = dir.join(format!("{name}.toml"));
+    let content = toml::to_string(&dump).context("failed to serialize dump metadata")?;
+    std::fs::write(&meta_path, content)
+        .with_context(|| format!("failed to write {}", meta_path.display()))?;
+
+    prune(dir, max_total)?;
+    Ok(dump)
+}
+
+/// Stored dumps, oldest first. A missing directory has none.
+pub fn list(dir: &Path) -> Result<Vec<Dump>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+    let mut dumps: Vec<Dump> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|path| toml::from_str(&std::fs::read_to_string(path).ok()?).ok())
+        .collect();
+    dumps.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then(a.name().cmp(&b.name())));
+    Ok(dumps)
+}
+
+/// Delete the oldest dumps until the rest take up at most `max_total`. The newest is
+/// always kept. Returns the deleted ones.
+pub fn prune(dir: &Path, max_total: u64) -> Result<Vec<Dump>> {
+    let mut dumps = list(dir)?;
+    let mut total: u64 = dumps.iter().map(|dump| dump.size).sum();
+    let mut removed = Vec::new();
+    while total > max_total && dumps.len() > 1 {
+        let dump = dumps.remove(0);
+        for ext in ["toml", "core.gz"] {
+            let path = dir.join(format!("{}.{ext}", dump.name()));
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to remove {}", path.display()));
+                }
+            }
+        }
+        total -= dump.size;
+        removed.push(dump);
+    }
+    Ok(removed)
+}
+
+/// The conventional name of a signal that dumps core, e.g. "SIGSEGV".
+pub fn signal_name(signal: u32) -> Option<&'static str> {
+    Some(match signal {
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        11 => "SIGSEGV",
+        24 => "SIGXCPU",
+        25 => "SIGXFSZ",
+        31 => "SIGSYS",
+        _ => return None,
+    })
+}
+
+/// Which service `pid` belongs to: the one whose main process is `pid` or one of its
+/// ancestors, found by walking parent pids through `proc_dir`. `services` pairs each
+/// service's name with its main pid.
+pub fn service_of(proc_dir: &Path, pid: u32, services: &[(String, u32)]) -> Option<String> {
+    let mut current = pid;
+    // Bounded, in case /proc changes under us into a loop
+    for _ in 0..64 {
+        if let Some((name, _)) = services.iter().find(|(_, main)| *main == current) {
+            return Some(name.clone());
+        }
+        let stat = std::fs::read_to_string(proc_dir.join(current.to_string()).join("stat")).ok()?;
+        // The command name in parentheses may itself contain spaces and parentheses
+        let (_, rest) = stat.rsplit_once(')')?;
+        current = rest.split_whitespace().nth(1)?.parse().ok()?;
+        if current <= 1 {
+            return None;
+        }
+    }
+    None
+}
+
+/// Service main pids from initd's `status` reply, whose lines read "name State pid=N".
+pub fn parse_status(status: &str) -> Vec<(String, u32)> {
+    status
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let name = words.next()?;
+            let pid = words.find_map(|word| word.strip_prefix("pid="))?;
+            Some((name.to_string(), pid.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(service: Option<&str>, pid: u32, timestamp: u64) -> Dump {
+        Dump {
+            service: service.map(String::from),
+            command: "mos-modem".to_string(),
+            pid,
+            signal: 11,
+            timestamp,
+            size: 0,
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn stores_compressed_cores_and_prunes_the_oldest() {
+        let dir = tempfile::tempdir().unwrap();
+        let core = vec![7u8; 64 * 1024];
+
+        let first = store(
+            dir.path(),
+            dump(Some("modem"), 42, 100),
+            &core[..],
+            1 << 20,
+            1 << 20,
+        )
+        .unwrap();
+        assert!(!first.truncated);
+        assert!(first.size > 0 && first.size < core.len() as u64);
+        assert!(dir.path().join("100-modem-42.core.gz").exists());
+
+        let mut decoded = Vec::new();
+        let file = std::fs::File::open(dir.path().join("100-modem-42.core.gz")).unwrap();
+        flate2::read::GzDecoder::new(file)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, core);
+
+        let second = store(dir.path(), dump(None, 43, 200), &core[..], 1000, 1 << 20).unwrap();
+        assert!(second.truncated);
+        assert_eq!(list(dir.path()).unwrap(), [first.clone(), second.clone()]);
+
+        // Room for one dump only: the oldest goes
+        let third = store(
+            dir.path(),
+            dump(Some("audio"), 44, 300),
+            &core[..],
+            1 << 20,
+            first.size + 1,
+        )
+        .unwrap();
+        assert_eq!(list(dir.path()).unwrap(), [third]);
+        assert!(!dir.path().join("100-modem-42.core.gz").exists());
+
+        assert!(list(&dir.path().join("missing")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn finds_the_service_of_a_child_process() {
+        let dir = tempfile::tempdir().unwrap();
+        for (pid, ppid) in [(300, 200), (200, 1), (500, 1)] {
+            let proc_pid = dir.path().join(pid.to_string());
+            std::fs::create_dir(&proc_pid).unwrap();
+            std::fs::write(
+                proc_pid.join("stat"),
+                format!("{pid} (a (weird) name) S {ppid} {pid} 0 0"),
+            )
+            .unwrap();
+        }
+
+        let services =
+            parse_status("dbus Running pid=100\nmodem Running pid=200\nseatd Finished\n");
+        assert_eq!(
+            services,
+            [("dbus".to_string(), 100), ("modem".to_string(), 200)]
+        );
+
+        assert_eq!(
+            service_of(dir.path(), 300, &services).as_deref(),
+            Some("modem")
+        );
+        assert_eq!(
+            service_of(dir.path(), 200, &services).as_deref(),
+            Some("modem")
+        );
+        assert_eq!(service_of(dir.path(), 500, &services), None);
+        assert_eq!(service_of(dir.path(), 999, &services), None);
+    }
+}
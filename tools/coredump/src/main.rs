@@ -0,0 +1,71 @@
+// ABOUTME: mos-coredump — core_pattern pipe handler that stores crashing processes' cores.
+// ABOUTME: Started by the kernel with the core on stdin; asks initd which service crashed.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use anyhow::{Context, bail};
+
+use mos_coredump::{Dump, MAX_CORE_SIZE, MAX_TOTAL_SIZE};
+
+const CONTROL_SOCKET: &str = "/run/mos/initctl";
+
+fn main() {
+    if let Err(e) = run() {
+        log(&format!("failed to store core dump: {e:#}"));
+        std::process::exit(1);
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [pid, signal, timestamp, command] = args.as_slice() else {
+        bail!("usage: mos-coredump <pid> <signal> <timestamp> <command>");
+    };
+    let pid: u32 = pid.parse().context("invalid pid")?;
+
+    // Without initd, the dump is still kept, just without a service name
+    let services = service_pids().unwrap_or_default();
+    let dump = Dump {
+        service: mos_coredump::service_of(Path::new("/proc"), pid, &services),
+        command: command.clone(),
+        pid,
+        signal: signal.parse().context("invalid signal")?,
+        timestamp: timestamp.parse().context("invalid timestamp")?,
+        size: 0,
+        truncated: false,
+    };
+
+    let dump = mos_coredump::store(
+        Path::new(mos_coredump::DUMP_DIR),
+        dump,
+        std::io::stdin().lock(),
+        MAX_CORE_SIZE,
+        MAX_TOTAL_SIZE,
+    )?;
+    log(&format!(
+        "stored core dump {} ({} bytes{})",
+        dump.name(),
+        dump.size,
+        if dump.truncated { ", truncated" } else { "" }
+    ));
+    Ok(())
+}
+
+/// Main pids of the running services, from initd's status.
+fn service_pids() -> anyhow::Result<Vec<(String, u32)>> {
+    let mut stream = UnixStream::connect(CONTROL_SOCKET)?;
+    stream.write_all(b"status\n")?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut status = String::new();
+    stream.read_to_string(&mut status)?;
+    Ok(mos_coredump::parse_status(&status))
+}
+
+/// The kernel runs us without a stdout or stderr anyone reads, so log to the kernel log.
+fn log(message: &str) {
+    if let Ok(mut kmsg) = std::fs::OpenOptions::new().write(true).open("/dev/kmsg") {
+        let _ = writeln!(kmsg, "mos-coredump: {message}");
+    }
+}
@@ -18,9 +18,10 @@ echo "Profile:   $PROFILE"
 mkdir -p "$BUILD_DIR" "$KERNEL_DIR"
 
 # --- Step 1: Cross-compile initd + services ---
-SERVICES=(mos-power mos-audio mos-network mos-modem mos-sensors)
+SERVICES=(mos-power mos-audio mos-network mos-modem mos-sensors mos-leds mos-bridge mos-search mos-screenshot mos-wellbeing)
+TOOLS=(mosctl mos-coredump)
 PACKAGES=("-p" "mos-initd")
-for svc in "${SERVICES[@]}"; do
+for svc in "${SERVICES[@]}" "${TOOLS[@]}"; do
     PACKAGES+=("-p" "$svc")
 done
 
@@ -124,11 +125,16 @@ for svc in "${SERVICES[@]}"; do
 done
 echo "Installed ${#SERVICES[@]} service binaries to /usr/bin/"
 
+for tool in "${TOOLS[@]}"; do
+    cp "$BIN_DIR/$tool" "$INITRAMFS_DIR/usr/bin/$tool"
+done
+
 # Busybox and essential command symlinks
 cp "$BUSYBOX" "$INITRAMFS_DIR/bin/busybox"
-for cmd in sh ls cat echo mkdir mount umount ps kill sleep; do
+for cmd in sh ls cat echo mkdir mount umount ps kill sleep chgrp chmod; do
     ln -sf busybox "$INITRAMFS_DIR/bin/$cmd"
 done
+ln -sf ../bin/busybox "$INITRAMFS_DIR/sbin/getty"
 
 # Shared libraries for the dynamically linked init
 for lib in "${REQUIRED_LIBS[@]}"; do
@@ -137,32 +143,16 @@ done
 
 # dbus-daemon and musl libraries (Alpine musl-linked binaries)
 cp "$DBUS_DIR/usr/bin/dbus-daemon" "$INITRAMFS_DIR/usr/bin/"
+# initd pings services with it for their dbus_name health checks
+cp "$DBUS_DIR/usr/bin/dbus-send" "$INITRAMFS_DIR/usr/bin/"
 mkdir -p "$INITRAMFS_DIR/usr/share/dbus-1"
 cp -a "$DBUS_DIR/usr/share/dbus-1/"* "$INITRAMFS_DIR/usr/share/dbus-1/" 2>/dev/null || true
 # musl dynamic linker + shared libs for dbus-daemon
 for musl_lib in "$DBUS_DIR/lib/"*.so* "$DBUS_DIR/usr/lib/"*.so*; do
     [ -f "$musl_lib" ] && cp "$musl_lib" "$INITRAMFS_DIR/lib/"
 done
-# dbus session config
-mkdir -p "$INITRAMFS_DIR/etc/dbus-1"
-cat > "$INITRAMFS_DIR/etc/dbus-1/session.conf" << 'DBUSCONF'
-<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-BUS Bus Configuration 1.0//EN"
- "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
-<busconfig>
-  <type>custom</type>
-  <listen>unix:path=/run/dbus/session_bus_socket</listen>
-  <auth>EXTERNAL</auth>
-  <allow_anonymous/>
-  <policy context="default">
-    <allow send_destination="*" eavesdrop="true"/>
-    <allow eavesdrop="true"/>
-    <allow own="*"/>
-  </policy>
-</busconfig>
-DBUSCONF
-# Minimal passwd/group for dbus-daemon UID lookup
-echo "root:x:0:0:root:/root:/bin/sh" > "$INITRAMFS_DIR/etc/passwd"
-echo "root:x:0:" > "$INITRAMFS_DIR/etc/group"
+# The system bus config is written by initd at boot (initd/src/dbus.rs)
+# passwd/group (root plus service accounts) come from the rootfs overlay below
 
 # Overlay rootfs static files (service configs, etc.)
 if [ -d "$ROOT_DIR/rootfs" ]; then
@@ -170,6 +160,37 @@ if [ -d "$ROOT_DIR/rootfs" ]; then
     echo "Installed rootfs overlay"
 fi
 
+# Root has no password here, so the console getty goes straight to a shell
+cat >> "$INITRAMFS_DIR/etc/mos/getty.toml" << 'GETTY'
+login = "/bin/sh"
+GETTY
+
+# Optional virtual modem: with VMODEM_SOCKET set, the modem service talks to
+# tools/vmodem on the host over a virtio-serial port. Start it first with
+#   cargo run -p vmodem -- --socket "$VMODEM_SOCKET" [--script FILE]
+QEMU_EXTRA=()
+if [ -n "${VMODEM_SOCKET:-}" ]; then
+    mkdir -p "$INITRAMFS_DIR/etc/mos/env"
+    echo "MOS_MODEM_PORT=/dev/vport0p1" > "$INITRAMFS_DIR/etc/mos/env/modem.env"
+    # devtmpfs creates the port root-only; the modem service runs as modem:dialout
+    cat > "$INITRAMFS_DIR/etc/mos/services/12-vmodem-port.toml" << 'VMODEM'
+[service]
+name = "vmodem-port"
+exec = "/bin/sh"
+args = ["-c", "chgrp dialout /dev/vport0p1 && chmod 660 /dev/vport0p1"]
+service_type = "oneshot"
+VMODEM
+    sed -i -e 's/^depends_on = \["dbus"\]$/depends_on = ["dbus", "vmodem-port"]/' \
+        -e 's|^path_exists = \[|path_exists = ["/dev/vport0p1", |' \
+        "$INITRAMFS_DIR/etc/mos/services/13-modem.toml"
+    QEMU_EXTRA+=(
+        -device virtio-serial-device
+        -chardev "socket,id=modem,path=$VMODEM_SOCKET"
+        -device virtserialport,chardev=modem,name=modem
+    )
+    echo "Virtual modem on $VMODEM_SOCKET"
+fi
+
 # Minimal /dev nodes for early boot (devtmpfs takes over once mounted)
 pushd "$INITRAMFS_DIR/dev" > /dev/null
 mknod -m 622 console c 5 1 2>/dev/null || true
@@ -201,4 +222,5 @@ exec qemu-system-aarch64 \
     -kernel "$KERNEL_IMAGE" \
     -initrd "$INITRAMFS_CPIO" \
     -append "console=ttyAMA0 rdinit=/init" \
-    -no-reboot
+    -no-reboot \
+    "${QEMU_EXTRA[@]}"
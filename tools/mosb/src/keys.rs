@@ -0,0 +1,138 @@
+// ABOUTME: Host keys for the device bridge: an Ed25519 keypair per host, and the challenges it signs.
+// ABOUTME: The device only ever sees the public key and a signature over a fresh nonce, so a session can't be replayed.
+
+use std::fs::File;
+use std::io::{self, Read};
+
+use anyhow::{bail, Context};
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+/// Bytes of the nonce the device challenges each session with.
+pub const NONCE_LEN: usize = 32;
+
+/// Signed ahead of the nonce, so a bridge signature can't be passed off as anything else.
+const CHALLENGE_CONTEXT: &[u8] = b"mosb challenge v1\0";
+
+/// A host's private key. Only its public half, in hex, goes over the link.
+pub struct HostKey(SigningKey);
+
+impl HostKey {
+    /// A new key from the kernel's random source.
+    pub fn generate() -> io::Result<Self> {
+        let mut seed = [0u8; 32];
+        File::open("/dev/urandom")?.read_exact(&mut seed)?;
+        Ok(Self(SigningKey::from_bytes(&seed)))
+    }
+
+    /// A key saved with `to_hex`.
+    pub fn from_hex(hex: &str) -> anyhow::Result<Self> {
+        let seed = decode_hex(hex.trim())?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("host key is not 32 bytes"))?;
+        Ok(Self(SigningKey::from_bytes(&seed)))
+    }
+
+    pub fn to_hex(&self) -> String {
+        encode_hex(self.0.as_bytes())
+    }
+
+    /// The public key the device authorizes, in hex.
+    pub fn public_key(&self) -> String {
+        encode_hex(self.0.verifying_key().as_bytes())
+    }
+
+    /// Answer the device's challenge.
+    pub fn sign(&self, nonce: &[u8]) -> Vec<u8> {
+        self.0.sign(&challenge(nonce)).to_bytes().to_vec()
+    }
+}
+
+/// Whether `signature` is `public_key`'s answer to `nonce`.
+pub fn verify(public_key: &str, nonce: &[u8], signature: &[u8]) -> bool {
+    let Ok(Ok(bytes)) = decode_hex(public_key).map(<[u8; 32]>::try_from) else {
+        return false;
+    };
+    let (Ok(key), Ok(signature)) = (
+        VerifyingKey::from_bytes(&bytes),
+        Signature::from_slice(signature),
+    ) else {
+        return false;
+    };
+    key.verify_strict(&challenge(nonce), &signature).is_ok()
+}
+
+/// A fresh nonce for one session.
+pub fn nonce() -> io::Result<[u8; NONCE_LEN]> {
+    let mut nonce = [0u8; NONCE_LEN];
+    File::open("/dev/urandom")?.read_exact(&mut nonce)?;
+    Ok(nonce)
+}
+
+/// What the user compares on the device and the host, in the `SHA256:` form ssh uses.
+pub fn fingerprint(public_key: &str) -> String {
+    let digest = Sha256::digest(public_key.as_bytes());
+    format!(
+        "SHA256:{}",
+        base64::engine::general_purpose::STANDARD_NO_PAD.encode(digest)
+    )
+}
+
+fn challenge(nonce: &[u8]) -> Vec<u8> {
+    [CHALLENGE_CONTEXT, nonce].concat()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        bail!("invalid hex");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).context("invalid hex"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_challenges_verify_once() {
+        let key = HostKey::generate().unwrap();
+        let nonce = nonce().unwrap();
+        let signature = key.sign(&nonce);
+        assert!(verify(&key.public_key(), &nonce, &signature));
+
+        // Another nonce, another key, or a mangled key all fail
+        let other_nonce = self::nonce().unwrap();
+        assert!(!verify(&key.public_key(), &other_nonce, &signature));
+        let other = HostKey::generate().unwrap();
+        assert!(!verify(&other.public_key(), &nonce, &signature));
+        assert!(!verify("not hex", &nonce, &signature));
+        assert!(!verify(&key.public_key(), &nonce, &signature[1..]));
+    }
+
+    #[test]
+    fn keys_round_trip_through_hex() {
+        let key = HostKey::generate().unwrap();
+        let loaded = HostKey::from_hex(&format!("{}\n", key.to_hex())).unwrap();
+        assert_eq!(loaded.public_key(), key.public_key());
+        assert!(HostKey::from_hex("abcd").is_err());
+        assert!(HostKey::from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn fingerprints_are_sha256() {
+        // echo -n abc | sha256sum, in base64
+        assert_eq!(
+            fingerprint("abc"),
+            "SHA256:ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0"
+        );
+        assert_ne!(fingerprint("abc"), fingerprint("abd"));
+    }
+}
@@ -0,0 +1,6 @@
+// ABOUTME: Shared pieces of the MobileOS device bridge.
+// ABOUTME: Used by the mosb host CLI and by the mos-bridge daemon on the device.
+
+pub mod keys;
+pub mod protocol;
+pub mod serial;
@@ -0,0 +1,512 @@
+// ABOUTME: mosb — host-side client for the MobileOS device bridge.
+// ABOUTME: Runs shells, transfers files, streams logs, installs and runs apps, and forwards ports.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context};
+use mosb::keys::HostKey;
+use mosb::protocol::{read_message, write_message, Message, CHUNK_SIZE, DEFAULT_PORT};
+
+const USAGE: &str = "\
+usage: mosb [-t HOST[:PORT] | -s TTY] <command> [args...]
+
+commands:
+  shell [command...]                  run a command, or an interactive shell
+  push <local> <remote>               copy a file to the device
+  pull <remote> <local>               copy a file from the device
+  logs [-f] <service>                 show a service's journal, -f to follow
+  install <binary> [name]             install an app binary on the device
+  launch <app>                        (re)start an installed app and show its output
+  run [--release] <crate>             cross-compile, install, and launch an app crate
+  forward <local-port> <remote-port>  relay a local TCP port to the device
+
+The device defaults to $MOSB_DEVICE (a HOST[:PORT] or a /dev tty path),
+falling back to 127.0.0.1. `run` builds for $MOSB_TARGET, default
+aarch64-unknown-linux-gnu.";
+
+const DEFAULT_TRIPLE: &str = "aarch64-unknown-linux-gnu";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Tcp(String),
+    Serial(PathBuf),
+}
+
+impl Target {
+    fn parse(spec: &str) -> Self {
+        if spec.starts_with('/') {
+            Target::Serial(PathBuf::from(spec))
+        } else if spec.contains(':') {
+            Target::Tcp(spec.to_string())
+        } else {
+            Target::Tcp(format!("{spec}:{DEFAULT_PORT}"))
+        }
+    }
+}
+
+/// A connection to the device; either transport reads and writes the same frames.
+enum Link {
+    Tcp(TcpStream),
+    Serial(File),
+}
+
+impl Link {
+    fn try_clone(&self) -> std::io::Result<Link> {
+        Ok(match self {
+            Link::Tcp(s) => Link::Tcp(s.try_clone()?),
+            Link::Serial(f) => Link::Serial(f.try_clone()?),
+        })
+    }
+}
+
+impl Read for Link {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Link::Tcp(s) => s.read(buf),
+            Link::Serial(f) => f.read(buf),
+        }
+    }
+}
+
+impl Write for Link {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Link::Tcp(s) => s.write(buf),
+            Link::Serial(f) => f.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Link::Tcp(s) => s.flush(),
+            Link::Serial(f) => f.flush(),
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    let mut target = Target::parse(
+        &std::env::var("MOSB_DEVICE").unwrap_or_else(|_| "127.0.0.1".to_string()),
+    );
+    while let Some(flag) = args.first().filter(|a| *a == "-t" || *a == "-s").cloned() {
+        if args.len() < 2 {
+            bail!("{flag} requires an argument\n\n{USAGE}");
+        }
+        let value = args.remove(1);
+        args.remove(0);
+        target = match flag.as_str() {
+            "-s" => Target::Serial(PathBuf::from(value)),
+            _ => Target::parse(&value),
+        };
+    }
+
+    let Some(command) = args.first().cloned() else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+    let rest: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+
+    match (command.as_str(), rest.as_slice()) {
+        ("-h" | "--help" | "help", _) => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        ("shell", words) => {
+            let code = shell(&target, &words.join(" "))?;
+            std::process::exit(code);
+        }
+        ("push", [local, remote]) => push(&target, Path::new(local), remote),
+        ("pull", [remote, local]) => pull(&target, remote, Path::new(local)),
+        ("logs", ["-f", service]) => logs(&target, service, true),
+        ("logs", [service]) => logs(&target, service, false),
+        ("install", [binary]) => install(&target, Path::new(binary), None),
+        ("install", [binary, name]) => install(&target, Path::new(binary), Some(name)),
+        ("launch", [app]) => {
+            let code = launch(&target, app)?;
+            std::process::exit(code);
+        }
+        ("run", ["--release", package]) | ("run", [package, "--release"]) => {
+            let code = run(&target, package, true)?;
+            std::process::exit(code);
+        }
+        ("run", [package]) => {
+            let code = run(&target, package, false)?;
+            std::process::exit(code);
+        }
+        ("forward", [local, remote]) => forward(
+            &target,
+            local.parse().context("invalid local port")?,
+            remote.parse().context("invalid remote port")?,
+        ),
+        _ => bail!("invalid command line\n\n{USAGE}"),
+    }
+}
+
+/// Connect to the device and authenticate. Blocks while the device shows its prompt.
+fn open_session(target: &Target) -> anyhow::Result<Link> {
+    let mut link = match target {
+        Target::Tcp(addr) => Link::Tcp(
+            TcpStream::connect(addr).with_context(|| format!("failed to connect to {addr}"))?,
+        ),
+        Target::Serial(path) => Link::Serial(
+            mosb::serial::open_raw(path)
+                .with_context(|| format!("failed to open {}", path.display()))?,
+        ),
+    };
+
+    let key = load_or_create_key()?;
+    let hello = Message::Hello {
+        host: host_name(),
+        public_key: key.public_key(),
+    };
+    write_message(&mut link, &hello)?;
+
+    let nonce = match read_message(&mut link)? {
+        Some(Message::Challenge(nonce)) => nonce,
+        Some(Message::Error(e)) => bail!("device refused connection: {e}"),
+        other => bail!("unexpected reply from device: {other:?}"),
+    };
+    write_message(&mut link, &Message::Proof(key.sign(&nonce)))?;
+
+    match read_message(&mut link)? {
+        Some(Message::Ready) => Ok(link),
+        Some(Message::Error(e)) => bail!("device refused connection: {e}"),
+        other => bail!("unexpected reply from device: {other:?}"),
+    }
+}
+
+fn shell(target: &Target, command: &str) -> anyhow::Result<i32> {
+    let mut link = open_session(target)?;
+    write_message(
+        &mut link,
+        &Message::Shell {
+            command: command.to_string(),
+        },
+    )?;
+
+    let mut input = link.try_clone()?;
+    std::thread::spawn(move || {
+        let _ = pump(&mut std::io::stdin(), &mut input);
+    });
+
+    loop {
+        match read_message(&mut link)? {
+            Some(Message::Data(data)) => {
+                let mut out = std::io::stdout();
+                out.write_all(&data)?;
+                out.flush()?;
+            }
+            Some(Message::Stderr(data)) => std::io::stderr().write_all(&data)?,
+            Some(Message::Exit(code)) => return Ok(code),
+            Some(Message::Error(e)) => bail!("{e}"),
+            Some(other) => bail!("unexpected message from device: {other:?}"),
+            None => bail!("device closed the connection"),
+        }
+    }
+}
+
+fn push(target: &Target, local: &Path, remote: &str) -> anyhow::Result<()> {
+    let mut file =
+        File::open(local).with_context(|| format!("failed to open {}", local.display()))?;
+    let mode = file.metadata()?.permissions().mode() & 0o7777;
+
+    let mut path = remote.to_string();
+    if path.ends_with('/')
+        && let Some(name) = local.file_name()
+    {
+        path.push_str(&name.to_string_lossy());
+    }
+
+    let mut link = open_session(target)?;
+    write_message(&mut link, &Message::Push { path, mode })?;
+    pump(&mut file, &mut link)?;
+    expect_done(&mut link)
+}
+
+fn pull(target: &Target, remote: &str, local: &Path) -> anyhow::Result<()> {
+    let mut link = open_session(target)?;
+    write_message(
+        &mut link,
+        &Message::Pull {
+            path: remote.to_string(),
+        },
+    )?;
+
+    let mut file = None;
+    loop {
+        match read_message(&mut link)? {
+            Some(Message::Data(data)) => {
+                if file.is_none() {
+                    file = Some(File::create(local).with_context(|| {
+                        format!("failed to create {}", local.display())
+                    })?);
+                }
+                file.as_mut().unwrap().write_all(&data)?;
+            }
+            Some(Message::Eof) => {
+                if file.is_none() {
+                    File::create(local)?;
+                }
+                return Ok(());
+            }
+            Some(Message::Error(e)) => bail!("{e}"),
+            Some(other) => bail!("unexpected message from device: {other:?}"),
+            None => bail!("device closed the connection"),
+        }
+    }
+}
+
+fn logs(target: &Target, service: &str, follow: bool) -> anyhow::Result<()> {
+    let mut link = open_session(target)?;
+    write_message(
+        &mut link,
+        &Message::Logs {
+            service: service.to_string(),
+            follow,
+        },
+    )?;
+
+    let mut out = std::io::stdout();
+    loop {
+        match read_message(&mut link)? {
+            Some(Message::Data(data)) => {
+                out.write_all(&data)?;
+                out.flush()?;
+            }
+            Some(Message::Eof) | None => return Ok(()),
+            Some(Message::Error(e)) => bail!("{e}"),
+            Some(other) => bail!("unexpected message from device: {other:?}"),
+        }
+    }
+}
+
+fn install(target: &Target, binary: &Path, name: Option<&str>) -> anyhow::Result<()> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => binary
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .context("binary path has no file name")?,
+    };
+    let mut file =
+        File::open(binary).with_context(|| format!("failed to open {}", binary.display()))?;
+
+    let mut link = open_session(target)?;
+    write_message(&mut link, &Message::Install { name: name.clone() })?;
+    pump(&mut file, &mut link)?;
+    expect_done(&mut link)?;
+    println!("installed {name}");
+    Ok(())
+}
+
+/// Restart an installed app and stream its output until it exits. Returns its exit code.
+fn launch(target: &Target, app: &str) -> anyhow::Result<i32> {
+    let mut link = open_session(target)?;
+    write_message(
+        &mut link,
+        &Message::Launch {
+            name: app.to_string(),
+        },
+    )?;
+
+    loop {
+        match read_message(&mut link)? {
+            Some(Message::Data(data)) => {
+                let mut out = std::io::stdout();
+                out.write_all(&data)?;
+                out.flush()?;
+            }
+            Some(Message::Stderr(data)) => std::io::stderr().write_all(&data)?,
+            Some(Message::Exit(code)) => return Ok(code),
+            Some(Message::Eof) | None => return Ok(0),
+            Some(Message::Error(e)) => bail!("{e}"),
+            Some(other) => bail!("unexpected message from device: {other:?}"),
+        }
+    }
+}
+
+/// The edit-run loop for app developers: build the crate for the device, install the
+/// binary under the crate's name, restart it, and stream its output back.
+fn run(target: &Target, package: &str, release: bool) -> anyhow::Result<i32> {
+    let triple = std::env::var("MOSB_TARGET").unwrap_or_else(|_| DEFAULT_TRIPLE.to_string());
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+
+    let mut build = Command::new(&cargo);
+    build.args(["build", "-p", package, "--target", &triple]);
+    if release {
+        build.arg("--release");
+    }
+    let status = build.status().context("failed to run cargo")?;
+    if !status.success() {
+        bail!("build of {package} failed");
+    }
+
+    let profile = if release { "release" } else { "debug" };
+    let binary = target_dir(&cargo)?.join(&triple).join(profile).join(package);
+    install(target, &binary, Some(package))?;
+    launch(target, package)
+}
+
+fn target_dir(cargo: &str) -> anyhow::Result<PathBuf> {
+    if let Ok(dir) = std::env::var("CARGO_TARGET_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    let output = Command::new(cargo)
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .output()
+        .context("failed to run cargo metadata")?;
+    parse_target_dir(&String::from_utf8_lossy(&output.stdout))
+        .context("cargo metadata did not report a target directory")
+}
+
+/// Pull `target_directory` out of `cargo metadata` JSON without a JSON parser.
+fn parse_target_dir(metadata: &str) -> Option<PathBuf> {
+    let key = "\"target_directory\":\"";
+    let start = metadata.find(key)? + key.len();
+    let end = start + metadata[start..].find('"')?;
+    Some(PathBuf::from(metadata[start..end].replace("\\\\", "\\")))
+}
+
+fn forward(target: &Target, local_port: u16, remote_port: u16) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", local_port))
+        .with_context(|| format!("failed to listen on port {local_port}"))?;
+    println!("forwarding 127.0.0.1:{local_port} -> device:{remote_port}");
+
+    for client in listener.incoming() {
+        let client = client?;
+        let target = target.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = relay(&target, client, remote_port) {
+                eprintln!("mosb: forward: {e:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn relay(target: &Target, mut client: TcpStream, port: u16) -> anyhow::Result<()> {
+    let mut link = open_session(target)?;
+    write_message(&mut link, &Message::Forward { port })?;
+    expect_done(&mut link)?;
+
+    let mut upstream = link.try_clone()?;
+    let mut client_in = client.try_clone()?;
+    std::thread::spawn(move || {
+        let _ = pump(&mut client_in, &mut upstream);
+    });
+
+    loop {
+        match read_message(&mut link)? {
+            Some(Message::Data(data)) => client.write_all(&data)?,
+            Some(Message::Eof) | None => break,
+            Some(Message::Error(e)) => bail!("{e}"),
+            Some(other) => bail!("unexpected message from device: {other:?}"),
+        }
+    }
+    let _ = client.shutdown(std::net::Shutdown::Both);
+    Ok(())
+}
+
+/// Copy a local reader to the device as `Data` frames, finishing with `Eof`.
+fn pump<R: Read>(reader: &mut R, link: &mut Link) -> std::io::Result<()> {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return write_message(link, &Message::Eof);
+        }
+        write_message(link, &Message::Data(buf[..n].to_vec()))?;
+    }
+}
+
+fn expect_done(link: &mut Link) -> anyhow::Result<()> {
+    match read_message(link)? {
+        Some(Message::Done) => Ok(()),
+        Some(Message::Error(e)) => bail!("{e}"),
+        other => bail!("unexpected reply from device: {other:?}"),
+    }
+}
+
+fn host_name() -> String {
+    rustix::system::uname()
+        .nodename()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The host's bridge key, generated on first use. The device remembers the public keys
+/// it has authorized.
+fn load_or_create_key() -> anyhow::Result<HostKey> {
+    let home = std::env::var("HOME").context("HOME is not set")?;
+    let path = Path::new(&home).join(".config/mosb/host_key");
+    if let Ok(key) = std::fs::read_to_string(&path) {
+        return HostKey::from_hex(&key).with_context(|| format!("invalid {}", path.display()));
+    }
+
+    let key = HostKey::generate()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut f| writeln!(f, "{}", key.to_hex()))
+        .with_context(|| format!("failed to save key to {}", path.display()))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_parsing() {
+        assert_eq!(
+            Target::parse("/dev/ttyACM0"),
+            Target::Serial(PathBuf::from("/dev/ttyACM0"))
+        );
+        assert_eq!(
+            Target::parse("192.168.1.20"),
+            Target::Tcp(format!("192.168.1.20:{DEFAULT_PORT}"))
+        );
+        assert_eq!(
+            Target::parse("phone.local:6000"),
+            Target::Tcp("phone.local:6000".to_string())
+        );
+    }
+
+    #[test]
+    fn target_dir_from_metadata() {
+        let metadata = r#"{"packages":[],"target_directory":"/home/dev/mobileOS/target","version":1}"#;
+        assert_eq!(
+            parse_target_dir(metadata),
+            Some(PathBuf::from("/home/dev/mobileOS/target"))
+        );
+        assert_eq!(parse_target_dir("{}"), None);
+    }
+
+    #[test]
+    fn pump_sends_data_then_eof() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut link = Link::Tcp(TcpStream::connect(addr).unwrap());
+        let (mut peer, _) = listener.accept().unwrap();
+
+        pump(&mut b"hello".as_slice(), &mut link).unwrap();
+
+        assert_eq!(
+            read_message(&mut peer).unwrap(),
+            Some(Message::Data(b"hello".to_vec()))
+        );
+        assert_eq!(read_message(&mut peer).unwrap(), Some(Message::Eof));
+    }
+}
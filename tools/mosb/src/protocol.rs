@@ -0,0 +1,337 @@
+// ABOUTME: Wire protocol spoken between the mosb host CLI and the on-device bridge daemon.
+// ABOUTME: Length-prefixed frames carrying a tag byte and a small, hand-rolled payload encoding.
+
+use std::io::{self, Read, Write};
+
+/// TCP port the bridge daemon listens on.
+pub const DEFAULT_PORT: u16 = 5557;
+
+/// Largest data chunk sent in one `Data`/`Stderr` frame.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Frame header: one tag byte followed by a little-endian u32 payload length.
+pub const HEADER_LEN: usize = 5;
+
+/// Upper bound on payload size, so a corrupt header can't make us allocate gigabytes.
+const MAX_PAYLOAD: usize = CHUNK_SIZE + 4096;
+
+/// A session is `Hello` -> `Challenge` -> `Proof` -> `Ready`, then one command and the
+/// frames it exchanges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Host introduces itself with its name and the public half of its host key, in hex.
+    Hello { host: String, public_key: String },
+    /// Host's signature over the nonce of the device's `Challenge`.
+    Proof(Vec<u8>),
+    /// Run a command through `sh -c`; an empty command starts an interactive shell.
+    Shell { command: String },
+    /// Upload a file; followed by `Data` frames and `Eof`.
+    Push { path: String, mode: u32 },
+    /// Download a file; answered with `Data` frames and `Eof`.
+    Pull { path: String },
+    /// Stream a service's journal; with `follow`, keep streaming until the host sends `Eof`.
+    Logs { service: String, follow: bool },
+    /// Install an app binary; followed by `Data` frames and `Eof`.
+    Install { name: String },
+    /// Connect to a TCP port on the device and relay bytes in both directions.
+    Forward { port: u16 },
+    /// (Re)start an installed app and stream its output until it exits or the host sends `Eof`.
+    Launch { name: String },
+    /// A fresh nonce for the host to sign, proving it holds the key it named.
+    Challenge(Vec<u8>),
+    /// Device accepted the host.
+    Ready,
+    /// Payload bytes: file contents, stdin/stdout, or forwarded traffic.
+    Data(Vec<u8>),
+    /// Standard error of a shell command.
+    Stderr(Vec<u8>),
+    /// End of the current data stream.
+    Eof,
+    /// Shell command exited with this status.
+    Exit(i32),
+    /// Command completed successfully.
+    Done,
+    /// Command failed; the session ends.
+    Error(String),
+}
+
+mod tag {
+    pub const HELLO: u8 = 1;
+    pub const SHELL: u8 = 2;
+    pub const PUSH: u8 = 3;
+    pub const PULL: u8 = 4;
+    pub const LOGS: u8 = 5;
+    pub const INSTALL: u8 = 6;
+    pub const FORWARD: u8 = 7;
+    pub const LAUNCH: u8 = 8;
+    pub const PROOF: u8 = 9;
+    pub const READY: u8 = 20;
+    pub const DATA: u8 = 21;
+    pub const STDERR: u8 = 22;
+    pub const EOF: u8 = 23;
+    pub const EXIT: u8 = 24;
+    pub const DONE: u8 = 25;
+    pub const ERROR: u8 = 26;
+    pub const CHALLENGE: u8 = 27;
+}
+
+impl Message {
+    /// Serialize into a complete frame, header included.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let tag = match self {
+            Message::Hello { host, public_key } => {
+                put_str(&mut payload, host);
+                put_str(&mut payload, public_key);
+                tag::HELLO
+            }
+            Message::Proof(signature) => {
+                payload.extend_from_slice(signature);
+                tag::PROOF
+            }
+            Message::Shell { command } => {
+                put_str(&mut payload, command);
+                tag::SHELL
+            }
+            Message::Push { path, mode } => {
+                put_str(&mut payload, path);
+                payload.extend_from_slice(&mode.to_le_bytes());
+                tag::PUSH
+            }
+            Message::Pull { path } => {
+                put_str(&mut payload, path);
+                tag::PULL
+            }
+            Message::Logs { service, follow } => {
+                put_str(&mut payload, service);
+                payload.push(u8::from(*follow));
+                tag::LOGS
+            }
+            Message::Install { name } => {
+                put_str(&mut payload, name);
+                tag::INSTALL
+            }
+            Message::Forward { port } => {
+                payload.extend_from_slice(&port.to_le_bytes());
+                tag::FORWARD
+            }
+            Message::Launch { name } => {
+                put_str(&mut payload, name);
+                tag::LAUNCH
+            }
+            Message::Challenge(nonce) => {
+                payload.extend_from_slice(nonce);
+                tag::CHALLENGE
+            }
+            Message::Ready => tag::READY,
+            Message::Data(data) => {
+                payload.extend_from_slice(data);
+                tag::DATA
+            }
+            Message::Stderr(data) => {
+                payload.extend_from_slice(data);
+                tag::STDERR
+            }
+            Message::Eof => tag::EOF,
+            Message::Exit(code) => {
+                payload.extend_from_slice(&code.to_le_bytes());
+                tag::EXIT
+            }
+            Message::Done => tag::DONE,
+            Message::Error(message) => {
+                put_str(&mut payload, message);
+                tag::ERROR
+            }
+        };
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.push(tag);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    /// Parse a payload previously announced by `parse_header`.
+    pub fn decode(tag: u8, payload: &[u8]) -> io::Result<Message> {
+        let mut cur = Cursor { buf: payload };
+        let message = match tag {
+            tag::HELLO => Message::Hello {
+                host: cur.string()?,
+                public_key: cur.string()?,
+            },
+            tag::PROOF => Message::Proof(cur.rest()),
+            tag::SHELL => Message::Shell {
+                command: cur.string()?,
+            },
+            tag::PUSH => Message::Push {
+                path: cur.string()?,
+                mode: u32::from_le_bytes(cur.array()?),
+            },
+            tag::PULL => Message::Pull { path: cur.string()? },
+            tag::LOGS => Message::Logs {
+                service: cur.string()?,
+                follow: cur.array::<1>()?[0] != 0,
+            },
+            tag::INSTALL => Message::Install { name: cur.string()? },
+            tag::FORWARD => Message::Forward {
+                port: u16::from_le_bytes(cur.array()?),
+            },
+            tag::LAUNCH => Message::Launch { name: cur.string()? },
+            tag::CHALLENGE => Message::Challenge(cur.rest()),
+            tag::READY => Message::Ready,
+            tag::DATA => Message::Data(cur.rest()),
+            tag::STDERR => Message::Stderr(cur.rest()),
+            tag::EOF => Message::Eof,
+            tag::EXIT => Message::Exit(i32::from_le_bytes(cur.array()?)),
+            tag::DONE => Message::Done,
+            tag::ERROR => Message::Error(cur.string()?),
+            other => return Err(invalid(format!("unknown message tag {other}"))),
+        };
+
+        if !cur.buf.is_empty() {
+            return Err(invalid("trailing bytes in frame".to_string()));
+        }
+        Ok(message)
+    }
+}
+
+/// Split a frame header into its tag and payload length.
+pub fn parse_header(header: &[u8; HEADER_LEN]) -> io::Result<(u8, usize)> {
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_PAYLOAD {
+        return Err(invalid(format!("frame of {len} bytes exceeds limit")));
+    }
+    Ok((header[0], len))
+}
+
+/// Read one message. Returns `None` on a clean end of stream between frames.
+pub fn read_message<R: Read>(reader: &mut R) -> io::Result<Option<Message>> {
+    let mut header = [0u8; HEADER_LEN];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let (tag, len) = parse_header(&header)?;
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    Message::decode(tag, &payload).map(Some)
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Message) -> io::Result<()> {
+    writer.write_all(&message.encode())?;
+    writer.flush()
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+}
+
+impl Cursor<'_> {
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        if self.buf.len() < N {
+            return Err(invalid("truncated frame".to_string()));
+        }
+        let (head, tail) = self.buf.split_at(N);
+        self.buf = tail;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = u32::from_le_bytes(self.array()?) as usize;
+        if self.buf.len() < len {
+            return Err(invalid("truncated string".to_string()));
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        String::from_utf8(head.to_vec()).map_err(|_| invalid("string is not UTF-8".to_string()))
+    }
+
+    fn rest(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf).to_vec()
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: Message) {
+        let frame = message.encode();
+        let decoded = read_message(&mut frame.as_slice()).unwrap().unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        round_trip(Message::Hello {
+            host: "laptop".to_string(),
+            public_key: "abc123".to_string(),
+        });
+        round_trip(Message::Challenge(vec![7; 32]));
+        round_trip(Message::Proof(vec![1; 64]));
+        round_trip(Message::Shell {
+            command: "ls -l /".to_string(),
+        });
+        round_trip(Message::Push {
+            path: "/data/tmp/x".to_string(),
+            mode: 0o755,
+        });
+        round_trip(Message::Pull {
+            path: "/etc/mos/rootfs.toml".to_string(),
+        });
+        round_trip(Message::Logs {
+            service: "modem".to_string(),
+            follow: true,
+        });
+        round_trip(Message::Install {
+            name: "hello".to_string(),
+        });
+        round_trip(Message::Forward { port: 8080 });
+        round_trip(Message::Launch {
+            name: "mos-dialer".to_string(),
+        });
+        round_trip(Message::Ready);
+        round_trip(Message::Data(vec![0, 1, 2, 255]));
+        round_trip(Message::Stderr(b"oops\n".to_vec()));
+        round_trip(Message::Eof);
+        round_trip(Message::Exit(-1));
+        round_trip(Message::Done);
+        round_trip(Message::Error("denied".to_string()));
+    }
+
+    #[test]
+    fn consecutive_frames_are_read_in_order() {
+        let mut stream = Message::Ready.encode();
+        stream.extend(Message::Exit(3).encode());
+        let mut reader = stream.as_slice();
+
+        assert_eq!(read_message(&mut reader).unwrap(), Some(Message::Ready));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(Message::Exit(3)));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected() {
+        let mut header = [tag::DATA, 0, 0, 0, 0];
+        header[1..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse_header(&header).is_err());
+    }
+
+    #[test]
+    fn truncated_and_unknown_frames_are_rejected() {
+        assert!(Message::decode(tag::EXIT, &[1, 2]).is_err());
+        assert!(Message::decode(tag::PULL, &[10, 0, 0, 0, b'a']).is_err());
+        assert!(Message::decode(99, &[]).is_err());
+        assert!(Message::decode(tag::DONE, &[0]).is_err());
+    }
+}
@@ -0,0 +1,17 @@
+// ABOUTME: Serial link setup for the USB CDC-ACM bridge transport.
+// ABOUTME: Puts the tty into raw mode so frames pass through without echo or line editing.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use rustix::termios::{tcgetattr, tcsetattr, OptionalActions};
+
+/// Open a tty read-write and switch it to raw mode.
+pub fn open_raw(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut termios = tcgetattr(&file)?;
+    termios.make_raw();
+    tcsetattr(&file, OptionalActions::Now, &termios)?;
+    Ok(file)
+}
@@ -0,0 +1,90 @@
+// ABOUTME: mosctl — command-line client for the MobileOS init system.
+// ABOUTME: Sends a one-line request to the initd control socket and prints the reply.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+use anyhow::{bail, Context};
+
+const CONTROL_SOCKET: &str = "/run/mos/initctl";
+
+const USAGE: &str = "\
+usage: mosctl [--socket PATH] <command> [args...]
+
+commands:
+  status                 list services and their state
+  logs <service> [N]     show captured output of a service (last N lines)
+  logs --kernel [N]      show kernel messages and all service output as one boot log
+  timers                 list timers with their next and last run
+  top                    list running services by CPU time used, with their memory
+  boot-analyze           show how long boot took, which services held it up, and failures
+  boot-progress          print how many services have started out of those booting
+  dumps                  list core dumps of crashed processes
+  dev-mode [on|off]      show or set writable overlay mode (applies on next boot)
+  inhibit <seconds> <who> [reason...]
+                         delay shutdown for up to <seconds>; prints the inhibitor id
+  uninhibit <id>         release a shutdown inhibitor
+  inhibitors             list who is holding back shutdown
+  rescue                 show why init is in rescue mode, if it is
+  reboot [normal|bootloader|recovery] [reason...]
+                         reboot, into the bootloader or recovery if asked";
+
+fn main() -> anyhow::Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    let mut socket = CONTROL_SOCKET.to_string();
+    if args.first().is_some_and(|a| a == "--socket") {
+        if args.len() < 2 {
+            bail!("--socket requires a path\n\n{USAGE}");
+        }
+        socket = args.remove(1);
+        args.remove(0);
+    }
+
+    if args.is_empty() || args[0] == "--help" || args[0] == "-h" {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let response = send_request(&socket, &build_request(&args))?;
+    print!("{response}");
+
+    if response.starts_with("error:") {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Join command-line words into the single-line request format initd expects.
+fn build_request(args: &[String]) -> String {
+    format!("{}\n", args.join(" "))
+}
+
+fn send_request(socket: &str, request: &str) -> anyhow::Result<String> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("failed to connect to init control socket {socket}"))?;
+    stream.write_all(request.as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .context("failed to read response from init")?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_joins_words_on_one_line() {
+        let args = vec!["logs".to_string(), "modem".to_string(), "20".to_string()];
+        assert_eq!(build_request(&args), "logs modem 20\n");
+    }
+
+    #[test]
+    fn missing_socket_is_an_error() {
+        assert!(send_request("/nonexistent/initctl", "status\n").is_err());
+    }
+}
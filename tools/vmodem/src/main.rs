@@ -0,0 +1,192 @@
+// ABOUTME: vmodem — a virtual AT-command modem for testing telephony without hardware.
+// ABOUTME: Serves a pty or a QEMU chardev socket and plays scripted calls, SMS, and signal changes.
+
+mod modem;
+mod port;
+mod script;
+
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, bail};
+
+use port::Emulator;
+
+const USAGE: &str = "\
+usage: vmodem [--link PATH] [--script FILE]   emulate a modem on a new pty
+       vmodem --socket PATH [--script FILE]  emulate a modem for QEMU on a Unix socket
+
+The pty is printed on stderr; --link also symlinks it to PATH. Run the modem
+service against it with MOS_MODEM_PORT=<pty>. With --socket, start QEMU with
+  -chardev socket,id=modem,path=PATH -device virtio-serial-device
+  -device virtserialport,chardev=modem,name=modem
+and the guest sees the modem on /dev/vport0p1.
+
+Events come from the script and from stdin, one per line:
+  sleep MS, call NUMBER, hangup, sms NUMBER TEXT, signal 0-31|99,
+  register 0-5, operator NAME, sim on|off
+What the phone does (dial, answer, hangup, sms-sent) is printed on stdout.";
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if matches!(args.as_slice(), ["-h" | "--help" | "help"]) {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let mut link = None;
+    let mut socket = None;
+    let mut script = None;
+    let mut flags = args.iter();
+    while let Some(flag) = flags.next() {
+        let value = flags
+            .next()
+            .with_context(|| format!("{flag} requires an argument"))?;
+        match *flag {
+            "--link" => link = Some(PathBuf::from(value)),
+            "--socket" => socket = Some(PathBuf::from(value)),
+            "--script" => script = Some(PathBuf::from(value)),
+            _ => bail!("invalid command line\n\n{USAGE}"),
+        }
+    }
+    if link.is_some() && socket.is_some() {
+        bail!("--link and --socket can't be combined\n\n{USAGE}");
+    }
+    let steps = match &script {
+        Some(path) => {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            script::parse(&content).with_context(|| format!("invalid script {}", path.display()))?
+        }
+        None => Vec::new(),
+    };
+
+    let (emulator, activity) = Emulator::new();
+    let emulator = Arc::new(emulator);
+    std::thread::spawn(move || {
+        for activity in activity {
+            println!("{activity}");
+        }
+    });
+    {
+        let emulator = emulator.clone();
+        std::thread::spawn(move || emulator.run(steps));
+    }
+    {
+        let emulator = emulator.clone();
+        std::thread::spawn(move || read_commands(&emulator));
+    }
+
+    match socket {
+        Some(path) => {
+            eprintln!("vmodem: listening on {}", path.display());
+            port::serve_socket(&emulator, &path)
+        }
+        None => {
+            let pty = port::open_pty()?;
+            eprintln!("vmodem: modem on {}", pty.path.display());
+            if let Some(link) = &link {
+                make_link(&pty.path, link)?;
+            }
+            port::serve_pty(&emulator, pty)
+        }
+    }
+}
+
+/// Take events typed on stdin until it closes.
+fn read_commands(emulator: &Emulator) {
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        match script::parse_line(&line) {
+            Ok(Some(step)) => emulator.run([step]),
+            Ok(None) => {}
+            Err(e) => eprintln!("vmodem: {e}"),
+        }
+    }
+}
+
+fn make_link(target: &Path, link: &Path) -> anyhow::Result<()> {
+    let _ = std::fs::remove_file(link);
+    std::os::unix::fs::symlink(target, link)
+        .with_context(|| format!("failed to link {}", link.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modem::{Activity, Event};
+    use mos_modem::at::{AtModem, Urc};
+    use mos_modem::pdu::Delivery;
+
+    /// The modem service's own AT backend, talking to the emulator over a pty.
+    #[test]
+    fn at_backend_drives_the_emulator() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(drive_the_emulator());
+        // The backend reads the pty on a blocking thread, which a runtime waits for
+        // when dropped, and the emulator never closes the pty to end the read
+        runtime.shutdown_background();
+    }
+
+    async fn drive_the_emulator() {
+        let pty = port::open_pty().unwrap();
+        let path = pty.path.clone();
+        let (emulator, activity) = Emulator::new();
+        let emulator = Arc::new(emulator);
+        {
+            let emulator = emulator.clone();
+            std::thread::spawn(move || port::serve_pty(&emulator, pty));
+        }
+
+        let (modem, mut urcs) = AtModem::open(&path).unwrap();
+        modem.init().await.unwrap();
+        assert!(modem.sim_present().await.unwrap());
+        assert_eq!(modem.signal_strength().await.unwrap(), Some(64));
+        assert_eq!(
+            modem.operator().await.unwrap().as_deref(),
+            Some("MobileOS Virtual")
+        );
+
+        let reference = modem
+            .send_sms("+15551234", "on my way", true)
+            .await
+            .unwrap();
+        assert_eq!(
+            activity.recv().unwrap(),
+            Activity::SmsSent {
+                recipient: "+15551234".into(),
+                text: "on my way".into()
+            }
+        );
+        assert_eq!(urcs.recv().await, Some(Urc::StatusReport(1)));
+        let report = modem.take_status_report(1).await.unwrap();
+        assert_eq!(report.reference, reference);
+        assert_eq!(report.delivery(), Delivery::Delivered);
+
+        emulator
+            .inject(Event::Sms {
+                sender: "+15550000".into(),
+                text: "see you".into(),
+            })
+            .unwrap();
+        assert_eq!(urcs.recv().await, Some(Urc::NewSms(1)));
+        let sms = modem.take_sms(1).await.unwrap();
+        assert_eq!(
+            (sms.sender.as_str(), sms.text.as_str()),
+            ("+15550000", "see you")
+        );
+
+        emulator.inject(Event::Call("+15550000".into())).unwrap();
+        assert_eq!(urcs.recv().await, Some(Urc::Ring));
+        assert_eq!(urcs.recv().await, Some(Urc::CallerId("+15550000".into())));
+        emulator.inject(Event::Hangup).unwrap();
+        assert_eq!(urcs.recv().await, Some(Urc::CallEnded));
+
+        emulator.inject(Event::Sim(false)).unwrap();
+        assert!(!modem.sim_present().await.unwrap());
+    }
+}
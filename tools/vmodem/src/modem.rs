@@ -0,0 +1,765 @@
+// ABOUTME: The emulated modem: a Hayes-style AT command interpreter with call and SMS state.
+// ABOUTME: Turns bytes from the host into replies, and scripted events into unsolicited results; sent messages are reported delivered.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::{Result, bail};
+use mos_modem::stk::{self, Command};
+use mos_modem::{at, pdu};
+
+/// Ends the PDU written after the `> ` prompt of AT+CMGS; ESC cancels it.
+const CTRL_Z: u8 = 0x1a;
+const ESC: u8 = 0x1b;
+const BACKSPACE: u8 = 0x08;
+
+/// Longest command line accepted before the line is thrown away.
+const MAX_LINE: usize = 1024;
+
+/// Error codes from 3GPP TS 27.005 and 27.007, reported with AT+CMEE=1.
+const CME_NOT_ALLOWED: u16 = 3;
+const CME_NOT_SUPPORTED: u16 = 4;
+const CME_SIM_NOT_INSERTED: u16 = 10;
+const CMS_INVALID_PDU: u16 = 304;
+const CMS_INVALID_INDEX: u16 = 321;
+const CMS_NO_NETWORK: u16 = 331;
+
+/// The SIM's IMSI, on the 3GPP test network 001/01.
+const IMSI: &str = "001010123456789";
+
+/// The SIM toolkit's services, which subscribe by SMS to this number.
+const SERVICES: &[&str] = &["News", "Weather"];
+const SERVICES_NUMBER: &str = "+15550100";
+
+/// Things that happen on the emulated network, from the script or stdin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Someone calls this number.
+    Call(String),
+    /// The other end hangs up, or stops ringing.
+    Hangup,
+    /// A text message arrives.
+    Sms {
+        sender: String,
+        text: String,
+    },
+    /// Signal quality as AT+CSQ reports it: 0-31, or 99 for unknown.
+    Signal(u8),
+    /// Network registration status as in +CREG: 0 not registered, 1 home, 2 searching,
+    /// 3 denied, 5 roaming.
+    Registration(u8),
+    Operator(String),
+    Sim(bool),
+}
+
+/// What the host did, reported so tests can check the phone side behaved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Activity {
+    Dialed(String),
+    Answered,
+    HungUp,
+    SmsSent {
+        recipient: String,
+        text: String,
+    },
+    Apn(String),
+    /// An item of the SIM toolkit's menu opened, or chosen when it asked.
+    ToolkitItem(u8),
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Activity::Dialed(number) => write!(f, "dial {number}"),
+            Activity::Answered => write!(f, "answer"),
+            Activity::HungUp => write!(f, "hangup"),
+            Activity::SmsSent { recipient, text } => write!(f, "sms-sent {recipient} {text}"),
+            Activity::Apn(apn) => write!(f, "apn {apn}"),
+            Activity::ToolkitItem(item) => write!(f, "stk-item {item}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Call {
+    Idle,
+    Ringing,
+    Active,
+}
+
+enum Final {
+    Ok,
+    /// The `> ` prompt of AT+CMGS, written in place of a final result.
+    Prompt,
+    Error,
+    NoCarrier,
+    Cme(u16),
+    Cms(u16),
+}
+
+/// Modem state, fed bytes from the host with `input` and network events with `event`.
+pub struct Modem {
+    line: Vec<u8>,
+    /// Bytes of the PDU being written after the AT+CMGS prompt.
+    pdu: Option<Vec<u8>>,
+    echo: bool,
+    numeric_errors: bool,
+    caller_id: bool,
+    registration_urcs: bool,
+    /// Whether status reports are stored and announced with +CDSI, as AT+CNMI asks.
+    status_report_urcs: bool,
+    registration: u8,
+    signal: u8,
+    operator: String,
+    sim: bool,
+    call: Call,
+    /// Who the call is with, and whether they called, for AT+CLCC.
+    peer: (String, bool),
+    /// Received messages and status reports by storage index, as (PDU hex, TPDU
+    /// length).
+    messages: BTreeMap<u32, (String, usize)>,
+    message_reference: u8,
+    /// The SIM toolkit command waiting on its terminal response, and the number of
+    /// the last one sent.
+    toolkit: Option<Command>,
+    toolkit_number: u8,
+    /// Unsolicited results a command caused, written after its final result.
+    urcs: Vec<u8>,
+    activity: Vec<Activity>,
+}
+
+impl Default for Modem {
+    fn default() -> Self {
+        Self {
+            line: Vec::new(),
+            pdu: None,
+            echo: true,
+            numeric_errors: false,
+            caller_id: false,
+            registration_urcs: false,
+            status_report_urcs: false,
+            registration: 1,
+            signal: 20,
+            operator: "MobileOS Virtual".to_string(),
+            sim: true,
+            call: Call::Idle,
+            peer: Default::default(),
+            messages: BTreeMap::new(),
+            message_reference: 0,
+            toolkit: None,
+            toolkit_number: 0,
+            urcs: Vec::new(),
+            activity: Vec::new(),
+        }
+    }
+}
+
+impl Modem {
+    /// Feed bytes written by the host; returns what the modem writes back.
+    pub fn input(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &byte in bytes {
+            if let Some(mut pdu) = self.pdu.take() {
+                match byte {
+                    CTRL_Z => out.extend(self.submit(&String::from_utf8_lossy(&pdu))),
+                    ESC => out.extend(self.finish(Final::Ok)),
+                    _ => {
+                        pdu.push(byte);
+                        self.pdu = Some(pdu);
+                    }
+                }
+                continue;
+            }
+
+            if self.echo {
+                out.push(byte);
+            }
+            match byte {
+                b'\r' => {
+                    let line = String::from_utf8_lossy(&self.line).trim().to_string();
+                    self.line.clear();
+                    out.extend(self.command_line(&line));
+                }
+                b'\n' => {}
+                BACKSPACE => {
+                    self.line.pop();
+                }
+                _ if self.line.len() < MAX_LINE => self.line.push(byte),
+                _ => self.line.clear(),
+            }
+        }
+        out
+    }
+
+    /// Apply a network event; returns the unsolicited result codes it causes.
+    pub fn event(&mut self, event: Event) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match event {
+            Event::Call(number) => {
+                if self.call != Call::Idle {
+                    bail!("a call is already in progress");
+                }
+                out.extend(info("RING"));
+                if self.caller_id {
+                    let kind = number_type(&number);
+                    out.extend(info(&format!("+CLIP: \"{number}\",{kind}")));
+                }
+                self.call = Call::Ringing;
+                self.peer = (number, true);
+            }
+            Event::Hangup => {
+                if self.call == Call::Idle {
+                    bail!("no call to hang up");
+                }
+                self.call = Call::Idle;
+                out.extend(info("NO CARRIER"));
+            }
+            Event::Sms { sender, text } => {
+                let index = self.store(pdu::encode_deliver(&sender, &text)?);
+                out.extend(info(&format!("+CMTI: \"SM\",{index}")));
+            }
+            Event::Signal(rssi) => {
+                if rssi > 31 && rssi != 99 {
+                    bail!("signal must be 0-31, or 99 for unknown");
+                }
+                self.signal = rssi;
+            }
+            Event::Registration(status) => {
+                if status > 5 {
+                    bail!("registration status must be 0-5");
+                }
+                self.registration = status;
+                if self.registration_urcs {
+                    out.extend(info(&format!("+CREG: {status}")));
+                }
+            }
+            Event::Operator(name) => self.operator = name,
+            Event::Sim(present) => self.sim = present,
+        }
+        Ok(out)
+    }
+
+    /// Take what the host has done since the last call.
+    pub fn take_activity(&mut self) -> Vec<Activity> {
+        std::mem::take(&mut self.activity)
+    }
+
+    fn command_line(&mut self, line: &str) -> Vec<u8> {
+        let Some(prefix) = line.get(..2) else {
+            return Vec::new();
+        };
+        if !prefix.eq_ignore_ascii_case("AT") {
+            return Vec::new();
+        }
+        let command = line[2..].to_ascii_uppercase();
+
+        let mut out = Vec::new();
+        let result = self.command(&command, &mut out);
+        out.extend(self.finish(result));
+        out.append(&mut self.urcs);
+        out
+    }
+
+    /// Run one command, appending its information lines to `out`.
+    fn command(&mut self, command: &str, out: &mut Vec<u8>) -> Final {
+        match command {
+            "" | "Z" => Final::Ok,
+            "E" | "E0" => {
+                self.echo = false;
+                Final::Ok
+            }
+            "E1" => {
+                self.echo = true;
+                Final::Ok
+            }
+            "I" | "+CGMI" => {
+                out.extend(info("MobileOS virtual modem"));
+                Final::Ok
+            }
+            "+CSQ" => {
+                out.extend(info(&format!("+CSQ: {},99", self.signal)));
+                Final::Ok
+            }
+            "+CREG?" => {
+                let mode = u8::from(self.registration_urcs);
+                out.extend(info(&format!("+CREG: {mode},{}", self.registration)));
+                Final::Ok
+            }
+            "+COPS?" => {
+                if matches!(self.registration, 1 | 5) {
+                    out.extend(info(&format!("+COPS: 0,0,\"{}\"", self.operator)));
+                } else {
+                    out.extend(info("+COPS: 0"));
+                }
+                Final::Ok
+            }
+            "+CPIN?" if self.sim => {
+                out.extend(info("+CPIN: READY"));
+                Final::Ok
+            }
+            "+CPIN?" => Final::Cme(CME_SIM_NOT_INSERTED),
+            "+CIMI" if self.sim => {
+                out.extend(info(IMSI));
+                Final::Ok
+            }
+            "+CIMI" => Final::Cme(CME_SIM_NOT_INSERTED),
+            "+CMGF=0" => Final::Ok,
+            "+CMGF?" => {
+                out.extend(info("+CMGF: 0"));
+                Final::Ok
+            }
+            // Text mode isn't emulated; the backend only uses PDU mode
+            "+CMGF=1" => Final::Cme(CME_NOT_SUPPORTED),
+            "A" if self.call == Call::Ringing => {
+                self.call = Call::Active;
+                self.activity.push(Activity::Answered);
+                Final::Ok
+            }
+            "A" => Final::NoCarrier,
+            // The one call there can be, never in a conference
+            "+CLCC" => {
+                let state = match self.call {
+                    Call::Idle => return Final::Ok,
+                    Call::Ringing => 4,
+                    Call::Active => 0,
+                };
+                let (number, incoming) = &self.peer;
+                out.extend(info(&format!(
+                    "+CLCC: 1,{},{state},0,0,\"{number}\",{}",
+                    u8::from(*incoming),
+                    number_type(number)
+                )));
+                Final::Ok
+            }
+            "H" | "+CHUP" => {
+                if self.call != Call::Idle {
+                    self.call = Call::Idle;
+                    self.activity.push(Activity::HungUp);
+                }
+                Final::Ok
+            }
+            _ => self.command_with_value(command, out),
+        }
+    }
+
+    /// Commands carrying a value: setters and the ones taking numbers or indexes.
+    fn command_with_value(&mut self, command: &str, out: &mut Vec<u8>) -> Final {
+        if let Some(number) = command.strip_prefix('D') {
+            // Without the trailing `;` it would be a data call
+            let Some(number) = number.strip_suffix(';') else {
+                return Final::Cme(CME_NOT_SUPPORTED);
+            };
+            if !self.sim {
+                return Final::Cme(CME_SIM_NOT_INSERTED);
+            }
+            if self.call != Call::Idle || number.is_empty() {
+                return Final::Cme(CME_NOT_ALLOWED);
+            }
+            self.call = Call::Active;
+            self.peer = (number.to_string(), false);
+            self.activity.push(Activity::Dialed(number.to_string()));
+            return Final::Ok;
+        }
+
+        let Some((name, value)) = command.split_once('=') else {
+            return Final::Error;
+        };
+        let number = value.parse::<u32>();
+        match (name, number) {
+            ("+CMEE", Ok(mode)) => self.numeric_errors = mode != 0,
+            ("+CLIP", Ok(mode)) => self.caller_id = mode != 0,
+            ("+CREG", Ok(mode)) => self.registration_urcs = mode != 0,
+            // +CNMI=<mode>,<mt>,<bm>,<ds>,<bfr>; ds 2 stores status reports, announced
+            ("+CNMI", _) => {
+                self.status_report_urcs = value.split(',').nth(3) == Some("2");
+            }
+            ("+CMGS", Ok(_)) if !self.sim => return Final::Cme(CME_SIM_NOT_INSERTED),
+            ("+CMGS", Ok(_)) if !at::is_registered(self.registration) => {
+                return Final::Cms(CMS_NO_NETWORK);
+            }
+            ("+CMGS", Ok(_)) => {
+                self.pdu = Some(Vec::new());
+                return Final::Prompt;
+            }
+            ("+CMGR", Ok(index)) => {
+                // +CMGR: <stat>,[<alpha>],<length> followed by the PDU on its own line
+                let Some((pdu, length)) = self.messages.get(&index) else {
+                    return Final::Cms(CMS_INVALID_INDEX);
+                };
+                out.extend(format!("\r\n+CMGR: 0,,{length}\r\n{pdu}\r\n").into_bytes());
+            }
+            ("+CMGD", Ok(index)) => {
+                self.messages.remove(&index);
+            }
+            // +CGDCONT=<cid>,"<type>","<apn>"; only the APN is reported, upper cased
+            // like the rest of the command
+            ("+CGDCONT", _) => {
+                let Some(apn) = value.split(',').nth(2) else {
+                    return Final::Error;
+                };
+                self.activity
+                    .push(Activity::Apn(apn.trim_matches('"').to_string()));
+            }
+            // The bearer and its authentication are taken as given
+            ("+CGAUTH" | "+CGACT", _) => {}
+            ("+CUSATA", Ok(0)) => {}
+            ("+CUSATA", Ok(_)) if !self.sim => return Final::Cme(CME_SIM_NOT_INSERTED),
+            ("+CUSATA", Ok(_)) => {
+                let menu = Command::SetUpMenu {
+                    title: "MobileOS SIM".to_string(),
+                    items: vec![(1, "Balance".to_string()), (2, "Services".to_string())],
+                };
+                return self.proactive(menu);
+            }
+            ("+CUSATE", _) => {
+                let Ok(item) = stk::parse_menu_selection(value.trim_matches('"')) else {
+                    return Final::Error;
+                };
+                self.activity.push(Activity::ToolkitItem(item));
+                let command = match item {
+                    1 => Command::DisplayText {
+                        text: "Your balance is 10.00".to_string(),
+                    },
+                    2 => Command::SelectItem {
+                        title: "Services".to_string(),
+                        items: (1..).zip(SERVICES.iter().map(|s| s.to_string())).collect(),
+                    },
+                    _ => return Final::Cme(CME_NOT_ALLOWED),
+                };
+                return self.proactive(command);
+            }
+            ("+CUSATT", _) => {
+                let Ok(response) = stk::parse_terminal_response(value.trim_matches('"')) else {
+                    return Final::Error;
+                };
+                return self.toolkit_answered(response);
+            }
+            _ => return Final::Error,
+        }
+        Final::Ok
+    }
+
+    /// Send a proactive command from the SIM, after the command's final result.
+    fn proactive(&mut self, command: Command) -> Final {
+        self.toolkit_number = self.toolkit_number.wrapping_add(1);
+        let Ok(hex) = stk::encode(self.toolkit_number, &command) else {
+            return Final::Error;
+        };
+        self.urcs.extend(info(&format!("+CUSATP: \"{hex}\"")));
+        self.toolkit = Some(command);
+        Final::Ok
+    }
+
+    /// Go on with the toolkit session once the phone answered: a chosen service is
+    /// subscribed to by SMS, and anything else ends the session.
+    fn toolkit_answered(&mut self, response: stk::Response) -> Final {
+        let Some(command) = self.toolkit.take() else {
+            return Final::Cme(CME_NOT_ALLOWED);
+        };
+        if let Some(item) = response.item {
+            self.activity.push(Activity::ToolkitItem(item));
+        }
+        let chosen = response
+            .item
+            .and_then(|item| SERVICES.get(usize::from(item).checked_sub(1)?));
+        match (command, chosen) {
+            // The menu is set up outside any session
+            (Command::SetUpMenu { .. }, _) => Final::Ok,
+            (Command::SelectItem { .. }, Some(service)) if response.result == 0 => {
+                self.proactive(Command::SendSms {
+                    alpha: format!("Subscribing to {service}"),
+                    recipient: SERVICES_NUMBER.to_string(),
+                    text: format!("SUBSCRIBE {}", service.to_uppercase()),
+                })
+            }
+            _ => {
+                self.urcs.extend(info("+CUSATEND"));
+                Final::Ok
+            }
+        }
+    }
+
+    /// Send the message written after the prompt. It arrives at once, and is reported
+    /// delivered if the host asked.
+    fn submit(&mut self, pdu: &str) -> Vec<u8> {
+        let Ok(submit) = pdu::decode_submit(pdu.trim()) else {
+            return self.finish(Final::Cms(CMS_INVALID_PDU));
+        };
+        self.message_reference = self.message_reference.wrapping_add(1);
+        let reference = self.message_reference;
+        let mut out = info(&format!("+CMGS: {reference}"));
+        out.extend(self.finish(Final::Ok));
+        if submit.status_report && self.status_report_urcs {
+            match pdu::encode_status_report(reference, &submit.recipient, 0) {
+                Ok(report) => {
+                    let index = self.store(report);
+                    out.extend(info(&format!("+CDSI: \"SR\",{index}")));
+                }
+                Err(_) => return self.finish(Final::Cms(CMS_INVALID_PDU)),
+            }
+        }
+        self.activity.push(Activity::SmsSent {
+            recipient: submit.recipient,
+            text: submit.text,
+        });
+        out
+    }
+
+    /// Keep a PDU for AT+CMGR at the first free index, which is returned.
+    fn store(&mut self, message: (String, usize)) -> u32 {
+        let index = (1..)
+            .find(|i| !self.messages.contains_key(i))
+            .unwrap_or_default();
+        self.messages.insert(index, message);
+        index
+    }
+
+    fn finish(&self, result: Final) -> Vec<u8> {
+        match result {
+            Final::Ok => info("OK"),
+            Final::Prompt => b"\r\n> ".to_vec(),
+            Final::Error => info("ERROR"),
+            Final::NoCarrier => info("NO CARRIER"),
+            Final::Cme(code) if self.numeric_errors => info(&format!("+CME ERROR: {code}")),
+            Final::Cms(code) if self.numeric_errors => info(&format!("+CMS ERROR: {code}")),
+            Final::Cme(_) | Final::Cms(_) => info("ERROR"),
+        }
+    }
+}
+
+/// A line as modems send them in verbose mode, framed by CR LF on both sides.
+/// The type of address +CLIP and +CLCC give a number: international or not.
+fn number_type(number: &str) -> u8 {
+    if number.starts_with('+') { 145 } else { 129 }
+}
+
+fn info(line: &str) -> Vec<u8> {
+    format!("\r\n{line}\r\n").into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a command line and return the reply as text.
+    fn send(modem: &mut Modem, line: &str) -> String {
+        String::from_utf8(modem.input(format!("{line}\r").as_bytes())).unwrap()
+    }
+
+    fn quiet_modem() -> Modem {
+        let mut modem = Modem::default();
+        send(&mut modem, "ATE0");
+        modem
+    }
+
+    #[test]
+    fn takes_the_apn_from_the_sim_carrier() {
+        let mut modem = quiet_modem();
+        assert!(send(&mut modem, "AT+CIMI").contains("001010123456789"));
+        assert!(send(&mut modem, "AT+CGACT=0,1").ends_with("\r\nOK\r\n"));
+        assert!(send(&mut modem, "AT+CGDCONT=1,\"IPV4V6\",\"internet\"").ends_with("OK\r\n"));
+        assert!(send(&mut modem, "AT+CGAUTH=1,0").ends_with("OK\r\n"));
+        assert_eq!(modem.take_activity(), [Activity::Apn("INTERNET".into())]);
+        assert!(send(&mut modem, "AT+CGDCONT=1").ends_with("\r\nERROR\r\n"));
+
+        modem.event(Event::Sim(false)).unwrap();
+        assert!(send(&mut modem, "AT+CIMI").ends_with("\r\nERROR\r\n"));
+    }
+
+    #[test]
+    fn sim_toolkit_subscribes_to_a_service() {
+        let mut modem = quiet_modem();
+        let toolkit = |reply: &str| {
+            let hex = reply.split("+CUSATP: \"").nth(1).unwrap();
+            stk::parse(&hex[..hex.find('"').unwrap()]).unwrap()
+        };
+        let reply = send(&mut modem, "AT+CUSATA=1");
+        assert!(reply.starts_with("\r\nOK\r\n"));
+        let menu = toolkit(&reply);
+        assert!(matches!(&menu.command, Command::SetUpMenu { items, .. } if items.len() == 2));
+        let answer = stk::terminal_response(menu.details, stk::Outcome::Done, None);
+        assert_eq!(
+            send(&mut modem, &format!("AT+CUSATT=\"{answer}\"")),
+            "\r\nOK\r\n"
+        );
+
+        let reply = send(&mut modem, &format!("AT+CUSATE={}", stk::menu_selection(2)));
+        let services = toolkit(&reply);
+        let answer = stk::terminal_response(services.details, stk::Outcome::Done, Some(2));
+        let reply = send(&mut modem, &format!("AT+CUSATT={answer}"));
+        let Command::SendSms {
+            recipient, text, ..
+        } = toolkit(&reply).command
+        else {
+            panic!("expected the SIM to send an SMS, got {reply}");
+        };
+        assert_eq!(
+            (recipient.as_str(), text.as_str()),
+            ("+15550100", "SUBSCRIBE WEATHER")
+        );
+        assert_eq!(
+            modem.take_activity(),
+            [Activity::ToolkitItem(2), Activity::ToolkitItem(2)]
+        );
+
+        // Reading the balance ends the session
+        let reply = send(&mut modem, &format!("AT+CUSATE={}", stk::menu_selection(1)));
+        let balance = toolkit(&reply);
+        let answer = stk::terminal_response(balance.details, stk::Outcome::Done, None);
+        assert!(send(&mut modem, &format!("AT+CUSATT={answer}")).ends_with("+CUSATEND\r\n"));
+        assert!(send(&mut modem, &format!("AT+CUSATT={answer}")).contains("ERROR"));
+    }
+
+    #[test]
+    fn echoes_until_disabled() {
+        let mut modem = Modem::default();
+        assert_eq!(send(&mut modem, "AT"), "AT\r\r\nOK\r\n");
+        assert_eq!(send(&mut modem, "ATE0"), "ATE0\r\r\nOK\r\n");
+        assert_eq!(send(&mut modem, "AT"), "\r\nOK\r\n");
+        assert_eq!(send(&mut modem, "hello"), "");
+    }
+
+    #[test]
+    fn answers_status_queries() {
+        let mut modem = quiet_modem();
+        assert_eq!(send(&mut modem, "AT+CSQ"), "\r\n+CSQ: 20,99\r\n\r\nOK\r\n");
+        modem.event(Event::Signal(99)).unwrap();
+        assert!(send(&mut modem, "at+csq").contains("+CSQ: 99,99"));
+        assert!(modem.event(Event::Signal(40)).is_err());
+
+        modem.event(Event::Operator("Test Net".into())).unwrap();
+        assert!(send(&mut modem, "AT+COPS?").contains("+COPS: 0,0,\"Test Net\""));
+        assert!(send(&mut modem, "AT+CPIN?").contains("+CPIN: READY"));
+        assert!(send(&mut modem, "AT+BOGUS").ends_with("\r\nERROR\r\n"));
+    }
+
+    #[test]
+    fn numeric_errors_follow_cmee() {
+        let mut modem = quiet_modem();
+        modem.event(Event::Sim(false)).unwrap();
+        assert_eq!(send(&mut modem, "AT+CPIN?"), "\r\nERROR\r\n");
+        send(&mut modem, "AT+CMEE=1");
+        assert_eq!(send(&mut modem, "AT+CPIN?"), "\r\n+CME ERROR: 10\r\n");
+        assert_eq!(send(&mut modem, "AT+CMGR=9"), "\r\n+CMS ERROR: 321\r\n");
+    }
+
+    #[test]
+    fn registration_changes_are_reported_once_enabled() {
+        let mut modem = quiet_modem();
+        assert!(modem.event(Event::Registration(2)).unwrap().is_empty());
+        assert!(send(&mut modem, "AT+COPS?").contains("+COPS: 0\r\n"));
+        send(&mut modem, "AT+CREG=1");
+        assert_eq!(
+            modem.event(Event::Registration(5)).unwrap(),
+            info("+CREG: 5")
+        );
+        assert!(send(&mut modem, "AT+CREG?").contains("+CREG: 1,5"));
+    }
+
+    #[test]
+    fn incoming_call_is_answered_and_ended() {
+        let mut modem = quiet_modem();
+        send(&mut modem, "AT+CLIP=1");
+        let urcs = modem.event(Event::Call("+15551234".into())).unwrap();
+        assert_eq!(
+            String::from_utf8(urcs).unwrap(),
+            "\r\nRING\r\n\r\n+CLIP: \"+15551234\",145\r\n"
+        );
+        assert!(modem.event(Event::Call("+15550000".into())).is_err());
+
+        assert!(send(&mut modem, "AT+CLCC").contains("+CLCC: 1,1,4,0,0,\"+15551234\",145"));
+        assert!(send(&mut modem, "ATA").contains("OK"));
+        assert_eq!(modem.take_activity(), [Activity::Answered]);
+        assert_eq!(modem.event(Event::Hangup).unwrap(), info("NO CARRIER"));
+        assert!(send(&mut modem, "ATA").contains("NO CARRIER"));
+        assert!(modem.event(Event::Hangup).is_err());
+    }
+
+    #[test]
+    fn outgoing_call_is_dialed_and_hung_up() {
+        let mut modem = quiet_modem();
+        assert!(send(&mut modem, "ATD+15551234;").contains("OK"));
+        assert!(send(&mut modem, "ATD5550000;").contains("ERROR"));
+        assert!(send(&mut modem, "AT+CLCC").contains("+CLCC: 1,0,0,0,0,\"+15551234\",145"));
+        assert!(send(&mut modem, "ATH").contains("OK"));
+        assert_eq!(send(&mut modem, "AT+CLCC"), "\r\nOK\r\n");
+        assert_eq!(
+            modem.take_activity(),
+            [Activity::Dialed("+15551234".into()), Activity::HungUp]
+        );
+    }
+
+    #[test]
+    fn delivered_sms_is_read_and_deleted() {
+        let mut modem = quiet_modem();
+        let urc = modem
+            .event(Event::Sms {
+                sender: "+15551234".into(),
+                text: "hello there".into(),
+            })
+            .unwrap();
+        assert_eq!(urc, info("+CMTI: \"SM\",1"));
+
+        let reply = send(&mut modem, "AT+CMGR=1");
+        let pdu = reply.lines().nth(2).unwrap();
+        let sms = pdu::decode_deliver(pdu).unwrap();
+        assert_eq!(sms.sender, "+15551234");
+        assert_eq!(sms.text, "hello there");
+
+        assert!(send(&mut modem, "AT+CMGD=1").contains("OK"));
+        assert!(send(&mut modem, "AT+CMGR=1").contains("ERROR"));
+    }
+
+    #[test]
+    fn submitted_sms_is_decoded() {
+        let mut modem = quiet_modem();
+        let (pdu, length) = pdu::encode_submit("+15551234", "on my way", false).unwrap();
+        assert_eq!(send(&mut modem, &format!("AT+CMGS={length}")), "\r\n> ");
+
+        let reply = modem.input(format!("{pdu}\x1a").as_bytes());
+        assert_eq!(reply, b"\r\n+CMGS: 1\r\n\r\nOK\r\n");
+        assert_eq!(
+            modem.take_activity(),
+            [Activity::SmsSent {
+                recipient: "+15551234".into(),
+                text: "on my way".into()
+            }]
+        );
+
+        send(&mut modem, "AT+CMGS=10");
+        assert_eq!(modem.input(b"zz\x1b"), info("OK"));
+        send(&mut modem, "AT+CMGS=10");
+        assert!(
+            String::from_utf8(modem.input(b"0011\x1a"))
+                .unwrap()
+                .contains("ERROR")
+        );
+    }
+
+    #[test]
+    fn sent_sms_is_reported_delivered() {
+        let mut modem = quiet_modem();
+        send(&mut modem, "AT+CNMI=2,1,0,2,0");
+        let (pdu, length) = pdu::encode_submit("+15551234", "on my way", true).unwrap();
+        send(&mut modem, &format!("AT+CMGS={length}"));
+        let reply = modem.input(format!("{pdu}\x1a").as_bytes());
+        let mut expected = info("+CMGS: 1");
+        expected.extend(info("OK"));
+        expected.extend(info("+CDSI: \"SR\",1"));
+        assert_eq!(reply, expected);
+
+        let reply = send(&mut modem, "AT+CMGR=1");
+        let report = pdu::decode_status_report(reply.lines().nth(2).unwrap()).unwrap();
+        assert_eq!(report.reference, 1);
+        assert_eq!(report.recipient, "+15551234");
+        assert_eq!(report.delivery(), pdu::Delivery::Delivered);
+
+        // Off the network, nothing goes out
+        modem.event(Event::Registration(2)).unwrap();
+        send(&mut modem, "AT+CMEE=1");
+        assert_eq!(
+            send(&mut modem, &format!("AT+CMGS={length}")),
+            "\r\n+CMS ERROR: 331\r\n"
+        );
+    }
+}
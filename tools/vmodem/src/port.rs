@@ -0,0 +1,194 @@
+// ABOUTME: Connects the emulated modem to its host: a pty for tests on the build machine, or
+// ABOUTME: a Unix socket that a QEMU chardev behind a virtio-serial port connects to.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::mpsc;
+
+use anyhow::{Context, Result};
+use rustix::fs::OFlags;
+use rustix::pty::{OpenptFlags, grantpt, openpt, ptsname, unlockpt};
+use rustix::termios::{OptionalActions, tcgetattr, tcsetattr};
+
+use crate::modem::{Activity, Event, Modem};
+use crate::script::Step;
+
+/// The modem together with whatever host is connected to it right now.
+pub struct Emulator {
+    modem: Mutex<Modem>,
+    host: Mutex<Option<Box<dyn Write + Send>>>,
+    activity: mpsc::Sender<Activity>,
+}
+
+impl Emulator {
+    /// Create the emulator; what the host does is reported on the returned receiver.
+    pub fn new() -> (Self, mpsc::Receiver<Activity>) {
+        let (activity, receiver) = mpsc::channel();
+        let emulator = Self {
+            modem: Mutex::new(Modem::default()),
+            host: Mutex::new(None),
+            activity,
+        };
+        (emulator, receiver)
+    }
+
+    /// Answer the host on one connection until it closes.
+    pub fn serve(&self, mut reader: impl Read, writer: impl Write + Send + 'static) -> Result<()> {
+        *self.host.lock().unwrap() = Some(Box::new(writer));
+        let mut buf = [0u8; 512];
+        let result = loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e).context("failed to read from host"),
+            };
+            let mut modem = self.modem.lock().unwrap();
+            let reply = modem.input(&buf[..n]);
+            self.send(&reply);
+            for activity in modem.take_activity() {
+                let _ = self.activity.send(activity);
+            }
+        };
+        *self.host.lock().unwrap() = None;
+        result
+    }
+
+    /// Apply a network event and send the host the result codes it causes.
+    pub fn inject(&self, event: Event) -> Result<()> {
+        let mut modem = self.modem.lock().unwrap();
+        let urcs = modem.event(event)?;
+        self.send(&urcs);
+        Ok(())
+    }
+
+    /// Run script steps in order, reporting events that don't apply and carrying on.
+    pub fn run(&self, steps: impl IntoIterator<Item = Step>) {
+        for step in steps {
+            match step {
+                Step::Sleep(duration) => std::thread::sleep(duration),
+                Step::Event(event) => {
+                    if let Err(e) = self.inject(event.clone()) {
+                        eprintln!("vmodem: {event:?}: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write to the host, if one is connected. Result codes sent while nobody is
+    /// listening are lost, as on a real modem.
+    fn send(&self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        if let Some(host) = self.host.lock().unwrap().as_mut()
+            && let Err(e) = host.write_all(bytes).and_then(|()| host.flush())
+        {
+            eprintln!("vmodem: failed to write to host: {e}");
+        }
+    }
+}
+
+/// A pseudo-terminal whose far end looks like a modem's serial port.
+pub struct Pty {
+    pub master: File,
+    /// Held open so reads on the master don't fail while no host has the port open.
+    _slave: File,
+    pub path: PathBuf,
+}
+
+/// Allocate a pty, with the port side in raw mode so nothing is echoed or translated
+/// before a host configures it.
+pub fn open_pty() -> Result<Pty> {
+    let master = openpt(OpenptFlags::RDWR | OpenptFlags::NOCTTY | OpenptFlags::CLOEXEC)
+        .context("failed to allocate a pty")?;
+    grantpt(&master)?;
+    unlockpt(&master)?;
+    let name = ptsname(&master, Vec::new())?;
+    let path = PathBuf::from(OsString::from_vec(name.into_bytes()));
+
+    let slave = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(OFlags::NOCTTY.bits() as i32)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut termios = tcgetattr(&slave)?;
+    termios.make_raw();
+    tcsetattr(&slave, OptionalActions::Now, &termios)?;
+
+    Ok(Pty {
+        master: File::from(master),
+        _slave: slave,
+        path,
+    })
+}
+
+/// Serve the modem on a pty until reading it fails.
+pub fn serve_pty(emulator: &Emulator, pty: Pty) -> Result<()> {
+    let writer = pty.master.try_clone()?;
+    emulator.serve(&pty.master, writer)
+}
+
+/// Accept connections on a Unix socket one at a time, e.g. from QEMU started with
+/// `-chardev socket,id=modem,path=PATH`.
+pub fn serve_socket(emulator: &Emulator, path: &Path) -> Result<()> {
+    // A stale socket from an earlier run would make bind fail
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("failed to listen on {}", path.display()))?;
+
+    for stream in listener.incoming() {
+        let stream = stream.context("failed to accept connection")?;
+        eprintln!("vmodem: host connected");
+        if let Err(e) = emulator.serve(&stream, stream.try_clone()?) {
+            eprintln!("vmodem: {e:#}");
+        }
+        eprintln!("vmodem: host disconnected");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+
+    #[test]
+    fn socket_host_gets_replies_and_result_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modem.sock");
+        let (emulator, activity) = Emulator::new();
+        let emulator = Arc::new(emulator);
+        {
+            let (emulator, path) = (emulator.clone(), path.clone());
+            std::thread::spawn(move || serve_socket(&emulator, &path));
+        }
+        while !path.exists() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let mut host = UnixStream::connect(&path).unwrap();
+        let mut lines = std::io::BufReader::new(host.try_clone().unwrap()).lines();
+        // Replies are framed in CR LF; lines() strips the LF and one CR
+        host.write_all(b"ATE0\r").unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "ATE0\r");
+        assert_eq!(lines.next().unwrap().unwrap(), "OK");
+
+        host.write_all(b"ATD5551234;\r").unwrap();
+        assert_eq!(lines.nth(1).unwrap().unwrap(), "OK");
+        assert_eq!(activity.recv().unwrap(), Activity::Dialed("5551234".into()));
+
+        emulator.inject(Event::Hangup).unwrap();
+        assert_eq!(lines.nth(1).unwrap().unwrap(), "NO CARRIER");
+    }
+}
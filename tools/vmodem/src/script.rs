@@ -0,0 +1,118 @@
+// ABOUTME: Scenario scripts for the virtual modem: network events separated by pauses.
+// ABOUTME: The same one-line commands drive the modem interactively from stdin.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+
+use crate::modem::Event;
+
+/// One line of a script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Sleep(Duration),
+    Event(Event),
+}
+
+/// Parse a whole script. Blank lines and `#` comments are skipped.
+pub fn parse(content: &str) -> Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        if let Some(step) = parse_line(line).with_context(|| format!("line {}", number + 1))? {
+            steps.push(step);
+        }
+    }
+    Ok(steps)
+}
+
+/// Parse one command:
+///
+/// ```text
+/// sleep 500             pause for 500 ms
+/// call +15551234        incoming call
+/// hangup                the other end hangs up
+/// sms +15551234 Hello   incoming text message
+/// signal 0-31|99        signal quality as in AT+CSQ
+/// register 0-5          registration status as in +CREG
+/// operator Some Net     network name
+/// sim on|off            insert or remove the SIM
+/// ```
+pub fn parse_line(line: &str) -> Result<Option<Step>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim();
+
+    let event = match command {
+        "sleep" => {
+            let ms = rest.parse().context("sleep takes milliseconds")?;
+            return Ok(Some(Step::Sleep(Duration::from_millis(ms))));
+        }
+        "call" if !rest.is_empty() => Event::Call(rest.to_string()),
+        "hangup" => Event::Hangup,
+        "sms" => {
+            let Some((sender, text)) = rest.split_once(' ') else {
+                bail!("sms takes a sender and the message text");
+            };
+            Event::Sms {
+                sender: sender.to_string(),
+                text: text.trim().to_string(),
+            }
+        }
+        "signal" => Event::Signal(rest.parse().context("signal takes 0-31 or 99")?),
+        "register" => Event::Registration(rest.parse().context("register takes 0-5")?),
+        "operator" if !rest.is_empty() => Event::Operator(rest.to_string()),
+        "sim" => match rest {
+            "on" => Event::Sim(true),
+            "off" => Event::Sim(false),
+            _ => bail!("sim takes on or off"),
+        },
+        _ => bail!("invalid command '{line}'"),
+    };
+    Ok(Some(Step::Event(event)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_scenario() {
+        let script = "
+            # incoming call, then a message
+            signal 25
+            call +15551234
+            sleep 2000
+            hangup
+            sms +15551234   Sorry, call me back?
+            sim off
+        ";
+
+        assert_eq!(
+            parse(script).unwrap(),
+            [
+                Step::Event(Event::Signal(25)),
+                Step::Event(Event::Call("+15551234".into())),
+                Step::Sleep(Duration::from_secs(2)),
+                Step::Event(Event::Hangup),
+                Step::Event(Event::Sms {
+                    sender: "+15551234".into(),
+                    text: "Sorry, call me back?".into(),
+                }),
+                Step::Event(Event::Sim(false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = parse("signal 20\nsleep soon\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2");
+        assert!(parse_line("call").is_err());
+        assert!(parse_line("sms +1555").is_err());
+        assert!(parse_line("sim maybe").is_err());
+        assert!(parse_line("dance").is_err());
+    }
+}
@@ -0,0 +1,249 @@
+// ABOUTME: Dump file format: one timestamped protocol message per line.
+// ABOUTME: Written by the recording proxy, read back for printing and replay.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+
+use crate::wire::{Interfaces, Message};
+
+const MAGIC: &str = "# wldump 1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Client to compositor.
+    Request,
+    /// Compositor to client.
+    Event,
+}
+
+/// A message as seen by the proxy. `fds` counts the descriptors that arrived in the same
+/// read; they are attributed to the first message completed by it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub time: Duration,
+    pub client: u32,
+    pub direction: Direction,
+    pub message: Message,
+    pub fds: usize,
+}
+
+impl fmt::Display for Record {
+    /// `<secs>.<micros> <client> <'>'|'<'> <object> <opcode> <fds> <args as hex or '-'>`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arrow = match self.direction {
+            Direction::Request => '>',
+            Direction::Event => '<',
+        };
+        write!(
+            f,
+            "{}.{:06} {} {} {} {} {} ",
+            self.time.as_secs(),
+            self.time.subsec_micros(),
+            self.client,
+            arrow,
+            self.message.object,
+            self.message.opcode,
+            self.fds
+        )?;
+        if self.message.args.is_empty() {
+            f.write_str("-")
+        } else {
+            self.message
+                .args
+                .iter()
+                .try_for_each(|b| write!(f, "{b:02x}"))
+        }
+    }
+}
+
+impl Record {
+    pub fn parse(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let [time, client, arrow, object, opcode, fds, args] = fields.as_slice() else {
+            bail!("expected 7 fields");
+        };
+
+        let (secs, micros) = time.split_once('.').context("invalid timestamp")?;
+        let direction = match *arrow {
+            ">" => Direction::Request,
+            "<" => Direction::Event,
+            other => bail!("invalid direction '{other}'"),
+        };
+        let args = if *args == "-" {
+            Vec::new()
+        } else {
+            if !args.len().is_multiple_of(2) {
+                bail!("odd-length argument bytes");
+            }
+            (0..args.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&args[i..i + 2], 16))
+                .collect::<Result<_, _>>()
+                .context("invalid argument bytes")?
+        };
+
+        Ok(Record {
+            time: Duration::from_secs(secs.parse()?) + Duration::from_micros(micros.parse()?),
+            client: client.parse()?,
+            direction,
+            message: Message {
+                object: object.parse()?,
+                opcode: opcode.parse()?,
+                args,
+            },
+            fds: fds.parse()?,
+        })
+    }
+
+    /// A one-line, human-oriented rendering, naming objects where `interfaces` knows them.
+    pub fn describe(&self, interfaces: &Interfaces) -> String {
+        let arrow = match self.direction {
+            Direction::Request => "->",
+            Direction::Event => "<-",
+        };
+        let object = match interfaces.name(self.message.object) {
+            Some(name) => format!("{name}@{}", self.message.object),
+            None => format!("@{}", self.message.object),
+        };
+        let mut line = format!(
+            "[{:>4}.{:06}] client {} {arrow} {object} opcode {} ({} bytes)",
+            self.time.as_secs(),
+            self.time.subsec_micros(),
+            self.client,
+            self.message.opcode,
+            self.message.args.len()
+        );
+        if self.fds > 0 {
+            line.push_str(&format!(" +{} fd", self.fds));
+        }
+        if interfaces.name(self.message.object) == Some("wl_registry")
+            && self.direction == Direction::Request
+            && let Some((interface, _)) = self.message.string_arg(4)
+        {
+            line.push_str(&format!(" bind {interface}"));
+        }
+        line
+    }
+}
+
+/// Appends records to a dump file. Shared by every relay thread of the proxy.
+pub struct Writer {
+    out: Mutex<BufWriter<File>>,
+    start: Instant,
+}
+
+impl Writer {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "{MAGIC}")?;
+        out.flush()?;
+        Ok(Self {
+            out: Mutex::new(out),
+            start: Instant::now(),
+        })
+    }
+
+    /// Write one record, flushing so a crash of the compositor or the proxy loses nothing.
+    pub fn record(&self, client: u32, direction: Direction, message: Message, fds: usize) {
+        let record = Record {
+            time: self.start.elapsed(),
+            client,
+            direction,
+            message,
+            fds,
+        };
+        let mut out = self.out.lock().unwrap();
+        let _ = writeln!(out, "{record}").and_then(|()| out.flush());
+    }
+}
+
+pub fn read(path: &Path) -> Result<Vec<Record>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+    match lines.next() {
+        Some(Ok(first)) if first == MAGIC => {}
+        _ => bail!("{} is not a wldump file", path.display()),
+    }
+
+    let mut records = Vec::new();
+    for (n, line) in lines.enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let record = Record::parse(&line)
+            .with_context(|| format!("{}:{}: malformed record", path.display(), n + 2))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(direction: Direction, args: Vec<u8>) -> Record {
+        Record {
+            time: Duration::from_micros(1_234_567),
+            client: 2,
+            direction,
+            message: Message {
+                object: 5,
+                opcode: 3,
+                args,
+            },
+            fds: 1,
+        }
+    }
+
+    #[test]
+    fn records_round_trip_through_text() {
+        for r in [
+            record(Direction::Request, vec![0, 1, 0xab, 0xff]),
+            record(Direction::Event, Vec::new()),
+        ] {
+            let line = r.to_string();
+            assert_eq!(Record::parse(&line).unwrap(), r);
+        }
+        assert_eq!(
+            record(Direction::Request, vec![0xab]).to_string(),
+            "1.234567 2 > 5 3 1 ab"
+        );
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        assert!(Record::parse("1.0 1 > 1 1 0").is_err());
+        assert!(Record::parse("1.0 1 ? 1 1 0 -").is_err());
+        assert!(Record::parse("1.0 1 > 1 1 0 abc").is_err());
+    }
+
+    #[test]
+    fn writer_output_reads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.wldump");
+        let writer = Writer::create(&path).unwrap();
+        let message = Message {
+            object: 1,
+            opcode: 1,
+            args: 2u32.to_ne_bytes().to_vec(),
+        };
+        writer.record(1, Direction::Request, message.clone(), 0);
+        drop(writer);
+
+        let records = read(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, message);
+
+        std::fs::write(&path, "not a dump\n").unwrap();
+        assert!(read(&path).is_err());
+    }
+}
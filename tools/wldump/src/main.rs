@@ -0,0 +1,143 @@
+// ABOUTME: wldump — Wayland protocol recorder and replayer for debugging the compositor.
+// ABOUTME: Records client sessions through a proxy socket, prints dumps, and replays them.
+
+mod dump;
+mod proxy;
+mod replay;
+mod wire;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, bail};
+
+const USAGE: &str = "\
+usage: wldump record [-o FILE] [-s NAME]   proxy clients to the compositor and record them
+       wldump print FILE                   show a recording
+       wldump replay [-c CLIENT] [--speed N] FILE
+                                           resend one client's requests to the compositor
+
+record listens on $XDG_RUNTIME_DIR/NAME (default wldump-0) and forwards to
+$WAYLAND_DISPLAY; start clients with WAYLAND_DISPLAY=NAME. The dump defaults
+to wayland.wldump. replay connects to $WAYLAND_DISPLAY, e.g. a compositor
+started with MOS_BACKEND=headless, and replays the busiest client by default
+at recorded speed (--speed 0 sends without delays).";
+
+const DEFAULT_SOCKET: &str = "wldump-0";
+const DEFAULT_DUMP: &str = "wayland.wldump";
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        [] | ["-h" | "--help" | "help"] => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        ["record", rest @ ..] => {
+            let mut output = PathBuf::from(DEFAULT_DUMP);
+            let mut socket = DEFAULT_SOCKET.to_string();
+            let mut rest = rest.iter();
+            while let Some(flag) = rest.next() {
+                let value = rest
+                    .next()
+                    .with_context(|| format!("{flag} requires an argument"))?;
+                match *flag {
+                    "-o" => output = PathBuf::from(value),
+                    "-s" => socket = value.to_string(),
+                    _ => bail!("invalid command line\n\n{USAGE}"),
+                }
+            }
+            record(&output, &socket)
+        }
+        ["print", file] => print(Path::new(file)),
+        ["replay", rest @ ..] => {
+            let Some((file, flags)) = rest.split_last() else {
+                bail!("invalid command line\n\n{USAGE}");
+            };
+            let mut client = None;
+            let mut speed: f64 = 1.0;
+            let mut flags = flags.iter();
+            while let Some(flag) = flags.next() {
+                let value = flags
+                    .next()
+                    .with_context(|| format!("{flag} requires an argument"))?;
+                match *flag {
+                    "-c" => client = Some(value.parse().context("invalid client number")?),
+                    "--speed" => speed = value.parse().context("invalid speed")?,
+                    _ => bail!("invalid command line\n\n{USAGE}"),
+                }
+            }
+            if speed.is_nan() || speed < 0.0 {
+                bail!("speed must not be negative");
+            }
+            replay(Path::new(file), client, speed)
+        }
+        _ => bail!("invalid command line\n\n{USAGE}"),
+    }
+}
+
+fn record(output: &Path, socket: &str) -> anyhow::Result<()> {
+    let upstream = socket_path(&display_name())?;
+    let listen = socket_path(socket)?;
+    let writer = Arc::new(dump::Writer::create(output)?);
+
+    eprintln!(
+        "wldump: recording to {}; run clients with WAYLAND_DISPLAY={socket}",
+        output.display()
+    );
+    proxy::run(&listen, &upstream, writer)
+}
+
+fn print(file: &Path) -> anyhow::Result<()> {
+    // One registry view per client: object ids are only unique within a connection
+    let mut interfaces = std::collections::HashMap::new();
+    for record in dump::read(file)? {
+        let known: &mut wire::Interfaces = interfaces.entry(record.client).or_default();
+        println!("{}", record.describe(known));
+        match record.direction {
+            dump::Direction::Request => known.observe_request(&record.message),
+            dump::Direction::Event => known.observe_event(&record.message),
+        }
+    }
+    Ok(())
+}
+
+fn replay(file: &Path, client: Option<u32>, speed: f64) -> anyhow::Result<()> {
+    let records = dump::read(file)?;
+    let client = match client {
+        Some(client) => client,
+        None => replay::busiest_client(&records).context("recording has no requests")?,
+    };
+    let display = socket_path(&display_name())?;
+    eprintln!("wldump: replaying client {client} to {}", display.display());
+    replay::run(&records, &display, client, speed)
+}
+
+fn display_name() -> String {
+    std::env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".to_string())
+}
+
+/// Resolve a Wayland socket name the way libwayland does: absolute paths are used as is,
+/// anything else is relative to $XDG_RUNTIME_DIR.
+fn socket_path(name: &str) -> anyhow::Result<PathBuf> {
+    if name.starts_with('/') {
+        return Ok(PathBuf::from(name));
+    }
+    let runtime_dir = std::env::var("XDG_RUNTIME_DIR").context("XDG_RUNTIME_DIR is not set")?;
+    Ok(Path::new(&runtime_dir).join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn absolute_socket_names_are_kept() {
+        assert_eq!(
+            socket_path("/tmp/wayland-9").unwrap(),
+            PathBuf::from("/tmp/wayland-9")
+        );
+    }
+}
@@ -0,0 +1,157 @@
+// ABOUTME: Recording proxy that sits between Wayland clients and the compositor socket.
+// ABOUTME: Relays bytes and fds unchanged in both directions while logging each message.
+
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::dump::{Direction, Writer};
+use crate::wire::{self, Splitter};
+
+/// Largest read; libwayland's connection buffers are 4 KiB, so this takes several at once.
+const READ_SIZE: usize = 16 * 1024;
+
+/// Accept clients on `listen` forever, connecting each to the compositor at `upstream`.
+pub fn run(listen: &Path, upstream: &Path, writer: Arc<Writer>) -> Result<()> {
+    // A socket left over from an earlier run would make bind() fail
+    let _ = std::fs::remove_file(listen);
+    let listener = UnixListener::bind(listen)
+        .with_context(|| format!("failed to listen on {}", listen.display()))?;
+
+    for (client, stream) in (1..).zip(listener.incoming()) {
+        let stream = stream.context("failed to accept client")?;
+        match UnixStream::connect(upstream) {
+            Ok(compositor) => {
+                eprintln!("wldump: client {client} connected");
+                start_relays(client, stream, compositor, &writer)?;
+            }
+            Err(e) => eprintln!(
+                "wldump: client {client} rejected, cannot reach {}: {e}",
+                upstream.display()
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn start_relays(
+    client: u32,
+    stream: UnixStream,
+    compositor: UnixStream,
+    writer: &Arc<Writer>,
+) -> Result<()> {
+    let requests = (stream.try_clone()?, compositor.try_clone()?, writer.clone());
+    std::thread::spawn(move || {
+        let (from, to, writer) = requests;
+        relay(client, Direction::Request, &from, &to, &writer);
+    });
+
+    let writer = writer.clone();
+    std::thread::spawn(move || {
+        relay(client, Direction::Event, &compositor, &stream, &writer);
+        eprintln!("wldump: client {client} disconnected");
+    });
+    Ok(())
+}
+
+/// Copy one direction of a connection until either side goes away, then tear down both.
+fn relay(client: u32, direction: Direction, from: &UnixStream, to: &UnixStream, writer: &Writer) {
+    let mut splitter = Splitter::default();
+    let mut buf = vec![0u8; READ_SIZE];
+
+    loop {
+        let mut fds = Vec::new();
+        let n = match wire::recv(from, &mut buf, &mut fds) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        // Record before forwarding, so the dump is complete by the time the peer reacts
+        let parsed = splitter.push(&buf[..n]);
+        if let Ok(messages) = &parsed {
+            let mut fd_count = fds.len();
+            for message in messages {
+                writer.record(
+                    client,
+                    direction,
+                    message.clone(),
+                    std::mem::take(&mut fd_count),
+                );
+            }
+        }
+        if wire::send(to, &buf[..n], &fds).is_err() {
+            break;
+        }
+        if let Err(e) = parsed {
+            eprintln!("wldump: client {client}: {e}; no longer recording this stream");
+            let _ = std::io::copy(&mut &*from, &mut &*to);
+            break;
+        }
+    }
+
+    let _ = from.shutdown(Shutdown::Both);
+    let _ = to.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump;
+    use crate::wire::Message;
+
+    #[test]
+    fn relays_and_records_both_directions() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream_path = dir.path().join("wayland-0");
+        let listen_path = dir.path().join("wldump-0");
+        let dump_path = dir.path().join("session.wldump");
+
+        let upstream = UnixListener::bind(&upstream_path).unwrap();
+        let writer = Arc::new(Writer::create(&dump_path).unwrap());
+        {
+            let (listen, upstream_path) = (listen_path.clone(), upstream_path.clone());
+            std::thread::spawn(move || run(&listen, &upstream_path, writer));
+        }
+        while !listen_path.exists() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let client = UnixStream::connect(&listen_path).unwrap();
+        let (compositor, _) = upstream.accept().unwrap();
+
+        let request = Message {
+            object: 1,
+            opcode: 1,
+            args: 2u32.to_ne_bytes().to_vec(),
+        };
+        let fd = rustix::fs::memfd_create("pool", rustix::fs::MemfdFlags::CLOEXEC).unwrap();
+        wire::send(&client, &request.encode(), &[fd]).unwrap();
+
+        let mut buf = [0u8; 64];
+        let mut fds = Vec::new();
+        let n = wire::recv(&compositor, &mut buf, &mut fds).unwrap();
+        assert_eq!(&buf[..n], request.encode().as_slice());
+        assert_eq!(fds.len(), 1);
+
+        let event = Message {
+            object: 1,
+            opcode: 1,
+            args: 3u32.to_ne_bytes().to_vec(),
+        };
+        wire::send(&compositor, &event.encode(), &[]).unwrap();
+        let n = wire::recv(&client, &mut buf, &mut fds).unwrap();
+        assert_eq!(&buf[..n], event.encode().as_slice());
+
+        drop(client);
+        drop(compositor);
+        let records = dump::read(&dump_path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::Request);
+        assert_eq!(records[0].message, request);
+        assert_eq!(records[0].fds, 1);
+        assert_eq!(records[1].direction, Direction::Event);
+        assert_eq!(records[1].message, event);
+    }
+}
@@ -0,0 +1,229 @@
+// ABOUTME: Replays one recorded client's requests against a compositor, with the original timing.
+// ABOUTME: Substitutes fresh fds for recorded ones and reports protocol errors the compositor raises.
+
+use std::fs::OpenOptions;
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use rustix::fs::{MemfdFlags, ftruncate, memfd_create};
+
+use crate::dump::{Direction, Record};
+use crate::wire::{self, DISPLAY_ID, Interfaces, Message, Splitter};
+
+/// How long to keep listening for errors after the last request.
+const LINGER: Duration = Duration::from_secs(1);
+
+/// Send `client`'s requests from `records` to the compositor at `display`. A `speed` of 2
+/// replays twice as fast; 0 sends everything back to back.
+pub fn run(records: &[Record], display: &Path, client: u32, speed: f64) -> Result<()> {
+    let requests: Vec<&Record> = records
+        .iter()
+        .filter(|r| r.client == client && r.direction == Direction::Request)
+        .collect();
+    if requests.is_empty() {
+        bail!("no requests recorded for client {client}");
+    }
+
+    let socket = UnixStream::connect(display)
+        .with_context(|| format!("failed to connect to {}", display.display()))?;
+    let events = socket.try_clone()?;
+    let reader = std::thread::spawn(move || watch_events(&events));
+
+    let mut interfaces = Interfaces::default();
+    let mut previous = requests[0].time;
+    for record in &requests {
+        if speed > 0.0 {
+            std::thread::sleep(record.time.saturating_sub(previous).div_f64(speed));
+        }
+        previous = record.time;
+
+        interfaces.observe_request(&record.message);
+        let fds = substitute_fds(&interfaces, &record.message, record.fds)?;
+        wire::send(&socket, &record.message.encode(), &fds)
+            .context("compositor closed the connection")?;
+    }
+    eprintln!("wldump: replayed {} requests", requests.len());
+
+    std::thread::sleep(LINGER);
+    let _ = socket.shutdown(std::net::Shutdown::Both);
+    let _ = reader.join();
+    Ok(())
+}
+
+/// The client with the most requests, which is usually the one being debugged.
+pub fn busiest_client(records: &[Record]) -> Option<u32> {
+    let mut counts = std::collections::BTreeMap::new();
+    for record in records.iter().filter(|r| r.direction == Direction::Request) {
+        *counts.entry(record.client).or_insert(0usize) += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(client, count)| (count, std::cmp::Reverse(client)))
+        .map(|(client, _)| client)
+}
+
+/// Recorded fds can't be replayed, so stand in for them. Shared memory pools get a zeroed
+/// memfd of the recorded size, so buffers attach but render blank; anything else gets /dev/null.
+fn substitute_fds(
+    interfaces: &Interfaces,
+    message: &Message,
+    count: usize,
+) -> Result<Vec<OwnedFd>> {
+    // wl_shm.create_pool(id: new_id, fd: fd, size: int)
+    if interfaces.name(message.object) == Some("wl_shm") && message.opcode == 0 {
+        let size = message.u32_arg(4).context("truncated create_pool")?;
+        let pool = memfd_create("wldump-pool", MemfdFlags::CLOEXEC)?;
+        ftruncate(&pool, u64::from(size))?;
+        return Ok(vec![pool]);
+    }
+
+    (0..count)
+        .map(|_| {
+            let null = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/null")?;
+            Ok(OwnedFd::from(null))
+        })
+        .collect()
+}
+
+/// Drain events until the compositor hangs up, printing any protocol error it sends.
+fn watch_events(socket: &UnixStream) {
+    let mut splitter = Splitter::default();
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let mut fds = Vec::new();
+        let n = match wire::recv(socket, &mut buf, &mut fds) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        let Ok(messages) = splitter.push(&buf[..n]) else {
+            return;
+        };
+        for message in messages {
+            if let Some(error) = protocol_error(&message) {
+                eprintln!("wldump: compositor raised a protocol error: {error}");
+            }
+        }
+    }
+}
+
+/// Decode wl_display.error(object_id, code, message).
+fn protocol_error(message: &Message) -> Option<String> {
+    if message.object != DISPLAY_ID || message.opcode != 0 {
+        return None;
+    }
+    let object = message.u32_arg(0)?;
+    let code = message.u32_arg(4)?;
+    let (text, _) = message.string_arg(8)?;
+    Some(format!("object {object}, code {code}: {text}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::string_bytes;
+
+    fn request(client: u32, time_ms: u64) -> Record {
+        Record {
+            time: Duration::from_millis(time_ms),
+            client,
+            direction: Direction::Request,
+            message: Message {
+                object: DISPLAY_ID,
+                opcode: 1,
+                args: 2u32.to_ne_bytes().to_vec(),
+            },
+            fds: 0,
+        }
+    }
+
+    #[test]
+    fn shm_pools_get_a_sized_memfd() {
+        let mut interfaces = Interfaces::default();
+        interfaces.observe_request(&request(1, 0).message);
+        let mut bind = 1u32.to_ne_bytes().to_vec();
+        bind.extend(string_bytes("wl_shm"));
+        bind.extend(1u32.to_ne_bytes());
+        bind.extend(3u32.to_ne_bytes());
+        interfaces.observe_request(&Message {
+            object: 2,
+            opcode: 0,
+            args: bind,
+        });
+
+        let mut args = 4u32.to_ne_bytes().to_vec();
+        args.extend(8192u32.to_ne_bytes());
+        let create_pool = Message {
+            object: 3,
+            opcode: 0,
+            args,
+        };
+        let fds = substitute_fds(&interfaces, &create_pool, 0).unwrap();
+        assert_eq!(fds.len(), 1);
+        assert_eq!(rustix::fs::fstat(&fds[0]).unwrap().st_size, 8192);
+
+        let other = Message {
+            object: 9,
+            opcode: 2,
+            args: Vec::new(),
+        };
+        assert_eq!(substitute_fds(&interfaces, &other, 2).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn decodes_protocol_errors() {
+        let mut args = 3u32.to_ne_bytes().to_vec();
+        args.extend(2u32.to_ne_bytes());
+        args.extend(string_bytes("invalid buffer"));
+        let error = Message {
+            object: DISPLAY_ID,
+            opcode: 0,
+            args,
+        };
+        assert_eq!(
+            protocol_error(&error).as_deref(),
+            Some("object 3, code 2: invalid buffer")
+        );
+        assert_eq!(protocol_error(&request(1, 0).message), None);
+    }
+
+    #[test]
+    fn replays_requests_to_compositor() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wayland-1");
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let records = vec![request(1, 0), request(2, 5), request(1, 10)];
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut splitter = Splitter::default();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 256];
+            while received.len() < 2 {
+                let n = wire::recv(&stream, &mut buf, &mut Vec::new()).unwrap();
+                received.extend(splitter.push(&buf[..n]).unwrap());
+            }
+            received
+        });
+
+        run(&records, &path, 1, 0.0).unwrap();
+        let received = server.join().unwrap();
+        assert_eq!(
+            received,
+            vec![records[0].message.clone(), records[2].message.clone()]
+        );
+        assert!(run(&records, &path, 7, 0.0).is_err());
+    }
+
+    #[test]
+    fn busiest_client_is_default() {
+        let records = vec![request(1, 0), request(2, 1), request(2, 2)];
+        assert_eq!(busiest_client(&records), Some(2));
+        assert_eq!(busiest_client(&[]), None);
+    }
+}
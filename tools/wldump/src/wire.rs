@@ -0,0 +1,279 @@
+// ABOUTME: Wayland wire format: message framing, argument decoding, and fd-carrying socket I/O.
+// ABOUTME: Also tracks which interface each object id belongs to, as far as the registry reveals.
+
+use std::collections::HashMap;
+use std::io::{self, IoSlice, IoSliceMut, Write};
+use std::mem::MaybeUninit;
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+
+use rustix::net::{
+    RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags, SendAncillaryBuffer,
+    SendAncillaryMessage, SendFlags, recvmsg, sendmsg,
+};
+
+/// Object id and a size/opcode word.
+pub const HEADER_LEN: usize = 8;
+
+/// libwayland never passes more fds than this in one sendmsg.
+pub const MAX_FDS: usize = 28;
+
+/// The wl_display singleton every connection starts with.
+pub const DISPLAY_ID: u32 = 1;
+
+/// One request or event, with its arguments still encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub object: u32,
+    pub opcode: u16,
+    pub args: Vec<u8>,
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let size = (HEADER_LEN + self.args.len()) as u32;
+        let mut bytes = Vec::with_capacity(size as usize);
+        bytes.extend_from_slice(&self.object.to_ne_bytes());
+        bytes.extend_from_slice(&((size << 16) | u32::from(self.opcode)).to_ne_bytes());
+        bytes.extend_from_slice(&self.args);
+        bytes
+    }
+
+    pub fn u32_arg(&self, offset: usize) -> Option<u32> {
+        let bytes = self.args.get(offset..offset + 4)?;
+        Some(u32::from_ne_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Decode a string argument, returning it and the offset just past its padding.
+    pub fn string_arg(&self, offset: usize) -> Option<(String, usize)> {
+        let len = self.u32_arg(offset)? as usize;
+        let start = offset + 4;
+        let bytes = self.args.get(start..start + len)?;
+        let text = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+        Some((
+            String::from_utf8_lossy(text).into_owned(),
+            start + len.next_multiple_of(4),
+        ))
+    }
+}
+
+/// Reassembles messages from a byte stream that may split them anywhere.
+#[derive(Default)]
+pub struct Splitter {
+    buf: Vec<u8>,
+}
+
+impl Splitter {
+    /// Append bytes and return every message they completed.
+    pub fn push(&mut self, bytes: &[u8]) -> io::Result<Vec<Message>> {
+        self.buf.extend_from_slice(bytes);
+
+        let mut messages = Vec::new();
+        let mut pos = 0;
+        while self.buf.len() - pos >= HEADER_LEN {
+            let word = |i: usize| u32::from_ne_bytes(self.buf[i..i + 4].try_into().unwrap());
+            let object = word(pos);
+            let size = (word(pos + 4) >> 16) as usize;
+            let opcode = (word(pos + 4) & 0xffff) as u16;
+            if size < HEADER_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("message of {size} bytes is shorter than its header"),
+                ));
+            }
+            if self.buf.len() - pos < size {
+                break;
+            }
+            messages.push(Message {
+                object,
+                opcode,
+                args: self.buf[pos + HEADER_LEN..pos + size].to_vec(),
+            });
+            pos += size;
+        }
+        self.buf.drain(..pos);
+        Ok(messages)
+    }
+}
+
+/// Read whatever is available, collecting any fds that came with it. Returns 0 at end of stream.
+pub fn recv(socket: &UnixStream, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+    let mut space = [MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(MAX_FDS))];
+    let mut control = RecvAncillaryBuffer::new(&mut space);
+    let received = recvmsg(
+        socket,
+        &mut [IoSliceMut::new(buf)],
+        &mut control,
+        RecvFlags::CMSG_CLOEXEC,
+    )?;
+    for message in control.drain() {
+        if let RecvAncillaryMessage::ScmRights(received_fds) = message {
+            fds.extend(received_fds);
+        }
+    }
+    Ok(received.bytes)
+}
+
+/// Write all of `bytes`, passing `fds` along with the first chunk.
+pub fn send(socket: &UnixStream, bytes: &[u8], fds: &[OwnedFd]) -> io::Result<()> {
+    let borrowed: Vec<_> = fds.iter().map(|fd| fd.as_fd()).collect();
+    let mut space = [MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(MAX_FDS))];
+    let mut control = SendAncillaryBuffer::new(&mut space);
+    if !borrowed.is_empty() && !control.push(SendAncillaryMessage::ScmRights(&borrowed)) {
+        return Err(io::Error::other(format!("too many fds ({})", fds.len())));
+    }
+
+    let sent = sendmsg(
+        socket,
+        &[IoSlice::new(bytes)],
+        &mut control,
+        SendFlags::NOSIGNAL,
+    )?;
+    let mut rest = socket;
+    rest.write_all(&bytes[sent..])
+}
+
+/// Interface names learned from wl_display.get_registry and wl_registry.bind. Objects
+/// created any other way stay unnamed; naming them would need the protocol XML.
+pub struct Interfaces {
+    names: HashMap<u32, String>,
+}
+
+impl Default for Interfaces {
+    fn default() -> Self {
+        Self {
+            names: HashMap::from([(DISPLAY_ID, "wl_display".to_string())]),
+        }
+    }
+}
+
+impl Interfaces {
+    pub fn name(&self, object: u32) -> Option<&str> {
+        self.names.get(&object).map(String::as_str)
+    }
+
+    pub fn observe_request(&mut self, message: &Message) {
+        match (self.name(message.object), message.opcode) {
+            // wl_display.get_registry(new_id)
+            (Some("wl_display"), 1) => {
+                if let Some(id) = message.u32_arg(0) {
+                    self.names.insert(id, "wl_registry".to_string());
+                }
+            }
+            // wl_registry.bind(name, new_id of any interface: string, version, id)
+            (Some("wl_registry"), 0) => {
+                if let Some((interface, next)) = message.string_arg(4)
+                    && let Some(id) = message.u32_arg(next + 4)
+                {
+                    self.names.insert(id, interface);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn observe_event(&mut self, message: &Message) {
+        // wl_display.delete_id(id): the id may be reused for another interface
+        if message.object == DISPLAY_ID
+            && message.opcode == 1
+            && let Some(id) = message.u32_arg(0)
+        {
+            self.names.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn string_bytes(s: &str) -> Vec<u8> {
+    let mut bytes = ((s.len() + 1) as u32).to_ne_bytes().to_vec();
+    bytes.extend_from_slice(s.as_bytes());
+    bytes.push(0);
+    while !bytes.len().is_multiple_of(4) {
+        bytes.push(0);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bind(name: u32, interface: &str, version: u32, id: u32) -> Message {
+        let mut args = name.to_ne_bytes().to_vec();
+        args.extend(string_bytes(interface));
+        args.extend(version.to_ne_bytes());
+        args.extend(id.to_ne_bytes());
+        Message {
+            object: 2,
+            opcode: 0,
+            args,
+        }
+    }
+
+    #[test]
+    fn splitter_reassembles_split_messages() {
+        let first = Message {
+            object: 1,
+            opcode: 1,
+            args: 2u32.to_ne_bytes().to_vec(),
+        };
+        let second = bind(1, "wl_compositor", 5, 3);
+        let mut stream = first.encode();
+        stream.extend(second.encode());
+
+        let mut splitter = Splitter::default();
+        assert_eq!(splitter.push(&stream[..5]).unwrap(), vec![]);
+        assert_eq!(splitter.push(&stream[5..14]).unwrap(), vec![first]);
+        assert_eq!(splitter.push(&stream[14..]).unwrap(), vec![second]);
+    }
+
+    #[test]
+    fn splitter_rejects_undersized_message() {
+        let mut bytes = 1u32.to_ne_bytes().to_vec();
+        bytes.extend((4u32 << 16).to_ne_bytes());
+        assert!(Splitter::default().push(&bytes).is_err());
+    }
+
+    #[test]
+    fn string_arguments_are_padded() {
+        let message = bind(7, "wl_shm", 1, 4);
+        assert_eq!(message.u32_arg(0), Some(7));
+        assert_eq!(message.string_arg(4), Some(("wl_shm".to_string(), 16)));
+        assert_eq!(message.u32_arg(16), Some(1));
+        assert_eq!(message.u32_arg(20), Some(4));
+        assert_eq!(message.u32_arg(24), None);
+    }
+
+    #[test]
+    fn interfaces_follow_registry_binds() {
+        let mut interfaces = Interfaces::default();
+        interfaces.observe_request(&Message {
+            object: DISPLAY_ID,
+            opcode: 1,
+            args: 2u32.to_ne_bytes().to_vec(),
+        });
+        interfaces.observe_request(&bind(4, "wl_shm", 1, 3));
+        assert_eq!(interfaces.name(2), Some("wl_registry"));
+        assert_eq!(interfaces.name(3), Some("wl_shm"));
+
+        interfaces.observe_event(&Message {
+            object: DISPLAY_ID,
+            opcode: 1,
+            args: 3u32.to_ne_bytes().to_vec(),
+        });
+        assert_eq!(interfaces.name(3), None);
+    }
+
+    #[test]
+    fn fds_travel_with_bytes() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let fd = rustix::fs::memfd_create("wldump-test", rustix::fs::MemfdFlags::CLOEXEC).unwrap();
+        send(&a, b"hello", &[fd]).unwrap();
+
+        let mut buf = [0u8; 16];
+        let mut fds = Vec::new();
+        assert_eq!(recv(&b, &mut buf, &mut fds).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(fds.len(), 1);
+    }
+}